@@ -20,7 +20,12 @@ pub struct LogEvent {
 pub struct PortStats {
     pub packets: u64,
     pub bytes: u64,
-    pub last_seen: u64,
+    pub last_seen: u64, // 最后一个包的 bpf_ktime_get_ns
+    // 按方向拆分，rx 为网卡收到(ingress)，tx 为网卡发出(egress)
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
 }
 
 // 定义设备流量统计结构，供用户空间和内核空间共享
@@ -29,7 +34,74 @@ pub struct PortStats {
 pub struct DeviceStats {
     pub packets: u64,
     pub bytes: u64,
-    pub last_seen: u64,
+    pub last_seen: u64, // 最后一个包的 bpf_ktime_get_ns
+}
+
+// 流记录的地址族
+pub const FLOW_FAMILY_V4: u8 = 4;
+pub const FLOW_FAMILY_V6: u8 = 6;
+
+// 与地址族无关的流五元组，所有程序和用户空间使用同一格式
+// 地址为网络字节序，IPv4 地址以 IPv4 映射的 IPv6 地址(::ffff:a.b.c.d)保存，端口为主机字节序
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct FlowTuple {
+    pub saddr: [u8; 16],
+    pub daddr: [u8; 16],
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub family: u8,   // FLOW_FAMILY_V4 / FLOW_FAMILY_V6
+    pub protocol: u8, // IP 协议号，IPv6 为固定头中的 next header
+    pub reserved: u16,
+}
+
+impl FlowTuple {
+    // saddr/daddr 为 IPv4 头中的网络字节序地址
+    pub fn from_v4(saddr: u32, daddr: u32, src_port: u16, dst_port: u16, protocol: u8) -> Self {
+        Self {
+            saddr: v4_mapped(saddr),
+            daddr: v4_mapped(daddr),
+            src_port,
+            dst_port,
+            family: FLOW_FAMILY_V4,
+            protocol,
+            reserved: 0,
+        }
+    }
+
+    pub fn from_v6(saddr: [u8; 16], daddr: [u8; 16], src_port: u16, dst_port: u16, protocol: u8) -> Self {
+        Self {
+            saddr,
+            daddr,
+            src_port,
+            dst_port,
+            family: FLOW_FAMILY_V6,
+            protocol,
+            reserved: 0,
+        }
+    }
+
+    pub fn src_ip(&self) -> core::net::IpAddr {
+        self.ip(&self.saddr)
+    }
+
+    pub fn dst_ip(&self) -> core::net::IpAddr {
+        self.ip(&self.daddr)
+    }
+
+    fn ip(&self, addr: &[u8; 16]) -> core::net::IpAddr {
+        if self.family == FLOW_FAMILY_V4 {
+            core::net::Ipv4Addr::new(addr[12], addr[13], addr[14], addr[15]).into()
+        } else {
+            core::net::Ipv6Addr::from(*addr).into()
+        }
+    }
+}
+
+// 网络字节序的 IPv4 地址转换为 ::ffff:a.b.c.d
+fn v4_mapped(addr: u32) -> [u8; 16] {
+    let v4 = addr.to_ne_bytes();
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, v4[0], v4[1], v4[2], v4[3]]
 }
 
 // 定义设备连接统计结构，供用户空间和内核空间共享
@@ -37,13 +109,781 @@ pub struct DeviceStats {
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 pub struct DeviceConnectionStats {
     pub device_id: u32,      // 设备ID
-    pub src_port: u16,       // 源端口
-    pub dst_port: u16,       // 目标端口
     pub direction: u32,      // 方向: 0=ingress, 1=egress (使用u32确保对齐)
-    pub protocol: u32,       // 协议: 6=TCP, 17=UDP (使用u32确保对齐)
-    pub timestamp: u64,      // 时间戳
+    pub flow: FlowTuple,     // 地址、端口和协议(6=TCP, 17=UDP)
+    pub timestamp: u64,      // 最后一个包的 bpf_ktime_get_ns
     pub total_packets: u64,  // 总包数
     pub total_bytes: u64,    // 总字节数
+    pub retransmissions: u64, // TCP重传包数
+    pub dup_acks: u64,       // TCP重复ACK数
+    pub last_window: u32,    // 最近一次观测到的TCP窗口大小
+    pub min_window: u32,     // 观测到的最小TCP窗口
+    pub max_window: u32,     // 观测到的最大TCP窗口
+    pub reserved: u32,
+    pub netns_cookie: u64,   // 所在网络命名空间的 cookie，内核不支持时为0
+    pub first_seen: u64,     // 第一个包的 bpf_ktime_get_ns
+    pub socket_cookie: u64,  // 本机 socket 的 bpf_get_socket_cookie，只有本机发出的包能取到，取不到时为0
+}
+
+// 设备按目的MAC类型区分的流量，key 与 device_stats 相同
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct DeviceCastStats {
+    pub unicast_packets: u64,
+    pub unicast_bytes: u64,
+    pub broadcast_packets: u64,
+    pub broadcast_bytes: u64,
+    pub multicast_packets: u64,
+    pub multicast_bytes: u64,
+}
+
+// 设备统计的key，不同网络命名空间中的设备可能使用相同的 ifindex
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct DeviceStatsKey {
+    pub netns_cookie: u64, // 所在网络命名空间的 cookie，内核不支持时为0
+    pub key: u32,          // 设备ID*2 + 方向，偶数为ingress，奇数为egress
+    pub reserved: u32,
+}
+
+// 按设备、方向和 IP 协议号统计流量的key，IPv6 取固定头中的 next header
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ProtocolStatsKey {
+    pub device: DeviceStatsKey,
+    pub protocol: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ProtocolStats {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+// ICMPv6 类型
+pub const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub const ICMPV6_ECHO_REPLY: u8 = 129;
+pub const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+pub const ICMPV6_ROUTER_ADVERT: u8 = 134;
+pub const ICMPV6_NEIGHBOR_SOLICIT: u8 = 135;
+pub const ICMPV6_NEIGHBOR_ADVERT: u8 = 136;
+pub const ICMPV6_REDIRECT: u8 = 137;
+
+// 按 ICMPv6 类型统计的包数，key 与 device_stats 相同
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct Icmpv6Stats {
+    pub echo_request: u64,
+    pub echo_reply: u64,
+    pub router_solicit: u64,
+    pub router_advert: u64,
+    pub neighbor_solicit: u64,
+    pub neighbor_advert: u64,
+    pub redirect: u64,
+    pub other: u64,
+}
+
+// 定义远端IP的行为信号，由XDP程序统计，用户空间据此计算IP信誉分
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct IpSignals {
+    pub syn_packets: u64,           // 新建连接请求(SYN)数
+    pub handshakes_completed: u64,  // 完成三次握手的连接数
+    pub rst_packets: u64,           // RST包数
+    pub port_switches: u64,         // 连续SYN目的端口变化次数，用于识别端口扫描
+    pub dropped_syns: u64,          // 因信誉分过低被丢弃的SYN数
+    pub last_dst_port: u16,         // 最近一次SYN的目的端口
+    pub reserved: [u8; 6],
+}
+
+// bogon 前缀表容量，前缀的编号即 bogon_stats 的下标
+pub const BOGON_MAX_PREFIXES: u32 = 256;
+// 设备的 bogon 处理方式
+pub const BOGON_MODE_OFF: u32 = 0;
+pub const BOGON_MODE_COUNT: u32 = 1;
+pub const BOGON_MODE_DROP: u32 = 2;
+
+// 源地址命中 bogon 前缀的包数和字节数
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct BogonStats {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+// 威胁情报订阅源数量上限，订阅源编号即 blocklist_stats 的下标
+pub const BLOCKLIST_MAX_FEEDS: u32 = 64;
+
+// 按国家拒绝入站流量的规则数量上限，规则编号即 geo_rules 和 geo_stats 的下标
+pub const GEO_MAX_RULES: u32 = 64;
+pub const GEO_MAX_EXCEPT_PORTS: usize = 8;
+
+// 按国家拒绝入站流量的规则，目的端口在例外列表中的 TCP/UDP 包放行
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct GeoRule {
+    pub except_ports: [u16; GEO_MAX_EXCEPT_PORTS], // 主机字节序，0 表示列表结束
+}
+
+// TTL 分布的桶数，按 TTL/32 分桶
+pub const TTL_BUCKETS: usize = 8;
+// 同一源IP相邻两个包的 TTL 相差超过该值时计为一次跳变，
+// 正常路径变化通常只有几跳，伪造源地址或注入的包往往来自完全不同的距离
+pub const TTL_JUMP_THRESHOLD: u8 = 10;
+
+// 每个源IP观测到的TTL分布，由XDP程序统计
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct TtlStats {
+    pub packets: u64,
+    pub jumps: u64,                       // TTL 跳变次数
+    pub histogram: [u32; TTL_BUCKETS],
+    pub last_ttl: u8,
+    pub min_ttl: u8,
+    pub max_ttl: u8,
+    pub reserved: [u8; 5],
+}
+
+// XDP连接跟踪的TCP状态
+// XDP 解析程序尾调用表的槽位，没有对应程序的槽位尾调用失败后按默认方式处理
+pub const XDP_PARSER_IPV4: u32 = 0;
+pub const XDP_PARSER_IPV6: u32 = 1;
+pub const XDP_PARSER_TCP: u32 = 2;
+pub const XDP_PARSER_UDP: u32 = 3;
+pub const XDP_PARSER_ICMP: u32 = 4;
+pub const XDP_PARSER_MAX: u32 = 8;
+
+pub const TCP_STATE_SYN_SENT: u32 = 1;
+pub const TCP_STATE_ESTABLISHED: u32 = 2;
+pub const TCP_STATE_FIN_WAIT: u32 = 3;
+pub const TCP_STATE_TIME_WAIT: u32 = 4;
+pub const TCP_STATE_CLOSED: u32 = 5;
+
+// 各状态的空闲超时(秒)，用户空间和内核中的垃圾回收共用，未知状态按 closed 处理
+pub const fn conntrack_timeout_secs(state: u32) -> u64 {
+    match state {
+        TCP_STATE_SYN_SENT => 60,
+        TCP_STATE_ESTABLISHED => 3600,
+        TCP_STATE_FIN_WAIT => 120,
+        TCP_STATE_TIME_WAIT => 120,
+        _ => 10,
+    }
+}
+
+// 定义XDP连接跟踪条目，用户空间按状态超时清理
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ConnTrackEntry {
+    pub state: u32,         // TCP_STATE_*
+    pub reserved: u32,
+    pub last_seen_ns: u64,  // 最后一个包的 bpf_ktime_get_ns
+}
+
+// 采样包头的最大长度
+pub const SAMPLE_HEADER_LEN: usize = 128;
+
+// 定义包采样事件，由TC程序通过ring buffer推送给用户空间，用于sFlow导出
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct PacketSample {
+    pub ifindex: u32,                      // 采样所在设备的ifindex
+    pub direction: u32,                    // 方向: 0=ingress, 1=egress
+    pub frame_len: u32,                    // 原始帧长度
+    pub header_len: u32,                   // 实际拷贝的包头长度
+    pub header: [u8; SAMPLE_HEADER_LEN],   // 截断后的包头
+}
+
+// 抓包时每个包保留的最大长度
+pub const CAPTURE_SNAPLEN: usize = 256;
+
+// 抓包过滤条件，由用户空间写入 capture_config map
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct CaptureFilter {
+    pub enabled: u32,   // 是否正在抓包
+    pub ifindex: u32,   // 设备ifindex, 0表示任意设备
+    pub ip: u32,        // IPv4地址(网络字节序), 匹配源或目的, 0表示任意
+    pub port: u16,      // 端口, 匹配源或目的, 0表示任意
+    pub reserved: u16,
+}
+
+// 抓到的包，由TC程序通过ring buffer推送给用户空间
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct CapturedPacket {
+    pub timestamp_ns: u64,                 // bpf_ktime_get_ns 时间戳
+    pub ifindex: u32,                      // 设备ifindex
+    pub direction: u32,                    // 方向: 0=ingress, 1=egress
+    pub frame_len: u32,                    // 原始帧长度
+    pub header_len: u32,                   // 实际拷贝的长度
+    pub header: [u8; CAPTURE_SNAPLEN],     // 截断后的包数据
+}
+
+// 连接结束原因
+pub const CONNECTION_CLOSE_FIN: u32 = 1;
+pub const CONNECTION_CLOSE_RST: u32 = 2;
+
+// TCP连接生命周期事件，连接结束(FIN/RST)时由TC程序通过ring buffer推送给用户空间
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ConnectionEvent {
+    pub start_ns: u64,         // SYN 的 bpf_ktime_get_ns 时间戳
+    pub end_ns: u64,           // FIN/RST 的时间戳
+    pub packets: u64,          // 双向总包数
+    pub bytes: u64,            // 双向总字节数
+    pub retransmissions: u64,  // 重传包数
+    pub flow: FlowTuple,       // 源为发起方，目的为接收方
+    pub device_id: u32,        // 设备ID
+    pub close_reason: u32,     // 结束原因: CONNECTION_CLOSE_FIN / CONNECTION_CLOSE_RST
+}
+
+// 延迟直方图的桶数，第i个桶统计 [2^i, 2^(i+1)) 微秒的样本，第0个桶包含 [0, 2)
+pub const LATENCY_BUCKETS: u32 = 32;
+// 延迟类型: 三次握手(SYN到SYN-ACK)延迟、同一流相邻包的间隔
+pub const LATENCY_KIND_HANDSHAKE: u8 = 0;
+pub const LATENCY_KIND_INTER_PACKET: u8 = 1;
+
+// 延迟直方图的key，value为该桶的样本数
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct LatencyHistKey {
+    pub device_id: u32, // 设备ID
+    pub port: u16,      // 服务端口
+    pub kind: u8,       // 延迟类型
+    pub bucket: u8,     // 桶序号
+}
+
+// 负载均衡的服务数和每个服务的后端数上限，后端按 服务ID * LB_MAX_BACKENDS + 序号 存放
+pub const LB_MAX_SERVICES: u32 = 64;
+pub const LB_MAX_BACKENDS: u32 = 32;
+
+// 负载均衡虚拟服务的匹配条件
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct LbVipKey {
+    pub addr: u32,    // VIP(网络字节序)
+    pub port: u16,    // 端口(网络字节序)
+    pub protocol: u8, // 6: TCP, 17: UDP
+    pub reserved: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct LbService {
+    pub id: u32,            // 服务ID, 0 ~ LB_MAX_SERVICES-1
+    pub backend_count: u32, // 有效后端数
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct LbBackend {
+    pub addr: u32,    // 后端IP(网络字节序)
+    pub port: u16,    // 后端端口(网络字节序)
+    pub mac: [u8; 6], // 下一跳MAC地址
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct LbBackendStats {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+pub const NAT_KIND_DNAT: u8 = 0;
+pub const NAT_KIND_SNAT: u8 = 1;
+
+// 无状态NAT规则的匹配条件，DNAT匹配目的地址，SNAT匹配源地址
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct NatKey {
+    pub addr: u32,    // IP(网络字节序)
+    pub port: u16,    // 端口(网络字节序)
+    pub protocol: u8, // 6: TCP, 17: UDP
+    pub kind: u8,     // NAT_KIND_DNAT / NAT_KIND_SNAT
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct NatRewrite {
+    pub addr: u32, // 改写后的IP(网络字节序)
+    pub port: u16, // 改写后的端口(网络字节序)
+    pub reserved: u16,
+    pub packets: u64, // 命中的包数
+}
+
+pub const SHAPING_MAX_RULES: u32 = 64;
+pub const SHAPING_MODE_DEVICE: u32 = 0;
+pub const SHAPING_MODE_FLOW: u32 = 1;
+
+// 出方向整形规则，key 为设备 ifindex
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ShapingRule {
+    pub rate: u64,       // 速率(字节/秒)
+    pub horizon_ns: u64, // 发送时间超过当前时间该值时丢包
+    pub mode: u32,       // SHAPING_MODE_DEVICE: 整个设备共享速率, SHAPING_MODE_FLOW: 每条流独立限速
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ShapingStats {
+    pub packets: u64,
+    pub bytes: u64,
+    pub delayed: u64,        // 被推迟发送的包数
+    pub dropped: u64,        // 超过 horizon 被丢弃的包数
+    pub total_delay_ns: u64, // 推迟时间累计
+}
+
+pub const QUOTA_MAX_DEVICES: u32 = 64;
+pub const QUOTA_ACTION_DROP: u32 = 0;
+pub const QUOTA_ACTION_THROTTLE: u32 = 1;
+
+// 设备出方向配额的执行状态，key 为设备 ifindex，由用户空间按用量写入
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct QuotaState {
+    pub over: u32,          // 非0时本周期的配额已用完
+    pub action: u32,        // QUOTA_ACTION_DROP: 丢弃, QUOTA_ACTION_THROTTLE: 按 throttle_rate 限速
+    pub throttle_rate: u64, // 超出配额后的速率(字节/秒)
+}
+
+// 设备出方向配额的用量，由 TC egress 程序累加
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct QuotaUsage {
+    pub packets: u64,
+    pub bytes: u64,     // 发出的字节数，不含被丢弃的包
+    pub dropped: u64,   // 超出配额被丢弃的包数
+    pub throttled: u64, // 超出配额被推迟发送的包数
+}
+
+// DSCP重标记规则的匹配条件，addr/port 为0表示不限，按端口匹配时 protocol 必填
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct DscpKey {
+    pub addr: u32,    // 源或目的IP(网络字节序)
+    pub port: u16,    // 源或目的端口(网络字节序)
+    pub protocol: u8, // 6: TCP, 17: UDP, 0: 不限
+    pub reserved: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct DscpMark {
+    pub dscp: u8, // 0-63
+    pub reserved: [u8; 7],
+    pub packets: u64, // 命中的包数
+}
+
+// skb mark 规则与DSCP重标记规则使用相同的匹配条件
+pub type MarkKey = DscpKey;
+
+pub const MARK_MAX_RULES: u32 = 1024;
+pub const MARK_SKIP_MAX: u32 = 8;
+
+// skb mark 规则把命中的包的 mark 改写为 (mark & !mask) | value，
+// 跳过条件匹配 mark & mask == value 的包，mask 为0的跳过条件未使用
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct MarkEntry {
+    pub value: u32,
+    pub mask: u32,
+    pub direction: u8, // FIREWALL_DIRECTION_*
+    pub reserved: [u8; 7],
+    pub packets: u64, // 命中的包数
+}
+
+// sock_ops 程序记录的 TCP socket，地址为网络字节序，端口为主机字节序
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct SocketKey {
+    pub local_addr: u32,
+    pub remote_addr: u32,
+    pub local_port: u16,
+    pub remote_port: u16,
+}
+
+// 内核 TCP 协议栈中的 socket 指标
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct SocketStats {
+    pub bytes_acked: u64,    // 对端确认的发送字节数
+    pub bytes_received: u64, // 接收字节数
+    pub established_ns: u64, // 连接建立时间(bpf_ktime_get_ns)
+    pub last_update_ns: u64, // 最近一次更新时间
+    pub rtt_samples: u64,    // RTT 采样次数
+    pub srtt_us: u32,        // 平滑RTT(微秒)
+    pub rtt_min_us: u32,     // 最小RTT(微秒)
+    pub snd_cwnd: u32,       // 拥塞窗口(包数)
+    pub snd_ssthresh: u32,   // 慢启动阈值
+    pub total_retrans: u32,  // 重传段数
+    pub segs_in: u32,
+    pub segs_out: u32,
+    pub state: u32, // 内核 TCP 状态，如 1: ESTABLISHED, 7: CLOSE
+    pub cookie: u64, // bpf_get_socket_cookie，用于关联 TC 连接统计和 socket_owners
+}
+
+// 发起 connect() 或首次 sendmsg() 的进程，key 为 socket cookie
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct SocketOwner {
+    pub timestamp_ns: u64, // 记录时间(bpf_ktime_get_ns)
+    pub cgroup_id: u64,
+    pub pid: u32,
+    pub reserved: u32,
+    pub comm: [u8; 16], // 进程名
+}
+
+// sock:inet_sock_set_state 跟踪点记录的 TCP socket 状态，key 为 SocketKey
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct TcpSockState {
+    pub opened_ns: u64,   // 首次观测到状态变化的时间(bpf_ktime_get_ns)
+    pub changed_ns: u64,  // 最近一次状态变化的时间
+    pub state: u32,       // 内核 TCP 状态，如 1: ESTABLISHED, 7: CLOSE
+    pub old_state: u32,   // 变化前的状态
+    pub transitions: u32, // 状态变化次数
+    pub reserved: u32,
+}
+
+// sock:inet_sock_set_state 跟踪点各字段在 struct trace_event_raw_inet_sock_set_state 中的偏移
+// 加载时由用户空间按内核 BTF 设置，同一个 eBPF 目标文件可在字段布局不同的内核上运行
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct TcpStateOffsets {
+    pub oldstate: u32,
+    pub newstate: u32,
+    pub sport: u32,
+    pub dport: u32,
+    pub family: u32,
+    pub protocol: u32,
+    pub saddr: u32,
+    pub daddr: u32,
+}
+
+impl TcpStateOffsets {
+    // 没有 BTF 时使用的偏移，与 5.x/6.x 内核的 tracefs format 一致
+    pub const DEFAULT: TcpStateOffsets = TcpStateOffsets {
+        oldstate: 16,
+        newstate: 20,
+        sport: 24,
+        dport: 26,
+        family: 28,
+        protocol: 30,
+        saddr: 32,
+        daddr: 36,
+    };
+}
+
+// uprobe 统计的 TLS 连接，同一进程内按 SSL 对象地址区分
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct TlsConnKey {
+    pub tgid: u32, // 进程ID
+    pub reserved: u32,
+    pub ssl: u64, // SSL* 地址
+}
+
+// SSL_read/SSL_write 返回的明文字节数
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct TlsConnStats {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub reads: u64,
+    pub writes: u64,
+    pub last_ns: u64,     // 最近一次读写的 bpf_ktime_get_ns
+    pub fd: i32,          // SSL_set_fd 设置的 socket fd，-1 表示未知
+    pub reserved: u32,
+    pub comm: [u8; 16],   // 进程名
+}
+
+// 出方向连接策略的匹配条件，cgroup_id/addr/port 为0表示不限
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct EgressPolicyKey {
+    pub cgroup_id: u64, // cgroup v2 ID(目录 inode)
+    pub addr: u32,      // 目的IP(网络字节序)
+    pub port: u16,      // 目的端口(网络字节序)
+    pub reserved: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct EgressPolicyRule {
+    pub denied: u64, // 拒绝的连接数
+}
+
+// 被策略拒绝的 connect()，由 LSM 程序通过 ring buffer 推送给用户空间审计
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct EgressDenial {
+    pub timestamp_ns: u64, // bpf_ktime_get_ns 时间戳
+    pub cgroup_id: u64,
+    pub pid: u32,
+    pub addr: u32, // 目的IP(网络字节序)
+    pub port: u16, // 目的端口(主机字节序)
+    pub reserved: u16,
+    pub comm: [u8; 16], // 进程名
+    pub reserved2: u32,
+}
+
+// 内核通过 xnet_events ring buffer 推送给用户空间的事件，每个事件以 EventHeader 开头
+// 事件结构变化时递增 EVENT_VERSION，用户空间丢弃版本不一致的事件
+pub const EVENT_VERSION: u16 = 1;
+pub const EVENT_CONN_OPEN: u16 = 1;
+pub const EVENT_CONN_CLOSE: u16 = 2;
+pub const EVENT_RULE_HIT: u16 = 3;
+pub const EVENT_ALERT: u16 = 4;
+pub const EVENT_HONEYPOT_PROBE: u16 = 5;
+pub const EVENT_DNS_QUERY: u16 = 6;
+
+// 信誉分过低的源地址新建连接被丢弃
+pub const ALERT_REPUTATION_BLOCK: u32 = 1;
+// 非法 TCP 标志组合，value 为包中的标志位
+pub const ALERT_TCP_NULL_SCAN: u32 = 2;
+pub const ALERT_TCP_FIN_SCAN: u32 = 3;
+pub const ALERT_TCP_XMAS_SCAN: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct EventHeader {
+    pub version: u16,
+    pub kind: u16, // EVENT_*
+    pub reserved: u32,
+    pub timestamp_ns: u64, // bpf_ktime_get_ns 时间戳
+}
+
+// 事件涉及的四元组
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct EventFlow {
+    pub saddr: u32,    // 源IP(网络字节序)
+    pub daddr: u32,    // 目的IP(网络字节序)
+    pub src_port: u16, // 主机字节序
+    pub dst_port: u16, // 主机字节序
+    pub protocol: u8,
+    pub reserved: [u8; 3],
+}
+
+// XDP 程序看到的新建 TCP 连接(SYN)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ConnOpen {
+    pub header: EventHeader,
+    pub flow: EventFlow,
+    pub ifindex: u32,
+    pub reserved: u32,
+}
+
+// XDP 程序看到的 TCP 连接结束，双方都发出 FIN 或收到 RST 时推送
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct ConnClose {
+    pub header: EventHeader,
+    pub flow: EventFlow,
+    pub ifindex: u32,
+    pub reason: u32, // CONNECTION_CLOSE_FIN / CONNECTION_CLOSE_RST
+}
+
+// 命中动作为 log 的防火墙规则
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct RuleHit {
+    pub header: EventHeader,
+    pub flow: EventFlow,
+    pub rule_id: u32,
+    pub action: u8,    // FIREWALL_ACTION_*
+    pub direction: u8, // FIREWALL_DIRECTION_INGRESS / FIREWALL_DIRECTION_EGRESS
+    pub reserved: u16,
+}
+
+// 内核检测到的异常
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct Alert {
+    pub header: EventHeader,
+    pub flow: EventFlow,
+    pub kind: u32, // ALERT_*
+    pub value: u32,
+}
+
+// 访问蜜罐端口的包，带完整的包头信息
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct HoneypotProbe {
+    pub header: EventHeader,
+    pub flow: EventFlow,
+    pub ifindex: u32,
+    pub len: u32, // 包长度
+    pub ttl: u8,
+    pub tcp_flags: u8, // UDP 为 0
+    pub action: u8,    // HONEYPOT_ACTION_*
+    pub reserved: [u8; 5],
+}
+
+// tcp_scan_config 的取值，只计数或计数并丢弃非法标志组合的包
+pub const TCP_SCAN_COUNT: u32 = 0;
+pub const TCP_SCAN_DROP: u32 = 1;
+
+// 每个源IP(网络字节序)发出的非法 TCP 标志组合
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct TcpScanStats {
+    pub null: u64, // 没有任何标志
+    pub fin: u64,  // 只有 FIN
+    pub xmas: u64, // FIN、PSH、URG 且没有 ACK
+    pub last_seen_ns: u64,
+    pub last_dst_port: u16,
+    pub last_flags: u8,
+    pub reserved: [u8; 5],
+}
+
+// DnsQuery 中复制的 DNS 报文长度上限，足够容纳最长 255 字节的查询名
+pub const DNS_CAPTURE_LEN: usize = 256;
+
+// 发往 53 端口的 UDP 包，开启 DNS 检查后由 XDP 程序推送
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct DnsQuery {
+    pub header: EventHeader,
+    pub flow: EventFlow,
+    pub ifindex: u32,
+    pub len: u32, // payload 中有效的字节数
+    pub payload: [u8; DNS_CAPTURE_LEN], // 从 DNS 头开始的报文
+}
+
+pub const HONEYPOT_MAX_PORTS: u32 = 256;
+pub const HONEYPOT_ACTION_LOG: u8 = 0;
+pub const HONEYPOT_ACTION_DROP: u8 = 1;
+pub const HONEYPOT_PROTO_TCP: u8 = 1;
+pub const HONEYPOT_PROTO_UDP: u8 = 2;
+
+// 蜜罐端口配置，按目的端口(主机字节序)索引
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct HoneypotPort {
+    pub action: u8,    // HONEYPOT_ACTION_*
+    pub protocols: u8, // HONEYPOT_PROTO_* 的组合
+    pub reserved: u16,
+}
+
+// 每个源IP(网络字节序)访问蜜罐端口的统计
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct HoneypotSource {
+    pub probes: u64,
+    pub first_seen_ns: u64, // bpf_ktime_get_ns 时间戳
+    pub last_seen_ns: u64,
+    pub last_port: u16,
+    pub last_protocol: u8,
+    pub reserved: [u8; 5],
+}
+
+pub const FIREWALL_MAX_RULES: u32 = 256;
+// 信任名单容量，条目编号即 firewall_allowlist_stats 的下标
+pub const FIREWALL_ALLOWLIST_MAX: u32 = 256;
+pub const FIREWALL_DIRECTION_ANY: u8 = 0;
+pub const FIREWALL_DIRECTION_INGRESS: u8 = 1;
+pub const FIREWALL_DIRECTION_EGRESS: u8 = 2;
+pub const FIREWALL_ACTION_ALLOW: u8 = 0;
+pub const FIREWALL_ACTION_DENY: u8 = 1;
+pub const FIREWALL_ACTION_LOG: u8 = 2;
+pub const FIREWALL_ACTION_RATELIMIT: u8 = 3;
+
+// 防火墙规则表中的一条规则，按优先级排好序后由用户空间写入
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct FirewallRuleEntry {
+    pub id: u32,            // 规则ID
+    pub src_addr: u32,      // 源网段(网络字节序，已按掩码截断)
+    pub src_mask: u32,      // 源网段掩码(网络字节序)，0表示不限
+    pub dst_addr: u32,      // 目的网段(网络字节序，已按掩码截断)
+    pub dst_mask: u32,      // 目的网段掩码(网络字节序)，0表示不限
+    pub src_port_min: u16,  // 源端口范围(主机字节序)，0-65535表示不限
+    pub src_port_max: u16,
+    pub dst_port_min: u16,  // 目的端口范围(主机字节序)，0-65535表示不限
+    pub dst_port_max: u16,
+    pub protocol: u8,       // 6: TCP, 17: UDP, 1: ICMP, 0: 不限
+    pub direction: u8,      // FIREWALL_DIRECTION_*
+    pub action: u8,         // FIREWALL_ACTION_*
+    pub reserved: u8,
+    pub rate_pps: u32,      // FIREWALL_ACTION_RATELIMIT 允许的每秒包数
+}
+
+// 防火墙规则的命中统计，按规则ID索引
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct FirewallRuleStats {
+    pub packets: u64,
+    pub bytes: u64,
+    pub last_hit_ns: u64, // 最近一次命中的 bpf_ktime_get_ns，0表示未命中
+    pub dropped: u64,     // deny 及超过速率的 ratelimit 丢弃的包数
+}
+
+// 限速规则的计数窗口，按规则ID索引
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct FirewallRateState {
+    pub window_start_ns: u64,
+    pub packets: u64, // 当前窗口内匹配的包数
+}
+
+// 设备的默认策略，key 为 ifindex
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct FirewallIfacePolicy {
+    pub default_deny: u32, // 非0时丢弃未被规则放行且不是出方向流回包的入方向包
+    pub reserved: u32,
+    pub dropped: u64, // 因默认拒绝丢弃的包数
+}
+
+// 本机发出的流，地址为网络字节序，端口为主机字节序
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct FirewallFlowKey {
+    pub local_addr: u32,
+    pub remote_addr: u32,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub protocol: u8,
+    pub reserved: [u8; 3],
+}
+
+pub const MAC_MAX_RULES: u32 = 1024;
+pub const MAC_ACTION_ALLOW: u32 = 0;
+pub const MAC_ACTION_DENY: u32 = 1;
+pub const MAC_MODE_DENYLIST: u32 = 0;
+pub const MAC_MODE_ALLOWLIST: u32 = 1;
+
+// 二层规则和统计的 key
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+pub struct MacKey {
+    pub addr: [u8; 6],
+    pub reserved: u16,
+}
+
+// 按源MAC匹配的二层规则
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct MacRule {
+    pub action: u32, // MAC_ACTION_*
+    pub reserved: u32,
+    pub packets: u64, // 命中的包数
+}
+
+// 每个MAC发出(作为源MAC)和收到(作为单播目的MAC)的流量
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct MacStats {
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
 }
 
 // Add aya::Pod implementation for PortStats when aya feature is enabled
@@ -58,6 +898,34 @@ unsafe impl aya::Pod for DeviceStats {}
 #[cfg(feature = "aya")]
 unsafe impl aya::Pod for DeviceConnectionStats {}
 
+// Add aya::Pod implementation for DeviceStatsKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for DeviceStatsKey {}
+
+// Add aya::Pod implementation for IpSignals when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for IpSignals {}
+
+// Add aya::Pod implementation for ConnTrackEntry when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for ConnTrackEntry {}
+
+// Add aya::Pod implementation for PacketSample when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for PacketSample {}
+
+// Add aya::Pod implementation for CaptureFilter when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for CaptureFilter {}
+
+// Add aya::Pod implementation for CapturedPacket when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for CapturedPacket {}
+
+// Add aya::Pod implementation for LatencyHistKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for LatencyHistKey {}
+
 // 存储IP地址的静态缓冲区
 static mut IP_BUFFER: [u8; 16] = [0; 16];
 
@@ -115,5 +983,174 @@ fn write_num(buf: &mut [u8], num: u32) -> usize {
     i
 }
 
-#[cfg(test)]
-mod tests {}
+// Add aya::Pod implementation for ConnectionEvent when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for ConnectionEvent {}
+
+// Add aya::Pod implementation for LbVipKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for LbVipKey {}
+
+// Add aya::Pod implementation for LbService when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for LbService {}
+
+// Add aya::Pod implementation for LbBackend when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for LbBackend {}
+
+// Add aya::Pod implementation for LbBackendStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for LbBackendStats {}
+
+// Add aya::Pod implementation for NatKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for NatKey {}
+
+// Add aya::Pod implementation for NatRewrite when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for NatRewrite {}
+
+// Add aya::Pod implementation for ShapingRule when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for ShapingRule {}
+
+// Add aya::Pod implementation for ShapingStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for ShapingStats {}
+
+// Add aya::Pod implementation for QuotaState when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for QuotaState {}
+
+// Add aya::Pod implementation for QuotaUsage when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for QuotaUsage {}
+
+// Add aya::Pod implementation for DscpKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for DscpKey {}
+
+// Add aya::Pod implementation for DscpMark when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for DscpMark {}
+
+// Add aya::Pod implementation for MarkEntry when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for MarkEntry {}
+
+// Add aya::Pod implementation for SocketKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for SocketKey {}
+
+// Add aya::Pod implementation for SocketStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for SocketStats {}
+
+// Add aya::Pod implementation for SocketOwner when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for SocketOwner {}
+
+// Add aya::Pod implementation for TcpSockState when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for TcpSockState {}
+
+// Add aya::Pod implementation for TcpStateOffsets when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for TcpStateOffsets {}
+
+// Add aya::Pod implementation for TlsConnKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for TlsConnKey {}
+
+// Add aya::Pod implementation for TlsConnStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for TlsConnStats {}
+
+// Add aya::Pod implementation for EgressPolicyKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for EgressPolicyKey {}
+
+// Add aya::Pod implementation for EgressPolicyRule when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for EgressPolicyRule {}
+
+// Add aya::Pod implementation for EgressDenial when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for EgressDenial {}
+
+// Add aya::Pod implementation for FirewallRuleEntry when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for FirewallRuleEntry {}
+
+// Add aya::Pod implementation for FirewallRuleStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for FirewallRuleStats {}
+
+// Add aya::Pod implementation for FirewallRateState when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for FirewallRateState {}
+
+// Add aya::Pod implementation for FirewallIfacePolicy when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for FirewallIfacePolicy {}
+
+// Add aya::Pod implementation for FirewallFlowKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for FirewallFlowKey {}
+
+// Add aya::Pod implementation for MacKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for MacKey {}
+
+// Add aya::Pod implementation for MacRule when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for MacRule {}
+
+// Add aya::Pod implementation for MacStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for MacStats {}
+
+// Add aya::Pod implementation for DeviceCastStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for DeviceCastStats {}
+
+// Add aya::Pod implementation for ProtocolStatsKey when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for ProtocolStatsKey {}
+
+// Add aya::Pod implementation for ProtocolStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for ProtocolStats {}
+
+// Add aya::Pod implementation for TtlStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for TtlStats {}
+
+// Add aya::Pod implementation for BogonStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for BogonStats {}
+
+// Add aya::Pod implementation for Icmpv6Stats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for Icmpv6Stats {}
+
+// Add aya::Pod implementation for FlowTuple when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for FlowTuple {}
+
+// Add aya::Pod implementation for GeoRule when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for GeoRule {}
+
+// Add aya::Pod implementation for TcpScanStats when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for TcpScanStats {}
+
+// Add aya::Pod implementation for HoneypotPort when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for HoneypotPort {}
+
+// Add aya::Pod implementation for HoneypotSource when aya feature is enabled
+#[cfg(feature = "aya")]
+unsafe impl aya::Pod for HoneypotSource {}
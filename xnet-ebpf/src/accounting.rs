@@ -0,0 +1,55 @@
+use aya_ebpf::{
+    helpers::bpf_xdp_adjust_meta,
+    macros::map,
+    maps::HashMap,
+    programs::{TcContext, XdpContext},
+};
+
+// XDP 程序写入包元数据的标记，tc 程序看到该标记时说明包已由 XDP 计入总统计
+const SEEN_MARK: u32 = 0x786e_6574; // "xnet"
+
+// 总包数(key 0)和总字节数(key 1)，XDP 和 tc 程序共用，每个包只计一次:
+// 挂载了XDP防火墙的设备上由 XDP 计入收到的包，其余的包(发出的包、未挂载XDP的设备收到的包)由 tc 计入
+#[map(name = "total_stats")]
+static mut TOTAL_STATS: HashMap<u32, u64> = HashMap::pinned(2, 0);
+
+fn add(key: u32, value: u64) {
+    unsafe {
+        match TOTAL_STATS.get_ptr_mut(&key) {
+            Some(total) => *total += value,
+            None => {
+                let _ = TOTAL_STATS.insert(&key, &value, 0);
+            }
+        }
+    }
+}
+
+pub fn count_total(packet_len: u64) {
+    add(0, 1);
+    add(1, packet_len);
+}
+
+// 在包前写入标记并计入总统计，驱动不支持元数据时不计入，由 tc 计入
+// 会使之前读取的 data/data_end 失效，需在解析包之前调用
+pub fn mark_seen(ctx: &XdpContext, packet_len: u64) {
+    let size = core::mem::size_of::<u32>();
+    if unsafe { bpf_xdp_adjust_meta(ctx.ctx, -(size as i32)) } != 0 {
+        return;
+    }
+    let meta = ctx.metadata();
+    if meta + size > ctx.data() {
+        return;
+    }
+    unsafe { *(meta as *mut u32) = SEEN_MARK };
+    count_total(packet_len);
+}
+
+// 收到的包是否已由 XDP 计入总统计
+pub fn seen_by_xdp(ctx: &TcContext) -> bool {
+    let (meta, data) = unsafe { ((*ctx.skb.skb).data_meta as usize, ctx.data()) };
+    let size = core::mem::size_of::<u32>();
+    if meta + size > data {
+        return false;
+    }
+    unsafe { *(meta as *const u32) == SEEN_MARK }
+}
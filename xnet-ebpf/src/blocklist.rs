@@ -0,0 +1,25 @@
+use aya_ebpf::{
+    bindings::BPF_F_NO_PREALLOC,
+    macros::map,
+    maps::{lpm_trie::Key, Array, LpmTrie},
+};
+use xnet_common::BLOCKLIST_MAX_FEEDS;
+
+// 威胁情报订阅源中的地址前缀(网络字节序)，值为订阅源编号，由用户空间同步
+#[map(name = "blocklist")]
+static BLOCKLIST: LpmTrie<u32, u32> = LpmTrie::pinned(65536, BPF_F_NO_PREALLOC);
+
+// 按订阅源编号统计丢弃的包数
+#[map(name = "blocklist_stats")]
+static BLOCKLIST_STATS: Array<u64> = Array::pinned(BLOCKLIST_MAX_FEEDS, 0);
+
+// 源地址在订阅源中时计数并返回 true
+pub fn blocked(src_ip: u32) -> bool {
+    let Some(feed) = BLOCKLIST.get(&Key::new(32, src_ip)) else {
+        return false;
+    };
+    if let Some(dropped) = BLOCKLIST_STATS.get_ptr_mut(*feed) {
+        unsafe { *dropped += 1 };
+    }
+    true
+}
@@ -0,0 +1,37 @@
+use aya_ebpf::{
+    bindings::BPF_F_NO_PREALLOC,
+    macros::map,
+    maps::{lpm_trie::Key, Array, HashMap, LpmTrie},
+};
+use xnet_common::{BogonStats, BOGON_MAX_PREFIXES, BOGON_MODE_DROP, BOGON_MODE_OFF};
+
+// bogon/martian 源地址前缀(网络字节序)，值为前缀编号，由用户空间写入
+#[map(name = "bogon_prefixes")]
+static BOGON_PREFIXES: LpmTrie<u32, u32> =
+    LpmTrie::pinned(BOGON_MAX_PREFIXES, BPF_F_NO_PREALLOC);
+
+// 按前缀编号统计命中的包
+#[map(name = "bogon_stats")]
+static BOGON_STATS: Array<BogonStats> = Array::pinned(BOGON_MAX_PREFIXES, 0);
+
+// 需要检查 bogon 源地址的设备(通常是面向外网的设备)，值为 BOGON_MODE_*
+#[map(name = "bogon_ifaces")]
+static BOGON_IFACES: HashMap<u32, u32> = HashMap::pinned(64, 0);
+
+// 检查收到的包的源地址，命中时计数，设备为丢弃模式时返回 true
+pub fn check(ifindex: u32, src_ip: u32, packet_len: u64) -> bool {
+    let mode = match unsafe { BOGON_IFACES.get(&ifindex) } {
+        Some(mode) if *mode != BOGON_MODE_OFF => *mode,
+        _ => return false,
+    };
+    let Some(id) = BOGON_PREFIXES.get(&Key::new(32, src_ip)) else {
+        return false;
+    };
+    if let Some(stats) = BOGON_STATS.get_ptr_mut(*id) {
+        unsafe {
+            (*stats).packets += 1;
+            (*stats).bytes += packet_len;
+        }
+    }
+    mode == BOGON_MODE_DROP
+}
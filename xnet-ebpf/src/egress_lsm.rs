@@ -0,0 +1,97 @@
+use aya_ebpf::{
+    helpers::{
+        bpf_get_current_cgroup_id, bpf_get_current_comm, bpf_get_current_pid_tgid,
+        bpf_ktime_get_ns, bpf_probe_read_kernel,
+    },
+    macros::{lsm, map},
+    maps::{HashMap, RingBuf},
+    programs::LsmContext,
+};
+
+use xnet_common::{EgressDenial, EgressPolicyKey, EgressPolicyRule};
+
+const AF_INET: u16 = 2;
+const EPERM: i32 = 1;
+
+// struct sockaddr_in
+#[repr(C)]
+struct SockAddrIn {
+    family: u16,
+    port: u16,
+    addr: u32,
+}
+
+// 出方向连接策略，由用户空间通过 /egress/rules 配置
+#[map(name = "egress_policy")]
+static EGRESS_POLICY: HashMap<EgressPolicyKey, EgressPolicyRule> =
+    HashMap::pinned(4096, 0);
+
+// 被拒绝的连接通过ring buffer推送到用户空间审计
+#[map(name = "egress_denials")]
+static EGRESS_DENIALS: RingBuf = RingBuf::pinned(256 * 1024, 0);
+
+// LSM_HOOK(int, 0, socket_connect, struct socket *sock, struct sockaddr *address, int addrlen)
+#[lsm(hook = "socket_connect")]
+pub fn xnet_egress_policy(ctx: LsmContext) -> i32 {
+    // 之前的 LSM 程序已拒绝时沿用其结果
+    let retval: i32 = unsafe { ctx.arg(3) };
+    if retval != 0 {
+        return retval;
+    }
+    let address: *const SockAddrIn = unsafe { ctx.arg(1) };
+    let addrlen: i32 = unsafe { ctx.arg(2) };
+    if addrlen < core::mem::size_of::<SockAddrIn>() as i32 {
+        return 0;
+    }
+    let Ok(sockaddr) = (unsafe { bpf_probe_read_kernel(address) }) else {
+        return 0;
+    };
+    if sockaddr.family != AF_INET {
+        return 0;
+    }
+
+    let cgroup_id = unsafe { bpf_get_current_cgroup_id() };
+    if !policy_denies(cgroup_id, sockaddr.addr, sockaddr.port) {
+        return 0;
+    }
+
+    if let Some(mut entry) = EGRESS_DENIALS.reserve::<EgressDenial>(0) {
+        entry.write(EgressDenial {
+            timestamp_ns: unsafe { bpf_ktime_get_ns() },
+            cgroup_id,
+            pid: (bpf_get_current_pid_tgid() >> 32) as u32,
+            addr: sockaddr.addr,
+            port: u16::from_be(sockaddr.port),
+            reserved: 0,
+            comm: bpf_get_current_comm().unwrap_or([0; 16]),
+            reserved2: 0,
+        });
+        entry.submit(0);
+    }
+    -EPERM
+}
+
+// 按 cgroup+IP+端口、cgroup+IP、cgroup+端口 的顺序查找，之后再查找不限 cgroup 的规则
+fn policy_denies(cgroup_id: u64, addr: u32, port: u16) -> bool {
+    let key = |cgroup_id: u64, addr: u32, port: u16| EgressPolicyKey {
+        cgroup_id,
+        addr,
+        port,
+        reserved: 0,
+    };
+    let keys = [
+        key(cgroup_id, addr, port),
+        key(cgroup_id, addr, 0),
+        key(cgroup_id, 0, port),
+        key(0, addr, port),
+        key(0, addr, 0),
+        key(0, 0, port),
+    ];
+    for key in keys.iter() {
+        if let Some(rule) = EGRESS_POLICY.get_ptr_mut(key) {
+            unsafe { (*rule).denied += 1 };
+            return true;
+        }
+    }
+    false
+}
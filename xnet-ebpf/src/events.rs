@@ -0,0 +1,115 @@
+use aya_ebpf::{helpers::bpf_ktime_get_ns, macros::map, maps::RingBuf, programs::XdpContext};
+use xnet_common::{
+    Alert, ConnClose, ConnOpen, DnsQuery, EventFlow, EventHeader, HoneypotProbe, RuleHit,
+    DNS_CAPTURE_LEN, EVENT_ALERT, EVENT_CONN_CLOSE, EVENT_CONN_OPEN, EVENT_DNS_QUERY,
+    EVENT_HONEYPOT_PROBE, EVENT_RULE_HIT, EVENT_VERSION,
+};
+
+// 连接、规则命中和告警事件通过ring buffer推送到用户空间，格式见 xnet-common 中的 EventHeader
+#[map(name = "xnet_events")]
+static XNET_EVENTS: RingBuf = RingBuf::pinned(256 * 1024, 0);
+
+fn header(kind: u16) -> EventHeader {
+    EventHeader {
+        version: EVENT_VERSION,
+        kind,
+        reserved: 0,
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+    }
+}
+
+// ring buffer 已满时丢弃事件
+fn submit<T: 'static>(event: T) {
+    if let Some(mut entry) = XNET_EVENTS.reserve::<T>(0) {
+        entry.write(event);
+        entry.submit(0);
+    }
+}
+
+// saddr、daddr 为网络字节序，端口为主机字节序
+pub fn flow(saddr: u32, daddr: u32, src_port: u16, dst_port: u16, protocol: u8) -> EventFlow {
+    EventFlow {
+        saddr,
+        daddr,
+        src_port,
+        dst_port,
+        protocol,
+        reserved: [0; 3],
+    }
+}
+
+pub fn conn_open(flow: EventFlow, ifindex: u32) {
+    submit(ConnOpen {
+        header: header(EVENT_CONN_OPEN),
+        flow,
+        ifindex,
+        reserved: 0,
+    });
+}
+
+pub fn conn_close(flow: EventFlow, ifindex: u32, reason: u32) {
+    submit(ConnClose {
+        header: header(EVENT_CONN_CLOSE),
+        flow,
+        ifindex,
+        reason,
+    });
+}
+
+pub fn rule_hit(flow: EventFlow, rule_id: u32, action: u8, direction: u8) {
+    submit(RuleHit {
+        header: header(EVENT_RULE_HIT),
+        flow,
+        rule_id,
+        action,
+        direction,
+        reserved: 0,
+    });
+}
+
+pub fn alert(flow: EventFlow, kind: u32, value: u32) {
+    submit(Alert {
+        header: header(EVENT_ALERT),
+        flow,
+        kind,
+        value,
+    });
+}
+
+pub fn honeypot_probe(flow: EventFlow, ifindex: u32, len: u32, ttl: u8, tcp_flags: u8, action: u8) {
+    submit(HoneypotProbe {
+        header: header(EVENT_HONEYPOT_PROBE),
+        flow,
+        ifindex,
+        len,
+        ttl,
+        tcp_flags,
+        action,
+        reserved: [0; 5],
+    });
+}
+
+// 复制 payload 开始的 DNS 报文，最多 DNS_CAPTURE_LEN 字节，直接写入 ring buffer 避免占用栈空间
+pub fn dns_query(ctx: &XdpContext, flow: EventFlow, ifindex: u32, payload: usize) {
+    let Some(mut entry) = XNET_EVENTS.reserve::<DnsQuery>(0) else {
+        return;
+    };
+    let event = entry.as_mut_ptr();
+    let data_end = ctx.data_end();
+    unsafe {
+        (*event).header = header(EVENT_DNS_QUERY);
+        (*event).flow = flow;
+        (*event).ifindex = ifindex;
+        let mut len = 0;
+        for i in 0..DNS_CAPTURE_LEN {
+            let byte = payload + i;
+            if byte + 1 > data_end {
+                break;
+            }
+            (*event).payload[i] = *(byte as *const u8);
+            len += 1;
+        }
+        (*event).len = len;
+    }
+    entry.submit(0);
+}
@@ -0,0 +1,227 @@
+use aya_ebpf::{
+    bindings::BPF_F_NO_PREALLOC,
+    macros::map,
+    maps::{lpm_trie::Key, Array, HashMap, LpmTrie, LruHashMap},
+};
+use xnet_common::{
+    FirewallFlowKey, FirewallIfacePolicy, FirewallRateState, FirewallRuleEntry, FirewallRuleStats,
+    FIREWALL_ACTION_ALLOW,
+    FIREWALL_ACTION_DENY, FIREWALL_ACTION_LOG, FIREWALL_ACTION_RATELIMIT, FIREWALL_DIRECTION_ANY,
+    FIREWALL_ALLOWLIST_MAX, FIREWALL_MAX_RULES,
+};
+
+use crate::events;
+
+// 防火墙规则表，分为两半，用户空间写入未生效的一半后再切换，避免更新过程中匹配到不完整的规则表
+#[map(name = "firewall_rules")]
+static FIREWALL_RULES: Array<FirewallRuleEntry> =
+    Array::pinned(FIREWALL_MAX_RULES * 2, 0);
+
+// index 0 为生效的一半(0或1)，index 1、2 分别为两半中的规则数
+#[map(name = "firewall_config")]
+static FIREWALL_CONFIG: Array<u32> = Array::pinned(3, 0);
+
+// 每条规则命中的包数、字节数和最近命中时间，按规则ID索引
+#[map(name = "firewall_rule_stats")]
+static FIREWALL_RULE_STATS: Array<FirewallRuleStats> =
+    Array::pinned(FIREWALL_MAX_RULES, 0);
+
+// 限速规则的计数窗口，按规则ID索引
+#[map(name = "firewall_ratelimit")]
+static FIREWALL_RATELIMIT: Array<FirewallRateState> =
+    Array::pinned(FIREWALL_MAX_RULES, 0);
+
+// 设备的默认策略，由用户空间通过 /firewall/default_deny 配置
+#[map(name = "firewall_iface_policy")]
+static FIREWALL_IFACE_POLICY: HashMap<u32, FirewallIfacePolicy> = HashMap::pinned(64, 0);
+
+// tc 程序看到的本机发出的流及最近发包时间，默认拒绝模式下放行这些流的回包
+#[map(name = "firewall_outbound_flows")]
+static FIREWALL_OUTBOUND_FLOWS: LruHashMap<FirewallFlowKey, u64> =
+    LruHashMap::pinned(16384, 0);
+
+// 信任的网段(网络字节序)，值为条目编号，匹配的包跳过所有丢弃逻辑
+#[map(name = "firewall_allowlist")]
+static FIREWALL_ALLOWLIST: LpmTrie<u32, u32> =
+    LpmTrie::pinned(FIREWALL_ALLOWLIST_MAX, BPF_F_NO_PREALLOC);
+
+// 信任名单条目放行的包数、字节数和最近命中时间，按条目编号索引
+#[map(name = "firewall_allowlist_stats")]
+static FIREWALL_ALLOWLIST_STATS: Array<FirewallRuleStats> =
+    Array::pinned(FIREWALL_ALLOWLIST_MAX, 0);
+
+// 出方向流超过该时间没有发包后不再放行回包
+const OUTBOUND_FLOW_TIMEOUT_NS: u64 = 300 * 1_000_000_000;
+
+// 参与规则匹配的包信息，地址为网络字节序，端口为主机字节序，非TCP/UDP时端口为0
+pub struct FirewallPacket {
+    pub saddr: u32,
+    pub daddr: u32,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+    pub direction: u8,
+    pub len: u64,
+}
+
+// 地址是否在信任名单中，不计数，供后续的解析程序跳过丢弃逻辑
+pub fn trusted(addr: u32) -> bool {
+    FIREWALL_ALLOWLIST.get(&Key::new(32, addr)).is_some()
+}
+
+// 远端地址在信任名单中时计数并返回 true，调用方跳过 bogon、黑名单、防火墙规则和默认拒绝
+pub fn allowlisted(addr: u32, len: u64, now: u64) -> bool {
+    let Some(id) = FIREWALL_ALLOWLIST.get(&Key::new(32, addr)) else {
+        return false;
+    };
+    if let Some(stats) = FIREWALL_ALLOWLIST_STATS.get_ptr_mut(*id) {
+        unsafe {
+            (*stats).packets += 1;
+            (*stats).bytes += len;
+            (*stats).last_hit_ns = now;
+        }
+    }
+    true
+}
+
+fn rule_matches(rule: &FirewallRuleEntry, packet: &FirewallPacket) -> bool {
+    if rule.direction != FIREWALL_DIRECTION_ANY && rule.direction != packet.direction {
+        return false;
+    }
+    if rule.protocol != 0 && rule.protocol != packet.protocol {
+        return false;
+    }
+    if packet.saddr & rule.src_mask != rule.src_addr
+        || packet.daddr & rule.dst_mask != rule.dst_addr
+    {
+        return false;
+    }
+    // 指定端口范围的规则只匹配TCP和UDP
+    let any_port = rule.src_port_min == 0
+        && rule.src_port_max == u16::MAX
+        && rule.dst_port_min == 0
+        && rule.dst_port_max == u16::MAX;
+    if !any_port && packet.protocol != 6 && packet.protocol != 17 {
+        return false;
+    }
+    packet.src_port >= rule.src_port_min
+        && packet.src_port <= rule.src_port_max
+        && packet.dst_port >= rule.dst_port_min
+        && packet.dst_port <= rule.dst_port_max
+}
+
+fn record_hit(rule: &FirewallRuleEntry, packet: &FirewallPacket, now: u64) {
+    if let Some(stats) = FIREWALL_RULE_STATS.get_ptr_mut(rule.id) {
+        unsafe {
+            (*stats).packets += 1;
+            (*stats).bytes += packet.len;
+            (*stats).last_hit_ns = now;
+        }
+    }
+}
+
+fn record_drop(rule: &FirewallRuleEntry) {
+    if let Some(stats) = FIREWALL_RULE_STATS.get_ptr_mut(rule.id) {
+        unsafe { (*stats).dropped += 1 };
+    }
+}
+
+// 按秒计数，窗口内匹配的包数不超过 rate_pps 时返回 true
+fn within_rate(rule: &FirewallRuleEntry, now: u64) -> bool {
+    let Some(state) = FIREWALL_RATELIMIT.get_ptr_mut(rule.id) else {
+        return true;
+    };
+    unsafe {
+        if now.wrapping_sub((*state).window_start_ns) >= 1_000_000_000 {
+            (*state).window_start_ns = now;
+            (*state).packets = 0;
+        }
+        (*state).packets += 1;
+        (*state).packets <= rule.rate_pps as u64
+    }
+}
+
+// 按优先级顺序匹配规则，Some(true) 表示丢弃，Some(false) 表示放行，None 表示没有规则给出结论
+// allow/deny 命中即停止；log 推送规则命中事件后继续匹配；ratelimit 未超过速率时放行，超过时丢弃
+pub fn evaluate(packet: &FirewallPacket, now: u64) -> Option<bool> {
+    let half = *FIREWALL_CONFIG.get(0)? & 1;
+    let count = *FIREWALL_CONFIG.get(1 + half)?;
+
+    for i in 0..FIREWALL_MAX_RULES {
+        if i >= count {
+            break;
+        }
+        let Some(rule) = FIREWALL_RULES.get(half * FIREWALL_MAX_RULES + i) else {
+            break;
+        };
+        if !rule_matches(rule, packet) {
+            continue;
+        }
+        record_hit(rule, packet, now);
+        match rule.action {
+            FIREWALL_ACTION_ALLOW => return Some(false),
+            FIREWALL_ACTION_DENY => {
+                record_drop(rule);
+                return Some(true);
+            }
+            FIREWALL_ACTION_LOG => {
+                let flow = events::flow(
+                    packet.saddr,
+                    packet.daddr,
+                    packet.src_port,
+                    packet.dst_port,
+                    packet.protocol,
+                );
+                events::rule_hit(flow, rule.id, rule.action, packet.direction);
+            }
+            FIREWALL_ACTION_RATELIMIT => {
+                let exceeded = !within_rate(rule, now);
+                if exceeded {
+                    record_drop(rule);
+                }
+                return Some(exceeded);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// 记录本机发出的包所属的流
+pub fn record_outbound(packet: &FirewallPacket, now: u64) {
+    let key = FirewallFlowKey {
+        local_addr: packet.saddr,
+        remote_addr: packet.daddr,
+        local_port: packet.src_port,
+        remote_port: packet.dst_port,
+        protocol: packet.protocol,
+        reserved: [0; 3],
+    };
+    let _ = FIREWALL_OUTBOUND_FLOWS.insert(&key, &now, 0);
+}
+
+// 未被规则放行的入方向包是否因设备的默认拒绝策略丢弃，本机发出的流的回包不丢弃
+pub fn default_denied(ifindex: u32, packet: &FirewallPacket, now: u64) -> bool {
+    let Some(policy) = FIREWALL_IFACE_POLICY.get_ptr_mut(&ifindex) else {
+        return false;
+    };
+    if unsafe { (*policy).default_deny } == 0 {
+        return false;
+    }
+
+    let key = FirewallFlowKey {
+        local_addr: packet.daddr,
+        remote_addr: packet.saddr,
+        local_port: packet.dst_port,
+        remote_port: packet.src_port,
+        protocol: packet.protocol,
+        reserved: [0; 3],
+    };
+    if let Some(last_sent) = unsafe { FIREWALL_OUTBOUND_FLOWS.get(&key) } {
+        if now.wrapping_sub(*last_sent) < OUTBOUND_FLOW_TIMEOUT_NS {
+            return false;
+        }
+    }
+    unsafe { (*policy).dropped += 1 };
+    true
+}
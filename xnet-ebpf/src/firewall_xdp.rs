@@ -1,87 +1,492 @@
 use aya_ebpf::{
     bindings::xdp_action,
+    helpers::{bpf_ktime_get_ns, bpf_xdp_get_buff_len},
     macros::{map, xdp},
-    maps::HashMap,
+    maps::{Array, HashMap, ProgramArray, XskMap},
     programs::XdpContext,
 };
 
-use aya_log_ebpf::{debug, info};
-use xnet_common::int_to_ip;
-use xnet_ebpf::{EthHdr, IpHdr, Protocol, TcpHdr, UdpHdr};
+use aya_log_ebpf::debug;
+use xnet_common::{
+    int_to_ip, CaptureFilter, ALERT_REPUTATION_BLOCK, CONNECTION_CLOSE_FIN, CONNECTION_CLOSE_RST, ConnTrackEntry, IpSignals, NatKey, NatRewrite,
+    FIREWALL_DIRECTION_INGRESS, NAT_KIND_DNAT,
+    NAT_KIND_SNAT, TCP_STATE_CLOSED, TCP_STATE_ESTABLISHED,
+    TCP_STATE_FIN_WAIT, TCP_STATE_SYN_SENT, TCP_STATE_TIME_WAIT, XDP_PARSER_ICMP,
+    XDP_PARSER_IPV4, XDP_PARSER_IPV6, XDP_PARSER_MAX, XDP_PARSER_TCP, XDP_PARSER_UDP,
+};
+use xnet_ebpf::{rewrite_endpoint, EthHdr, IpHdr, Protocol, TcpHdr, UdpHdr};
+
+use crate::accounting;
+use crate::events;
+use crate::firewall_rules::{self, FirewallPacket};
+use crate::icmpv6;
+
+#[map]
+static mut IP_STATS: HashMap<u32, u64> = HashMap::pinned(1024, 0);
 
+// 连接跟踪状态，过期条目由用户空间按状态超时清理
 #[map]
-static mut IP_STATS: HashMap<u32, u64> = HashMap::with_max_entries(1024, 0);
+pub(crate) static mut CONNECTION_TRACK: HashMap<u64, ConnTrackEntry> = HashMap::pinned(8192, 0);
 
 #[map]
-static mut CONNECTION_TRACK: HashMap<u64, u32> = HashMap::with_max_entries(8192, 0);
+pub(crate) static mut CONNECTION_STATS: HashMap<u64, u64> = HashMap::pinned(8192, 0);
 
+// 远端IP行为信号，用于用户空间计算信誉分
 #[map]
-static mut CONNECTION_STATS: HashMap<u64, u64> = HashMap::with_max_entries(8192, 0);
+static mut IP_SIGNALS: HashMap<u32, IpSignals> = HashMap::pinned(8192, 0);
+
+// 信誉分低于策略阈值的IP，来自这些IP的新建连接(SYN)会被丢弃
+#[map]
+static mut REPUTATION_BLOCK: HashMap<u32, u32> = HashMap::pinned(4096, 0);
+
+// 按接收队列索引的 AF_XDP socket
+#[map]
+static XSK_SOCKETS: XskMap = XskMap::pinned(64, 0);
+
+// 无状态NAT规则，由用户空间通过 /nat/rules 配置
+#[map]
+static NAT_RULES: HashMap<NatKey, NatRewrite> = HashMap::pinned(1024, 0);
+
+// 需要重定向到 AF_XDP socket 的流，ifindex 为 socket 绑定的设备
+#[map]
+static AFXDP_FILTER: Array<CaptureFilter> = Array::pinned(1, 0);
+
+// 下标 0 为 1 时推送 DNS 查询，由用户空间按 --dns-inspect 写入
+#[map(name = "dns_inspect")]
+static DNS_INSPECT: Array<u32> = Array::pinned(1, 0);
 
-#[xdp]
+// 按协议拆分的解析程序，以太网头 -> IPv4/IPv6 -> TCP/UDP/ICMP 逐级尾调用，
+// 每个协议的解析程序单独通过校验器，槽位由用户空间加载时填入
+#[map(name = "xdp_parsers")]
+static XDP_PARSERS: ProgramArray = ProgramArray::with_max_entries(XDP_PARSER_MAX, 0);
+
+// 以太网头和不带选项的IPv4头之后的传输层头部偏移
+const L4_OFFSET: usize = core::mem::size_of::<EthHdr>() + core::mem::size_of::<IpHdr>();
+
+// 解析出的IPv4包信息，尾调用的程序之间不共享状态，每个程序重新解析
+struct Ipv4Packet {
+    data: usize,
+    data_end: usize,
+    src_ip: u32,
+    dst_ip: u32,
+    protocol: u8,
+    ttl: u8,
+}
+
+// 尾调用成功时不返回，槽位为空时返回，由调用方继续处理
+fn tail_call(ctx: &XdpContext, parser: u32) {
+    let _ = unsafe { XDP_PARSERS.tail_call(ctx, parser) };
+}
+
+// frags: 支持巨帧/多缓冲区驱动，data..data_end 只覆盖第一个分片
+// 尾调用的程序也需要声明 frags
+#[xdp(frags)]
 pub fn xnet_xdp(ctx: XdpContext) -> u32 {
-    match try_xnet(ctx) {
+    let parser = match eth_proto(&ctx) {
+        Some(0x0800) => {
+            // IPv4 包由 XDP 计入总统计并打上标记，tc 程序不再重复计入
+            accounting::mark_seen(&ctx, packet_len(&ctx));
+            XDP_PARSER_IPV4
+        }
+        Some(0x86dd) => XDP_PARSER_IPV6,
+        _ => return xdp_action::XDP_PASS,
+    };
+    tail_call(&ctx, parser);
+    xdp_action::XDP_PASS
+}
+
+#[xdp(frags)]
+pub fn xnet_xdp_ipv4(ctx: XdpContext) -> u32 {
+    match try_ipv4(&ctx) {
+        Ok(ret) => ret,
+        Err(_) => xdp_action::XDP_PASS,
+    }
+}
+
+#[xdp(frags)]
+pub fn xnet_xdp_ipv6(ctx: XdpContext) -> u32 {
+    try_ipv6(&ctx)
+}
+
+#[xdp(frags)]
+pub fn xnet_xdp_tcp(ctx: XdpContext) -> u32 {
+    match try_tcp(&ctx) {
+        Ok(ret) => ret,
+        Err(_) => xdp_action::XDP_PASS,
+    }
+}
+
+#[xdp(frags)]
+pub fn xnet_xdp_udp(ctx: XdpContext) -> u32 {
+    match try_udp(&ctx) {
         Ok(ret) => ret,
         Err(_) => xdp_action::XDP_PASS,
     }
 }
 
-fn try_xnet(ctx: XdpContext) -> Result<u32, ()> {
+// 包含所有分片的完整包长度
+fn packet_len(ctx: &XdpContext) -> u64 {
+    unsafe { bpf_xdp_get_buff_len(ctx.ctx) }
+}
+
+// 以太网类型，包长度不足时返回 None
+fn eth_proto(ctx: &XdpContext) -> Option<u16> {
+    let data = ctx.data();
+    if data + core::mem::size_of::<EthHdr>() > ctx.data_end() {
+        return None;
+    }
+    Some(u16::from_be(unsafe { (*(data as *const EthHdr)).eth_proto }))
+}
+
+// IP头部边界检查，以太网类型已由 xnet_xdp 检查
+fn parse_ipv4(ctx: &XdpContext) -> Option<Ipv4Packet> {
     let data = ctx.data();
     let data_end = ctx.data_end();
+    if data + L4_OFFSET > data_end {
+        return None;
+    }
+
+    // 安全访问IP头部
+    let iphdr = (data + core::mem::size_of::<EthHdr>()) as *const IpHdr;
+    Some(Ipv4Packet {
+        data,
+        data_end,
+        src_ip: unsafe { (*iphdr).saddr },
+        dst_ip: unsafe { (*iphdr).daddr },
+        protocol: unsafe { (*iphdr).protocol },
+        ttl: unsafe { (*iphdr).ttl },
+    })
+}
 
-    // 以太网头部边界检查
-    let eth_size = core::mem::size_of::<EthHdr>();
-    if data + eth_size > data_end {
+fn try_ipv4(ctx: &XdpContext) -> Result<u32, ()> {
+    let packet_len = packet_len(ctx);
+    let Some(ip) = parse_ipv4(ctx) else {
         return Ok(xdp_action::XDP_PASS);
+    };
+    let Ipv4Packet {
+        src_ip,
+        dst_ip,
+        protocol,
+        ttl,
+        ..
+    } = ip;
+
+    // 按源地址的流量和 TTL 统计只处理采中的包，流量按采样率放大
+    if let Some(weight) = crate::sampling::weight(unsafe { (*ctx.ctx).ingress_ifindex }) {
+        update_ip_stats(src_ip, packet_len * weight as u64)?;
+        crate::ttl::observe(src_ip, ttl);
+
+        // 记录基本包信息
+        debug!(
+            ctx,
+            "IP Packet: src={}, dst={}, proto={}",
+            int_to_ip(src_ip),
+            int_to_ip(dst_ip),
+            Protocol(protocol)
+        );
     }
 
-    // 安全访问以太网头部
-    let ethhdr = data as *const EthHdr;
-    let eth_proto = unsafe { (*ethhdr).eth_proto.to_be() };
-    if eth_proto != 0x0800 {
-        return Ok(xdp_action::XDP_PASS);
+    // 信任名单中的源地址跳过以下所有丢弃逻辑
+    let now = unsafe { bpf_ktime_get_ns() };
+    if !firewall_rules::allowlisted(src_ip, packet_len, now) && filtered(ctx, &ip, packet_len, now) {
+        return Ok(xdp_action::XDP_DROP);
     }
 
-    // IP头部边界检查
-    let ip_offset = eth_size;
-    let ip_size = core::mem::size_of::<IpHdr>();
-    if data + ip_offset + ip_size > data_end {
-        return Ok(xdp_action::XDP_PASS);
+    // 交给传输层协议的解析程序，没有对应程序时直接放行
+    let parser = match protocol {
+        6 => XDP_PARSER_TCP,
+        17 => XDP_PARSER_UDP,
+        1 => XDP_PARSER_ICMP,
+        _ => return Ok(pass(ctx, &ip)),
+    };
+    tail_call(ctx, parser);
+    Ok(pass(ctx, &ip))
+}
+
+// IPv6 目前只处理紧跟在固定头之后的 ICMPv6：开启 RA guard 的设备丢弃收到的 Router Advertisement
+fn try_ipv6(ctx: &XdpContext) -> u32 {
+    let data = ctx.data();
+    let l4 = data + core::mem::size_of::<EthHdr>() + icmpv6::IPV6_HDR_LEN;
+    if l4 + 1 > ctx.data_end() {
+        return xdp_action::XDP_PASS;
     }
+    let next_header =
+        unsafe { *((data + core::mem::size_of::<EthHdr>() + icmpv6::IPV6_NEXT_HEADER_OFFSET) as *const u8) };
+    if next_header != icmpv6::IPPROTO_ICMPV6 {
+        return xdp_action::XDP_PASS;
+    }
+    let icmp_type = unsafe { *(l4 as *const u8) };
+    if icmpv6::ra_guarded(unsafe { (*ctx.ctx).ingress_ifindex }, icmp_type) {
+        return xdp_action::XDP_DROP;
+    }
+    xdp_action::XDP_PASS
+}
 
-    // 安全访问IP头部
-    let iphdr = (data + ip_offset) as *const IpHdr;
-    let src_ip = unsafe { (*iphdr).saddr };
-    let dst_ip = unsafe { (*iphdr).daddr };
-    let protocol = unsafe { (*iphdr).protocol };
-
-    // 更新IP流量统计
-    update_ip_stats(src_ip, (data_end - data) as u64)?;
-
-    // 记录基本包信息
-    debug!(
-        &ctx,
-        "IP Packet: src={}, dst={}, proto={}",
-        int_to_ip(src_ip),
-        int_to_ip(dst_ip),
-        Protocol(protocol)
+// bogon、威胁情报黑名单、国家规则、防火墙规则和设备默认策略，需要丢弃时返回 true
+fn filtered(ctx: &XdpContext, ip: &Ipv4Packet, packet_len: u64, now: u64) -> bool {
+    let ifindex = unsafe { (*ctx.ctx).ingress_ifindex };
+
+    // 面向外网的设备上源地址为 bogon 的包
+    if crate::bogon::check(ifindex, ip.src_ip, packet_len) {
+        return true;
+    }
+
+    // 威胁情报订阅源中的地址
+    if crate::blocklist::blocked(ip.src_ip) {
+        return true;
+    }
+
+    // 通用防火墙规则，XDP 只能看到收到的包
+    let packet = firewall_packet(
+        ctx,
+        ip.data,
+        ip.data_end,
+        L4_OFFSET,
+        ip.src_ip,
+        ip.dst_ip,
+        ip.protocol,
     );
 
-    // 处理TCP连接
-    if protocol == 6 {
-        handle_tcp_connection(&ctx, data, data_end, ip_offset + ip_size, src_ip, dst_ip)?;
-    } else if protocol == 17 {
-        handle_udp_connection(&ctx, data, data_end, ip_offset + ip_size, src_ip, dst_ip)?;
+    // 按国家拒绝的源地址，例外端口除外
+    if crate::geo::blocked(ip.src_ip, packet.dst_port, ip.protocol) {
+        return true;
     }
 
-    Ok(xdp_action::XDP_PASS)
+    match firewall_rules::evaluate(&packet, now) {
+        Some(drop) => drop,
+        // 没有规则给出结论时按设备的默认策略处理
+        None => firewall_rules::default_denied(ifindex, &packet, now),
+    }
 }
 
-fn handle_udp_connection(
+// 处理TCP连接
+fn try_tcp(ctx: &XdpContext) -> Result<u32, ()> {
+    let ip = parse_ipv4(ctx).ok_or(())?;
+    if honeypot(ctx, &ip)? {
+        return Ok(xdp_action::XDP_DROP);
+    }
+    let action = handle_tcp_connection(
+        ctx,
+        ip.data,
+        ip.data_end,
+        packet_len(ctx),
+        L4_OFFSET,
+        ip.src_ip,
+        ip.dst_ip,
+    )?;
+    if action != xdp_action::XDP_PASS {
+        return Ok(action);
+    }
+    Ok(pass(ctx, &ip))
+}
+
+fn try_udp(ctx: &XdpContext) -> Result<u32, ()> {
+    let ip = parse_ipv4(ctx).ok_or(())?;
+    if honeypot(ctx, &ip)? {
+        return Ok(xdp_action::XDP_DROP);
+    }
+    dns_query(ctx, &ip);
+    handle_udp_connection(
+        ip.data,
+        ip.data_end,
+        packet_len(ctx),
+        L4_OFFSET,
+        ip.src_ip,
+        ip.dst_ip,
+    )?;
+    Ok(pass(ctx, &ip))
+}
+
+// 访问蜜罐端口的 TCP SYN 和 UDP 包，需要丢弃时返回 true，信任名单中的源地址不记录
+fn honeypot(ctx: &XdpContext, ip: &Ipv4Packet) -> Result<bool, ()> {
+    let l4 = ip.data + L4_OFFSET;
+    let (src_port, dst_port, flags) = if ip.protocol == 6 {
+        if l4 + core::mem::size_of::<TcpHdr>() > ip.data_end {
+            return Err(());
+        }
+        let tcphdr = l4 as *const TcpHdr;
+        let flags = unsafe { (*tcphdr).flags };
+        // 只看新建连接的 SYN
+        if flags & 0x12 != 0x02 {
+            return Ok(false);
+        }
+        unsafe { ((*tcphdr).source, (*tcphdr).dest, flags) }
+    } else {
+        if l4 + core::mem::size_of::<UdpHdr>() > ip.data_end {
+            return Err(());
+        }
+        let udphdr = l4 as *const UdpHdr;
+        unsafe { ((*udphdr).source, (*udphdr).dest, 0) }
+    };
+    if firewall_rules::trusted(ip.src_ip) {
+        return Ok(false);
+    }
+
+    let flow = events::flow(
+        ip.src_ip,
+        ip.dst_ip,
+        u16::from_be(src_port),
+        u16::from_be(dst_port),
+        ip.protocol,
+    );
+    let ifindex = unsafe { (*ctx.ctx).ingress_ifindex };
+    Ok(crate::honeypot::check(flow, ifindex, packet_len(ctx) as u32, ip.ttl, flags))
+}
+
+// 开启 DNS 检查时把发往 53 端口的报文推送到用户空间
+fn dns_query(ctx: &XdpContext, ip: &Ipv4Packet) {
+    if DNS_INSPECT.get(0).copied() != Some(1) {
+        return;
+    }
+    let l4 = ip.data + L4_OFFSET;
+    if l4 + core::mem::size_of::<UdpHdr>() > ip.data_end {
+        return;
+    }
+    let udphdr = l4 as *const UdpHdr;
+    let (src_port, dst_port) =
+        unsafe { (u16::from_be((*udphdr).source), u16::from_be((*udphdr).dest)) };
+    if dst_port != 53 {
+        return;
+    }
+    let flow = events::flow(ip.src_ip, ip.dst_ip, src_port, dst_port, 17);
+    let ifindex = unsafe { (*ctx.ctx).ingress_ifindex };
+    events::dns_query(ctx, flow, ifindex, l4 + core::mem::size_of::<UdpHdr>());
+}
+
+// 放行的包如果属于选中的流，重定向到 AF_XDP socket，否则按NAT规则改写后放行
+fn pass(ctx: &XdpContext, ip: &Ipv4Packet) -> u32 {
+    if afxdp_selected(ctx, ip.data, ip.data_end, L4_OFFSET, ip.src_ip, ip.dst_ip, ip.protocol) {
+        let queue = unsafe { (*ctx.ctx).rx_queue_index };
+        // 该队列没有 socket 时按 flags 低位回退为 XDP_PASS
+        return XSK_SOCKETS
+            .redirect(queue, xdp_action::XDP_PASS as u64)
+            .unwrap_or_else(|action| action);
+    }
+
+    apply_nat(ip.data, ip.data_end, core::mem::size_of::<EthHdr>(), ip.protocol);
+    xdp_action::XDP_PASS
+}
+
+// 按 NAT_RULES 改写放行的包，先做DNAT再做SNAT
+fn apply_nat(data: usize, data_end: usize, ip_offset: usize, protocol: u8) {
+    let ip_size = core::mem::size_of::<IpHdr>();
+    let l4 = data + ip_offset + ip_size;
+    let l4_size = match protocol {
+        6 => core::mem::size_of::<TcpHdr>(),
+        17 => core::mem::size_of::<UdpHdr>(),
+        _ => return,
+    };
+    if l4 + l4_size > data_end {
+        return;
+    }
+
+    let iphdr = (data + ip_offset) as *mut IpHdr;
+    // 只改写不带选项且未分片的包
+    let (version_ihl, frag_off) = unsafe { ((*iphdr).version_ihl, (*iphdr).frag_off) };
+    if version_ihl != 0x45 || u16::from_be(frag_off) & 0x3fff != 0 {
+        return;
+    }
+
+    let udphdr = l4 as *const UdpHdr;
+    let (daddr, dport) = unsafe { ((*iphdr).daddr, (*udphdr).dest) };
+    if let Some(rewrite) = nat_lookup(daddr, dport, protocol, NAT_KIND_DNAT) {
+        unsafe { rewrite_endpoint(iphdr, l4, protocol, true, rewrite.0, rewrite.1) };
+    }
+    let (saddr, sport) = unsafe { ((*iphdr).saddr, (*udphdr).source) };
+    if let Some(rewrite) = nat_lookup(saddr, sport, protocol, NAT_KIND_SNAT) {
+        unsafe { rewrite_endpoint(iphdr, l4, protocol, false, rewrite.0, rewrite.1) };
+    }
+}
+
+// 查找NAT规则并计数，返回改写后的(地址, 端口)，端口为0时保持原端口
+fn nat_lookup(addr: u32, port: u16, protocol: u8, kind: u8) -> Option<(u32, u16)> {
+    let key = NatKey {
+        addr,
+        port,
+        protocol,
+        kind,
+    };
+    let rule = NAT_RULES.get_ptr_mut(&key)?;
+    unsafe {
+        (*rule).packets += 1;
+        let new_port = if (*rule).port == 0 { port } else { (*rule).port };
+        Some(((*rule).addr, new_port))
+    }
+}
+
+// 收到的包参与防火墙规则匹配的信息
+fn firewall_packet(
     ctx: &XdpContext,
     data: usize,
     data_end: usize,
+    l4_offset: usize,
+    src_ip: u32,
+    dst_ip: u32,
+    protocol: u8,
+) -> FirewallPacket {
+    let (src_port, dst_port) = if (protocol == 6 || protocol == 17) && data + l4_offset + 4 <= data_end
+    {
+        // TCP和UDP头部的端口位置相同
+        let udphdr = (data + l4_offset) as *const UdpHdr;
+        unsafe { (u16::from_be((*udphdr).source), u16::from_be((*udphdr).dest)) }
+    } else {
+        (0, 0)
+    };
+    FirewallPacket {
+        saddr: src_ip,
+        daddr: dst_ip,
+        src_port,
+        dst_port,
+        protocol,
+        direction: FIREWALL_DIRECTION_INGRESS,
+        len: packet_len(ctx),
+    }
+}
+
+// 检查包是否匹配 AF_XDP 过滤条件
+fn afxdp_selected(
+    ctx: &XdpContext,
+    data: usize,
+    data_end: usize,
+    l4_offset: usize,
+    src_ip: u32,
+    dst_ip: u32,
+    protocol: u8,
+) -> bool {
+    let filter = match AFXDP_FILTER.get(0) {
+        Some(filter) if filter.enabled != 0 => filter,
+        _ => return false,
+    };
+    if filter.ifindex != unsafe { (*ctx.ctx).ingress_ifindex } {
+        return false;
+    }
+    if filter.ip != 0 && src_ip != filter.ip && dst_ip != filter.ip {
+        return false;
+    }
+
+    if filter.port != 0 {
+        if protocol != 6 && protocol != 17 {
+            return false;
+        }
+        // TCP和UDP头部的端口位置相同
+        if data + l4_offset + 4 > data_end {
+            return false;
+        }
+        let udphdr = (data + l4_offset) as *const UdpHdr;
+        let src_port = u16::from_be(unsafe { (*udphdr).source });
+        let dst_port = u16::from_be(unsafe { (*udphdr).dest });
+        if src_port != filter.port && dst_port != filter.port {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn handle_udp_connection(
+    data: usize,
+    data_end: usize,
+    packet_len: u64,
     udp_offset: usize,
     src_ip: u32,
     dst_ip: u32,
@@ -91,25 +496,9 @@ fn handle_udp_connection(
         return Err(());
     }
 
-    // 安全访问UDP头部
-    let udphdr = (data + udp_offset) as *const UdpHdr;
-    let src_port = unsafe { (*udphdr).source };
-    let dst_port = unsafe { (*udphdr).dest };
-    let _udp_len = unsafe { (*udphdr).len };
-
     // 更新IP统计
-    update_ip_stats(src_ip, (data_end - data) as u64)?;
-    update_ip_stats(dst_ip, (data_end - data) as u64)?;
-
-    // 记录UDP数据包
-    info!(
-        ctx,
-        "UDP: {}:{} -> {}:{}",
-        int_to_ip(src_ip),
-        u16::from_be(src_port),
-        int_to_ip(dst_ip),
-        u16::from_be(dst_port)
-    );
+    update_ip_stats(src_ip, packet_len)?;
+    update_ip_stats(dst_ip, packet_len)?;
 
     Ok(())
 }
@@ -118,10 +507,11 @@ fn handle_tcp_connection(
     ctx: &XdpContext,
     data: usize,
     data_end: usize,
+    packet_len: u64,
     tcp_offset: usize,
     src_ip: u32,
     dst_ip: u32,
-) -> Result<(), ()> {
+) -> Result<u32, ()> {
     let tcp_size = core::mem::size_of::<TcpHdr>();
     if data + tcp_offset + tcp_size > data_end {
         return Err(());
@@ -133,6 +523,19 @@ fn handle_tcp_connection(
     let dst_port = unsafe { (*tcphdr).dest };
     let flags = unsafe { (*tcphdr).flags };
 
+    let ifindex = unsafe { (*ctx.ctx).ingress_ifindex };
+    let flow = events::flow(src_ip, dst_ip, u16::from_be(src_port), u16::from_be(dst_port), 6);
+
+    // NULL、FIN、XMAS 扫描等非法标志组合，信任名单中的源地址除外
+    if let Some(kind) = crate::tcp_scan::classify(flags) {
+        if !firewall_rules::trusted(src_ip) {
+            events::alert(flow, kind, flags as u32);
+            if crate::tcp_scan::record(src_ip, u16::from_be(dst_port), flags, kind) {
+                return Ok(xdp_action::XDP_DROP);
+            }
+        }
+    }
+
     let syn = (flags & 0x02) != 0;
     let ack = (flags & 0x10) != 0;
     let fin = (flags & 0x01) != 0;
@@ -143,70 +546,91 @@ fn handle_tcp_connection(
     let reverse_conn_key = generate_conn_key(dst_ip, src_ip, dst_port, src_port);
 
     // 更新连接统计
-    let packet_size = (data_end - data) as u64;
-    update_connection_stats(conn_key, packet_size)?;
+    update_connection_stats(conn_key, packet_len)?;
 
     // 处理连接状态
+    let now = unsafe { bpf_ktime_get_ns() };
+    let state = connection_state(conn_key);
     if syn && !ack {
-        // SYN包 - 新连接建立
-        unsafe {
-            let _ = CONNECTION_TRACK.insert(&conn_key, &1, 0); // 1表示连接建立中
+        // 信誉分过低的IP不允许新建连接，信任名单中的IP除外
+        if unsafe { REPUTATION_BLOCK.get(&src_ip).is_some() } && !firewall_rules::trusted(src_ip) {
+            update_ip_signals(src_ip, |s| s.dropped_syns += 1);
+            events::alert(flow, ALERT_REPUTATION_BLOCK, 0);
+            debug!(
+                ctx,
+                "TCP SYN dropped by reputation: {}:{}",
+                int_to_ip(src_ip),
+                u16::from_be(src_port)
+            );
+            return Ok(xdp_action::XDP_DROP);
         }
+
+        let dst_port_host = u16::from_be(dst_port);
+        update_ip_signals(src_ip, |s| {
+            s.syn_packets += 1;
+            if s.syn_packets > 1 && s.last_dst_port != dst_port_host {
+                s.port_switches += 1;
+            }
+            s.last_dst_port = dst_port_host;
+        });
+
+        // SYN包 - 新连接建立
+        set_connection_state(conn_key, TCP_STATE_SYN_SENT, now);
+        events::conn_open(flow, ifindex);
         debug!(
             ctx,
-            "TCP SYN: {}:{} -> {}:{} (NEW_CONN)",
+            "TCP SYN: {}:{} -> {}:{} (SYN_SENT)",
             int_to_ip(src_ip),
             u16::from_be(src_port),
             int_to_ip(dst_ip),
             u16::from_be(dst_port)
         );
     } else if syn && ack {
-        // SYN+ACK包 - 连接确认
-        unsafe {
-            let _ = CONNECTION_TRACK.insert(&conn_key, &2, 0); // 2表示连接已建立
-            let _ = CONNECTION_TRACK.insert(&reverse_conn_key, &2, 0);
-        }
+        // SYN+ACK包 - 握手进行中，等待发起方的ACK
+        set_connection_state(conn_key, TCP_STATE_SYN_SENT, now);
         debug!(
             ctx,
-            "TCP SYN+ACK: {}:{} -> {}:{} (ESTABLISHED)",
-            int_to_ip(src_ip),
-            u16::from_be(src_port),
-            int_to_ip(dst_ip),
-            u16::from_be(dst_port)
-        );
-    } else if ack && !syn {
-        // ACK包 - 数据传输
-        debug!(
-            ctx,
-            "TCP ACK: {}:{} -> {}:{} (DATA)",
-            int_to_ip(src_ip),
-            u16::from_be(src_port),
-            int_to_ip(dst_ip),
-            u16::from_be(dst_port)
-        );
-    } else if fin {
-        // FIN包 - 连接关闭
-        unsafe {
-            let _ = CONNECTION_TRACK.insert(&conn_key, &3, 0); // 3表示连接关闭中
-            let _ = CONNECTION_TRACK.insert(&reverse_conn_key, &3, 0);
-        }
-        info!(
-            ctx,
-            "TCP FIN: {}:{} -> {}:{} (CLOSING)",
+            "TCP SYN+ACK: {}:{} -> {}:{} (SYN_SENT)",
             int_to_ip(src_ip),
             u16::from_be(src_port),
             int_to_ip(dst_ip),
             u16::from_be(dst_port)
         );
     } else if rst {
+        update_ip_signals(src_ip, |s| s.rst_packets += 1);
+
         // RST包 - 连接重置
-        unsafe {
-            let _ = CONNECTION_TRACK.insert(&conn_key, &4, 0); // 4表示连接重置
-            let _ = CONNECTION_TRACK.insert(&reverse_conn_key, &4, 0);
+        set_connection_state(conn_key, TCP_STATE_CLOSED, now);
+        set_connection_state(reverse_conn_key, TCP_STATE_CLOSED, now);
+        events::conn_close(flow, ifindex, CONNECTION_CLOSE_RST);
+    } else if fin {
+        // 第一个FIN进入FIN_WAIT，对端也发出FIN后进入TIME_WAIT
+        let next = if state == TCP_STATE_FIN_WAIT || state == TCP_STATE_TIME_WAIT {
+            TCP_STATE_TIME_WAIT
+        } else {
+            TCP_STATE_FIN_WAIT
+        };
+        set_connection_state(conn_key, next, now);
+        set_connection_state(reverse_conn_key, next, now);
+        // 双方都发出 FIN 后连接结束
+        if next == TCP_STATE_TIME_WAIT && state == TCP_STATE_FIN_WAIT {
+            events::conn_close(flow, ifindex, CONNECTION_CLOSE_FIN);
         }
-        info!(
+    } else if ack {
+        // 握手中的连接收到ACK，表示三次握手完成
+        if state == TCP_STATE_SYN_SENT {
+            set_connection_state(conn_key, TCP_STATE_ESTABLISHED, now);
+            set_connection_state(reverse_conn_key, TCP_STATE_ESTABLISHED, now);
+            update_ip_signals(src_ip, |s| s.handshakes_completed += 1);
+        } else {
+            touch_connection(conn_key, now);
+            touch_connection(reverse_conn_key, now);
+        }
+
+        // ACK包 - 数据传输
+        debug!(
             ctx,
-            "TCP RST: {}:{} -> {}:{} (RESET)",
+            "TCP ACK: {}:{} -> {}:{} (DATA)",
             int_to_ip(src_ip),
             u16::from_be(src_port),
             int_to_ip(dst_ip),
@@ -214,7 +638,52 @@ fn handle_tcp_connection(
         );
     }
 
-    Ok(())
+    Ok(xdp_action::XDP_PASS)
+}
+
+// 未跟踪的连接返回 0
+fn connection_state(conn_key: u64) -> u32 {
+    unsafe { CONNECTION_TRACK.get(&conn_key) }
+        .map(|entry| entry.state)
+        .unwrap_or(0)
+}
+
+fn set_connection_state(conn_key: u64, state: u32, now: u64) {
+    let entry = ConnTrackEntry {
+        state,
+        reserved: 0,
+        last_seen_ns: now,
+    };
+    unsafe {
+        let _ = CONNECTION_TRACK.insert(&conn_key, &entry, 0);
+    }
+}
+
+// 刷新已跟踪连接的最后活跃时间
+fn touch_connection(conn_key: u64, now: u64) {
+    if let Some(entry) = unsafe { CONNECTION_TRACK.get_ptr_mut(&conn_key) } {
+        unsafe { (*entry).last_seen_ns = now };
+    }
+}
+
+// 更新远端IP的行为信号
+fn update_ip_signals(ip: u32, update: impl FnOnce(&mut IpSignals)) {
+    let mut signals = match unsafe { IP_SIGNALS.get(&ip) } {
+        Some(s) => *s,
+        None => IpSignals {
+            syn_packets: 0,
+            handshakes_completed: 0,
+            rst_packets: 0,
+            port_switches: 0,
+            dropped_syns: 0,
+            last_dst_port: 0,
+            reserved: [0; 6],
+        },
+    };
+    update(&mut signals);
+    unsafe {
+        let _ = IP_SIGNALS.insert(&ip, &signals, 0);
+    }
 }
 
 fn generate_conn_key(src_ip: u32, dst_ip: u32, src_port: u16, dst_port: u16) -> u64 {
@@ -0,0 +1,42 @@
+use aya_ebpf::{
+    bindings::BPF_F_NO_PREALLOC,
+    macros::map,
+    maps::{lpm_trie::Key, Array, LpmTrie},
+};
+use xnet_common::{GeoRule, GEO_MAX_EXCEPT_PORTS, GEO_MAX_RULES};
+
+// 各国家的地址前缀(网络字节序)，值为规则编号，由用户空间按 GeoIP 数据同步
+#[map(name = "geo_prefixes")]
+static GEO_PREFIXES: LpmTrie<u32, u32> = LpmTrie::pinned(262144, BPF_F_NO_PREALLOC);
+
+#[map(name = "geo_rules")]
+static GEO_RULES: Array<GeoRule> = Array::pinned(GEO_MAX_RULES, 0);
+
+// 按规则编号统计丢弃的包数
+#[map(name = "geo_stats")]
+static GEO_STATS: Array<u64> = Array::pinned(GEO_MAX_RULES, 0);
+
+// 源地址属于被拒绝的国家且目的端口不在例外列表中时计数并返回 true
+pub fn blocked(src_ip: u32, dst_port: u16, protocol: u8) -> bool {
+    let Some(id) = GEO_PREFIXES.get(&Key::new(32, src_ip)) else {
+        return false;
+    };
+    let Some(rule) = GEO_RULES.get(*id) else {
+        return false;
+    };
+    if protocol == 6 || protocol == 17 {
+        for i in 0..GEO_MAX_EXCEPT_PORTS {
+            let port = rule.except_ports[i];
+            if port == 0 {
+                break;
+            }
+            if port == dst_port {
+                return false;
+            }
+        }
+    }
+    if let Some(dropped) = GEO_STATS.get_ptr_mut(*id) {
+        unsafe { *dropped += 1 };
+    }
+    true
+}
@@ -0,0 +1,59 @@
+use aya_ebpf::{
+    helpers::bpf_ktime_get_ns,
+    macros::map,
+    maps::{HashMap, LruHashMap},
+};
+use xnet_common::{
+    EventFlow, HoneypotPort, HoneypotSource, HONEYPOT_ACTION_DROP, HONEYPOT_MAX_PORTS,
+    HONEYPOT_PROTO_TCP, HONEYPOT_PROTO_UDP,
+};
+
+use crate::events;
+
+// 蜜罐端口，按目的端口(主机字节序)索引，由用户空间写入
+#[map(name = "honeypot_ports")]
+static HONEYPOT_PORTS: HashMap<u16, HoneypotPort> = HashMap::pinned(HONEYPOT_MAX_PORTS, 0);
+
+// 访问过蜜罐端口的源IP(网络字节序)
+#[map(name = "honeypot_sources")]
+static HONEYPOT_SOURCES: LruHashMap<u32, HoneypotSource> = LruHashMap::pinned(4096, 0);
+
+// 目的端口是蜜罐端口时记录来源并推送事件，需要丢弃时返回 true
+pub fn check(flow: EventFlow, ifindex: u32, len: u32, ttl: u8, tcp_flags: u8) -> bool {
+    let Some(port) = (unsafe { HONEYPOT_PORTS.get(&flow.dst_port) }) else {
+        return false;
+    };
+    let protocol = if flow.protocol == 6 {
+        HONEYPOT_PROTO_TCP
+    } else {
+        HONEYPOT_PROTO_UDP
+    };
+    if port.protocols & protocol == 0 {
+        return false;
+    }
+
+    let now = unsafe { bpf_ktime_get_ns() };
+    match HONEYPOT_SOURCES.get_ptr_mut(&flow.saddr) {
+        Some(source) => unsafe {
+            let source = &mut *source;
+            source.probes += 1;
+            source.last_seen_ns = now;
+            source.last_port = flow.dst_port;
+            source.last_protocol = flow.protocol;
+        },
+        None => {
+            let source = HoneypotSource {
+                probes: 1,
+                first_seen_ns: now,
+                last_seen_ns: now,
+                last_port: flow.dst_port,
+                last_protocol: flow.protocol,
+                reserved: [0; 5],
+            };
+            let _ = HONEYPOT_SOURCES.insert(&flow.saddr, &source, 0);
+        }
+    }
+
+    events::honeypot_probe(flow, ifindex, len, ttl, tcp_flags, port.action);
+    port.action == HONEYPOT_ACTION_DROP
+}
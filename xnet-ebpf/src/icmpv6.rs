@@ -0,0 +1,69 @@
+use aya_ebpf::{macros::map, maps::HashMap};
+use xnet_common::{
+    DeviceStatsKey, Icmpv6Stats, ICMPV6_ECHO_REPLY, ICMPV6_ECHO_REQUEST, ICMPV6_NEIGHBOR_ADVERT,
+    ICMPV6_NEIGHBOR_SOLICIT, ICMPV6_REDIRECT, ICMPV6_ROUTER_ADVERT, ICMPV6_ROUTER_SOLICIT,
+};
+
+// IPv6 固定头长度
+pub const IPV6_HDR_LEN: usize = 40;
+// IPv6 固定头中 next header 的偏移
+pub const IPV6_NEXT_HEADER_OFFSET: usize = 6;
+pub const IPPROTO_ICMPV6: u8 = 58;
+
+// 按设备和方向统计的 ICMPv6 类型，由 tc 程序更新
+#[map(name = "icmpv6_stats")]
+static ICMPV6_STATS: HashMap<DeviceStatsKey, Icmpv6Stats> = HashMap::pinned(1024, 0);
+
+// 开启 RA guard 的设备(接入侧设备)，值为丢弃的 RA 数，由 XDP 程序更新
+#[map(name = "ra_guard")]
+static RA_GUARD: HashMap<u32, u64> = HashMap::pinned(64, 0);
+
+// 按类型计数，只识别紧跟在固定头之后的 ICMPv6，不跟随扩展头
+pub fn count(key: &DeviceStatsKey, icmp_type: u8) {
+    let stats = match ICMPV6_STATS.get_ptr_mut(key) {
+        Some(stats) => stats,
+        None => {
+            let zero = Icmpv6Stats {
+                echo_request: 0,
+                echo_reply: 0,
+                router_solicit: 0,
+                router_advert: 0,
+                neighbor_solicit: 0,
+                neighbor_advert: 0,
+                redirect: 0,
+                other: 0,
+            };
+            let _ = ICMPV6_STATS.insert(key, &zero, 0);
+            match ICMPV6_STATS.get_ptr_mut(key) {
+                Some(stats) => stats,
+                None => return,
+            }
+        }
+    };
+    unsafe {
+        match icmp_type {
+            ICMPV6_ECHO_REQUEST => (*stats).echo_request += 1,
+            ICMPV6_ECHO_REPLY => (*stats).echo_reply += 1,
+            ICMPV6_ROUTER_SOLICIT => (*stats).router_solicit += 1,
+            ICMPV6_ROUTER_ADVERT => (*stats).router_advert += 1,
+            ICMPV6_NEIGHBOR_SOLICIT => (*stats).neighbor_solicit += 1,
+            ICMPV6_NEIGHBOR_ADVERT => (*stats).neighbor_advert += 1,
+            ICMPV6_REDIRECT => (*stats).redirect += 1,
+            _ => (*stats).other += 1,
+        }
+    }
+}
+
+// 设备开启了 RA guard 且收到的是 Router Advertisement 时计数并返回 true
+pub fn ra_guarded(ifindex: u32, icmp_type: u8) -> bool {
+    if icmp_type != ICMPV6_ROUTER_ADVERT {
+        return false;
+    }
+    match RA_GUARD.get_ptr_mut(&ifindex) {
+        Some(dropped) => {
+            unsafe { *dropped += 1 };
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,116 @@
+use aya_ebpf::{
+    bindings::xdp_action,
+    macros::{map, xdp},
+    maps::{Array, HashMap},
+    programs::XdpContext,
+};
+
+use xnet_common::{
+    LbBackend, LbBackendStats, LbService, LbVipKey, LB_MAX_BACKENDS, LB_MAX_SERVICES,
+};
+use xnet_ebpf::{rewrite_endpoint, EthHdr, IpHdr, TcpHdr, UdpHdr};
+
+// 虚拟服务，由用户空间通过 /lb/services 配置
+#[map]
+static LB_SERVICES: HashMap<LbVipKey, LbService> =
+    HashMap::pinned(LB_MAX_SERVICES, 0);
+
+// 后端列表，下标为 服务ID * LB_MAX_BACKENDS + 序号
+#[map]
+static LB_BACKENDS: Array<LbBackend> =
+    Array::pinned(LB_MAX_SERVICES * LB_MAX_BACKENDS, 0);
+
+// 每个后端转发的包数和字节数，下标同 LB_BACKENDS
+#[map]
+static LB_STATS: Array<LbBackendStats> =
+    Array::pinned(LB_MAX_SERVICES * LB_MAX_BACKENDS, 0);
+
+// 四层负载均衡: 目的地址匹配虚拟服务的包改写为后端地址后从原设备发回(XDP_TX)
+#[xdp]
+pub fn xnet_lb(ctx: XdpContext) -> u32 {
+    match try_lb(&ctx) {
+        Ok(ret) => ret,
+        Err(_) => xdp_action::XDP_PASS,
+    }
+}
+
+fn try_lb(ctx: &XdpContext) -> Result<u32, ()> {
+    let data = ctx.data();
+    let data_end = ctx.data_end();
+
+    let eth_size = core::mem::size_of::<EthHdr>();
+    let ip_size = core::mem::size_of::<IpHdr>();
+    // TCP和UDP头部的端口位置相同，校验和偏移不同，按较长的TCP头部检查
+    let tcp_size = core::mem::size_of::<TcpHdr>();
+    if data + eth_size + ip_size + core::mem::size_of::<UdpHdr>() > data_end {
+        return Ok(xdp_action::XDP_PASS);
+    }
+
+    let ethhdr = data as *mut EthHdr;
+    if u16::from_be(unsafe { (*ethhdr).eth_proto }) != 0x0800 {
+        return Ok(xdp_action::XDP_PASS);
+    }
+
+    let iphdr = (data + eth_size) as *mut IpHdr;
+    let (version_ihl, frag_off, protocol) =
+        unsafe { ((*iphdr).version_ihl, (*iphdr).frag_off, (*iphdr).protocol) };
+    // 只处理不带选项且未分片的包
+    if version_ihl != 0x45 || u16::from_be(frag_off) & 0x3fff != 0 {
+        return Ok(xdp_action::XDP_PASS);
+    }
+    if protocol != 6 && protocol != 17 {
+        return Ok(xdp_action::XDP_PASS);
+    }
+    if protocol == 6 && data + eth_size + ip_size + tcp_size > data_end {
+        return Ok(xdp_action::XDP_PASS);
+    }
+
+    let l4 = data + eth_size + ip_size;
+    let udphdr = l4 as *mut UdpHdr;
+    let (saddr, daddr) = unsafe { ((*iphdr).saddr, (*iphdr).daddr) };
+    let (sport, dport) = unsafe { ((*udphdr).source, (*udphdr).dest) };
+
+    let key = LbVipKey {
+        addr: daddr,
+        port: dport,
+        protocol,
+        reserved: 0,
+    };
+    let service = match unsafe { LB_SERVICES.get(&key) } {
+        Some(service) if service.backend_count > 0 => *service,
+        _ => return Ok(xdp_action::XDP_PASS),
+    };
+
+    // 同一条流总是选择同一个后端
+    let hash = flow_hash(saddr, daddr, ((sport as u32) << 16) | dport as u32, protocol);
+    let index = service.id * LB_MAX_BACKENDS + hash % service.backend_count;
+    let backend = match LB_BACKENDS.get(index) {
+        Some(backend) => *backend,
+        None => return Ok(xdp_action::XDP_PASS),
+    };
+
+    unsafe {
+        rewrite_endpoint(iphdr, l4, protocol, true, backend.addr, backend.port);
+
+        // 从收到包的设备发往后端所在的下一跳
+        (*ethhdr).eth_smac = (*ethhdr).eth_dmac;
+        (*ethhdr).eth_dmac = backend.mac;
+    }
+
+    if let Some(stats) = LB_STATS.get_ptr_mut(index) {
+        unsafe {
+            (*stats).packets += 1;
+            (*stats).bytes += (data_end - data) as u64;
+        }
+    }
+
+    Ok(xdp_action::XDP_TX)
+}
+
+fn flow_hash(saddr: u32, daddr: u32, ports: u32, protocol: u8) -> u32 {
+    let mut hash = saddr ^ 0x9e37_79b9;
+    hash = (hash ^ daddr).wrapping_mul(0x85eb_ca6b);
+    hash = (hash ^ ports).wrapping_mul(0xc2b2_ae35);
+    hash ^= protocol as u32;
+    hash ^ (hash >> 16)
+}
@@ -71,3 +71,74 @@ pub struct UdpHdr {
     pub len: u16,
     pub check: u16,
 }
+
+// 把 32 位累加和折叠为 16 位反码和
+#[inline(always)]
+fn csum_fold(sum: u32) -> u16 {
+    let sum = (sum & 0xffff) + (sum >> 16);
+    ((sum & 0xffff) + (sum >> 16)) as u16
+}
+
+// 增量更新校验和(RFC 1624): 报文中 16 位字段由 old 改为 new 后的校验和
+// 参数均为报文中的原始字节序，反码和与字节序无关
+#[inline(always)]
+pub fn csum_replace2(check: u16, old: u16, new: u16) -> u16 {
+    !csum_fold(!check as u32 + !old as u32 + new as u32)
+}
+
+// 同 csum_replace2，用于 IP 地址等 32 位字段
+#[inline(always)]
+pub fn csum_replace4(check: u16, old: u32, new: u32) -> u16 {
+    !csum_fold(
+        !check as u32
+            + !(old as u16) as u32
+            + !((old >> 16) as u16) as u32
+            + (new & 0xffff)
+            + (new >> 16),
+    )
+}
+
+/// 改写IPv4包的目的(dst=true)或源地址和端口，并增量修正IP和TCP/UDP校验和
+///
+/// # Safety
+///
+/// - `iphdr` 须指向包内可写的完整IPv4头部，且调用方已对 data_end 做过边界检查
+/// - `l4` 须为该包传输层头部的地址: protocol 为 6 时其后有完整的 `TcpHdr`，
+///   否则有完整的 `UdpHdr`，同样须已对 data_end 做过边界检查
+/// - 边界检查之后、调用之前不能调整包的头部或尾部(如 bpf_xdp_adjust_head)，否则两个指针失效
+#[inline(always)]
+pub unsafe fn rewrite_endpoint(
+    iphdr: *mut IpHdr,
+    l4: usize,
+    protocol: u8,
+    dst: bool,
+    addr: u32,
+    port: u16,
+) {
+    // TCP和UDP头部的端口位置相同
+    let udphdr = l4 as *mut UdpHdr;
+    let (old_addr, old_port) = if dst {
+        let old = ((*iphdr).daddr, (*udphdr).dest);
+        (*iphdr).daddr = addr;
+        (*udphdr).dest = port;
+        old
+    } else {
+        let old = ((*iphdr).saddr, (*udphdr).source);
+        (*iphdr).saddr = addr;
+        (*udphdr).source = port;
+        old
+    };
+
+    (*iphdr).check = csum_replace4((*iphdr).check, old_addr, addr);
+    // 传输层校验和的伪首部包含IP地址
+    if protocol == 6 {
+        let tcphdr = l4 as *mut TcpHdr;
+        let check = csum_replace4((*tcphdr).check, old_addr, addr);
+        (*tcphdr).check = csum_replace2(check, old_port, port);
+    } else if (*udphdr).check != 0 {
+        // UDP 校验和为 0 表示未计算，计算结果为 0 时写为 0xffff
+        let check = csum_replace4((*udphdr).check, old_addr, addr);
+        let check = csum_replace2(check, old_port, port);
+        (*udphdr).check = if check == 0 { 0xffff } else { check };
+    }
+}
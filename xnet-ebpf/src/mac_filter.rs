@@ -0,0 +1,80 @@
+use aya_ebpf::{
+    macros::map,
+    maps::{Array, HashMap, LruHashMap},
+};
+use xnet_common::{
+    MacKey, MacRule, MacStats, MAC_ACTION_ALLOW, MAC_ACTION_DENY, MAC_MAX_RULES, MAC_MODE_ALLOWLIST,
+};
+use xnet_ebpf::EthHdr;
+
+// 按源MAC匹配的二层规则，由用户空间通过 /mac/rules 配置
+#[map(name = "mac_rules")]
+static MAC_RULES: HashMap<MacKey, MacRule> = HashMap::pinned(MAC_MAX_RULES, 0);
+
+// index 0 为过滤模式 MAC_MODE_*
+#[map(name = "mac_config")]
+static MAC_CONFIG: Array<u32> = Array::pinned(1, 0);
+
+// 每个MAC的收发统计
+#[map(name = "mac_stats")]
+static MAC_STATS: LruHashMap<MacKey, MacStats> = LruHashMap::pinned(4096, 0);
+
+fn mac_key(addr: [u8; 6]) -> MacKey {
+    MacKey { addr, reserved: 0 }
+}
+
+fn update_stats(key: &MacKey, len: u64, tx: bool) {
+    if let Some(stats) = MAC_STATS.get_ptr_mut(key) {
+        unsafe {
+            if tx {
+                (*stats).tx_packets += 1;
+                (*stats).tx_bytes += len;
+            } else {
+                (*stats).rx_packets += 1;
+                (*stats).rx_bytes += len;
+            }
+        }
+        return;
+    }
+    let stats = if tx {
+        MacStats {
+            tx_packets: 1,
+            tx_bytes: len,
+            rx_packets: 0,
+            rx_bytes: 0,
+        }
+    } else {
+        MacStats {
+            tx_packets: 0,
+            tx_bytes: 0,
+            rx_packets: 1,
+            rx_bytes: len,
+        }
+    };
+    let _ = MAC_STATS.insert(key, &stats, 0);
+}
+
+// 统计源MAC和单播目的MAC的流量
+pub fn count(eth_hdr: &EthHdr, len: u64) {
+    update_stats(&mac_key(eth_hdr.eth_smac), len, true);
+    // 组播和广播地址的最低位为1
+    if eth_hdr.eth_dmac[0] & 1 == 0 {
+        update_stats(&mac_key(eth_hdr.eth_dmac), len, false);
+    }
+}
+
+// 收到的帧是否按源MAC丢弃: 命中 deny 规则时丢弃，白名单模式下没有命中 allow 规则时也丢弃
+pub fn denied(eth_hdr: &EthHdr) -> bool {
+    let allowlist = MAC_CONFIG.get(0).copied().unwrap_or(0) == MAC_MODE_ALLOWLIST;
+    match MAC_RULES.get_ptr_mut(&mac_key(eth_hdr.eth_smac)) {
+        Some(rule) => unsafe {
+            (*rule).packets += 1;
+            match (*rule).action {
+                MAC_ACTION_ALLOW => false,
+                MAC_ACTION_DENY => true,
+                _ => allowlist,
+            }
+        },
+        None => allowlist,
+    }
+}
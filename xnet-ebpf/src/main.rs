@@ -1,8 +1,31 @@
 #![no_std]
 #![no_main]
 
+// map 都按名称固定到 bpffs，/reload 加载的新程序沿用原有的 map；
+// gc_timer 和 xdp_parsers 引用了具体的程序，不固定
+mod accounting;
+mod blocklist;
+mod bogon;
+mod egress_lsm;
+mod events;
+mod firewall_rules;
 mod firewall_xdp;
+mod geo;
+mod honeypot;
+mod icmpv6;
+mod lb_xdp;
+mod mac_filter;
+mod map_gc;
+mod quota;
+mod sampling;
+mod shaping_tc;
+mod socket_owner;
+mod sockops;
+mod ssl_uprobe;
+mod tcp_scan;
+mod tcp_state_tp;
 mod traffic_count_tc;
+mod ttl;
 
 
 #[cfg(not(test))]
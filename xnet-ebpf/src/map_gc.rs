@@ -0,0 +1,120 @@
+use core::ffi::c_void;
+
+use aya_ebpf::{
+    bindings::{bpf_timer, TC_ACT_OK},
+    helpers::{
+        bpf_for_each_map_elem, bpf_ktime_get_ns, bpf_map_delete_elem, bpf_timer_init,
+        bpf_timer_set_callback, bpf_timer_start,
+    },
+    macros::{classifier, map},
+    maps::Array,
+    programs::TcContext,
+};
+use xnet_common::{conntrack_timeout_secs, ConnTrackEntry};
+
+use crate::firewall_xdp::{CONNECTION_STATS, CONNECTION_TRACK};
+
+// bpf_timer_init 的时钟参数
+const CLOCK_MONOTONIC: u64 = 1;
+
+#[repr(C)]
+pub struct GcTimer {
+    timer: bpf_timer,
+}
+
+// 周期清理连接跟踪的定时器，只有一个
+#[map(name = "gc_timer")]
+static GC_TIMER: Array<GcTimer> = Array::with_max_entries(1, 0);
+
+// index 0 为清理间隔(纳秒)，由用户空间在启动定时器前写入
+#[map(name = "gc_config")]
+static GC_CONFIG: Array<u64> = Array::pinned(1, 0);
+
+// index 0 为清理次数，index 1 为累计删除的条目数
+#[map(name = "gc_stats")]
+static GC_STATS: Array<u64> = Array::pinned(2, 0);
+
+struct GcContext {
+    now: u64,
+    removed: u64,
+}
+
+fn add_stat(index: u32, value: u64) {
+    if let Some(stat) = GC_STATS.get_ptr_mut(index) {
+        unsafe { *stat += value };
+    }
+}
+
+// 删除超时的连接跟踪条目及其流量统计，返回0继续遍历
+extern "C" fn expire_entry(
+    _map: *mut c_void,
+    key: *const u64,
+    entry: *const ConnTrackEntry,
+    ctx: *mut GcContext,
+) -> i64 {
+    unsafe {
+        let timeout_ns = conntrack_timeout_secs((*entry).state) * 1_000_000_000;
+        if (*ctx).now.saturating_sub((*entry).last_seen_ns) > timeout_ns {
+            bpf_map_delete_elem(
+                core::ptr::addr_of_mut!(CONNECTION_TRACK) as *mut c_void,
+                key as *const c_void,
+            );
+            bpf_map_delete_elem(
+                core::ptr::addr_of_mut!(CONNECTION_STATS) as *mut c_void,
+                key as *const c_void,
+            );
+            (*ctx).removed += 1;
+        }
+    }
+    0
+}
+
+// 定时器回调: 遍历连接跟踪表删除过期条目，然后重新启动定时器
+extern "C" fn gc_callback(_map: *mut c_void, _key: *mut u32, value: *mut GcTimer) -> i32 {
+    let mut ctx = GcContext {
+        now: unsafe { bpf_ktime_get_ns() },
+        removed: 0,
+    };
+    unsafe {
+        bpf_for_each_map_elem(
+            core::ptr::addr_of_mut!(CONNECTION_TRACK) as *mut c_void,
+            expire_entry as *mut c_void,
+            &mut ctx as *mut GcContext as *mut c_void,
+            0,
+        );
+    }
+    add_stat(0, 1);
+    add_stat(1, ctx.removed);
+
+    let interval = GC_CONFIG.get(0).copied().unwrap_or(0);
+    if interval > 0 {
+        unsafe { bpf_timer_start(&mut (*value).timer, interval, 0) };
+    }
+    0
+}
+
+// 由用户空间通过 BPF_PROG_TEST_RUN 运行一次，初始化并启动定时器
+// 内核不支持 bpf_timer 时程序加载失败，用户空间回退到自己清理
+#[classifier]
+pub fn xnet_map_gc(_ctx: TcContext) -> i32 {
+    let interval = GC_CONFIG.get(0).copied().unwrap_or(0);
+    let Some(gc) = GC_TIMER.get_ptr_mut(0) else {
+        return 1;
+    };
+    unsafe {
+        let timer = &mut (*gc).timer;
+        // 重复运行时定时器已初始化，返回 EBUSY，直接重新设置回调和间隔
+        bpf_timer_init(
+            timer,
+            core::ptr::addr_of!(GC_TIMER) as *mut c_void,
+            CLOCK_MONOTONIC,
+        );
+        if bpf_timer_set_callback(timer, gc_callback as *mut c_void) != 0 {
+            return 1;
+        }
+        if interval > 0 && bpf_timer_start(timer, interval, 0) != 0 {
+            return 1;
+        }
+    }
+    TC_ACT_OK
+}
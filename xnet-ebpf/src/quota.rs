@@ -0,0 +1,65 @@
+use aya_ebpf::{helpers::bpf_ktime_get_ns, macros::map, maps::HashMap, programs::TcContext};
+
+use xnet_common::{QuotaState, QuotaUsage, QUOTA_ACTION_THROTTLE, QUOTA_MAX_DEVICES};
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+// 限速时发送时间超过当前时间该值的包被丢弃
+const THROTTLE_HORIZON_NS: u64 = 2 * NSEC_PER_SEC;
+
+// 配额执行状态，key 为设备 ifindex，由用户空间通过 /quotas 配置并在用完配额时置位
+#[map(name = "quota_state")]
+static QUOTA_STATE: HashMap<u32, QuotaState> = HashMap::pinned(QUOTA_MAX_DEVICES, 0);
+
+// 配额用量，配置配额时由用户空间初始化，没有该设备的条目时不统计
+#[map(name = "quota_usage")]
+static QUOTA_USAGE: HashMap<u32, QuotaUsage> = HashMap::pinned(QUOTA_MAX_DEVICES, 0);
+
+// 限速时上一个包的发送时间，仅内核使用
+#[map(name = "quota_pacing")]
+static QUOTA_PACING: HashMap<u32, u64> = HashMap::pinned(QUOTA_MAX_DEVICES, 0);
+
+// 累加设备出方向的用量，超出配额时按配置丢弃或推迟发送，返回 true 时丢弃
+pub fn exceeded(ctx: &TcContext) -> bool {
+    let ifindex = unsafe { (*ctx.skb.skb).ifindex };
+    let Some(usage) = QUOTA_USAGE.get_ptr_mut(&ifindex) else {
+        return false;
+    };
+    let len = ctx.len() as u64;
+
+    if let Some(state) = unsafe { QUOTA_STATE.get(&ifindex) } {
+        if state.over != 0 {
+            if state.action != QUOTA_ACTION_THROTTLE || state.throttle_rate == 0 {
+                unsafe { (*usage).dropped += 1 };
+                return true;
+            }
+            // 与 xnet_shaper 相同，按 earliest departure time 设置 skb->tstamp，需要 fq qdisc
+            let now = unsafe { bpf_ktime_get_ns() };
+            let mut tstamp = unsafe { (*ctx.skb.skb).tstamp };
+            if tstamp < now {
+                tstamp = now;
+            }
+            let t_next = match unsafe { QUOTA_PACING.get(&ifindex) } {
+                Some(last) => *last + len * NSEC_PER_SEC / state.throttle_rate,
+                None => 0,
+            };
+            if t_next <= tstamp {
+                let _ = QUOTA_PACING.insert(&ifindex, &tstamp, 0);
+            } else if t_next - now >= THROTTLE_HORIZON_NS {
+                unsafe { (*usage).dropped += 1 };
+                return true;
+            } else {
+                let _ = QUOTA_PACING.insert(&ifindex, &t_next, 0);
+                unsafe {
+                    (*ctx.skb.skb).tstamp = t_next;
+                    (*usage).throttled += 1;
+                }
+            }
+        }
+    }
+
+    unsafe {
+        (*usage).packets += 1;
+        (*usage).bytes += len;
+    }
+    false
+}
@@ -0,0 +1,26 @@
+use aya_ebpf::{helpers::bpf_get_prandom_u32, macros::map, maps::HashMap};
+
+// 按设备配置的采样率 N，只有 1/N 的包进入按连接、按端口、按源地址的统计和调试日志，
+// 总量、设备和协议统计以及防火墙等执行逻辑仍处理每个包。没有配置的设备处理所有包
+#[map(name = "flow_sampling")]
+static FLOW_SAMPLING: HashMap<u32, u32> = HashMap::pinned(256, 0);
+
+// 设备的采样率，没有配置时为1
+pub fn rate(ifindex: u32) -> u32 {
+    match FLOW_SAMPLING.get_ptr(&ifindex) {
+        Some(rate) => match unsafe { *rate } {
+            0 => 1,
+            rate => rate,
+        },
+        None => 1,
+    }
+}
+
+// 包被采中时返回权重(采样率)，计数按权重放大，未采中返回 None
+pub fn weight(ifindex: u32) -> Option<u32> {
+    let rate = rate(ifindex);
+    if rate > 1 && unsafe { bpf_get_prandom_u32() } % rate != 0 {
+        return None;
+    }
+    Some(rate)
+}
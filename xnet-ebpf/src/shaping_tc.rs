@@ -0,0 +1,123 @@
+use aya_ebpf::{
+    bindings::{TC_ACT_SHOT, TC_ACT_UNSPEC},
+    helpers::bpf_ktime_get_ns,
+    macros::{classifier, map},
+    maps::{HashMap, LruHashMap},
+    programs::TcContext,
+};
+
+use xnet_common::{ShapingRule, ShapingStats, SHAPING_MAX_RULES, SHAPING_MODE_FLOW};
+use xnet_ebpf::IpHdr;
+
+use crate::traffic_count_tc;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+// 整形规则，key 为设备 ifindex，由用户空间通过 /shaping/rules 配置
+#[map(name = "shaping_rules")]
+static SHAPING_RULES: HashMap<u32, ShapingRule> = HashMap::pinned(SHAPING_MAX_RULES, 0);
+
+// 各设备的整形统计，规则创建时由用户空间初始化
+#[map(name = "shaping_stats")]
+static SHAPING_STATS: HashMap<u32, ShapingStats> = HashMap::pinned(SHAPING_MAX_RULES, 0);
+
+// 上一个包的发送时间，key 高32位为 ifindex，低32位为流哈希(按设备整形时为0)
+#[map(name = "shaping_state")]
+static SHAPING_STATE: LruHashMap<u64, u64> = LruHashMap::pinned(65536, 0);
+
+// 出方向整形: 按 earliest departure time 设置 skb->tstamp，由设备上的 fq qdisc 按时间发送
+// 不丢包时返回 TC_ACT_UNSPEC，继续执行同一挂载点上的其他程序(如 xnet_tc_egress)
+#[classifier]
+pub fn xnet_shaper(ctx: TcContext) -> i32 {
+    // 带有跳过条件中 mark 的包由设备上已有的 qdisc 处理
+    if traffic_count_tc::mark_skipped(&ctx, false) {
+        return TC_ACT_UNSPEC;
+    }
+    let ifindex = unsafe { (*ctx.skb.skb).ifindex };
+    let rule = match unsafe { SHAPING_RULES.get(&ifindex) } {
+        Some(rule) if rule.rate > 0 => *rule,
+        _ => return TC_ACT_UNSPEC,
+    };
+
+    let len = ctx.len() as u64;
+    let flow = if rule.mode == SHAPING_MODE_FLOW {
+        flow_hash(&ctx)
+    } else {
+        0
+    };
+    let key = ((ifindex as u64) << 32) | flow as u64;
+
+    let delay = len * NSEC_PER_SEC / rule.rate;
+    let now = unsafe { bpf_ktime_get_ns() };
+    let mut tstamp = unsafe { (*ctx.skb.skb).tstamp };
+    if tstamp < now {
+        tstamp = now;
+    }
+
+    let stats = SHAPING_STATS.get_ptr_mut(&ifindex);
+    let t_next = match SHAPING_STATE.get_ptr(&key) {
+        Some(last) => unsafe { *last + delay },
+        None => 0,
+    };
+
+    // 未超过速率，立即发送
+    if t_next <= tstamp {
+        let _ = SHAPING_STATE.insert(&key, &tstamp, 0);
+        if let Some(stats) = stats {
+            unsafe {
+                (*stats).packets += 1;
+                (*stats).bytes += len;
+            }
+        }
+        return TC_ACT_UNSPEC;
+    }
+
+    // 排队时间过长，丢包让上层感知拥塞
+    if t_next - now >= rule.horizon_ns {
+        if let Some(stats) = stats {
+            unsafe {
+                (*stats).dropped += 1;
+            }
+        }
+        return TC_ACT_SHOT as i32;
+    }
+
+    let _ = SHAPING_STATE.insert(&key, &t_next, 0);
+    unsafe {
+        (*ctx.skb.skb).tstamp = t_next;
+    }
+    if let Some(stats) = stats {
+        unsafe {
+            (*stats).packets += 1;
+            (*stats).bytes += len;
+            (*stats).delayed += 1;
+            (*stats).total_delay_ns += t_next - tstamp;
+        }
+    }
+    TC_ACT_UNSPEC
+}
+
+// 按五元组计算流哈希，非 IPv4 的包归为同一条流
+fn flow_hash(ctx: &TcContext) -> u32 {
+    let l2_len = traffic_count_tc::l2_len(ctx);
+    if traffic_count_tc::l3_proto(ctx, l2_len) != Some(0x0800) {
+        return 1;
+    }
+    let ip_hdr: IpHdr = match ctx.load(l2_len) {
+        Ok(hdr) => hdr,
+        Err(_) => return 1,
+    };
+
+    let mut ports = 0u32;
+    if ip_hdr.protocol == 6 || ip_hdr.protocol == 17 {
+        let ihl = ((ip_hdr.version_ihl & 0x0f) as usize) * 4;
+        ports = ctx.load(l2_len + ihl).unwrap_or(0);
+    }
+
+    let mut hash = ip_hdr.saddr ^ 0x9e37_79b9;
+    hash = (hash ^ ip_hdr.daddr).wrapping_mul(0x85eb_ca6b);
+    hash = (hash ^ ports).wrapping_mul(0xc2b2_ae35);
+    hash ^= ip_hdr.protocol as u32;
+    // 0 保留给按设备整形
+    (hash ^ (hash >> 16)) | 1
+}
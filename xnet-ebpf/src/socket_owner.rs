@@ -0,0 +1,49 @@
+use core::ffi::c_void;
+
+use aya_ebpf::{
+    helpers::{
+        bpf_get_current_cgroup_id, bpf_get_current_comm, bpf_get_current_pid_tgid,
+        bpf_get_socket_cookie, bpf_ktime_get_ns,
+    },
+    macros::{fentry, map},
+    maps::LruHashMap,
+    programs::FEntryContext,
+};
+
+use xnet_common::SocketOwner;
+
+// socket cookie -> 使用该 socket 的进程，TC 连接统计和 sock_ops 指标中的 cookie 在用户空间与之关联
+#[map(name = "socket_owners")]
+static SOCKET_OWNERS: LruHashMap<u64, SocketOwner> = LruHashMap::pinned(16384, 0);
+
+// int tcp_connect(struct sock *sk)，在 connect() 的进程上下文中调用
+#[fentry(function = "tcp_connect")]
+pub fn xnet_sock_connect(ctx: FEntryContext) -> u32 {
+    record(&ctx);
+    0
+}
+
+// int tcp_sendmsg(struct sock *sk, struct msghdr *msg, size_t size)
+// accept() 得到的 socket 没有 connect()，在第一次发送时记录
+#[fentry(function = "tcp_sendmsg")]
+pub fn xnet_sock_sendmsg(ctx: FEntryContext) -> u32 {
+    record(&ctx);
+    0
+}
+
+fn record(ctx: &FEntryContext) {
+    let sk: *const c_void = unsafe { ctx.arg(0) };
+    let cookie = unsafe { bpf_get_socket_cookie(sk as *mut _) };
+    // 每次发送都会调用，已记录的 socket 只做一次查找
+    if unsafe { SOCKET_OWNERS.get(&cookie) }.is_some() {
+        return;
+    }
+    let owner = SocketOwner {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        cgroup_id: unsafe { bpf_get_current_cgroup_id() },
+        pid: (bpf_get_current_pid_tgid() >> 32) as u32,
+        reserved: 0,
+        comm: bpf_get_current_comm().unwrap_or([0; 16]),
+    };
+    let _ = SOCKET_OWNERS.insert(&cookie, &owner, 0);
+}
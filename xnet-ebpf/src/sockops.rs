@@ -0,0 +1,79 @@
+use aya_ebpf::{
+    bindings::{
+        BPF_SOCK_OPS_ACTIVE_ESTABLISHED_CB, BPF_SOCK_OPS_PASSIVE_ESTABLISHED_CB,
+        BPF_SOCK_OPS_RTT_CB, BPF_SOCK_OPS_RTT_CB_FLAG, BPF_SOCK_OPS_STATE_CB,
+        BPF_SOCK_OPS_STATE_CB_FLAG,
+    },
+    helpers::{bpf_get_socket_cookie, bpf_ktime_get_ns},
+    macros::{map, sock_ops},
+    maps::LruHashMap,
+    programs::SockOpsContext,
+};
+
+use xnet_common::{SocketKey, SocketStats};
+
+const AF_INET: u32 = 2;
+
+// TCP socket 指标，连接关闭后保留最终状态，由 LRU 淘汰
+#[map(name = "socket_stats")]
+static SOCKET_STATS: LruHashMap<SocketKey, SocketStats> = LruHashMap::pinned(16384, 0);
+
+// 挂载到 cgroup 的 sock_ops 程序: 连接建立时开启 RTT 和状态回调，每次回调记录协议栈中的指标
+#[sock_ops]
+pub fn xnet_sockops(ctx: SockOpsContext) -> u32 {
+    if ctx.family() != AF_INET {
+        return 0;
+    }
+
+    match ctx.op() {
+        BPF_SOCK_OPS_ACTIVE_ESTABLISHED_CB | BPF_SOCK_OPS_PASSIVE_ESTABLISHED_CB => {
+            let flags = ctx.cb_flags() | BPF_SOCK_OPS_RTT_CB_FLAG | BPF_SOCK_OPS_STATE_CB_FLAG;
+            let _ = ctx.set_cb_flags(flags as i32);
+            record(&ctx, None, false);
+        }
+        BPF_SOCK_OPS_RTT_CB => record(&ctx, None, true),
+        // args[1] 为新状态
+        BPF_SOCK_OPS_STATE_CB => record(&ctx, Some(ctx.arg(1)), false),
+        _ => {}
+    }
+    0
+}
+
+fn record(ctx: &SockOpsContext, state: Option<u32>, rtt_sample: bool) {
+    let key = SocketKey {
+        local_addr: ctx.local_ip4(),
+        remote_addr: ctx.remote_ip4(),
+        local_port: ctx.local_port() as u16,
+        // remote_port 为网络字节序，位于高16位
+        remote_port: u32::from_be(ctx.remote_port()) as u16,
+    };
+    let now = unsafe { bpf_ktime_get_ns() };
+
+    let (established_ns, rtt_samples) = match SOCKET_STATS.get_ptr(&key) {
+        Some(stats) => unsafe { ((*stats).established_ns, (*stats).rtt_samples) },
+        None => (now, 0),
+    };
+
+    let ops = ctx.ops;
+    let cookie = unsafe { bpf_get_socket_cookie(ops as *mut _) };
+    let stats = unsafe {
+        SocketStats {
+            bytes_acked: (*ops).bytes_acked,
+            bytes_received: (*ops).bytes_received,
+            established_ns,
+            last_update_ns: now,
+            rtt_samples: rtt_samples + rtt_sample as u64,
+            // srtt_us 为 8 倍的平滑RTT
+            srtt_us: (*ops).srtt_us >> 3,
+            rtt_min_us: (*ops).rtt_min,
+            snd_cwnd: (*ops).snd_cwnd,
+            snd_ssthresh: (*ops).snd_ssthresh,
+            total_retrans: (*ops).total_retrans,
+            segs_in: (*ops).segs_in,
+            segs_out: (*ops).segs_out,
+            state: state.unwrap_or((*ops).state),
+            cookie,
+        }
+    };
+    let _ = SOCKET_STATS.insert(&key, &stats, 0);
+}
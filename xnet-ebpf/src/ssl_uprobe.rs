@@ -0,0 +1,106 @@
+use aya_ebpf::{
+    helpers::{bpf_get_current_comm, bpf_get_current_pid_tgid, bpf_ktime_get_ns},
+    macros::{map, uprobe, uretprobe},
+    maps::{HashMap, LruHashMap},
+    programs::{ProbeContext, RetProbeContext},
+};
+
+use xnet_common::{TlsConnKey, TlsConnStats};
+
+// 进行中的 SSL_read/SSL_write 调用的 SSL* 参数，key 为 pid_tgid，返回时取出
+#[map(name = "ssl_args")]
+static SSL_ARGS: HashMap<u64, u64> = HashMap::pinned(10240, 0);
+
+// SSL_set_fd 设置的 socket fd
+#[map(name = "ssl_fds")]
+static SSL_FDS: LruHashMap<TlsConnKey, i32> = LruHashMap::pinned(16384, 0);
+
+// 每个 TLS 连接的明文字节数
+#[map(name = "tls_conn_stats")]
+static TLS_CONN_STATS: LruHashMap<TlsConnKey, TlsConnStats> =
+    LruHashMap::pinned(16384, 0);
+
+fn conn_key(pid_tgid: u64, ssl: u64) -> TlsConnKey {
+    TlsConnKey {
+        tgid: (pid_tgid >> 32) as u32,
+        reserved: 0,
+        ssl,
+    }
+}
+
+// int SSL_set_fd(SSL *ssl, int fd)
+#[uprobe]
+pub fn xnet_ssl_set_fd(ctx: ProbeContext) -> u32 {
+    let (Some(ssl), Some(fd)) = (ctx.arg::<u64>(0), ctx.arg::<i32>(1)) else {
+        return 0;
+    };
+    let key = conn_key(bpf_get_current_pid_tgid(), ssl);
+    let _ = SSL_FDS.insert(&key, &fd, 0);
+    0
+}
+
+// SSL_read/SSL_write 入口，记录 SSL* 供返回时使用
+#[uprobe]
+pub fn xnet_ssl_enter(ctx: ProbeContext) -> u32 {
+    if let Some(ssl) = ctx.arg::<u64>(0) {
+        let _ = SSL_ARGS.insert(&bpf_get_current_pid_tgid(), &ssl, 0);
+    }
+    0
+}
+
+// int SSL_read(SSL *ssl, void *buf, int num)，返回读到的明文字节数
+#[uretprobe]
+pub fn xnet_ssl_read_ret(ctx: RetProbeContext) -> u32 {
+    record(&ctx, false);
+    0
+}
+
+// int SSL_write(SSL *ssl, const void *buf, int num)，返回写入的明文字节数
+#[uretprobe]
+pub fn xnet_ssl_write_ret(ctx: RetProbeContext) -> u32 {
+    record(&ctx, true);
+    0
+}
+
+fn record(ctx: &RetProbeContext, write: bool) {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let Some(&ssl) = (unsafe { SSL_ARGS.get(&pid_tgid) }) else {
+        return;
+    };
+    let _ = SSL_ARGS.remove(&pid_tgid);
+
+    // 返回值 <= 0 表示失败或需要重试
+    let bytes = match ctx.ret::<i32>() {
+        Some(ret) if ret > 0 => ret as u64,
+        _ => return,
+    };
+    let key = conn_key(pid_tgid, ssl);
+    let now = unsafe { bpf_ktime_get_ns() };
+
+    if let Some(stats) = TLS_CONN_STATS.get_ptr_mut(&key) {
+        unsafe {
+            if write {
+                (*stats).write_bytes += bytes;
+                (*stats).writes += 1;
+            } else {
+                (*stats).read_bytes += bytes;
+                (*stats).reads += 1;
+            }
+            (*stats).last_ns = now;
+        }
+        return;
+    }
+
+    let fd = unsafe { SSL_FDS.get(&key) }.copied().unwrap_or(-1);
+    let stats = TlsConnStats {
+        read_bytes: if write { 0 } else { bytes },
+        write_bytes: if write { bytes } else { 0 },
+        reads: !write as u64,
+        writes: write as u64,
+        last_ns: now,
+        fd,
+        reserved: 0,
+        comm: bpf_get_current_comm().unwrap_or([0; 16]),
+    };
+    let _ = TLS_CONN_STATS.insert(&key, &stats, 0);
+}
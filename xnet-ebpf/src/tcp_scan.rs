@@ -0,0 +1,67 @@
+use aya_ebpf::{
+    helpers::bpf_ktime_get_ns,
+    macros::map,
+    maps::{Array, LruHashMap},
+};
+use xnet_common::{
+    TcpScanStats, ALERT_TCP_FIN_SCAN, ALERT_TCP_NULL_SCAN, ALERT_TCP_XMAS_SCAN, TCP_SCAN_DROP,
+};
+
+const TCP_FIN: u8 = 0x01;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_URG: u8 = 0x20;
+
+// 发出非法标志组合的源IP(网络字节序)
+#[map(name = "tcp_scan_sources")]
+static TCP_SCAN_SOURCES: LruHashMap<u32, TcpScanStats> = LruHashMap::pinned(4096, 0);
+
+// 下标 0 为 TCP_SCAN_COUNT 或 TCP_SCAN_DROP，由用户空间写入
+#[map(name = "tcp_scan_config")]
+static TCP_SCAN_CONFIG: Array<u32> = Array::pinned(1, 0);
+
+// 正常的 TCP 栈不会发出的标志组合，返回对应的告警类型
+pub fn classify(flags: u8) -> Option<u32> {
+    // 只看 FIN、SYN、RST、PSH、ACK、URG，忽略 ECE 和 CWR
+    let flags = flags & 0x3f;
+    if flags == 0 {
+        Some(ALERT_TCP_NULL_SCAN)
+    } else if flags == TCP_FIN {
+        Some(ALERT_TCP_FIN_SCAN)
+    } else if flags & (TCP_FIN | TCP_PSH | TCP_URG | TCP_ACK) == TCP_FIN | TCP_PSH | TCP_URG {
+        Some(ALERT_TCP_XMAS_SCAN)
+    } else {
+        None
+    }
+}
+
+// 按源地址计数，需要丢弃时返回 true
+pub fn record(src_ip: u32, dst_port: u16, flags: u8, kind: u32) -> bool {
+    let now = unsafe { bpf_ktime_get_ns() };
+    match TCP_SCAN_SOURCES.get_ptr_mut(&src_ip) {
+        Some(stats) => unsafe {
+            let stats = &mut *stats;
+            match kind {
+                ALERT_TCP_NULL_SCAN => stats.null += 1,
+                ALERT_TCP_FIN_SCAN => stats.fin += 1,
+                _ => stats.xmas += 1,
+            }
+            stats.last_seen_ns = now;
+            stats.last_dst_port = dst_port;
+            stats.last_flags = flags;
+        },
+        None => {
+            let stats = TcpScanStats {
+                null: (kind == ALERT_TCP_NULL_SCAN) as u64,
+                fin: (kind == ALERT_TCP_FIN_SCAN) as u64,
+                xmas: (kind == ALERT_TCP_XMAS_SCAN) as u64,
+                last_seen_ns: now,
+                last_dst_port: dst_port,
+                last_flags: flags,
+                reserved: [0; 5],
+            };
+            let _ = TCP_SCAN_SOURCES.insert(&src_ip, &stats, 0);
+        }
+    }
+    TCP_SCAN_CONFIG.get(0).copied() == Some(TCP_SCAN_DROP)
+}
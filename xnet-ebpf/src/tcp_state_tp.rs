@@ -0,0 +1,74 @@
+use aya_ebpf::{
+    helpers::bpf_ktime_get_ns,
+    macros::{map, tracepoint},
+    maps::{HashMap, LruHashMap},
+    programs::TracePointContext,
+};
+
+use xnet_common::{SocketKey, TcpSockState, TcpStateOffsets};
+
+const AF_INET: u16 = 2;
+const IPPROTO_TCP: u16 = 6;
+
+// sock:inet_sock_set_state 的字段偏移，加载时由用户空间按内核 BTF 设置
+#[no_mangle]
+static TCP_STATE_OFFSETS: TcpStateOffsets = TcpStateOffsets::DEFAULT;
+
+// 各 TCP socket 的当前状态，关闭后保留最终状态，由 LRU 淘汰
+#[map(name = "tcp_sock_states")]
+static TCP_SOCK_STATES: LruHashMap<SocketKey, TcpSockState> =
+    LruHashMap::pinned(16384, 0);
+
+// 状态变化计数，key 为 旧状态 << 8 | 新状态
+#[map(name = "tcp_state_transitions")]
+static TCP_STATE_TRANSITIONS: HashMap<u32, u64> = HashMap::pinned(256, 0);
+
+// 内核 TCP 协议栈的状态变化，包括超时和内核主动关闭，不依赖观测到的 SYN/FIN/RST
+#[tracepoint]
+pub fn xnet_tcp_state(ctx: TracePointContext) -> u32 {
+    let _ = try_tcp_state(&ctx);
+    0
+}
+
+fn try_tcp_state(ctx: &TracePointContext) -> Result<(), i64> {
+    let offsets = unsafe { core::ptr::read_volatile(&TCP_STATE_OFFSETS) };
+    let family: u16 = unsafe { ctx.read_at(offsets.family as usize)? };
+    let protocol: u16 = unsafe { ctx.read_at(offsets.protocol as usize)? };
+    if family != AF_INET || protocol != IPPROTO_TCP {
+        return Ok(());
+    }
+
+    let old_state: i32 = unsafe { ctx.read_at(offsets.oldstate as usize)? };
+    let new_state: i32 = unsafe { ctx.read_at(offsets.newstate as usize)? };
+    // saddr/daddr 为网络字节序的字节数组，端口已转换为主机字节序
+    let key = SocketKey {
+        local_addr: unsafe { ctx.read_at::<u32>(offsets.saddr as usize)? },
+        remote_addr: unsafe { ctx.read_at::<u32>(offsets.daddr as usize)? },
+        local_port: unsafe { ctx.read_at::<u16>(offsets.sport as usize)? },
+        remote_port: unsafe { ctx.read_at::<u16>(offsets.dport as usize)? },
+    };
+    let now = unsafe { bpf_ktime_get_ns() };
+
+    let (opened_ns, transitions) = match unsafe { TCP_SOCK_STATES.get(&key) } {
+        Some(entry) => (entry.opened_ns, entry.transitions),
+        None => (now, 0),
+    };
+    let entry = TcpSockState {
+        opened_ns,
+        changed_ns: now,
+        state: new_state as u32,
+        old_state: old_state as u32,
+        transitions: transitions + 1,
+        reserved: 0,
+    };
+    let _ = TCP_SOCK_STATES.insert(&key, &entry, 0);
+
+    let transition = ((old_state as u32 & 0xff) << 8) | (new_state as u32 & 0xff);
+    match TCP_STATE_TRANSITIONS.get_ptr_mut(&transition) {
+        Some(count) => unsafe { *count += 1 },
+        None => {
+            let _ = TCP_STATE_TRANSITIONS.insert(&transition, &1, 0);
+        }
+    }
+    Ok(())
+}
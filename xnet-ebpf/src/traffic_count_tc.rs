@@ -1,68 +1,205 @@
 use aya_ebpf::{
-    bindings::{TC_ACT_OK, TC_ACT_RECLASSIFY},
+    bindings::{TC_ACT_OK, TC_ACT_RECLASSIFY, TC_ACT_SHOT},
+    helpers::{bpf_get_netns_cookie, bpf_get_prandom_u32, bpf_get_socket_cookie, bpf_ktime_get_ns, bpf_skb_load_bytes},
     macros::{classifier, map},
-    maps::HashMap,
+    maps::{Array, HashMap, LruHashMap, RingBuf},
     programs::TcContext,
 };
 use aya_log_ebpf::{debug, info, WriteToBuf};
-use xnet_common::{int_to_ip, DeviceConnectionStats, DeviceStats, PortStats};
-use xnet_ebpf::{EthHdr, IpHdr, Protocol, TcpHdr};
+use xnet_common::{
+    int_to_ip, CaptureFilter, CapturedPacket, ConnectionEvent, DeviceCastStats, DeviceConnectionStats, DeviceStats,
+    DeviceStatsKey, DscpKey, FIREWALL_DIRECTION_ANY, FIREWALL_DIRECTION_EGRESS, FIREWALL_DIRECTION_INGRESS, DscpMark, FlowTuple,
+    MarkEntry, MarkKey, MARK_MAX_RULES, MARK_SKIP_MAX,
+    LatencyHistKey, PacketSample, PortStats, ProtocolStats, ProtocolStatsKey, CAPTURE_SNAPLEN, LATENCY_BUCKETS,
+    LATENCY_KIND_HANDSHAKE, LATENCY_KIND_INTER_PACKET, SAMPLE_HEADER_LEN, CONNECTION_CLOSE_FIN,
+    CONNECTION_CLOSE_RST,
+};
+use xnet_ebpf::{EthHdr, IpHdr, Protocol, TcpHdr, UdpHdr};
+
+use crate::accounting;
+use crate::firewall_rules::{self, FirewallPacket};
+use crate::icmpv6;
+use crate::mac_filter;
+use crate::quota;
+use crate::sampling;
 
 // 定义端口统计map
 #[map(name = "port_stats")]
-static mut PORT_STATS: HashMap<u16, PortStats> = HashMap::with_max_entries(65536, 0);
+static mut PORT_STATS: HashMap<u16, PortStats> = HashMap::pinned(65536, 0);
 
-// 定义总统计map
-#[map(name = "total_stats")]
-static mut TOTAL_STATS: HashMap<u32, u64> = HashMap::with_max_entries(2, 0);
+// 各目的端口收到的新建连接请求(SYN)数
+#[map(name = "port_syns")]
+static mut PORT_SYNS: HashMap<u16, u64> = HashMap::pinned(65536, 0);
 
 // 定义设备map流量统计，key为设备名_方向，value为流量统计
 // 流量统计包含总包数、总字节数、最后活跃时间
 #[map(name = "device_stats")]
-static mut DEVICE_STATS: HashMap<u32, DeviceStats> = HashMap::with_max_entries(1024, 0);
+static mut DEVICE_STATS: HashMap<DeviceStatsKey, DeviceStats> = HashMap::pinned(1024, 0);
+
+// 设备按单播、广播、组播区分的流量，key 与 device_stats 相同，统计所有协议
+#[map(name = "device_cast_stats")]
+static mut DEVICE_CAST_STATS: HashMap<DeviceStatsKey, DeviceCastStats> =
+    HashMap::pinned(1024, 0);
+
+// 加载时由用户空间设置，内核支持在 tc 程序中调用 bpf_get_netns_cookie 时为1
+// 为0时校验器会裁剪掉对该 helper 的调用，旧内核上仍可加载
+#[no_mangle]
+static NETNS_COOKIE_ENABLED: u8 = 0;
 
 // 设备名称到ID的映射，用于生成key
 #[map(name = "device_map")]
-static mut DEVICE_MAP: HashMap<[u8; 16], u32> = HashMap::with_max_entries(64, 0);
-
-// 当前设备上下文信息 - 使用设备ID作为key，如果没有此上下文，则不会统计流量
-#[map(name = "device_context")]
-static mut DEVICE_CONTEXT: HashMap<u32, u32> = HashMap::with_max_entries(64, 0);
+static mut DEVICE_MAP: HashMap<[u8; 16], u32> = HashMap::pinned(64, 0);
 
 // 记录设备的连接的信息，例如 device_id, src_port, dst_port, direction, protocol, timestamp, total_packets, total_bytes
 #[map(name = "device_connection_stats")]
 static mut DEVICE_CONNECTION_STATS: HashMap<u32, DeviceConnectionStats> =
-    HashMap::with_max_entries(1024, 0);
+    HashMap::pinned(1024, 0);
+
+// TCP流的序列号跟踪状态，仅内核使用
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TcpFlowState {
+    pub highest_seq_end: u32, // 已观测到的最大序列号(seq + 段长度)
+    pub last_ack: u32,        // 最近一次ACK号
+    pub last_window: u16,     // 最近一次窗口大小
+    pub initialized: u16,
+}
+
+// 按四元组跟踪TCP序列号，用于识别重传和重复ACK
+#[map(name = "tcp_flow_state")]
+static mut TCP_FLOW_STATE: LruHashMap<u64, TcpFlowState> = LruHashMap::pinned(16384, 0);
+
+// 延迟直方图，按设备、服务端口、延迟类型和桶统计样本数
+#[map(name = "latency_hist")]
+static mut LATENCY_HIST: HashMap<LatencyHistKey, u64> = HashMap::pinned(16384, 0);
+
+// 记录SYN的时间戳，收到对应的SYN-ACK时计算握手延迟
+#[map(name = "handshake_start")]
+static mut HANDSHAKE_START: LruHashMap<u64, u64> = LruHashMap::pinned(16384, 0);
+
+// 记录每个流最近一个包的时间戳，用于计算包间隔
+#[map(name = "flow_last_seen")]
+static mut FLOW_LAST_SEEN: LruHashMap<u64, u64> = LruHashMap::pinned(16384, 0);
+
+// 连接跟踪配置，index 0 非0时启用连接生命周期事件
+#[map(name = "connection_trace_config")]
+static mut CONNECTION_TRACE_CONFIG: Array<u32> = Array::pinned(1, 0);
+
+// 进行中的TCP连接，key为与方向无关的四元组哈希，在SYN时创建，FIN/RST时删除
+#[map(name = "connection_track")]
+static mut CONNECTION_TRACK: LruHashMap<u64, ConnectionEvent> =
+    LruHashMap::pinned(16384, 0);
+
+// 结束的连接通过ring buffer推送到用户空间，由用户空间导出为trace span
+#[map(name = "connection_events")]
+static mut CONNECTION_EVENTS: RingBuf = RingBuf::pinned(256 * 1024, 0);
+
+// 包采样配置，index 0 为采样率N（每N个包采样1个），0表示关闭采样
+#[map(name = "sample_config")]
+static mut SAMPLE_CONFIG: Array<u32> = Array::pinned(1, 0);
+
+// 采样得到的包头通过ring buffer推送到用户空间，由用户空间导出为sFlow
+#[map(name = "sample_events")]
+static mut SAMPLE_EVENTS: RingBuf = RingBuf::pinned(256 * 1024, 0);
+
+// 抓包过滤条件，index 0 为当前抓包任务的过滤条件
+#[map(name = "capture_config")]
+static mut CAPTURE_CONFIG: Array<CaptureFilter> = Array::pinned(1, 0);
+
+// 抓到的包通过ring buffer推送到用户空间，由用户空间写成pcap
+#[map(name = "capture_events")]
+static mut CAPTURE_EVENTS: RingBuf = RingBuf::pinned(1024 * 1024, 0);
+
+// 设备按 IP 协议号区分的流量，统计所有 IPv4 和 IPv6 包
+#[map(name = "protocol_stats")]
+static PROTOCOL_STATS: HashMap<ProtocolStatsKey, ProtocolStats> = HashMap::pinned(4096, 0);
+
+// DSCP重标记规则，由用户空间通过 /dscp/rules 配置
+#[map(name = "dscp_rules")]
+static DSCP_RULES: HashMap<DscpKey, DscpMark> = HashMap::pinned(1024, 0);
+
+// skb mark 规则，由用户空间通过 /mark/rules 配置
+#[map(name = "mark_rules")]
+static MARK_RULES: HashMap<MarkKey, MarkEntry> = HashMap::pinned(MARK_MAX_RULES, 0);
+
+// 已带有这些 mark 的包由其他 iptables/tc 规则处理，由用户空间通过 /mark/skip 或 --skip-mark 配置
+#[map(name = "mark_skip")]
+static MARK_SKIP: Array<MarkEntry> = Array::pinned(MARK_SKIP_MAX, 0);
+
+// 没有以太网头的三层设备(tun、wireguard 等)，key 为 ifindex，由用户空间挂载时按设备类型写入
+#[map(name = "l3_devices")]
+static L3_DEVICES: HashMap<u32, u32> = HashMap::pinned(256, 0);
+
+// 网络层头部的偏移，三层设备上包直接从 IP 头开始
+pub(crate) fn l2_len(ctx: &TcContext) -> usize {
+    if L3_DEVICES.get_ptr(&device_id(ctx)).is_some() {
+        0
+    } else {
+        core::mem::size_of::<EthHdr>()
+    }
+}
+
+// 网络层协议(以太网类型)，三层设备没有以太网头，取 skb->protocol
+pub(crate) fn l3_proto(ctx: &TcContext, l2_len: usize) -> Option<u16> {
+    if l2_len == 0 {
+        return Some(u16::from_be(unsafe { (*ctx.skb.skb).protocol } as u16));
+    }
+    ctx.load::<EthHdr>(0).ok().map(|eth_hdr| u16::from_be(eth_hdr.eth_proto))
+}
 
 // 生成设备统计key的函数
-fn generate_device_key(device_id: u32, is_ingress: bool) -> u32 {
+fn generate_device_key(netns_cookie: u64, device_id: u32, is_ingress: bool) -> DeviceStatsKey {
     // 使用设备ID和方向生成key
     // 偶数表示ingress，奇数表示egress
-    if is_ingress {
+    let key = if is_ingress {
         device_id * 2
     } else {
         device_id * 2 + 1
+    };
+    DeviceStatsKey {
+        netns_cookie,
+        key,
+        reserved: 0,
     }
 }
 
+// 包所在网络命名空间的 cookie，未启用时为0
+fn netns_cookie(ctx: &TcContext) -> u64 {
+    if unsafe { core::ptr::read_volatile(&NETNS_COOKIE_ENABLED) } == 0 {
+        return 0;
+    }
+    unsafe { bpf_get_netns_cookie(ctx.skb.skb as *mut _) }
+}
+
+// 包所属本机 socket 的 cookie，入方向的包在 TC 中还没有关联 socket，为0
+fn socket_cookie(ctx: &TcContext) -> u64 {
+    unsafe { bpf_get_socket_cookie(ctx.skb.skb as *mut _) }
+}
+
 // 生成设备连接统计key的函数
-fn generate_connection_key(
-    device_id: u32,
-    src_port: u16,
-    dst_port: u16,
-    direction: u32,
-    protocol: u32,
-) -> u32 {
+fn generate_connection_key(netns_cookie: u64, device_id: u32, flow: &FlowTuple, direction: u32) -> u32 {
     // 使用设备ID、端口、方向和协议生成key
     // 使用简单的哈希算法组合这些值
     let mut key = device_id;
-    key = key.wrapping_add(src_port as u32);
-    key = key.wrapping_add((dst_port as u32) << 16);
+    key = key.wrapping_add(flow.src_port as u32);
+    key = key.wrapping_add((flow.dst_port as u32) << 16);
     key = key.wrapping_add(direction << 24);
-    key = key.wrapping_add(protocol << 28);
+    key = key.wrapping_add((flow.protocol as u32) << 28);
+    // 混入地址，端口相同但地址不同的流分开统计
+    key ^= fold_addr(&flow.saddr).wrapping_mul(0x9E37_79B1) ^ fold_addr(&flow.daddr);
+    // 混入命名空间，避免不同命名空间中相同 ifindex 的连接合并
+    key ^= (netns_cookie as u32) ^ ((netns_cookie >> 32) as u32);
     key
 }
 
+// 把16字节地址折叠为 u32
+fn fold_addr(addr: &[u8; 16]) -> u32 {
+    u32::from_ne_bytes([addr[0], addr[1], addr[2], addr[3]])
+        ^ u32::from_ne_bytes([addr[4], addr[5], addr[6], addr[7]])
+        ^ u32::from_ne_bytes([addr[8], addr[9], addr[10], addr[11]])
+        ^ u32::from_ne_bytes([addr[12], addr[13], addr[14], addr[15]])
+}
+
 // 检查设备是否为veth设备
 fn is_veth_device(device_id: u32) -> bool {
     unsafe {
@@ -141,24 +278,29 @@ fn query_device_connection_stats(device_id: u32) -> Option<DeviceConnectionStats
 }
 
 // 更新设备统计信息
-fn update_device_stats(device_id: u32, is_ingress: bool, packet_len: u64) -> Result<(), ()> {
-    let key = generate_device_key(device_id, is_ingress);
+fn update_device_stats(
+    netns_cookie: u64,
+    device_id: u32,
+    is_ingress: bool,
+    packet_len: u64,
+) -> Result<(), ()> {
+    let key = generate_device_key(netns_cookie, device_id, is_ingress);
 
     unsafe {
-        let current_total = TOTAL_STATS.get(&0).unwrap_or(&0);
+        let now = bpf_ktime_get_ns();
 
         if let Some(stats) = DEVICE_STATS.get(&key) {
             let new_stats = DeviceStats {
                 packets: stats.packets + 1,
                 bytes: stats.bytes + packet_len,
-                last_seen: *current_total,
+                last_seen: now,
             };
             DEVICE_STATS.insert(&key, &new_stats, 0);
         } else {
             let new_stats = DeviceStats {
                 packets: 1,
                 bytes: packet_len,
-                last_seen: *current_total,
+                last_seen: now,
             };
             DEVICE_STATS.insert(&key, &new_stats, 0);
         }
@@ -167,44 +309,129 @@ fn update_device_stats(device_id: u32, is_ingress: bool, packet_len: u64) -> Res
     Ok(())
 }
 
+fn update_protocol_stats(device: &DeviceStatsKey, protocol: u8, packet_len: u64) {
+    let key = ProtocolStatsKey {
+        device: *device,
+        protocol: protocol as u32,
+        reserved: 0,
+    };
+    match PROTOCOL_STATS.get_ptr_mut(&key) {
+        Some(stats) => unsafe {
+            (*stats).packets += 1;
+            (*stats).bytes += packet_len;
+        },
+        None => {
+            let stats = ProtocolStats {
+                packets: 1,
+                bytes: packet_len,
+            };
+            let _ = PROTOCOL_STATS.insert(&key, &stats, 0);
+        }
+    }
+}
+
+// 按目的MAC统计单播、广播和组播流量
+fn update_device_cast_stats(key: &DeviceStatsKey, eth_hdr: &EthHdr, packet_len: u64) {
+    let stats = match unsafe { DEVICE_CAST_STATS.get_ptr_mut(key) } {
+        Some(stats) => stats,
+        None => {
+            let zero = DeviceCastStats {
+                unicast_packets: 0,
+                unicast_bytes: 0,
+                broadcast_packets: 0,
+                broadcast_bytes: 0,
+                multicast_packets: 0,
+                multicast_bytes: 0,
+            };
+            let _ = unsafe { DEVICE_CAST_STATS.insert(key, &zero, 0) };
+            match unsafe { DEVICE_CAST_STATS.get_ptr_mut(key) } {
+                Some(stats) => stats,
+                None => return,
+            }
+        }
+    };
+    let dmac = eth_hdr.eth_dmac;
+    unsafe {
+        if dmac == [0xff; 6] {
+            (*stats).broadcast_packets += 1;
+            (*stats).broadcast_bytes += packet_len;
+        } else if dmac[0] & 1 != 0 {
+            // 组播地址的 I/G 位为1，例如 mDNS 的 01:00:5e:00:00:fb、SSDP 的 01:00:5e:7f:ff:fa
+            (*stats).multicast_packets += 1;
+            (*stats).multicast_bytes += packet_len;
+        } else {
+            (*stats).unicast_packets += 1;
+            (*stats).unicast_bytes += packet_len;
+        }
+    }
+}
+
 // 更新设备连接统计信息
 fn update_device_connection_stats(
+    netns_cookie: u64,
+    socket_cookie: u64,
     device_id: u32,
-    src_port: u16,
-    dst_port: u16,
+    flow: &FlowTuple,
     is_ingress: bool,
-    protocol: u8,
-    packet_len: u64,
+    packets: u64,
+    bytes: u64,
+    tcp_events: TcpEvents,
 ) -> Result<(), ()> {
     let direction = adjust_direction_for_device(device_id, is_ingress);
-    let protocol_u32 = protocol as u32;
-    let key = generate_connection_key(device_id, src_port, dst_port, direction, protocol_u32);
+    let key = generate_connection_key(netns_cookie, device_id, flow, direction);
 
     unsafe {
-        let current_total = TOTAL_STATS.get(&0).unwrap_or(&0);
+        let now = bpf_ktime_get_ns();
 
         if let Some(stats) = DEVICE_CONNECTION_STATS.get(&key) {
             let new_stats = DeviceConnectionStats {
                 device_id: stats.device_id,
-                src_port: stats.src_port,
-                dst_port: stats.dst_port,
                 direction: stats.direction,
-                protocol: stats.protocol,
-                timestamp: *current_total,
-                total_packets: stats.total_packets + 1,
-                total_bytes: stats.total_bytes + packet_len,
+                flow: stats.flow,
+                timestamp: now,
+                total_packets: stats.total_packets + packets,
+                total_bytes: stats.total_bytes + bytes,
+                retransmissions: stats.retransmissions + tcp_events.retransmission as u64,
+                dup_acks: stats.dup_acks + tcp_events.dup_ack as u64,
+                last_window: tcp_events.window as u32,
+                min_window: if (tcp_events.window as u32) < stats.min_window {
+                    tcp_events.window as u32
+                } else {
+                    stats.min_window
+                },
+                max_window: if (tcp_events.window as u32) > stats.max_window {
+                    tcp_events.window as u32
+                } else {
+                    stats.max_window
+                },
+                reserved: 0,
+                netns_cookie: stats.netns_cookie,
+                first_seen: stats.first_seen,
+                // 同一四元组的 socket 关闭后重新建立时换成新的 cookie
+                socket_cookie: if socket_cookie != 0 {
+                    socket_cookie
+                } else {
+                    stats.socket_cookie
+                },
             };
             DEVICE_CONNECTION_STATS.insert(&key, &new_stats, 0);
         } else {
             let new_stats = DeviceConnectionStats {
                 device_id,
-                src_port,
-                dst_port,
                 direction,
-                protocol: protocol_u32,
-                timestamp: *current_total,
-                total_packets: 1,
-                total_bytes: packet_len,
+                flow: *flow,
+                timestamp: now,
+                total_packets: packets,
+                total_bytes: bytes,
+                retransmissions: tcp_events.retransmission as u64,
+                dup_acks: tcp_events.dup_ack as u64,
+                last_window: tcp_events.window as u32,
+                min_window: tcp_events.window as u32,
+                max_window: tcp_events.window as u32,
+                reserved: 0,
+                netns_cookie,
+                first_seen: now,
+                socket_cookie,
             };
             DEVICE_CONNECTION_STATS.insert(&key, &new_stats, 0);
         }
@@ -213,72 +440,722 @@ fn update_device_connection_stats(
     Ok(())
 }
 
-// 获取当前设备上下文
-fn get_current_device_context() -> Option<(u32, bool)> {
+// 单个TCP包的分析结果
+#[derive(Clone, Copy, Default)]
+struct TcpEvents {
+    retransmission: bool,
+    dup_ack: bool,
+    window: u16,
+}
+
+// 序列号比较，考虑回绕: a <= b
+fn seq_before_eq(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+fn tcp_flow_key(saddr: u32, daddr: u32, src_port: u16, dst_port: u16) -> u64 {
+    let addrs = ((saddr as u64) << 32) | daddr as u64;
+    let ports = ((src_port as u64) << 16) | dst_port as u64;
+    addrs ^ ports.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+// 跟踪TCP序列号，识别重传(段结束序列号不超过已观测最大值)和重复ACK(纯ACK且ACK号和窗口不变)
+fn track_tcp_sequence(ip_hdr: &IpHdr, tcp_hdr: &TcpHdr) -> TcpEvents {
+    let mut events = TcpEvents {
+        window: u16::from_be(tcp_hdr.window),
+        ..Default::default()
+    };
+
+    let flags = tcp_hdr.flags;
+    let syn = (flags & 0x02) != 0;
+    let fin = (flags & 0x01) != 0;
+    let rst = (flags & 0x04) != 0;
+    let ack = (flags & 0x10) != 0;
+
+    let ip_header_len = ((ip_hdr.version_ihl & 0x0f) as u32) * 4;
+    let tcp_header_len = ((tcp_hdr.doff_reserved >> 4) as u32) * 4;
+    let payload_len = (u16::from_be(ip_hdr.tot_len) as u32)
+        .saturating_sub(ip_header_len)
+        .saturating_sub(tcp_header_len);
+    let seg_len = payload_len + syn as u32 + fin as u32;
+
+    let seq = u32::from_be(tcp_hdr.seq);
+    let ack_seq = u32::from_be(tcp_hdr.ack_seq);
+    let window = events.window;
+    let seq_end = seq.wrapping_add(seg_len);
+
+    let key = tcp_flow_key(ip_hdr.saddr, ip_hdr.daddr, tcp_hdr.source, tcp_hdr.dest);
+    let mut state = match unsafe { TCP_FLOW_STATE.get(&key) } {
+        Some(state) => *state,
+        None => TcpFlowState {
+            highest_seq_end: seq_end,
+            last_ack: ack_seq,
+            last_window: window,
+            initialized: 0,
+        },
+    };
+
+    if state.initialized != 0 {
+        if seg_len > 0 {
+            if seq_before_eq(seq_end, state.highest_seq_end) {
+                events.retransmission = true;
+            }
+        } else if ack && !rst && ack_seq == state.last_ack && window == state.last_window {
+            events.dup_ack = true;
+        }
+    }
+
+    if state.initialized == 0 || !seq_before_eq(seq_end, state.highest_seq_end) {
+        state.highest_seq_end = seq_end;
+    }
+    if ack {
+        state.last_ack = ack_seq;
+        state.last_window = window;
+    }
+    state.initialized = 1;
+    unsafe {
+        let _ = TCP_FLOW_STATE.insert(&key, &state, 0);
+    }
+
+    events
+}
+
+// 延迟(纳秒)所在的直方图桶，按微秒取log2
+fn latency_bucket(delta_ns: u64) -> u8 {
+    let us = delta_ns / 1000;
+    let bits = 64 - us.leading_zeros();
+    let bucket = if bits > 0 { bits - 1 } else { 0 };
+    if bucket >= LATENCY_BUCKETS {
+        (LATENCY_BUCKETS - 1) as u8
+    } else {
+        bucket as u8
+    }
+}
+
+fn record_latency(device_id: u32, port: u16, kind: u8, delta_ns: u64) {
+    let key = LatencyHistKey {
+        device_id,
+        port,
+        kind,
+        bucket: latency_bucket(delta_ns),
+    };
     unsafe {
-        // 从设备上下文中获取当前设备ID和方向
-        // 遍历所有设备上下文，找到匹配的设备ID
-        for device_id in 1..64 {
-            if let Some(context) = DEVICE_CONTEXT.get(&device_id) {
-                let is_ingress = (*context >> 16) & 1 == 0;
-                return Some((device_id, is_ingress));
+        let count = LATENCY_HIST.get(&key).copied().unwrap_or(0);
+        let _ = LATENCY_HIST.insert(&key, &(count + 1), 0);
+    }
+}
+
+// 记录握手延迟和包间隔
+// 包间隔按较小的端口归类，通常即为服务端口
+fn track_latency(device_id: u32, ip_hdr: &IpHdr, tcp_hdr: &TcpHdr, protocol: u8) {
+    let now = unsafe { bpf_ktime_get_ns() };
+    let src_port = u16::from_be(tcp_hdr.source);
+    let dst_port = u16::from_be(tcp_hdr.dest);
+    let key = tcp_flow_key(ip_hdr.saddr, ip_hdr.daddr, tcp_hdr.source, tcp_hdr.dest);
+
+    if let Some(&last) = unsafe { FLOW_LAST_SEEN.get(&key) } {
+        let service_port = if src_port < dst_port { src_port } else { dst_port };
+        record_latency(
+            device_id,
+            service_port,
+            LATENCY_KIND_INTER_PACKET,
+            now.wrapping_sub(last),
+        );
+    }
+    unsafe {
+        let _ = FLOW_LAST_SEEN.insert(&key, &now, 0);
+    }
+
+    if protocol != 6 {
+        return;
+    }
+    let flags = tcp_hdr.flags;
+    let syn = (flags & 0x02) != 0;
+    let ack = (flags & 0x10) != 0;
+    if syn && !ack {
+        unsafe {
+            let _ = HANDSHAKE_START.insert(&key, &now, 0);
+        }
+    } else if syn && ack {
+        // SYN-ACK 与 SYN 方向相反
+        let syn_key = tcp_flow_key(ip_hdr.daddr, ip_hdr.saddr, tcp_hdr.dest, tcp_hdr.source);
+        if let Some(&start) = unsafe { HANDSHAKE_START.get(&syn_key) } {
+            record_latency(
+                device_id,
+                src_port,
+                LATENCY_KIND_HANDSHAKE,
+                now.wrapping_sub(start),
+            );
+            unsafe {
+                let _ = HANDSHAKE_START.remove(&syn_key);
             }
         }
-        // 如果没有找到上下文，返回None表示不统计
-        None
     }
 }
 
-// 根据TC挂载点确定方向
-fn get_tc_direction(ctx: &TcContext) -> bool {
-    // 这里我们需要根据TC程序的挂载点来确定方向
-    // 由于eBPF无法直接获取挂载点信息，我们使用一个简化的方案
-    // 在实际部署中，ingress和egress会使用不同的程序实例
-    true // 暂时返回true，表示ingress
-         // let mark = ctx.skb.mark();
-         // let is_ingress = mark & 1 == 0;
-         // return is_ingress;
+// 与方向无关的四元组哈希，同一连接的双向包得到相同的key
+fn connection_key(saddr: u32, daddr: u32, src_port: u16, dst_port: u16) -> u64 {
+    if (saddr, src_port) < (daddr, dst_port) {
+        tcp_flow_key(saddr, daddr, src_port, dst_port)
+    } else {
+        tcp_flow_key(daddr, saddr, dst_port, src_port)
+    }
+}
+
+// 跟踪TCP连接生命周期，连接结束时推送 ConnectionEvent
+fn track_connection(
+    device_id: u32,
+    ip_hdr: &IpHdr,
+    tcp_hdr: &TcpHdr,
+    packet_len: u64,
+    tcp_events: &TcpEvents,
+) {
+    match unsafe { CONNECTION_TRACE_CONFIG.get(0) } {
+        Some(&enabled) if enabled != 0 => {}
+        _ => return,
+    }
+
+    let now = unsafe { bpf_ktime_get_ns() };
+    let flags = tcp_hdr.flags;
+    let syn = (flags & 0x02) != 0;
+    let ack = (flags & 0x10) != 0;
+    let fin = (flags & 0x01) != 0;
+    let rst = (flags & 0x04) != 0;
+    let key = connection_key(ip_hdr.saddr, ip_hdr.daddr, tcp_hdr.source, tcp_hdr.dest);
+
+    if syn && !ack {
+        let conn = ConnectionEvent {
+            start_ns: now,
+            end_ns: 0,
+            packets: 1,
+            bytes: packet_len,
+            retransmissions: 0,
+            flow: FlowTuple::from_v4(
+                ip_hdr.saddr,
+                ip_hdr.daddr,
+                u16::from_be(tcp_hdr.source),
+                u16::from_be(tcp_hdr.dest),
+                6,
+            ),
+            device_id,
+            close_reason: 0,
+        };
+        unsafe {
+            let _ = CONNECTION_TRACK.insert(&key, &conn, 0);
+        }
+        return;
+    }
+
+    let Some(conn) = (unsafe { CONNECTION_TRACK.get_ptr_mut(&key) }) else {
+        return;
+    };
+    unsafe {
+        (*conn).packets += 1;
+        (*conn).bytes += packet_len;
+        (*conn).retransmissions += tcp_events.retransmission as u64;
+    }
+    if !fin && !rst {
+        return;
+    }
+
+    if let Some(mut entry) = unsafe { CONNECTION_EVENTS.reserve::<ConnectionEvent>(0) } {
+        unsafe {
+            let mut event = *conn;
+            event.end_ns = now;
+            event.close_reason = if rst {
+                CONNECTION_CLOSE_RST
+            } else {
+                CONNECTION_CLOSE_FIN
+            };
+            entry.write(event);
+        }
+        entry.submit(0);
+    }
+    unsafe {
+        let _ = CONNECTION_TRACK.remove(&key);
+    }
 }
 
-#[classifier]
-pub fn xnet_tc(ctx: TcContext) -> i32 {
-    debug!(&ctx, "xnet_tc");
+// 程序所在设备的 ifindex，即设备ID，ingress 和 egress 上都是当前处理该包的设备
+fn device_id(ctx: &TcContext) -> u32 {
+    unsafe { (*ctx.skb.skb).ifindex }
+}
+
+// 发出的包参与防火墙规则匹配的信息
+fn firewall_packet(
+    ctx: &TcContext,
+    data: usize,
+    data_end: usize,
+    l4_offset: usize,
+    ip_hdr: &IpHdr,
+) -> FirewallPacket {
+    let protocol = ip_hdr.protocol;
+    let (src_port, dst_port) = if (protocol == 6 || protocol == 17) && data + l4_offset + 4 <= data_end
+    {
+        // TCP和UDP头部的端口位置相同
+        let udp_hdr = unsafe { &*((data + l4_offset) as *const UdpHdr) };
+        (u16::from_be(udp_hdr.source), u16::from_be(udp_hdr.dest))
+    } else {
+        (0, 0)
+    };
+    FirewallPacket {
+        saddr: ip_hdr.saddr,
+        daddr: ip_hdr.daddr,
+        src_port,
+        dst_port,
+        protocol,
+        direction: FIREWALL_DIRECTION_EGRESS,
+        len: ctx.len() as u64,
+    }
+}
+
+// 按 1-in-N 的概率采样包头，并推送到 sample_events ring buffer
+fn sample_packet(ctx: &TcContext) {
+    let rate = match unsafe { SAMPLE_CONFIG.get(0) } {
+        Some(&rate) if rate > 0 => rate,
+        _ => return,
+    };
+    if unsafe { bpf_get_prandom_u32() } % rate != 0 {
+        return;
+    }
+
+    let frame_len = ctx.len();
+    let header_len = if frame_len < SAMPLE_HEADER_LEN as u32 {
+        frame_len
+    } else {
+        SAMPLE_HEADER_LEN as u32
+    };
+    if header_len == 0 {
+        return;
+    }
+
+    let Some(mut entry) = (unsafe { SAMPLE_EVENTS.reserve::<PacketSample>(0) }) else {
+        return;
+    };
+    let sample = entry.as_mut_ptr();
+    let skb = ctx.skb.skb;
+    let ret = unsafe {
+        let ifindex = (*skb).ifindex;
+        (*sample).ifindex = ifindex;
+        // ingress时 ingress_ifindex 与当前设备一致，本地发出或转发的包则不同
+        (*sample).direction = if (*skb).ingress_ifindex == ifindex { 0 } else { 1 };
+        (*sample).frame_len = frame_len;
+        (*sample).header_len = header_len;
+        bpf_skb_load_bytes(
+            skb as *const _,
+            0,
+            (*sample).header.as_mut_ptr() as *mut _,
+            header_len,
+        )
+    };
 
+    if ret == 0 {
+        entry.submit(0);
+    } else {
+        entry.discard(0);
+    }
+}
+
+// 检查包是否匹配抓包过滤条件中的IP和端口
+fn capture_filter_match(ctx: &TcContext, filter: &CaptureFilter, l2_len: usize) -> bool {
     let data = ctx.data();
     let data_end = ctx.data_end();
-    let eth_size = core::mem::size_of::<EthHdr>();
-    if data + eth_size > data_end {
-        return TC_ACT_OK;
+    let ip_size = core::mem::size_of::<IpHdr>();
+    if data + l2_len + ip_size > data_end {
+        return false;
     }
 
-    let eth_hdr = unsafe { &*(data as *const EthHdr) };
-    let eth_proto = u16::from_be(eth_hdr.eth_proto);
-    if eth_proto != 0x0800 {
+    if l3_proto(ctx, l2_len) != Some(0x0800) {
+        return false;
+    }
+
+    let ip_hdr = unsafe { &*((data + l2_len) as *const IpHdr) };
+    if filter.ip != 0 && ip_hdr.saddr != filter.ip && ip_hdr.daddr != filter.ip {
+        return false;
+    }
+
+    if filter.port != 0 {
+        if ip_hdr.protocol != 6 && ip_hdr.protocol != 17 {
+            return false;
+        }
+        // TCP和UDP头部的端口位置相同
+        let transport_offset = l2_len + ip_size;
+        if data + transport_offset + 4 > data_end {
+            return false;
+        }
+        let tcp_hdr = unsafe { &*((data + transport_offset) as *const TcpHdr) };
+        let src_port = u16::from_be(tcp_hdr.source);
+        let dst_port = u16::from_be(tcp_hdr.dest);
+        if src_port != filter.port && dst_port != filter.port {
+            return false;
+        }
+    }
+
+    true
+}
+
+// 抓包: 匹配过滤条件的包推送到 capture_events ring buffer，三层设备上的包从 IP 头开始
+fn capture_packet(ctx: &TcContext, l2_len: usize) {
+    let filter = match unsafe { CAPTURE_CONFIG.get(0) } {
+        Some(filter) if filter.enabled != 0 => *filter,
+        _ => return,
+    };
+
+    let skb = ctx.skb.skb;
+    let ifindex = unsafe { (*skb).ifindex };
+    if filter.ifindex != 0 && filter.ifindex != ifindex {
+        return;
+    }
+    if (filter.ip != 0 || filter.port != 0) && !capture_filter_match(ctx, &filter, l2_len) {
+        return;
+    }
+
+    let frame_len = ctx.len();
+    let header_len = if frame_len < CAPTURE_SNAPLEN as u32 {
+        frame_len
+    } else {
+        CAPTURE_SNAPLEN as u32
+    };
+    if header_len == 0 {
+        return;
+    }
+
+    let Some(mut entry) = (unsafe { CAPTURE_EVENTS.reserve::<CapturedPacket>(0) }) else {
+        return;
+    };
+    let packet = entry.as_mut_ptr();
+    let ret = unsafe {
+        (*packet).timestamp_ns = bpf_ktime_get_ns();
+        (*packet).ifindex = ifindex;
+        (*packet).direction = if (*skb).ingress_ifindex == ifindex { 0 } else { 1 };
+        (*packet).frame_len = frame_len;
+        (*packet).header_len = header_len;
+        bpf_skb_load_bytes(
+            skb as *const _,
+            0,
+            (*packet).header.as_mut_ptr() as *mut _,
+            header_len,
+        )
+    };
+
+    if ret == 0 {
+        entry.submit(0);
+    } else {
+        entry.discard(0);
+    }
+}
+
+// 按 目的IP+端口、源IP+端口、目的端口、源端口、目的IP、源IP 的顺序查找第一条匹配的规则
+fn rule_lookup<V>(
+    rules: &HashMap<DscpKey, V>,
+    ip_hdr: &IpHdr,
+    ports: Option<(u16, u16)>,
+) -> Option<*mut V> {
+    let ip_key = |addr: u32| DscpKey {
+        addr,
+        port: 0,
+        protocol: 0,
+        reserved: 0,
+    };
+    if let Some((src_port, dst_port)) = ports {
+        let port_key = |addr: u32, port: u16| DscpKey {
+            addr,
+            port,
+            protocol: ip_hdr.protocol,
+            reserved: 0,
+        };
+        let keys = [
+            port_key(ip_hdr.daddr, dst_port),
+            port_key(ip_hdr.saddr, src_port),
+            port_key(0, dst_port),
+            port_key(0, src_port),
+        ];
+        for key in keys.iter() {
+            if let Some(value) = rules.get_ptr_mut(key) {
+                return Some(value);
+            }
+        }
+    }
+    rules
+        .get_ptr_mut(&ip_key(ip_hdr.daddr))
+        .or_else(|| rules.get_ptr_mut(&ip_key(ip_hdr.saddr)))
+}
+
+// DSCP 和 skb mark 规则匹配用的IPv4头和端口(网络字节序)
+fn rule_match(ctx: &TcContext, l2_len: usize) -> Option<(IpHdr, Option<(u16, u16)>)> {
+    if l3_proto(ctx, l2_len) != Some(0x0800) {
+        return None;
+    }
+    let ip_hdr: IpHdr = ctx.load(l2_len).ok()?;
+
+    // 非首个分片不带四层头部，只按IP匹配
+    let mut ports = None;
+    let first_fragment = u16::from_be(ip_hdr.frag_off) & 0x1fff == 0;
+    if first_fragment && (ip_hdr.protocol == 6 || ip_hdr.protocol == 17) {
+        let ihl = ((ip_hdr.version_ihl & 0x0f) as usize) * 4;
+        if let Ok(raw) = ctx.load::<[u16; 2]>(l2_len + ihl) {
+            ports = Some((raw[0], raw[1]));
+        }
+    }
+    Some((ip_hdr, ports))
+}
+
+// DSCP重标记: 改写 ToS 字节的高6位，保留ECN位并更新IP校验和
+fn remark_dscp(ctx: &mut TcContext, l2_len: usize, ip_hdr: &IpHdr, ports: Option<(u16, u16)>) {
+    let Some(mark) = rule_lookup(&DSCP_RULES, ip_hdr, ports) else {
+        return;
+    };
+    let dscp = unsafe {
+        (*mark).packets += 1;
+        (*mark).dscp
+    };
+
+    let tos = (dscp << 2) | (ip_hdr.tos & 0x03);
+    if tos == ip_hdr.tos {
+        return;
+    }
+    // version_ihl 与 tos 组成校验和计算中的同一个16位字
+    let old = u16::from_ne_bytes([ip_hdr.version_ihl, ip_hdr.tos]);
+    let new = u16::from_ne_bytes([ip_hdr.version_ihl, tos]);
+    let check_offset = l2_len + core::mem::offset_of!(IpHdr, check);
+    if ctx
+        .l3_csum_replace(check_offset, old as u64, new as u64, 2)
+        .is_ok()
+    {
+        let _ = ctx.store(l2_len + core::mem::offset_of!(IpHdr, tos), &tos, 0);
+    }
+}
+
+fn mark_direction_matches(direction: u8, is_ingress: bool) -> bool {
+    direction == FIREWALL_DIRECTION_ANY
+        || (is_ingress && direction == FIREWALL_DIRECTION_INGRESS)
+        || (!is_ingress && direction == FIREWALL_DIRECTION_EGRESS)
+}
+
+// 按规则设置 skb->mark，入方向设置的 mark 可被 iptables 和策略路由使用，出方向的可被设备上 qdisc 的 fw 过滤器使用
+fn set_mark(ctx: &mut TcContext, is_ingress: bool, ip_hdr: &IpHdr, ports: Option<(u16, u16)>) {
+    let Some(rule) = rule_lookup(&MARK_RULES, ip_hdr, ports) else {
+        return;
+    };
+    unsafe {
+        if !mark_direction_matches((*rule).direction, is_ingress) {
+            return;
+        }
+        (*rule).packets += 1;
+        let skb = ctx.skb.skb;
+        (*skb).mark = ((*skb).mark & !(*rule).mask) | (*rule).value;
+    }
+}
+
+// 包已带有跳过条件中的 mark 时返回 true，这些包不统计、不过滤也不改写
+pub(crate) fn mark_skipped(ctx: &TcContext, is_ingress: bool) -> bool {
+    let mark = unsafe { (*ctx.skb.skb).mark };
+    // 跳过条件的 value 不为0，没有 mark 的包不会匹配
+    if mark == 0 {
+        return false;
+    }
+    for i in 0..MARK_SKIP_MAX {
+        let Some(entry) = MARK_SKIP.get_ptr_mut(i) else {
+            return false;
+        };
+        unsafe {
+            if (*entry).mask != 0
+                && mark & (*entry).mask == (*entry).value
+                && mark_direction_matches((*entry).direction, is_ingress)
+            {
+                (*entry).packets += 1;
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// 挂载到 ingress，处理收到的包
+#[classifier]
+pub fn xnet_tc_ingress(ctx: TcContext) -> i32 {
+    xnet_tc(ctx, true)
+}
+
+// 挂载到 egress，处理发出和转发的包
+#[classifier]
+pub fn xnet_tc_egress(ctx: TcContext) -> i32 {
+    xnet_tc(ctx, false)
+}
+
+// 累加端口统计，同时按收发方向分别计数
+fn update_port_stats(port: u16, is_ingress: bool, packets: u64, bytes: u64, now: u64) {
+    let mut stats = unsafe { PORT_STATS.get(&port) }.copied().unwrap_or(PortStats {
+        packets: 0,
+        bytes: 0,
+        last_seen: 0,
+        rx_packets: 0,
+        rx_bytes: 0,
+        tx_packets: 0,
+        tx_bytes: 0,
+    });
+    stats.packets += packets;
+    stats.bytes += bytes;
+    stats.last_seen = now;
+    if is_ingress {
+        stats.rx_packets += packets;
+        stats.rx_bytes += bytes;
+    } else {
+        stats.tx_packets += packets;
+        stats.tx_bytes += bytes;
+    }
+    let _ = unsafe { PORT_STATS.insert(&port, &stats, 0) };
+}
+
+// 更新端口和设备连接统计，IPv4 和 IPv6 的 TCP/UDP 流共用，只处理采中的包，计数按 weight 放大
+fn count_flow(
+    ctx: &TcContext,
+    device_id: u32,
+    is_ingress: bool,
+    flow: &FlowTuple,
+    packet_len: u64,
+    weight: u32,
+    tcp_events: TcpEvents,
+) {
+    let packets = weight as u64;
+    let bytes = packet_len * weight as u64;
+
+    // 更新源端口和目标端口统计信息
+    let now = unsafe { bpf_ktime_get_ns() };
+    update_port_stats(flow.src_port, is_ingress, packets, bytes, now);
+    update_port_stats(flow.dst_port, is_ingress, packets, bytes, now);
+
+    // 更新设备连接统计
+    let _ = update_device_connection_stats(
+        netns_cookie(ctx),
+        socket_cookie(ctx),
+        device_id,
+        flow,
+        is_ingress,
+        packets,
+        bytes,
+        tcp_events,
+    );
+}
+
+// 固定头之后直接是 TCP/UDP 的 IPv6 包计入流统计，不跟随扩展头，也不做重传、延迟和连接生命周期跟踪
+fn count_ipv6_flow(
+    ctx: &TcContext,
+    ip_offset: usize,
+    device_id: u32,
+    is_ingress: bool,
+    next_header: u8,
+    packet_len: u64,
+) {
+    let l4_offset = ip_offset + icmpv6::IPV6_HDR_LEN;
+    // 设备统计计入每个包
+    let _ = update_device_stats(netns_cookie(ctx), device_id, is_ingress, packet_len);
+    let Some(weight) = sampling::weight(device_id) else {
+        return;
+    };
+
+    let (Ok(saddr), Ok(daddr), Ok(src_port), Ok(dst_port)) = (
+        ctx.load::<[u8; 16]>(ip_offset + 8),
+        ctx.load::<[u8; 16]>(ip_offset + 24),
+        ctx.load::<u16>(l4_offset),
+        ctx.load::<u16>(l4_offset + 2),
+    ) else {
+        return;
+    };
+    let tcp_events = TcpEvents {
+        window: if next_header == 6 {
+            ctx.load::<u16>(l4_offset + 14).map(u16::from_be).unwrap_or(0)
+        } else {
+            0
+        },
+        ..TcpEvents::default()
+    };
+    let flow = FlowTuple::from_v6(saddr, daddr, u16::from_be(src_port), u16::from_be(dst_port), next_header);
+    count_flow(ctx, device_id, is_ingress, &flow, packet_len, weight, tcp_events);
+}
+
+fn xnet_tc(mut ctx: TcContext, is_ingress: bool) -> i32 {
+    debug!(&ctx, "xnet_tc");
+
+    // 已由其他 iptables/tc 规则处理的包
+    if mark_skipped(&ctx, is_ingress) {
         return TC_ACT_OK;
     }
 
+    // 需在改写包之前读取 XDP 写入的元数据
+    let seen_by_xdp = accounting::seen_by_xdp(&ctx);
+
+    // 设备出方向配额用完时丢弃或限速，被丢弃的包不计入统计
+    if !is_ingress && quota::exceeded(&ctx) {
+        return TC_ACT_SHOT;
+    }
+
+    // 三层设备上没有以太网头，以下偏移都从网络层头部开始计算
+    let l2_len = l2_len(&ctx);
+
+    // 按规则改写DSCP并设置 skb mark，需在读取 data 指针之前完成
+    if let Some((ip_hdr, ports)) = rule_match(&ctx, l2_len) {
+        remark_dscp(&mut ctx, l2_len, &ip_hdr, ports);
+        set_mark(&mut ctx, is_ingress, &ip_hdr, ports);
+    }
+
+    // sFlow 包采样，对所有协议生效
+    sample_packet(&ctx);
+
+    // API 触发的抓包
+    capture_packet(&ctx, l2_len);
+
+    let data = ctx.data();
+    let data_end = ctx.data_end();
+
     // 获取数据包长度
     let packet_len = ctx.len() as u64;
+    let device_id = device_id(&ctx);
+    let key = generate_device_key(netns_cookie(&ctx), device_id, is_ingress);
 
-    // 更新总统计信息
-    unsafe {
-        // 更新总包数
-        if let Some(total_packets) = TOTAL_STATS.get(&0) {
-            TOTAL_STATS.insert(&0, &(total_packets + 1), 0);
-        } else {
-            TOTAL_STATS.insert(&0, &1, 0);
+    let eth_proto = if l2_len == 0 {
+        u16::from_be(unsafe { (*ctx.skb.skb).protocol } as u16)
+    } else {
+        let eth_size = core::mem::size_of::<EthHdr>();
+        if data + eth_size > data_end {
+            return TC_ACT_OK;
         }
+        let eth_hdr = unsafe { &*(data as *const EthHdr) };
 
-        // 更新总字节数
-        if let Some(total_bytes) = TOTAL_STATS.get(&1) {
-            TOTAL_STATS.insert(&1, &(total_bytes + packet_len), 0);
-        } else {
-            TOTAL_STATS.insert(&1, &packet_len, 0);
+        // 二层过滤只作用于收到的帧，统计对所有协议生效
+        if is_ingress && mac_filter::denied(eth_hdr) {
+            return TC_ACT_SHOT;
         }
+        mac_filter::count(eth_hdr, packet_len);
+        update_device_cast_stats(&key, eth_hdr, packet_len);
+        u16::from_be(eth_hdr.eth_proto)
+    };
+
+    // IPv6 只按固定头的 next header 计入协议统计，不跟随扩展头
+    if eth_proto == 0x86DD {
+        if let Ok(next_header) = ctx.load::<u8>(l2_len + icmpv6::IPV6_NEXT_HEADER_OFFSET) {
+            update_protocol_stats(&key, next_header, packet_len);
+            if next_header == icmpv6::IPPROTO_ICMPV6 {
+                if let Ok(icmp_type) = ctx.load::<u8>(l2_len + icmpv6::IPV6_HDR_LEN) {
+                    icmpv6::count(&key, icmp_type);
+                }
+            } else if next_header == 6 || next_header == 17 {
+                count_ipv6_flow(&ctx, l2_len, device_id, is_ingress, next_header, packet_len);
+            }
+        }
+        return TC_ACT_OK;
+    }
+    if eth_proto != 0x0800 {
+        return TC_ACT_OK;
+    }
+
+    // 更新总统计信息，XDP 已计入的包不再重复计入
+    if !seen_by_xdp {
+        accounting::count_total(packet_len);
     }
 
     // 解析IP头
-    let ip_offset = eth_size;
+    let ip_offset = l2_len;
     let ip_size = core::mem::size_of::<IpHdr>();
     if data + ip_offset + ip_size > data_end {
         return TC_ACT_OK;
@@ -286,6 +1163,20 @@ pub fn xnet_tc(ctx: TcContext) -> i32 {
 
     let ip_hdr = unsafe { &*((data + ip_offset) as *const IpHdr) };
     let protocol = ip_hdr.protocol;
+    update_protocol_stats(&key, protocol, packet_len);
+
+    // 出方向的包按防火墙规则表过滤，并记录所属的流供默认拒绝模式放行回包，入方向由 XDP 程序处理
+    if !is_ingress {
+        let packet = firewall_packet(&ctx, data, data_end, ip_offset + ip_size, ip_hdr);
+        let now = unsafe { bpf_ktime_get_ns() };
+        // 发往信任名单中地址的包不经过规则过滤
+        if !firewall_rules::allowlisted(packet.daddr, packet_len, now)
+            && firewall_rules::evaluate(&packet, now) == Some(true)
+        {
+            return TC_ACT_SHOT;
+        }
+        firewall_rules::record_outbound(&packet, now);
+    }
 
     // 只处理TCP和UDP协议
     if protocol != 6 && protocol != 17 {
@@ -303,78 +1194,54 @@ pub fn xnet_tc(ctx: TcContext) -> i32 {
     let src_port = u16::from_be(tcp_hdr.source);
     let dst_port = u16::from_be(tcp_hdr.dest);
 
-    // 更新端口统计信息
-    unsafe {
-        let current_total = TOTAL_STATS.get(&0).unwrap_or(&0);
-
-        // 更新源端口统计
-        if let Some(src_stats) = PORT_STATS.get(&src_port) {
-            let new_stats = PortStats {
-                packets: src_stats.packets + 1,
-                bytes: src_stats.bytes + packet_len,
-                last_seen: *current_total,
-            };
-            PORT_STATS.insert(&src_port, &new_stats, 0);
-        } else {
-            let new_stats = PortStats {
-                packets: 1,
-                bytes: packet_len,
-                last_seen: *current_total,
-            };
-            PORT_STATS.insert(&src_port, &new_stats, 0);
-        }
+    // 设备统计计入每个包
+    let _ = update_device_stats(netns_cookie(&ctx), device_id, is_ingress, packet_len);
 
-        // 更新目标端口统计
-        if let Some(dst_stats) = PORT_STATS.get(&dst_port) {
-            let new_stats = PortStats {
-                packets: dst_stats.packets + 1,
-                bytes: dst_stats.bytes + packet_len,
-                last_seen: *current_total,
-            };
-            PORT_STATS.insert(&dst_port, &new_stats, 0);
-        } else {
-            let new_stats = PortStats {
-                packets: 1,
-                bytes: packet_len,
-                last_seen: *current_total,
-            };
-            PORT_STATS.insert(&dst_port, &new_stats, 0);
+    // 以下按端口和连接的统计只处理采中的包；重传、延迟和连接生命周期需要连续的包，开启采样的设备上不跟踪
+    let Some(weight) = sampling::weight(device_id) else {
+        return TC_ACT_OK;
+    };
+    let sampled = weight > 1;
+
+    // 统计各端口的新建连接请求
+    if protocol == 6 && (tcp_hdr.flags & 0x02) != 0 && (tcp_hdr.flags & 0x10) == 0 {
+        unsafe {
+            let syns = PORT_SYNS.get(&dst_port).copied().unwrap_or(0);
+            let _ = PORT_SYNS.insert(&dst_port, &(syns + weight as u64), 0);
         }
     }
 
-    // 获取当前设备上下文
-    if let Some((device_id, is_ingress)) = get_current_device_context() {
-        // 更新设备统计
-        let _ = update_device_stats(device_id, is_ingress, packet_len);
+    // TCP重传和重复ACK检测
+    let tcp_events = if protocol == 6 && !sampled {
+        track_tcp_sequence(ip_hdr, tcp_hdr)
+    } else {
+        TcpEvents::default()
+    };
 
-        // 更新设备连接统计
-        let _ = update_device_connection_stats(
-            device_id, src_port, dst_port, is_ingress, protocol, packet_len,
-        );
+    let flow = FlowTuple::from_v4(ip_hdr.saddr, ip_hdr.daddr, src_port, dst_port, protocol);
+    count_flow(&ctx, device_id, is_ingress, &flow, packet_len, weight, tcp_events);
+
+    if !sampled {
+        // 握手延迟和包间隔直方图
+        track_latency(device_id, ip_hdr, tcp_hdr, protocol);
+
+        // TCP连接生命周期
+        if protocol == 6 {
+            track_connection(device_id, ip_hdr, tcp_hdr, packet_len, &tcp_events);
+        }
     }
 
     // 记录调试信息
-    if let Some((device_id, is_ingress)) = get_current_device_context() {
-        debug!(
-            &ctx,
-            "Port stats - src: {}, dst: {}, len: {}, protocol: {}, device: {}, direction: {}",
-            src_port,
-            dst_port,
-            packet_len,
-            protocol,
-            device_id,
-            if is_ingress { "ingress" } else { "egress" }
-        );
-    } else {
-        debug!(
-            &ctx,
-            "Port stats - src: {}, dst: {}, len: {}, protocol: {} (no device context)",
-            src_port,
-            dst_port,
-            packet_len,
-            protocol
-        );
-    }
+    debug!(
+        &ctx,
+        "Port stats - src: {}, dst: {}, len: {}, protocol: {}, device: {}, direction: {}",
+        src_port,
+        dst_port,
+        packet_len,
+        protocol,
+        device_id,
+        if is_ingress { "ingress" } else { "egress" }
+    );
 
     TC_ACT_OK
 }
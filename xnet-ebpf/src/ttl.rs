@@ -0,0 +1,40 @@
+use aya_ebpf::{macros::map, maps::LruHashMap};
+use xnet_common::{TtlStats, TTL_BUCKETS, TTL_JUMP_THRESHOLD};
+
+// 每个源IP(网络字节序)的TTL分布，由用户空间定期检查跳变
+#[map(name = "ttl_stats")]
+static TTL_STATS: LruHashMap<u32, TtlStats> = LruHashMap::pinned(8192, 0);
+
+// 记录源IP的TTL，与上一个包相差超过阈值时计为一次跳变
+pub fn observe(src_ip: u32, ttl: u8) {
+    let bucket = (ttl >> 5) as usize;
+    if bucket >= TTL_BUCKETS {
+        return;
+    }
+    match TTL_STATS.get_ptr_mut(&src_ip) {
+        Some(stats) => unsafe {
+            let stats = &mut *stats;
+            if stats.last_ttl.abs_diff(ttl) > TTL_JUMP_THRESHOLD {
+                stats.jumps += 1;
+            }
+            stats.packets += 1;
+            stats.histogram[bucket] += 1;
+            stats.last_ttl = ttl;
+            stats.min_ttl = stats.min_ttl.min(ttl);
+            stats.max_ttl = stats.max_ttl.max(ttl);
+        },
+        None => {
+            let mut stats = TtlStats {
+                packets: 1,
+                jumps: 0,
+                histogram: [0; TTL_BUCKETS],
+                last_ttl: ttl,
+                min_ttl: ttl,
+                max_ttl: ttl,
+                reserved: [0; 5],
+            };
+            stats.histogram[bucket] = 1;
+            let _ = TTL_STATS.insert(&src_ip, &stats, 0);
+        }
+    }
+}
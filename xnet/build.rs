@@ -1,7 +1,33 @@
+use std::process::Command;
+
 use anyhow::{anyhow, Context as _};
 use aya_build::cargo_metadata;
 
+// 构建时的 git 提交，不在 git 仓库中构建时为 unknown
+fn git_hash() -> String {
+    for path in ["HEAD", "logs/HEAD"] {
+        if let Ok(output) = Command::new("git")
+            .args(["rev-parse", "--git-path", path])
+            .output()
+        {
+            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if output.status.success() && !path.is_empty() {
+                println!("cargo:rerun-if-changed={path}");
+            }
+        }
+    }
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
 fn main() -> anyhow::Result<()> {
+    println!("cargo:rustc-env=XNET_GIT_HASH={}", git_hash());
     let cargo_metadata::Metadata { packages, .. } = cargo_metadata::MetadataCommand::new()
         .no_deps()
         .exec()
@@ -10,5 +36,9 @@ fn main() -> anyhow::Result<()> {
         .into_iter()
         .find(|cargo_metadata::Package { name, .. }| name == "xnet-ebpf")
         .ok_or_else(|| anyhow!("xnet-ebpf package not found"))?;
-    aya_build::build_ebpf([ebpf_package])
+    aya_build::build_ebpf([ebpf_package])?;
+    // 集成测试据此确认构建出了真实的 eBPF 目标文件
+    let out_dir = std::env::var("OUT_DIR").context("OUT_DIR not set")?;
+    println!("cargo:rustc-env=XNET_EBPF_OBJECT={out_dir}/xnet");
+    Ok(())
 }
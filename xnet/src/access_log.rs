@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Instrument;
+
+pub static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+lazy_static::lazy_static! {
+    // 进程启动时间(秒)，作为请求 ID 的前缀，重启后的 ID 不会重复
+    static ref EPOCH: u64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn generate() -> String {
+    format!(
+        "{:08x}-{:08x}",
+        *EPOCH,
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+// 沿用客户端或反向代理传入的 X-Request-Id，长度和字符不合法时重新生成
+fn request_id(request: &Request) -> String {
+    request
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 64
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+        })
+        .map(String::from)
+        .unwrap_or_else(generate)
+}
+
+// 为每个请求分配 ID，在带 ID 的 span 中处理请求并记录访问日志，响应头返回该 ID
+// 须放在最外层，认证失败等被中间件拒绝的请求也会记录
+pub async fn log_request(mut request: Request, next: Next) -> Response {
+    let id = request_id(&request);
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let header = HeaderValue::from_str(&id).expect("request id is valid header value");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID.clone(), header.clone());
+
+    let span = tracing::info_span!("request", request_id = %id, method = %method, path = %path);
+    let started = Instant::now();
+    let mut response = next.run(request).instrument(span).await;
+    let status = response.status().as_u16();
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+    if response.status().is_server_error() {
+        tracing::warn!(target: "xnet::access", request_id = %id, %method, %path, status, duration_ms, "{} {} {}", method, path, status);
+    } else {
+        tracing::info!(target: "xnet::access", request_id = %id, %method, %path, status, duration_ms, "{} {} {}", method, path, status);
+    }
+    response.headers_mut().insert(REQUEST_ID.clone(), header);
+    response
+}
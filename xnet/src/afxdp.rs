@@ -0,0 +1,582 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{Array, MapData, XskMap};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use xnet_common::CaptureFilter;
+
+use crate::capture::CaptureError;
+use crate::server::EbpfManager;
+
+// UMEM 帧大小，也是单个包的最大抓取长度
+const FRAME_SIZE: u32 = 4096;
+// UMEM 帧数，fill/rx ring 与之等大，帧在两个 ring 之间循环使用
+const FRAME_COUNT: u32 = 4096;
+const COMPLETION_RING_SIZE: u32 = 64;
+// 与 eBPF 中 XSK_SOCKETS 的 max_entries 一致
+const MAX_QUEUES: u32 = 64;
+const POLL_TIMEOUT_MS: i32 = 100;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct AfXdpRequest {
+    pub iface: String,
+    // 接收队列，默认 0
+    pub queue: Option<u32>,
+    pub ip: Option<Ipv4Addr>,
+    pub port: Option<u16>,
+    // pcap 文件，为抓包目录下的相对路径
+    pub output: String,
+    // 不指定时一直抓取，直到调用 DELETE /capture/afxdp
+    pub max_packets: Option<u64>,
+    pub duration_secs: Option<u64>,
+}
+
+// 抓包线程与 API 共享的计数
+#[derive(Debug, Default)]
+struct Counters {
+    stop: AtomicBool,
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    // 内核因 rx ring 满或 fill ring 为空丢弃的包
+    dropped: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Session {
+    iface: String,
+    queue: u32,
+    ip: Option<Ipv4Addr>,
+    port: Option<u16>,
+    output: String,
+    zerocopy: bool,
+    started_at: u64,
+    finished_at: Option<u64>,
+    error: Option<String>,
+    counters: Arc<Counters>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "running": self.finished_at.is_none(),
+            "iface": self.iface,
+            "queue": self.queue,
+            "ip": self.ip,
+            "port": self.port,
+            "output": self.output,
+            "mode": if self.zerocopy { "zerocopy" } else { "copy" },
+            "started_at": self.started_at,
+            "finished_at": self.finished_at,
+            "packets": self.counters.packets.load(Ordering::Relaxed),
+            "bytes": self.counters.bytes.load(Ordering::Relaxed),
+            "dropped": self.counters.dropped.load(Ordering::Relaxed),
+            "error": self.error,
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    // 当前或最近一次 AF_XDP 抓包，同一时间只允许一个
+    static ref AFXDP: Mutex<Option<Session>> = Mutex::new(None);
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// mmap 的内存区域，drop 时 munmap
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// 创建后只在抓包线程中访问
+unsafe impl Send for Mmap {}
+
+impl Mmap {
+    // fd 为 None 时分配匿名内存
+    fn new(fd: Option<RawFd>, len: usize, offset: u64) -> std::io::Result<Self> {
+        let (fd, flags) = match fd {
+            Some(fd) => (fd, libc::MAP_SHARED | libc::MAP_POPULATE),
+            None => (-1, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS),
+        };
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+// 内核与用户空间共享的单生产者/单消费者 ring
+struct Ring {
+    map: Mmap,
+    offsets: libc::xdp_ring_offset,
+    size: u32,
+}
+
+impl Ring {
+    fn open(
+        fd: RawFd,
+        offsets: libc::xdp_ring_offset,
+        size: u32,
+        entry_size: usize,
+        pgoff: u64,
+    ) -> std::io::Result<Self> {
+        let len = offsets.desc as usize + size as usize * entry_size;
+        Ok(Self {
+            map: Mmap::new(Some(fd), len, pgoff)?,
+            offsets,
+            size,
+        })
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        unsafe { &*(self.map.ptr.add(self.offsets.producer as usize) as *const AtomicU32) }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        unsafe { &*(self.map.ptr.add(self.offsets.consumer as usize) as *const AtomicU32) }
+    }
+
+    fn entry<T>(&self, index: u32) -> *mut T {
+        let slot = (index & (self.size - 1)) as usize;
+        unsafe { (self.map.ptr.add(self.offsets.desc as usize) as *mut T).add(slot) }
+    }
+}
+
+fn set_option<T>(fd: &OwnedFd, name: libc::c_int, value: &T) -> std::io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_XDP,
+            name,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// T 须为 linux/if_xdp.h 中的结构体，旧内核返回的长度可能小于 T，其余字段保持为零
+fn get_option<T: Copy>(fd: &OwnedFd, name: libc::c_int) -> std::io::Result<T> {
+    let mut value: T = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<T>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_XDP,
+            name,
+            &mut value as *mut T as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+// 只用于接收的 AF_XDP socket，UMEM 由本 socket 独占
+struct XskSocket {
+    rx: Ring,
+    fill: Ring,
+    _completion: Ring,
+    umem: Mmap,
+    fd: OwnedFd,
+}
+
+impl XskSocket {
+    fn open(ifindex: u32, queue: u32) -> Result<Self, anyhow::Error> {
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            anyhow::bail!(
+                "failed to create AF_XDP socket: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let umem_len = (FRAME_COUNT * FRAME_SIZE) as usize;
+        let umem = Mmap::new(None, umem_len, 0)
+            .map_err(|e| anyhow::anyhow!("failed to allocate UMEM: {}", e))?;
+        let reg = libc::xdp_umem_reg {
+            addr: umem.ptr as u64,
+            len: umem_len as u64,
+            chunk_size: FRAME_SIZE,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        set_option(&fd, libc::XDP_UMEM_REG, &reg)
+            .map_err(|e| anyhow::anyhow!("failed to register UMEM: {}", e))?;
+        set_option(&fd, libc::XDP_UMEM_FILL_RING, &FRAME_COUNT)
+            .and_then(|_| set_option(&fd, libc::XDP_UMEM_COMPLETION_RING, &COMPLETION_RING_SIZE))
+            .and_then(|_| set_option(&fd, libc::XDP_RX_RING, &FRAME_COUNT))
+            .map_err(|e| anyhow::anyhow!("failed to set AF_XDP ring size: {}", e))?;
+
+        let offsets: libc::xdp_mmap_offsets = get_option(&fd, libc::XDP_MMAP_OFFSETS)
+            .map_err(|e| anyhow::anyhow!("failed to get AF_XDP ring offsets: {}", e))?;
+        let raw = fd.as_raw_fd();
+        let map_ring = |offsets, size, entry_size, pgoff| {
+            Ring::open(raw, offsets, size, entry_size, pgoff)
+                .map_err(|e| anyhow::anyhow!("failed to map AF_XDP ring: {}", e))
+        };
+        let rx = map_ring(
+            offsets.rx,
+            FRAME_COUNT,
+            std::mem::size_of::<libc::xdp_desc>(),
+            libc::XDP_PGOFF_RX_RING as u64,
+        )?;
+        let fill = map_ring(
+            offsets.fr,
+            FRAME_COUNT,
+            std::mem::size_of::<u64>(),
+            libc::XDP_UMEM_PGOFF_FILL_RING,
+        )?;
+        let completion = map_ring(
+            offsets.cr,
+            COMPLETION_RING_SIZE,
+            std::mem::size_of::<u64>(),
+            libc::XDP_UMEM_PGOFF_COMPLETION_RING,
+        )?;
+
+        // 所有帧交给内核用于接收
+        for i in 0..FRAME_COUNT {
+            unsafe { *fill.entry::<u64>(i) = (i * FRAME_SIZE) as u64 };
+        }
+        fill.producer().store(FRAME_COUNT, Ordering::Release);
+
+        // flags 为 0 时内核优先使用零拷贝，驱动不支持时回退到拷贝模式
+        let addr = libc::sockaddr_xdp {
+            sxdp_family: libc::AF_XDP as u16,
+            sxdp_flags: 0,
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: queue,
+            sxdp_shared_umem_fd: 0,
+        };
+        let ret = unsafe {
+            libc::bind(
+                raw,
+                &addr as *const libc::sockaddr_xdp as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_xdp>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            anyhow::bail!(
+                "failed to bind AF_XDP socket to queue {}: {}",
+                queue,
+                std::io::Error::last_os_error()
+            );
+        }
+
+        Ok(Self {
+            rx,
+            fill,
+            _completion: completion,
+            umem,
+            fd,
+        })
+    }
+
+    fn zerocopy(&self) -> bool {
+        get_option::<libc::xdp_options>(&self.fd, libc::XDP_OPTIONS)
+            .map(|options| options.flags & libc::XDP_OPTIONS_ZEROCOPY != 0)
+            .unwrap_or(false)
+    }
+
+    fn dropped(&self) -> u64 {
+        get_option::<libc::xdp_statistics>(&self.fd, libc::XDP_STATISTICS)
+            .map(|stats| stats.rx_dropped + stats.rx_ring_full)
+            .unwrap_or(0)
+    }
+
+    // 等待 rx ring 中有包，超时返回 false
+    fn poll(&self, timeout_ms: i32) -> std::io::Result<bool> {
+        let mut pfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pfd, 1, timeout_ms) } >= 0 {
+            return Ok(pfd.revents & libc::POLLIN != 0);
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() == std::io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        Err(e)
+    }
+
+    // 处理 rx ring 中的所有包，处理完的帧放回 fill ring
+    fn receive(&mut self, mut on_packet: impl FnMut(&[u8])) {
+        let producer = self.rx.producer().load(Ordering::Acquire);
+        let consumer = self.rx.consumer().load(Ordering::Relaxed);
+        let count = producer.wrapping_sub(consumer);
+        if count == 0 {
+            return;
+        }
+
+        let fill_producer = self.fill.producer().load(Ordering::Relaxed);
+        for i in 0..count {
+            let desc = unsafe { *self.rx.entry::<libc::xdp_desc>(consumer.wrapping_add(i)) };
+            let len = (desc.len as usize).min(FRAME_SIZE as usize);
+            let data =
+                unsafe { std::slice::from_raw_parts(self.umem.ptr.add(desc.addr as usize), len) };
+            on_packet(data);
+            // addr 可能带有帧内偏移，放回 fill ring 时取帧起始地址
+            let frame = desc.addr & !(FRAME_SIZE as u64 - 1);
+            unsafe { *self.fill.entry::<u64>(fill_producer.wrapping_add(i)) = frame };
+        }
+        self.rx
+            .consumer()
+            .store(consumer.wrapping_add(count), Ordering::Release);
+        self.fill
+            .producer()
+            .store(fill_producer.wrapping_add(count), Ordering::Release);
+    }
+}
+
+fn wall_clock_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+// 抓包线程: 把收到的包写入 pcap，直到停止、达到包数或时长
+fn capture_loop(
+    mut socket: XskSocket,
+    output: &str,
+    counters: &Counters,
+    max_packets: Option<u64>,
+    deadline: Option<Instant>,
+) -> Result<(), anyhow::Error> {
+    let file =
+        File::create(output).map_err(|e| anyhow::anyhow!("failed to create {}: {}", output, e))?;
+    let mut writer = BufWriter::new(file);
+    let mut buf = Vec::new();
+    crate::capture::write_pcap_header(&mut buf, FRAME_SIZE, crate::capture::PCAP_LINKTYPE_ETHERNET);
+    writer.write_all(&buf)?;
+
+    let limit = max_packets.unwrap_or(u64::MAX);
+    while !counters.stop.load(Ordering::Relaxed)
+        && counters.packets.load(Ordering::Relaxed) < limit
+        && deadline.is_none_or(|deadline| Instant::now() < deadline)
+    {
+        let ready = socket.poll(POLL_TIMEOUT_MS)?;
+        counters.dropped.store(socket.dropped(), Ordering::Relaxed);
+        if !ready {
+            continue;
+        }
+
+        let mut packets = counters.packets.load(Ordering::Relaxed);
+        let mut bytes = 0;
+        buf.clear();
+        socket.receive(|data| {
+            if packets < limit {
+                crate::capture::write_pcap_record(
+                    &mut buf,
+                    wall_clock_ns(),
+                    data,
+                    data.len() as u32,
+                );
+                packets += 1;
+                bytes += data.len() as u64;
+            }
+        });
+        writer.write_all(&buf)?;
+        counters.packets.store(packets, Ordering::Relaxed);
+        counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// 写入 AF_XDP 重定向条件，enabled 为 0 时停止重定向
+async fn set_filter(
+    ebpf_manager: &EbpfManager,
+    filter: CaptureFilter,
+) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut config = Array::<&mut MapData, CaptureFilter>::try_from(
+        ebpf.map_mut("AFXDP_FILTER")
+            .ok_or_else(|| anyhow::anyhow!("AFXDP_FILTER map not found"))?,
+    )?;
+    config.set(0, filter, 0)?;
+    Ok(())
+}
+
+async fn register_socket(
+    ebpf_manager: &EbpfManager,
+    queue: u32,
+    socket_fd: RawFd,
+) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut sockets = XskMap::<&mut MapData>::try_from(
+        ebpf.map_mut("XSK_SOCKETS")
+            .ok_or_else(|| anyhow::anyhow!("XSK_SOCKETS map not found"))?,
+    )?;
+    sockets.set(queue, socket_fd, 0)?;
+    Ok(())
+}
+
+// 创建 AF_XDP socket 并开始把匹配的流重定向到该 socket，返回抓包状态
+pub async fn start(
+    ebpf_manager: Arc<EbpfManager>,
+    request: AfXdpRequest,
+) -> Result<serde_json::Value, CaptureError> {
+    let mut current = AFXDP.lock().await;
+    if current.as_ref().is_some_and(|s| s.finished_at.is_none()) {
+        return Err(CaptureError::Busy);
+    }
+
+    let ifindex = std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", request.iface))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .ok_or_else(|| {
+            CaptureError::InvalidRequest(format!("Interface {} does not exist", request.iface))
+        })?;
+    let output = crate::capture::output_path(&request.output)
+        .await
+        .map_err(CaptureError::InvalidRequest)?
+        .display()
+        .to_string();
+    let queue = request.queue.unwrap_or(0);
+    if queue >= MAX_QUEUES {
+        return Err(CaptureError::InvalidRequest(format!(
+            "queue must be less than {}",
+            MAX_QUEUES
+        )));
+    }
+
+    let socket = XskSocket::open(ifindex, queue)?;
+    let zerocopy = socket.zerocopy();
+    register_socket(&ebpf_manager, queue, socket.fd.as_raw_fd()).await?;
+    let filter = CaptureFilter {
+        enabled: 1,
+        ifindex,
+        ip: request.ip.map(|ip| u32::from(ip).to_be()).unwrap_or(0),
+        port: request.port.unwrap_or(0),
+        reserved: 0,
+    };
+    set_filter(&ebpf_manager, filter).await?;
+    info!(
+        iface = %request.iface,
+        queue,
+        "开始 AF_XDP 抓包: iface={}, queue={}, ip={:?}, port={:?}, mode={}",
+        request.iface,
+        queue,
+        request.ip,
+        request.port,
+        if zerocopy { "zerocopy" } else { "copy" }
+    );
+
+    let counters = Arc::new(Counters::default());
+    let deadline = request
+        .duration_secs
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let task = {
+        let counters = counters.clone();
+        let output = output.clone();
+        let max_packets = request.max_packets;
+        tokio::spawn(async move {
+            let result = tokio::task::spawn_blocking(move || {
+                capture_loop(socket, &output, &counters, max_packets, deadline)
+            })
+            .await;
+            // socket 关闭时内核会将其从 XSK_SOCKETS 中移除
+            if let Err(e) = set_filter(&ebpf_manager, bytemuck::Zeroable::zeroed()).await {
+                warn!("停止 AF_XDP 重定向失败: {}", e);
+            }
+
+            let error = match result {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(e) => Some(e.to_string()),
+            };
+            let mut current = AFXDP.lock().await;
+            if let Some(session) = current.as_mut() {
+                session.finished_at = Some(now_secs());
+                if let Some(e) = &error {
+                    warn!("AF_XDP 抓包失败: {}", e);
+                }
+                session.error = error;
+                info!(
+                    "AF_XDP 抓包结束: 共 {} 个包",
+                    session.counters.packets.load(Ordering::Relaxed)
+                );
+            }
+        })
+    };
+
+    let session = Session {
+        iface: request.iface,
+        queue,
+        ip: request.ip,
+        port: request.port,
+        output,
+        zerocopy,
+        started_at: now_secs(),
+        finished_at: None,
+        error: None,
+        counters,
+        task: Some(task),
+    };
+    let status = session.to_json();
+    *current = Some(session);
+    Ok(status)
+}
+
+// 停止正在运行的抓包，等待 pcap 写完后返回最终状态
+pub async fn stop() -> Option<serde_json::Value> {
+    let task = {
+        let mut current = AFXDP.lock().await;
+        let session = current.as_mut()?;
+        session.counters.stop.store(true, Ordering::Relaxed);
+        session.task.take()
+    };
+    if let Some(task) = task {
+        let _ = task.await;
+    }
+    status().await
+}
+
+// 当前或最近一次抓包的状态
+pub async fn status() -> Option<serde_json::Value> {
+    AFXDP.lock().await.as_ref().map(Session::to_json)
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::events::Event;
+use crate::server::EbpfManager;
+
+// 保留的告警事件数
+const MAX_HISTORY: usize = 1000;
+
+// 告警规则监控的指标
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertMetric {
+    // 全部流量的字节速率
+    TotalBytesPerSec,
+    // 设备(ingress+egress)的字节速率
+    DeviceBytesPerSec { device: String },
+    // 设备(ingress+egress)的包速率
+    DevicePacketsPerSec { device: String },
+    // 端口的字节速率
+    PortBytesPerSec { port: u16 },
+    // 到端口的新建连接数(每分钟)
+    PortNewConnectionsPerMin { port: u16 },
+    // 所有TCP连接的重传率
+    RetransmissionRate,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    #[default]
+    Gt,
+    Lt,
+}
+
+// 告警规则: 指标持续 for_secs 满足比较条件时触发
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: AlertMetric,
+    #[serde(default)]
+    pub op: Comparison,
+    pub threshold: f64,
+    #[serde(default)]
+    pub for_secs: u64,
+    // 触发和恢复时 POST 告警事件到该地址，仅支持 http
+    pub webhook: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Inactive,
+    Pending,
+    Firing,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AlertStatus {
+    pub id: u64,
+    pub rule: AlertRule,
+    pub state: AlertState,
+    pub value: Option<f64>,
+    // 进入当前状态的时间
+    pub since: u64,
+    #[serde(skip)]
+    pending_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AlertEvent {
+    pub rule_id: u64,
+    pub name: String,
+    pub state: AlertState,
+    pub value: f64,
+    pub threshold: f64,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Alerts {
+    next_id: u64,
+    pub rules: Vec<AlertStatus>,
+    pub history: VecDeque<AlertEvent>,
+}
+
+impl Alerts {
+    pub fn add_rule(&mut self, rule: AlertRule) -> u64 {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.rules.push(AlertStatus {
+            id,
+            rule,
+            state: AlertState::Inactive,
+            value: None,
+            since: now_secs(),
+            pending_at: None,
+        });
+        id
+    }
+
+    pub fn remove_rule(&mut self, id: u64) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|r| r.id != id);
+        self.rules.len() != len
+    }
+
+    fn record(&mut self, event: AlertEvent) {
+        if self.history.len() >= MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(event);
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref ALERTS: Mutex<Alerts> = Mutex::new(Alerts::default());
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// 某一时刻的累计计数
+#[derive(Debug, Default)]
+struct Counters {
+    total_bytes: u64,
+    // 设备名 -> (包数, 字节数)，两个方向合计
+    devices: HashMap<String, (u64, u64)>,
+    port_bytes: HashMap<u16, u64>,
+    port_syns: HashMap<u16, u64>,
+    retransmission_rate: f64,
+}
+
+async fn read_counters(ebpf_manager: &EbpfManager) -> Counters {
+    let mut counters = Counters::default();
+    let traffic_stats = crate::traffic::snapshot();
+    let maps = ebpf_manager.maps();
+
+    counters.total_bytes = traffic_stats.total_bytes;
+    // 设备统计的key为 设备名_方向
+    for (key, stats) in traffic_stats.device_stats.iter() {
+        let device = key.rsplit_once('_').map(|(d, _)| d).unwrap_or(key);
+        let entry = counters.devices.entry(device.to_string()).or_default();
+        entry.0 += stats.packets;
+        entry.1 += stats.bytes;
+    }
+    counters.port_bytes = traffic_stats
+        .port_stats
+        .iter()
+        .map(|(port, stats)| (*port, stats.bytes))
+        .collect();
+    counters.retransmission_rate = traffic_stats.total_retransmission_rate();
+
+    if let Some(map) = maps.map("port_syns") {
+        if let Ok(map) = AyaHashMap::<&MapData, u16, u64>::try_from(map) {
+            counters.port_syns = map.iter().filter_map(|r| r.ok()).collect();
+        }
+    }
+    counters
+}
+
+// 根据两次采样计算指标值
+fn evaluate(metric: &AlertMetric, current: &Counters, previous: &Counters, elapsed: f64) -> f64 {
+    let rate = |cur: u64, prev: u64| cur.saturating_sub(prev) as f64 / elapsed;
+    match metric {
+        AlertMetric::TotalBytesPerSec => rate(current.total_bytes, previous.total_bytes),
+        AlertMetric::DeviceBytesPerSec { device } => rate(
+            current.devices.get(device).map(|d| d.1).unwrap_or(0),
+            previous.devices.get(device).map(|d| d.1).unwrap_or(0),
+        ),
+        AlertMetric::DevicePacketsPerSec { device } => rate(
+            current.devices.get(device).map(|d| d.0).unwrap_or(0),
+            previous.devices.get(device).map(|d| d.0).unwrap_or(0),
+        ),
+        AlertMetric::PortBytesPerSec { port } => rate(
+            current.port_bytes.get(port).copied().unwrap_or(0),
+            previous.port_bytes.get(port).copied().unwrap_or(0),
+        ),
+        AlertMetric::PortNewConnectionsPerMin { port } => {
+            rate(
+                current.port_syns.get(port).copied().unwrap_or(0),
+                previous.port_syns.get(port).copied().unwrap_or(0),
+            ) * 60.0
+        }
+        AlertMetric::RetransmissionRate => current.retransmission_rate,
+    }
+}
+
+// 更新规则状态，返回需要记录的状态变化
+fn transition(status: &mut AlertStatus, value: f64, now: Instant) -> Option<AlertEvent> {
+    let rule = &status.rule;
+    let matched = match rule.op {
+        Comparison::Gt => value > rule.threshold,
+        Comparison::Lt => value < rule.threshold,
+    };
+    status.value = Some(value);
+
+    let next = match (status.state, matched) {
+        (AlertState::Inactive, true) => {
+            status.pending_at = Some(now);
+            if rule.for_secs == 0 {
+                AlertState::Firing
+            } else {
+                AlertState::Pending
+            }
+        }
+        (AlertState::Pending, true) => {
+            let pending = status.pending_at.map(|at| now.duration_since(at)).unwrap_or_default();
+            if pending >= Duration::from_secs(rule.for_secs) {
+                AlertState::Firing
+            } else {
+                AlertState::Pending
+            }
+        }
+        (AlertState::Firing, true) => AlertState::Firing,
+        (_, false) => AlertState::Inactive,
+    };
+    if next == status.state {
+        return None;
+    }
+
+    let previous = status.state;
+    status.state = next;
+    status.since = now_secs();
+    // 只有触发和从触发恢复才产生事件
+    let notify = next == AlertState::Firing || previous == AlertState::Firing;
+    notify.then(|| AlertEvent {
+        rule_id: status.id,
+        name: status.rule.name.clone(),
+        state: next,
+        value,
+        threshold: status.rule.threshold,
+        timestamp: status.since,
+    })
+}
+
+// 启动告警规则评估任务
+pub fn start(ebpf_manager: Arc<EbpfManager>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut previous: Option<(Instant, Counters)> = None;
+        loop {
+            ticker.tick().await;
+            let counters = read_counters(&ebpf_manager).await;
+            let now = Instant::now();
+
+            if let Some((at, prev)) = &previous {
+                let elapsed = now.duration_since(*at).as_secs_f64().max(f64::EPSILON);
+                let mut alerts = ALERTS.lock().await;
+                let mut events = Vec::new();
+                for status in alerts.rules.iter_mut() {
+                    let value = evaluate(&status.rule.metric, &counters, prev, elapsed);
+                    if let Some(event) = transition(status, value, now) {
+                        events.push((status.rule.webhook.clone(), event));
+                    }
+                }
+                for (webhook, event) in events {
+                    match event.state {
+                        AlertState::Firing => warn!(
+                            "告警触发: {} 当前值 {:.2} 阈值 {:.2}",
+                            event.name, event.value, event.threshold
+                        ),
+                        _ => info!("告警恢复: {} 当前值 {:.2}", event.name, event.value),
+                    }
+                    if let Some(url) = webhook {
+                        tokio::spawn(crate::webhook::post(url, event.clone()));
+                    }
+                    crate::events::publish(Event::Alert(event.clone()));
+                    alerts.record(event);
+                }
+            }
+            previous = Some((now, counters));
+        }
+    });
+}
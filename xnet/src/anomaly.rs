@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::events::Event;
+
+// 基线稳定前需要的样本数
+const WARMUP_SAMPLES: u64 = 10;
+// 基线低于该速率(字节/秒)时不判定，避免空闲端口的小流量误报
+const MIN_BASELINE_BYTES_PER_SEC: f64 = 1024.0;
+// 保留的异常事件数
+const MAX_HISTORY: usize = 1000;
+
+// 异常检测配置
+#[derive(Debug, Clone, Copy)]
+pub struct AnomalyConfig {
+    // EWMA 平滑系数，越大基线跟随越快
+    pub alpha: f64,
+    // 速率超过基线的该倍数，或低于基线的该倍数分之一时判定为异常
+    pub multiple: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    Spike,
+    Drop,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AnomalyEvent {
+    // 例如 device:eth0、port:80
+    pub key: String,
+    // None 表示异常结束
+    pub kind: Option<AnomalyKind>,
+    pub bytes_per_sec: f64,
+    pub baseline: f64,
+    pub timestamp: u64,
+}
+
+// 单个维度的EWMA基线
+#[derive(Debug, Clone, Default)]
+struct Baseline {
+    ewma: f64,
+    samples: u64,
+    anomaly: Option<AnomalyKind>,
+}
+
+impl Baseline {
+    // 用新样本判定异常并更新基线，返回异常状态是否变化
+    fn observe(&mut self, rate: f64, config: &AnomalyConfig) -> bool {
+        let previous = self.anomaly;
+        if self.samples >= WARMUP_SAMPLES && self.ewma >= MIN_BASELINE_BYTES_PER_SEC {
+            self.anomaly = if rate > self.ewma * config.multiple {
+                Some(AnomalyKind::Spike)
+            } else if rate < self.ewma / config.multiple {
+                Some(AnomalyKind::Drop)
+            } else {
+                None
+            };
+        }
+
+        self.ewma = if self.samples == 0 {
+            rate
+        } else {
+            config.alpha * rate + (1.0 - config.alpha) * self.ewma
+        };
+        self.samples += 1;
+        self.anomaly != previous
+    }
+}
+
+// 处于异常状态的维度
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ActiveAnomaly {
+    pub key: String,
+    pub kind: AnomalyKind,
+    pub baseline: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct Anomalies {
+    baselines: HashMap<String, Baseline>,
+    pub history: VecDeque<AnomalyEvent>,
+}
+
+impl Anomalies {
+    // 当前处于异常状态的维度及其基线
+    pub fn active(&self) -> Vec<ActiveAnomaly> {
+        self.baselines
+            .iter()
+            .filter_map(|(key, baseline)| {
+                baseline.anomaly.map(|kind| ActiveAnomaly {
+                    key: key.clone(),
+                    kind,
+                    baseline: baseline.ewma,
+                })
+            })
+            .collect()
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref ANOMALIES: Mutex<Anomalies> = Mutex::new(Anomalies::default());
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// 读取各设备(两个方向合计)和端口的累计字节数
+fn read_bytes() -> HashMap<String, u64> {
+    let traffic_stats = crate::traffic::snapshot();
+
+    let mut bytes = HashMap::new();
+    // 设备统计的key为 设备名_方向
+    for (key, stats) in traffic_stats.device_stats.iter() {
+        let device = key.rsplit_once('_').map(|(d, _)| d).unwrap_or(key);
+        *bytes.entry(format!("device:{}", device)).or_default() += stats.bytes;
+    }
+    for (port, stats) in traffic_stats.port_stats.iter() {
+        bytes.insert(format!("port:{}", port), stats.bytes);
+    }
+    bytes
+}
+
+// 启动异常检测任务，每个周期用速率更新基线
+pub fn start(interval: Duration, config: AnomalyConfig) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut previous: Option<(Instant, HashMap<String, u64>)> = None;
+        loop {
+            ticker.tick().await;
+            let current = read_bytes();
+            let now = Instant::now();
+
+            if let Some((at, prev)) = &previous {
+                let elapsed = now.duration_since(*at).as_secs_f64().max(f64::EPSILON);
+                let mut anomalies = ANOMALIES.lock().await;
+                let mut events = Vec::new();
+                for (key, bytes) in current.iter() {
+                    let rate = bytes.saturating_sub(prev.get(key).copied().unwrap_or(0)) as f64
+                        / elapsed;
+                    let baseline = anomalies.baselines.entry(key.clone()).or_default();
+                    let ewma = baseline.ewma;
+                    if baseline.observe(rate, &config) {
+                        events.push(AnomalyEvent {
+                            key: key.clone(),
+                            kind: baseline.anomaly,
+                            bytes_per_sec: rate,
+                            baseline: ewma,
+                            timestamp: now_secs(),
+                        });
+                    }
+                }
+
+                for event in events {
+                    match event.kind {
+                        Some(kind) => warn!(
+                            "流量异常 {} {:?}: {:.0} B/s, 基线 {:.0} B/s",
+                            event.key, kind, event.bytes_per_sec, event.baseline
+                        ),
+                        None => info!("流量异常结束 {}: {:.0} B/s", event.key, event.bytes_per_sec),
+                    }
+                    if anomalies.history.len() >= MAX_HISTORY {
+                        anomalies.history.pop_front();
+                    }
+                    anomalies.history.push_back(event.clone());
+                    crate::events::publish(Event::Anomaly(event));
+                }
+            }
+            previous = Some((now, current));
+        }
+    });
+}
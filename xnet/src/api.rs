@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+
+use axum::extract::Request;
+use axum::http::{header, HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::alert::{AlertEvent, AlertStatus};
+use crate::anomaly::{ActiveAnomaly, AnomalyEvent};
+use crate::xdp_attach::XdpMode;
+
+// 当前 API 版本的路径前缀，旧路径作为弃用的别名保留
+pub const PREFIX: &str = "/api/v1";
+
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+// 新建规则、服务等返回的 ID
+#[derive(Debug, Serialize)]
+pub struct IdResponse<T> {
+    pub id: T,
+}
+
+// 按设备操作的接口返回的设备名
+#[derive(Debug, Serialize)]
+pub struct IfaceResponse {
+    pub iface: String,
+}
+
+// 400/404/409 等错误的 JSON 响应
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+// 接口错误，统一返回 {"error": "..."} 和对应的状态码
+#[derive(Debug)]
+pub enum ApiError {
+    // 请求参数或配置不合法
+    BadRequest(String),
+    NotFound(String),
+    // 与当前状态冲突，例如设备已挂载
+    Conflict(String),
+    // 系统调用、map 读写或程序挂载失败
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// 未分类的错误按内部错误处理
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (ApiError::BadRequest(error)
+        | ApiError::NotFound(error)
+        | ApiError::Conflict(error)
+        | ApiError::Internal(error)) = self;
+        f.write_str(error)
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        ApiError::Internal(format!("{:#}", e.into()))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let (ApiError::BadRequest(error)
+        | ApiError::NotFound(error)
+        | ApiError::Conflict(error)
+        | ApiError::Internal(error)) = self;
+        if status.is_server_error() {
+            warn!("接口内部错误: {}", error);
+        }
+        (status, Json(ErrorResponse { error })).into_response()
+    }
+}
+
+// 抓包写入文件时的结果
+#[derive(Debug, Serialize)]
+pub struct CaptureSaved {
+    pub path: String,
+    pub packets: usize,
+}
+
+// 流量统计程序的挂载点
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct TcAttachment {
+    pub iface: String,
+    // ingress 或 egress
+    pub direction: String,
+}
+
+// 防火墙和负载均衡 XDP 程序的挂载点
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct XdpAttachment {
+    pub iface: String,
+    // native 或 generic
+    pub mode: XdpMode,
+}
+
+// 已登记的设备模式及按模式挂载的程序
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttachPattern {
+    pub pattern: String,
+    pub tc: bool,
+    pub xdp: bool,
+}
+
+// 各类程序挂载的网卡
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttachedInterfaces {
+    pub traffic: Vec<TcAttachment>,
+    pub firewall: Vec<XdpAttachment>,
+    pub lb: Vec<XdpAttachment>,
+    pub shaping: Vec<String>,
+    pub patterns: Vec<AttachPattern>,
+}
+
+// 告警规则状态、历史和流量异常
+#[derive(Debug, Serialize)]
+pub struct AlertsResponse {
+    pub rules: Vec<AlertStatus>,
+    pub history: VecDeque<AlertEvent>,
+    pub anomalies: AnomaliesResponse,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnomaliesResponse {
+    pub active: Vec<ActiveAnomaly>,
+    pub history: VecDeque<AnomalyEvent>,
+}
+
+// 请求体、路径和查询参数解析失败时 axum 返回纯文本，统一改写为 JSON 错误
+pub async fn json_errors(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let plain_text = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/plain"));
+    if !plain_text || !(response.status().is_client_error() || response.status().is_server_error()) {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let error = match axum::body::to_bytes(body, 64 * 1024).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => parts.status.canonical_reason().unwrap_or_default().to_string(),
+    };
+    let mut response = (parts.status, Json(ErrorResponse { error })).into_response();
+    // 保留 WWW-Authenticate 等其他响应头
+    for (name, value) in &parts.headers {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+// 旧路径的响应加上 Deprecation 和指向新路径的 Link 头
+pub async fn deprecated(request: Request, next: Next) -> Response {
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        PREFIX,
+        request.uri().path()
+    );
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION.clone(), HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(axum::http::header::LINK, link);
+    }
+    response
+}
@@ -0,0 +1,78 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+// API 访问令牌，为空时不做认证
+#[derive(Debug, Clone, Default)]
+pub struct ApiAuth {
+    tokens: Vec<String>,
+}
+
+impl ApiAuth {
+    // 合并命令行/环境变量中的令牌和令牌文件，文件中每行一个令牌，# 开头为注释
+    pub fn load(token: Option<String>, token_file: Option<&Path>) -> Result<Self, anyhow::Error> {
+        let mut tokens: Vec<String> = token.into_iter().filter(|t| !t.is_empty()).collect();
+        if let Some(path) = token_file {
+            let content = std::fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("failed to read token file {}: {}", path.display(), e)
+            })?;
+            tokens.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        Ok(Self { tokens })
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    fn verify(&self, token: &str) -> bool {
+        self.tokens
+            .iter()
+            .any(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// 只读请求直接放行，其余请求需要携带 Authorization: Bearer <token>
+pub async fn require_token(
+    State(auth): State<Arc<ApiAuth>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !auth.enabled()
+        || matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS
+        )
+    {
+        return next.run(request).await;
+    }
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if auth.verify(token.trim()) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "missing or invalid bearer token",
+        )
+            .into_response(),
+    }
+}
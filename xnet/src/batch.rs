@@ -0,0 +1,157 @@
+use std::borrow::{Borrow, BorrowMut};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use aya::maps::{HashMap as AyaHashMap, IterableMap, MapData};
+use tracing::{debug, info};
+
+use crate::bpf_sys::{bpf, BatchAttr, BPF_MAP_DELETE_BATCH, BPF_MAP_LOOKUP_BATCH};
+
+// 内核未定义到用户态头文件中的 ENOTSUPP
+const ENOTSUPP: i32 = 524;
+// 每次系统调用读取的条目数
+const BATCH_SIZE: usize = 4096;
+
+// 内核不支持批量操作时置位，之后直接逐条读取和删除
+static BATCH_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+// 用 BPF_MAP_LOOKUP_BATCH 读取整个 map，每次系统调用最多读取 BATCH_SIZE 个条目
+fn lookup_batch<K: bytemuck::Pod, V: bytemuck::Pod>(
+    map: &MapData,
+) -> Result<Vec<(K, V)>, std::io::Error> {
+    let mut keys = vec![K::zeroed(); BATCH_SIZE];
+    let mut values = vec![V::zeroed(); BATCH_SIZE];
+    // hash map 的批次游标是 u32 桶序号，其他 map 类型为 key，按两者中较大的分配
+    let token_size = std::mem::size_of::<K>().max(std::mem::size_of::<u64>());
+    let mut in_batch = vec![0u8; token_size];
+    let mut out_batch = vec![0u8; token_size];
+    let mut entries = Vec::new();
+    let mut first = true;
+
+    loop {
+        let mut attr = BatchAttr {
+            in_batch: if first { 0 } else { in_batch.as_ptr() as u64 },
+            out_batch: out_batch.as_mut_ptr() as u64,
+            keys: keys.as_mut_ptr() as u64,
+            values: values.as_mut_ptr() as u64,
+            count: BATCH_SIZE as u32,
+            map_fd: map.fd().as_fd().as_raw_fd() as u32,
+            ..Default::default()
+        };
+        let result = unsafe { bpf(BPF_MAP_LOOKUP_BATCH, &mut attr) };
+        // 返回 ENOENT 时 count 仍可能包含最后一批条目
+        let error = result.err();
+        let count = (attr.count as usize).min(BATCH_SIZE);
+        entries.extend(
+            keys[..count]
+                .iter()
+                .copied()
+                .zip(values[..count].iter().copied()),
+        );
+
+        match error {
+            None => {}
+            Some(e) if e.raw_os_error() == Some(libc::ENOENT) => return Ok(entries),
+            Some(e) => return Err(e),
+        }
+        in_batch.copy_from_slice(&out_batch);
+        first = false;
+    }
+}
+
+fn is_unsupported(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EINVAL | libc::EOPNOTSUPP | libc::ENOSYS | ENOTSUPP)
+    )
+}
+
+// 在 key 和 value 均为 4 字节的 map 上试读一个条目，探测内核是否支持批量操作
+pub fn probe(map_fd: BorrowedFd<'_>) -> bool {
+    let mut key = 0u32;
+    let mut value = 0u32;
+    let mut out_batch = 0u64;
+    let mut attr = BatchAttr {
+        out_batch: &mut out_batch as *mut u64 as u64,
+        keys: &mut key as *mut u32 as u64,
+        values: &mut value as *mut u32 as u64,
+        count: 1,
+        map_fd: map_fd.as_raw_fd() as u32,
+        ..Default::default()
+    };
+    match unsafe { bpf(BPF_MAP_LOOKUP_BATCH, &mut attr) } {
+        Ok(_) => true,
+        Err(e) => !is_unsupported(&e),
+    }
+}
+
+// 读取 hash map 的全部条目，优先使用批量读取，内核不支持时回退到逐条迭代
+pub fn entries<T, K, V>(map: &AyaHashMap<T, K, V>) -> Vec<(K, V)>
+where
+    T: Borrow<MapData>,
+    K: aya::Pod + bytemuck::Pod,
+    V: aya::Pod + bytemuck::Pod,
+{
+    if !BATCH_UNSUPPORTED.load(Ordering::Relaxed) {
+        match lookup_batch(map.map()) {
+            Ok(entries) => return entries,
+            Err(e) => {
+                if is_unsupported(&e) {
+                    BATCH_UNSUPPORTED.store(true, Ordering::Relaxed);
+                    info!("内核不支持 BPF_MAP_LOOKUP_BATCH，改为逐条读取 map: {}", e);
+                } else {
+                    debug!("批量读取 map 失败，本次改为逐条读取: {}", e);
+                }
+            }
+        }
+    }
+    map.iter().filter_map(|r| r.ok()).collect()
+}
+
+// 用 BPF_MAP_DELETE_BATCH 删除 keys，失败时返回已删除的条目数和错误
+fn delete_batch<K: bytemuck::Pod>(
+    map: &MapData,
+    keys: &[K],
+) -> Result<(), (usize, std::io::Error)> {
+    let mut attr = BatchAttr {
+        keys: keys.as_ptr() as u64,
+        count: keys.len() as u32,
+        map_fd: map.fd().as_fd().as_raw_fd() as u32,
+        ..Default::default()
+    };
+    match unsafe { bpf(BPF_MAP_DELETE_BATCH, &mut attr) } {
+        Ok(_) => Ok(()),
+        Err(e) => Err((attr.count as usize, e)),
+    }
+}
+
+// 删除 hash map 中的 keys，返回删除的条目数，已不存在的 key 直接跳过
+pub fn remove_keys<T, K, V>(map: &mut AyaHashMap<T, K, V>, keys: &[K]) -> usize
+where
+    T: BorrowMut<MapData>,
+    K: aya::Pod + bytemuck::Pod,
+    V: aya::Pod,
+{
+    if keys.is_empty() {
+        return 0;
+    }
+    let mut remaining = keys;
+    if !BATCH_UNSUPPORTED.load(Ordering::Relaxed) {
+        match delete_batch(map.map(), keys) {
+            Ok(()) => return keys.len(),
+            // 批量删除在第一个失败的 key 处停止，其余的逐条删除
+            Err((deleted, e)) => {
+                if deleted == 0 && is_unsupported(&e) {
+                    BATCH_UNSUPPORTED.store(true, Ordering::Relaxed);
+                    info!("内核不支持 BPF_MAP_DELETE_BATCH，改为逐条删除: {}", e);
+                }
+                remaining = &keys[deleted.min(keys.len())..];
+            }
+        }
+    }
+    let removed = remaining
+        .iter()
+        .filter(|key| map.remove(key).is_ok())
+        .count();
+    keys.len() - remaining.len() + removed
+}
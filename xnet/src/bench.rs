@@ -0,0 +1,284 @@
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const IPV4_HDR_LEN: usize = 20;
+const TCP_HDR_LEN: usize = 20;
+const UDP_HDR_LEN: usize = 8;
+const TCP_FLAG_SYN: u8 = 0x02;
+// 源端口从该值开始按流编号递增
+const BASE_SRC_PORT: u16 = 20000;
+// 每次检查发送进度的间隔
+const PACING_TICK: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum BenchProtocol {
+    Tcp,
+    Udp,
+}
+
+// xnet bench 的参数
+#[derive(Debug, Clone, clap::Args)]
+pub struct BenchArgs {
+    /// 目的地址
+    #[clap(long)]
+    dst: Ipv4Addr,
+    /// 源地址，默认取发往目的地址的路由源地址
+    #[clap(long)]
+    src: Option<Ipv4Addr>,
+    /// 发包的网卡，默认按路由选择
+    #[clap(long)]
+    iface: Option<String>,
+    /// 在该网络命名空间中发包，如 /var/run/netns/ns1
+    #[clap(long)]
+    netns: Option<PathBuf>,
+    #[clap(long, value_enum, default_value = "udp")]
+    protocol: BenchProtocol,
+    #[clap(long, default_value = "9")]
+    dst_port: u16,
+    /// 并发的流数，各流使用不同的源端口
+    #[clap(long, default_value = "1")]
+    flows: u16,
+    /// 每秒发包数，0 表示不限速
+    #[clap(long, default_value = "1000")]
+    rate: u64,
+    #[clap(long, default_value = "10")]
+    duration_secs: u64,
+    /// 每个包的负载字节数
+    #[clap(long, default_value = "64")]
+    payload: usize,
+}
+
+#[derive(Debug, Default)]
+struct BenchResult {
+    packets: u64,
+    bytes: u64,
+    errors: u64,
+    elapsed: Duration,
+}
+
+// 16 位反码求和
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in chunks {
+        for word in chunk.chunks(2) {
+            let hi = word[0] as u32;
+            let lo = word.get(1).copied().unwrap_or(0) as u32;
+            sum += (hi << 8) | lo;
+        }
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// 构造完整的 IPv4 包，IP 头校验和及 id 由内核填写
+fn build_packet(args: &BenchArgs, src: Ipv4Addr, src_port: u16, seq: u32) -> Vec<u8> {
+    let (proto, l4_len) = match args.protocol {
+        BenchProtocol::Tcp => (libc::IPPROTO_TCP as u8, TCP_HDR_LEN + args.payload),
+        BenchProtocol::Udp => (libc::IPPROTO_UDP as u8, UDP_HDR_LEN + args.payload),
+    };
+    let total_len = IPV4_HDR_LEN + l4_len;
+    let mut packet = vec![0u8; total_len];
+
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    packet[6] = 0x40; // DF
+    packet[8] = 64;
+    packet[9] = proto;
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&args.dst.octets());
+
+    let l4 = &mut packet[IPV4_HDR_LEN..];
+    l4[0..2].copy_from_slice(&src_port.to_be_bytes());
+    l4[2..4].copy_from_slice(&args.dst_port.to_be_bytes());
+    let checksum_offset = match args.protocol {
+        BenchProtocol::Tcp => {
+            l4[4..8].copy_from_slice(&seq.to_be_bytes());
+            l4[12] = ((TCP_HDR_LEN / 4) as u8) << 4;
+            l4[13] = TCP_FLAG_SYN;
+            l4[14..16].copy_from_slice(&65535u16.to_be_bytes());
+            16
+        }
+        BenchProtocol::Udp => {
+            l4[4..6].copy_from_slice(&(l4_len as u16).to_be_bytes());
+            6
+        }
+    };
+
+    // 伪首部 + 四层头及负载
+    let mut pseudo = [0u8; 12];
+    pseudo[0..4].copy_from_slice(&src.octets());
+    pseudo[4..8].copy_from_slice(&args.dst.octets());
+    pseudo[9] = proto;
+    pseudo[10..12].copy_from_slice(&(l4_len as u16).to_be_bytes());
+    let sum = match checksum(&[&pseudo, l4]) {
+        // UDP 中 0 表示未计算校验和
+        0 if args.protocol == BenchProtocol::Udp => 0xffff,
+        sum => sum,
+    };
+    l4[checksum_offset..checksum_offset + 2].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+// 发往 dst 时内核选择的源地址
+fn route_source(dst: Ipv4Addr) -> Result<Ipv4Addr, anyhow::Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket
+        .connect(SocketAddrV4::new(dst, 9))
+        .map_err(|e| anyhow::anyhow!("no route to {}: {}", dst, e))?;
+    match socket.local_addr()?.ip() {
+        std::net::IpAddr::V4(ip) => Ok(ip),
+        ip => Err(anyhow::anyhow!("unexpected source address {}", ip)),
+    }
+}
+
+// IP_HDRINCL 的 raw socket，可选绑定到网卡
+fn open_socket(iface: Option<&str>) -> Result<OwnedFd, anyhow::Error> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::IPPROTO_RAW,
+        )
+    };
+    if fd < 0 {
+        anyhow::bail!(
+            "failed to create raw socket (needs CAP_NET_RAW): {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if let Some(iface) = iface {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                iface.as_ptr() as *const libc::c_void,
+                iface.len() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            anyhow::bail!(
+                "failed to bind to {}: {}",
+                iface,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(fd)
+}
+
+// 按速率发包，每秒打印一次进度
+fn send(args: &BenchArgs) -> Result<BenchResult, anyhow::Error> {
+    if IPV4_HDR_LEN + TCP_HDR_LEN + args.payload > u16::MAX as usize {
+        anyhow::bail!("payload {} is too large", args.payload);
+    }
+    let src = match args.src {
+        Some(src) => src,
+        None => route_source(args.dst)?,
+    };
+    let fd = open_socket(args.iface.as_deref())?;
+    let flows = args.flows.max(1);
+    let packets: Vec<Vec<u8>> = (0..flows)
+        .map(|i| build_packet(args, src, BASE_SRC_PORT.wrapping_add(i), i as u32))
+        .collect();
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: 0,
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(args.dst).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    println!(
+        "sending {:?} {}:{}-{} -> {}:{}, {} flows, {} bytes/packet, rate {}",
+        args.protocol,
+        src,
+        BASE_SRC_PORT,
+        BASE_SRC_PORT.wrapping_add(flows - 1),
+        args.dst,
+        args.dst_port,
+        flows,
+        packets[0].len(),
+        if args.rate == 0 {
+            "unlimited".to_string()
+        } else {
+            format!("{} pps", args.rate)
+        }
+    );
+
+    let mut result = BenchResult::default();
+    let duration = Duration::from_secs(args.duration_secs);
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut last_packets = 0;
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            break;
+        }
+        // 按已用时间计算应发的包数，补足差额
+        let target = if args.rate == 0 {
+            result.packets + result.errors + 1024
+        } else {
+            (elapsed.as_secs_f64() * args.rate as f64) as u64
+        };
+        while result.packets + result.errors < target {
+            let packet = &packets[((result.packets + result.errors) % flows as u64) as usize];
+            let ret = unsafe {
+                libc::sendto(
+                    fd.as_raw_fd(),
+                    packet.as_ptr() as *const libc::c_void,
+                    packet.len(),
+                    0,
+                    &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            };
+            if ret < 0 {
+                result.errors += 1;
+            } else {
+                result.packets += 1;
+                result.bytes += packet.len() as u64;
+            }
+        }
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            println!(
+                "{:>4}s {:>10} pps",
+                start.elapsed().as_secs(),
+                result.packets - last_packets
+            );
+            last_report = Instant::now();
+            last_packets = result.packets;
+        }
+        if args.rate != 0 {
+            std::thread::sleep(PACING_TICK);
+        }
+    }
+    result.elapsed = start.elapsed();
+    Ok(result)
+}
+
+pub async fn run(args: BenchArgs) -> Result<(), anyhow::Error> {
+    let result = tokio::task::spawn_blocking(move || match &args.netns {
+        Some(path) => crate::netns::run_in(path, || send(&args)),
+        None => send(&args),
+    })
+    .await??;
+
+    let secs = result.elapsed.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "sent {} packets, {} bytes, {} errors in {:.1}s: {:.0} pps, {:.2} Mbit/s",
+        result.packets,
+        result.bytes,
+        result.errors,
+        secs,
+        result.packets as f64 / secs,
+        result.bytes as f64 * 8.0 / secs / 1e6
+    );
+    Ok(())
+}
@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aya::maps::lpm_trie::{Key, LpmTrie};
+use aya::maps::{Array, MapData};
+use aya::Ebpf;
+use hyper::Client;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::BLOCKLIST_MAX_FEEDS;
+
+use crate::server::EbpfManager;
+
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
+// 下载的订阅源内容上限
+const MAX_FEED_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    // 每行一个 IP 或 CIDR，# 或 ; 开头的行及行尾内容为注释
+    #[default]
+    Text,
+    // 字符串数组，或对象数组中 field 字段的值
+    Json,
+}
+
+// 威胁情报订阅源，url 为 http:// 地址或本地文件路径
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FeedConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub format: FeedFormat,
+    // JSON 对象数组中地址所在的字段，默认 ip
+    pub field: Option<String>,
+    pub interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize)]
+struct FeedStatus {
+    entries: usize,
+    // 最近一次同步新增和删除的条目数
+    added: usize,
+    removed: usize,
+    // 无法解析的行
+    invalid: usize,
+    last_fetch: Option<u64>,
+    last_success: Option<u64>,
+    last_error: Option<String>,
+}
+
+struct Feed {
+    id: u32,
+    config: FeedConfig,
+    // 网络字节序的网段和前缀长度
+    prefixes: BTreeSet<(u32, u32)>,
+    status: FeedStatus,
+}
+
+lazy_static::lazy_static! {
+    static ref FEEDS: Mutex<BTreeMap<String, Feed>> = Mutex::new(BTreeMap::new());
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn parse_entry(entry: &str) -> Option<(u32, u32)> {
+    let (addr, mask) = crate::firewall::parse_cidr(entry).ok()?;
+    Some((addr, u32::from_be(mask).count_ones()))
+}
+
+// 解析订阅源内容，返回前缀集合和无法解析的条目数
+fn parse(config: &FeedConfig, body: &[u8]) -> Result<(BTreeSet<(u32, u32)>, usize), anyhow::Error> {
+    let mut entries = Vec::new();
+    match config.format {
+        FeedFormat::Text => {
+            for line in String::from_utf8_lossy(body).lines() {
+                let line = line.split(['#', ';']).next().unwrap_or("").trim();
+                if let Some(entry) = line.split_whitespace().next() {
+                    entries.push(Some(entry.to_string()));
+                }
+            }
+        }
+        FeedFormat::Json => {
+            let field = config.field.as_deref().unwrap_or("ip");
+            let items: Vec<Value> = serde_json::from_slice(body)?;
+            for item in items {
+                let entry = item.as_str().or_else(|| item[field].as_str());
+                entries.push(entry.map(str::to_string));
+            }
+        }
+    }
+    let mut prefixes = BTreeSet::new();
+    let mut invalid = 0;
+    for entry in entries {
+        match entry.as_deref().and_then(parse_entry) {
+            Some(prefix) => {
+                prefixes.insert(prefix);
+            }
+            None => invalid += 1,
+        }
+    }
+    Ok((prefixes, invalid))
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, anyhow::Error> {
+    if url.starts_with("https://") {
+        anyhow::bail!("https is not supported, use an http url or a local file");
+    }
+    if !url.starts_with("http://") {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        return Ok(tokio::fs::read(path).await?);
+    }
+    let response = Client::new().get(url.parse()?).await?;
+    if !response.status().is_success() {
+        anyhow::bail!("GET {} returned {}", url, response.status());
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    if body.len() > MAX_FEED_BYTES {
+        anyhow::bail!("feed larger than {} bytes", MAX_FEED_BYTES);
+    }
+    Ok(body.to_vec())
+}
+
+fn blocklist_map(ebpf: &mut Ebpf) -> Result<LpmTrie<&mut MapData, u32, u32>, anyhow::Error> {
+    Ok(LpmTrie::try_from(ebpf.map_mut("blocklist").ok_or_else(
+        || anyhow::anyhow!("blocklist map not found"),
+    )?)?)
+}
+
+// 把订阅源的变化同步到内核，多个订阅源包含同一前缀时，删除后交给仍包含该前缀的订阅源
+fn apply(
+    ebpf: &mut Ebpf,
+    feeds: &BTreeMap<String, Feed>,
+    id: u32,
+    added: &BTreeSet<(u32, u32)>,
+    removed: &BTreeSet<(u32, u32)>,
+) -> Result<(), anyhow::Error> {
+    let mut map = blocklist_map(ebpf)?;
+    for (addr, len) in added {
+        map.insert(&Key::new(*len, *addr), id, 0)?;
+    }
+    for prefix @ (addr, len) in removed {
+        let key = Key::new(*len, *addr);
+        match feeds
+            .values()
+            .find(|feed| feed.id != id && feed.prefixes.contains(prefix))
+        {
+            Some(other) => map.insert(&key, other.id, 0)?,
+            None => {
+                let _ = map.remove(&key);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 下载并解析订阅源，与上次的内容比较后同步到内核
+async fn refresh(ebpf_manager: &EbpfManager, name: &str) -> Result<(), anyhow::Error> {
+    let config = match FEEDS.lock().await.get(name) {
+        Some(feed) => feed.config.clone(),
+        None => return Ok(()),
+    };
+    let result = match fetch(&config.url).await {
+        Ok(body) => parse(&config, &body),
+        Err(e) => Err(e),
+    };
+
+    let mut feeds = FEEDS.lock().await;
+    let (prefixes, invalid) = match result {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            if let Some(feed) = feeds.get_mut(name) {
+                feed.status.last_fetch = Some(now_secs());
+                feed.status.last_error = Some(e.to_string());
+            }
+            return Err(e);
+        }
+    };
+    let Some(feed) = feeds.get(name) else {
+        return Ok(());
+    };
+    let id = feed.id;
+    let added: BTreeSet<(u32, u32)> = prefixes.difference(&feed.prefixes).copied().collect();
+    let removed: BTreeSet<(u32, u32)> = feed.prefixes.difference(&prefixes).copied().collect();
+    // 先更新本订阅源的前缀，删除的前缀只会交给其他订阅源
+    let old = std::mem::replace(&mut feeds.get_mut(name).unwrap().prefixes, prefixes);
+    if let Err(e) = apply(
+        &mut *ebpf_manager.ebpf.lock().await,
+        &feeds,
+        id,
+        &added,
+        &removed,
+    ) {
+        let feed = feeds.get_mut(name).unwrap();
+        feed.prefixes = old;
+        feed.status.last_fetch = Some(now_secs());
+        feed.status.last_error = Some(e.to_string());
+        return Err(e);
+    }
+
+    let feed = feeds.get_mut(name).unwrap();
+    let now = now_secs();
+    feed.status = FeedStatus {
+        entries: feed.prefixes.len(),
+        added: added.len(),
+        removed: removed.len(),
+        invalid,
+        last_fetch: Some(now),
+        last_success: Some(now),
+        last_error: None,
+    };
+    if !added.is_empty() || !removed.is_empty() {
+        info!(
+            "订阅源 {} 已同步: {} 个条目，新增 {}，删除 {}",
+            name,
+            feed.prefixes.len(),
+            added.len(),
+            removed.len()
+        );
+    }
+    Ok(())
+}
+
+// 读取并校验订阅源配置文件(FeedConfig 数组)
+pub(crate) fn read_configs(path: &Path) -> Result<Vec<FeedConfig>, anyhow::Error> {
+    let configs: Vec<FeedConfig> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    if configs.len() > BLOCKLIST_MAX_FEEDS as usize {
+        anyhow::bail!("at most {} blocklist feeds", BLOCKLIST_MAX_FEEDS);
+    }
+    let mut names = BTreeSet::new();
+    for config in &configs {
+        if !names.insert(config.name.as_str()) {
+            anyhow::bail!("duplicate blocklist feed {}", config.name);
+        }
+    }
+    Ok(configs)
+}
+
+// 读取订阅源配置文件，每个订阅源按各自的间隔定期同步
+pub async fn start(ebpf_manager: Arc<EbpfManager>, path: &Path) -> Result<(), anyhow::Error> {
+    let configs = read_configs(path)?;
+    let mut feeds = FEEDS.lock().await;
+    for (id, config) in configs.into_iter().enumerate() {
+        let name = config.name.clone();
+        let interval =
+            Duration::from_secs(config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS).max(1));
+        feeds.insert(
+            name.clone(),
+            Feed {
+                id: id as u32,
+                config,
+                prefixes: BTreeSet::new(),
+                status: FeedStatus::default(),
+            },
+        );
+
+        let ebpf_manager = ebpf_manager.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = refresh(&ebpf_manager, &name).await {
+                    warn!("同步订阅源 {} 失败: {}", name, e);
+                }
+            }
+        });
+    }
+    info!(
+        "已配置 {} 个威胁情报订阅源: {}",
+        feeds.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+// 订阅源状态及各自丢弃的包数
+pub async fn status(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let feeds = FEEDS.lock().await;
+    let maps = ebpf_manager.maps();
+    let stats = Array::<&MapData, u64>::try_from(
+        maps.map("blocklist_stats")
+            .ok_or_else(|| anyhow::anyhow!("blocklist_stats map not found"))?,
+    )?;
+    Ok(feeds
+        .values()
+        .map(|feed| {
+            serde_json::json!({
+                "name": feed.config.name,
+                "url": feed.config.url,
+                "format": feed.config.format,
+                "interval_secs": feed.config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS),
+                "status": feed.status,
+                "dropped": stats.get(&feed.id, 0).unwrap_or(0),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use aya::maps::lpm_trie::{Key, LpmTrie};
+use aya::maps::{Array, HashMap as AyaHashMap, MapData};
+use aya::Ebpf;
+use bytemuck::Zeroable;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{
+    BogonStats, BOGON_MAX_PREFIXES, BOGON_MODE_COUNT, BOGON_MODE_DROP, BOGON_MODE_OFF,
+};
+
+use crate::server::EbpfManager;
+
+// 内置的 bogon/martian 前缀，不应出现在公网收到的包的源地址中，见 RFC 6890
+const BUILTIN: &[(&str, &str)] = &[
+    ("0.0.0.0/8", "this network"),
+    ("10.0.0.0/8", "private"),
+    ("100.64.0.0/10", "carrier-grade nat"),
+    ("127.0.0.0/8", "loopback"),
+    ("169.254.0.0/16", "link local"),
+    ("172.16.0.0/12", "private"),
+    ("192.0.0.0/24", "ietf protocol assignments"),
+    ("192.0.2.0/24", "documentation"),
+    ("192.168.0.0/16", "private"),
+    ("198.18.0.0/15", "benchmarking"),
+    ("198.51.100.0/24", "documentation"),
+    ("203.0.113.0/24", "documentation"),
+    ("224.0.0.0/4", "multicast"),
+    ("240.0.0.0/4", "reserved"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BogonMode {
+    Off,
+    // 只计数
+    Count,
+    // 计数并丢弃
+    Drop,
+}
+
+impl BogonMode {
+    fn value(self) -> u32 {
+        match self {
+            BogonMode::Off => BOGON_MODE_OFF,
+            BogonMode::Count => BOGON_MODE_COUNT,
+            BogonMode::Drop => BOGON_MODE_DROP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BogonPrefix {
+    pub prefix: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BogonPrefixesRequest {
+    // 不设置时恢复内置的前缀
+    pub prefixes: Option<Vec<BogonPrefix>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BogonIfaceRequest {
+    pub iface: String,
+    pub mode: BogonMode,
+}
+
+struct BogonState {
+    // 下标即前缀编号
+    prefixes: Vec<BogonPrefix>,
+    builtin: bool,
+    // 设备名 -> (ifindex, 模式)
+    ifaces: BTreeMap<String, (u32, BogonMode)>,
+}
+
+lazy_static::lazy_static! {
+    static ref BOGON: Mutex<BogonState> = Mutex::new(BogonState {
+        prefixes: builtin(),
+        builtin: true,
+        ifaces: BTreeMap::new(),
+    });
+}
+
+fn builtin() -> Vec<BogonPrefix> {
+    BUILTIN
+        .iter()
+        .map(|(prefix, description)| BogonPrefix {
+            prefix: prefix.to_string(),
+            description: description.to_string(),
+        })
+        .collect()
+}
+
+// 返回网络字节序的网段和前缀长度
+fn parse_prefix(prefix: &str) -> Result<(u32, u32), String> {
+    let (addr, mask) = crate::firewall::parse_cidr(prefix)?;
+    Ok((addr, u32::from_be(mask).count_ones()))
+}
+
+fn validate(prefixes: &[BogonPrefix]) -> Result<(), String> {
+    if prefixes.len() > BOGON_MAX_PREFIXES as usize {
+        return Err(format!("at most {} bogon prefixes", BOGON_MAX_PREFIXES));
+    }
+    for prefix in prefixes {
+        parse_prefix(&prefix.prefix)?;
+    }
+    Ok(())
+}
+
+// 用前缀列表替换内核中的前缀表并清零计数，编号随列表顺序变化
+fn sync(ebpf: &mut Ebpf, prefixes: &[BogonPrefix]) -> Result<(), anyhow::Error> {
+    {
+        let mut trie = LpmTrie::<&mut MapData, u32, u32>::try_from(
+            ebpf.map_mut("bogon_prefixes")
+                .ok_or_else(|| anyhow::anyhow!("bogon_prefixes map not found"))?,
+        )?;
+        let keys: Vec<Key<u32>> = trie.keys().filter_map(|k| k.ok()).collect();
+        for key in keys {
+            trie.remove(&key)?;
+        }
+        for (id, prefix) in prefixes.iter().enumerate() {
+            let (addr, len) = parse_prefix(&prefix.prefix).map_err(anyhow::Error::msg)?;
+            trie.insert(&Key::new(len, addr), id as u32, 0)?;
+        }
+    }
+    let mut stats = Array::<&mut MapData, BogonStats>::try_from(
+        ebpf.map_mut("bogon_stats")
+            .ok_or_else(|| anyhow::anyhow!("bogon_stats map not found"))?,
+    )?;
+    for id in 0..BOGON_MAX_PREFIXES {
+        stats.set(id, BogonStats::zeroed(), 0)?;
+    }
+    Ok(())
+}
+
+// 读取前缀文件，每行一个 CIDR，后面可以跟说明，# 开头的行为注释
+pub(crate) fn read_file(path: &Path) -> Result<Vec<BogonPrefix>, anyhow::Error> {
+    let prefixes: Vec<BogonPrefix> = std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (prefix, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            BogonPrefix {
+                prefix: prefix.to_string(),
+                description: description.trim().to_string(),
+            }
+        })
+        .collect();
+    validate(&prefixes).map_err(anyhow::Error::msg)?;
+    Ok(prefixes)
+}
+
+// 启动时写入前缀表，设置了文件时用文件中的前缀替换内置前缀
+pub async fn init(ebpf_manager: &EbpfManager, path: Option<&Path>) -> Result<(), anyhow::Error> {
+    let mut state = BOGON.lock().await;
+    if let Some(path) = path {
+        state.prefixes = read_file(path)?;
+        state.builtin = false;
+    }
+    sync(&mut *ebpf_manager.ebpf.lock().await, &state.prefixes)?;
+    info!("已加载 {} 个 bogon 前缀", state.prefixes.len());
+    Ok(())
+}
+
+// 前缀及其命中计数，以及检查 bogon 的设备
+pub async fn status(ebpf_manager: &EbpfManager) -> Result<Value, anyhow::Error> {
+    let state = BOGON.lock().await;
+    let maps = ebpf_manager.maps();
+    let stats = Array::<&MapData, BogonStats>::try_from(
+        maps.map("bogon_stats")
+            .ok_or_else(|| anyhow::anyhow!("bogon_stats map not found"))?,
+    )?;
+    let prefixes: Vec<Value> = state
+        .prefixes
+        .iter()
+        .enumerate()
+        .map(|(id, prefix)| {
+            let hits = stats
+                .get(&(id as u32), 0)
+                .unwrap_or_else(|_| BogonStats::zeroed());
+            serde_json::json!({
+                "prefix": prefix.prefix,
+                "description": prefix.description,
+                "packets": hits.packets,
+                "bytes": hits.bytes,
+            })
+        })
+        .collect();
+    let ifaces: Vec<Value> = state
+        .ifaces
+        .iter()
+        .map(|(iface, (ifindex, mode))| {
+            serde_json::json!({"iface": iface, "ifindex": ifindex, "mode": mode})
+        })
+        .collect();
+    Ok(serde_json::json!({
+        "builtin": state.builtin,
+        "prefixes": prefixes,
+        "interfaces": ifaces,
+    }))
+}
+
+// 替换前缀表，prefixes 为空时恢复内置前缀
+pub async fn set_prefixes(
+    ebpf_manager: &EbpfManager,
+    request: BogonPrefixesRequest,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let (prefixes, builtin) = match request.prefixes {
+        Some(prefixes) => (prefixes, false),
+        None => (builtin(), true),
+    };
+    if let Err(e) = validate(&prefixes) {
+        return Ok(Err(e));
+    }
+    let mut state = BOGON.lock().await;
+    sync(&mut *ebpf_manager.ebpf.lock().await, &prefixes)?;
+    info!("bogon 前缀已更新: {} 个", prefixes.len());
+    state.prefixes = prefixes;
+    state.builtin = builtin;
+    Ok(Ok(()))
+}
+
+// 设置设备的 bogon 处理方式，需要设备上已挂载 XDP 防火墙
+pub async fn set_iface(
+    ebpf_manager: &EbpfManager,
+    request: BogonIfaceRequest,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let ifindex = match std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", request.iface))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+    {
+        Some(ifindex) => ifindex,
+        None => return Ok(Err(format!("Interface {} does not exist", request.iface))),
+    };
+
+    let mut state = BOGON.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut ifaces = AyaHashMap::<&mut MapData, u32, u32>::try_from(
+        ebpf.map_mut("bogon_ifaces")
+            .ok_or_else(|| anyhow::anyhow!("bogon_ifaces map not found"))?,
+    )?;
+    if request.mode == BogonMode::Off {
+        let _ = ifaces.remove(&ifindex);
+        state.ifaces.remove(&request.iface);
+    } else {
+        ifaces.insert(ifindex, request.mode.value(), 0)?;
+        state
+            .ifaces
+            .insert(request.iface.clone(), (ifindex, request.mode));
+    }
+    info!(
+        iface = %request.iface,
+        mode = ?request.mode,
+        "设备 {} bogon 源地址处理: {:?}",
+        request.iface, request.mode
+    );
+    Ok(Ok(()))
+}
@@ -0,0 +1,83 @@
+// aya 未提供的 bpf(2) 命令: 命令号、map 类型及 union bpf_attr 中各命令使用的部分，
+// 见 include/uapi/linux/bpf.h
+
+pub const BPF_MAP_CREATE: libc::c_long = 0;
+pub const BPF_MAP_LOOKUP_ELEM: libc::c_long = 1;
+pub const BPF_MAP_GET_NEXT_KEY: libc::c_long = 4;
+pub const BPF_PROG_TEST_RUN: libc::c_long = 10;
+pub const BPF_MAP_LOOKUP_BATCH: libc::c_long = 24;
+pub const BPF_MAP_DELETE_BATCH: libc::c_long = 27;
+
+pub const BPF_MAP_TYPE_HASH: u32 = 1;
+pub const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+pub const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+
+// BPF_MAP_CREATE
+#[repr(C)]
+#[derive(Default)]
+pub struct MapCreateAttr {
+    pub map_type: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub map_flags: u32,
+}
+
+// BPF_MAP_*_ELEM 及 BPF_MAP_GET_NEXT_KEY，GET_NEXT_KEY 的下一个 key 写入 value
+#[repr(C)]
+#[derive(Default)]
+pub struct ElemAttr {
+    pub map_fd: u32,
+    pub reserved: u32,
+    pub key: u64,
+    pub value: u64,
+    pub flags: u64,
+}
+
+// BPF_MAP_*_BATCH
+#[repr(C)]
+#[derive(Default)]
+pub struct BatchAttr {
+    pub in_batch: u64,
+    pub out_batch: u64,
+    pub keys: u64,
+    pub values: u64,
+    pub count: u32,
+    pub map_fd: u32,
+    pub elem_flags: u64,
+    pub flags: u64,
+}
+
+// BPF_PROG_TEST_RUN
+#[repr(C)]
+#[derive(Default)]
+pub struct TestRunAttr {
+    pub prog_fd: u32,
+    pub retval: u32,
+    pub data_size_in: u32,
+    pub data_size_out: u32,
+    pub data_in: u64,
+    pub data_out: u64,
+    pub repeat: u32,
+    pub duration: u32,
+    pub ctx_size_in: u32,
+    pub ctx_size_out: u32,
+    pub ctx_in: u64,
+    pub ctx_out: u64,
+    pub flags: u32,
+    pub cpu: u32,
+}
+
+// 以 attr 调用 bpf(2)，返回系统调用的返回值，BPF_MAP_CREATE 时为新 map 的 fd
+//
+// # Safety
+//
+// attr 须为 cmd 对应的结构，其中的指针须指向调用期间有效、大小与 map 的 key/value 或
+// 批量条目数相符的缓冲区，内核会读写这些缓冲区
+pub unsafe fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> Result<libc::c_long, std::io::Error> {
+    let ret = libc::syscall(libc::SYS_bpf, cmd, attr as *mut T, std::mem::size_of::<T>());
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(ret)
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+// 内核自身类型的 BTF，5.4 以上且开启 CONFIG_DEBUG_INFO_BTF 时存在
+const VMLINUX_BTF: &str = "/sys/kernel/btf/vmlinux";
+const BTF_MAGIC: u16 = 0xeb9f;
+
+// 类型种类，见 include/uapi/linux/btf.h
+const BTF_KIND_INT: u32 = 1;
+const BTF_KIND_ARRAY: u32 = 3;
+const BTF_KIND_STRUCT: u32 = 4;
+const BTF_KIND_UNION: u32 = 5;
+const BTF_KIND_ENUM: u32 = 6;
+const BTF_KIND_FUNC_PROTO: u32 = 13;
+const BTF_KIND_VAR: u32 = 14;
+const BTF_KIND_DATASEC: u32 = 15;
+const BTF_KIND_DECL_TAG: u32 = 17;
+const BTF_KIND_ENUM64: u32 = 19;
+
+// 只读取用到的部分: 按名称查找结构体并取出成员的偏移
+pub struct Btf {
+    data: Vec<u8>,
+    types: std::ops::Range<usize>,
+    strings: std::ops::Range<usize>,
+}
+
+impl Btf {
+    pub fn from_sys_fs() -> Result<Btf, anyhow::Error> {
+        Btf::parse(std::fs::read(VMLINUX_BTF)?)
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Btf, anyhow::Error> {
+        if data.len() < 24 || u16::from_ne_bytes([data[0], data[1]]) != BTF_MAGIC {
+            return Err(anyhow::anyhow!("invalid btf header"));
+        }
+        let word = |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+        let hdr_len = word(4) as usize;
+        let (type_off, type_len) = (word(8) as usize, word(12) as usize);
+        let (str_off, str_len) = (word(16) as usize, word(20) as usize);
+        let types = hdr_len + type_off..hdr_len + type_off + type_len;
+        let strings = hdr_len + str_off..hdr_len + str_off + str_len;
+        if types.end > data.len() || strings.end > data.len() {
+            return Err(anyhow::anyhow!("btf sections out of range"));
+        }
+        Ok(Btf {
+            data,
+            types,
+            strings,
+        })
+    }
+
+    fn word(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?;
+        Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn string(&self, offset: u32) -> Option<&str> {
+        let start = self.strings.start + offset as usize;
+        let rest = self.data.get(start..self.strings.end)?;
+        let end = rest.iter().position(|b| *b == 0)?;
+        std::str::from_utf8(&rest[..end]).ok()
+    }
+
+    // 结构体各成员相对结构体起始的字节偏移，位域成员不包含在内
+    pub fn struct_members(&self, name: &str) -> Option<HashMap<String, u32>> {
+        let mut offset = self.types.start;
+        while offset + 12 <= self.types.end {
+            let name_off = self.word(offset)?;
+            let info = self.word(offset + 4)?;
+            let kind = (info >> 24) & 0x1f;
+            let vlen = (info & 0xffff) as usize;
+            let kind_flag = info >> 31 == 1;
+            let body = offset + 12;
+
+            if kind == BTF_KIND_STRUCT && self.string(name_off) == Some(name) {
+                let mut members = HashMap::new();
+                for i in 0..vlen {
+                    let member = body + i * 12;
+                    let member_name = self.string(self.word(member)?)?;
+                    let mut bit_offset = self.word(member + 8)?;
+                    if kind_flag {
+                        if bit_offset >> 24 != 0 {
+                            continue;
+                        }
+                        bit_offset &= 0xff_ffff;
+                    }
+                    if bit_offset % 8 == 0 {
+                        members.insert(member_name.to_string(), bit_offset / 8);
+                    }
+                }
+                return Some(members);
+            }
+
+            offset = body
+                + match kind {
+                    BTF_KIND_INT | BTF_KIND_VAR | BTF_KIND_DECL_TAG => 4,
+                    BTF_KIND_ARRAY => 12,
+                    BTF_KIND_STRUCT | BTF_KIND_UNION | BTF_KIND_DATASEC | BTF_KIND_ENUM64 => {
+                        vlen * 12
+                    }
+                    BTF_KIND_ENUM | BTF_KIND_FUNC_PROTO => vlen * 8,
+                    _ => 0,
+                };
+        }
+        None
+    }
+}
@@ -0,0 +1,396 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::IpSignals;
+
+use crate::events::{CanaryRollbackEvent, Event};
+use crate::firewall::{FirewallAction, FirewallRule};
+use crate::reputation::ReputationPolicy;
+use crate::server::EbpfManager;
+
+// 观测期内至少需要的新建连接数，样本过少时不判定
+const MIN_SAMPLE_CONNECTIONS: u64 = 10;
+// 观测防火墙规则时至少需要的包数
+const MIN_SAMPLE_PACKETS: u64 = 100;
+// 观测期内采样影响指标的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// 保留的灰度记录数
+const MAX_HISTORY: usize = 100;
+
+// 灰度发布配置
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct CanaryConfig {
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    // 允许的最大丢包率（被丢弃的新建连接比例）
+    #[serde(default = "default_max_drop_rate")]
+    pub max_drop_rate: f64,
+    // 允许的最大连接失败率（未完成握手的新建连接比例）
+    #[serde(default = "default_max_connection_failure_rate")]
+    pub max_connection_failure_rate: f64,
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+fn default_max_drop_rate() -> f64 {
+    0.05
+}
+
+fn default_max_connection_failure_rate() -> f64 {
+    0.5
+}
+
+// 规则回滚动作，记录变更前的状态
+#[derive(Debug, Clone)]
+pub enum Rollback {
+    ReputationPolicy(ReputationPolicy),
+    // applied 为灰度发布的规则，previous 为修改前的规则，新增的规则为 None，回滚时删除
+    FirewallRule {
+        id: u32,
+        applied: FirewallRule,
+        previous: Option<FirewallRule>,
+    },
+    // 动作为 ratelimit 的防火墙规则
+    RatelimitRule {
+        id: u32,
+        applied: FirewallRule,
+        previous: Option<FirewallRule>,
+    },
+}
+
+impl Rollback {
+    // 按新规则的动作区分防火墙规则和限速规则
+    pub fn firewall_rule(id: u32, applied: FirewallRule, previous: Option<FirewallRule>) -> Self {
+        if applied.action == FirewallAction::Ratelimit {
+            Rollback::RatelimitRule {
+                id,
+                applied,
+                previous,
+            }
+        } else {
+            Rollback::FirewallRule {
+                id,
+                applied,
+                previous,
+            }
+        }
+    }
+
+    // 灰度发布的防火墙规则ID
+    fn rule_id(&self) -> Option<u32> {
+        match self {
+            Rollback::ReputationPolicy(_) => None,
+            Rollback::FirewallRule { id, .. } | Rollback::RatelimitRule { id, .. } => Some(*id),
+        }
+    }
+
+    fn target(&self) -> &'static str {
+        match self {
+            Rollback::ReputationPolicy(_) => "reputation_policy",
+            Rollback::FirewallRule { .. } => "firewall_rule",
+            Rollback::RatelimitRule { .. } => "ratelimit_rule",
+        }
+    }
+
+    async fn apply(&self, ebpf_manager: &EbpfManager) -> Result<(), anyhow::Error> {
+        match self {
+            Rollback::ReputationPolicy(policy) => {
+                *crate::reputation::REPUTATION_POLICY.lock().await = *policy;
+                crate::reputation::refresh(ebpf_manager).await
+            }
+            Rollback::FirewallRule {
+                id,
+                applied,
+                previous,
+            }
+            | Rollback::RatelimitRule {
+                id,
+                applied,
+                previous,
+            } => crate::firewall::restore(ebpf_manager, *id, applied, previous.clone())
+                .await?
+                .map_err(anyhow::Error::msg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CanaryState {
+    Running,
+    Promoted,
+    RolledBack,
+    // 超出阈值但回滚失败或因规则已被改动而跳过，原因见 rollback_error
+    RollbackFailed,
+}
+
+// 灰度发布状态
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CanaryStatus {
+    pub id: u64,
+    pub description: String,
+    pub state: CanaryState,
+    pub config: CanaryConfig,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub connections: u64,
+    // 观测防火墙规则时流量统计看到的包数及规则丢弃的包数之和
+    pub packets: u64,
+    pub drop_rate: f64,
+    pub connection_failure_rate: f64,
+    pub reason: Option<String>,
+    pub rollback_error: Option<String>,
+}
+
+// 影响指标计数，连接数来自 XDP 的 IP_SIGNALS 汇总
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImpactCounters {
+    syn_packets: u64,
+    handshakes_completed: u64,
+    dropped_syns: u64,
+    // 流量统计看到的包数
+    packets: u64,
+    // 灰度规则丢弃的包数，规则在 IP_SIGNALS 之前丢弃的包不计入 dropped_syns
+    rule_dropped: u64,
+}
+
+// 观测期内的影响指标
+struct Impact {
+    connections: u64,
+    packets: u64,
+    drop_rate: f64,
+    failure_rate: f64,
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total > 0 {
+        part as f64 / total as f64
+    } else {
+        0.0
+    }
+}
+
+impl ImpactCounters {
+    // rule 为 true 时按规则丢弃的包数计算丢包率，否则按被拦截的新建连接计算
+    fn since(&self, baseline: &ImpactCounters, rule: bool) -> Impact {
+        let syns = self.syn_packets.saturating_sub(baseline.syn_packets);
+        let completed = self
+            .handshakes_completed
+            .saturating_sub(baseline.handshakes_completed);
+        let dropped_syns = self.dropped_syns.saturating_sub(baseline.dropped_syns);
+        let connections = syns + dropped_syns;
+        let failure_rate = ratio(syns.saturating_sub(completed), syns);
+
+        if rule {
+            // XDP 中被规则丢弃的包不经过流量统计，加上后为规则看到的全部流量
+            let dropped = self.rule_dropped.saturating_sub(baseline.rule_dropped);
+            let packets = self.packets.saturating_sub(baseline.packets) + dropped;
+            Impact {
+                connections,
+                packets,
+                drop_rate: ratio(dropped, packets),
+                failure_rate,
+            }
+        } else {
+            Impact {
+                connections,
+                packets: 0,
+                drop_rate: ratio(dropped_syns, connections),
+                failure_rate,
+            }
+        }
+    }
+}
+
+// rule 为灰度发布的防火墙规则ID，规则在发布时从0开始计数，基线不需要读取
+pub async fn impact_snapshot(
+    ebpf_manager: &EbpfManager,
+    rule: Option<u32>,
+) -> Result<ImpactCounters, anyhow::Error> {
+    let evicted = crate::reputation::EVICTED_SIGNALS.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = maps
+        .map("IP_SIGNALS")
+        .ok_or_else(|| anyhow::anyhow!("IP_SIGNALS map not found"))?;
+    let map = AyaHashMap::<&MapData, u32, IpSignals>::try_from(map)?;
+
+    // 加上已从 IP_SIGNALS 移除的IP的信号，使计数在移除前后保持单调
+    let mut counters = ImpactCounters {
+        syn_packets: evicted.syn_packets,
+        handshakes_completed: evicted.handshakes_completed,
+        dropped_syns: evicted.dropped_syns,
+        ..Default::default()
+    };
+    for (_, signals) in map.iter().filter_map(|r| r.ok()) {
+        counters.syn_packets += signals.syn_packets;
+        counters.handshakes_completed += signals.handshakes_completed;
+        counters.dropped_syns += signals.dropped_syns;
+    }
+    counters.packets = crate::traffic::snapshot()
+        .device_stats
+        .values()
+        .map(|stats| stats.packets)
+        .sum();
+    if let Some(id) = rule {
+        counters.rule_dropped = crate::firewall::rule_stats(&maps, id)?.dropped;
+    }
+    Ok(counters)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+lazy_static::lazy_static! {
+    pub static ref CANARIES: Mutex<Vec<CanaryStatus>> = Mutex::new(Vec::new());
+}
+
+async fn update_status(id: u64, update: impl FnOnce(&mut CanaryStatus)) {
+    let mut canaries = CANARIES.lock().await;
+    if let Some(status) = canaries.iter_mut().find(|c| c.id == id) {
+        update(status);
+    }
+}
+
+// 在规则变更生效后启动灰度观测，超过阈值时自动回滚
+// baseline 需在应用规则之前通过 impact_snapshot 获取
+pub async fn start(
+    ebpf_manager: Arc<EbpfManager>,
+    description: String,
+    config: CanaryConfig,
+    baseline: ImpactCounters,
+    rollback: Rollback,
+) -> u64 {
+    let id = {
+        let mut canaries = CANARIES.lock().await;
+        let id = canaries.last().map(|c| c.id + 1).unwrap_or(1);
+        canaries.push(CanaryStatus {
+            id,
+            description: description.clone(),
+            state: CanaryState::Running,
+            config,
+            started_at: now_secs(),
+            finished_at: None,
+            connections: 0,
+            packets: 0,
+            drop_rate: 0.0,
+            connection_failure_rate: 0.0,
+            reason: None,
+            rollback_error: None,
+        });
+        if canaries.len() > MAX_HISTORY {
+            canaries.remove(0);
+        }
+        id
+    };
+    info!(
+        canary_id = id,
+        "灰度发布 #{} 开始: {}, 观测 {}s", id, description, config.window_secs
+    );
+
+    tokio::spawn(async move {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(config.window_secs);
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+
+        while tokio::time::Instant::now() < deadline {
+            ticker.tick().await;
+
+            let current = match impact_snapshot(&ebpf_manager, rollback.rule_id()).await {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!(canary_id = id, "灰度发布 #{} 读取影响指标失败: {}", id, e);
+                    continue;
+                }
+            };
+            let impact = current.since(&baseline, rollback.rule_id().is_some());
+            update_status(id, |s| {
+                s.connections = impact.connections;
+                s.packets = impact.packets;
+                s.drop_rate = impact.drop_rate;
+                s.connection_failure_rate = impact.failure_rate;
+            })
+            .await;
+
+            // 样本过少的指标不参与判定
+            let drop_sampled = match rollback.rule_id() {
+                Some(_) => impact.packets >= MIN_SAMPLE_PACKETS,
+                None => impact.connections >= MIN_SAMPLE_CONNECTIONS,
+            };
+            let failure_sampled = impact.connections >= MIN_SAMPLE_CONNECTIONS;
+            let (drop_rate, failure_rate) = (impact.drop_rate, impact.failure_rate);
+
+            let reason = if drop_sampled && drop_rate > config.max_drop_rate {
+                Some(format!(
+                    "drop rate {:.3} exceeds {:.3}",
+                    drop_rate, config.max_drop_rate
+                ))
+            } else if failure_sampled && failure_rate > config.max_connection_failure_rate {
+                Some(format!(
+                    "connection failure rate {:.3} exceeds {:.3}",
+                    failure_rate, config.max_connection_failure_rate
+                ))
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                warn!(
+                    canary_id = id,
+                    action = "rollback",
+                    "灰度发布 #{} 影响超出阈值，自动回滚: {}",
+                    id,
+                    reason
+                );
+                let error = rollback
+                    .apply(&ebpf_manager)
+                    .await
+                    .err()
+                    .map(|e| e.to_string());
+                if let Some(e) = &error {
+                    warn!(canary_id = id, "灰度发布 #{} 回滚失败: {}", id, e);
+                }
+                crate::events::publish(Event::CanaryRollback(CanaryRollbackEvent {
+                    timestamp: now_secs(),
+                    canary_id: id,
+                    target: rollback.target(),
+                    description: description.clone(),
+                    reason: reason.clone(),
+                    error: error.clone(),
+                }));
+                update_status(id, |s| {
+                    s.state = match error {
+                        Some(_) => CanaryState::RollbackFailed,
+                        None => CanaryState::RolledBack,
+                    };
+                    s.finished_at = Some(now_secs());
+                    s.reason = Some(reason);
+                    s.rollback_error = error;
+                })
+                .await;
+                return;
+            }
+        }
+
+        info!(
+            canary_id = id,
+            action = "keep",
+            "灰度发布 #{} 观测结束，规则保留",
+            id
+        );
+        update_status(id, |s| {
+            s.state = CanaryState::Promoted;
+            s.finished_at = Some(now_secs());
+        })
+        .await;
+    });
+
+    id
+}
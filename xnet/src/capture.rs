@@ -0,0 +1,305 @@
+use std::net::Ipv4Addr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aya::maps::{Array, MapData, RingBuf};
+use aya::Ebpf;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{CaptureFilter, CapturedPacket, CAPTURE_SNAPLEN};
+
+use crate::server::EbpfManager;
+
+// 单次抓包的默认及最大限制
+const DEFAULT_MAX_PACKETS: u32 = 1000;
+const LIMIT_MAX_PACKETS: u32 = 100_000;
+const DEFAULT_DURATION_SECS: u64 = 10;
+const LIMIT_DURATION_SECS: u64 = 300;
+
+// pcap 文件格式常量 (微秒精度)
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+pub(crate) const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+// 三层设备上抓到的包从 IP 头开始
+const PCAP_LINKTYPE_RAW: u32 = 101;
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CaptureRequest {
+    pub iface: Option<String>,
+    pub ip: Option<Ipv4Addr>,
+    pub port: Option<u16>,
+    pub max_packets: Option<u32>,
+    pub duration_secs: Option<u64>,
+    // 指定后将pcap保存到抓包目录下的该文件，否则直接在响应中返回pcap内容
+    pub output: Option<String>,
+}
+
+pub enum CaptureError {
+    // 已有抓包任务在运行
+    Busy,
+    InvalidRequest(String),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for CaptureError {
+    fn from(e: anyhow::Error) -> Self {
+        CaptureError::Failed(e)
+    }
+}
+
+lazy_static::lazy_static! {
+    // 抓包 ring buffer，持有该锁即表示正在抓包，保证同一时间只有一个抓包任务
+    static ref CAPTURE_RING: Mutex<Option<AsyncFd<RingBuf<MapData>>>> = Mutex::new(None);
+    // 抓包文件只能写入该目录
+    static ref CAPTURE_DIR: Mutex<PathBuf> = Mutex::new(PathBuf::new());
+}
+
+pub async fn set_dir(dir: PathBuf) {
+    *CAPTURE_DIR.lock().await = dir;
+}
+
+// 抓包文件名须为抓包目录下的相对路径，不能是绝对路径或包含 ..
+fn resolve(dir: &Path, output: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(output);
+    if output.is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(format!(
+            "output '{}' must be a relative path inside the capture directory",
+            output
+        ));
+    }
+    Ok(dir.join(relative))
+}
+
+// 抓包文件在抓包目录中的路径，需要时创建所在目录
+pub async fn output_path(output: &str) -> Result<PathBuf, String> {
+    let path = resolve(&CAPTURE_DIR.lock().await, output)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+    }
+    Ok(path)
+}
+
+// 从 eBPF 中取出抓包 ring buffer，需在 EbpfManager 创建前调用
+pub async fn init(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    let ring_buf = RingBuf::try_from(
+        ebpf.take_map("capture_events")
+            .ok_or_else(|| anyhow::anyhow!("capture_events map not found"))?,
+    )?;
+    *CAPTURE_RING.lock().await = Some(AsyncFd::new(ring_buf)?);
+    Ok(())
+}
+
+// 写入抓包过滤条件
+async fn set_filter(
+    ebpf_manager: &EbpfManager,
+    filter: CaptureFilter,
+) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut capture_config = Array::<&mut MapData, CaptureFilter>::try_from(
+        ebpf.map_mut("capture_config")
+            .ok_or_else(|| anyhow::anyhow!("capture_config map not found"))?,
+    )?;
+    capture_config.set(0, filter, 0)?;
+    Ok(())
+}
+
+fn drain(ring_buf: &mut RingBuf<MapData>, packets: &mut Vec<CapturedPacket>, max_packets: usize) {
+    while let Some(item) = ring_buf.next() {
+        if packets.len() < max_packets && item.len() >= std::mem::size_of::<CapturedPacket>() {
+            packets.push(bytemuck::pod_read_unaligned::<CapturedPacket>(
+                &item[..std::mem::size_of::<CapturedPacket>()],
+            ));
+        }
+    }
+}
+
+// 执行一次有界抓包，直到达到最大包数或时长，返回pcap内容。
+// 抓包在独立任务中执行，客户端断开导致请求被取消时抓包仍会结束并关闭内核中的过滤条件
+pub async fn run_capture(
+    ebpf_manager: &Arc<EbpfManager>,
+    request: &CaptureRequest,
+) -> Result<(Vec<u8>, usize), CaptureError> {
+    tokio::spawn(capture(ebpf_manager.clone(), request.clone()))
+        .await
+        .map_err(|e| CaptureError::Failed(e.into()))?
+}
+
+async fn capture(
+    ebpf_manager: Arc<EbpfManager>,
+    request: CaptureRequest,
+) -> Result<(Vec<u8>, usize), CaptureError> {
+    let ebpf_manager = &*ebpf_manager;
+    let mut ring = CAPTURE_RING.try_lock().map_err(|_| CaptureError::Busy)?;
+    let ring = ring.as_mut().ok_or_else(|| {
+        CaptureError::Failed(anyhow::anyhow!("capture ring buffer not initialized"))
+    })?;
+
+    let ifindex = match request.iface.as_deref() {
+        Some(iface) => std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", iface))
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .ok_or_else(|| {
+                CaptureError::InvalidRequest(format!("Interface {} does not exist", iface))
+            })?,
+        None => 0,
+    };
+    let linktype = linktype(ebpf_manager, &request).await?;
+    let max_packets = request
+        .max_packets
+        .unwrap_or(DEFAULT_MAX_PACKETS)
+        .clamp(1, LIMIT_MAX_PACKETS) as usize;
+    let duration = Duration::from_secs(
+        request
+            .duration_secs
+            .unwrap_or(DEFAULT_DURATION_SECS)
+            .clamp(1, LIMIT_DURATION_SECS),
+    );
+
+    // 丢弃上一次抓包残留的数据
+    drain(ring.get_mut(), &mut Vec::new(), 0);
+
+    let filter = CaptureFilter {
+        enabled: 1,
+        ifindex,
+        ip: request.ip.map(|ip| u32::from(ip).to_be()).unwrap_or(0),
+        port: request.port.unwrap_or(0),
+        reserved: 0,
+    };
+    set_filter(ebpf_manager, filter).await?;
+    info!(
+        iface = ?request.iface,
+        "开始抓包: iface={:?}, ip={:?}, port={:?}, max_packets={}, duration={:?}",
+        request.iface, request.ip, request.port, max_packets, duration
+    );
+
+    let mut packets = Vec::new();
+    let deadline = tokio::time::Instant::now() + duration;
+    while packets.len() < max_packets {
+        let mut guard = match tokio::time::timeout_at(deadline, ring.readable_mut()).await {
+            Ok(Ok(guard)) => guard,
+            Ok(Err(e)) => {
+                let _ = set_filter(ebpf_manager, bytemuck::Zeroable::zeroed()).await;
+                return Err(CaptureError::Failed(e.into()));
+            }
+            Err(_) => break,
+        };
+        drain(guard.get_inner_mut(), &mut packets, max_packets);
+        guard.clear_ready();
+    }
+
+    set_filter(ebpf_manager, bytemuck::Zeroable::zeroed()).await?;
+    drain(ring.get_mut(), &mut packets, max_packets);
+    info!("抓包结束: 共 {} 个包", packets.len());
+
+    Ok((write_pcap(&packets, linktype), packets.len()))
+}
+
+// 抓包的链路层类型，不指定设备时同时挂载了二层和三层设备则无法写入同一个 pcap 文件
+async fn linktype(
+    ebpf_manager: &EbpfManager,
+    request: &CaptureRequest,
+) -> Result<u32, CaptureError> {
+    if let Some(iface) = request.iface.as_deref() {
+        return Ok(match crate::l3::is_l3(iface)? {
+            true => PCAP_LINKTYPE_RAW,
+            false => PCAP_LINKTYPE_ETHERNET,
+        });
+    }
+    let l3_devices = crate::l3::devices(&*ebpf_manager.ebpf.lock().await)?;
+    let attached: Vec<u32> = crate::server::DEVICE_MAPPINGS
+        .lock()
+        .await
+        .values()
+        .map(|mapping| mapping.device_id)
+        .collect();
+    let l3 = attached.iter().filter(|id| l3_devices.contains(id)).count();
+    match l3 {
+        0 => Ok(PCAP_LINKTYPE_ETHERNET),
+        l3 if l3 == attached.len() => Ok(PCAP_LINKTYPE_RAW),
+        _ => Err(CaptureError::InvalidRequest(
+            "both layer 2 and layer 3 devices are attached, specify iface to capture".to_string(),
+        )),
+    }
+}
+
+// CLOCK_REALTIME 与 CLOCK_MONOTONIC 的差值，用于把 bpf_ktime_get_ns 转换为墙上时间
+pub(crate) fn monotonic_to_realtime_offset_ns() -> u64 {
+    let mut realtime = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let mut monotonic = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_REALTIME, &mut realtime);
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut monotonic);
+    }
+    let to_ns = |ts: &libc::timespec| ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+    to_ns(&realtime).saturating_sub(to_ns(&monotonic))
+}
+
+// pcap 文件头
+pub(crate) fn write_pcap_header(buf: &mut Vec<u8>, snaplen: u32, linktype: u32) {
+    buf.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+    buf.extend_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+    buf.extend_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+    buf.extend_from_slice(&0i32.to_le_bytes()); // thiszone
+    buf.extend_from_slice(&0u32.to_le_bytes()); // sigfigs
+    buf.extend_from_slice(&snaplen.to_le_bytes());
+    buf.extend_from_slice(&linktype.to_le_bytes());
+}
+
+// 单个包的 pcap 记录，ts_ns 为墙上时间
+pub(crate) fn write_pcap_record(buf: &mut Vec<u8>, ts_ns: u64, data: &[u8], frame_len: u32) {
+    buf.extend_from_slice(&((ts_ns / 1_000_000_000) as u32).to_le_bytes());
+    buf.extend_from_slice(&((ts_ns % 1_000_000_000 / 1_000) as u32).to_le_bytes());
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&frame_len.to_le_bytes());
+    buf.extend_from_slice(data);
+}
+
+// 生成 pcap 文件内容
+pub fn write_pcap(packets: &[CapturedPacket], linktype: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(24 + packets.len() * (16 + CAPTURE_SNAPLEN));
+    write_pcap_header(&mut buf, CAPTURE_SNAPLEN as u32, linktype);
+
+    let offset_ns = monotonic_to_realtime_offset_ns();
+    for packet in packets {
+        let incl_len = (packet.header_len as usize).min(CAPTURE_SNAPLEN);
+        write_pcap_record(
+            &mut buf,
+            packet.timestamp_ns + offset_ns,
+            &packet.header[..incl_len],
+            packet.frame_len,
+        );
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_outputs_inside_capture_dir() {
+        let dir = Path::new("/var/lib/xnet/captures");
+        assert_eq!(
+            resolve(dir, "eth0/443.pcap").unwrap(),
+            dir.join("eth0/443.pcap")
+        );
+        assert!(resolve(dir, "/etc/passwd").is_err());
+        assert!(resolve(dir, "../../etc/cron.d/x").is_err());
+        assert!(resolve(dir, "a/../../x").is_err());
+        assert!(resolve(dir, "").is_err());
+    }
+}
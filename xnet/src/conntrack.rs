@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use xnet_common::{
+    conntrack_timeout_secs, ConnTrackEntry, TCP_STATE_CLOSED, TCP_STATE_ESTABLISHED, TCP_STATE_FIN_WAIT,
+    TCP_STATE_SYN_SENT, TCP_STATE_TIME_WAIT,
+};
+
+use crate::server::EbpfManager;
+
+// 各状态的空闲超时(秒)，超过后由垃圾回收删除
+const TIMEOUTS: [(u32, &str, u64); 5] = [
+    (TCP_STATE_SYN_SENT, "syn_sent", conntrack_timeout_secs(TCP_STATE_SYN_SENT)),
+    (TCP_STATE_ESTABLISHED, "established", conntrack_timeout_secs(TCP_STATE_ESTABLISHED)),
+    (TCP_STATE_FIN_WAIT, "fin_wait", conntrack_timeout_secs(TCP_STATE_FIN_WAIT)),
+    (TCP_STATE_TIME_WAIT, "time_wait", conntrack_timeout_secs(TCP_STATE_TIME_WAIT)),
+    (TCP_STATE_CLOSED, "closed", conntrack_timeout_secs(TCP_STATE_CLOSED)),
+];
+
+fn state_name(state: u32) -> &'static str {
+    TIMEOUTS
+        .iter()
+        .find(|(s, _, _)| *s == state)
+        .map(|(_, name, _)| *name)
+        .unwrap_or("unknown")
+}
+
+fn timeout_ns(state: u32) -> u64 {
+    conntrack_timeout_secs(state) * 1_000_000_000
+}
+
+// 与 bpf_ktime_get_ns 相同的时钟
+pub(crate) fn monotonic_now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+// 连接跟踪的最近一次回收结果
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ConntrackStatus {
+    // 回收后各状态的连接数
+    pub states: BTreeMap<&'static str, u64>,
+    pub expired_total: u64,
+    pub last_gc: u64,
+    // 过期条目是否由内核中的定时器删除
+    pub kernel_gc: bool,
+}
+
+impl ConntrackStatus {
+    pub fn to_json(&self) -> serde_json::Value {
+        let timeouts: BTreeMap<&str, u64> = TIMEOUTS
+            .iter()
+            .map(|(_, name, secs)| (*name, *secs))
+            .collect();
+        serde_json::json!({
+            "states": self.states,
+            "expired_total": self.expired_total,
+            "last_gc": self.last_gc,
+            "kernel_gc": self.kernel_gc,
+            "timeout_secs": timeouts,
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref CONNTRACK: Mutex<ConntrackStatus> = Mutex::new(ConntrackStatus::default());
+}
+
+// 删除超时的连接跟踪条目及其流量统计，返回删除数和剩余各状态的连接数
+// 内核定时器已启用时只统计未过期的连接，不删除
+fn collect(ebpf: &mut aya::Ebpf) -> Result<(usize, BTreeMap<&'static str, u64>), anyhow::Error> {
+    let now = monotonic_now_ns();
+    let mut track = AyaHashMap::<&mut MapData, u64, ConnTrackEntry>::try_from(
+        ebpf.map_mut("CONNECTION_TRACK")
+            .ok_or_else(|| anyhow::anyhow!("CONNECTION_TRACK map not found"))?,
+    )?;
+
+    let mut expired = Vec::new();
+    let mut states = BTreeMap::new();
+    for (key, entry) in crate::batch::entries(&track) {
+        if now.saturating_sub(entry.last_seen_ns) > timeout_ns(entry.state) {
+            expired.push(key);
+        } else {
+            *states.entry(state_name(entry.state)).or_default() += 1;
+        }
+    }
+    if crate::map_gc::enabled() {
+        return Ok((0, states));
+    }
+    let removed = crate::batch::remove_keys(&mut track, &expired);
+
+    if let Some(map) = ebpf.map_mut("CONNECTION_STATS") {
+        let mut stats = AyaHashMap::<&mut MapData, u64, u64>::try_from(map)?;
+        crate::batch::remove_keys(&mut stats, &expired);
+    }
+    Ok((removed, states))
+}
+
+// 启动连接跟踪垃圾回收任务
+pub fn start(ebpf_manager: Arc<EbpfManager>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let (result, kernel_removed) = {
+                let mut ebpf = ebpf_manager.ebpf.lock().await;
+                let kernel_removed = crate::map_gc::enabled()
+                    .then(|| crate::map_gc::removed_total(&ebpf).ok())
+                    .flatten();
+                (collect(&mut ebpf), kernel_removed)
+            };
+            match result {
+                Ok((removed, states)) => {
+                    if removed > 0 {
+                        debug!("连接跟踪回收 {} 个过期条目", removed);
+                    }
+                    let mut status = CONNTRACK.lock().await;
+                    status.kernel_gc = crate::map_gc::enabled();
+                    match kernel_removed {
+                        Some(total) => status.expired_total = total,
+                        None => status.expired_total += removed as u64,
+                    }
+                    status.states = states;
+                    status.last_gc = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                }
+                Err(e) => warn!("连接跟踪回收失败: {}", e),
+            }
+        }
+    });
+}
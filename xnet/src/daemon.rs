@@ -0,0 +1,139 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use tracing::{info, warn};
+
+// 后台运行相关的参数
+pub struct DaemonOptions {
+    pub daemonize: bool,
+    pub pid_file: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+}
+
+// 持有 flock 的 PID 文件，进程退出时锁随之释放，残留的文件不会妨碍下次启动
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    // 加锁失败说明已有实例在运行
+    fn lock(path: &Path) -> Result<Self, anyhow::Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("failed to open pid file {}: {}", path.display(), e))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            anyhow::bail!(
+                "xnet is already running (pid {}, pid file {})",
+                pid.trim(),
+                path.display()
+            );
+        }
+        Ok(PidFile {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    fn write_pid(&mut self) -> Result<(), anyhow::Error> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        writeln!(self.file, "{}", std::process::id())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn open_log(path: &Path) -> Result<File, anyhow::Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("failed to open log file {}: {}", path.display(), e))
+}
+
+// 把标准输出和标准错误重定向到 file，日志和 panic 信息都会写入其中
+fn redirect_output(file: &File) -> Result<(), anyhow::Error> {
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+fn fork() -> Result<bool, anyhow::Error> {
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+// 两次 fork 并 setsid 脱离终端，父进程直接退出
+// 不切换到 /，命令行中的相对路径保持有效
+fn detach() -> Result<(), anyhow::Error> {
+    if fork()? {
+        unsafe { libc::_exit(0) };
+    }
+    if unsafe { libc::setsid() } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    if fork()? {
+        unsafe { libc::_exit(0) };
+    }
+    let null = File::open("/dev/null")?;
+    if unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+// 须在创建 tokio 运行时之前调用：fork 只复制调用线程
+// 先锁 PID 文件，已有实例运行时在前台报错退出
+pub fn prepare(options: &DaemonOptions) -> Result<Option<PidFile>, anyhow::Error> {
+    let mut pid_file = options.pid_file.as_deref().map(PidFile::lock).transpose()?;
+    let log = match &options.log_file {
+        Some(path) => Some(open_log(path)?),
+        None if options.daemonize => Some(OpenOptions::new().write(true).open("/dev/null")?),
+        None => None,
+    };
+    if options.daemonize {
+        detach()?;
+    }
+    if let Some(log) = &log {
+        redirect_output(log)?;
+    }
+    if let Some(pid_file) = &mut pid_file {
+        pid_file.write_pid()?;
+    }
+    Ok(pid_file)
+}
+
+// 收到 SIGHUP 时重新打开日志文件，配合 logrotate 的 move/create 使用
+pub fn reopen_log_on_sighup(path: PathBuf) -> Result<(), anyhow::Error> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match open_log(&path).and_then(|file| redirect_output(&file)) {
+                Ok(()) => info!("已重新打开日志文件 {}", path.display()),
+                Err(e) => warn!("重新打开日志文件失败: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
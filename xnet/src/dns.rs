@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{Array, MapData};
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::events::Event;
+use crate::kernel_events::DnsQueryEvent;
+use crate::server::EbpfManager;
+
+// 按源地址统计的周期
+const WINDOW: Duration = Duration::from_secs(60);
+// 查询名或其中一个标签超过该长度视为过长
+const LONG_NAME_LEN: usize = 100;
+const LONG_LABEL_LEN: usize = 50;
+// 去掉最后两级域名后至少这么长才计算熵，短名字的熵没有意义
+const ENTROPY_MIN_LEN: usize = 24;
+// 每个字符的香农熵(bit)，base32/base64/十六进制编码的数据通常在 4 以上
+const ENTROPY_THRESHOLD: f64 = 4.0;
+// 一个周期内可疑查询名或 TXT/NULL 查询达到阈值时告警
+const MIN_SUSPICIOUS_NAMES: u64 = 20;
+const MIN_TXT_NULL_QUERIES: u64 = 50;
+// 告警中附带的查询名示例数
+const MAX_EXAMPLES: usize = 5;
+// 一个周期内统计的源地址上限
+const MAX_SOURCES: usize = 4096;
+// 保留的告警数
+const MAX_HISTORY: usize = 1000;
+
+const QTYPE_NULL: u16 = 10;
+const QTYPE_TXT: u16 = 16;
+
+// 疑似通过 DNS 查询外传数据的源地址
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DnsExfilEvent {
+    pub ip: Ipv4Addr,
+    // 本周期内的查询数及各类可疑查询数
+    pub queries: u64,
+    pub long_names: u64,
+    pub high_entropy_names: u64,
+    pub txt_null_queries: u64,
+    // 可疑的查询名示例，供排查
+    pub examples: Vec<String>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Default)]
+struct SourceStats {
+    queries: u64,
+    long_names: u64,
+    high_entropy_names: u64,
+    txt_null_queries: u64,
+    examples: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct DnsState {
+    // 当前周期按源地址的统计
+    sources: HashMap<Ipv4Addr, SourceStats>,
+    // 当前处于告警状态的源地址，恢复正常前不重复告警
+    flagged: HashSet<Ipv4Addr>,
+    history: VecDeque<DnsExfilEvent>,
+}
+
+lazy_static::lazy_static! {
+    static ref DNS: Mutex<DnsState> = Mutex::new(DnsState::default());
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// 解析 DNS 查询报文中的第一个问题，返回查询名和类型；应答或无法解析的报文返回 None
+pub fn parse_query(payload: &[u8]) -> Option<(String, u16)> {
+    if payload.len() < 12 || payload[2] & 0x80 != 0 {
+        return None;
+    }
+    let qdcount = u16::from_be_bytes([payload[4], payload[5]]);
+    if qdcount == 0 {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *payload.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // 查询中不应出现压缩指针
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        let label = payload.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += len;
+    }
+    let qtype = u16::from_be_bytes([*payload.get(pos)?, *payload.get(pos + 1)?]);
+    Some((labels.join("."), qtype))
+}
+
+// 每个字符的香农熵
+fn entropy(s: &str) -> f64 {
+    let mut counts = [0u32; 256];
+    for b in s.bytes() {
+        counts[b as usize] += 1;
+    }
+    let len = s.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn long_name(name: &str) -> bool {
+    name.len() > LONG_NAME_LEN || name.split('.').any(|label| label.len() > LONG_LABEL_LEN)
+}
+
+// 只看最后两级域名之前的部分，编码后的数据通常放在这里
+fn high_entropy(name: &str) -> bool {
+    let labels: Vec<&str> = name.split('.').collect();
+    if labels.len() <= 2 {
+        return false;
+    }
+    let subdomain = labels[..labels.len() - 2].concat();
+    subdomain.len() >= ENTROPY_MIN_LEN && entropy(&subdomain) >= ENTROPY_THRESHOLD
+}
+
+async fn record(query: &DnsQueryEvent) {
+    let mut state = DNS.lock().await;
+    let ip = query.flow.src_ip;
+    if !state.sources.contains_key(&ip) && state.sources.len() >= MAX_SOURCES {
+        return;
+    }
+    let stats = state.sources.entry(ip).or_default();
+    stats.queries += 1;
+    let long = long_name(&query.name);
+    let encoded = high_entropy(&query.name);
+    if long {
+        stats.long_names += 1;
+    }
+    if encoded {
+        stats.high_entropy_names += 1;
+    }
+    if query.qtype == QTYPE_TXT || query.qtype == QTYPE_NULL {
+        stats.txt_null_queries += 1;
+    }
+    if (long || encoded) && stats.examples.len() < MAX_EXAMPLES {
+        stats.examples.push(query.name.clone());
+    }
+}
+
+// 结束一个周期，对超过阈值的源地址告警
+async fn check() {
+    let mut state = DNS.lock().await;
+    let sources = std::mem::take(&mut state.sources);
+    for (ip, stats) in &sources {
+        let suspicious = stats.long_names + stats.high_entropy_names >= MIN_SUSPICIOUS_NAMES
+            || stats.txt_null_queries >= MIN_TXT_NULL_QUERIES;
+        if !suspicious || !state.flagged.insert(*ip) {
+            continue;
+        }
+        warn!(
+            src_ip = %ip,
+            "源IP {} 疑似通过 DNS 外传数据: {} 个查询，过长 {}，高熵 {}，TXT/NULL {}，例如 {}",
+            ip,
+            stats.queries,
+            stats.long_names,
+            stats.high_entropy_names,
+            stats.txt_null_queries,
+            stats.examples.first().map(String::as_str).unwrap_or("-")
+        );
+        let event = DnsExfilEvent {
+            ip: *ip,
+            queries: stats.queries,
+            long_names: stats.long_names,
+            high_entropy_names: stats.high_entropy_names,
+            txt_null_queries: stats.txt_null_queries,
+            examples: stats.examples.clone(),
+            timestamp: now_secs(),
+        };
+        crate::events::publish(Event::DnsExfil(event.clone()));
+        if state.history.len() >= MAX_HISTORY {
+            state.history.pop_front();
+        }
+        state.history.push_back(event);
+    }
+    // 整个周期没有可疑查询的源地址恢复正常
+    state.flagged.retain(|ip| {
+        sources.get(ip).is_none_or(|stats| {
+            stats.long_names + stats.high_entropy_names + stats.txt_null_queries > 0
+        })
+    });
+}
+
+// 开启 XDP 程序推送 DNS 查询，并按周期检查各源地址的查询
+pub async fn start(ebpf_manager: &EbpfManager) -> Result<(), anyhow::Error> {
+    {
+        let mut ebpf = ebpf_manager.ebpf.lock().await;
+        let mut inspect = Array::<&mut MapData, u32>::try_from(
+            ebpf.map_mut("dns_inspect")
+                .ok_or_else(|| anyhow::anyhow!("dns_inspect map not found"))?,
+        )?;
+        inspect.set(0, 1, 0)?;
+    }
+    info!("已开启 DNS 查询检查");
+
+    let mut events = crate::events::subscribe();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(WINDOW);
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => check().await,
+                event = events.recv() => match event {
+                    Ok(Event::DnsQuery(query)) => record(&query).await,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("DNS 检查落后，跳过 {} 个事件", skipped);
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+            }
+        }
+    });
+    Ok(())
+}
+
+// 最近的告警及当前仍处于告警状态的源地址
+pub async fn report() -> Value {
+    let state = DNS.lock().await;
+    let mut active: Vec<Ipv4Addr> = state.flagged.iter().copied().collect();
+    active.sort();
+    serde_json::json!({
+        "window_secs": WINDOW.as_secs(),
+        "active": active,
+        "history": state.history,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut payload = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            payload.push(label.len() as u8);
+            payload.extend_from_slice(label.as_bytes());
+        }
+        payload.push(0);
+        payload.extend_from_slice(&qtype.to_be_bytes());
+        payload.extend_from_slice(&1u16.to_be_bytes());
+        payload
+    }
+
+    #[test]
+    fn parses_query_name_and_type() {
+        let payload = query("www.example.com", QTYPE_TXT);
+        assert_eq!(
+            parse_query(&payload),
+            Some(("www.example.com".to_string(), QTYPE_TXT))
+        );
+
+        // 应答报文
+        let mut response = payload.clone();
+        response[2] |= 0x80;
+        assert_eq!(parse_query(&response), None);
+
+        // 截断的报文
+        assert_eq!(parse_query(&payload[..payload.len() - 6]), None);
+    }
+
+    #[test]
+    fn flags_encoded_names() {
+        assert!(!high_entropy("www.example.com"));
+        assert!(!high_entropy("mail.google.com"));
+        assert!(high_entropy(
+            "mzxw6ytboi2gk3tfnfzgc4lpnvqwk5dsn5xgk3q.x7k2p9.exfil.example"
+        ));
+        assert!(long_name(&format!("{}.example.com", "a".repeat(60))));
+        assert!(!long_name("www.example.com"));
+    }
+}
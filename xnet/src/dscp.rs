@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{DscpKey, DscpMark};
+
+use crate::lb::L4Protocol;
+use crate::server::EbpfManager;
+
+// 与 eBPF 中 dscp_rules 的 max_entries 一致
+const MAX_RULES: u32 = 1024;
+
+// 常用的 DSCP 名称，见 RFC 2474/2597/3246
+const DSCP_NAMES: [(&str, u8); 22] = [
+    ("cs0", 0),
+    ("cs1", 8),
+    ("af11", 10),
+    ("af12", 12),
+    ("af13", 14),
+    ("cs2", 16),
+    ("af21", 18),
+    ("af22", 20),
+    ("af23", 22),
+    ("cs3", 24),
+    ("af31", 26),
+    ("af32", 28),
+    ("af33", 30),
+    ("cs4", 32),
+    ("af41", 34),
+    ("af42", 36),
+    ("af43", 38),
+    ("cs5", 40),
+    ("ef", 46),
+    ("cs6", 48),
+    ("cs7", 56),
+    ("be", 0),
+];
+
+// DSCP 值，可以是 0-63 的数字或 ef、af41、cs1 等名称
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Dscp {
+    Value(u8),
+    Name(String),
+}
+
+impl Dscp {
+    fn value(&self) -> Result<u8, String> {
+        match self {
+            Dscp::Value(v) if *v < 64 => Ok(*v),
+            Dscp::Value(v) => Err(format!("dscp {} out of range 0-63", v)),
+            Dscp::Name(name) => DSCP_NAMES
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| *v)
+                .ok_or_else(|| format!("unknown dscp name {}", name)),
+        }
+    }
+}
+
+fn dscp_name(value: u8) -> Option<&'static str> {
+    DSCP_NAMES.iter().find(|(_, v)| *v == value).map(|(n, _)| *n)
+}
+
+// DSCP重标记规则: 源或目的地址匹配 ip、源或目的端口匹配 port 的包改写为指定 DSCP
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DscpRule {
+    pub ip: Option<Ipv4Addr>,
+    pub port: Option<u16>,
+    // 按端口匹配时必填
+    pub protocol: Option<L4Protocol>,
+    pub dscp: Dscp,
+}
+
+impl DscpRule {
+    fn validate(&self) -> Result<u8, String> {
+        if self.ip.is_none() && self.port.is_none() {
+            return Err("ip or port is required".to_string());
+        }
+        if self.port.is_some() != self.protocol.is_some() {
+            return Err("protocol is required with port and only allowed with port".to_string());
+        }
+        self.dscp.value()
+    }
+
+    fn key(&self) -> DscpKey {
+        DscpKey {
+            addr: self.ip.map(|ip| u32::from(ip).to_be()).unwrap_or(0),
+            port: self.port.unwrap_or(0).to_be(),
+            protocol: self.protocol.map(|p| p.number()).unwrap_or(0),
+            reserved: 0,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    // 规则ID -> 规则
+    static ref DSCP_RULES: Mutex<BTreeMap<u32, DscpRule>> = Mutex::new(BTreeMap::new());
+}
+
+// 列出所有DSCP重标记规则及命中的包数
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let rules = DSCP_RULES.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, DscpKey, DscpMark>::try_from(
+        maps.map("dscp_rules")
+            .ok_or_else(|| anyhow::anyhow!("dscp_rules map not found"))?,
+    )?;
+
+    Ok(rules
+        .iter()
+        .map(|(id, rule)| {
+            let mark = map.get(&rule.key(), 0).ok();
+            let dscp = mark.map(|m| m.dscp).or_else(|| rule.dscp.value().ok());
+            serde_json::json!({
+                "id": id,
+                "ip": rule.ip,
+                "port": rule.port,
+                "protocol": rule.protocol,
+                "dscp": dscp,
+                "dscp_name": dscp.and_then(dscp_name),
+                "packets": mark.map(|m| m.packets).unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+// 新增DSCP重标记规则，匹配条件相同的规则会被替换，返回规则ID
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    rule: DscpRule,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    let dscp = match rule.validate() {
+        Ok(dscp) => dscp,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let mut rules = DSCP_RULES.lock().await;
+    let key = rule.key();
+    let existing = rules
+        .iter()
+        .find(|(_, r)| r.key() == key)
+        .map(|(id, _)| *id);
+    let id = match existing.or_else(|| (0..MAX_RULES).find(|id| !rules.contains_key(id))) {
+        Some(id) => id,
+        None => return Ok(Err(format!("at most {} dscp rules", MAX_RULES))),
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, DscpKey, DscpMark>::try_from(
+        ebpf.map_mut("dscp_rules")
+            .ok_or_else(|| anyhow::anyhow!("dscp_rules map not found"))?,
+    )?;
+    let mark = DscpMark {
+        dscp,
+        reserved: [0; 7],
+        packets: 0,
+    };
+    map.insert(key, mark, 0)?;
+
+    info!(
+        rule_id = id,
+        "DSCP规则 {}: ip={:?} port={:?} {:?} -> {}",
+        id, rule.ip, rule.port, rule.protocol, dscp
+    );
+    let action = if rules.insert(id, rule).is_some() {
+        "update"
+    } else {
+        "create"
+    };
+    crate::events::rule_change("dscp", id as u64, action);
+    Ok(Ok(id))
+}
+
+// 删除DSCP重标记规则，规则不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut rules = DSCP_RULES.lock().await;
+    let Some(rule) = rules.remove(&id) else {
+        return Ok(false);
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, DscpKey, DscpMark>::try_from(
+        ebpf.map_mut("dscp_rules")
+            .ok_or_else(|| anyhow::anyhow!("dscp_rules map not found"))?,
+    )?;
+    map.remove(&rule.key())?;
+    info!(rule_id = id, action = "delete", "DSCP规则 {} 已删除", id);
+    crate::events::rule_change("dscp", id as u64, "delete");
+    Ok(true)
+}
@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use aya::maps::{HashMap as AyaHashMap, MapData, RingBuf};
+use aya::programs::Lsm;
+use aya::{Btf, Ebpf};
+use serde_json::Value;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::{EgressDenial, EgressPolicyKey, EgressPolicyRule};
+
+use crate::events::Event;
+use crate::server::EbpfManager;
+
+// 与 eBPF 中 egress_policy 的 max_entries 一致
+const MAX_RULES: u32 = 4096;
+// 保留的拒绝记录数
+const MAX_DENIALS: usize = 1000;
+
+// LSM 程序挂载成功后置位
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// 出方向连接策略: 拒绝 cgroup 内进程 connect() 到匹配 ip 和/或 port 的地址，cgroup 为空时对所有进程生效
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct EgressRule {
+    // cgroup v2 目录，例如 /sys/fs/cgroup/system.slice/nginx.service
+    pub cgroup: Option<PathBuf>,
+    pub ip: Option<Ipv4Addr>,
+    pub port: Option<u16>,
+}
+
+impl EgressRule {
+    fn key(&self) -> Result<EgressPolicyKey, String> {
+        if self.ip.is_none() && self.port.is_none() {
+            return Err("ip or port is required".to_string());
+        }
+        // cgroup v2 的 ID 即目录的 inode
+        let cgroup_id = match &self.cgroup {
+            Some(path) => std::fs::metadata(path)
+                .map_err(|e| format!("failed to stat cgroup {}: {}", path.display(), e))?
+                .ino(),
+            None => 0,
+        };
+        Ok(EgressPolicyKey {
+            cgroup_id,
+            addr: self.ip.map(|ip| u32::from(ip).to_be()).unwrap_or(0),
+            port: self.port.unwrap_or(0).to_be(),
+            reserved: 0,
+        })
+    }
+}
+
+// 被拒绝的连接
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct EgressDenialEvent {
+    pub timestamp: u64,
+    pub pid: u32,
+    pub comm: String,
+    pub cgroup_id: u64,
+    // 匹配到规则中配置的 cgroup 时为其路径
+    pub cgroup: Option<PathBuf>,
+    pub ip: Ipv4Addr,
+    pub port: u16,
+}
+
+lazy_static::lazy_static! {
+    // 规则ID -> (规则, 写入 map 的 key)
+    static ref EGRESS_RULES: Mutex<BTreeMap<u32, (EgressRule, EgressPolicyKey)>> = Mutex::new(BTreeMap::new());
+    static ref DENIALS: Mutex<VecDeque<EgressDenialEvent>> = Mutex::new(VecDeque::new());
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// 加载并挂载 socket_connect LSM 程序，需要内核 BTF 和 lsm=...,bpf 启动参数
+// 需在创建 EbpfManager 之前调用，以取出拒绝事件的 ring buffer
+pub fn start(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    attach_program(ebpf)?;
+
+    let ring_buf = RingBuf::try_from(
+        ebpf.take_map("egress_denials")
+            .ok_or_else(|| anyhow::anyhow!("egress_denials map not found"))?,
+    )?;
+    let mut ring_buf = AsyncFd::new(ring_buf)?;
+    tokio::spawn(async move {
+        loop {
+            let mut guard = match ring_buf.readable_mut().await {
+                Ok(guard) => guard,
+                Err(e) => {
+                    warn!("读取出方向拒绝事件失败: {}", e);
+                    return;
+                }
+            };
+            let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+            let mut denials = Vec::new();
+            let ring_buf = guard.get_inner_mut();
+            while let Some(item) = ring_buf.next() {
+                if item.len() >= std::mem::size_of::<EgressDenial>() {
+                    denials.push(bytemuck::pod_read_unaligned::<EgressDenial>(
+                        &item[..std::mem::size_of::<EgressDenial>()],
+                    ));
+                }
+            }
+            guard.clear_ready();
+            for denial in denials {
+                record(&denial, offset_ns).await;
+            }
+        }
+    });
+
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("出方向连接策略 LSM 程序已挂载");
+    Ok(())
+}
+
+fn attach_program(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    let btf = Btf::from_sys_fs()?;
+    let program: &mut Lsm = ebpf
+        .program_mut("xnet_egress_policy")
+        .ok_or_else(|| anyhow::anyhow!("xnet_egress_policy program not found"))?
+        .try_into()?;
+    program.load("socket_connect", &btf)?;
+    program.attach()?;
+    Ok(())
+}
+
+// 热重载时挂载新的程序，拒绝事件 ring buffer 已固定在 bpffs 中，读取任务不受影响
+pub fn reattach(new: &mut Ebpf) -> Result<(), anyhow::Error> {
+    if !enabled() {
+        return Ok(());
+    }
+    attach_program(new)
+}
+
+async fn record(denial: &EgressDenial, offset_ns: u64) {
+    let cgroup = EGRESS_RULES
+        .lock()
+        .await
+        .values()
+        .find(|(_, key)| key.cgroup_id == denial.cgroup_id)
+        .and_then(|(rule, _)| rule.cgroup.clone());
+    let len = denial
+        .comm
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(denial.comm.len());
+    let event = EgressDenialEvent {
+        timestamp: (denial.timestamp_ns + offset_ns) / 1_000_000_000,
+        pid: denial.pid,
+        comm: String::from_utf8_lossy(&denial.comm[..len]).into_owned(),
+        cgroup_id: denial.cgroup_id,
+        cgroup,
+        ip: Ipv4Addr::from(u32::from_be(denial.addr)),
+        port: denial.port,
+    };
+    info!(
+        pid = event.pid,
+        comm = %event.comm,
+        dst_ip = %event.ip,
+        dst_port = event.port,
+        action = "deny",
+        "拒绝出方向连接: pid={} comm={} -> {}:{}",
+        event.pid, event.comm, event.ip, event.port
+    );
+    crate::events::publish(Event::EgressDenied(event.clone()));
+
+    let mut denials = DENIALS.lock().await;
+    if denials.len() >= MAX_DENIALS {
+        denials.pop_front();
+    }
+    denials.push_back(event);
+}
+
+// 最近被拒绝的连接，从新到旧
+pub async fn denials() -> Vec<EgressDenialEvent> {
+    DENIALS.lock().await.iter().rev().cloned().collect()
+}
+
+// 列出所有出方向连接策略及拒绝的连接数
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let rules = EGRESS_RULES.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, EgressPolicyKey, EgressPolicyRule>::try_from(
+        maps.map("egress_policy")
+            .ok_or_else(|| anyhow::anyhow!("egress_policy map not found"))?,
+    )?;
+
+    Ok(rules
+        .iter()
+        .map(|(id, (rule, key))| {
+            serde_json::json!({
+                "id": id,
+                "cgroup": rule.cgroup,
+                "cgroup_id": key.cgroup_id,
+                "ip": rule.ip,
+                "port": rule.port,
+                "denied": map.get(key, 0).map(|r| r.denied).unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+// 新增出方向连接策略，匹配条件相同的规则会被替换，返回规则ID
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    rule: EgressRule,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    if !enabled() {
+        return Ok(Err(
+            "egress policy is not enabled, start with --egress-policy".to_string(),
+        ));
+    }
+    let key = match rule.key() {
+        Ok(key) => key,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let mut rules = EGRESS_RULES.lock().await;
+    let existing = rules
+        .iter()
+        .find(|(_, (_, k))| *k == key)
+        .map(|(id, _)| *id);
+    let id = match existing.or_else(|| (0..MAX_RULES).find(|id| !rules.contains_key(id))) {
+        Some(id) => id,
+        None => return Ok(Err(format!("at most {} egress rules", MAX_RULES))),
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, EgressPolicyKey, EgressPolicyRule>::try_from(
+        ebpf.map_mut("egress_policy")
+            .ok_or_else(|| anyhow::anyhow!("egress_policy map not found"))?,
+    )?;
+    map.insert(key, EgressPolicyRule { denied: 0 }, 0)?;
+
+    info!(
+        "出方向连接策略 {}: cgroup={:?} ip={:?} port={:?}",
+        id, rule.cgroup, rule.ip, rule.port
+    );
+    rules.insert(id, (rule, key));
+    let action = if existing.is_some() { "update" } else { "create" };
+    crate::events::rule_change("egress", id as u64, action);
+    Ok(Ok(id))
+}
+
+// 删除出方向连接策略，规则不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut rules = EGRESS_RULES.lock().await;
+    let Some((_, key)) = rules.remove(&id) else {
+        return Ok(false);
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, EgressPolicyKey, EgressPolicyRule>::try_from(
+        ebpf.map_mut("egress_policy")
+            .ok_or_else(|| anyhow::anyhow!("egress_policy map not found"))?,
+    )?;
+    map.remove(&key)?;
+    info!("出方向连接策略 {} 已删除", id);
+    crate::events::rule_change("egress", id as u64, "delete");
+    Ok(true)
+}
@@ -0,0 +1,169 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::alert::AlertEvent;
+use crate::anomaly::AnomalyEvent;
+use crate::dns::DnsExfilEvent;
+use crate::egress::EgressDenialEvent;
+use crate::kernel_events::{
+    ConnCloseEvent, ConnOpenEvent, DnsQueryEvent, HoneypotProbeEvent, KernelAlertEvent, RuleHitEvent,
+};
+use crate::quota::QuotaEvent;
+use crate::ttl::TtlAnomalyEvent;
+
+// 事件流缓冲区大小，订阅者落后超过该数量时丢弃旧事件
+const EVENT_CAPACITY: usize = 1024;
+
+// 程序挂载到设备或从设备卸载
+#[derive(Debug, Clone, Serialize)]
+pub struct AttachmentEvent {
+    pub timestamp: u64,
+    // traffic、firewall、lb 或 shaping
+    pub program: &'static str,
+    pub iface: String,
+    // attach 或 detach
+    pub action: &'static str,
+}
+
+// 规则新增、修改或删除
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleChangeEvent {
+    pub timestamp: u64,
+    // firewall、allowlist、default_deny、egress、alert、watch、geo、nat、dscp 或 shaping
+    pub rules: &'static str,
+    // replace 时没有 id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    // 按设备配置的规则(shaping、default_deny)为设备名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iface: Option<String>,
+    // 仅 replace 时有，为替换后的规则数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    // create、update、delete 或 replace(通过 /rules 整体替换)
+    pub action: &'static str,
+}
+
+// 灰度发布超过影响阈值后自动回滚
+#[derive(Debug, Clone, Serialize)]
+pub struct CanaryRollbackEvent {
+    pub timestamp: u64,
+    pub canary_id: u64,
+    // reputation_policy、firewall_rule 或 ratelimit_rule
+    pub target: &'static str,
+    pub description: String,
+    pub reason: String,
+    // 回滚失败时的错误
+    pub error: Option<String>,
+}
+
+// 推送到 /events 的事件
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Alert(AlertEvent),
+    Anomaly(AnomalyEvent),
+    EgressDenied(EgressDenialEvent),
+    TtlAnomaly(TtlAnomalyEvent),
+    DnsExfil(DnsExfilEvent),
+    Quota(QuotaEvent),
+    Attachment(AttachmentEvent),
+    RuleChange(RuleChangeEvent),
+    CanaryRollback(CanaryRollbackEvent),
+    // 以下由内核通过 xnet_events ring buffer 推送
+    ConnOpen(ConnOpenEvent),
+    ConnClose(ConnCloseEvent),
+    RuleHit(RuleHitEvent),
+    KernelAlert(KernelAlertEvent),
+    HoneypotProbe(HoneypotProbeEvent),
+    DnsQuery(DnsQueryEvent),
+}
+
+lazy_static::lazy_static! {
+    static ref EVENTS: broadcast::Sender<Event> = broadcast::channel(EVENT_CAPACITY).0;
+}
+
+// 发布事件，没有订阅者时直接丢弃
+pub fn publish(event: Event) {
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    EVENTS.subscribe()
+}
+
+impl Event {
+    // 与序列化后的 type 字段相同，作为 SSE 的事件名
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Alert(_) => "alert",
+            Event::Anomaly(_) => "anomaly",
+            Event::EgressDenied(_) => "egress_denied",
+            Event::TtlAnomaly(_) => "ttl_anomaly",
+            Event::DnsExfil(_) => "dns_exfil",
+            Event::Quota(_) => "quota",
+            Event::Attachment(_) => "attachment",
+            Event::RuleChange(_) => "rule_change",
+            Event::CanaryRollback(_) => "canary_rollback",
+            Event::ConnOpen(_) => "conn_open",
+            Event::ConnClose(_) => "conn_close",
+            Event::RuleHit(_) => "rule_hit",
+            Event::KernelAlert(_) => "kernel_alert",
+            Event::HoneypotProbe(_) => "honeypot_probe",
+            Event::DnsQuery(_) => "dns_query",
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn attachment(program: &'static str, iface: &str, action: &'static str) {
+    publish(Event::Attachment(AttachmentEvent {
+        timestamp: now_secs(),
+        program,
+        iface: iface.to_string(),
+        action,
+    }));
+}
+
+pub fn rule_change(rules: &'static str, id: u64, action: &'static str) {
+    publish(Event::RuleChange(RuleChangeEvent {
+        timestamp: now_secs(),
+        rules,
+        id: Some(id),
+        iface: None,
+        count: None,
+        action,
+    }));
+}
+
+// 按设备配置的规则变更
+pub fn iface_rule_change(rules: &'static str, iface: &str, action: &'static str) {
+    publish(Event::RuleChange(RuleChangeEvent {
+        timestamp: now_secs(),
+        rules,
+        id: None,
+        iface: Some(iface.to_string()),
+        count: None,
+        action,
+    }));
+}
+
+// 通过 /rules 整体替换规则
+pub fn rules_replaced(rules: &'static str, count: usize) {
+    publish(Event::RuleChange(RuleChangeEvent {
+        timestamp: now_secs(),
+        rules,
+        id: None,
+        iface: None,
+        count: Some(count),
+        action: "replace",
+    }));
+}
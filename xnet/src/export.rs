@@ -0,0 +1,227 @@
+use std::path::PathBuf;
+
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use hyper::client::HttpConnector;
+use hyper::Client;
+use serde_json::{Map as JsonMap, Value};
+
+// 统计接口的输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+    Jsonl,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct FormatQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+// 导出的统计表及对应的接口
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Table {
+    Port,
+    Device,
+    Connection,
+}
+
+impl Table {
+    fn name(&self) -> &'static str {
+        match self {
+            Table::Port => "port",
+            Table::Device => "device",
+            Table::Connection => "connection",
+        }
+    }
+
+    fn path(&self) -> &'static str {
+        match self {
+            Table::Port => "/api/v1/traffic_port_stats",
+            Table::Device => "/api/v1/traffic_device_stats",
+            Table::Connection => "/api/v1/traffic_device_connection_stats",
+        }
+    }
+}
+
+// xnet export 的参数
+#[derive(Debug, clap::Args)]
+pub struct ExportArgs {
+    /// 本地 xnet API 地址
+    #[clap(long, default_value = "http://127.0.0.1:8080")]
+    url: String,
+    #[clap(long, value_enum, default_value = "csv")]
+    format: ExportFormat,
+    /// 输出文件
+    #[clap(long)]
+    out: PathBuf,
+    /// 导出的表，可重复指定，默认导出全部
+    #[clap(long = "table", value_enum)]
+    tables: Vec<Table>,
+}
+
+// 嵌套对象展开为 a.b 形式的列，数组保留为 JSON 文本
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        _ => out.push((prefix.to_string(), value.clone())),
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+// 列为所有行出现过的字段的并集
+pub fn to_csv(rows: &[Value]) -> String {
+    let rows: Vec<Vec<(String, Value)>> = rows
+        .iter()
+        .map(|row| {
+            let mut fields = Vec::new();
+            flatten("", row, &mut fields);
+            fields
+        })
+        .collect();
+
+    let mut columns: Vec<&str> = Vec::new();
+    for fields in rows.iter() {
+        for (key, _) in fields {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+
+    let mut csv = columns.join(",");
+    csv.push('\n');
+    for fields in rows.iter() {
+        let line: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                fields
+                    .iter()
+                    .find(|(key, _)| key == column)
+                    .map(|(_, value)| csv_field(value))
+                    .unwrap_or_default()
+            })
+            .collect();
+        csv.push_str(&line.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+pub fn to_jsonl(rows: &[Value]) -> String {
+    rows.iter().map(|row| format!("{}\n", row)).collect()
+}
+
+// 取出分页结果或数组中的行
+pub fn items(page: Value) -> Vec<Value> {
+    match page {
+        Value::Array(rows) => rows,
+        Value::Object(mut map) => match map.remove("items") {
+            Some(Value::Array(items)) => items,
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+// json 格式原样返回，csv/jsonl 格式返回 rows 转换后的文本
+pub fn response(
+    format: ExportFormat,
+    json: Value,
+    rows: impl FnOnce(Value) -> Vec<Value>,
+) -> Response {
+    match format {
+        ExportFormat::Json => (StatusCode::OK, Json(json)).into_response(),
+        ExportFormat::Csv => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            to_csv(&rows(json)),
+        )
+            .into_response(),
+        ExportFormat::Jsonl => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/x-ndjson")],
+            to_jsonl(&rows(json)),
+        )
+            .into_response(),
+    }
+}
+
+async fn fetch_rows(
+    client: &Client<HttpConnector>,
+    base: &str,
+    table: Table,
+) -> Result<Vec<Value>, anyhow::Error> {
+    let url = format!("{}{}?format=jsonl", base, table.path());
+    let response = client.get(url.parse()?).await?;
+    if !response.status().is_success() {
+        anyhow::bail!("GET {} returned {}", url, response.status());
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    let mut rows = Vec::new();
+    for line in std::str::from_utf8(&body)?
+        .lines()
+        .filter(|l| !l.is_empty())
+    {
+        // 每行加上 table 列，便于多表导出到同一文件后区分
+        let mut row = JsonMap::new();
+        row.insert("table".to_string(), Value::from(table.name()));
+        if let Value::Object(fields) = serde_json::from_str(line)? {
+            row.extend(fields);
+        }
+        rows.push(Value::Object(row));
+    }
+    Ok(rows)
+}
+
+// 从本地 API 拉取统计表并写入文件
+pub async fn run(args: ExportArgs) -> Result<(), anyhow::Error> {
+    let client = Client::new();
+    let base = args.url.trim_end_matches('/');
+    let tables = if args.tables.is_empty() {
+        vec![Table::Port, Table::Device, Table::Connection]
+    } else {
+        args.tables
+    };
+
+    let mut rows = Vec::new();
+    for table in tables {
+        rows.extend(fetch_rows(&client, base, table).await?);
+    }
+
+    let content = match args.format {
+        ExportFormat::Json => serde_json::to_string_pretty(&rows)?,
+        ExportFormat::Csv => to_csv(&rows),
+        ExportFormat::Jsonl => to_jsonl(&rows),
+    };
+    std::fs::write(&args.out, content)
+        .map_err(|e| anyhow::anyhow!("failed to write {}: {}", args.out.display(), e))?;
+    println!("exported {} rows to {}", rows.len(), args.out.display());
+    Ok(())
+}
@@ -0,0 +1,1072 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use aya::maps::lpm_trie::{Key, LpmTrie};
+use aya::maps::{Array, HashMap as AyaHashMap, MapData};
+use aya::Ebpf;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::{
+    FirewallIfacePolicy, FirewallRuleEntry, FirewallRuleStats, FIREWALL_ACTION_ALLOW, FIREWALL_ACTION_DENY,
+    FIREWALL_ACTION_LOG, FIREWALL_ACTION_RATELIMIT, FIREWALL_DIRECTION_ANY,
+    FIREWALL_ALLOWLIST_MAX, FIREWALL_DIRECTION_EGRESS, FIREWALL_DIRECTION_INGRESS,
+    FIREWALL_MAX_RULES, TcpScanStats, TCP_SCAN_COUNT, TCP_SCAN_DROP,
+};
+
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallAction {
+    Allow,
+    Deny,
+    // 记录日志后继续匹配后面的规则
+    Log,
+    // 未超过 rate_pps 时放行，超过时丢弃
+    Ratelimit,
+}
+
+// ingress 在 XDP 程序中匹配收到的包，egress 在 tc 程序中匹配发出的包
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Any,
+    Ingress,
+    Egress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+// 端口或端口范围，例如 22 或 "8000-8100"
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum PortMatch {
+    Port(u16),
+    Range(String),
+}
+
+impl PortMatch {
+    fn range(&self) -> Result<(u16, u16), String> {
+        match self {
+            PortMatch::Port(port) => Ok((*port, *port)),
+            PortMatch::Range(range) => {
+                let (min, max) = range.split_once('-').unwrap_or((range, range));
+                let parse = |s: &str| {
+                    s.trim()
+                        .parse::<u16>()
+                        .map_err(|_| format!("invalid port range {}", range))
+                };
+                let (min, max) = (parse(min)?, parse(max)?);
+                if min > max {
+                    return Err(format!("invalid port range {}", range));
+                }
+                Ok((min, max))
+            }
+        }
+    }
+}
+
+// 防火墙规则，未指定的匹配条件表示不限；按 priority 从小到大匹配，相同时按规则ID
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FirewallRule {
+    #[serde(default)]
+    pub priority: u32,
+    // 源网段，例如 10.0.0.0/8，也可以是单个IP
+    pub src: Option<String>,
+    pub dst: Option<String>,
+    pub src_port: Option<PortMatch>,
+    pub dst_port: Option<PortMatch>,
+    // 指定端口时只匹配 TCP 和 UDP
+    pub protocol: Option<FirewallProtocol>,
+    #[serde(default)]
+    pub direction: Direction,
+    pub action: FirewallAction,
+    // ratelimit 允许的每秒包数
+    pub rate_pps: Option<u32>,
+}
+
+// 解析 CIDR，返回网络字节序的 (网段, 掩码)
+pub(crate) fn parse_cidr(cidr: &str) -> Result<(u32, u32), String> {
+    let (ip, prefix) = cidr.split_once('/').unwrap_or((cidr, "32"));
+    let ip: Ipv4Addr = ip
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid cidr {}", cidr))?;
+    let prefix: u32 = prefix
+        .trim()
+        .parse()
+        .ok()
+        .filter(|prefix| *prefix <= 32)
+        .ok_or_else(|| format!("invalid cidr {}", cidr))?;
+    let mask = if prefix == 0 {
+        0
+    } else {
+        u32::MAX << (32 - prefix)
+    };
+    Ok(((u32::from(ip) & mask).to_be(), mask.to_be()))
+}
+
+impl FirewallRule {
+    fn entry(&self, id: u32) -> Result<FirewallRuleEntry, String> {
+        let (src_addr, src_mask) = self
+            .src
+            .as_deref()
+            .map(parse_cidr)
+            .transpose()?
+            .unwrap_or((0, 0));
+        let (dst_addr, dst_mask) = self
+            .dst
+            .as_deref()
+            .map(parse_cidr)
+            .transpose()?
+            .unwrap_or((0, 0));
+        let (src_port_min, src_port_max) = self
+            .src_port
+            .as_ref()
+            .map(PortMatch::range)
+            .transpose()?
+            .unwrap_or((0, u16::MAX));
+        let (dst_port_min, dst_port_max) = self
+            .dst_port
+            .as_ref()
+            .map(PortMatch::range)
+            .transpose()?
+            .unwrap_or((0, u16::MAX));
+        if self.protocol == Some(FirewallProtocol::Icmp)
+            && (self.src_port.is_some() || self.dst_port.is_some())
+        {
+            return Err("ports can not be used with icmp".to_string());
+        }
+        let rate_pps = match (self.action, self.rate_pps) {
+            (FirewallAction::Ratelimit, Some(rate)) if rate > 0 => rate,
+            (FirewallAction::Ratelimit, _) => {
+                return Err("rate_pps is required for ratelimit".to_string())
+            }
+            _ => 0,
+        };
+
+        Ok(FirewallRuleEntry {
+            id,
+            src_addr,
+            src_mask,
+            dst_addr,
+            dst_mask,
+            src_port_min,
+            src_port_max,
+            dst_port_min,
+            dst_port_max,
+            protocol: match self.protocol {
+                Some(FirewallProtocol::Tcp) => 6,
+                Some(FirewallProtocol::Udp) => 17,
+                Some(FirewallProtocol::Icmp) => 1,
+                None => 0,
+            },
+            direction: match self.direction {
+                Direction::Any => FIREWALL_DIRECTION_ANY,
+                Direction::Ingress => FIREWALL_DIRECTION_INGRESS,
+                Direction::Egress => FIREWALL_DIRECTION_EGRESS,
+            },
+            action: match self.action {
+                FirewallAction::Allow => FIREWALL_ACTION_ALLOW,
+                FirewallAction::Deny => FIREWALL_ACTION_DENY,
+                FirewallAction::Log => FIREWALL_ACTION_LOG,
+                FirewallAction::Ratelimit => FIREWALL_ACTION_RATELIMIT,
+            },
+            reserved: 0,
+            rate_pps,
+        })
+    }
+}
+
+// 规则文件中的一条规则
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StoredRule {
+    pub id: u32,
+    #[serde(flatten)]
+    pub rule: FirewallRule,
+}
+
+lazy_static::lazy_static! {
+    // 规则ID -> (规则, 写入规则表的条目)
+    static ref FIREWALL_RULES: Mutex<BTreeMap<u32, (FirewallRule, FirewallRuleEntry)>> = Mutex::new(BTreeMap::new());
+    // 设置后每次修改规则都写入该文件
+    static ref RULES_FILE: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+    // 条目ID -> 信任名单条目
+    static ref ALLOWLIST: Mutex<BTreeMap<u32, AllowlistEntry>> = Mutex::new(BTreeMap::new());
+    // 设置后每次修改信任名单都写入该文件
+    static ref ALLOWLIST_FILE: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+    // 开启 RA guard 的设备名 -> ifindex
+    static ref RA_GUARD: Mutex<BTreeMap<String, u32>> = Mutex::new(BTreeMap::new());
+    // 设备名 -> 默认拒绝状态
+    static ref DEFAULT_DENY: Mutex<BTreeMap<String, DefaultDenyState>> = Mutex::new(BTreeMap::new());
+}
+
+// 默认拒绝的确认等待时间
+const DEFAULT_CONFIRM_TIMEOUT_SECS: u64 = 60;
+
+// 每次修改默认拒绝状态递增，超时任务据此判断修改是否已被确认或覆盖
+static DEFAULT_DENY_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// 按匹配顺序排列的规则ID
+fn ordered(rules: &BTreeMap<u32, (FirewallRule, FirewallRuleEntry)>) -> Vec<u32> {
+    let mut ids: Vec<u32> = rules.keys().copied().collect();
+    ids.sort_by_key(|id| (rules[id].0.priority, *id));
+    ids
+}
+
+fn config_map(ebpf: &mut Ebpf) -> Result<Array<&mut MapData, u32>, anyhow::Error> {
+    Ok(Array::try_from(
+        ebpf.map_mut("firewall_config")
+            .ok_or_else(|| anyhow::anyhow!("firewall_config map not found"))?,
+    )?)
+}
+
+// 把规则按顺序写入未生效的一半规则表，再切换生效的一半
+fn sync(
+    ebpf: &mut Ebpf,
+    rules: &BTreeMap<u32, (FirewallRule, FirewallRuleEntry)>,
+) -> Result<(), anyhow::Error> {
+    let inactive = (config_map(ebpf)?.get(&0, 0)? & 1) ^ 1;
+
+    let mut table = Array::<&mut MapData, FirewallRuleEntry>::try_from(
+        ebpf.map_mut("firewall_rules")
+            .ok_or_else(|| anyhow::anyhow!("firewall_rules map not found"))?,
+    )?;
+    let ids = ordered(rules);
+    for (i, id) in ids.iter().enumerate() {
+        table.set(inactive * FIREWALL_MAX_RULES + i as u32, rules[id].1, 0)?;
+    }
+
+    let mut config = config_map(ebpf)?;
+    config.set(1 + inactive, ids.len() as u32, 0)?;
+    config.set(0, inactive, 0)?;
+    Ok(())
+}
+
+fn stored(rules: &BTreeMap<u32, (FirewallRule, FirewallRuleEntry)>) -> Vec<StoredRule> {
+    rules
+        .iter()
+        .map(|(id, (rule, _))| StoredRule {
+            id: *id,
+            rule: rule.clone(),
+        })
+        .collect()
+}
+
+// 写入规则文件，未设置规则文件时不保存
+fn save(rules: &BTreeMap<u32, (FirewallRule, FirewallRuleEntry)>) -> Result<(), anyhow::Error> {
+    let Some(path) = RULES_FILE.lock().unwrap().clone() else {
+        return Ok(());
+    };
+    crate::rules::write_file(&path, &serde_json::to_string_pretty(&stored(rules))?)
+}
+
+// 校验一组规则，规则ID不能重复
+pub(crate) fn validate(
+    stored: Vec<StoredRule>,
+) -> Result<Vec<(u32, FirewallRule, FirewallRuleEntry)>, String> {
+    let mut seen = std::collections::BTreeSet::new();
+    stored
+        .into_iter()
+        .map(|StoredRule { id, rule }| {
+            if id >= FIREWALL_MAX_RULES {
+                return Err(format!("firewall rule id {} out of range", id));
+            }
+            if !seen.insert(id) {
+                return Err(format!("duplicate firewall rule id {}", id));
+            }
+            let entry = rule
+                .entry(id)
+                .map_err(|e| format!("firewall rule {}: {}", id, e))?;
+            Ok((id, rule, entry))
+        })
+        .collect()
+}
+
+// 读取并校验规则文件，文件不存在时为空
+pub(crate) fn read_rules(path: &Path) -> Result<Vec<(u32, FirewallRule, FirewallRuleEntry)>, anyhow::Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let stored: Vec<StoredRule> = serde_json::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?;
+    validate(stored).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+// 从规则文件加载规则并写入规则表，文件不存在时从空规则开始
+pub async fn load(ebpf_manager: &EbpfManager, path: &Path) -> Result<(), anyhow::Error> {
+    *RULES_FILE.lock().unwrap() = Some(path.to_path_buf());
+    let stored = read_rules(path)?;
+    let mut rules = FIREWALL_RULES.lock().await;
+    for (id, rule, entry) in stored {
+        rules.insert(id, (rule, entry));
+    }
+    sync(&mut *ebpf_manager.ebpf.lock().await, &rules)?;
+    info!("从 {} 加载了 {} 条防火墙规则", path.display(), rules.len());
+    Ok(())
+}
+
+// 规则及其命中统计，last_hit 为 unix 时间戳(秒)，从未命中的规则为 null
+fn rule_json(id: u32, rule: &FirewallRule, stats: &FirewallRuleStats, offset_ns: u64) -> Value {
+    let mut value = serde_json::json!(rule);
+    value["id"] = id.into();
+    value["hits"] = serde_json::json!({
+        "packets": stats.packets,
+        "bytes": stats.bytes,
+        "dropped": stats.dropped,
+        "last_hit": (stats.last_hit_ns != 0).then(|| (stats.last_hit_ns + offset_ns) / 1_000_000_000),
+    });
+    value
+}
+
+pub(crate) fn rule_stats(maps: &MapCache, id: u32) -> Result<FirewallRuleStats, anyhow::Error> {
+    let map = Array::<&MapData, FirewallRuleStats>::try_from(
+        maps.map("firewall_rule_stats")
+            .ok_or_else(|| anyhow::anyhow!("firewall_rule_stats map not found"))?,
+    )?;
+    Ok(map.get(&id, 0)?)
+}
+
+// 按匹配顺序列出所有防火墙规则及命中统计，信任名单先于规则生效，排在最前
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let mut values = allowlist(ebpf_manager).await?;
+    let rules = FIREWALL_RULES.lock().await;
+    let maps = ebpf_manager.maps();
+    let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+    for id in ordered(&rules) {
+        values.push(rule_json(
+            id,
+            &rules[&id].0,
+            &rule_stats(&maps, id)?,
+            offset_ns,
+        ));
+    }
+    Ok(values)
+}
+
+pub async fn get(ebpf_manager: &EbpfManager, id: u32) -> Result<Option<Value>, anyhow::Error> {
+    let rules = FIREWALL_RULES.lock().await;
+    let Some((rule, _)) = rules.get(&id) else {
+        return Ok(None);
+    };
+    let stats = rule_stats(&ebpf_manager.maps(), id)?;
+    let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+    Ok(Some(rule_json(id, rule, &stats, offset_ns)))
+}
+
+// 新增防火墙规则，返回规则ID
+pub async fn create(
+    ebpf_manager: &EbpfManager,
+    rule: FirewallRule,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    let mut rules = FIREWALL_RULES.lock().await;
+    let Some(id) = (0..FIREWALL_MAX_RULES).find(|id| !rules.contains_key(id)) else {
+        return Ok(Err(format!(
+            "at most {} firewall rules",
+            FIREWALL_MAX_RULES
+        )));
+    };
+    if let Err(e) = store(ebpf_manager, &mut rules, id, rule).await? {
+        return Ok(Err(e));
+    }
+    crate::events::rule_change("firewall", id as u64, "create");
+    Ok(Ok(id))
+}
+
+// 一次新增多条防火墙规则，任何一条不合法或超出规则数上限时都不新增，返回各规则的ID
+pub async fn create_all(
+    ebpf_manager: &EbpfManager,
+    new: Vec<FirewallRule>,
+) -> Result<Result<Vec<u32>, String>, anyhow::Error> {
+    let mut rules = FIREWALL_RULES.lock().await;
+    let free: Vec<u32> = (0..FIREWALL_MAX_RULES)
+        .filter(|id| !rules.contains_key(id))
+        .take(new.len())
+        .collect();
+    if free.len() < new.len() {
+        return Ok(Err(format!(
+            "at most {} firewall rules",
+            FIREWALL_MAX_RULES
+        )));
+    }
+    let mut entries = Vec::with_capacity(new.len());
+    for (rule, id) in new.into_iter().zip(&free) {
+        match rule.entry(*id) {
+            Ok(entry) => entries.push((*id, rule, entry)),
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    {
+        let mut stats = Array::<&mut MapData, FirewallRuleStats>::try_from(
+            ebpf.map_mut("firewall_rule_stats")
+                .ok_or_else(|| anyhow::anyhow!("firewall_rule_stats map not found"))?,
+        )?;
+        for id in &free {
+            stats.set(
+                *id,
+                FirewallRuleStats {
+                    packets: 0,
+                    bytes: 0,
+                    last_hit_ns: 0,
+                    dropped: 0,
+                },
+                0,
+            )?;
+        }
+    }
+    for (id, rule, entry) in entries {
+        rules.insert(id, (rule, entry));
+    }
+    sync(&mut ebpf, &rules)?;
+    save(&rules)?;
+    info!("新增了 {} 条防火墙规则", free.len());
+    for id in &free {
+        crate::events::rule_change("firewall", *id as u64, "create");
+    }
+    Ok(Ok(free))
+}
+
+// 替换已有的防火墙规则，返回替换前的规则，规则不存在时返回 None
+pub async fn replace(
+    ebpf_manager: &EbpfManager,
+    id: u32,
+    rule: FirewallRule,
+) -> Result<Result<Option<FirewallRule>, String>, anyhow::Error> {
+    let mut rules = FIREWALL_RULES.lock().await;
+    let Some(previous) = rules.get(&id).map(|(rule, _)| rule.clone()) else {
+        return Ok(Ok(None));
+    };
+    if let Err(e) = store(ebpf_manager, &mut rules, id, rule).await? {
+        return Ok(Err(e));
+    }
+    crate::events::rule_change("firewall", id as u64, "update");
+    Ok(Ok(Some(previous)))
+}
+
+async fn store(
+    ebpf_manager: &EbpfManager,
+    rules: &mut BTreeMap<u32, (FirewallRule, FirewallRuleEntry)>,
+    id: u32,
+    rule: FirewallRule,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let entry = match rule.entry(id) {
+        Ok(entry) => entry,
+        Err(e) => return Ok(Err(e)),
+    };
+    info!(
+        rule_id = id,
+        action = ?rule.action,
+        "防火墙规则 {}: priority={} {:?} {:?} src={:?}:{:?} dst={:?}:{:?} -> {:?}",
+        id,
+        rule.priority,
+        rule.direction,
+        rule.protocol,
+        rule.src,
+        rule.src_port,
+        rule.dst,
+        rule.dst_port,
+        rule.action
+    );
+    rules.insert(id, (rule, entry));
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    // 新增或修改的规则从0开始计数
+    let mut stats = Array::<&mut MapData, FirewallRuleStats>::try_from(
+        ebpf.map_mut("firewall_rule_stats")
+            .ok_or_else(|| anyhow::anyhow!("firewall_rule_stats map not found"))?,
+    )?;
+    let zero = FirewallRuleStats {
+        packets: 0,
+        bytes: 0,
+        last_hit_ns: 0,
+        dropped: 0,
+    };
+    stats.set(id, zero, 0)?;
+    sync(&mut ebpf, rules)?;
+    save(rules)?;
+    Ok(Ok(()))
+}
+
+// 撤销灰度发布的规则: 规则ID处仍是 applied 时恢复为 previous，previous 为 None 时删除，
+// 观测期内规则已被删除或修改(包括ID被新规则复用)时不做改动并返回错误
+pub async fn restore(
+    ebpf_manager: &EbpfManager,
+    id: u32,
+    applied: &FirewallRule,
+    previous: Option<FirewallRule>,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let mut rules = FIREWALL_RULES.lock().await;
+    if rules.get(&id).map(|(rule, _)| rule) != Some(applied) {
+        return Ok(Err(format!(
+            "firewall rule {} was changed or removed during the canary, rollback skipped",
+            id
+        )));
+    }
+    match previous {
+        Some(rule) => {
+            if let Err(e) = store(ebpf_manager, &mut rules, id, rule).await? {
+                return Ok(Err(e));
+            }
+            crate::events::rule_change("firewall", id as u64, "update");
+        }
+        None => {
+            rules.remove(&id);
+            sync(&mut *ebpf_manager.ebpf.lock().await, &rules)?;
+            save(&rules)?;
+            info!("防火墙规则 {} 已删除", id);
+            crate::events::rule_change("firewall", id as u64, "delete");
+        }
+    }
+    Ok(Ok(()))
+}
+
+// 删除防火墙规则，规则不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut rules = FIREWALL_RULES.lock().await;
+    if rules.remove(&id).is_none() {
+        return Ok(false);
+    }
+    sync(&mut *ebpf_manager.ebpf.lock().await, &rules)?;
+    save(&rules)?;
+    info!("防火墙规则 {} 已删除", id);
+    crate::events::rule_change("firewall", id as u64, "delete");
+    Ok(true)
+}
+
+// 当前的全部规则，格式与规则文件相同
+pub async fn export() -> Vec<StoredRule> {
+    stored(&*FIREWALL_RULES.lock().await)
+}
+
+// 用已校验的规则整体替换现有规则，新规则表写入未生效的一半后一次切换，命中统计从0开始
+pub async fn replace_all(
+    ebpf_manager: &EbpfManager,
+    validated: Vec<(u32, FirewallRule, FirewallRuleEntry)>,
+) -> Result<(), anyhow::Error> {
+    let mut rules = FIREWALL_RULES.lock().await;
+    let new: BTreeMap<u32, (FirewallRule, FirewallRuleEntry)> = validated
+        .into_iter()
+        .map(|(id, rule, entry)| (id, (rule, entry)))
+        .collect();
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    {
+        let mut stats = Array::<&mut MapData, FirewallRuleStats>::try_from(
+            ebpf.map_mut("firewall_rule_stats")
+                .ok_or_else(|| anyhow::anyhow!("firewall_rule_stats map not found"))?,
+        )?;
+        for id in new.keys() {
+            stats.set(
+                *id,
+                FirewallRuleStats {
+                    packets: 0,
+                    bytes: 0,
+                    last_hit_ns: 0,
+                    dropped: 0,
+                },
+                0,
+            )?;
+        }
+    }
+    sync(&mut ebpf, &new)?;
+    *rules = new;
+    save(&rules)?;
+    info!("防火墙规则已整体替换为 {} 条", rules.len());
+    crate::events::rules_replaced("firewall", rules.len());
+    Ok(())
+}
+
+// 信任名单中的网段，源地址(入方向)或目的地址(出方向)在其中的包跳过
+// bogon、黑名单、防火墙规则(含限速)、默认拒绝和信誉分拦截
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AllowlistEntry {
+    pub cidr: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+// 信任名单文件中的一个条目
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredAllowlistEntry {
+    id: u32,
+    #[serde(flatten)]
+    entry: AllowlistEntry,
+}
+
+// 返回网络字节序的网段和前缀长度
+fn allowlist_prefix(cidr: &str) -> Result<(u32, u32), String> {
+    let (addr, mask) = parse_cidr(cidr)?;
+    Ok((addr, u32::from_be(mask).count_ones()))
+}
+
+fn allowlist_map(ebpf: &mut Ebpf) -> Result<LpmTrie<&mut MapData, u32, u32>, anyhow::Error> {
+    Ok(LpmTrie::try_from(
+        ebpf.map_mut("firewall_allowlist")
+            .ok_or_else(|| anyhow::anyhow!("firewall_allowlist map not found"))?,
+    )?)
+}
+
+// 写入信任名单并从0开始计数
+fn insert_allowlist(ebpf: &mut Ebpf, id: u32, (addr, len): (u32, u32)) -> Result<(), anyhow::Error> {
+    let mut stats = Array::<&mut MapData, FirewallRuleStats>::try_from(
+        ebpf.map_mut("firewall_allowlist_stats")
+            .ok_or_else(|| anyhow::anyhow!("firewall_allowlist_stats map not found"))?,
+    )?;
+    let zero = FirewallRuleStats {
+        packets: 0,
+        bytes: 0,
+        last_hit_ns: 0,
+        dropped: 0,
+    };
+    stats.set(id, zero, 0)?;
+    allowlist_map(ebpf)?.insert(&Key::new(len, addr), id, 0)?;
+    Ok(())
+}
+
+// 写入信任名单文件，未设置文件时不保存
+fn save_allowlist(entries: &BTreeMap<u32, AllowlistEntry>) -> Result<(), anyhow::Error> {
+    let Some(path) = ALLOWLIST_FILE.lock().unwrap().clone() else {
+        return Ok(());
+    };
+    let stored: Vec<StoredAllowlistEntry> = entries
+        .iter()
+        .map(|(id, entry)| StoredAllowlistEntry {
+            id: *id,
+            entry: entry.clone(),
+        })
+        .collect();
+    std::fs::write(&path, serde_json::to_string_pretty(&stored)?)
+        .map_err(|e| anyhow::anyhow!("failed to write {}: {}", path.display(), e))
+}
+
+// 条目ID、条目及网络字节序的网段和前缀长度
+pub(crate) type LoadedAllowlistEntry = (u32, AllowlistEntry, (u32, u32));
+
+// 读取并校验信任名单文件，文件不存在时为空
+pub(crate) fn read_allowlist(path: &Path) -> Result<Vec<LoadedAllowlistEntry>, anyhow::Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let stored: Vec<StoredAllowlistEntry> = serde_json::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?;
+    stored
+        .into_iter()
+        .map(|StoredAllowlistEntry { id, entry }| {
+            if id >= FIREWALL_ALLOWLIST_MAX {
+                return Err(anyhow::anyhow!("allowlist entry id {} out of range", id));
+            }
+            let prefix = allowlist_prefix(&entry.cidr)
+                .map_err(|e| anyhow::anyhow!("allowlist entry {}: {}", id, e))?;
+            Ok((id, entry, prefix))
+        })
+        .collect()
+}
+
+// 从信任名单文件加载条目，文件不存在时从空名单开始
+pub async fn load_allowlist(ebpf_manager: &EbpfManager, path: &Path) -> Result<(), anyhow::Error> {
+    *ALLOWLIST_FILE.lock().unwrap() = Some(path.to_path_buf());
+    let stored = read_allowlist(path)?;
+    let mut entries = ALLOWLIST.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    for (id, entry, prefix) in stored {
+        insert_allowlist(&mut ebpf, id, prefix)?;
+        entries.insert(id, entry);
+    }
+    info!("从 {} 加载了 {} 条信任名单", path.display(), entries.len());
+    Ok(())
+}
+
+// 列出信任名单及放行的包数、字节数和最近放行时间
+pub async fn allowlist(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let entries = ALLOWLIST.lock().await;
+    let maps = ebpf_manager.maps();
+    let stats = Array::<&MapData, FirewallRuleStats>::try_from(
+        maps.map("firewall_allowlist_stats")
+            .ok_or_else(|| anyhow::anyhow!("firewall_allowlist_stats map not found"))?,
+    )?;
+    let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+    entries
+        .iter()
+        .map(|(id, entry)| {
+            let stats = stats.get(id, 0)?;
+            Ok(serde_json::json!({
+                "id": id,
+                "allowlist": true,
+                "cidr": entry.cidr,
+                "description": entry.description,
+                "action": "bypass",
+                "hits": {
+                    "packets": stats.packets,
+                    "bytes": stats.bytes,
+                    "last_hit": (stats.last_hit_ns != 0).then(|| (stats.last_hit_ns + offset_ns) / 1_000_000_000),
+                },
+            }))
+        })
+        .collect()
+}
+
+// 新增信任名单条目，返回条目ID
+pub async fn add_allowlist(
+    ebpf_manager: &EbpfManager,
+    entry: AllowlistEntry,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    let prefix = match allowlist_prefix(&entry.cidr) {
+        Ok(prefix) => prefix,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mut entries = ALLOWLIST.lock().await;
+    if let Some((id, _)) = entries
+        .iter()
+        .find(|(_, e)| allowlist_prefix(&e.cidr) == Ok(prefix))
+    {
+        return Ok(Err(format!("{} is already allowlisted as {}", entry.cidr, id)));
+    }
+    let Some(id) = (0..FIREWALL_ALLOWLIST_MAX).find(|id| !entries.contains_key(id)) else {
+        return Ok(Err(format!(
+            "at most {} allowlist entries",
+            FIREWALL_ALLOWLIST_MAX
+        )));
+    };
+    insert_allowlist(&mut *ebpf_manager.ebpf.lock().await, id, prefix)?;
+    info!("信任名单 {}: {} {}", id, entry.cidr, entry.description);
+    entries.insert(id, entry);
+    save_allowlist(&entries)?;
+    crate::events::rule_change("allowlist", id as u64, "create");
+    Ok(Ok(id))
+}
+
+// 删除信任名单条目，条目不存在时返回 false
+pub async fn remove_allowlist(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut entries = ALLOWLIST.lock().await;
+    let Some(entry) = entries.remove(&id) else {
+        return Ok(false);
+    };
+    let (addr, len) = allowlist_prefix(&entry.cidr).map_err(anyhow::Error::msg)?;
+    allowlist_map(&mut *ebpf_manager.ebpf.lock().await)?.remove(&Key::new(len, addr))?;
+    save_allowlist(&entries)?;
+    info!("信任名单 {} ({}) 已删除", id, entry.cidr);
+    crate::events::rule_change("allowlist", id as u64, "delete");
+    Ok(true)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RaGuardRequest {
+    pub iface: String,
+    pub enabled: bool,
+}
+
+fn ra_guard_map(ebpf: &mut Ebpf) -> Result<AyaHashMap<&mut MapData, u32, u64>, anyhow::Error> {
+    Ok(AyaHashMap::try_from(
+        ebpf.map_mut("ra_guard")
+            .ok_or_else(|| anyhow::anyhow!("ra_guard map not found"))?,
+    )?)
+}
+
+// 列出开启 RA guard 的设备及丢弃的 Router Advertisement 数
+pub async fn ra_guard_list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let ifaces = RA_GUARD.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, u32, u64>::try_from(
+        maps.map("ra_guard")
+            .ok_or_else(|| anyhow::anyhow!("ra_guard map not found"))?,
+    )?;
+    Ok(ifaces
+        .iter()
+        .map(|(iface, ifindex)| {
+            serde_json::json!({
+                "iface": iface,
+                "ifindex": ifindex,
+                "dropped": map.get(ifindex, 0).unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+// 开启或关闭设备的 RA guard，关闭时丢弃计数清零
+pub async fn set_ra_guard(
+    ebpf_manager: &EbpfManager,
+    request: RaGuardRequest,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let ifindex = match std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", request.iface))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+    {
+        Some(ifindex) => ifindex,
+        None => return Ok(Err(format!("Interface {} does not exist", request.iface))),
+    };
+
+    let mut ifaces = RA_GUARD.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = ra_guard_map(&mut ebpf)?;
+    if request.enabled {
+        if map.get(&ifindex, 0).is_err() {
+            map.insert(ifindex, 0, 0)?;
+        }
+        ifaces.insert(request.iface.clone(), ifindex);
+    } else {
+        let _ = map.remove(&ifindex);
+        ifaces.remove(&request.iface);
+    }
+    info!("设备 {} RA guard: {}", request.iface, request.enabled);
+    Ok(Ok(()))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TcpScanRequest {
+    // 丢弃 NULL、FIN、XMAS 扫描包，否则只计数
+    pub drop: bool,
+}
+
+fn tcp_scan_drop(maps: &MapCache) -> Result<bool, anyhow::Error> {
+    let config = Array::<&MapData, u32>::try_from(
+        maps.map("tcp_scan_config")
+            .ok_or_else(|| anyhow::anyhow!("tcp_scan_config map not found"))?,
+    )?;
+    Ok(config.get(&0, 0)? == TCP_SCAN_DROP)
+}
+
+// 非法 TCP 标志组合的处理方式及各类扫描包的总数
+pub fn tcp_scan_status(maps: &MapCache) -> Result<Value, anyhow::Error> {
+    let sources = tcp_scan_sources(maps)?;
+    let total = |kind: &str| -> u64 { sources.iter().filter_map(|s| s[kind].as_u64()).sum() };
+    Ok(serde_json::json!({
+        "drop": tcp_scan_drop(maps)?,
+        "sources": sources.len(),
+        "null": total("null"),
+        "fin": total("fin"),
+        "xmas": total("xmas"),
+    }))
+}
+
+// 发出非法 TCP 标志组合的源地址，按扫描包数排序
+pub fn tcp_scan_sources(maps: &MapCache) -> Result<Vec<Value>, anyhow::Error> {
+    let map = AyaHashMap::<&MapData, u32, TcpScanStats>::try_from(
+        maps.map("tcp_scan_sources")
+            .ok_or_else(|| anyhow::anyhow!("tcp_scan_sources map not found"))?,
+    )?;
+    let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+    let mut sources: Vec<(u32, TcpScanStats)> = map.iter().filter_map(|entry| entry.ok()).collect();
+    sources.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.null + stats.fin + stats.xmas));
+    Ok(sources
+        .into_iter()
+        .map(|(addr, stats)| {
+            serde_json::json!({
+                "ip": Ipv4Addr::from(u32::from_be(addr)),
+                "null": stats.null,
+                "fin": stats.fin,
+                "xmas": stats.xmas,
+                "last_seen": (stats.last_seen_ns + offset_ns) / 1_000_000_000,
+                "last_dst_port": stats.last_dst_port,
+                "last_flags": stats.last_flags,
+            })
+        })
+        .collect())
+}
+
+// 设置非法 TCP 标志组合只计数还是丢弃，对所有挂载了XDP防火墙的设备生效
+pub async fn set_tcp_scan(
+    ebpf_manager: &EbpfManager,
+    request: TcpScanRequest,
+) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut config = Array::<&mut MapData, u32>::try_from(
+        ebpf.map_mut("tcp_scan_config")
+            .ok_or_else(|| anyhow::anyhow!("tcp_scan_config map not found"))?,
+    )?;
+    config.set(0, if request.drop { TCP_SCAN_DROP } else { TCP_SCAN_COUNT }, 0)?;
+    info!("非法 TCP 标志组合: {}", if request.drop { "丢弃" } else { "只计数" });
+    Ok(())
+}
+
+// 开启默认拒绝后等待确认的修改
+struct PendingConfirm {
+    generation: u64,
+    deadline: Instant,
+    // 超时未确认时恢复的状态
+    revert_to: bool,
+}
+
+struct DefaultDenyState {
+    ifindex: u32,
+    enabled: bool,
+    pending: Option<PendingConfirm>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DefaultDenyRequest {
+    pub iface: String,
+    pub enabled: bool,
+    // 开启后需在该时间内确认，否则恢复原状态，默认60秒，0 表示不需要确认
+    pub confirm_timeout_secs: Option<u64>,
+}
+
+fn iface_policy_map(
+    ebpf: &mut Ebpf,
+) -> Result<AyaHashMap<&mut MapData, u32, FirewallIfacePolicy>, anyhow::Error> {
+    Ok(AyaHashMap::try_from(
+        ebpf.map_mut("firewall_iface_policy")
+            .ok_or_else(|| anyhow::anyhow!("firewall_iface_policy map not found"))?,
+    )?)
+}
+
+// 写入设备的默认策略，保留已丢弃的包数
+fn write_policy(ebpf: &mut Ebpf, ifindex: u32, enabled: bool) -> Result<(), anyhow::Error> {
+    let mut map = iface_policy_map(ebpf)?;
+    let dropped = map.get(&ifindex, 0).map(|p| p.dropped).unwrap_or(0);
+    let policy = FirewallIfacePolicy {
+        default_deny: enabled as u32,
+        reserved: 0,
+        dropped,
+    };
+    map.insert(ifindex, policy, 0)?;
+    Ok(())
+}
+
+fn default_deny_json(iface: &str, state: &DefaultDenyState, dropped: u64) -> Value {
+    serde_json::json!({
+        "iface": iface,
+        "ifindex": state.ifindex,
+        "default_deny": state.enabled,
+        "dropped": dropped,
+        "pending_confirm_secs": state
+            .pending
+            .as_ref()
+            .map(|p| p.deadline.saturating_duration_since(Instant::now()).as_secs()),
+    })
+}
+
+// 列出配置过默认策略的设备
+pub async fn default_deny_list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let states = DEFAULT_DENY.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, u32, FirewallIfacePolicy>::try_from(
+        maps.map("firewall_iface_policy")
+            .ok_or_else(|| anyhow::anyhow!("firewall_iface_policy map not found"))?,
+    )?;
+    Ok(states
+        .iter()
+        .map(|(iface, state)| {
+            let dropped = map.get(&state.ifindex, 0).map(|p| p.dropped).unwrap_or(0);
+            default_deny_json(iface, state, dropped)
+        })
+        .collect())
+}
+
+// 开启或关闭设备的默认拒绝，开启后未在 confirm_timeout_secs 内确认时自动恢复，避免把自己锁在外面
+pub async fn set_default_deny(
+    ebpf_manager: Arc<EbpfManager>,
+    request: DefaultDenyRequest,
+) -> Result<Result<Value, String>, anyhow::Error> {
+    let ifindex = match std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", request.iface))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+    {
+        Some(ifindex) => ifindex,
+        None => return Ok(Err(format!("Interface {} does not exist", request.iface))),
+    };
+
+    let mut states = DEFAULT_DENY.lock().await;
+    // 尚未确认的开启以开启前的策略为准，重复开启时重新计时而不是视为已确认
+    let previous = states
+        .get(&request.iface)
+        .filter(|state| state.ifindex == ifindex)
+        .map(|state| match &state.pending {
+            Some(pending) => pending.revert_to,
+            None => state.enabled,
+        })
+        .unwrap_or(false);
+    write_policy(&mut *ebpf_manager.ebpf.lock().await, ifindex, request.enabled)?;
+
+    let generation = DEFAULT_DENY_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+    let timeout = request
+        .confirm_timeout_secs
+        .unwrap_or(DEFAULT_CONFIRM_TIMEOUT_SECS);
+    let pending = (request.enabled && !previous && timeout > 0).then(|| PendingConfirm {
+        generation,
+        deadline: Instant::now() + Duration::from_secs(timeout),
+        revert_to: previous,
+    });
+    if pending.is_some() {
+        let ebpf_manager = ebpf_manager.clone();
+        let iface = request.iface.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(timeout)).await;
+            if let Err(e) = revert_unconfirmed(&ebpf_manager, &iface, generation).await {
+                warn!("恢复设备 {} 的默认策略失败: {}", iface, e);
+            }
+        });
+    }
+    info!(
+        "设备 {} 默认拒绝: {} (确认超时 {:?}s)",
+        request.iface,
+        request.enabled,
+        pending.as_ref().map(|_| timeout)
+    );
+    crate::events::iface_rule_change("default_deny", &request.iface, "update");
+
+    let state = DefaultDenyState {
+        ifindex,
+        enabled: request.enabled,
+        pending,
+    };
+    let dropped = iface_policy_map(&mut *ebpf_manager.ebpf.lock().await)?
+        .get(&ifindex, 0)
+        .map(|p| p.dropped)
+        .unwrap_or(0);
+    let value = default_deny_json(&request.iface, &state, dropped);
+    states.insert(request.iface, state);
+    Ok(Ok(value))
+}
+
+async fn revert_unconfirmed(
+    ebpf_manager: &EbpfManager,
+    iface: &str,
+    generation: u64,
+) -> Result<(), anyhow::Error> {
+    let mut states = DEFAULT_DENY.lock().await;
+    let Some(state) = states.get_mut(iface) else {
+        return Ok(());
+    };
+    let revert_to = match &state.pending {
+        Some(pending) if pending.generation == generation => pending.revert_to,
+        _ => return Ok(()),
+    };
+    write_policy(&mut *ebpf_manager.ebpf.lock().await, state.ifindex, revert_to)?;
+    state.enabled = revert_to;
+    state.pending = None;
+    warn!("设备 {} 的默认拒绝未在超时前确认，已恢复", iface);
+    crate::events::iface_rule_change("default_deny", iface, "update");
+    Ok(())
+}
+
+// 确认设备的默认拒绝修改，没有等待确认的修改时返回 false
+pub async fn confirm_default_deny(iface: &str) -> bool {
+    let mut states = DEFAULT_DENY.lock().await;
+    let confirmed = states
+        .get_mut(iface)
+        .and_then(|state| state.pending.take())
+        .is_some();
+    if confirmed {
+        info!("设备 {} 的默认拒绝已确认", iface);
+    }
+    confirmed
+}
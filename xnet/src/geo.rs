@@ -0,0 +1,238 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use aya::maps::lpm_trie::{Key, LpmTrie};
+use aya::maps::{Array, MapData};
+use aya::Ebpf;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{GeoRule, GEO_MAX_EXCEPT_PORTS, GEO_MAX_RULES};
+
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+
+// 拒绝来自指定国家的入站流量，except_ports 中的目的端口放行
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GeoRuleConfig {
+    // ISO 3166-1 alpha-2 国家代码
+    pub countries: Vec<String>,
+    #[serde(default)]
+    pub except_ports: Vec<u16>,
+    #[serde(default)]
+    pub description: String,
+}
+
+struct Rule {
+    config: GeoRuleConfig,
+    // 网络字节序的网段和前缀长度
+    prefixes: BTreeSet<(u32, u32)>,
+}
+
+#[derive(Default)]
+struct GeoState {
+    // 每个国家一个 <国家代码>.zone 文件，每行一个 CIDR
+    dir: Option<PathBuf>,
+    // 规则编号即 geo_rules 的下标
+    rules: BTreeMap<u32, Rule>,
+}
+
+lazy_static::lazy_static! {
+    static ref GEO: Mutex<GeoState> = Mutex::new(GeoState::default());
+}
+
+// 启动时设置 GeoIP 数据目录
+pub async fn init(dir: &Path) -> Result<(), anyhow::Error> {
+    if !dir.is_dir() {
+        anyhow::bail!("GeoIP directory {} does not exist", dir.display());
+    }
+    GEO.lock().await.dir = Some(dir.to_path_buf());
+    Ok(())
+}
+
+// 读取国家的地址前缀，# 开头的行及行尾内容为注释
+fn load_country(dir: &Path, country: &str) -> Result<BTreeSet<(u32, u32)>, String> {
+    let path = dir.join(format!("{}.zone", country));
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| format!("no GeoIP data for {}: {}: {}", country, path.display(), e))?;
+    let mut prefixes = BTreeSet::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (addr, mask) =
+            crate::firewall::parse_cidr(line).map_err(|e| format!("{}: {}", path.display(), e))?;
+        prefixes.insert((addr, u32::from_be(mask).count_ones()));
+    }
+    Ok(prefixes)
+}
+
+fn validate(state: &GeoState, config: &mut GeoRuleConfig) -> Result<(), String> {
+    if config.countries.is_empty() {
+        return Err("countries must not be empty".to_string());
+    }
+    if config.except_ports.len() > GEO_MAX_EXCEPT_PORTS {
+        return Err(format!("at most {} except_ports", GEO_MAX_EXCEPT_PORTS));
+    }
+    if config.except_ports.contains(&0) {
+        return Err("except_ports must be between 1 and 65535".to_string());
+    }
+    for country in config.countries.iter_mut() {
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("invalid country code {}", country));
+        }
+        *country = country.to_ascii_lowercase();
+    }
+    // 同一国家只能属于一条规则，否则前缀的归属不确定
+    for (id, rule) in &state.rules {
+        if let Some(country) = config
+            .countries
+            .iter()
+            .find(|c| rule.config.countries.contains(c))
+        {
+            return Err(format!(
+                "country {} is already used by geo rule {}",
+                country, id
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn prefixes_map(ebpf: &mut Ebpf) -> Result<LpmTrie<&mut MapData, u32, u32>, anyhow::Error> {
+    Ok(LpmTrie::try_from(
+        ebpf.map_mut("geo_prefixes")
+            .ok_or_else(|| anyhow::anyhow!("geo_prefixes map not found"))?,
+    )?)
+}
+
+fn rules_map(ebpf: &mut Ebpf) -> Result<Array<&mut MapData, GeoRule>, anyhow::Error> {
+    Ok(Array::try_from(ebpf.map_mut("geo_rules").ok_or_else(
+        || anyhow::anyhow!("geo_rules map not found"),
+    )?)?)
+}
+
+fn stats_map(ebpf: &mut Ebpf) -> Result<Array<&mut MapData, u64>, anyhow::Error> {
+    Ok(Array::try_from(ebpf.map_mut("geo_stats").ok_or_else(
+        || anyhow::anyhow!("geo_stats map not found"),
+    )?)?)
+}
+
+// 删除前缀，其他国家的数据中也有该前缀时交给对应的规则
+fn release<'a>(
+    map: &mut LpmTrie<&mut MapData, u32, u32>,
+    rules: &BTreeMap<u32, Rule>,
+    prefixes: impl Iterator<Item = &'a (u32, u32)>,
+) -> Result<(), anyhow::Error> {
+    for prefix @ (addr, len) in prefixes {
+        let key = Key::new(*len, *addr);
+        match rules
+            .iter()
+            .find(|(_, other)| other.prefixes.contains(prefix))
+        {
+            Some((other, _)) => map.insert(&key, *other, 0)?,
+            None => {
+                let _ = map.remove(&key);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn entry(config: &GeoRuleConfig) -> GeoRule {
+    let mut rule = GeoRule {
+        except_ports: [0; GEO_MAX_EXCEPT_PORTS],
+    };
+    rule.except_ports[..config.except_ports.len()].copy_from_slice(&config.except_ports);
+    rule
+}
+
+// 规则列表及每条规则的前缀数和丢弃的包数
+pub async fn list(maps: &MapCache) -> Result<Vec<Value>, anyhow::Error> {
+    let state = GEO.lock().await;
+    let stats = Array::<&MapData, u64>::try_from(
+        maps.map("geo_stats")
+            .ok_or_else(|| anyhow::anyhow!("geo_stats map not found"))?,
+    )?;
+    Ok(state
+        .rules
+        .iter()
+        .map(|(id, rule)| {
+            serde_json::json!({
+                "id": id,
+                "countries": rule.config.countries,
+                "except_ports": rule.config.except_ports,
+                "description": rule.config.description,
+                "prefixes": rule.prefixes.len(),
+                "dropped": stats.get(id, 0).unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+// 新增规则，把国家的地址前缀同步到内核，返回规则编号
+pub async fn add(
+    ebpf_manager: &EbpfManager,
+    mut config: GeoRuleConfig,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    let mut state = GEO.lock().await;
+    let Some(dir) = state.dir.clone() else {
+        return Ok(Err(
+            "GeoIP data is not configured, start with --geoip-dir".to_string()
+        ));
+    };
+    if let Err(e) = validate(&state, &mut config) {
+        return Ok(Err(e));
+    }
+    let Some(id) = (0..GEO_MAX_RULES).find(|id| !state.rules.contains_key(id)) else {
+        return Ok(Err(format!("at most {} geo rules", GEO_MAX_RULES)));
+    };
+    let mut prefixes = BTreeSet::new();
+    for country in &config.countries {
+        match load_country(&dir, country) {
+            Ok(country) => prefixes.extend(country),
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    stats_map(&mut ebpf)?.set(id, 0, 0)?;
+    rules_map(&mut ebpf)?.set(id, entry(&config), 0)?;
+    let mut map = prefixes_map(&mut ebpf)?;
+    for (i, (addr, len)) in prefixes.iter().enumerate() {
+        if let Err(e) = map.insert(&Key::new(*len, *addr), id, 0) {
+            // 前缀表写满时撤销本条规则已写入的前缀
+            release(&mut map, &state.rules, prefixes.iter().take(i))?;
+            return Err(e.into());
+        }
+    }
+    info!(
+        rule_id = id,
+        "国家规则 {} 已添加: {}，{} 个前缀，例外端口 {:?}",
+        id,
+        config.countries.join(","),
+        prefixes.len(),
+        config.except_ports
+    );
+    state.rules.insert(id, Rule { config, prefixes });
+    crate::events::rule_change("geo", id as u64, "create");
+    Ok(Ok(id))
+}
+
+// 删除规则及其前缀，规则不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut state = GEO.lock().await;
+    let Some(rule) = state.rules.remove(&id) else {
+        return Ok(false);
+    };
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    release(
+        &mut prefixes_map(&mut ebpf)?,
+        &state.rules,
+        rule.prefixes.iter(),
+    )?;
+    info!(rule_id = id, action = "delete", "国家规则 {} 已删除", id);
+    crate::events::rule_change("geo", id as u64, "delete");
+    Ok(true)
+}
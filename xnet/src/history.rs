@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+
+// 包数和字节数计数
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Counter {
+    fn delta(&self, previous: &Counter) -> Counter {
+        Counter {
+            packets: self.packets.saturating_sub(previous.packets),
+            bytes: self.bytes.saturating_sub(previous.bytes),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.packets == 0 && self.bytes == 0
+    }
+}
+
+// 某一时刻的累计计数
+#[derive(Debug, Clone, Default)]
+struct Snapshot {
+    total: Counter,
+    devices: HashMap<String, Counter>,
+    ports: HashMap<u16, Counter>,
+}
+
+// 一个采样周期内的增量，只保存有流量的设备和端口
+#[derive(Debug, Clone)]
+pub struct HistorySample {
+    pub timestamp: u64,
+    pub interval_secs: f64,
+    pub total: Counter,
+    pub devices: HashMap<String, Counter>,
+    pub ports: HashMap<u16, Counter>,
+}
+
+impl HistorySample {
+    fn point(&self, counter: Counter) -> Value {
+        let per_sec = |v: u64| {
+            if self.interval_secs > 0.0 {
+                v as f64 / self.interval_secs
+            } else {
+                0.0
+            }
+        };
+        serde_json::json!({
+            "timestamp": self.timestamp,
+            "packets": counter.packets,
+            "bytes": counter.bytes,
+            "packets_per_sec": per_sec(counter.packets),
+            "bytes_per_sec": per_sec(counter.bytes),
+        })
+    }
+}
+
+// 周期采样的环形缓冲区
+pub struct History {
+    interval: Duration,
+    capacity: usize,
+    samples: VecDeque<HistorySample>,
+    previous: Option<(Instant, Snapshot)>,
+}
+
+impl History {
+    fn new(interval: Duration, retention: Duration) -> Self {
+        let capacity = (retention.as_secs_f64() / interval.as_secs_f64().max(1.0)).ceil() as usize;
+        Self {
+            interval,
+            capacity: capacity.max(1),
+            samples: VecDeque::new(),
+            previous: None,
+        }
+    }
+
+    fn record(&mut self, snapshot: Snapshot, now: Instant) {
+        if let Some((at, previous)) = &self.previous {
+            let sample = HistorySample {
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+                interval_secs: now.duration_since(*at).as_secs_f64(),
+                total: snapshot.total.delta(&previous.total),
+                devices: deltas(&snapshot.devices, &previous.devices),
+                ports: deltas(&snapshot.ports, &previous.ports),
+            };
+            if self.samples.len() >= self.capacity {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+        }
+        self.previous = Some((now, snapshot));
+    }
+
+    // 返回适合绘图的时间序列，device 匹配设备名，port 匹配端口，都未指定时返回总流量
+    pub fn query(&self, device: Option<&str>, port: Option<u16>, last_secs: Option<u64>) -> Value {
+        let since = last_secs.map(|secs| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+                .saturating_sub(secs)
+        });
+        let samples: Vec<&HistorySample> = self
+            .samples
+            .iter()
+            .filter(|s| since.is_none_or(|since| s.timestamp >= since))
+            .collect();
+
+        let mut series: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+        if let Some(device) = device {
+            // 设备统计的key为 设备名_方向
+            let prefix = format!("{}_", device);
+            for sample in &samples {
+                for direction in ["ingress", "egress"] {
+                    let key = format!("{}{}", prefix, direction);
+                    let counter = sample.devices.get(&key).copied().unwrap_or_default();
+                    series.entry(key).or_default().push(sample.point(counter));
+                }
+            }
+        }
+        if let Some(port) = port {
+            let key = format!("port_{}", port);
+            for sample in &samples {
+                let counter = sample.ports.get(&port).copied().unwrap_or_default();
+                series.entry(key.clone()).or_default().push(sample.point(counter));
+            }
+        }
+        if device.is_none() && port.is_none() {
+            series.insert(
+                "total".to_string(),
+                samples.iter().map(|s| s.point(s.total)).collect(),
+            );
+        }
+
+        serde_json::json!({
+            "interval_secs": self.interval.as_secs_f64(),
+            "retention_secs": self.interval.as_secs_f64() * self.capacity as f64,
+            "series": series,
+        })
+    }
+}
+
+fn deltas<K: Clone + Eq + std::hash::Hash>(
+    current: &HashMap<K, Counter>,
+    previous: &HashMap<K, Counter>,
+) -> HashMap<K, Counter> {
+    current
+        .iter()
+        .filter_map(|(key, counter)| {
+            let delta = counter.delta(&previous.get(key).copied().unwrap_or_default());
+            (!delta.is_zero()).then(|| (key.clone(), delta))
+        })
+        .collect()
+}
+
+lazy_static::lazy_static! {
+    pub static ref HISTORY: Mutex<History> = Mutex::new(History::new(Duration::from_secs(5), Duration::from_secs(3600)));
+}
+
+fn take_snapshot() -> Snapshot {
+    let traffic_stats = crate::traffic::snapshot();
+
+    Snapshot {
+        total: Counter {
+            packets: traffic_stats.total_packets,
+            bytes: traffic_stats.total_bytes,
+        },
+        devices: traffic_stats
+            .device_stats
+            .iter()
+            .map(|(name, stats)| {
+                let counter = Counter {
+                    packets: stats.packets,
+                    bytes: stats.bytes,
+                };
+                (name.clone(), counter)
+            })
+            .collect(),
+        ports: traffic_stats
+            .port_stats
+            .iter()
+            .map(|(port, stats)| {
+                let counter = Counter {
+                    packets: stats.packets,
+                    bytes: stats.bytes,
+                };
+                (*port, counter)
+            })
+            .collect(),
+    }
+}
+
+// 启动历史采样任务，按 interval 采样，保留 retention 时长的数据
+pub async fn start(interval: Duration, retention: Duration) {
+    *HISTORY.lock().await = History::new(interval, retention);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let snapshot = take_snapshot();
+            HISTORY.lock().await.record(snapshot, Instant::now());
+            crate::systemd::heartbeat();
+        }
+    });
+}
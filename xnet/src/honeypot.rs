@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::net::Ipv4Addr;
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::{
+    HoneypotPort, HoneypotSource, HONEYPOT_ACTION_DROP, HONEYPOT_ACTION_LOG, HONEYPOT_MAX_PORTS,
+    HONEYPOT_PROTO_TCP, HONEYPOT_PROTO_UDP,
+};
+
+use crate::events::Event;
+use crate::kernel_events::HoneypotProbeEvent;
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+
+// 报告中保留的最近访问记录数
+const MAX_RECENT_PROBES: usize = 256;
+// 记录访问端口集合的源地址上限，超出后不再记录新的源地址
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HoneypotAction {
+    // 只记录，包照常交给协议栈，端口上没有监听时由内核回复 RST 或 ICMP 不可达
+    #[default]
+    Log,
+    // 记录后在 XDP 中丢弃，扫描方只能等待超时
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HoneypotProtocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct HoneypotPortConfig {
+    pub port: u16,
+    #[serde(default)]
+    pub action: HoneypotAction,
+    // 不设置时 TCP 和 UDP 都记录
+    pub protocol: Option<HoneypotProtocol>,
+}
+
+impl HoneypotPortConfig {
+    fn entry(&self) -> HoneypotPort {
+        HoneypotPort {
+            action: match self.action {
+                HoneypotAction::Log => HONEYPOT_ACTION_LOG,
+                HoneypotAction::Drop => HONEYPOT_ACTION_DROP,
+            },
+            protocols: match self.protocol {
+                Some(HoneypotProtocol::Tcp) => HONEYPOT_PROTO_TCP,
+                Some(HoneypotProtocol::Udp) => HONEYPOT_PROTO_UDP,
+                None => HONEYPOT_PROTO_TCP | HONEYPOT_PROTO_UDP,
+            },
+            reserved: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct HoneypotState {
+    ports: BTreeMap<u16, HoneypotPortConfig>,
+    // 源地址 -> 访问过的蜜罐端口
+    sources: HashMap<Ipv4Addr, BTreeSet<u16>>,
+    recent: VecDeque<HoneypotProbeEvent>,
+}
+
+lazy_static::lazy_static! {
+    static ref HONEYPOT: Mutex<HoneypotState> = Mutex::new(HoneypotState::default());
+}
+
+// 本机正在监听的端口，TCP 取 LISTEN 状态，UDP 取所有已绑定的 socket
+fn listening(protocol: HoneypotProtocol, port: u16) -> bool {
+    let (files, state) = match protocol {
+        HoneypotProtocol::Tcp => (["/proc/net/tcp", "/proc/net/tcp6"], Some("0A")),
+        HoneypotProtocol::Udp => (["/proc/net/udp", "/proc/net/udp6"], None),
+    };
+    files.iter().any(|file| {
+        let Ok(table) = std::fs::read_to_string(file) else {
+            return false;
+        };
+        table.lines().skip(1).any(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields
+                .get(1)
+                .and_then(|addr| addr.rsplit_once(':'))
+                .and_then(|(_, port)| u16::from_str_radix(port, 16).ok());
+            local_port == Some(port) && state.is_none_or(|state| fields.get(3) == Some(&state))
+        })
+    })
+}
+
+fn validate(config: &HoneypotPortConfig) -> Result<(), String> {
+    if config.port == 0 {
+        return Err("port must be between 1 and 65535".to_string());
+    }
+    let protocols = match config.protocol {
+        Some(protocol) => vec![protocol],
+        None => vec![HoneypotProtocol::Tcp, HoneypotProtocol::Udp],
+    };
+    for protocol in protocols {
+        if listening(protocol, config.port) {
+            return Err(format!(
+                "port {} is in use by a local {:?} listener",
+                config.port, protocol
+            ));
+        }
+    }
+    Ok(())
+}
+
+// 添加或更新蜜罐端口
+pub async fn set_port(
+    ebpf_manager: &EbpfManager,
+    config: HoneypotPortConfig,
+) -> Result<Result<(), String>, anyhow::Error> {
+    if let Err(e) = validate(&config) {
+        return Ok(Err(e));
+    }
+    let mut state = HONEYPOT.lock().await;
+    if !state.ports.contains_key(&config.port) && state.ports.len() >= HONEYPOT_MAX_PORTS as usize {
+        return Ok(Err(format!(
+            "at most {} honeypot ports",
+            HONEYPOT_MAX_PORTS
+        )));
+    }
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut ports = AyaHashMap::<&mut MapData, u16, HoneypotPort>::try_from(
+        ebpf.map_mut("honeypot_ports")
+            .ok_or_else(|| anyhow::anyhow!("honeypot_ports map not found"))?,
+    )?;
+    ports.insert(config.port, config.entry(), 0)?;
+    info!(
+        dst_port = config.port,
+        action = ?config.action,
+        "蜜罐端口 {} 已设置: {:?}",
+        config.port,
+        config.action
+    );
+    state.ports.insert(config.port, config);
+    Ok(Ok(()))
+}
+
+// 删除蜜罐端口，端口不存在时返回 false
+pub async fn remove_port(ebpf_manager: &EbpfManager, port: u16) -> Result<bool, anyhow::Error> {
+    let mut state = HONEYPOT.lock().await;
+    if state.ports.remove(&port).is_none() {
+        return Ok(false);
+    }
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut ports = AyaHashMap::<&mut MapData, u16, HoneypotPort>::try_from(
+        ebpf.map_mut("honeypot_ports")
+            .ok_or_else(|| anyhow::anyhow!("honeypot_ports map not found"))?,
+    )?;
+    let _ = ports.remove(&port);
+    info!(
+        dst_port = port,
+        action = "delete",
+        "蜜罐端口 {} 已删除",
+        port
+    );
+    Ok(true)
+}
+
+// 蜜罐端口、探测过本机的源地址(按访问次数排序)以及最近的访问记录
+pub async fn report(maps: &MapCache) -> Result<Value, anyhow::Error> {
+    let state = HONEYPOT.lock().await;
+    let map = AyaHashMap::<&MapData, u32, HoneypotSource>::try_from(
+        maps.map("honeypot_sources")
+            .ok_or_else(|| anyhow::anyhow!("honeypot_sources map not found"))?,
+    )?;
+    let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+    let mut sources: Vec<(Ipv4Addr, HoneypotSource)> = map
+        .iter()
+        .filter_map(|entry| entry.ok())
+        .map(|(addr, source)| (Ipv4Addr::from(u32::from_be(addr)), source))
+        .collect();
+    sources.sort_by_key(|(_, source)| std::cmp::Reverse(source.probes));
+    let sources: Vec<Value> = sources
+        .into_iter()
+        .map(|(ip, source)| {
+            let ports: Vec<u16> = state
+                .sources
+                .get(&ip)
+                .map(|ports| ports.iter().copied().collect())
+                .unwrap_or_else(|| vec![source.last_port]);
+            serde_json::json!({
+                "ip": ip,
+                "probes": source.probes,
+                "ports": ports,
+                "first_seen": (source.first_seen_ns + offset_ns) / 1_000_000_000,
+                "last_seen": (source.last_seen_ns + offset_ns) / 1_000_000_000,
+                "last_port": source.last_port,
+                "last_protocol": source.last_protocol,
+            })
+        })
+        .collect();
+    let ports: Vec<&HoneypotPortConfig> = state.ports.values().collect();
+    let recent: Vec<&HoneypotProbeEvent> = state.recent.iter().rev().collect();
+    Ok(serde_json::json!({
+        "ports": ports,
+        "sources": sources,
+        "recent": recent,
+    }))
+}
+
+async fn record(probe: HoneypotProbeEvent) {
+    let mut state = HONEYPOT.lock().await;
+    let ip = probe.flow.src_ip;
+    if state.sources.contains_key(&ip) || state.sources.len() < MAX_TRACKED_SOURCES {
+        state
+            .sources
+            .entry(ip)
+            .or_default()
+            .insert(probe.flow.dst_port);
+    }
+    if state.recent.len() >= MAX_RECENT_PROBES {
+        state.recent.pop_front();
+    }
+    state.recent.push_back(probe);
+}
+
+// 汇总内核推送的蜜罐访问事件
+pub fn start() {
+    let mut events = crate::events::subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(Event::HoneypotProbe(probe)) => record(probe).await,
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("蜜罐统计落后，跳过 {} 个事件", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
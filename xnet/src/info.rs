@@ -0,0 +1,103 @@
+use std::ffi::CStr;
+use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+
+use serde_json::Value;
+
+use crate::api::AttachedInterfaces;
+use crate::bpf_sys::{
+    bpf, MapCreateAttr, BPF_MAP_CREATE, BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_LRU_HASH, BPF_MAP_TYPE_RINGBUF,
+};
+use crate::server::EbpfManager;
+
+// ring buffer 的大小须为页大小的整数倍
+const RINGBUF_PROBE_SIZE: u32 = 4096;
+
+// 创建一个临时 map，内核不支持该类型时返回 None
+fn create_map(map_type: u32, key_size: u32, value_size: u32, max_entries: u32) -> Option<OwnedFd> {
+    let mut attr = MapCreateAttr {
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        ..Default::default()
+    };
+    let fd = unsafe { bpf(BPF_MAP_CREATE, &mut attr) }.ok()?;
+    Some(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+fn kernel_release() -> String {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return "unknown".to_string();
+    }
+    unsafe { CStr::from_ptr(uts.release.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// 通过创建临时 map 探测内核支持的 eBPF 特性
+fn features() -> Value {
+    let batch_ops = create_map(BPF_MAP_TYPE_HASH, 4, 4, 1)
+        .map(|fd| crate::batch::probe(fd.as_fd()))
+        .unwrap_or(false);
+    let aya = aya::features();
+    serde_json::json!({
+        "ringbuf": create_map(BPF_MAP_TYPE_RINGBUF, 0, 0, RINGBUF_PROBE_SIZE).is_some(),
+        "lru": create_map(BPF_MAP_TYPE_LRU_HASH, 4, 4, 1).is_some(),
+        "batch_ops": batch_ops,
+        "btf": aya.btf().is_some(),
+        "bpf_cookie": aya.bpf_cookie(),
+        "perf_link": aya.bpf_perf_link(),
+        "global_data": aya.bpf_global_data(),
+        "bpf_timer": crate::map_gc::enabled(),
+        "netns_cookie": crate::netns::cookie_supported(),
+    })
+}
+
+// xnet 版本、已加载的程序、挂载的网卡、内核版本及特性，以及各 map 的使用率
+pub async fn info(ebpf_manager: &EbpfManager, attached: AttachedInterfaces) -> Value {
+    let programs: Vec<Value> = {
+        let ebpf = ebpf_manager.ebpf.lock().await;
+        let stats = crate::profile::programs(&ebpf);
+        let mut programs: Vec<Value> = ebpf
+            .programs()
+            .map(|(name, program)| {
+                let mut value = serde_json::json!({
+                    "name": name,
+                    "type": format!("{:?}", program.prog_type()),
+                    "loaded": program.fd().is_ok(),
+                });
+                // 附上运行次数及累计耗时
+                if let Some(stat) = stats.iter().find(|s| s["name"] == name) {
+                    for key in ["id", "run_count", "run_time_ns", "avg_ns"] {
+                        value[key] = stat[key].clone();
+                    }
+                }
+                value
+            })
+            .collect();
+        programs.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+        programs
+    };
+    let plugins: Vec<Value> = crate::plugin::list()
+        .await
+        .into_iter()
+        .map(|plugin| plugin["name"].clone())
+        .collect();
+
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("XNET_GIT_HASH"),
+        "ebpf_object": crate::reload::current_version().await,
+        "programs": programs,
+        "plugins": plugins,
+        "attached": attached,
+        "kernel": {
+            "release": kernel_release(),
+            "version": aya::util::KernelVersion::current().ok().map(|v| v.to_string()),
+        },
+        "features": features(),
+        "bpf_stats_enabled": crate::profile::enabled().await,
+        "maps": crate::maps::cached_usage().await,
+    })
+}
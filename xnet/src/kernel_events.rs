@@ -0,0 +1,290 @@
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use aya::maps::RingBuf;
+use aya::Ebpf;
+use serde::Serialize;
+use tokio::io::unix::AsyncFd;
+use tracing::{info, warn};
+use xnet_common::{
+    Alert, ConnClose, ConnOpen, DnsQuery, EventFlow, EventHeader, HoneypotProbe, RuleHit,
+    ALERT_REPUTATION_BLOCK, ALERT_TCP_FIN_SCAN, ALERT_TCP_NULL_SCAN, ALERT_TCP_XMAS_SCAN,
+    CONNECTION_CLOSE_RST, EVENT_ALERT, EVENT_CONN_CLOSE, EVENT_CONN_OPEN,
+    EVENT_DNS_QUERY, EVENT_HONEYPOT_PROBE, EVENT_RULE_HIT, EVENT_VERSION, FIREWALL_ACTION_LOG,
+    FIREWALL_DIRECTION_EGRESS, HONEYPOT_ACTION_DROP,
+};
+
+use crate::events::Event;
+
+// 只对第一个版本不一致的事件告警，避免日志刷屏
+static VERSION_MISMATCH: AtomicBool = AtomicBool::new(false);
+
+// 事件中的四元组
+#[derive(Debug, Clone, Serialize)]
+pub struct Flow {
+    pub src_ip: Ipv4Addr,
+    pub dst_ip: Ipv4Addr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+}
+
+impl From<&EventFlow> for Flow {
+    fn from(flow: &EventFlow) -> Self {
+        Flow {
+            src_ip: Ipv4Addr::from(u32::from_be(flow.saddr)),
+            dst_ip: Ipv4Addr::from(u32::from_be(flow.daddr)),
+            src_port: flow.src_port,
+            dst_port: flow.dst_port,
+            protocol: flow.protocol,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnOpenEvent {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub flow: Flow,
+    pub ifindex: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnCloseEvent {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub flow: Flow,
+    pub ifindex: u32,
+    // fin 或 rst
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleHitEvent {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub flow: Flow,
+    pub rule_id: u32,
+    pub action: &'static str,
+    pub direction: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KernelAlertEvent {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub flow: Flow,
+    pub kind: &'static str,
+    pub value: u32,
+}
+
+// 访问蜜罐端口的包
+#[derive(Debug, Clone, Serialize)]
+pub struct HoneypotProbeEvent {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub flow: Flow,
+    pub ifindex: u32,
+    pub len: u32,
+    pub ttl: u8,
+    pub tcp_flags: u8,
+    // log 或 drop
+    pub action: &'static str,
+}
+
+// XDP 程序看到的 DNS 查询
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsQueryEvent {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub flow: Flow,
+    pub ifindex: u32,
+    pub name: String,
+    pub qtype: u16,
+}
+
+fn read<T: bytemuck::Pod>(item: &[u8]) -> Option<T> {
+    let size = std::mem::size_of::<T>();
+    (item.len() >= size).then(|| bytemuck::pod_read_unaligned(&item[..size]))
+}
+
+fn unix_secs(timestamp_ns: u64, offset_ns: u64) -> u64 {
+    (timestamp_ns + offset_ns) / 1_000_000_000
+}
+
+// 按事件头的类型解析 ring buffer 中的一条记录，版本不一致或长度不足时返回 None
+fn decode(item: &[u8], offset_ns: u64) -> Option<Event> {
+    let header: EventHeader = read(item)?;
+    if header.version != EVENT_VERSION {
+        if !VERSION_MISMATCH.swap(true, Ordering::Relaxed) {
+            warn!(
+                "内核事件版本 {} 与用户空间版本 {} 不一致，已丢弃，请同时更新 eBPF 目标文件",
+                header.version, EVENT_VERSION
+            );
+        }
+        return None;
+    }
+    let timestamp = unix_secs(header.timestamp_ns, offset_ns);
+    let event = match header.kind {
+        EVENT_CONN_OPEN => {
+            let event: ConnOpen = read(item)?;
+            Event::ConnOpen(ConnOpenEvent {
+                timestamp,
+                flow: Flow::from(&event.flow),
+                ifindex: event.ifindex,
+            })
+        }
+        EVENT_CONN_CLOSE => {
+            let event: ConnClose = read(item)?;
+            Event::ConnClose(ConnCloseEvent {
+                timestamp,
+                flow: Flow::from(&event.flow),
+                ifindex: event.ifindex,
+                reason: if event.reason == CONNECTION_CLOSE_RST {
+                    "rst"
+                } else {
+                    "fin"
+                },
+            })
+        }
+        EVENT_RULE_HIT => {
+            let event: RuleHit = read(item)?;
+            Event::RuleHit(RuleHitEvent {
+                timestamp,
+                flow: Flow::from(&event.flow),
+                rule_id: event.rule_id,
+                action: if event.action == FIREWALL_ACTION_LOG {
+                    "log"
+                } else {
+                    "unknown"
+                },
+                direction: if event.direction == FIREWALL_DIRECTION_EGRESS {
+                    "egress"
+                } else {
+                    "ingress"
+                },
+            })
+        }
+        EVENT_ALERT => {
+            let event: Alert = read(item)?;
+            Event::KernelAlert(KernelAlertEvent {
+                timestamp,
+                flow: Flow::from(&event.flow),
+                kind: match event.kind {
+                    ALERT_REPUTATION_BLOCK => "reputation_block",
+                    ALERT_TCP_NULL_SCAN => "tcp_null_scan",
+                    ALERT_TCP_FIN_SCAN => "tcp_fin_scan",
+                    ALERT_TCP_XMAS_SCAN => "tcp_xmas_scan",
+                    _ => "unknown",
+                },
+                value: event.value,
+            })
+        }
+        EVENT_HONEYPOT_PROBE => {
+            let event: HoneypotProbe = read(item)?;
+            Event::HoneypotProbe(HoneypotProbeEvent {
+                timestamp,
+                flow: Flow::from(&event.flow),
+                ifindex: event.ifindex,
+                len: event.len,
+                ttl: event.ttl,
+                tcp_flags: event.tcp_flags,
+                action: if event.action == HONEYPOT_ACTION_DROP {
+                    "drop"
+                } else {
+                    "log"
+                },
+            })
+        }
+        EVENT_DNS_QUERY => {
+            let event: DnsQuery = read(item)?;
+            let len = (event.len as usize).min(event.payload.len());
+            let (name, qtype) = crate::dns::parse_query(&event.payload[..len])?;
+            Event::DnsQuery(DnsQueryEvent {
+                timestamp,
+                flow: Flow::from(&event.flow),
+                ifindex: event.ifindex,
+                name,
+                qtype,
+            })
+        }
+        _ => return None,
+    };
+    Some(event)
+}
+
+// 规则命中、告警和蜜罐访问写入日志，取代 eBPF 程序中的 aya-log 文本
+fn log(event: &Event) {
+    match event {
+        Event::RuleHit(hit) => info!(
+            rule_id = hit.rule_id,
+            action = hit.action,
+            src_ip = %hit.flow.src_ip,
+            dst_ip = %hit.flow.dst_ip,
+            "防火墙规则 {} 命中: {} {}:{} -> {}:{}",
+            hit.rule_id,
+            hit.flow.protocol,
+            hit.flow.src_ip,
+            hit.flow.src_port,
+            hit.flow.dst_ip,
+            hit.flow.dst_port
+        ),
+        Event::KernelAlert(alert) => warn!(
+            kind = alert.kind,
+            src_ip = %alert.flow.src_ip,
+            "内核告警 {}: {}:{} -> {}:{}",
+            alert.kind,
+            alert.flow.src_ip,
+            alert.flow.src_port,
+            alert.flow.dst_ip,
+            alert.flow.dst_port
+        ),
+        Event::HoneypotProbe(probe) => warn!(
+            src_ip = %probe.flow.src_ip,
+            dst_port = probe.flow.dst_port,
+            action = probe.action,
+            "蜜罐端口被访问: {} {}:{} -> {}:{} ifindex={} len={} ttl={} flags={:#04x}",
+            probe.flow.protocol,
+            probe.flow.src_ip,
+            probe.flow.src_port,
+            probe.flow.dst_ip,
+            probe.flow.dst_port,
+            probe.ifindex,
+            probe.len,
+            probe.ttl,
+            probe.tcp_flags
+        ),
+        _ => {}
+    }
+}
+
+// 读取 xnet_events ring buffer，解析后发布到事件流
+// 需在创建 EbpfManager 之前调用；ring buffer 已固定在 bpffs 中，热重载后读取任务不受影响
+pub fn start(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    let ring_buf = RingBuf::try_from(
+        ebpf.take_map("xnet_events")
+            .ok_or_else(|| anyhow::anyhow!("xnet_events map not found"))?,
+    )?;
+    let mut ring_buf = AsyncFd::new(ring_buf)?;
+    tokio::spawn(async move {
+        loop {
+            let mut guard = match ring_buf.readable_mut().await {
+                Ok(guard) => guard,
+                Err(e) => {
+                    warn!("读取内核事件失败: {}", e);
+                    return;
+                }
+            };
+            let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+            let ring_buf = guard.get_inner_mut();
+            while let Some(item) = ring_buf.next() {
+                if let Some(event) = decode(&item, offset_ns) {
+                    log(&event);
+                    crate::events::publish(event);
+                }
+            }
+            guard.clear_ready();
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use aya::Ebpf;
+use tracing::info;
+
+// 没有以太网头、包直接从 IP 头开始的设备类型
+const L3_TYPES: &[u16] = &[
+    libc::ARPHRD_NONE,    // tun、wireguard
+    libc::ARPHRD_PPP,     // ppp
+    519,                  // ARPHRD_RAWIP
+    libc::ARPHRD_TUNNEL,  // ipip
+    libc::ARPHRD_TUNNEL6, // ip6tnl
+    libc::ARPHRD_SIT,     // sit
+    libc::ARPHRD_IPGRE,   // gre
+    823,                  // ARPHRD_IP6GRE
+];
+
+// 设备的硬件类型(ARPHRD_*)，通过 SIOCGIFHWADDR 查询，只作用于调用线程所在的命名空间
+fn arphrd_type(iface: &str) -> Result<u16, anyhow::Error> {
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if sock < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in req
+        .ifr_name
+        .iter_mut()
+        .zip(iface.bytes().take(libc::IFNAMSIZ - 1))
+    {
+        *dst = src as libc::c_char;
+    }
+    let ret = unsafe { libc::ioctl(sock, libc::SIOCGIFHWADDR as _, &mut req) };
+    let err = std::io::Error::last_os_error();
+    unsafe { libc::close(sock) };
+    if ret < 0 {
+        return Err(anyhow::anyhow!(
+            "failed to query type of {}: {}",
+            iface,
+            err
+        ));
+    }
+    Ok(unsafe { req.ifr_ifru.ifru_hwaddr.sa_family })
+}
+
+// 是否为三层设备，在设备所在的命名空间中调用
+pub fn is_l3(iface: &str) -> Result<bool, anyhow::Error> {
+    Ok(L3_TYPES.contains(&arphrd_type(iface)?))
+}
+
+// l3_devices 中记录的三层设备 ifindex
+pub fn devices(ebpf: &Ebpf) -> Result<Vec<u32>, anyhow::Error> {
+    let devices = AyaHashMap::<&MapData, u32, u32>::try_from(
+        ebpf.map("l3_devices")
+            .ok_or_else(|| anyhow::anyhow!("l3_devices map not found"))?,
+    )?;
+    Ok(devices.keys().filter_map(Result::ok).collect())
+}
+
+// 按设备类型写入 l3_devices，tc 程序据此决定是否跳过以太网头
+// 每次挂载都重新写入，ifindex 被其他设备复用时不会沿用旧的类型
+pub fn set(ebpf: &mut Ebpf, ifindex: u32, l3: bool) -> Result<(), anyhow::Error> {
+    let mut devices = AyaHashMap::<&mut MapData, u32, u32>::try_from(
+        ebpf.map_mut("l3_devices")
+            .ok_or_else(|| anyhow::anyhow!("l3_devices map not found"))?,
+    )?;
+    if l3 {
+        devices.insert(ifindex, 1, 0)?;
+        info!(
+            device_id = ifindex,
+            "设备 {} 为三层设备，按 IP 头解析", ifindex
+        );
+    } else {
+        let _ = devices.remove(&ifindex);
+    }
+    Ok(())
+}
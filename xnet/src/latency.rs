@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use xnet_common::{
+    LatencyHistKey, LATENCY_BUCKETS, LATENCY_KIND_HANDSHAKE, LATENCY_KIND_INTER_PACKET,
+};
+
+use crate::server::EbpfManager;
+
+const PERCENTILES: [(&str, f64); 3] = [("p50", 0.50), ("p95", 0.95), ("p99", 0.99)];
+
+// 单个维度的延迟直方图，桶的含义与内核中的 latency_hist 一致
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS as usize],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; LATENCY_BUCKETS as usize],
+        }
+    }
+}
+
+impl Histogram {
+    fn add(&mut self, bucket: u8, count: u64) {
+        if let Some(b) = self.buckets.get_mut(bucket as usize) {
+            *b += count;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    // 计算百分位(微秒)，在命中的桶内按线性插值
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+        let target = (p * total as f64).ceil().max(1.0);
+        let mut cumulative = 0.0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            if cumulative + count as f64 >= target {
+                let lower = if i == 0 { 0.0 } else { (1u64 << i) as f64 };
+                let upper = (1u64 << (i + 1)) as f64;
+                let fraction = (target - cumulative) / count as f64;
+                return Some(lower + (upper - lower) * fraction);
+            }
+            cumulative += count as f64;
+        }
+        None
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut map = serde_json::Map::new();
+        map.insert("count".to_string(), self.count().into());
+        for (name, p) in PERCENTILES {
+            map.insert(name.to_string(), serde_json::json!(self.percentile(p)));
+        }
+        Value::Object(map)
+    }
+}
+
+// 握手延迟和包间隔两类直方图
+#[derive(Debug, Clone, Default)]
+struct LatencyHistograms {
+    handshake: Histogram,
+    inter_packet: Histogram,
+}
+
+impl LatencyHistograms {
+    fn add(&mut self, kind: u8, bucket: u8, count: u64) {
+        match kind {
+            LATENCY_KIND_HANDSHAKE => self.handshake.add(bucket, count),
+            LATENCY_KIND_INTER_PACKET => self.inter_packet.add(bucket, count),
+            _ => {}
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::json!({
+            "handshake": self.handshake.to_json(),
+            "inter_packet": self.inter_packet.to_json(),
+        })
+    }
+}
+
+// 读取内核延迟直方图，按设备和端口计算百分位
+// device_id/port 用于过滤，未指定时返回全部
+pub async fn percentiles(
+    ebpf_manager: &EbpfManager,
+    device_id: Option<u32>,
+    port: Option<u16>,
+) -> Result<Value, anyhow::Error> {
+    let entries: Vec<(LatencyHistKey, u64)> = {
+        let maps = ebpf_manager.maps();
+        let map = maps
+            .map("latency_hist")
+            .ok_or_else(|| anyhow::anyhow!("latency_hist map not found"))?;
+        let map = AyaHashMap::<&MapData, LatencyHistKey, u64>::try_from(map)?;
+        map.iter().filter_map(|r| r.ok()).collect()
+    };
+
+    let mut devices: BTreeMap<u32, (LatencyHistograms, BTreeMap<u16, LatencyHistograms>)> =
+        BTreeMap::new();
+    for (key, count) in entries {
+        if device_id.is_some_and(|id| id != key.device_id) || port.is_some_and(|p| p != key.port)
+        {
+            continue;
+        }
+        let (device, ports) = devices.entry(key.device_id).or_default();
+        device.add(key.kind, key.bucket, count);
+        ports
+            .entry(key.port)
+            .or_default()
+            .add(key.kind, key.bucket, count);
+    }
+
+    let devices: Vec<Value> = devices
+        .iter()
+        .map(|(device_id, (device, ports))| {
+            let mut value = device.to_json();
+            value["device_id"] = (*device_id).into();
+            value["ports"] = ports
+                .iter()
+                .map(|(port, histograms)| {
+                    let mut value = histograms.to_json();
+                    value["port"] = (*port).into();
+                    value
+                })
+                .collect::<Vec<_>>()
+                .into();
+            value
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "unit": "us",
+        "devices": devices,
+    }))
+}
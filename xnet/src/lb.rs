@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+use aya::maps::{Array, HashMap as AyaHashMap, MapData};
+use bytemuck::Zeroable;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{
+    LbBackend, LbBackendStats, LbService, LbVipKey, LB_MAX_BACKENDS, LB_MAX_SERVICES,
+};
+
+use crate::server::EbpfManager;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum L4Protocol {
+    Tcp,
+    Udp,
+}
+
+impl L4Protocol {
+    pub(crate) fn number(&self) -> u8 {
+        match self {
+            L4Protocol::Tcp => 6,
+            L4Protocol::Udp => 17,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LbBackendConfig {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    // 下一跳(后端或网关)的MAC地址，如 02:00:00:00:00:01
+    pub mac: String,
+}
+
+// 虚拟服务: 发往 vip:port 的包按流分发到各后端
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LbServiceConfig {
+    pub vip: Ipv4Addr,
+    pub port: u16,
+    pub protocol: L4Protocol,
+    pub backends: Vec<LbBackendConfig>,
+}
+
+impl LbServiceConfig {
+    fn key(&self) -> LbVipKey {
+        LbVipKey {
+            addr: u32::from(self.vip).to_be(),
+            port: self.port.to_be(),
+            protocol: self.protocol.number(),
+            reserved: 0,
+        }
+    }
+
+    fn same_vip(&self, other: &LbServiceConfig) -> bool {
+        self.vip == other.vip && self.port == other.port && self.protocol == other.protocol
+    }
+}
+
+lazy_static::lazy_static! {
+    // 服务ID -> 服务配置
+    static ref LB_SERVICES: Mutex<BTreeMap<u32, LbServiceConfig>> = Mutex::new(BTreeMap::new());
+}
+
+fn parse_mac(mac: &str) -> Result<[u8; 6], String> {
+    let parts: Vec<&str> = mac.split(':').collect();
+    let mut bytes = [0u8; 6];
+    if parts.len() != 6 {
+        return Err(format!("invalid mac address {}", mac));
+    }
+    for (byte, part) in bytes.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16).map_err(|_| format!("invalid mac address {}", mac))?;
+    }
+    Ok(bytes)
+}
+
+// 后端在 LB_BACKENDS/LB_STATS 中的下标
+fn backend_index(id: u32, backend: usize) -> u32 {
+    id * LB_MAX_BACKENDS + backend as u32
+}
+
+fn service_json(id: u32, service: &LbServiceConfig, stats: &[LbBackendStats]) -> Value {
+    let backends: Vec<Value> = service
+        .backends
+        .iter()
+        .enumerate()
+        .map(|(i, backend)| {
+            let stats = stats.get(i).copied().unwrap_or_else(LbBackendStats::zeroed);
+            serde_json::json!({
+                "ip": backend.ip,
+                "port": backend.port,
+                "mac": backend.mac,
+                "packets": stats.packets,
+                "bytes": stats.bytes,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "id": id,
+        "vip": service.vip,
+        "port": service.port,
+        "protocol": service.protocol,
+        "backends": backends,
+    })
+}
+
+// 列出所有虚拟服务及各后端的转发统计
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let services = LB_SERVICES.lock().await;
+    let maps = ebpf_manager.maps();
+    let stats = Array::<&MapData, LbBackendStats>::try_from(
+        maps.map("LB_STATS")
+            .ok_or_else(|| anyhow::anyhow!("LB_STATS map not found"))?,
+    )?;
+
+    let mut result = Vec::new();
+    for (id, service) in services.iter() {
+        let backend_stats: Vec<LbBackendStats> = (0..service.backends.len())
+            .map(|i| stats.get(&backend_index(*id, i), 0))
+            .collect::<Result<_, _>>()?;
+        result.push(service_json(*id, service, &backend_stats));
+    }
+    Ok(result)
+}
+
+// 新增虚拟服务，vip/port/protocol 相同的服务会被替换，返回服务ID
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    service: LbServiceConfig,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    if service.backends.is_empty() || service.backends.len() > LB_MAX_BACKENDS as usize {
+        return Ok(Err(format!(
+            "a service needs 1 to {} backends",
+            LB_MAX_BACKENDS
+        )));
+    }
+    let mut backends = Vec::new();
+    for backend in service.backends.iter() {
+        let mac = match parse_mac(&backend.mac) {
+            Ok(mac) => mac,
+            Err(e) => return Ok(Err(e)),
+        };
+        backends.push(LbBackend {
+            addr: u32::from(backend.ip).to_be(),
+            port: backend.port.to_be(),
+            mac,
+        });
+    }
+
+    let mut services = LB_SERVICES.lock().await;
+    let existing = services
+        .iter()
+        .find(|(_, s)| s.same_vip(&service))
+        .map(|(id, _)| *id);
+    let id = match existing.or_else(|| (0..LB_MAX_SERVICES).find(|id| !services.contains_key(id))) {
+        Some(id) => id,
+        None => return Ok(Err(format!("at most {} services", LB_MAX_SERVICES))),
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    {
+        let mut backend_map = Array::<&mut MapData, LbBackend>::try_from(
+            ebpf.map_mut("LB_BACKENDS")
+                .ok_or_else(|| anyhow::anyhow!("LB_BACKENDS map not found"))?,
+        )?;
+        for (i, backend) in backends.iter().enumerate() {
+            backend_map.set(backend_index(id, i), backend, 0)?;
+        }
+    }
+    {
+        // 后端列表变化后统计重新开始
+        let mut stats = Array::<&mut MapData, LbBackendStats>::try_from(
+            ebpf.map_mut("LB_STATS")
+                .ok_or_else(|| anyhow::anyhow!("LB_STATS map not found"))?,
+        )?;
+        for i in 0..LB_MAX_BACKENDS as usize {
+            stats.set(backend_index(id, i), LbBackendStats::zeroed(), 0)?;
+        }
+    }
+    let mut service_map = AyaHashMap::<&mut MapData, LbVipKey, LbService>::try_from(
+        ebpf.map_mut("LB_SERVICES")
+            .ok_or_else(|| anyhow::anyhow!("LB_SERVICES map not found"))?,
+    )?;
+    service_map.insert(
+        service.key(),
+        LbService {
+            id,
+            backend_count: backends.len() as u32,
+        },
+        0,
+    )?;
+
+    info!(
+        rule_id = id,
+        dst_ip = %service.vip,
+        dst_port = service.port,
+        "负载均衡服务 {} {}:{} -> {} 个后端",
+        id,
+        service.vip,
+        service.port,
+        backends.len()
+    );
+    services.insert(id, service);
+    Ok(Ok(id))
+}
+
+// 删除虚拟服务，服务不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut services = LB_SERVICES.lock().await;
+    let Some(service) = services.remove(&id) else {
+        return Ok(false);
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut service_map = AyaHashMap::<&mut MapData, LbVipKey, LbService>::try_from(
+        ebpf.map_mut("LB_SERVICES")
+            .ok_or_else(|| anyhow::anyhow!("LB_SERVICES map not found"))?,
+    )?;
+    service_map.remove(&service.key())?;
+    info!(
+        rule_id = id,
+        action = "delete",
+        "负载均衡服务 {} {}:{} 已删除",
+        id,
+        service.vip,
+        service.port
+    );
+    Ok(true)
+}
@@ -0,0 +1,168 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 扫描 /proc 的开销和进程数成正比，结果在该时间内复用
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ListenerProcess {
+    pub pid: u32,
+    pub name: String,
+}
+
+// 本地监听端口 -> 监听该端口的进程，按 pid 排序
+pub type Listeners = HashMap<u16, Vec<ListenerProcess>>;
+
+lazy_static::lazy_static! {
+    static ref LISTENERS: Mutex<Option<(Instant, Arc<Listeners>)>> = Mutex::new(None);
+}
+
+// 解析 /proc/net/tcp{,6} 的内容，返回 LISTEN 状态 socket 的 inode -> 本地端口
+fn parse_listening(table: &str) -> HashMap<u64, u16> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(3) != Some(&"0A") {
+                return None;
+            }
+            let port = fields
+                .get(1)
+                .and_then(|addr| addr.rsplit_once(':'))
+                .and_then(|(_, port)| u16::from_str_radix(port, 16).ok())?;
+            let inode = fields.get(9)?.parse::<u64>().ok()?;
+            // inode 为 0 的 socket 已不属于任何进程
+            (inode != 0).then_some((inode, port))
+        })
+        .collect()
+}
+
+// 进程打开的 socket 的 inode，fd 链接的形式为 socket:[12345]
+fn socket_inodes(pid: u32) -> Vec<u64> {
+    let Ok(fds) = std::fs::read_dir(format!("/proc/{}/fd", pid)) else {
+        // 进程已退出或没有权限读取
+        return Vec::new();
+    };
+    fds.flatten()
+        .filter_map(|fd| std::fs::read_link(fd.path()).ok())
+        .filter_map(|link| {
+            link.to_str()?
+                .strip_prefix("socket:[")?
+                .strip_suffix(']')?
+                .parse()
+                .ok()
+        })
+        .collect()
+}
+
+fn comm(pid: u32) -> String {
+    std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| pid.to_string())
+}
+
+// 扫描 /proc/net/tcp{,6} 和 /proc/*/fd，得到每个 TCP 监听端口所属的进程
+pub fn scan() -> Listeners {
+    let mut inodes = HashMap::new();
+    for file in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        if let Ok(table) = std::fs::read_to_string(file) {
+            inodes.extend(parse_listening(&table));
+        }
+    }
+    let mut listeners = Listeners::new();
+    if inodes.is_empty() {
+        return listeners;
+    }
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return listeners;
+    };
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let mut name = None;
+        for inode in socket_inodes(pid) {
+            let Some(port) = inodes.get(&inode) else {
+                continue;
+            };
+            let name = name.get_or_insert_with(|| comm(pid)).clone();
+            let processes = listeners.entry(*port).or_default();
+            // 同一进程在 IPv4 和 IPv6 上监听同一端口时只记录一次
+            if !processes.iter().any(|process| process.pid == pid) {
+                processes.push(ListenerProcess { pid, name });
+            }
+        }
+    }
+    for processes in listeners.values_mut() {
+        processes.sort_by_key(|process| process.pid);
+    }
+    listeners
+}
+
+// 带缓存的扫描结果，超过 REFRESH_INTERVAL 后重新扫描
+pub fn snapshot() -> Arc<Listeners> {
+    let mut cache = LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((scanned_at, listeners)) = cache.as_ref() {
+        if scanned_at.elapsed() < REFRESH_INTERVAL {
+            return listeners.clone();
+        }
+    }
+    let listeners = Arc::new(scan());
+    *cache = Some((Instant::now(), listeners.clone()));
+    listeners
+}
+
+// 监听端口的进程名，多个进程(如 fork 出的 worker)同名时只出现一次
+pub fn process_name(listeners: &Listeners, port: u16) -> Option<String> {
+    let names: BTreeSet<&str> = listeners
+        .get(&port)?
+        .iter()
+        .map(|process| process.name.as_str())
+        .collect();
+    Some(names.into_iter().collect::<Vec<_>>().join(","))
+}
+
+// 形如 "8080 (xnet)"，没有进程监听的端口只显示端口号
+pub fn port_label(listeners: &Listeners, port: u16) -> String {
+    match process_name(listeners, port) {
+        Some(name) => format!("{} ({})", port, name),
+        None => port.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listening_sockets() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 31337 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0035 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 0 1 0000000000000000 100 0 0 10 0
+   2: 0F02000A:A3B2 0F02000A:1F90 01 00000000:00000000 00:00000000 00000000  1000        0 40000 1 0000000000000000 20 4 30 10 -1
+";
+        assert_eq!(parse_listening(table), HashMap::from([(31337, 8080)]));
+
+        let listeners = Listeners::from([(
+            8080,
+            vec![
+                ListenerProcess {
+                    pid: 10,
+                    name: "nginx".to_string(),
+                },
+                ListenerProcess {
+                    pid: 11,
+                    name: "nginx".to_string(),
+                },
+            ],
+        )]);
+        assert_eq!(port_label(&listeners, 8080), "8080 (nginx)");
+        assert_eq!(port_label(&listeners, 22), "22");
+    }
+}
@@ -0,0 +1,28 @@
+use tracing_subscriber::EnvFilter;
+
+// 日志输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    // 便于阅读的单行文本
+    #[default]
+    Text,
+    // 每行一个 JSON 对象，字段(iface、device_id、action 等)单独输出，便于日志系统检索
+    Json,
+}
+
+// 初始化 tracing，级别沿用 RUST_LOG 的写法(例如 info,xnet=debug)，未设置时只输出错误和访问日志
+// eBPF 程序的日志经 aya-log 输出到 log，也会转发到 tracing
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error,xnet::access=info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use aya::maps::{Array, HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{
+    MacKey, MacRule, MacStats, MAC_ACTION_ALLOW, MAC_ACTION_DENY, MAC_MAX_RULES,
+    MAC_MODE_ALLOWLIST, MAC_MODE_DENYLIST,
+};
+
+use crate::server::EbpfManager;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MacAction {
+    Allow,
+    Deny,
+}
+
+// denylist: 只丢弃 deny 规则匹配的源MAC；allowlist: 只放行 allow 规则匹配的源MAC
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MacMode {
+    #[default]
+    Denylist,
+    Allowlist,
+}
+
+// 二层规则: 按收到的帧的源MAC放行或丢弃，在挂载了流量统计的设备上生效
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MacFilterRule {
+    // 例如 52:54:00:12:34:56
+    pub mac: String,
+    pub action: MacAction,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct MacModeRequest {
+    pub mode: MacMode,
+}
+
+lazy_static::lazy_static! {
+    // 规范化的MAC地址 -> 规则动作
+    static ref MAC_RULES: Mutex<BTreeMap<String, MacAction>> = Mutex::new(BTreeMap::new());
+    static ref MAC_MODE: Mutex<MacMode> = Mutex::new(MacMode::Denylist);
+}
+
+fn parse_mac(mac: &str) -> Result<[u8; 6], String> {
+    let parts: Vec<&str> = mac.split([':', '-']).collect();
+    if parts.len() != 6 {
+        return Err(format!("invalid mac address {}", mac));
+    }
+    let mut addr = [0u8; 6];
+    for (byte, part) in addr.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16).map_err(|_| format!("invalid mac address {}", mac))?;
+    }
+    Ok(addr)
+}
+
+fn format_mac(addr: &[u8; 6]) -> String {
+    addr.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn mac_key(addr: [u8; 6]) -> MacKey {
+    MacKey { addr, reserved: 0 }
+}
+
+// 当前过滤模式及所有二层规则和命中的包数
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Value, anyhow::Error> {
+    let rules = MAC_RULES.lock().await;
+    let mode = *MAC_MODE.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, MacKey, MacRule>::try_from(
+        maps.map("mac_rules")
+            .ok_or_else(|| anyhow::anyhow!("mac_rules map not found"))?,
+    )?;
+
+    let rules: Vec<Value> = rules
+        .iter()
+        .map(|(mac, action)| {
+            let packets = parse_mac(mac)
+                .ok()
+                .and_then(|addr| map.get(&mac_key(addr), 0).ok())
+                .map(|rule| rule.packets)
+                .unwrap_or(0);
+            serde_json::json!({
+                "mac": mac,
+                "action": action,
+                "packets": packets,
+            })
+        })
+        .collect();
+    Ok(serde_json::json!({ "mode": mode, "rules": rules }))
+}
+
+// 新增或替换源MAC的二层规则
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    rule: MacFilterRule,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let addr = match parse_mac(&rule.mac) {
+        Ok(addr) => addr,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mac = format_mac(&addr);
+
+    let mut rules = MAC_RULES.lock().await;
+    if !rules.contains_key(&mac) && rules.len() >= MAC_MAX_RULES as usize {
+        return Ok(Err(format!("at most {} mac rules", MAC_MAX_RULES)));
+    }
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, MacKey, MacRule>::try_from(
+        ebpf.map_mut("mac_rules")
+            .ok_or_else(|| anyhow::anyhow!("mac_rules map not found"))?,
+    )?;
+    let entry = MacRule {
+        action: match rule.action {
+            MacAction::Allow => MAC_ACTION_ALLOW,
+            MacAction::Deny => MAC_ACTION_DENY,
+        },
+        reserved: 0,
+        packets: 0,
+    };
+    map.insert(mac_key(addr), entry, 0)?;
+
+    info!(mac = %mac, action = ?rule.action, "二层规则 {} -> {:?}", mac, rule.action);
+    rules.insert(mac, rule.action);
+    Ok(Ok(()))
+}
+
+// 删除源MAC的二层规则，规则不存在时返回 false
+pub async fn remove(
+    ebpf_manager: &EbpfManager,
+    mac: &str,
+) -> Result<Result<bool, String>, anyhow::Error> {
+    let addr = match parse_mac(mac) {
+        Ok(addr) => addr,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mac = format_mac(&addr);
+
+    let mut rules = MAC_RULES.lock().await;
+    if rules.remove(&mac).is_none() {
+        return Ok(Ok(false));
+    }
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, MacKey, MacRule>::try_from(
+        ebpf.map_mut("mac_rules")
+            .ok_or_else(|| anyhow::anyhow!("mac_rules map not found"))?,
+    )?;
+    map.remove(&mac_key(addr))?;
+    info!(mac = %mac, action = "delete", "二层规则 {} 已删除", mac);
+    Ok(Ok(true))
+}
+
+// 切换过滤模式，白名单模式下没有 allow 规则的源MAC发来的帧都会被丢弃
+pub async fn set_mode(ebpf_manager: &EbpfManager, mode: MacMode) -> Result<(), anyhow::Error> {
+    let mut current = MAC_MODE.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut config = Array::<&mut MapData, u32>::try_from(
+        ebpf.map_mut("mac_config")
+            .ok_or_else(|| anyhow::anyhow!("mac_config map not found"))?,
+    )?;
+    let value = match mode {
+        MacMode::Denylist => MAC_MODE_DENYLIST,
+        MacMode::Allowlist => MAC_MODE_ALLOWLIST,
+    };
+    config.set(0, value, 0)?;
+    info!("二层过滤模式: {:?}", mode);
+    *current = mode;
+    Ok(())
+}
+
+// 每个MAC的收发统计，按总字节数从大到小排列
+pub async fn stats(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, MacKey, MacStats>::try_from(
+        maps.map("mac_stats")
+            .ok_or_else(|| anyhow::anyhow!("mac_stats map not found"))?,
+    )?;
+
+    let mut rows: Vec<(MacKey, MacStats)> = map.iter().filter_map(|entry| entry.ok()).collect();
+    rows.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.tx_bytes + stats.rx_bytes));
+    Ok(rows
+        .into_iter()
+        .map(|(key, stats)| {
+            serde_json::json!({
+                "mac": format_mac(&key.addr),
+                "tx_packets": stats.tx_packets,
+                "tx_bytes": stats.tx_bytes,
+                "rx_packets": stats.rx_packets,
+                "rx_bytes": stats.rx_bytes,
+            })
+        })
+        .collect())
+}
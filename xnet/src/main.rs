@@ -1,24 +1,277 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
 use anyhow::Context as _;
 use clap::Parser;
 #[rustfmt::skip]
-use log::{debug, warn};
+use tracing::{debug, warn};
 
+mod access_log;
+mod afxdp;
+mod alert;
+mod anomaly;
+mod api;
+mod auth;
+mod batch;
+mod bench;
+mod blocklist;
+mod bogon;
+mod bpf_sys;
+mod btf;
+mod canary;
+mod capture;
+mod conntrack;
+mod daemon;
+mod dns;
+mod dscp;
+mod egress;
+mod events;
+mod export;
+mod firewall;
+mod geo;
+mod history;
+mod honeypot;
+mod info;
+mod kernel_events;
+mod l3;
+mod lb;
+mod nat;
+mod latency;
+mod listeners;
+mod logging;
+mod mac;
+mod map_cache;
+mod map_gc;
+mod maps;
+mod netns;
+mod nft;
+mod otlp;
+mod peer;
+mod plugin;
+mod profile;
+mod quota;
+mod reload;
+mod reputation;
+mod rules;
+mod sampling;
 mod server;
+mod sflow;
+mod shaping;
+mod skb_mark;
+mod socket_owner;
+mod sockops;
+mod source;
+mod sqlite;
+mod startup_attach;
+mod ssl;
+mod systemd;
+mod tc_attach;
+mod tcpstate;
+mod tls;
+mod top;
 mod traffic;
+mod ttl;
+mod unix_socket;
+mod validate;
+mod watch;
+mod webhook;
+mod xdp_attach;
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// 终端实时查看流量、端口、连接和告警，数据来自本地 xnet API
+    Top(top::TopArgs),
+    /// 导出端口、设备和连接统计到 CSV/JSON lines 文件，数据来自本地 xnet API
+    Export(export::ExportArgs),
+    /// 用 raw socket 按指定速率发送 TCP/UDP 测试流量，用于验证计数、规则和性能
+    Bench(bench::BenchArgs),
+}
 
 #[derive(Debug, Parser)]
 struct Opt {
-    #[clap(short, long, default_value = "eth0")]
-    iface: String,
-    #[clap(short, long, default_value = "5")]
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// 启动时挂载的设备，格式 iface[=tc|xdp|both]，可重复指定：tc 为流量统计程序，xdp 为 XDP 防火墙，不指定时为 tc
+    #[clap(short = 'i', long = "attach", alias = "iface", value_parser = startup_attach::parse_attach)]
+    attach: Vec<startup_attach::AttachSpec>,
+    #[clap(long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
     interval_secs: u64,
+    /// 流量统计的刷新间隔（毫秒），查询接口返回最近一次刷新的结果
+    #[clap(long, default_value = "1000", value_parser = clap::value_parser!(u64).range(1..))]
+    stats_refresh_ms: u64,
+    /// 流量历史保留时长（秒），按 interval_secs 的分辨率采样
+    #[clap(long, default_value = "3600")]
+    history_retention_secs: u64,
+    /// SQLite 数据库文件路径，设置后按 interval_secs 持久化聚合统计
+    #[clap(long)]
+    sqlite_path: Option<PathBuf>,
+    /// SQLite 原始采样数据保留时长（秒）
+    #[clap(long, default_value = "86400")]
+    sqlite_retention_raw_secs: u64,
+    /// SQLite 分钟汇总数据保留时长（秒）
+    #[clap(long, default_value = "604800")]
+    sqlite_retention_1m_secs: u64,
+    /// SQLite 小时汇总数据保留时长（秒）
+    #[clap(long, default_value = "7776000")]
+    sqlite_retention_1h_secs: u64,
+    /// 启用 OTLP 指标和连接 trace 导出，采集端通过 OTEL_EXPORTER_OTLP_ENDPOINT 等环境变量配置
+    #[clap(long)]
+    otlp: bool,
+    /// 流量异常检测的 EWMA 平滑系数
+    #[clap(long, default_value = "0.1")]
+    anomaly_alpha: f64,
+    /// 速率偏离基线超过该倍数时判定为异常
+    #[clap(long, default_value = "3.0")]
+    anomaly_multiple: f64,
+    /// 修改类接口(POST/DELETE)的 Bearer 令牌
+    #[clap(long, env = "XNET_API_TOKEN", hide_env_values = true)]
+    api_token: Option<String>,
+    /// 令牌文件，每行一个令牌
+    #[clap(long, env = "XNET_API_TOKEN_FILE")]
+    api_token_file: Option<PathBuf>,
+    /// HTTPS 证书文件(PEM)，与 --tls-key 同时设置时启用 HTTPS
+    #[clap(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// HTTPS 私钥文件(PEM)
+    #[clap(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// 客户端 CA 证书(PEM)，设置后只接受持有该 CA 签发证书的客户端(mTLS)
+    #[clap(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+    /// 同时在该 unix socket 上提供 API，例如 /run/xnet.sock
+    #[clap(long)]
+    unix_socket: Option<PathBuf>,
+    /// unix socket 文件权限(八进制)
+    #[clap(long, default_value = "660", value_parser = unix_socket::parse_mode)]
+    unix_socket_mode: u32,
+    /// 不监听 TCP 端口，只通过 unix socket 提供 API
+    #[clap(long, requires = "unix_socket")]
+    no_tcp: bool,
+    /// API 监听地址，可重复指定或用逗号分隔多个地址
+    #[clap(long, env = "XNET_LISTEN", value_delimiter = ',', default_value = "127.0.0.1:8080")]
+    listen: Vec<SocketAddr>,
+    /// sFlow 采集器地址，例如 127.0.0.1:6343，设置后启用包采样导出
+    #[clap(long)]
+    sflow_collector: Option<SocketAddr>,
+    /// sFlow 数据报中上报的 agent 地址
+    #[clap(long, default_value = "0.0.0.0")]
+    sflow_agent: Ipv4Addr,
+    /// 包采样率 N，每 N 个包采样 1 个
+    #[clap(long, default_value = "1000")]
+    sample_rate: u32,
+    /// 对端 xnet 实例，格式 name=host:port，可重复指定，设置后启用对端模式
+    #[clap(long = "peer", value_parser = peer::parse_peer_spec)]
+    peers: Vec<peer::PeerSpec>,
+    /// 对端模式信标监听地址
+    #[clap(long, default_value = "0.0.0.0:7455")]
+    peer_listen: SocketAddr,
+    /// 对端模式信标发送间隔（毫秒）
+    #[clap(long, default_value = "1000", value_parser = clap::value_parser!(u64).range(1..))]
+    peer_interval_ms: u64,
+    /// cgroup v2 目录，例如 /sys/fs/cgroup，设置后通过 sock_ops 程序记录其中 TCP socket 的 RTT、拥塞窗口和字节数
+    #[clap(long)]
+    sock_ops_cgroup: Option<PathBuf>,
+    /// libssl 路径或库名，例如 libssl.so.3，设置后通过 uprobe 按进程和连接统计 TLS 明文字节数
+    #[clap(long)]
+    ssl_lib: Option<PathBuf>,
+    /// 启用 LSM 出方向连接策略(/egress/rules)，需要内核启用 BPF LSM，例如启动参数 lsm=...,bpf
+    #[clap(long)]
+    egress_policy: bool,
+    /// 把 XDP 防火墙收到的 DNS 查询推送到 /events，并检查过长、高熵的查询名和大量 TXT/NULL 查询
+    #[clap(long)]
+    dns_inspect: bool,
+    /// 通过 tcp_connect/tcp_sendmsg 的 fentry 程序记录 socket 所属进程，/socket_flows 按 socket cookie 关联进程、socket 指标和连接统计
+    #[clap(long)]
+    socket_owners: bool,
+    /// 防火墙规则文件，启动时加载，通过 /firewall/rules 修改规则后写回
+    #[clap(long)]
+    firewall_rules_file: Option<PathBuf>,
+    /// 规则目录，启动时加载其中的 firewall.json、nat.json 和 shaping.json，规则修改后写回；同时指定 --firewall-rules-file 时防火墙规则以该文件为准
+    #[clap(long)]
+    rules_dir: Option<PathBuf>,
+    /// 信任名单文件(JSON 数组)，其中的网段跳过所有丢弃和限速逻辑，通过 /firewall/allowlist 修改后写回
+    #[clap(long)]
+    firewall_allowlist_file: Option<PathBuf>,
+    /// 插件配置文件(JSON 数组)，启动时加载其中的外部 eBPF 目标文件并挂载，也可通过 /plugins 加载
+    #[clap(long)]
+    plugins_file: Option<PathBuf>,
+    /// 固定 map 的 bpffs 目录，/reload 重载 eBPF 程序时沿用其中的 map，启动时清空
+    #[clap(long, default_value = "/sys/fs/bpf/xnet")]
+    pin_path: PathBuf,
+    /// 覆盖 map 容量，格式 name=max_entries，可重复指定，例如 connection_track=262144
+    #[clap(long = "map-size", value_parser = reload::parse_map_size)]
+    map_sizes: Vec<reload::MapSize>,
+    /// bogon 前缀文件，每行一个 CIDR，替换内置的 bogon 前缀
+    #[clap(long)]
+    bogon_prefixes_file: Option<PathBuf>,
+    /// 威胁情报订阅源配置文件(JSON 数组)，定期下载其中的 IP 黑名单并由 XDP 程序丢弃来自这些地址的包
+    #[clap(long)]
+    blocklist_feeds_file: Option<PathBuf>,
+    /// GeoIP 数据目录，每个国家一个 <国家代码>.zone 文件(每行一个 CIDR)，用于按国家拒绝入站流量的规则
+    #[clap(long)]
+    geoip_dir: Option<PathBuf>,
+    /// 按设备采样，格式 iface=N，可重复指定：按连接、端口和源地址的统计只处理 1/N 的包并按 N 放大，总量和设备统计不受影响
+    #[clap(long = "sampling", value_parser = sampling::parse_sampling)]
+    sampling: Vec<sampling::SamplingConfig>,
+    /// 跳过已带有指定 mark 的包，格式 value[/mask]，可重复指定，例如 0x10/0xff：这些包由已有的 iptables/tc 规则处理，xnet 的 tc 程序不统计、不过滤也不改写
+    #[clap(long = "skip-mark", value_parser = skb_mark::parse_skip_mark)]
+    skip_mark: Vec<skb_mark::MarkSkip>,
+    /// 以后台守护进程运行，未设置 --log-file 时丢弃输出
+    #[clap(long)]
+    daemonize: bool,
+    /// PID 文件，已有实例持有该文件时拒绝启动
+    #[clap(long)]
+    pid_file: Option<PathBuf>,
+    /// 日志文件，标准输出和标准错误追加写入其中，收到 SIGHUP 时重新打开
+    #[clap(long)]
+    log_file: Option<PathBuf>,
+    /// 日志输出格式，json 时每行一个带 iface、device_id、action 等字段的 JSON 对象
+    #[clap(long, env = "XNET_LOG_FORMAT", value_enum, default_value = "text")]
+    log_format: logging::LogFormat,
+    /// 只加载程序通过校验器、校验配置文件和设备名并列出会挂载的程序，然后退出，不挂载任何程序
+    #[clap(long)]
+    dry_run: bool,
+    /// 抓包文件目录，/capture 和 /capture/afxdp 的 output 为该目录下的相对路径
+    #[clap(long, default_value = "/var/lib/xnet/captures")]
+    capture_dir: PathBuf,
+    /// map 条目数达到 max_entries 的该百分比时告警
+    #[clap(long, default_value = "80", value_parser = clap::value_parser!(u8).range(1..=100))]
+    map_pressure_percent: u8,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
 
-    env_logger::init();
+    // 后台运行须在创建 tokio 运行时之前 fork，PID 文件只用于服务模式
+    let _pid_file = if opt.command.is_none() {
+        daemon::prepare(&daemon::DaemonOptions {
+            daemonize: opt.daemonize,
+            pid_file: opt.pid_file.clone(),
+            log_file: opt.log_file.clone(),
+        })?
+    } else {
+        None
+    };
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(opt))
+}
+
+async fn run(opt: Opt) -> anyhow::Result<()> {
+    match opt.command {
+        Some(Command::Top(args)) => return top::run(args).await,
+        Some(Command::Export(args)) => return export::run(args).await,
+        Some(Command::Bench(args)) => return bench::run(args).await,
+        None => {}
+    }
+
+    logging::init(opt.log_format);
+
+    if let Some(path) = &opt.log_file {
+        daemon::reopen_log_on_sighup(path.clone())?;
+    }
 
     // Bump the memlock rlimit. This is needed for older kernels that don't use the
     // new memcg based accounting, see https://lwn.net/Articles/837122/
@@ -31,23 +284,124 @@ async fn main() -> anyhow::Result<()> {
         debug!("remove limit on locked memory failed, ret is: {ret}");
     }
 
+    // 记录 xnet 所在网络命名空间，用于区分其他命名空间中 ifindex 相同的设备
+    netns::init();
+    let netns_cookie = netns::cookie_supported() as u8;
+    let tcp_state_offsets = tcpstate::offsets();
+
+    // 校验模式使用临时的固定目录，不影响正在运行的实例
+    let pin_path = if opt.dry_run {
+        validate::pin_path(&opt.pin_path)
+    } else {
+        opt.pin_path.clone()
+    };
+
     // 加载eBPF程序
-    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
-        env!("OUT_DIR"),
-        "/xnet"
-    )))?;
+    let mut ebpf = reload::init(reload::LoaderOptions {
+        pin_path: pin_path.clone(),
+        netns_cookie,
+        tcp_state_offsets,
+        map_sizes: opt.map_sizes.clone(),
+    })
+    .await?;
 
     // 初始化 eBPF 日志
     if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
         warn!("failed to initialize eBPF logger: {e}");
     }
 
+    // 对端模式配置
+    let peer_config = if opt.peers.is_empty() {
+        None
+    } else {
+        Some(peer::PeerConfig {
+            listen: opt.peer_listen,
+            interval: std::time::Duration::from_millis(opt.peer_interval_ms),
+            peers: opt.peers.clone(),
+        })
+    };
+
+    let options = server::ServeOptions {
+        interval: std::time::Duration::from_secs(opt.interval_secs),
+        stats_refresh: std::time::Duration::from_millis(opt.stats_refresh_ms),
+        history_retention: std::time::Duration::from_secs(opt.history_retention_secs),
+        sqlite: opt.sqlite_path.clone().map(|path| sqlite::SqliteConfig {
+            path,
+            retention: sqlite::Retention {
+                raw: std::time::Duration::from_secs(opt.sqlite_retention_raw_secs),
+                minute: std::time::Duration::from_secs(opt.sqlite_retention_1m_secs),
+                hour: std::time::Duration::from_secs(opt.sqlite_retention_1h_secs),
+            },
+        }),
+        otlp: opt.otlp,
+        tls: opt
+            .tls_cert
+            .clone()
+            .zip(opt.tls_key.clone())
+            .map(|(cert, key)| tls::TlsOptions {
+                cert,
+                key,
+                client_ca: opt.tls_client_ca.clone(),
+            }),
+        unix_socket: opt.unix_socket.clone().map(|path| unix_socket::UnixSocketOptions {
+            path,
+            mode: opt.unix_socket_mode,
+        }),
+        listen: if opt.no_tcp { Vec::new() } else { opt.listen.clone() },
+        auth: auth::ApiAuth::load(opt.api_token.clone(), opt.api_token_file.as_deref())?,
+        anomaly: anomaly::AnomalyConfig {
+            alpha: opt.anomaly_alpha,
+            multiple: opt.anomaly_multiple,
+        },
+        peer_config,
+        sock_ops_cgroup: opt.sock_ops_cgroup.clone(),
+        ssl_lib: opt.ssl_lib.clone(),
+        egress_policy: opt.egress_policy,
+        dns_inspect: opt.dns_inspect,
+        socket_owners: opt.socket_owners,
+        firewall_rules_file: opt.firewall_rules_file.clone(),
+        rules_dir: opt.rules_dir.clone(),
+        firewall_allowlist_file: opt.firewall_allowlist_file.clone(),
+        plugins_file: opt.plugins_file.clone(),
+        map_pressure_percent: opt.map_pressure_percent,
+        bogon_prefixes_file: opt.bogon_prefixes_file.clone(),
+        blocklist_feeds_file: opt.blocklist_feeds_file.clone(),
+        geoip_dir: opt.geoip_dir.clone(),
+        sampling: opt.sampling.clone(),
+        skip_marks: opt.skip_mark.clone(),
+        attach: opt.attach.clone(),
+        capture_dir: opt.capture_dir.clone(),
+    };
+
+    // 校验模式在此退出
+    if opt.dry_run {
+        let result = validate::run(ebpf, &options).await;
+        validate::cleanup(&pin_path);
+        return result;
+    }
+
+    // sFlow 包采样导出
+    if let Some(collector) = opt.sflow_collector {
+        sflow::start(&mut ebpf, collector, opt.sflow_agent, opt.sample_rate).await?;
+    }
+
+    // 内核推送的连接、规则命中和告警事件
+    if let Err(e) = kernel_events::start(&mut ebpf) {
+        warn!("failed to read kernel events: {e}");
+    }
+
+    // OTLP 连接 trace 导出
+    if opt.otlp {
+        otlp::start_traces(&mut ebpf).await?;
+    }
+
     let _opt = opt;
 
     // server
-    if let Err(err) = server::serve(ebpf).await {
+    if let Err(err) = server::serve(ebpf, options).await {
         warn!("failed to start server: {err}");
     }
+    systemd::stopping();
 
     Ok(())
 }
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::os::fd::AsFd;
+
+use aya::maps::{HashMap as AyaHashMap, Map, MapData};
+use aya::Ebpf;
+use xnet_common::{
+    DeviceCastStats, DeviceConnectionStats, DeviceStats, DeviceStatsKey, Icmpv6Stats, PortStats,
+    ProtocolStats, ProtocolStatsKey,
+};
+
+// 复制 map 的文件描述符，新句柄与 Ebpf 中的句柄指向同一个内核 map
+fn dup(data: &MapData) -> Result<MapData, anyhow::Error> {
+    Ok(MapData::from_fd(data.fd().as_fd().try_clone_to_owned()?)?)
+}
+
+// 只缓存读统计和规则用到的 map 类型，ring buffer、程序数组等仍通过 Ebpf 访问
+fn clone_map(map: &Map) -> Option<Result<Map, anyhow::Error>> {
+    let cloned = match map {
+        Map::Array(data) => dup(data).map(Map::Array),
+        Map::HashMap(data) => dup(data).map(Map::HashMap),
+        Map::LruHashMap(data) => dup(data).map(Map::LruHashMap),
+        Map::LpmTrie(data) => dup(data).map(Map::LpmTrie),
+        Map::PerCpuArray(data) => dup(data).map(Map::PerCpuArray),
+        Map::PerCpuHashMap(data) => dup(data).map(Map::PerCpuHashMap),
+        Map::PerCpuLruHashMap(data) => dup(data).map(Map::PerCpuLruHashMap),
+        _ => return None,
+    };
+    Some(cloned)
+}
+
+// 按 key/value 类型打开 hash map，大小与 xnet-common 中的定义不一致时返回错误
+pub fn typed<K, V>(ebpf: &Ebpf, name: &str) -> Result<AyaHashMap<MapData, K, V>, anyhow::Error>
+where
+    K: aya::Pod,
+    V: aya::Pod,
+{
+    let map = ebpf
+        .map(name)
+        .and_then(clone_map)
+        .ok_or_else(|| anyhow::anyhow!("{} map not found", name))?
+        .map_err(|e| anyhow::anyhow!("failed to open map {}: {}", name, e))?;
+    AyaHashMap::try_from(map).map_err(|e| anyhow::anyhow!("map {} schema mismatch: {}", name, e))
+}
+
+// 流量统计读取的 map，每次查询时不再重复 try_from
+pub struct TrafficMaps {
+    pub total: AyaHashMap<MapData, u32, u64>,
+    pub ports: AyaHashMap<MapData, u16, PortStats>,
+    pub devices: AyaHashMap<MapData, DeviceStatsKey, DeviceStats>,
+    pub device_casts: AyaHashMap<MapData, DeviceStatsKey, DeviceCastStats>,
+    pub protocols: AyaHashMap<MapData, ProtocolStatsKey, ProtocolStats>,
+    pub icmpv6: AyaHashMap<MapData, DeviceStatsKey, Icmpv6Stats>,
+    // key 为连接哈希
+    pub connections: AyaHashMap<MapData, u32, DeviceConnectionStats>,
+}
+
+impl TrafficMaps {
+    fn new(ebpf: &Ebpf) -> Result<Self, anyhow::Error> {
+        Ok(TrafficMaps {
+            total: typed(ebpf, "total_stats")?,
+            ports: typed(ebpf, "port_stats")?,
+            devices: typed(ebpf, "device_stats")?,
+            device_casts: typed(ebpf, "device_cast_stats")?,
+            protocols: typed(ebpf, "protocol_stats")?,
+            icmpv6: typed(ebpf, "icmpv6_stats")?,
+            connections: typed(ebpf, "device_connection_stats")?,
+        })
+    }
+}
+
+// 启动和热重载时打开的 map 句柄，读取统计时不需要获取 Ebpf 的锁
+// 热重载沿用固定的 map，句柄在重载前后指向同一个内核 map
+pub struct MapCache {
+    maps: HashMap<String, Map>,
+    pub traffic: TrafficMaps,
+}
+
+impl MapCache {
+    pub fn new(ebpf: &Ebpf) -> Result<Self, anyhow::Error> {
+        let mut maps = HashMap::new();
+        for (name, map) in ebpf.maps() {
+            if let Some(cloned) = clone_map(map) {
+                let cloned =
+                    cloned.map_err(|e| anyhow::anyhow!("failed to open map {}: {}", name, e))?;
+                maps.insert(name.to_string(), cloned);
+            }
+        }
+        Ok(MapCache {
+            maps,
+            traffic: TrafficMaps::new(ebpf)?,
+        })
+    }
+
+    pub fn map(&self, name: &str) -> Option<&Map> {
+        self.maps.get(name)
+    }
+
+    pub fn maps(&self) -> impl Iterator<Item = (&str, &Map)> {
+        self.maps.iter().map(|(name, map)| (name.as_str(), map))
+    }
+}
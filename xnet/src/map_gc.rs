@@ -0,0 +1,78 @@
+use std::os::fd::{AsFd, AsRawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use aya::maps::{Array, MapData};
+use aya::programs::SchedClassifier;
+use aya::Ebpf;
+use tracing::info;
+
+use crate::bpf_sys::{bpf, TestRunAttr, BPF_PROG_TEST_RUN};
+use crate::server::EbpfManager;
+
+// skb 程序测试运行要求输入至少包含以太网头
+const TEST_PACKET_LEN: usize = 64;
+
+// 内核中的定时器启动后置位，之后连接跟踪的过期条目由内核删除
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// 加载 xnet_map_gc 并运行一次，由其在内核中启动周期清理连接跟踪的定时器
+// 内核不支持 bpf_timer (5.15 以下) 或 map 缺少 BTF 时返回错误，由用户空间继续清理
+pub async fn start(ebpf_manager: &EbpfManager, interval: Duration) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+
+    let mut config = Array::<&mut MapData, u64>::try_from(
+        ebpf.map_mut("gc_config")
+            .ok_or_else(|| anyhow::anyhow!("gc_config map not found"))?,
+    )?;
+    config.set(0, interval.as_nanos() as u64, 0)?;
+
+    run(&mut ebpf)?;
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("连接跟踪改由内核定时器每 {:?} 清理", interval);
+    Ok(())
+}
+
+// 热重载时在新的目标文件中重新启动定时器，gc_config 固定在 bpffs 中沿用原来的间隔
+// 旧的定时器随旧的 eBPF 实例释放
+pub fn reattach(new: &mut Ebpf) -> Result<(), anyhow::Error> {
+    if !enabled() {
+        return Ok(());
+    }
+    run(new)
+}
+
+fn run(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    let program: &mut SchedClassifier = ebpf
+        .program_mut("xnet_map_gc")
+        .ok_or_else(|| anyhow::anyhow!("xnet_map_gc program not found"))?
+        .try_into()?;
+    program.load()?;
+
+    let data = [0u8; TEST_PACKET_LEN];
+    let mut attr = TestRunAttr {
+        prog_fd: program.fd()?.as_fd().as_raw_fd() as u32,
+        data_size_in: data.len() as u32,
+        data_in: data.as_ptr() as u64,
+        repeat: 1,
+        ..Default::default()
+    };
+    unsafe { bpf(BPF_PROG_TEST_RUN, &mut attr) }?;
+    if attr.retval != 0 {
+        return Err(anyhow::anyhow!("xnet_map_gc returned {}", attr.retval));
+    }
+    Ok(())
+}
+
+// 内核定时器累计删除的条目数
+pub fn removed_total(ebpf: &aya::Ebpf) -> Result<u64, anyhow::Error> {
+    let stats = Array::<&MapData, u64>::try_from(
+        ebpf.map("gc_stats")
+            .ok_or_else(|| anyhow::anyhow!("gc_stats map not found"))?,
+    )?;
+    Ok(stats.get(&1, 0)?)
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeSet;
+use std::os::fd::{AsFd, AsRawFd};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aya::maps::{Map, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::bpf_sys::{bpf, ElemAttr, BPF_MAP_GET_NEXT_KEY, BPF_MAP_LOOKUP_ELEM};
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+
+// map 的填充情况，只统计条目按需插入的 map，数组类 map 始终是满的
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MapUsage {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub entries: u32,
+    pub max_entries: u32,
+    pub utilization: f64,
+}
+
+lazy_static::lazy_static! {
+    // 后台任务最近一次统计的结果
+    static ref USAGE: Mutex<Vec<MapUsage>> = Mutex::new(Vec::new());
+}
+
+// map 的底层数据及类型名
+fn map_data(map: &Map) -> (&MapData, &'static str) {
+    match map {
+        Map::Array(m) => (m, "array"),
+        Map::BloomFilter(m) => (m, "bloom_filter"),
+        Map::CpuMap(m) => (m, "cpu_map"),
+        Map::DevMap(m) => (m, "dev_map"),
+        Map::DevMapHash(m) => (m, "dev_map_hash"),
+        Map::HashMap(m) => (m, "hash"),
+        Map::LpmTrie(m) => (m, "lpm_trie"),
+        Map::LruHashMap(m) => (m, "lru_hash"),
+        Map::PerCpuArray(m) => (m, "percpu_array"),
+        Map::PerCpuHashMap(m) => (m, "percpu_hash"),
+        Map::PerCpuLruHashMap(m) => (m, "lru_percpu_hash"),
+        Map::PerfEventArray(m) => (m, "perf_event_array"),
+        Map::ProgramArray(m) => (m, "prog_array"),
+        Map::Queue(m) => (m, "queue"),
+        Map::RingBuf(m) => (m, "ringbuf"),
+        Map::SockHash(m) => (m, "sock_hash"),
+        Map::SockMap(m) => (m, "sock_map"),
+        Map::Stack(m) => (m, "stack"),
+        Map::StackTraceMap(m) => (m, "stack_trace"),
+        Map::Unsupported(m) => (m, "unsupported"),
+        Map::XskMap(m) => (m, "xsk_map"),
+    }
+}
+
+// 可以按 key 遍历并读出值的 map 类型
+fn dumpable(kind: &str) -> bool {
+    matches!(
+        kind,
+        "array"
+            | "hash"
+            | "lru_hash"
+            | "lpm_trie"
+            | "percpu_array"
+            | "percpu_hash"
+            | "lru_percpu_hash"
+    )
+}
+
+// 条目按需插入、可能被填满的 map 类型
+fn fillable(kind: &str) -> bool {
+    matches!(
+        kind,
+        "hash" | "lru_hash" | "lpm_trie" | "percpu_hash" | "lru_percpu_hash" | "dev_map_hash"
+    )
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 4 或 8 字节的值按本机字节序解释为计数
+fn counter(bytes: &[u8]) -> Option<u64> {
+    match bytes.len() {
+        4 => Some(u32::from_ne_bytes(bytes.try_into().ok()?) as u64),
+        8 => Some(u64::from_ne_bytes(bytes.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+pub fn describe(owner: &str, name: &str, map: &Map) -> Value {
+    let (data, kind) = map_data(map);
+    let info = data.info().ok();
+    serde_json::json!({
+        "owner": owner,
+        "name": name,
+        "type": kind,
+        "key_size": info.as_ref().map(|i| i.key_size()),
+        "value_size": info.as_ref().map(|i| i.value_size()),
+        "max_entries": info.as_ref().map(|i| i.max_entries()),
+    })
+}
+
+// 按原始字节读出 map 的前 limit 个条目，key 和 value 为十六进制，
+// 4/8 字节的值同时给出 counter，per-CPU map 的 counter 为各 CPU 之和
+pub fn dump(map: &Map, limit: usize) -> Result<Result<Vec<Value>, String>, anyhow::Error> {
+    let (data, kind) = map_data(map);
+    if !dumpable(kind) {
+        return Ok(Err(format!("{} map cannot be dumped", kind)));
+    }
+    let info = data.info()?;
+    let fd = data.fd().as_fd().as_raw_fd() as u32;
+    let key_size = info.key_size() as usize;
+    let value_size = info.value_size() as usize;
+    let percpu = kind.contains("percpu");
+    // per-CPU map 每个 CPU 的值按 8 字节对齐
+    let (cpus, stride) = if percpu {
+        let cpus = aya::util::nr_cpus().map_err(|(_, e)| e)?;
+        (cpus, value_size.div_ceil(8) * 8)
+    } else {
+        (1, value_size)
+    };
+
+    let mut key = vec![0u8; key_size];
+    let mut next = vec![0u8; key_size];
+    let mut value = vec![0u8; stride * cpus];
+    let mut entries = Vec::new();
+    let mut first = true;
+    while entries.len() < limit {
+        let mut attr = ElemAttr {
+            map_fd: fd,
+            key: if first { 0 } else { key.as_ptr() as u64 },
+            value: next.as_mut_ptr() as u64,
+            ..Default::default()
+        };
+        match unsafe { bpf(BPF_MAP_GET_NEXT_KEY, &mut attr) } {
+            Ok(_) => {}
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => break,
+            Err(e) => return Err(e.into()),
+        }
+        first = false;
+        key.copy_from_slice(&next);
+
+        let mut attr = ElemAttr {
+            map_fd: fd,
+            key: key.as_ptr() as u64,
+            value: value.as_mut_ptr() as u64,
+            ..Default::default()
+        };
+        match unsafe { bpf(BPF_MAP_LOOKUP_ELEM, &mut attr) } {
+            Ok(_) => {}
+            // 遍历过程中被删除的条目
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        let values: Vec<&[u8]> = value
+            .chunks(stride)
+            .map(|chunk| &chunk[..value_size])
+            .collect();
+        let counter: Option<u64> = values.iter().map(|v| counter(v)).sum();
+        let value_json = if percpu {
+            Value::from(values.iter().map(|v| to_hex(v)).collect::<Vec<_>>())
+        } else {
+            Value::from(to_hex(values[0]))
+        };
+        entries.push(serde_json::json!({
+            "key": to_hex(&key),
+            "value": value_json,
+            "counter": counter,
+        }));
+    }
+    Ok(Ok(entries))
+}
+
+// 遍历 key 统计条目数，遍历过程中条目被删除时会从头开始，最多数到 max_entries
+fn count_entries(data: &MapData, key_size: usize, max_entries: u32) -> Result<u32, std::io::Error> {
+    let fd = data.fd().as_fd().as_raw_fd() as u32;
+    let mut key = vec![0u8; key_size];
+    let mut next = vec![0u8; key_size];
+    let mut count = 0;
+    while count < max_entries {
+        let mut attr = ElemAttr {
+            map_fd: fd,
+            key: if count == 0 { 0 } else { key.as_ptr() as u64 },
+            value: next.as_mut_ptr() as u64,
+            ..Default::default()
+        };
+        match unsafe { bpf(BPF_MAP_GET_NEXT_KEY, &mut attr) } {
+            Ok(_) => {}
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => break,
+            Err(e) => return Err(e),
+        }
+        key.copy_from_slice(&next);
+        count += 1;
+    }
+    Ok(count)
+}
+
+// 统计 xnet 各 map 的条目数及占 max_entries 的比例
+pub fn usage(maps: &MapCache) -> Vec<MapUsage> {
+    let mut usage: Vec<MapUsage> = maps
+        .maps()
+        .filter_map(|(name, map)| {
+            let (data, kind) = map_data(map);
+            if !fillable(kind) {
+                return None;
+            }
+            let info = data.info().ok()?;
+            let max_entries = info.max_entries();
+            let entries = count_entries(data, info.key_size() as usize, max_entries).ok()?;
+            Some(MapUsage {
+                name: name.to_string(),
+                kind,
+                entries,
+                max_entries,
+                utilization: entries as f64 / max_entries.max(1) as f64,
+            })
+        })
+        .collect();
+    usage.sort_by(|a, b| a.name.cmp(&b.name));
+    usage
+}
+
+pub async fn cached_usage() -> Vec<MapUsage> {
+    USAGE.lock().await.clone()
+}
+
+// 定期统计 map 的填充率，超过 threshold_percent 时告警，回落后记录恢复
+// 填满的 hash map 会拒绝新条目，统计因此静默丢失
+pub fn start(ebpf_manager: Arc<EbpfManager>, interval: Duration, threshold_percent: u8) {
+    let threshold = threshold_percent as f64 / 100.0;
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut pressured = BTreeSet::new();
+        loop {
+            ticker.tick().await;
+            let usage = {
+                let maps = ebpf_manager.maps();
+                usage(&maps)
+            };
+            for map in &usage {
+                if map.utilization >= threshold {
+                    if pressured.insert(map.name.clone()) {
+                        warn!(
+                            "map {} 已使用 {}/{} ({:.0}%)，填满后新条目将被丢弃",
+                            map.name,
+                            map.entries,
+                            map.max_entries,
+                            map.utilization * 100.0
+                        );
+                    }
+                } else if pressured.remove(&map.name) {
+                    info!(
+                        "map {} 使用率回落到 {:.0}%",
+                        map.name,
+                        map.utilization * 100.0
+                    );
+                }
+            }
+            *USAGE.lock().await = usage;
+        }
+    });
+}
+
+// 指标名、说明及取值
+type Metric = (&'static str, &'static str, fn(&MapUsage) -> String);
+
+// Prometheus 文本格式的 map 使用情况
+pub fn prometheus(usage: &[MapUsage]) -> String {
+    let mut out = String::new();
+    let metrics: [Metric; 3] = [
+        (
+            "xnet_map_entries",
+            "Current number of entries in the map",
+            |m| m.entries.to_string(),
+        ),
+        ("xnet_map_max_entries", "Capacity of the map", |m| {
+            m.max_entries.to_string()
+        }),
+        (
+            "xnet_map_utilization_ratio",
+            "Entries divided by max_entries",
+            |m| format!("{:.6}", m.utilization),
+        ),
+    ];
+    for (name, help, value) in metrics {
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} gauge\n",
+            name, help, name
+        ));
+        for map in usage {
+            out.push_str(&format!(
+                "{}{{map=\"{}\",type=\"{}\"}} {}\n",
+                name,
+                map.name,
+                map.kind,
+                value(map)
+            ));
+        }
+    }
+    out
+}
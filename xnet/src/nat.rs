@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{NatKey, NatRewrite, NAT_KIND_DNAT, NAT_KIND_SNAT};
+
+use crate::lb::L4Protocol;
+use crate::server::EbpfManager;
+
+// 与 eBPF 中 NAT_RULES 的 max_entries 一致
+const MAX_RULES: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NatKind {
+    Dnat,
+    Snat,
+}
+
+// 无状态NAT规则: DNAT 把目的 match_ip:match_port 改写为 to_ip:to_port，SNAT 改写源地址
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NatRule {
+    pub kind: NatKind,
+    pub protocol: L4Protocol,
+    pub match_ip: Ipv4Addr,
+    pub match_port: u16,
+    pub to_ip: Ipv4Addr,
+    // 不指定时保持原端口
+    pub to_port: Option<u16>,
+}
+
+impl NatRule {
+    fn key(&self) -> NatKey {
+        NatKey {
+            addr: u32::from(self.match_ip).to_be(),
+            port: self.match_port.to_be(),
+            protocol: self.protocol.number(),
+            kind: match self.kind {
+                NatKind::Dnat => NAT_KIND_DNAT,
+                NatKind::Snat => NAT_KIND_SNAT,
+            },
+        }
+    }
+
+    fn rewrite(&self) -> NatRewrite {
+        NatRewrite {
+            addr: u32::from(self.to_ip).to_be(),
+            port: self.to_port.unwrap_or(0).to_be(),
+            reserved: 0,
+            packets: 0,
+        }
+    }
+}
+
+// 规则文件中的一条规则
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StoredNatRule {
+    pub id: u32,
+    #[serde(flatten)]
+    pub rule: NatRule,
+}
+
+lazy_static::lazy_static! {
+    // 规则ID -> 规则
+    static ref NAT_RULES: Mutex<BTreeMap<u32, NatRule>> = Mutex::new(BTreeMap::new());
+    // 设置后每次修改规则都写入该文件
+    static ref RULES_FILE: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+}
+
+fn nat_map(
+    ebpf: &mut aya::Ebpf,
+) -> Result<AyaHashMap<&mut MapData, NatKey, NatRewrite>, anyhow::Error> {
+    Ok(AyaHashMap::try_from(
+        ebpf.map_mut("NAT_RULES")
+            .ok_or_else(|| anyhow::anyhow!("NAT_RULES map not found"))?,
+    )?)
+}
+
+fn stored(rules: &BTreeMap<u32, NatRule>) -> Vec<StoredNatRule> {
+    rules
+        .iter()
+        .map(|(id, rule)| StoredNatRule {
+            id: *id,
+            rule: rule.clone(),
+        })
+        .collect()
+}
+
+// 写入规则文件，未设置规则文件时不保存
+fn save(rules: &BTreeMap<u32, NatRule>) -> Result<(), anyhow::Error> {
+    let Some(path) = RULES_FILE.lock().unwrap().clone() else {
+        return Ok(());
+    };
+    crate::rules::write_file(&path, &serde_json::to_string_pretty(&stored(rules))?)
+}
+
+// 校验一组规则，规则ID和匹配条件都不能重复
+pub(crate) fn validate(stored: Vec<StoredNatRule>) -> Result<BTreeMap<u32, NatRule>, String> {
+    let mut rules = BTreeMap::new();
+    let mut keys = HashSet::new();
+    for StoredNatRule { id, rule } in stored {
+        if id >= MAX_RULES {
+            return Err(format!("nat rule id {} out of range", id));
+        }
+        if !keys.insert(rule.key()) {
+            return Err(format!(
+                "nat rule {} duplicates the match of another rule",
+                id
+            ));
+        }
+        if rules.insert(id, rule).is_some() {
+            return Err(format!("duplicate nat rule id {}", id));
+        }
+    }
+    Ok(rules)
+}
+
+// 读取并校验规则文件，文件不存在时为空
+pub(crate) fn read_rules(path: &Path) -> Result<BTreeMap<u32, NatRule>, anyhow::Error> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let stored: Vec<StoredNatRule> = serde_json::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?;
+    validate(stored).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+// 从规则文件加载规则并写入 NAT_RULES，文件不存在时从空规则开始
+pub async fn load(ebpf_manager: &EbpfManager, path: &Path) -> Result<(), anyhow::Error> {
+    *RULES_FILE.lock().unwrap() = Some(path.to_path_buf());
+    let stored = read_rules(path)?;
+    let mut rules = NAT_RULES.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = nat_map(&mut ebpf)?;
+    for (id, rule) in stored {
+        map.insert(rule.key(), rule.rewrite(), 0)?;
+        rules.insert(id, rule);
+    }
+    info!("从 {} 加载了 {} 条NAT规则", path.display(), rules.len());
+    Ok(())
+}
+
+// 列出所有NAT规则及命中的包数
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let rules = NAT_RULES.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, NatKey, NatRewrite>::try_from(
+        maps.map("NAT_RULES")
+            .ok_or_else(|| anyhow::anyhow!("NAT_RULES map not found"))?,
+    )?;
+
+    Ok(rules
+        .iter()
+        .map(|(id, rule)| {
+            let packets = map.get(&rule.key(), 0).map(|r| r.packets).unwrap_or(0);
+            serde_json::json!({
+                "id": id,
+                "kind": rule.kind,
+                "protocol": rule.protocol,
+                "match_ip": rule.match_ip,
+                "match_port": rule.match_port,
+                "to_ip": rule.to_ip,
+                "to_port": rule.to_port,
+                "packets": packets,
+            })
+        })
+        .collect())
+}
+
+// 新增NAT规则，匹配条件相同的规则会被替换，返回规则ID
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    rule: NatRule,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    let mut rules = NAT_RULES.lock().await;
+    let key = rule.key();
+    let existing = rules
+        .iter()
+        .find(|(_, r)| r.key() == key)
+        .map(|(id, _)| *id);
+    let id = match existing.or_else(|| (0..MAX_RULES).find(|id| !rules.contains_key(id))) {
+        Some(id) => id,
+        None => return Ok(Err(format!("at most {} nat rules", MAX_RULES))),
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    nat_map(&mut ebpf)?.insert(key, rule.rewrite(), 0)?;
+
+    info!(
+        rule_id = id,
+        action = ?rule.kind,
+        "NAT规则 {}: {:?} {:?} {}:{} -> {}:{:?}",
+        id, rule.kind, rule.protocol, rule.match_ip, rule.match_port, rule.to_ip, rule.to_port
+    );
+    let action = if rules.insert(id, rule).is_some() {
+        "update"
+    } else {
+        "create"
+    };
+    save(&rules)?;
+    crate::events::rule_change("nat", id as u64, action);
+    Ok(Ok(id))
+}
+
+// 删除NAT规则，规则不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut rules = NAT_RULES.lock().await;
+    let Some(rule) = rules.remove(&id) else {
+        return Ok(false);
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    nat_map(&mut ebpf)?.remove(&rule.key())?;
+    save(&rules)?;
+    info!(rule_id = id, action = "delete", "NAT规则 {} 已删除", id);
+    crate::events::rule_change("nat", id as u64, "delete");
+    Ok(true)
+}
+
+// 当前的全部规则，格式与规则文件相同
+pub async fn export() -> Vec<StoredNatRule> {
+    stored(&*NAT_RULES.lock().await)
+}
+
+// 用已校验的规则整体替换现有规则，先写入新规则再删除不再需要的匹配条件，
+// 两组规则中都有的匹配条件在替换过程中一直生效
+pub async fn replace_all(
+    ebpf_manager: &EbpfManager,
+    new: BTreeMap<u32, NatRule>,
+) -> Result<(), anyhow::Error> {
+    let mut rules = NAT_RULES.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = nat_map(&mut ebpf)?;
+    for rule in new.values() {
+        map.insert(rule.key(), rule.rewrite(), 0)?;
+    }
+    let keys: HashSet<NatKey> = new.values().map(NatRule::key).collect();
+    for rule in rules.values() {
+        if !keys.contains(&rule.key()) {
+            map.remove(&rule.key())?;
+        }
+    }
+    *rules = new;
+    save(&rules)?;
+    info!("NAT规则已整体替换为 {} 条", rules.len());
+    crate::events::rules_replaced("nat", rules.len());
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use aya::util::KernelVersion;
+use tracing::info;
+
+lazy_static::lazy_static! {
+    // netns cookie -> 命名空间 inode，cookie 由 eBPF 程序通过 bpf_get_netns_cookie 记录，0 表示内核未记录 cookie
+    // inode 与 lsns、ip netns identify 输出的一致
+    static ref NAMESPACES: std::sync::Mutex<HashMap<u64, u64>> = std::sync::Mutex::new(HashMap::new());
+    // xnet 所在命名空间的 inode
+    static ref HOST_INODE: u64 = std::fs::metadata("/proc/self/ns/net").map(|m| m.ino()).unwrap_or(0);
+}
+
+// tc 程序从 6.15 起可以调用 bpf_get_netns_cookie，更早的内核上统计不区分命名空间
+pub fn cookie_supported() -> bool {
+    KernelVersion::current()
+        .map(|version| version >= KernelVersion::new(6, 15, 0))
+        .unwrap_or(false)
+}
+
+// 当前线程所在命名空间的 cookie
+fn current_cookie() -> std::io::Result<u64> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    let mut cookie: u64 = 0;
+    let mut len = std::mem::size_of::<u64>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NETNS_COOKIE,
+            &mut cookie as *mut u64 as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cookie)
+}
+
+// 记录 xnet 所在命名空间
+pub fn init() {
+    let mut namespaces = NAMESPACES.lock().unwrap();
+    namespaces.insert(0, *HOST_INODE);
+    if let Ok(cookie) = current_cookie() {
+        namespaces.insert(cookie, *HOST_INODE);
+    }
+}
+
+// 在指定命名空间中执行 f，并记录该命名空间的 cookie
+// setns 只影响调用线程，因此在独立线程中执行，netlink 和 ioctl 等操作都发生在目标命名空间内
+pub fn run_in<T: Send>(
+    path: &Path,
+    f: impl FnOnce() -> Result<T, anyhow::Error> + Send,
+) -> Result<T, anyhow::Error> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("failed to open netns {}: {}", path.display(), e))?;
+    let inode = file.metadata()?.ino();
+
+    let (result, cookie) = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+                    return Err(anyhow::anyhow!(
+                        "failed to enter netns {}: {}",
+                        path.display(),
+                        std::io::Error::last_os_error()
+                    ));
+                }
+                let cookie = current_cookie()?;
+                Ok((f()?, cookie))
+            })
+            .join()
+            .map_err(|_| anyhow::anyhow!("netns thread panicked"))?
+    })?;
+
+    let mut namespaces = NAMESPACES.lock().unwrap();
+    if namespaces.insert(cookie, inode).is_none() {
+        info!(
+            "网络命名空间 {} inode={} cookie={}",
+            path.display(),
+            inode,
+            cookie
+        );
+    }
+    Ok(result)
+}
+
+// 命名空间 inode，未知的 cookie 返回 None
+pub fn inode_of(cookie: u64) -> Option<u64> {
+    NAMESPACES.lock().unwrap().get(&cookie).copied()
+}
+
+// 命名空间文件的 inode
+pub fn inode_of_path(path: &Path) -> Result<u64, anyhow::Error> {
+    Ok(std::fs::metadata(path)
+        .map_err(|e| anyhow::anyhow!("failed to stat netns {}: {}", path.display(), e))?
+        .ino())
+}
+
+pub fn host_inode() -> u64 {
+    *HOST_INODE
+}
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::firewall::{
+    Direction, FirewallAction, FirewallProtocol, FirewallRule, PortMatch, StoredRule,
+};
+use crate::server::EbpfManager;
+
+// 一条 nft 规则中的集合展开后最多生成的 xnet 规则数
+const MAX_EXPANSION: usize = 64;
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct NftImportQuery {
+    // 第一条导入规则的 priority，之后的规则按 nft 中的顺序依次加1，默认 1000
+    pub priority: Option<u32>,
+    // 为 true 时用导入的规则替换所有现有的防火墙规则
+    #[serde(default)]
+    pub replace: bool,
+    // 为 true 时只返回转换结果，不修改规则
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+// 规则在 nft 中的位置
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct NftSource {
+    pub family: String,
+    pub table: String,
+    pub chain: String,
+    pub handle: Option<u64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ConvertedRule {
+    pub source: NftSource,
+    #[serde(flatten)]
+    pub rule: FirewallRule,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SkippedRule {
+    pub source: NftSource,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Conversion {
+    pub rules: Vec<ConvertedRule>,
+    pub skipped: Vec<SkippedRule>,
+    // 无法转换为规则但需要注意的配置，例如链的默认策略
+    pub notes: Vec<String>,
+}
+
+// 一条 nft 规则中的匹配条件，集合中的每个值都是一个候选
+#[derive(Default)]
+struct Matches {
+    src: Option<Vec<String>>,
+    dst: Option<Vec<String>>,
+    src_port: Option<Vec<PortMatch>>,
+    dst_port: Option<Vec<PortMatch>>,
+    protocol: Option<FirewallProtocol>,
+}
+
+// 链的 hook 对应的方向，prerouting/input 在 XDP 防火墙匹配，output/postrouting 在 tc 出方向匹配
+fn direction(hook: &str) -> Option<Direction> {
+    match hook {
+        "prerouting" | "input" => Some(Direction::Ingress),
+        "output" | "postrouting" => Some(Direction::Egress),
+        _ => None,
+    }
+}
+
+// 匹配条件右侧的值，匿名集合 {a, b} 展开为多个值
+fn candidates(right: &Value) -> Result<Vec<&Value>, String> {
+    match right.get("set") {
+        Some(Value::Array(items)) => Ok(items.iter().collect()),
+        Some(_) => Err("unsupported set".to_string()),
+        None => Ok(vec![right]),
+    }
+}
+
+fn addr(value: &Value) -> Result<String, String> {
+    let cidr = match value {
+        Value::String(s) if s.starts_with('@') => {
+            return Err(format!("named set {} is not supported", s))
+        }
+        Value::String(s) => s.clone(),
+        Value::Object(o) => match o.get("prefix") {
+            Some(prefix) => format!(
+                "{}/{}",
+                prefix["addr"].as_str().unwrap_or_default(),
+                prefix["len"]
+            ),
+            None => return Err(format!("unsupported address {}", value)),
+        },
+        _ => return Err(format!("unsupported address {}", value)),
+    };
+    crate::firewall::parse_cidr(&cidr)?;
+    Ok(cidr)
+}
+
+fn port(value: &Value) -> Result<PortMatch, String> {
+    let number = |v: &Value| {
+        v.as_u64()
+            .and_then(|p| u16::try_from(p).ok())
+            .ok_or_else(|| format!("unsupported port {}", value))
+    };
+    match value.get("range") {
+        Some(Value::Array(range)) if range.len() == 2 => Ok(PortMatch::Range(format!(
+            "{}-{}",
+            number(&range[0])?,
+            number(&range[1])?
+        ))),
+        Some(_) => Err(format!("unsupported port {}", value)),
+        None => Ok(PortMatch::Port(number(value)?)),
+    }
+}
+
+fn protocol(name: &str) -> Result<FirewallProtocol, String> {
+    match name {
+        "tcp" => Ok(FirewallProtocol::Tcp),
+        "udp" => Ok(FirewallProtocol::Udp),
+        "icmp" => Ok(FirewallProtocol::Icmp),
+        _ => Err(format!("unsupported protocol {}", name)),
+    }
+}
+
+impl Matches {
+    fn set_protocol(&mut self, p: FirewallProtocol) -> Result<(), String> {
+        match self.protocol {
+            Some(existing) if existing != p => Err("conflicting protocol matches".to_string()),
+            _ => {
+                self.protocol = Some(p);
+                Ok(())
+            }
+        }
+    }
+
+    fn add(&mut self, expr: &Value) -> Result<(), String> {
+        let op = expr["op"].as_str().unwrap_or("==");
+        if op != "==" && op != "in" {
+            return Err(format!("match operator {} is not supported", op));
+        }
+        let left = &expr["left"];
+        let right = &expr["right"];
+
+        if let Some(meta) = left.get("meta") {
+            return match meta["key"].as_str() {
+                Some("l4proto") => self.set_protocol(protocol(right.as_str().unwrap_or_default())?),
+                Some("nfproto") if right == "ipv4" => Ok(()),
+                key => Err(format!(
+                    "meta {} match is not supported",
+                    key.unwrap_or("?")
+                )),
+            };
+        }
+        let Some(payload) = left.get("payload") else {
+            return Err(format!("match on {} is not supported", left));
+        };
+        let proto = payload["protocol"].as_str().unwrap_or_default();
+        let field = payload["field"].as_str().unwrap_or_default();
+        let values = candidates(right)?;
+        match (proto, field) {
+            ("ip", "saddr") | ("ip", "daddr") => {
+                let cidrs = values
+                    .into_iter()
+                    .map(addr)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let slot = if field == "saddr" {
+                    &mut self.src
+                } else {
+                    &mut self.dst
+                };
+                if slot.replace(cidrs).is_some() {
+                    return Err(format!("multiple ip {} matches", field));
+                }
+            }
+            ("ip", "protocol") => {
+                self.set_protocol(protocol(right.as_str().unwrap_or_default())?)?
+            }
+            ("tcp" | "udp" | "th", "sport" | "dport") => {
+                if proto != "th" {
+                    self.set_protocol(protocol(proto)?)?;
+                }
+                let ports = values
+                    .into_iter()
+                    .map(port)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let slot = if field == "sport" {
+                    &mut self.src_port
+                } else {
+                    &mut self.dst_port
+                };
+                if slot.replace(ports).is_some() {
+                    return Err(format!("multiple {} matches", field));
+                }
+            }
+            _ => return Err(format!("{} {} match is not supported", proto, field)),
+        }
+        Ok(())
+    }
+
+    // 按集合展开为多条规则
+    fn expand(
+        self,
+        direction: Direction,
+        action: FirewallAction,
+    ) -> Result<Vec<FirewallRule>, String> {
+        fn options<T: Clone>(values: Option<Vec<T>>) -> Vec<Option<T>> {
+            match values {
+                Some(values) => values.into_iter().map(Some).collect(),
+                None => vec![None],
+            }
+        }
+        let (src, dst) = (options(self.src), options(self.dst));
+        let (src_port, dst_port) = (options(self.src_port), options(self.dst_port));
+        let count = src.len() * dst.len() * src_port.len() * dst_port.len();
+        if count > MAX_EXPANSION {
+            return Err(format!(
+                "sets expand to {} rules, at most {}",
+                count, MAX_EXPANSION
+            ));
+        }
+        let mut rules = Vec::with_capacity(count);
+        for src in &src {
+            for dst in &dst {
+                for src_port in &src_port {
+                    for dst_port in &dst_port {
+                        rules.push(FirewallRule {
+                            priority: 0,
+                            src: src.clone(),
+                            dst: dst.clone(),
+                            src_port: src_port.clone(),
+                            dst_port: dst_port.clone(),
+                            protocol: self.protocol,
+                            direction,
+                            action,
+                            rate_pps: None,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(rules)
+    }
+}
+
+// 转换一条 nft 规则的表达式，只支持地址、协议和端口匹配以及 accept/drop
+fn convert_rule(exprs: &[Value], direction: Direction) -> Result<Vec<FirewallRule>, String> {
+    let mut matches = Matches::default();
+    for expr in exprs {
+        let Some((kind, body)) = expr.as_object().and_then(|o| o.iter().next()) else {
+            return Err(format!("unsupported expression {}", expr));
+        };
+        match kind.as_str() {
+            "match" => matches.add(body)?,
+            // 计数和日志不影响匹配结果
+            "counter" | "log" => {}
+            "accept" => return matches.expand(direction, FirewallAction::Allow),
+            "drop" => return matches.expand(direction, FirewallAction::Deny),
+            _ => return Err(format!("{} is not supported", kind)),
+        }
+    }
+    Err("no accept or drop verdict".to_string())
+}
+
+// 转换 `nft -j list ruleset` 的输出，不支持的规则记入 skipped，不影响其他规则
+pub fn convert(ruleset: &Value, priority: u32) -> Result<Conversion, String> {
+    let items = ruleset["nftables"]
+        .as_array()
+        .ok_or_else(|| "expected the output of nft -j list ruleset".to_string())?;
+
+    // (family, table, chain) -> 链
+    let chains: HashMap<(&str, &str, &str), &Value> = items
+        .iter()
+        .filter_map(|item| item.get("chain"))
+        .map(|chain| {
+            let key = (
+                chain["family"].as_str().unwrap_or_default(),
+                chain["table"].as_str().unwrap_or_default(),
+                chain["name"].as_str().unwrap_or_default(),
+            );
+            (key, chain)
+        })
+        .collect();
+
+    let mut conversion = Conversion::default();
+    for item in items {
+        if let Some(chain) = item.get("chain") {
+            if chain["policy"] == "drop" && chain["type"] == "filter" {
+                conversion.notes.push(format!(
+                    "chain {} {} {} has policy drop, use /firewall/default_deny for the same effect",
+                    chain["family"].as_str().unwrap_or_default(),
+                    chain["table"].as_str().unwrap_or_default(),
+                    chain["name"].as_str().unwrap_or_default(),
+                ));
+            }
+        }
+        let Some(rule) = item.get("rule") else {
+            continue;
+        };
+        let source = NftSource {
+            family: rule["family"].as_str().unwrap_or_default().to_string(),
+            table: rule["table"].as_str().unwrap_or_default().to_string(),
+            chain: rule["chain"].as_str().unwrap_or_default().to_string(),
+            handle: rule["handle"].as_u64(),
+        };
+        let chain = chains.get(&(
+            source.family.as_str(),
+            source.table.as_str(),
+            source.chain.as_str(),
+        ));
+        let result = if source.family != "ip" && source.family != "inet" {
+            Err(format!("family {} is not supported", source.family))
+        } else if let Some(chain) = chain.filter(|chain| chain["type"] != "filter") {
+            Err(format!(
+                "{} chains are not supported",
+                chain["type"].as_str().unwrap_or("regular")
+            ))
+        } else {
+            match chain
+                .and_then(|chain| chain["hook"].as_str())
+                .and_then(direction)
+            {
+                Some(direction) => {
+                    let exprs = rule["expr"]
+                        .as_array()
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
+                    convert_rule(exprs, direction)
+                }
+                None => Err("chain is not attached to an input or output hook".to_string()),
+            }
+        };
+        match result {
+            Ok(rules) => conversion
+                .rules
+                .extend(rules.into_iter().map(|rule| ConvertedRule {
+                    source: source.clone(),
+                    rule,
+                })),
+            Err(reason) => conversion.skipped.push(SkippedRule { source, reason }),
+        }
+    }
+    // 保持 nft 中的顺序
+    for (i, converted) in conversion.rules.iter_mut().enumerate() {
+        converted.rule.priority = priority.saturating_add(i as u32);
+    }
+    Ok(conversion)
+}
+
+// 导入 nft 规则集，返回转换结果，导入的规则带有新的规则ID
+pub async fn import(
+    ebpf_manager: &EbpfManager,
+    ruleset: &Value,
+    query: &NftImportQuery,
+) -> Result<Result<Value, String>, anyhow::Error> {
+    let conversion = match convert(ruleset, query.priority.unwrap_or(1000)) {
+        Ok(conversion) => conversion,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mut value = serde_json::json!(conversion);
+    if query.dry_run {
+        return Ok(Ok(value));
+    }
+
+    let rules: Vec<FirewallRule> = conversion.rules.into_iter().map(|c| c.rule).collect();
+    let ids: Vec<u32> = if query.replace {
+        let stored = rules
+            .into_iter()
+            .enumerate()
+            .map(|(id, rule)| StoredRule {
+                id: id as u32,
+                rule,
+            })
+            .collect();
+        let validated = match crate::firewall::validate(stored) {
+            Ok(validated) => validated,
+            Err(e) => return Ok(Err(e)),
+        };
+        let ids = validated.iter().map(|(id, _, _)| *id).collect();
+        crate::firewall::replace_all(ebpf_manager, validated).await?;
+        ids
+    } else {
+        match crate::firewall::create_all(ebpf_manager, rules).await? {
+            Ok(ids) => ids,
+            Err(e) => return Ok(Err(e)),
+        }
+    };
+    if let Some(rules) = value["rules"].as_array_mut() {
+        for (rule, id) in rules.iter_mut().zip(ids) {
+            rule["id"] = id.into();
+        }
+    }
+    Ok(Ok(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_nft_ruleset() {
+        let ruleset = serde_json::json!({"nftables": [
+            {"metadata": {"json_schema_version": 1}},
+            {"table": {"family": "inet", "name": "filter", "handle": 1}},
+            {"chain": {"family": "inet", "table": "filter", "name": "input", "handle": 1,
+                "type": "filter", "hook": "input", "prio": 0, "policy": "drop"}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 4, "expr": [
+                {"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": ["established", "related"]}},
+                {"accept": null}]}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 5, "expr": [
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}},
+                    "right": {"prefix": {"addr": "10.0.0.0", "len": 8}}}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}},
+                    "right": {"set": [22, {"range": [8000, 8100]}]}}},
+                {"counter": {"packets": 0, "bytes": 0}},
+                {"accept": null}]}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 6, "expr": [
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "daddr"}},
+                    "right": "192.168.1.1"}},
+                {"drop": null}]}},
+        ]});
+        let conversion = convert(&ruleset, 100).unwrap();
+
+        assert_eq!(conversion.notes.len(), 1);
+        assert_eq!(conversion.skipped.len(), 1);
+        assert_eq!(conversion.skipped[0].source.handle, Some(4));
+        assert_eq!(conversion.rules.len(), 3);
+
+        let ssh = &conversion.rules[0].rule;
+        assert_eq!(ssh.priority, 100);
+        assert_eq!(ssh.src.as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(ssh.protocol, Some(FirewallProtocol::Tcp));
+        assert_eq!(ssh.direction, Direction::Ingress);
+        assert_eq!(ssh.action, FirewallAction::Allow);
+        assert!(matches!(ssh.dst_port, Some(PortMatch::Port(22))));
+        assert!(
+            matches!(&conversion.rules[1].rule.dst_port, Some(PortMatch::Range(r)) if r == "8000-8100")
+        );
+
+        let drop = &conversion.rules[2];
+        assert_eq!(drop.source.handle, Some(6));
+        assert_eq!(drop.rule.priority, 102);
+        assert_eq!(drop.rule.dst.as_deref(), Some("192.168.1.1"));
+        assert_eq!(drop.rule.action, FirewallAction::Deny);
+    }
+}
@@ -0,0 +1,263 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{Array, MapData, RingBuf};
+use aya::Ebpf;
+use opentelemetry::metrics::Meter;
+use opentelemetry::trace::{Span, SpanKind, Tracer};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tokio::io::unix::AsyncFd;
+use tracing::{info, warn};
+use xnet_common::{ConnectionEvent, CONNECTION_CLOSE_RST};
+
+
+// 导出到 OTLP 的指标快照，由后台任务刷新，指标回调中只读取快照
+#[derive(Debug, Clone, Default)]
+struct MetricsSnapshot {
+    total_packets: u64,
+    total_bytes: u64,
+    // (设备名, 方向, 包数, 字节数)
+    devices: Vec<(String, String, u64, u64)>,
+    connections: u64,
+    retransmission_rate: f64,
+    blocked_ips: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref SNAPSHOT: std::sync::Mutex<MetricsSnapshot> = std::sync::Mutex::new(MetricsSnapshot::default());
+}
+
+fn snapshot() -> MetricsSnapshot {
+    SNAPSHOT.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// 未设置 OTEL_SERVICE_NAME 时服务名默认为 xnet
+fn resource() -> Resource {
+    let builder = Resource::builder();
+    if std::env::var("OTEL_SERVICE_NAME").is_ok() {
+        builder.build()
+    } else {
+        builder.with_service_name("xnet").build()
+    }
+}
+
+fn register_instruments(meter: &Meter) {
+    meter
+        .u64_observable_counter("xnet.packets")
+        .with_description("Total packets seen by the TC program")
+        .with_callback(|observer| observer.observe(snapshot().total_packets, &[]))
+        .build();
+    meter
+        .u64_observable_counter("xnet.bytes")
+        .with_unit("By")
+        .with_description("Total bytes seen by the TC program")
+        .with_callback(|observer| observer.observe(snapshot().total_bytes, &[]))
+        .build();
+    meter
+        .u64_observable_counter("xnet.device.packets")
+        .with_description("Packets per attached device and direction")
+        .with_callback(|observer| {
+            for (device, direction, packets, _) in snapshot().devices {
+                observer.observe(
+                    packets,
+                    &[
+                        KeyValue::new("device", device),
+                        KeyValue::new("direction", direction),
+                    ],
+                );
+            }
+        })
+        .build();
+    meter
+        .u64_observable_counter("xnet.device.bytes")
+        .with_unit("By")
+        .with_description("Bytes per attached device and direction")
+        .with_callback(|observer| {
+            for (device, direction, _, bytes) in snapshot().devices {
+                observer.observe(
+                    bytes,
+                    &[
+                        KeyValue::new("device", device),
+                        KeyValue::new("direction", direction),
+                    ],
+                );
+            }
+        })
+        .build();
+    meter
+        .u64_observable_gauge("xnet.connections")
+        .with_description("Tracked connections on attached devices")
+        .with_callback(|observer| observer.observe(snapshot().connections, &[]))
+        .build();
+    meter
+        .f64_observable_gauge("xnet.tcp.retransmission_rate")
+        .with_description("Retransmitted TCP packets over all tracked TCP packets")
+        .with_callback(|observer| observer.observe(snapshot().retransmission_rate, &[]))
+        .build();
+    meter
+        .u64_observable_gauge("xnet.reputation.blocked_ips")
+        .with_description("Remote IPs currently blocked by the reputation policy")
+        .with_callback(|observer| observer.observe(snapshot().blocked_ips, &[]))
+        .build();
+}
+
+async fn refresh() {
+    let mut snapshot = MetricsSnapshot::default();
+    {
+        let traffic_stats = crate::traffic::snapshot();
+
+        snapshot.total_packets = traffic_stats.total_packets;
+        snapshot.total_bytes = traffic_stats.total_bytes;
+        // 设备统计的key为 设备名_方向
+        snapshot.devices = traffic_stats
+            .device_stats
+            .iter()
+            .map(|(key, stats)| {
+                let (device, direction) = key.rsplit_once('_').unwrap_or((key.as_str(), ""));
+                (
+                    device.to_string(),
+                    direction.to_string(),
+                    stats.packets,
+                    stats.bytes,
+                )
+            })
+            .collect();
+        snapshot.connections = traffic_stats.device_connection_stats.len() as u64;
+        snapshot.retransmission_rate = traffic_stats.total_retransmission_rate();
+    }
+    snapshot.blocked_ips = crate::reputation::REPUTATION
+        .lock()
+        .await
+        .values()
+        .filter(|entry| entry.blocked)
+        .count() as u64;
+
+    *SNAPSHOT.lock().unwrap_or_else(|e| e.into_inner()) = snapshot;
+}
+
+// 启动 OTLP 指标导出
+// 采集端地址、请求头、导出间隔等使用标准的 OTEL_EXPORTER_OTLP_* 和 OTEL_METRIC_EXPORT_INTERVAL 环境变量配置
+pub fn start(interval: Duration) -> Result<(), anyhow::Error> {
+    let exporter = opentelemetry_otlp::MetricExporter::builder()
+        .with_http()
+        .build()?;
+    let provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(exporter)
+        .with_resource(resource())
+        .build();
+    opentelemetry::global::set_meter_provider(provider);
+
+    let meter = opentelemetry::global::meter("xnet");
+    register_instruments(&meter);
+    info!("OTLP 指标导出已启用");
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            refresh().await;
+        }
+    });
+
+    Ok(())
+}
+
+fn ktime_to_system_time(ktime_ns: u64, offset_ns: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(ktime_ns + offset_ns)
+}
+
+fn device_name(device_id: u32) -> String {
+    crate::server::DEVICE_MAPPINGS
+        .try_lock()
+        .ok()
+        .and_then(|mappings| {
+            mappings
+                .iter()
+                .find(|(_, mapping)| mapping.device_id == device_id)
+                .map(|(name, _)| name.clone())
+        })
+        .unwrap_or_else(|| format!("device{}", device_id))
+}
+
+// 每个结束的TCP连接导出为一个span，起止时间为SYN和FIN/RST
+fn export_connection(tracer: &impl Tracer, event: &ConnectionEvent, offset_ns: u64) {
+    let src = event.flow.src_ip();
+    let dst = event.flow.dst_ip();
+    let close_reason = if event.close_reason == CONNECTION_CLOSE_RST {
+        "rst"
+    } else {
+        "fin"
+    };
+
+    let mut span = tracer
+        .span_builder(format!("tcp {}:{}", dst, event.flow.dst_port))
+        .with_kind(SpanKind::Internal)
+        .with_start_time(ktime_to_system_time(event.start_ns, offset_ns))
+        .with_attributes(vec![
+            KeyValue::new("network.transport", "tcp"),
+            KeyValue::new("source.address", src.to_string()),
+            KeyValue::new("source.port", event.flow.src_port as i64),
+            KeyValue::new("destination.address", dst.to_string()),
+            KeyValue::new("destination.port", event.flow.dst_port as i64),
+            KeyValue::new("xnet.device", device_name(event.device_id)),
+            KeyValue::new("xnet.packets", event.packets as i64),
+            KeyValue::new("xnet.bytes", event.bytes as i64),
+            KeyValue::new("xnet.retransmissions", event.retransmissions as i64),
+            KeyValue::new("xnet.close_reason", close_reason),
+        ])
+        .start(tracer);
+    span.end_with_timestamp(ktime_to_system_time(event.end_ns, offset_ns));
+}
+
+// 启动 TCP 连接生命周期的 OTLP trace 导出，需在 EbpfManager 创建前调用
+// 采集端地址等使用标准的 OTEL_EXPORTER_OTLP_* 环境变量配置
+pub async fn start_traces(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource())
+        .build();
+    opentelemetry::global::set_tracer_provider(provider);
+
+    let ring_buf = RingBuf::try_from(
+        ebpf.take_map("connection_events")
+            .ok_or_else(|| anyhow::anyhow!("connection_events map not found"))?,
+    )?;
+    let mut config = Array::<&mut MapData, u32>::try_from(
+        ebpf.map_mut("connection_trace_config")
+            .ok_or_else(|| anyhow::anyhow!("connection_trace_config map not found"))?,
+    )?;
+    config.set(0, 1, 0)?;
+    info!("OTLP 连接 trace 导出已启用");
+
+    let mut ring_buf = AsyncFd::new(ring_buf)?;
+    tokio::spawn(async move {
+        let tracer = opentelemetry::global::tracer("xnet");
+        loop {
+            let mut guard = match ring_buf.readable_mut().await {
+                Ok(guard) => guard,
+                Err(e) => {
+                    warn!("读取连接事件失败: {}", e);
+                    return;
+                }
+            };
+            let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+            let ring_buf = guard.get_inner_mut();
+            while let Some(item) = ring_buf.next() {
+                if item.len() >= std::mem::size_of::<ConnectionEvent>() {
+                    let event = bytemuck::pod_read_unaligned::<ConnectionEvent>(
+                        &item[..std::mem::size_of::<ConnectionEvent>()],
+                    );
+                    export_connection(&tracer, &event, offset_ns);
+                }
+            }
+            guard.clear_ready();
+        }
+    });
+
+    Ok(())
+}
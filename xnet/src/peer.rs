@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use xnet_common::FlowTuple;
+
+// 信标报文格式 (大端序):
+// magic[4] | version u8 | reserved u8 | flow_count u16 | seq u64 | sent_ns u64 | flows
+// 每个 flow 为 flow_hash u64 | packets u64
+const BEACON_MAGIC: &[u8; 4] = b"XNPB";
+const BEACON_VERSION: u8 = 1;
+const BEACON_HEADER_LEN: usize = 24;
+const BEACON_FLOW_LEN: usize = 16;
+// 每个信标最多携带的被动流数量
+const MAX_BEACON_FLOWS: usize = 64;
+// 序号回退超过该值时认为对端已重启，较小的回退视为信标乱序
+const BEACON_REORDER_WINDOW: u64 = 16;
+
+// 对端配置
+#[derive(Debug, Clone)]
+pub struct PeerSpec {
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+// 解析命令行中的 name=host:port
+pub fn parse_peer_spec(s: &str) -> Result<PeerSpec, String> {
+    let (name, addr) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid peer '{}', expected name=host:port", s))?;
+    let addr = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("invalid peer address '{}': {}", addr, e))?;
+    Ok(PeerSpec {
+        name: name.to_string(),
+        addr,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    pub listen: SocketAddr,
+    pub interval: Duration,
+    pub peers: Vec<PeerSpec>,
+}
+
+// 单个对端链路的质量统计
+#[derive(Debug, Default)]
+pub struct PeerState {
+    pub addr: Option<SocketAddr>,
+    pub first_seq: Option<u64>,
+    pub highest_seq: u64,
+    pub beacons_received: u64,
+    pub last_beacon: Option<Instant>,
+    // 单向时延（依赖两端时钟同步，单位ns）
+    pub last_delay_ns: i64,
+    pub min_delay_ns: i64,
+    pub max_delay_ns: i64,
+    pub sum_delay_ns: i128,
+    // RFC 3550 抖动估计
+    pub jitter_ns: f64,
+    // 被动流关联: flow_hash -> (对端包数, 本端包数)
+    pub flow_counters: HashMap<u64, (u64, u64)>,
+    pub flows_matched: usize,
+    pub passive_remote_packets: u64,
+    pub passive_local_packets: u64,
+}
+
+impl PeerState {
+    fn record_beacon(&mut self, beacon: &Beacon, recv_ns: u64, local_flows: &HashMap<u64, u64>) {
+        // 对端重启后序号从1重新开始，继续沿用旧的起始序号会把重启前后的差值都算作丢失
+        if self.first_seq.is_some()
+            && beacon.seq < self.highest_seq
+            && (beacon.seq == 1 || self.highest_seq - beacon.seq > BEACON_REORDER_WINDOW)
+        {
+            info!(
+                "对端 {:?} 的信标序号从 {} 回退到 {}，重新开始统计",
+                self.addr, self.highest_seq, beacon.seq
+            );
+            *self = PeerState {
+                addr: self.addr,
+                ..Default::default()
+            };
+        }
+        match self.first_seq {
+            None => {
+                self.first_seq = Some(beacon.seq);
+                self.highest_seq = beacon.seq;
+            }
+            Some(_) if beacon.seq > self.highest_seq => self.highest_seq = beacon.seq,
+            _ => {}
+        }
+
+        let delay = recv_ns as i64 - beacon.sent_ns as i64;
+        if self.beacons_received == 0 {
+            self.min_delay_ns = delay;
+            self.max_delay_ns = delay;
+        } else {
+            let d = (delay - self.last_delay_ns).abs() as f64;
+            self.jitter_ns += (d - self.jitter_ns) / 16.0;
+            self.min_delay_ns = self.min_delay_ns.min(delay);
+            self.max_delay_ns = self.max_delay_ns.max(delay);
+        }
+        self.last_delay_ns = delay;
+        self.sum_delay_ns += delay as i128;
+        self.beacons_received += 1;
+        self.last_beacon = Some(Instant::now());
+
+        // 对两端都观测到的流，比较两次信标之间的包数增量
+        let mut matched = 0;
+        for &(flow_hash, remote_packets) in beacon.flows.iter() {
+            let Some(&local_packets) = local_flows.get(&flow_hash) else {
+                continue;
+            };
+            if let Some(&(prev_remote, prev_local)) = self.flow_counters.get(&flow_hash) {
+                self.passive_remote_packets += remote_packets.saturating_sub(prev_remote);
+                self.passive_local_packets += local_packets.saturating_sub(prev_local);
+            }
+            self.flow_counters
+                .insert(flow_hash, (remote_packets, local_packets));
+            matched += 1;
+        }
+        self.flows_matched = matched;
+    }
+
+    fn beacons_expected(&self) -> u64 {
+        match self.first_seq {
+            Some(first) => self.highest_seq - first + 1,
+            None => 0,
+        }
+    }
+
+    pub fn quality(&self, name: &str) -> Value {
+        let expected = self.beacons_expected();
+        let beacon_loss = if expected > 0 {
+            1.0 - (self.beacons_received.min(expected) as f64 / expected as f64)
+        } else {
+            0.0
+        };
+        let passive_loss = if self.passive_remote_packets > 0 {
+            (1.0 - self.passive_local_packets as f64 / self.passive_remote_packets as f64).max(0.0)
+        } else {
+            0.0
+        };
+        let avg_delay_ns = if self.beacons_received > 0 {
+            (self.sum_delay_ns / self.beacons_received as i128) as i64
+        } else {
+            0
+        };
+
+        serde_json::json!({
+            "name": name,
+            "address": self.addr.map(|a| a.to_string()),
+            "beacons_received": self.beacons_received,
+            "beacons_expected": expected,
+            "beacon_loss_ratio": beacon_loss,
+            "one_way_delay_ms": {
+                "last": self.last_delay_ns as f64 / 1e6,
+                "min": self.min_delay_ns as f64 / 1e6,
+                "max": self.max_delay_ns as f64 / 1e6,
+                "avg": avg_delay_ns as f64 / 1e6,
+            },
+            "jitter_ms": self.jitter_ns / 1e6,
+            "passive": {
+                "flows_matched": self.flows_matched,
+                "remote_packets": self.passive_remote_packets,
+                "local_packets": self.passive_local_packets,
+                "loss_ratio": passive_loss,
+            },
+            "last_beacon_age_secs": self.last_beacon.map(|t| t.elapsed().as_secs_f64()),
+        })
+    }
+}
+
+struct Beacon {
+    seq: u64,
+    sent_ns: u64,
+    flows: Vec<(u64, u64)>,
+}
+
+impl Beacon {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(BEACON_HEADER_LEN + self.flows.len() * BEACON_FLOW_LEN);
+        buf.extend_from_slice(BEACON_MAGIC);
+        buf.push(BEACON_VERSION);
+        buf.push(0);
+        buf.extend_from_slice(&(self.flows.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&self.sent_ns.to_be_bytes());
+        for (flow_hash, packets) in self.flows.iter() {
+            buf.extend_from_slice(&flow_hash.to_be_bytes());
+            buf.extend_from_slice(&packets.to_be_bytes());
+        }
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < BEACON_HEADER_LEN || &buf[0..4] != BEACON_MAGIC || buf[4] != BEACON_VERSION {
+            return None;
+        }
+        let flow_count = u16::from_be_bytes(buf[6..8].try_into().ok()?) as usize;
+        let seq = u64::from_be_bytes(buf[8..16].try_into().ok()?);
+        let sent_ns = u64::from_be_bytes(buf[16..24].try_into().ok()?);
+        if buf.len() < BEACON_HEADER_LEN + flow_count * BEACON_FLOW_LEN {
+            return None;
+        }
+        let flows = buf[BEACON_HEADER_LEN..]
+            .chunks_exact(BEACON_FLOW_LEN)
+            .take(flow_count)
+            .map(|chunk| {
+                (
+                    u64::from_be_bytes(chunk[0..8].try_into().unwrap()),
+                    u64::from_be_bytes(chunk[8..16].try_into().unwrap()),
+                )
+            })
+            .collect();
+        Some(Self {
+            seq,
+            sent_ns,
+            flows,
+        })
+    }
+}
+
+// 按 (协议, 源地址, 目的地址, 源端口, 目的端口) 计算流哈希 (FNV-1a)，两端对同一条流得到相同的值
+pub fn flow_hash(flow: &FlowTuple) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let bytes = (flow.protocol as u32)
+        .to_be_bytes()
+        .into_iter()
+        .chain(flow.saddr)
+        .chain(flow.daddr)
+        .chain(flow.src_port.to_be_bytes())
+        .chain(flow.dst_port.to_be_bytes());
+    for b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+lazy_static::lazy_static! {
+    // 对端名称 -> 链路质量
+    pub static ref PEER_STATES: Mutex<HashMap<String, PeerState>> = Mutex::new(HashMap::new());
+    // 本端最近一次观测到的被动流: flow_hash -> 包数
+    static ref LOCAL_FLOWS: Mutex<HashMap<u64, u64>> = Mutex::new(HashMap::new());
+}
+
+// 刷新本端流统计并生成流哈希快照
+fn snapshot_local_flows() -> HashMap<u64, u64> {
+    let traffic_stats = crate::traffic::snapshot();
+
+    let mut flows = HashMap::new();
+    for stats in traffic_stats.device_connection_stats.values() {
+        let hash = flow_hash(&stats.flow);
+        *flows.entry(hash).or_insert(0) += stats.total_packets;
+    }
+    flows
+}
+
+// 启动对端模式: 周期性发送信标，并接收对端信标计算链路质量
+pub async fn start(config: PeerConfig) -> Result<(), anyhow::Error> {
+    let socket = Arc::new(UdpSocket::bind(config.listen).await?);
+    info!(
+        "对端模式已启用: 监听 {}, 对端 {:?}",
+        config.listen,
+        config.peers.iter().map(|p| &p.name).collect::<Vec<_>>()
+    );
+
+    {
+        let mut states = PEER_STATES.lock().await;
+        for peer in config.peers.iter() {
+            states.insert(
+                peer.name.clone(),
+                PeerState {
+                    addr: Some(peer.addr),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    // 发送任务
+    let send_socket = socket.clone();
+    let peers = config.peers.clone();
+    let interval = config.interval;
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut seq: u64 = 0;
+        loop {
+            ticker.tick().await;
+
+            let local_flows = snapshot_local_flows();
+            let mut top_flows: Vec<(u64, u64)> =
+                local_flows.iter().map(|(&h, &p)| (h, p)).collect();
+            top_flows.sort_by_key(|f| std::cmp::Reverse(f.1));
+            top_flows.truncate(MAX_BEACON_FLOWS);
+            *LOCAL_FLOWS.lock().await = local_flows;
+
+            seq += 1;
+            let beacon = Beacon {
+                seq,
+                sent_ns: now_ns(),
+                flows: top_flows,
+            }
+            .encode();
+            for peer in peers.iter() {
+                if let Err(e) = send_socket.send_to(&beacon, peer.addr).await {
+                    debug!("发送信标到对端 {} 失败: {}", peer.name, e);
+                }
+            }
+        }
+    });
+
+    // 接收任务
+    let peers = config.peers;
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; BEACON_HEADER_LEN + MAX_BEACON_FLOWS * BEACON_FLOW_LEN];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("接收对端信标失败: {}", e);
+                    continue;
+                }
+            };
+            let recv_ns = now_ns();
+            let Some(beacon) = Beacon::decode(&buf[..len]) else {
+                debug!("忽略来自 {} 的无效信标", from);
+                continue;
+            };
+            let Some(peer) = peers.iter().find(|p| p.addr.ip() == from.ip()) else {
+                debug!("忽略来自未配置对端 {} 的信标", from);
+                continue;
+            };
+
+            let local_flows = LOCAL_FLOWS.lock().await;
+            let mut states = PEER_STATES.lock().await;
+            if let Some(state) = states.get_mut(&peer.name) {
+                state.record_beacon(&beacon, recv_ns, &local_flows);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon(seq: u64) -> Beacon {
+        Beacon {
+            seq,
+            sent_ns: 0,
+            flows: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn restarts_tracking_when_peer_restarts() {
+        let mut state = PeerState::default();
+        let flows = HashMap::new();
+        for seq in 100..=110 {
+            state.record_beacon(&beacon(seq), 0, &flows);
+        }
+        // 乱序到达的旧信标不影响统计
+        state.record_beacon(&beacon(105), 0, &flows);
+        assert_eq!((state.beacons_expected(), state.beacons_received), (11, 12));
+
+        state.record_beacon(&beacon(1), 0, &flows);
+        state.record_beacon(&beacon(2), 0, &flows);
+        assert_eq!(state.first_seq, Some(1));
+        assert_eq!((state.beacons_expected(), state.beacons_received), (2, 2));
+    }
+}
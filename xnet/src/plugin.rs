@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use aya::programs::{tc, KProbe, SchedClassifier, TcAttachType, TracePoint, Xdp, XdpFlags};
+use aya::{Ebpf, EbpfLoader};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+// 外部 eBPF 目标文件，加载后按 attach 挂载其中的程序，其 map 通过 /maps 查看
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PluginConfig {
+    pub name: String,
+    // 预先编译好的 eBPF 目标文件
+    pub path: PathBuf,
+    #[serde(default)]
+    pub attach: Vec<PluginAttach>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PluginAttach {
+    // 目标文件中的程序名
+    pub program: String,
+    // 挂载点: xdp:<iface>、tc_ingress:<iface>、tc_egress:<iface>、
+    // tracepoint:<category>/<name>、kprobe:<function>
+    pub hook: String,
+}
+
+enum Hook {
+    Xdp(String),
+    Tc(TcAttachType, String),
+    TracePoint(String, String),
+    KProbe(String),
+}
+
+struct Plugin {
+    config: PluginConfig,
+    // 卸载时随之释放，程序自动脱离挂载点
+    ebpf: Ebpf,
+}
+
+lazy_static::lazy_static! {
+    static ref PLUGINS: Mutex<BTreeMap<String, Plugin>> = Mutex::new(BTreeMap::new());
+}
+
+fn parse_hook(hook: &str) -> Result<Hook, String> {
+    let invalid = || format!("invalid hook {}", hook);
+    let (kind, target) = hook.split_once(':').ok_or_else(invalid)?;
+    if target.is_empty() {
+        return Err(invalid());
+    }
+    match kind {
+        "xdp" => Ok(Hook::Xdp(target.to_string())),
+        "tc_ingress" => Ok(Hook::Tc(TcAttachType::Ingress, target.to_string())),
+        "tc_egress" => Ok(Hook::Tc(TcAttachType::Egress, target.to_string())),
+        "tracepoint" => {
+            let (category, name) = target.split_once('/').ok_or_else(invalid)?;
+            Ok(Hook::TracePoint(category.to_string(), name.to_string()))
+        }
+        "kprobe" => Ok(Hook::KProbe(target.to_string())),
+        _ => Err(invalid()),
+    }
+}
+
+// 校验插件配置：目标文件存在且挂载点格式正确
+pub(crate) fn check(config: &PluginConfig) -> Result<(), String> {
+    if !config.path.exists() {
+        return Err(format!("{} does not exist", config.path.display()));
+    }
+    for attach in &config.attach {
+        parse_hook(&attach.hook)?;
+    }
+    Ok(())
+}
+
+// 同一个程序可以挂载到多个挂载点，只加载一次
+fn attach(ebpf: &mut Ebpf, attach: &PluginAttach) -> Result<(), anyhow::Error> {
+    let hook = parse_hook(&attach.hook).map_err(anyhow::Error::msg)?;
+    let program = ebpf
+        .program_mut(&attach.program)
+        .ok_or_else(|| anyhow::anyhow!("program {} not found", attach.program))?;
+    match hook {
+        Hook::Xdp(iface) => {
+            let program: &mut Xdp = program.try_into()?;
+            if program.fd().is_err() {
+                program.load()?;
+            }
+            program.attach(&iface, XdpFlags::default())?;
+        }
+        Hook::Tc(attach_type, iface) => {
+            // clsact 已存在时返回错误，可以忽略
+            let _ = tc::qdisc_add_clsact(&iface);
+            let program: &mut SchedClassifier = program.try_into()?;
+            if program.fd().is_err() {
+                program.load()?;
+            }
+            program.attach(&iface, attach_type)?;
+        }
+        Hook::TracePoint(category, name) => {
+            let program: &mut TracePoint = program.try_into()?;
+            if program.fd().is_err() {
+                program.load()?;
+            }
+            program.attach(&category, &name)?;
+        }
+        Hook::KProbe(function) => {
+            let program: &mut KProbe = program.try_into()?;
+            if program.fd().is_err() {
+                program.load()?;
+            }
+            program.attach(&function, 0)?;
+        }
+    }
+    Ok(())
+}
+
+// 加载目标文件并挂载全部程序，任一步失败时已挂载的程序随 Ebpf 一起释放
+pub async fn load(config: PluginConfig) -> Result<Value, String> {
+    if config.name.is_empty() || config.name == "xnet" {
+        return Err(format!("invalid plugin name {}", config.name));
+    }
+    for item in &config.attach {
+        parse_hook(&item.hook)?;
+    }
+
+    let mut plugins = PLUGINS.lock().await;
+    if plugins.contains_key(&config.name) {
+        return Err(format!("plugin {} already loaded", config.name));
+    }
+
+    let mut ebpf = EbpfLoader::new()
+        .load_file(&config.path)
+        .map_err(|e| format!("load {}: {}", config.path.display(), e))?;
+    if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
+        warn!("插件 {} 日志初始化失败: {}", config.name, e);
+    }
+    for item in &config.attach {
+        attach(&mut ebpf, item)
+            .map_err(|e| format!("attach {} to {}: {}", item.program, item.hook, e))?;
+    }
+
+    info!(
+        "插件 {} 已加载: {}，挂载 {} 个程序",
+        config.name,
+        config.path.display(),
+        config.attach.len()
+    );
+    let summary = summary(&config, &ebpf);
+    plugins.insert(config.name.clone(), Plugin { config, ebpf });
+    Ok(summary)
+}
+
+// 卸载插件，程序脱离挂载点，map 随之释放，插件不存在时返回 false
+pub async fn unload(name: &str) -> bool {
+    let removed = PLUGINS.lock().await.remove(name).is_some();
+    if removed {
+        info!("插件 {} 已卸载", name);
+    }
+    removed
+}
+
+fn summary(config: &PluginConfig, ebpf: &Ebpf) -> Value {
+    let maps: Vec<&str> = ebpf.maps().map(|(name, _)| name).collect();
+    serde_json::json!({
+        "name": config.name,
+        "path": config.path,
+        "attach": config.attach,
+        "maps": maps,
+    })
+}
+
+pub async fn list() -> Vec<Value> {
+    PLUGINS
+        .lock()
+        .await
+        .values()
+        .map(|plugin| summary(&plugin.config, &plugin.ebpf))
+        .collect()
+}
+
+// 所有插件的 map
+pub async fn maps() -> Vec<Value> {
+    PLUGINS
+        .lock()
+        .await
+        .iter()
+        .flat_map(|(owner, plugin)| {
+            plugin
+                .ebpf
+                .maps()
+                .map(|(name, map)| crate::maps::describe(owner, name, map))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// 读出插件 map 的条目，插件或 map 不存在时返回 None
+pub async fn dump_map(
+    owner: &str,
+    name: &str,
+    limit: usize,
+) -> Option<Result<Result<Vec<Value>, String>, anyhow::Error>> {
+    let plugins = PLUGINS.lock().await;
+    let map = plugins.get(owner)?.ebpf.map(name)?;
+    Some(crate::maps::dump(map, limit))
+}
+
+// 读取插件配置文件，文件内容为 PluginConfig 数组
+pub(crate) fn read_file(path: &Path) -> Result<Vec<PluginConfig>, anyhow::Error> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+// 启动时加载插件配置文件
+pub async fn load_file(path: &Path) -> Result<(), anyhow::Error> {
+    for config in read_file(path)? {
+        let name = config.name.clone();
+        load(config)
+            .await
+            .map_err(|e| anyhow::anyhow!("plugin {}: {}", name, e))?;
+    }
+    Ok(())
+}
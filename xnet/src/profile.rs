@@ -0,0 +1,87 @@
+use std::os::fd::OwnedFd;
+
+use aya::sys::Stats;
+use aya::Ebpf;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+lazy_static::lazy_static! {
+    // bpf_enable_stats 返回的 fd，关闭后内核停止统计程序运行时间
+    static ref STATS_FD: Mutex<Option<OwnedFd>> = Mutex::new(None);
+}
+
+// 开启 eBPF 程序的运行次数和耗时统计，需要 5.8 以上内核及 CAP_SYS_ADMIN
+pub async fn enable() {
+    match aya::sys::enable_stats(Stats::RunTime) {
+        Ok(fd) => {
+            *STATS_FD.lock().await = Some(fd);
+            info!("已开启 eBPF 程序运行统计");
+        }
+        Err(e) => warn!("开启 eBPF 程序运行统计失败: {}", e),
+    }
+}
+
+// 运行统计是否开启；sysctl kernel.bpf_stats_enabled=1 时也会统计
+pub async fn enabled() -> bool {
+    STATS_FD.lock().await.is_some()
+        || std::fs::read_to_string("/proc/sys/kernel/bpf_stats_enabled")
+            .map(|s| s.trim() == "1")
+            .unwrap_or(false)
+}
+
+// 各已加载程序的运行次数及累计耗时
+pub fn programs(ebpf: &Ebpf) -> Vec<Value> {
+    let mut programs: Vec<Value> = ebpf
+        .programs()
+        .filter_map(|(name, program)| {
+            let info = program.info().ok()?;
+            let run_count = info.run_count();
+            let run_time_ns = info.run_time().as_nanos() as u64;
+            Some(serde_json::json!({
+                "name": name,
+                "id": info.id(),
+                "run_count": run_count,
+                "run_time_ns": run_time_ns,
+                "avg_ns": run_time_ns.checked_div(run_count).unwrap_or(0),
+            }))
+        })
+        .collect();
+    programs.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    programs
+}
+
+// 指标名、说明及取值
+type Metric = (&'static str, &'static str, fn(&Value) -> String);
+
+// Prometheus 文本格式的程序运行统计
+pub fn prometheus(programs: &[Value]) -> String {
+    let mut out = String::new();
+    let metrics: [Metric; 2] = [
+        (
+            "xnet_program_run_count_total",
+            "Number of times the eBPF program has run",
+            |p| p["run_count"].to_string(),
+        ),
+        (
+            "xnet_program_run_time_seconds_total",
+            "Cumulative time spent running the eBPF program",
+            |p| format!("{:.9}", p["run_time_ns"].as_u64().unwrap_or(0) as f64 / 1e9),
+        ),
+    ];
+    for (name, help, value) in metrics {
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} counter\n",
+            name, help, name
+        ));
+        for program in programs {
+            out.push_str(&format!(
+                "{}{{program=\"{}\"}} {}\n",
+                name,
+                program["name"].as_str().unwrap_or_default(),
+                value(program)
+            ));
+        }
+    }
+    out
+}
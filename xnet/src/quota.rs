@@ -0,0 +1,374 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use aya::Ebpf;
+use bytemuck::Zeroable;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::{
+    QuotaState, QuotaUsage, QUOTA_ACTION_DROP, QUOTA_ACTION_THROTTLE, QUOTA_MAX_DEVICES,
+};
+
+use crate::events::Event;
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+
+// 检查用量的间隔，配额最多超出一个间隔内发送的流量
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+    Hour,
+    #[default]
+    Day,
+    // 从 UTC 周一 0 点开始
+    Week,
+}
+
+impl QuotaPeriod {
+    // 包含 now 的周期的开始时间(UTC)
+    fn start(&self, now: u64) -> u64 {
+        const DAY: u64 = 86400;
+        match self {
+            QuotaPeriod::Hour => now - now % 3600,
+            QuotaPeriod::Day => now - now % DAY,
+            // 1970-01-01 是周四
+            QuotaPeriod::Week => now - (now + 3 * DAY) % (7 * DAY),
+        }
+    }
+
+    fn secs(&self) -> u64 {
+        match self {
+            QuotaPeriod::Hour => 3600,
+            QuotaPeriod::Day => 86400,
+            QuotaPeriod::Week => 7 * 86400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    // 丢弃设备发出的所有包，直到下一个周期
+    #[default]
+    Drop,
+    // 按 throttle_bps 限速，需要设备的 root qdisc 为 fq
+    Throttle,
+}
+
+// 设备出方向的字节配额，每个设备一条
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct QuotaConfig {
+    pub iface: String,
+    // 每个周期允许发送的字节数
+    pub limit_bytes: u64,
+    #[serde(default)]
+    pub period: QuotaPeriod,
+    #[serde(default)]
+    pub action: QuotaAction,
+    // 超出配额后的速率(bit/s)，action 为 throttle 时必填
+    pub throttle_bps: Option<u64>,
+}
+
+impl QuotaConfig {
+    fn state(&self, over: bool) -> QuotaState {
+        QuotaState {
+            over: over as u32,
+            action: match self.action {
+                QuotaAction::Drop => QUOTA_ACTION_DROP,
+                QuotaAction::Throttle => QUOTA_ACTION_THROTTLE,
+            },
+            throttle_rate: self.throttle_bps.unwrap_or(0) / 8,
+        }
+    }
+}
+
+// 配额用完或进入新周期
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QuotaEvent {
+    pub timestamp: u64,
+    pub iface: String,
+    // exceeded 或 reset
+    pub state: &'static str,
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+}
+
+struct Quota {
+    config: QuotaConfig,
+    ifindex: u32,
+    // 当前周期的开始时间及当时 quota_usage 中的字节数
+    period_start: u64,
+    base_bytes: u64,
+    over: bool,
+}
+
+lazy_static::lazy_static! {
+    // 设备名 -> 配额
+    static ref QUOTAS: Mutex<BTreeMap<String, Quota>> = Mutex::new(BTreeMap::new());
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn state_map(ebpf: &mut Ebpf) -> Result<AyaHashMap<&mut MapData, u32, QuotaState>, anyhow::Error> {
+    Ok(AyaHashMap::try_from(
+        ebpf.map_mut("quota_state")
+            .ok_or_else(|| anyhow::anyhow!("quota_state map not found"))?,
+    )?)
+}
+
+fn usage_map(ebpf: &mut Ebpf) -> Result<AyaHashMap<&mut MapData, u32, QuotaUsage>, anyhow::Error> {
+    Ok(AyaHashMap::try_from(
+        ebpf.map_mut("quota_usage")
+            .ok_or_else(|| anyhow::anyhow!("quota_usage map not found"))?,
+    )?)
+}
+
+// 用量由挂载在设备 egress 上的 xnet_tc_egress 统计，设备需在本命名空间中且已挂载流量统计
+async fn ifindex(iface: &str) -> Result<u32, String> {
+    let mappings = crate::server::DEVICE_MAPPINGS.lock().await;
+    match mappings.get(iface) {
+        Some(mapping) if mapping.netns == crate::netns::host_inode() => Ok(mapping.device_id),
+        _ => Err(format!(
+            "traffic counting is not attached to {}, attach it with /traffic_count_attach_device first",
+            iface
+        )),
+    }
+}
+
+fn validate(config: &QuotaConfig) -> Result<(), String> {
+    if config.limit_bytes == 0 {
+        return Err("limit_bytes must be greater than 0".to_string());
+    }
+    match (config.action, config.throttle_bps) {
+        (QuotaAction::Throttle, None) => {
+            Err("throttle_bps is required for action throttle".to_string())
+        }
+        (QuotaAction::Throttle, Some(bps)) if bps < 8 => {
+            Err("throttle_bps must be at least 8".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+// 新增或替换设备的配额，新增时从0开始计量，替换时保留本周期的用量
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    config: QuotaConfig,
+) -> Result<Result<(), String>, anyhow::Error> {
+    if let Err(e) = validate(&config) {
+        return Ok(Err(e));
+    }
+    let ifindex = match ifindex(&config.iface).await {
+        Ok(ifindex) => ifindex,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mut quotas = QUOTAS.lock().await;
+    if !quotas.contains_key(&config.iface) && quotas.len() >= QUOTA_MAX_DEVICES as usize {
+        return Ok(Err(format!("at most {} quotas", QUOTA_MAX_DEVICES)));
+    }
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let now = now_secs();
+    let quota = match quotas.get(&config.iface) {
+        Some(existing) if existing.ifindex == ifindex => {
+            // 提高配额后立即恢复，降低配额后由下一次检查置位
+            let used = current_bytes(&mut ebpf, ifindex)?.saturating_sub(existing.base_bytes);
+            Quota {
+                ifindex,
+                period_start: config.period.start(now),
+                base_bytes: existing.base_bytes,
+                over: existing.over && used >= config.limit_bytes,
+                config,
+            }
+        }
+        existing => {
+            // 设备重建后 ifindex 变化，清除旧 ifindex 上的配额
+            if let Some(stale) = existing.map(|existing| existing.ifindex) {
+                let _ = state_map(&mut ebpf)?.remove(&stale);
+                let _ = usage_map(&mut ebpf)?.remove(&stale);
+            }
+            usage_map(&mut ebpf)?.insert(ifindex, QuotaUsage::zeroed(), 0)?;
+            Quota {
+                ifindex,
+                period_start: config.period.start(now),
+                base_bytes: 0,
+                over: false,
+                config,
+            }
+        }
+    };
+    state_map(&mut ebpf)?.insert(ifindex, quota.config.state(quota.over), 0)?;
+    info!(
+        iface = %quota.config.iface,
+        ifindex,
+        action = ?quota.config.action,
+        "设备 {} 出方向配额: 每{:?} {} 字节，超出后 {:?}",
+        quota.config.iface, quota.config.period, quota.config.limit_bytes, quota.config.action
+    );
+    quotas.insert(quota.config.iface.clone(), quota);
+    Ok(Ok(()))
+}
+
+fn current_bytes(ebpf: &mut Ebpf, ifindex: u32) -> Result<u64, anyhow::Error> {
+    Ok(usage_map(ebpf)?
+        .get(&ifindex, 0)
+        .map(|usage| usage.bytes)
+        .unwrap_or(0))
+}
+
+// 删除设备的配额，配额不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, iface: &str) -> Result<bool, anyhow::Error> {
+    let mut quotas = QUOTAS.lock().await;
+    let Some(quota) = quotas.remove(iface) else {
+        return Ok(false);
+    };
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let _ = state_map(&mut ebpf)?.remove(&quota.ifindex);
+    let _ = usage_map(&mut ebpf)?.remove(&quota.ifindex);
+    info!(iface = %iface, "设备 {} 出方向配额已删除", iface);
+    Ok(true)
+}
+
+// 各设备的配额、本周期用量和执行情况
+pub async fn list(maps: &MapCache) -> Result<Vec<Value>, anyhow::Error> {
+    let quotas = QUOTAS.lock().await;
+    let usage = AyaHashMap::<&MapData, u32, QuotaUsage>::try_from(
+        maps.map("quota_usage")
+            .ok_or_else(|| anyhow::anyhow!("quota_usage map not found"))?,
+    )?;
+    Ok(quotas
+        .values()
+        .map(|quota| {
+            let usage = usage
+                .get(&quota.ifindex, 0)
+                .unwrap_or_else(|_| QuotaUsage::zeroed());
+            let used = usage.bytes.saturating_sub(quota.base_bytes);
+            serde_json::json!({
+                "iface": quota.config.iface,
+                "ifindex": quota.ifindex,
+                "limit_bytes": quota.config.limit_bytes,
+                "period": quota.config.period,
+                "action": quota.config.action,
+                "throttle_bps": quota.config.throttle_bps,
+                "used_bytes": used,
+                "remaining_bytes": quota.config.limit_bytes.saturating_sub(used),
+                "over_quota": quota.over,
+                "period_start": quota.period_start,
+                "reset_at": quota.period_start + quota.config.period.secs(),
+                "dropped": usage.dropped,
+                "throttled": usage.throttled,
+            })
+        })
+        .collect())
+}
+
+fn publish(quota: &Quota, state: &'static str, used_bytes: u64) {
+    crate::events::publish(Event::Quota(QuotaEvent {
+        timestamp: now_secs(),
+        iface: quota.config.iface.clone(),
+        state,
+        used_bytes,
+        limit_bytes: quota.config.limit_bytes,
+    }));
+}
+
+// 进入新周期时重新计量，用量达到配额时置位 quota_state
+async fn check(ebpf_manager: &EbpfManager) -> Result<(), anyhow::Error> {
+    let mut quotas = QUOTAS.lock().await;
+    if quotas.is_empty() {
+        return Ok(());
+    }
+    let usage: BTreeMap<u32, QuotaUsage> = {
+        let maps = ebpf_manager.maps();
+        let map = AyaHashMap::<&MapData, u32, QuotaUsage>::try_from(
+            maps.map("quota_usage")
+                .ok_or_else(|| anyhow::anyhow!("quota_usage map not found"))?,
+        )?;
+        crate::batch::entries(&map).into_iter().collect()
+    };
+    let now = now_secs();
+    let mut changed = Vec::new();
+    for quota in quotas.values_mut() {
+        let bytes = usage
+            .get(&quota.ifindex)
+            .map(|usage| usage.bytes)
+            .unwrap_or(0);
+        let period_start = quota.config.period.start(now);
+        if period_start != quota.period_start {
+            quota.period_start = period_start;
+            quota.base_bytes = bytes;
+            if quota.over {
+                quota.over = false;
+                info!(iface = %quota.config.iface, "设备 {} 进入新的配额周期，恢复发送", quota.config.iface);
+                publish(quota, "reset", 0);
+                changed.push((quota.ifindex, quota.config.state(false)));
+            }
+            continue;
+        }
+        let used = bytes.saturating_sub(quota.base_bytes);
+        if !quota.over && used >= quota.config.limit_bytes {
+            quota.over = true;
+            warn!(
+                iface = %quota.config.iface,
+                used,
+                action = ?quota.config.action,
+                "设备 {} 本周期已发送 {} 字节，超出配额 {} 字节，开始{}",
+                quota.config.iface,
+                used,
+                quota.config.limit_bytes,
+                match quota.config.action {
+                    QuotaAction::Drop => "丢包",
+                    QuotaAction::Throttle => "限速",
+                }
+            );
+            publish(quota, "exceeded", used);
+            changed.push((quota.ifindex, quota.config.state(true)));
+        }
+    }
+    if !changed.is_empty() {
+        let mut ebpf = ebpf_manager.ebpf.lock().await;
+        let mut states = state_map(&mut ebpf)?;
+        for (ifindex, state) in changed {
+            states.insert(ifindex, state, 0)?;
+        }
+    }
+    Ok(())
+}
+
+// 定期检查各设备的用量
+pub fn start(ebpf_manager: Arc<EbpfManager>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = check(&ebpf_manager).await {
+                warn!("检查设备配额失败: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_starts_on_utc_boundaries() {
+        // 2025-10-17 13:45:10 UTC，周五
+        let now = 1760708710;
+        assert_eq!(QuotaPeriod::Hour.start(now), 1760706000);
+        assert_eq!(QuotaPeriod::Day.start(now), 1760659200);
+        // 2025-10-13 00:00:00 UTC，周一
+        assert_eq!(QuotaPeriod::Week.start(now), 1760313600);
+    }
+}
@@ -0,0 +1,249 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aya::{Ebpf, EbpfLoader};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::TcpStateOffsets;
+
+use crate::server::EbpfManager;
+
+// statfs 返回的 bpffs 文件系统类型
+const BPF_FS_MAGIC: i64 = 0xcafe4a11;
+
+// 加载目标文件时设置的全局变量和固定 map 的目录，热重载时以相同参数加载新的目标文件
+#[derive(Debug, Clone)]
+pub struct LoaderOptions {
+    pub pin_path: PathBuf,
+    pub netns_cookie: u8,
+    pub tcp_state_offsets: TcpStateOffsets,
+    // 覆盖目标文件中编译时确定的 map 容量
+    pub map_sizes: Vec<MapSize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapSize {
+    pub name: String,
+    pub max_entries: u32,
+}
+
+// 解析 --map-size 参数，格式 name=max_entries
+pub fn parse_map_size(s: &str) -> Result<MapSize, String> {
+    let invalid = || format!("invalid map size '{}', expected name=max_entries", s);
+    let (name, entries) = s.split_once('=').ok_or_else(invalid)?;
+    let max_entries: u32 = entries.trim().parse().map_err(|_| invalid())?;
+    if name.trim().is_empty() || max_entries == 0 {
+        return Err(invalid());
+    }
+    Ok(MapSize {
+        name: name.trim().to_string(),
+        max_entries,
+    })
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProgramVersion {
+    // embedded 表示 xnet 内置的目标文件，否则为目标文件路径
+    pub source: String,
+    pub size: usize,
+    // 目标文件内容的 FNV-1a 哈希
+    pub hash: String,
+    pub loaded_at: u64,
+    // 启动时为0，每次重载加1
+    pub generation: u64,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct ReloadRequest {
+    // 新的 eBPF 目标文件，不设置时重新加载内置的目标文件
+    pub path: Option<PathBuf>,
+}
+
+lazy_static::lazy_static! {
+    static ref LOADER: Mutex<Option<LoaderOptions>> = Mutex::new(None);
+    static ref VERSION: Mutex<Option<ProgramVersion>> = Mutex::new(None);
+    // 同一时间只进行一次重载
+    static ref RELOADING: Mutex<()> = Mutex::new(());
+}
+
+fn embedded_object() -> &'static [u8] {
+    aya::include_bytes_aligned!(concat!(env!("OUT_DIR"), "/xnet"))
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn version(source: String, object: &[u8], generation: u64) -> ProgramVersion {
+    ProgramVersion {
+        source,
+        size: object.len(),
+        hash: format!("{:016x}", fnv1a(object)),
+        loaded_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        generation,
+    }
+}
+
+fn is_bpffs(path: &Path) -> Result<bool, anyhow::Error> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(stat.f_type as i64 == BPF_FS_MAGIC)
+}
+
+// 清空上次运行留下的固定 map，目录不在 bpffs 中时在该目录挂载 bpffs
+fn prepare_pin_path(path: &Path) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(path)?;
+    if !is_bpffs(path)? {
+        let fstype = CString::new("bpf")?;
+        let target = CString::new(path.as_os_str().as_bytes())?;
+        let ret = unsafe {
+            libc::mount(
+                fstype.as_ptr(),
+                target.as_ptr(),
+                fstype.as_ptr(),
+                0,
+                std::ptr::null(),
+            )
+        };
+        if ret != 0 {
+            return Err(anyhow::anyhow!(
+                "failed to mount bpffs on {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    for entry in std::fs::read_dir(path)? {
+        std::fs::remove_file(entry?.path())?;
+    }
+    Ok(())
+}
+
+// 已固定的 map 直接沿用，新增的 map 在加载时创建并固定
+async fn load_object(object: &[u8]) -> Result<Ebpf, anyhow::Error> {
+    let loader = LOADER.lock().await;
+    let options = loader
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("ebpf loader not initialized"))?;
+    let mut loader = EbpfLoader::new();
+    loader
+        .map_pin_path(&options.pin_path)
+        .set_global("NETNS_COOKIE_ENABLED", &options.netns_cookie, true)
+        .set_global("TCP_STATE_OFFSETS", &options.tcp_state_offsets, true);
+    for size in &options.map_sizes {
+        loader.set_max_entries(&size.name, size.max_entries);
+    }
+    let ebpf = loader.load(object)?;
+    // 目标文件中不存在的 map 会被加载器忽略
+    for size in &options.map_sizes {
+        if ebpf.map(&size.name).is_none() {
+            warn!("--map-size 指定的 map {} 不存在", size.name);
+        }
+    }
+    Ok(ebpf)
+}
+
+// 启动时加载内置的目标文件，每次启动都从空的 map 开始
+pub async fn init(options: LoaderOptions) -> Result<Ebpf, anyhow::Error> {
+    prepare_pin_path(&options.pin_path)?;
+    *LOADER.lock().await = Some(options);
+    let object = embedded_object();
+    let ebpf = load_object(object).await?;
+    *VERSION.lock().await = Some(version("embedded".to_string(), object, 0));
+    Ok(ebpf)
+}
+
+// 当前运行的目标文件版本
+pub async fn current_version() -> Option<ProgramVersion> {
+    VERSION.lock().await.clone()
+}
+
+// 加载新的目标文件并沿用固定的 map，先把新程序挂载到原有的挂载点再释放旧程序
+// 新的目标文件或其中的程序加载失败时原有程序保持不变
+pub async fn reload(
+    ebpf_manager: &EbpfManager,
+    request: ReloadRequest,
+) -> Result<Result<Value, String>, anyhow::Error> {
+    let _reloading = RELOADING.lock().await;
+
+    let owned;
+    let (source, object) = match &request.path {
+        Some(path) => {
+            owned = match std::fs::read(path) {
+                Ok(object) => object,
+                Err(e) => return Ok(Err(format!("read {}: {}", path.display(), e))),
+            };
+            (path.display().to_string(), owned.as_slice())
+        }
+        None => ("embedded".to_string(), embedded_object()),
+    };
+
+    let mut new = match load_object(object).await {
+        Ok(ebpf) => ebpf,
+        Err(e) => return Ok(Err(format!("load {}: {}", source, e))),
+    };
+    if let Err(e) = crate::server::load_programs(&mut new) {
+        return Ok(Err(format!("load programs from {}: {}", source, e)));
+    }
+    if let Err(e) = aya_log::EbpfLogger::init(&mut new) {
+        warn!("failed to initialize eBPF logger: {e}");
+    }
+
+    // XDP 和 tc 挂载原地替换，其他挂载点先挂载新程序，旧程序随旧实例释放
+    let mut failed = crate::server::swap_links(ebpf_manager, &mut new).await;
+    failed.extend(crate::shaping::reattach(ebpf_manager, &mut new).await);
+    let results = [
+        ("tcp_state", crate::tcpstate::reattach(&mut new)),
+        ("egress_policy", crate::egress::reattach(&mut new)),
+        ("map_gc", crate::map_gc::reattach(&mut new)),
+        ("sockops", crate::sockops::reattach(&mut new).await),
+        ("ssl", crate::ssl::reattach(&mut new).await),
+        ("socket_owner", crate::socket_owner::reattach(&mut new)),
+    ];
+    for (name, result) in results {
+        if let Err(e) = result {
+            failed.push(format!("{}: {}", name, e));
+        }
+    }
+
+    // 新目标文件中新增的 map 也需要句柄
+    let maps = match crate::map_cache::MapCache::new(&new) {
+        Ok(maps) => Some(maps),
+        Err(e) => {
+            failed.push(format!("map handles: {}", e));
+            None
+        }
+    };
+    let old = std::mem::replace(&mut *ebpf_manager.ebpf.lock().await, new);
+    if let Some(maps) = maps {
+        ebpf_manager.set_maps(maps);
+    }
+    drop(old);
+
+    let mut current = VERSION.lock().await;
+    let generation = current.as_ref().map(|v| v.generation + 1).unwrap_or(1);
+    let version = version(source, object, generation);
+    info!(
+        "eBPF 程序已重载: {} ({}), 第 {} 次",
+        version.source, version.hash, version.generation
+    );
+    for failure in &failed {
+        warn!("重载后挂载失败: {}", failure);
+    }
+    *current = Some(version.clone());
+    Ok(Ok(serde_json::json!({
+        "version": version,
+        "failed": failed,
+    })))
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use xnet_common::IpSignals;
+
+use crate::server::EbpfManager;
+
+// 信誉分范围 0~100，新IP默认为满分
+const MAX_SCORE: f64 = 100.0;
+// 各类信号的扣分权重
+const PENALTY_PORT_SWITCH: f64 = 2.0;
+const PENALTY_FAILED_HANDSHAKE: f64 = 1.0;
+const PENALTY_RST: f64 = 0.5;
+// 每次被封禁额外扣分
+const PENALTY_BAN: f64 = 10.0;
+// 信誉分恢复的半衰期
+const DECAY_HALF_LIFE: Duration = Duration::from_secs(600);
+// 信誉分恢复到与满分相差不到该值、且本轮没有新信号的IP视为已衰减完，从内核和内存中移除，
+// 否则 IP_SIGNALS 写满后新的远端IP无法再记录信号
+const PRUNE_SCORE_EPSILON: f64 = 0.01;
+
+// 单个远端IP的信誉状态
+#[derive(Debug, Clone)]
+pub struct ReputationEntry {
+    pub score: f64,
+    pub signals: IpSignals,
+    pub bans: u32,
+    pub blocked: bool,
+    pub last_update: Instant,
+}
+
+impl ReputationEntry {
+    fn new() -> Self {
+        Self {
+            score: MAX_SCORE,
+            signals: bytemuck::Zeroable::zeroed(),
+            bans: 0,
+            blocked: false,
+            last_update: Instant::now(),
+        }
+    }
+
+    // 按时间衰减历史扣分，再根据新增信号扣分
+    fn update(&mut self, signals: IpSignals, now: Instant) {
+        let elapsed = now.duration_since(self.last_update).as_secs_f64();
+        let decay = 0.5f64.powf(elapsed / DECAY_HALF_LIFE.as_secs_f64());
+        self.score = MAX_SCORE - (MAX_SCORE - self.score) * decay;
+
+        let prev = self.signals;
+        let port_switches = signals.port_switches.saturating_sub(prev.port_switches);
+        let rst_packets = signals.rst_packets.saturating_sub(prev.rst_packets);
+        let failed_handshakes =
+            failed_handshakes(&signals).saturating_sub(failed_handshakes(&prev));
+
+        let penalty = port_switches as f64 * PENALTY_PORT_SWITCH
+            + failed_handshakes as f64 * PENALTY_FAILED_HANDSHAKE
+            + rst_packets as f64 * PENALTY_RST;
+        self.score = (self.score - penalty).clamp(0.0, MAX_SCORE);
+        self.signals = signals;
+        self.last_update = now;
+    }
+
+    pub fn to_json(&self, ip: Ipv4Addr) -> Value {
+        serde_json::json!({
+            "ip": ip.to_string(),
+            "score": self.score,
+            "blocked": self.blocked,
+            "bans": self.bans,
+            "signals": {
+                "syn_packets": self.signals.syn_packets,
+                "handshakes_completed": self.signals.handshakes_completed,
+                "failed_handshakes": failed_handshakes(&self.signals),
+                "rst_packets": self.signals.rst_packets,
+                "port_switches": self.signals.port_switches,
+                "dropped_syns": self.signals.dropped_syns,
+            },
+            "last_update_secs": self.last_update.elapsed().as_secs_f64(),
+        })
+    }
+}
+
+fn failed_handshakes(signals: &IpSignals) -> u64 {
+    signals
+        .syn_packets
+        .saturating_sub(signals.handshakes_completed)
+}
+
+// 信誉策略: 信誉分低于 min_score 的IP会被禁止新建连接
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct ReputationPolicy {
+    pub min_score: Option<f64>,
+}
+
+lazy_static::lazy_static! {
+    pub static ref REPUTATION: Mutex<HashMap<Ipv4Addr, ReputationEntry>> = Mutex::new(HashMap::new());
+    pub static ref REPUTATION_POLICY: Mutex<ReputationPolicy> = Mutex::new(ReputationPolicy::default());
+    // 从 IP_SIGNALS 移除的IP的累计信号，汇总全部信号时需加上，否则移除后总数变小。
+    // 移除条目时持有该锁，汇总时先加锁再读取 IP_SIGNALS，避免读到移除了一半的状态
+    pub static ref EVICTED_SIGNALS: Mutex<IpSignals> = Mutex::new(bytemuck::Zeroable::zeroed());
+}
+
+// 读取内核信号，更新信誉分并同步封禁列表
+pub async fn refresh(ebpf_manager: &EbpfManager) -> Result<(), anyhow::Error> {
+    let policy = *REPUTATION_POLICY.lock().await;
+    let mut reputation = REPUTATION.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let now = Instant::now();
+
+    let signals: Vec<(u32, IpSignals)> = {
+        let map = ebpf
+            .map("IP_SIGNALS")
+            .ok_or_else(|| anyhow::anyhow!("IP_SIGNALS map not found"))?;
+        let map = AyaHashMap::<&MapData, u32, IpSignals>::try_from(map)?;
+        map.iter().filter_map(|r| r.ok()).collect()
+    };
+
+    let mut block = AyaHashMap::<&mut MapData, u32, u32>::try_from(
+        ebpf.map_mut("REPUTATION_BLOCK")
+            .ok_or_else(|| anyhow::anyhow!("REPUTATION_BLOCK map not found"))?,
+    )?;
+
+    let mut decayed = Vec::new();
+    for (raw_ip, signals) in signals {
+        // 内核中的IP为网络字节序
+        let ip = Ipv4Addr::from(u32::from_be(raw_ip));
+        let entry = reputation.entry(ip).or_insert_with(ReputationEntry::new);
+        let idle = bytemuck::bytes_of(&signals) == bytemuck::bytes_of(&entry.signals);
+        entry.update(signals, now);
+
+        let should_block = policy.min_score.is_some_and(|min| entry.score < min);
+        if should_block && !entry.blocked {
+            block.insert(raw_ip, 1, 0)?;
+            entry.blocked = true;
+            entry.bans += 1;
+            entry.score = (entry.score - PENALTY_BAN).max(0.0);
+            info!(
+                src_ip = %ip,
+                action = "block",
+                "IP {} 信誉分 {:.1} 过低，禁止新建连接",
+                ip,
+                entry.score
+            );
+        } else if !should_block && entry.blocked {
+            block.remove(&raw_ip)?;
+            entry.blocked = false;
+            info!(
+                src_ip = %ip,
+                action = "unblock",
+                "IP {} 信誉分恢复到 {:.1}，解除封禁",
+                ip,
+                entry.score
+            );
+        }
+
+        if idle && !entry.blocked && entry.score >= MAX_SCORE - PRUNE_SCORE_EPSILON {
+            decayed.push(raw_ip);
+        }
+    }
+
+    if !decayed.is_empty() {
+        let mut evicted = EVICTED_SIGNALS.lock().await;
+        let mut map = AyaHashMap::<&mut MapData, u32, IpSignals>::try_from(
+            ebpf.map_mut("IP_SIGNALS")
+                .ok_or_else(|| anyhow::anyhow!("IP_SIGNALS map not found"))?,
+        )?;
+        for raw_ip in decayed.iter() {
+            // 内核中已被删除的条目忽略
+            if map.remove(raw_ip).is_err() {
+                continue;
+            }
+            if let Some(entry) = reputation.remove(&Ipv4Addr::from(u32::from_be(*raw_ip))) {
+                evicted.syn_packets += entry.signals.syn_packets;
+                evicted.handshakes_completed += entry.signals.handshakes_completed;
+                evicted.rst_packets += entry.signals.rst_packets;
+                evicted.port_switches += entry.signals.port_switches;
+                evicted.dropped_syns += entry.signals.dropped_syns;
+            }
+        }
+        debug!("移除了 {} 个信誉分已恢复的IP", decayed.len());
+    }
+
+    Ok(())
+}
+
+// 启动信誉分后台刷新任务
+pub fn start(ebpf_manager: Arc<EbpfManager>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = refresh(&ebpf_manager).await {
+                warn!("刷新IP信誉分失败: {}", e);
+            }
+        }
+    });
+}
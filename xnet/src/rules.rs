@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use tracing::info;
+
+use crate::firewall::StoredRule;
+use crate::nat::StoredNatRule;
+use crate::server::EbpfManager;
+use crate::shaping::ShapingRuleConfig;
+
+// 防火墙、NAT和整形规则，GET /rules 导出全部规则，PUT /rules 整体替换，
+// 未出现的部分保持不变，给出空数组表示清空
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct RuleSet {
+    pub firewall: Option<Vec<StoredRule>>,
+    pub nat: Option<Vec<StoredNatRule>>,
+    pub shaping: Option<Vec<ShapingRuleConfig>>,
+}
+
+// 规则目录中各类规则的文件
+fn files(dir: &Path) -> (PathBuf, PathBuf, PathBuf) {
+    (
+        dir.join("firewall.json"),
+        dir.join("nat.json"),
+        dir.join("shaping.json"),
+    )
+}
+
+// 先写入同目录下的临时文件再重命名，写到一半时进程退出或断电不会留下不完整的规则文件
+pub(crate) fn write_file(path: &Path, content: &str) -> Result<(), anyhow::Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, content)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| anyhow::anyhow!("failed to write {}: {}", path.display(), e))
+}
+
+// 启动时从规则目录加载规则，之后每次修改都写回对应的文件，目录不存在时创建
+pub async fn load(
+    ebpf_manager: &EbpfManager,
+    dir: &Path,
+    load_firewall: bool,
+) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| anyhow::anyhow!("failed to create {}: {}", dir.display(), e))?;
+    let (firewall, nat, shaping) = files(dir);
+    // 同时指定了 --firewall-rules-file 时防火墙规则以该文件为准
+    if load_firewall {
+        crate::firewall::load(ebpf_manager, &firewall).await?;
+    }
+    crate::nat::load(ebpf_manager, &nat).await?;
+    crate::shaping::load(ebpf_manager, &shaping).await?;
+    info!("规则目录: {}", dir.display());
+    Ok(())
+}
+
+// 读取并校验规则目录中的文件，返回防火墙、NAT和整形规则数
+pub(crate) fn read(dir: &Path) -> Result<(usize, usize, usize), anyhow::Error> {
+    let (firewall, nat, shaping) = files(dir);
+    Ok((
+        crate::firewall::read_rules(&firewall)?.len(),
+        crate::nat::read_rules(&nat)?.len(),
+        crate::shaping::read_rules(&shaping)?.len(),
+    ))
+}
+
+pub async fn export() -> RuleSet {
+    RuleSet {
+        firewall: Some(crate::firewall::export().await),
+        nat: Some(crate::nat::export().await),
+        shaping: Some(crate::shaping::export().await),
+    }
+}
+
+// 先校验全部规则，有任何一条不合法时不做修改；都合法后逐类替换，
+// 防火墙规则表通过切换生效的一半一次替换，不会出现只有部分新规则生效的中间状态
+pub async fn replace(
+    ebpf_manager: &EbpfManager,
+    set: RuleSet,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let firewall = match set.firewall.map(crate::firewall::validate).transpose() {
+        Ok(rules) => rules,
+        Err(e) => return Ok(Err(e)),
+    };
+    let nat = match set.nat.map(crate::nat::validate).transpose() {
+        Ok(rules) => rules,
+        Err(e) => return Ok(Err(e)),
+    };
+    let shaping = match set.shaping.map(crate::shaping::validate).transpose() {
+        Ok(rules) => rules,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    if let Some(rules) = firewall {
+        crate::firewall::replace_all(ebpf_manager, rules).await?;
+    }
+    if let Some(rules) = nat {
+        crate::nat::replace_all(ebpf_manager, rules).await?;
+    }
+    if let Some(rules) = shaping {
+        crate::shaping::replace_all(ebpf_manager, rules).await?;
+    }
+    Ok(Ok(()))
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::server::EbpfManager;
+
+// 采样率上限，再大时按连接的统计已经没有意义
+const MAX_RATE: u32 = 1_000_000;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SamplingConfig {
+    pub iface: String,
+    // 每 rate 个包处理1个，1 表示关闭采样
+    pub rate: u32,
+}
+
+lazy_static::lazy_static! {
+    // 设备名 -> (ifindex, 采样率)
+    static ref SAMPLING: Mutex<BTreeMap<String, (u32, u32)>> = Mutex::new(BTreeMap::new());
+}
+
+// 解析 --sampling 参数，格式 iface=rate
+pub fn parse_sampling(s: &str) -> Result<SamplingConfig, String> {
+    let invalid = || format!("invalid sampling '{}', expected iface=rate", s);
+    let (iface, rate) = s.split_once('=').ok_or_else(invalid)?;
+    let rate: u32 = rate.trim().parse().map_err(|_| invalid())?;
+    if iface.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(SamplingConfig {
+        iface: iface.trim().to_string(),
+        rate,
+    })
+}
+
+// 各设备的采样率
+pub async fn list() -> Vec<Value> {
+    SAMPLING
+        .lock()
+        .await
+        .iter()
+        .map(|(iface, (ifindex, rate))| {
+            serde_json::json!({"iface": iface, "ifindex": ifindex, "rate": rate})
+        })
+        .collect()
+}
+
+// 校验采样率和设备，返回设备的 ifindex
+pub(crate) fn check(config: &SamplingConfig) -> Result<u32, String> {
+    if config.rate > MAX_RATE {
+        return Err(format!("rate must be at most {}", MAX_RATE));
+    }
+    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", config.iface))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .ok_or_else(|| format!("Interface {} does not exist", config.iface))
+}
+
+// 设置设备的采样率，rate 为 0 或 1 时关闭采样
+pub async fn set(
+    ebpf_manager: &EbpfManager,
+    config: SamplingConfig,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let ifindex = match check(&config) {
+        Ok(ifindex) => ifindex,
+        Err(msg) => return Ok(Err(msg)),
+    };
+
+    let mut sampling = SAMPLING.lock().await;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, u32, u32>::try_from(
+        ebpf.map_mut("flow_sampling")
+            .ok_or_else(|| anyhow::anyhow!("flow_sampling map not found"))?,
+    )?;
+    if config.rate <= 1 {
+        let _ = map.remove(&ifindex);
+        sampling.remove(&config.iface);
+        info!(iface = %config.iface, "设备 {} 关闭采样", config.iface);
+    } else {
+        map.insert(ifindex, config.rate, 0)?;
+        sampling.insert(config.iface.clone(), (ifindex, config.rate));
+        info!(
+            iface = %config.iface,
+            rate = config.rate,
+            "设备 {} 采样率 1/{}",
+            config.iface,
+            config.rate
+        );
+    }
+    Ok(Ok(()))
+}
+
+// 启动时应用 --sampling 参数
+pub async fn init(
+    ebpf_manager: &EbpfManager,
+    configs: &[SamplingConfig],
+) -> Result<(), anyhow::Error> {
+    for config in configs {
+        set(ebpf_manager, config.clone())
+            .await?
+            .map_err(anyhow::Error::msg)?;
+    }
+    Ok(())
+}
@@ -1,49 +1,107 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::response::IntoResponse;
+use axum::http::header;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::Extension;
-use axum::{extract::{Json, Path}, http::StatusCode, Router};
+use axum::{extract::{Json, Path, Query}, http::StatusCode, Router};
 use aya::maps::HashMap as AyaHashMap;
 use aya::maps::MapData;
+use aya::maps::ProgramArray;
 use aya::programs::tc::SchedClassifierLinkId;
-use aya::programs::Xdp;
+use aya::programs::xdp::XdpLinkId;
+use aya::programs::{Program, ProgramError, SockOps, TracePoint, UProbe, Xdp};
 use aya::programs::{SchedClassifier as Tc, TcAttachType};
 use aya::Ebpf;
-use log::info;
 use tokio::sync::Mutex;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{info, warn};
+use xnet_common::{XDP_PARSER_IPV4, XDP_PARSER_IPV6, XDP_PARSER_TCP, XDP_PARSER_UDP};
 
-use crate::traffic::TrafficStats;
+use crate::afxdp::AfXdpRequest;
+use crate::alert::AlertRule;
+use crate::anomaly::AnomalyConfig;
+use crate::api::{
+    AlertsResponse, AnomaliesResponse, ApiError, AttachPattern, AttachedInterfaces, CaptureSaved,
+    IdResponse, IfaceResponse, TcAttachment, XdpAttachment,
+};
+use crate::auth::ApiAuth;
+use crate::bogon::{BogonIfaceRequest, BogonPrefixesRequest};
+use crate::canary::{CanaryConfig, ImpactCounters, Rollback};
+use crate::capture::{CaptureError, CaptureRequest};
+use crate::dscp::DscpRule;
+use crate::mac::{MacFilterRule, MacModeRequest};
+use crate::egress::EgressRule;
+use crate::geo::GeoRuleConfig;
+use crate::honeypot::HoneypotPortConfig;
+use crate::firewall::{AllowlistEntry, DefaultDenyRequest, FirewallRule, RaGuardRequest, TcpScanRequest};
+use crate::lb::LbServiceConfig;
+use crate::map_cache::MapCache;
+use crate::nat::NatRule;
+use crate::nft::NftImportQuery;
+use crate::export::FormatQuery;
+use crate::peer::PeerConfig;
+use crate::plugin::PluginConfig;
+use crate::quota::QuotaConfig;
+use crate::reload::ReloadRequest;
+use crate::reputation::ReputationPolicy;
+use crate::rules::RuleSet;
+use crate::sampling::SamplingConfig;
+use crate::shaping::ShapingRuleConfig;
+use crate::skb_mark::{MarkRule, MarkSkip};
+use crate::socket_owner::SocketFlowQuery;
+use crate::sockops::SocketQuery;
+use crate::tc_attach::{TcDirection, TcOptions};
+use crate::ssl::TlsStatsQuery;
+use crate::tcpstate::TcpStateQuery;
+use crate::sqlite::SqliteConfig;
+use crate::startup_attach::{AttachPrograms, AttachSpec};
+use crate::tls::TlsOptions;
+use crate::traffic::{FanoutQuery, StatsQuery, TrafficStats};
+use crate::unix_socket::UnixSocketOptions;
+use crate::watch::WatchRule;
+use crate::xdp_attach::XdpMode;
 
 // 包装 eBPF 实例，提供线程安全的可变访问
+// 程序的加载、挂载和 map 的写入需要获取 ebpf 的锁，读取统计使用 maps 中的句柄
 pub struct EbpfManager {
-    ebpf: Mutex<Ebpf>,
+    pub(crate) ebpf: Mutex<Ebpf>,
+    maps: std::sync::RwLock<Arc<MapCache>>,
+    // 设备名到设备ID的映射，写入时不需要获取 ebpf 的锁
+    device_map: Mutex<AyaHashMap<MapData, [u8; 16], u32>>,
 }
 
 impl EbpfManager {
-    pub fn new(ebpf: Ebpf) -> Self {
-        Self {
+    pub fn new(ebpf: Ebpf) -> Result<Self, anyhow::Error> {
+        let maps = MapCache::new(&ebpf)?;
+        let device_map = crate::map_cache::typed(&ebpf, "device_map")?;
+        Ok(Self {
             ebpf: Mutex::new(ebpf),
-        }
+            maps: std::sync::RwLock::new(Arc::new(maps)),
+            device_map: Mutex::new(device_map),
+        })
+    }
+
+    // 只读的 map 句柄
+    pub fn maps(&self) -> Arc<MapCache> {
+        self.maps.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // 热重载后替换为新目标文件的 map 句柄
+    pub(crate) fn set_maps(&self, maps: MapCache) {
+        *self.maps.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(maps);
     }
 
     // 加载所有 eBPF 程序
     pub async fn load_programs(&self) -> Result<(), anyhow::Error> {
         let mut ebpf = self.ebpf.lock().await;
-
-        // 加载 XDP 程序
-        let xnet_xdp = ebpf.program_mut("xnet_xdp").unwrap();
-        let xnet_xdp: &mut Xdp = xnet_xdp.try_into().unwrap();
-        xnet_xdp.load()?;
-        info!("xnet_xdp program loaded");
-
-        // 加载 TC 程序
-        let xnet_tc = ebpf.program_mut("xnet_tc").unwrap();
-        let xnet_tc: &mut Tc = xnet_tc.try_into().unwrap();
-        xnet_tc.load()?;
-        info!("xnet_tc program loaded");
-
-        Ok(())
+        load_programs(&mut ebpf)
     }
 
     // 设置设备映射
@@ -52,54 +110,128 @@ impl EbpfManager {
         device_name: &str,
         device_id: u32,
     ) -> Result<(), anyhow::Error> {
-        let mut ebpf = self.ebpf.lock().await;
-
-        if let Some(device_map) = ebpf.map_mut("device_map") {
-            if let Ok(mut device_map) =
-                AyaHashMap::<&mut MapData, [u8; 16], u32>::try_from(device_map)
-            {
-                // 将设备名称转换为字节数组
-                let mut device_bytes = [0u8; 16];
-                let name_bytes = device_name.as_bytes();
-                let copy_len = std::cmp::min(name_bytes.len(), 16);
-                device_bytes[..copy_len].copy_from_slice(&name_bytes[..copy_len]);
+        // 将设备名称转换为字节数组
+        let mut device_bytes = [0u8; 16];
+        let name_bytes = device_name.as_bytes();
+        let copy_len = std::cmp::min(name_bytes.len(), 16);
+        device_bytes[..copy_len].copy_from_slice(&name_bytes[..copy_len]);
 
-                device_map.insert(&device_bytes, &device_id, 0)?;
-                info!("设备映射设置成功: {} -> {}", device_name, device_id);
-            }
-        }
+        self.device_map
+            .lock()
+            .await
+            .insert(device_bytes, device_id, 0)?;
+        info!("设备映射设置成功: {} -> {}", device_name, device_id);
 
         Ok(())
     }
+}
 
-    // 设置设备上下文
-    pub async fn set_device_context(
-        &self,
-        device_id: u32,
-        is_ingress: bool,
-    ) -> Result<(), anyhow::Error> {
-        let mut ebpf = self.ebpf.lock().await;
+// 热重载时把 XDP 和流量统计 tc 程序的挂载原子地替换为新目标文件中的程序，返回替换失败的挂载
+pub(crate) async fn swap_links(ebpf_manager: &EbpfManager, new: &mut Ebpf) -> Vec<String> {
+    let mut old = ebpf_manager.ebpf.lock().await;
+    let mut failed = Vec::new();
 
-        if let Some(device_context) = ebpf.map_mut("device_context") {
-            if let Ok(mut device_context) =
-                AyaHashMap::<&mut MapData, u32, u32>::try_from(device_context)
-            {
-                // 将设备ID和方向编码到一个u32中
-                let context_value = device_id | ((if is_ingress { 0 } else { 1 }) << 16);
-                device_context.insert(&device_id, &context_value, 0)?;
-                info!(
-                    "设备上下文设置成功: device_id={}, direction={}",
-                    device_id,
-                    if is_ingress { "ingress" } else { "egress" }
-                );
+    for (program, links) in [("xnet_xdp", &*XDP_LINK_ID), ("xnet_lb", &*LB_LINK_ID)] {
+        let mut links = links.lock().await;
+        let entries: Vec<(String, (XdpLinkId, XdpMode))> = links.drain().collect();
+        // 替换挂载不改变挂载模式
+        for (iface, (link_id, mode)) in entries {
+            let result = (|| -> Result<XdpLinkId, anyhow::Error> {
+                let xdp: &mut Xdp = program_mut(&mut old, program)?;
+                let link = xdp.take_link(link_id)?;
+                let xdp: &mut Xdp = program_mut(new, program)?;
+                Ok(xdp.attach_to_link(link)?)
+            })();
+            match result {
+                Ok(link_id) => {
+                    links.insert(iface, (link_id, mode));
+                }
+                Err(e) => failed.push(format!("{} {}: {}", program, iface, e)),
             }
         }
+    }
 
-        Ok(())
+    let mut links = TC_LINK_ID.lock().await;
+    let entries: Vec<(String, SchedClassifierLinkId)> = links.drain().collect();
+    for (key, link_id) in entries {
+        let attach_type = if key.ends_with("Egress") { TcAttachType::Egress } else { TcAttachType::Ingress };
+        let result = (|| -> Result<SchedClassifierLinkId, anyhow::Error> {
+            let tc: &mut Tc = program_mut(&mut old, tc_program(attach_type))?;
+            let link = tc.take_link(link_id)?;
+            let tc: &mut Tc = program_mut(new, tc_program(attach_type))?;
+            Ok(tc.attach_to_link(link)?)
+        })();
+        match result {
+            Ok(link_id) => {
+                links.insert(key, link_id);
+            }
+            Err(e) => failed.push(format!("{}: {}", key, e)),
+        }
     }
+    failed
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+// 加载所有 eBPF 程序，热重载时也用于加载新的目标文件中的程序
+pub(crate) fn load_programs(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    // 加载 XDP 程序
+    let xnet_xdp: &mut Xdp = program_mut(ebpf, "xnet_xdp")?;
+    xnet_xdp.load()?;
+    info!("xnet_xdp program loaded");
+
+    // 加载按协议拆分的 XDP 解析程序并填入尾调用表
+    let mut parsers = Vec::new();
+    for (index, name) in XDP_PARSERS {
+        let program: &mut Xdp = program_mut(ebpf, name)?;
+        program.load()?;
+        parsers.push((index, program.fd()?.try_clone()?));
+    }
+    let mut parser_map = ProgramArray::try_from(
+        ebpf.map_mut("xdp_parsers")
+            .ok_or_else(|| anyhow::anyhow!("xdp_parsers map not found"))?,
+    )?;
+    for (index, fd) in &parsers {
+        parser_map.set(*index, fd, 0)?;
+    }
+    info!("xnet_xdp parser programs loaded");
+
+    // 加载负载均衡 XDP 程序
+    let xnet_lb: &mut Xdp = program_mut(ebpf, "xnet_lb")?;
+    xnet_lb.load()?;
+    info!("xnet_lb program loaded");
+
+    // 加载 TC 程序，ingress 和 egress 分别使用各自的入口
+    for name in ["xnet_tc_ingress", "xnet_tc_egress"] {
+        let program: &mut Tc = program_mut(ebpf, name)?;
+        program.load()?;
+    }
+    info!("xnet_tc programs loaded");
+
+    // 加载出方向整形 TC 程序
+    let xnet_shaper: &mut Tc = program_mut(ebpf, "xnet_shaper")?;
+    xnet_shaper.load()?;
+    info!("xnet_shaper program loaded");
+
+    // 加载 sock_ops 程序，启用时再挂载到 cgroup
+    let xnet_sockops: &mut SockOps = program_mut(ebpf, "xnet_sockops")?;
+    xnet_sockops.load()?;
+    info!("xnet_sockops program loaded");
+
+    // 加载 TCP 状态跟踪点程序
+    let xnet_tcp_state: &mut TracePoint = program_mut(ebpf, "xnet_tcp_state")?;
+    xnet_tcp_state.load()?;
+    info!("xnet_tcp_state program loaded");
+
+    // 加载 libssl uprobe 程序，启用时再挂载
+    for name in ["xnet_ssl_set_fd", "xnet_ssl_enter", "xnet_ssl_read_ret", "xnet_ssl_write_ret"] {
+        let program: &mut UProbe = program_mut(ebpf, name)?;
+        program.load()?;
+    }
+    info!("xnet_ssl uprobe programs loaded");
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum Action {
     Add = 1,
@@ -110,76 +242,241 @@ enum Action {
 struct TrafficCountDeviceRequest {
     iface: String,
     action: Action,
+    // 网络命名空间文件，例如 /var/run/netns/ns1 或 /proc/<pid>/ns/net，为空时为 xnet 所在命名空间
+    #[serde(default)]
+    netns: Option<PathBuf>,
+    // tc filter 的优先级和句柄，两个方向使用相同的值
+    #[serde(flatten)]
+    tc: TcOptions,
+    // 只挂载/卸载流量统计程序的一个方向，为空时为两个方向
+    #[serde(default)]
+    direction: TcDirection,
 }
 
+// 已挂载设备的 ifindex 和所在命名空间的 inode
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceMapping {
+    pub device_id: u32,
+    pub netns: u64,
+}
+
+// xnet_xdp 尾调用的解析程序，新增协议时在 xnet-common 中分配槽位并加到这里
+const XDP_PARSERS: [(u32, &str); 4] = [
+    (XDP_PARSER_IPV4, "xnet_xdp_ipv4"),
+    (XDP_PARSER_IPV6, "xnet_xdp_ipv6"),
+    (XDP_PARSER_TCP, "xnet_xdp_tcp"),
+    (XDP_PARSER_UDP, "xnet_xdp_udp"),
+];
+
 lazy_static::lazy_static! {
     static ref TC_LINK_ID: Mutex<HashMap<String, SchedClassifierLinkId>> = Mutex::new(HashMap::new());
-    pub static ref DEVICE_MAPPINGS: Mutex<HashMap<String, u32>> = Mutex::new(HashMap::new());
+    // 设备名 -> (挂载ID, 挂载模式)
+    static ref XDP_LINK_ID: Mutex<HashMap<String, (XdpLinkId, XdpMode)>> = Mutex::new(HashMap::new());
+    static ref LB_LINK_ID: Mutex<HashMap<String, (XdpLinkId, XdpMode)>> = Mutex::new(HashMap::new());
+    // 通过接口卸载的 (设备名, ingress/egress/xdp)，设备模式的定期挂载跳过这些挂载点
+    static ref USER_DETACHED: Mutex<HashSet<(String, &'static str)>> = Mutex::new(HashSet::new());
+    // 设备名 -> 设备映射，其他命名空间中的设备名为 iface@netns_inode
+    pub static ref DEVICE_MAPPINGS: Mutex<HashMap<String, DeviceMapping>> = Mutex::new(HashMap::new());
+}
+
+// 挂载设备时使用的设备名，不同命名空间中的同名设备通过 inode 区分
+fn device_label(iface: &str, netns: Option<&std::path::Path>) -> Result<(String, u64), anyhow::Error> {
+    match netns {
+        Some(path) => {
+            let inode = crate::netns::inode_of_path(path)?;
+            if inode == crate::netns::host_inode() {
+                Ok((iface.to_string(), inode))
+            } else {
+                Ok((format!("{}@{}", iface, inode), inode))
+            }
+        }
+        None => Ok((iface.to_string(), crate::netns::host_inode())),
+    }
+}
+
+// 在指定命名空间中查询设备的 ifindex
+fn netns_ifindex(path: &std::path::Path, iface: &str) -> Result<u32, anyhow::Error> {
+    crate::netns::run_in(path, || {
+        let name = std::ffi::CString::new(iface)?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(anyhow::anyhow!("Interface {} does not exist in {}", iface, path.display()));
+        }
+        Ok(ifindex)
+    })
+}
+
+// 通过接口卸载时记录，重新挂载时清除，设备模式不会重新挂载记录中的设备和方向
+async fn user_detached(iface: &str, program: &'static str, action: Action) {
+    let mut detached = USER_DETACHED.lock().await;
+    match action {
+        Action::Remove => detached.insert((iface.to_string(), program)),
+        Action::Add => detached.remove(&(iface.to_string(), program)),
+    };
+}
+
+// 挂载点对应的流量统计程序
+fn tc_program(attach_type: TcAttachType) -> &'static str {
+    match attach_type {
+        TcAttachType::Egress => "xnet_tc_egress",
+        _ => "xnet_tc_ingress",
+    }
+}
+
+// 按名称取出程序并转换为对应类型，目标文件中缺少该程序时返回错误
+pub(crate) fn program_mut<'a, T>(ebpf: &'a mut Ebpf, name: &str) -> Result<&'a mut T, anyhow::Error>
+where
+    &'a mut T: TryFrom<&'a mut Program, Error = ProgramError>,
+{
+    let program = ebpf
+        .program_mut(name)
+        .ok_or_else(|| anyhow::anyhow!("{} program not found", name))?;
+    Ok(program.try_into()?)
 }
 
 fn key_from_iface(iface: &str, attach_type: TcAttachType) -> String {
     format!("xnet_tc_{}_{:?}", iface, attach_type)
 }
 
+// 各类程序挂载的网卡，流量统计按方向列出
+pub(crate) async fn attached_interfaces() -> AttachedInterfaces {
+    let mut traffic: Vec<TcAttachment> = TC_LINK_ID
+        .lock()
+        .await
+        .keys()
+        .filter_map(|key| {
+            let (iface, direction) = key.strip_prefix("xnet_tc_")?.rsplit_once('_')?;
+            Some(TcAttachment {
+                iface: iface.to_string(),
+                direction: direction.to_lowercase(),
+            })
+        })
+        .collect();
+    traffic.sort();
+    let xdp = |links: &HashMap<String, (XdpLinkId, XdpMode)>| {
+        let mut attachments: Vec<XdpAttachment> = links
+            .iter()
+            .map(|(iface, (_, mode))| XdpAttachment { iface: iface.clone(), mode: *mode })
+            .collect();
+        attachments.sort();
+        attachments
+    };
+    let firewall = xdp(&*XDP_LINK_ID.lock().await);
+    let lb = xdp(&*LB_LINK_ID.lock().await);
+    AttachedInterfaces {
+        traffic,
+        firewall,
+        lb,
+        shaping: crate::shaping::ifaces().await,
+        patterns: crate::startup_attach::patterns()
+            .await
+            .into_iter()
+            .map(|(pattern, attach)| AttachPattern { pattern, tc: attach.tc.is_some(), xdp: attach.xdp })
+            .collect(),
+    }
+}
+
 // 查询设备映射及流量统计
 async fn traffic_device_state(
-    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
-) -> impl IntoResponse {
-    let mut traffic_stats = crate::traffic::TRAFFIC_STATS.lock().await;
-    let ebpf = ebpf_manager.ebpf.lock().await;
-    traffic_stats.update_from_ebpf(&ebpf);
+    Query(format): Query<FormatQuery>,
+) -> Response {
+    let traffic_stats = crate::traffic::snapshot();
     let device_stats = traffic_stats.return_device_stats();
-    (StatusCode::OK, Json(device_stats))
+    crate::export::response(format.format, device_stats.into(), |_| traffic_stats.device_rows())
+}
+
+// 查询设备统计，每个设备和方向一行，包含速率
+async fn traffic_device_stats(
+    Query(format): Query<FormatQuery>,
+) -> Response {
+    let traffic_stats = crate::traffic::snapshot();
+    let rows = traffic_stats.device_rows();
+    crate::export::response(format.format, rows.into(), crate::export::items)
 }
 
-// 查询设备连接统计
+// 查询设备连接统计，支持过滤、排序和分页
 async fn traffic_device_connection_stats(
-    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
-) -> impl IntoResponse {
-    let mut traffic_stats = crate::traffic::TRAFFIC_STATS.lock().await;
-    let ebpf = ebpf_manager.ebpf.lock().await;
-    traffic_stats.update_from_ebpf(&ebpf);
-    let connection_stats = traffic_stats.return_device_connection_stats();
-    (StatusCode::OK, Json(connection_stats))
+    Query(query): Query<StatsQuery>,
+    Query(format): Query<FormatQuery>,
+) -> Response {
+    let traffic_stats = crate::traffic::snapshot();
+    let connection_stats = traffic_stats.query_connections(None, &query);
+    crate::export::response(format.format, connection_stats, crate::export::items)
+}
+
+// 合并两个方向后的连接统计及字节数的不对称程度
+async fn traffic_flows(Query(query): Query<StatsQuery>, Query(format): Query<FormatQuery>) -> Response {
+    let traffic_stats = crate::traffic::snapshot();
+    match traffic_stats.query_flows(&query) {
+        Ok(flows) => crate::export::response(format.format, flows, crate::export::items),
+        Err(msg) => ApiError::BadRequest(msg).into_response(),
+    }
+}
+
+// 滑动窗口内每个本地端口的对端IP数和每个对端IP访问的本地端口数
+async fn traffic_fanout(Query(query): Query<FanoutQuery>) -> impl IntoResponse {
+    let traffic_stats = crate::traffic::snapshot();
+    (StatusCode::OK, Json(traffic_stats.fanout(&query)))
 }
 
 // 查询指定设备的连接统计
 async fn traffic_device_connection_stats_by_id(
-    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
     Path(device_id): Path<u32>,
+    Query(query): Query<StatsQuery>,
+    Query(format): Query<FormatQuery>,
+) -> Response {
+    let traffic_stats = crate::traffic::snapshot();
+    let result = traffic_stats.query_connections(Some(device_id), &query);
+    crate::export::response(format.format, result, crate::export::items)
+}
+
+// 查询端口统计，支持过滤、排序和分页
+async fn traffic_port_stats(
+    Query(query): Query<StatsQuery>,
+    Query(format): Query<FormatQuery>,
+) -> Response {
+    let traffic_stats = crate::traffic::snapshot();
+    match traffic_stats.query_ports(&query) {
+        Ok(result) => crate::export::response(format.format, result, crate::export::items),
+        Err(e) => ApiError::BadRequest(e).into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProtocolStatsQuery {
+    device: Option<String>,
+}
+
+// 查询按 IP 协议区分的流量构成，可按设备过滤
+async fn traffic_protocol_stats(
+    Query(query): Query<ProtocolStatsQuery>,
 ) -> impl IntoResponse {
-    let mut traffic_stats = crate::traffic::TRAFFIC_STATS.lock().await;
-    let ebpf = ebpf_manager.ebpf.lock().await;
-    traffic_stats.update_from_ebpf(&ebpf);
-    let connection_stats = traffic_stats.query_device_connection_stats(device_id);
-    
-    let mut result = Vec::new();
-    for stats in connection_stats {
-        let direction_str = if stats.direction == 0 { "ingress" } else { "egress" };
-        let protocol_str = if stats.protocol == 6 { "TCP" } else if stats.protocol == 17 { "UDP" } else { "UNKNOWN" };
-        
-        let stats_info = serde_json::json!({
-            "device_id": stats.device_id,
-            "src_port": stats.src_port,
-            "dst_port": stats.dst_port,
-            "direction": direction_str,
-            "protocol": protocol_str,
-            "timestamp": stats.timestamp,
-            "total_packets": stats.total_packets,
-            "total_bytes": stats.total_bytes
-        });
-        
-        result.push(stats_info);
+    let traffic_stats = crate::traffic::snapshot();
+    (StatusCode::OK, Json(traffic_stats.protocol_breakdown(query.device.as_deref())))
+}
+
+// 查询按设备和方向统计的 ICMPv6 类型(邻居发现、路由器发现、echo)，可按设备过滤
+async fn traffic_icmpv6_stats(
+    Query(query): Query<ProtocolStatsQuery>,
+) -> impl IntoResponse {
+    let traffic_stats = crate::traffic::snapshot();
+    (StatusCode::OK, Json(traffic_stats.icmpv6_rows(query.device.as_deref())))
+}
+
+// 查询每个MAC地址的收发包数和字节数
+async fn traffic_mac_stats(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Query(format): Query<FormatQuery>,
+) -> Response {
+    match crate::mac::stats(&ebpf_manager).await {
+        Ok(rows) => crate::export::response(format.format, serde_json::json!(rows), crate::export::items),
+        Err(e) => ApiError::from(e).into_response(),
     }
-    
-    (StatusCode::OK, Json(result))
 }
 
 // 查询对应接口的流量统计信息
-async fn traffic_count(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> impl IntoResponse {
-    let mut traffic_stats = crate::traffic::TRAFFIC_STATS.lock().await;
-    let ebpf = ebpf_manager.ebpf.lock().await;
-    traffic_stats.update_from_ebpf(&ebpf);
+async fn traffic_count() -> impl IntoResponse {
+    let traffic_stats = crate::traffic::snapshot();
     traffic_stats.print_summary();
     traffic_stats.return_summary()
 }
@@ -187,34 +484,120 @@ async fn traffic_count(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) ->
 async fn traffic_count_attach_device(
     Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
     Json(request): Json<TrafficCountDeviceRequest>,
-) -> impl IntoResponse {
-    info!(
-        "traffic_count_attach_device 处理请求: iface={}, action={:?}",
-        request.iface, request.action
-    );
+) -> Result<String, ApiError> {
+    info!(iface = %request.iface, action = ?request.action, "traffic_count_attach_device 处理请求");
+
+    if crate::startup_attach::is_pattern(&request.iface) {
+        return attach_pattern(&ebpf_manager, request, AttachPrograms::Tc).await;
+    }
+    let (iface, action, local) = (request.iface.clone(), request.action, request.netns.is_none());
+    let directions = request.direction.attach_types();
+    let result = attach_traffic(&ebpf_manager, request).await;
+    if result.is_ok() && local {
+        for attach_type in directions {
+            user_detached(&iface, crate::tc_attach::direction(attach_type), action).await;
+        }
+    }
+    result
+}
 
+// 在单个设备上挂载/卸载流量统计程序
+async fn attach_traffic(
+    ebpf_manager: &Arc<EbpfManager>,
+    request: TrafficCountDeviceRequest,
+) -> Result<String, ApiError> {
     match request.action {
         Action::Add => {
-            // 查询linux系统中是否存在该设备
-            if !std::path::Path::new(&format!("/sys/class/net/{}", request.iface)).exists() {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    format!("Interface {} does not exist", request.iface),
-                );
-            }
-            // 获取对应的device_id, cat /sys/class/net/eth0/ifindex
-            let device_id =
-                std::fs::read_to_string(&format!("/sys/class/net/{}/ifindex", request.iface))
-                    .unwrap()
-                    .trim()
-                    .parse::<u32>()
-                    .unwrap();
-
-            // 保存设备映射到内存
+            let (label, netns) = device_label(&request.iface, request.netns.as_deref())
+                .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            // 只挂载尚未挂载的方向
+            let pending: Vec<TcAttachType> = {
+                let links = TC_LINK_ID.lock().await;
+                request.direction.attach_types()
+                    .into_iter()
+                    .filter(|attach_type| !links.contains_key(&key_from_iface(&label, *attach_type)))
+                    .collect()
+            };
+            if pending.is_empty() {
+                return Err(ApiError::Conflict(format!("设备 {} 已挂载流量统计程序", label)));
+            }
+            let device_id = match &request.netns {
+                // 其他命名空间中的设备在该命名空间内查询 ifindex
+                Some(path) => netns_ifindex(path, &request.iface)
+                    .map_err(|e| ApiError::BadRequest(e.to_string()))?,
+                None => {
+                    // 查询linux系统中是否存在该设备
+                    if !std::path::Path::new(&format!("/sys/class/net/{}", request.iface)).exists() {
+                        return Err(ApiError::BadRequest(format!(
+                            "Interface {} does not exist",
+                            request.iface
+                        )));
+                    }
+                    // 获取对应的device_id, cat /sys/class/net/eth0/ifindex
+                    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", request.iface))?
+                        .trim()
+                        .parse::<u32>()?
+                }
+            };
+            if request.netns.is_some() && !crate::netns::cookie_supported() {
+                warn!("内核不支持在 tc 程序中获取 netns cookie，{} 的统计不区分命名空间", label);
+            }
+
+            // tun、wireguard 等三层设备没有以太网头
+            let l3 = match &request.netns {
+                Some(path) => crate::netns::run_in(path, || crate::l3::is_l3(&request.iface)),
+                None => crate::l3::is_l3(&request.iface),
+            };
+            let l3 = l3.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+            // 获取 eBPF 实例的可变访问
+            let mut ebpf = ebpf_manager.ebpf.lock().await;
+            crate::l3::set(&mut ebpf, device_id, l3)?;
+
+            let mut attached = Vec::new();
+            for &attach_type in &pending {
+                let tc: &mut Tc = program_mut(&mut ebpf, tc_program(attach_type))?;
+                // 其他命名空间中的设备需在该命名空间内通过 netlink 挂载
+                let result = match &request.netns {
+                    Some(path) => crate::netns::run_in(path, || {
+                        crate::tc_attach::attach(tc, &request.iface, attach_type, &request.tc)
+                    }),
+                    None => crate::tc_attach::attach(tc, &request.iface, attach_type, &request.tc),
+                };
+                let error = match result {
+                    Ok(Ok(link_id)) => {
+                        attached.push((attach_type, link_id));
+                        continue;
+                    }
+                    Ok(Err(msg)) => ApiError::Conflict(msg),
+                    Err(e) => ApiError::Internal(format!("Failed to attach {} {:?}: {}", label, attach_type, e)),
+                };
+                // 只挂载了部分方向时卸载本次已挂载的方向
+                for (attach_type, link_id) in attached {
+                    let tc: &mut Tc = program_mut(&mut ebpf, tc_program(attach_type))?;
+                    let result = match &request.netns {
+                        Some(path) => crate::netns::run_in(path, || Ok(tc.detach(link_id)?)),
+                        None => tc.detach(link_id).map_err(anyhow::Error::from),
+                    };
+                    if let Err(e) = result {
+                        warn!("卸载设备 {} 失败: {}", label, e);
+                    }
+                }
+                return Err(error);
+            }
+            {
+                let mut links = TC_LINK_ID.lock().await;
+                for (attach_type, link_id) in attached {
+                    links.insert(key_from_iface(&label, attach_type), link_id);
+                }
+            }
+            drop(ebpf);
+
+            // 挂载成功后才保存设备映射，失败时不留下未挂载设备的映射
             DEVICE_MAPPINGS
                 .lock()
                 .await
-                .insert(request.iface.clone(), device_id);
+                .insert(label.clone(), DeviceMapping { device_id, netns });
 
             // 设置设备映射到eBPF
             if let Err(e) = ebpf_manager
@@ -224,98 +607,1904 @@ async fn traffic_count_attach_device(
                 info!("设置设备映射失败: {}", e);
             }
 
-            // 获取 eBPF 实例的可变访问
+            info!(iface = %label, device_id, action = "attach", direction = ?pending, "设备 {} 已挂载，设备ID: {}", label, device_id);
+            crate::events::attachment("traffic", &label, "attach");
+            match pending.as_slice() {
+                [attach_type] => Ok(format!("设备 {} {:?} 方向挂载成功，设备ID: {}", label, attach_type, device_id)),
+                _ => Ok(format!("设备 {} 挂载成功，设备ID: {}", label, device_id)),
+            }
+        }
+        Action::Remove => {
+            let (label, _) = device_label(&request.iface, request.netns.as_deref())
+                .map_err(|e| ApiError::BadRequest(e.to_string()))?;
             let mut ebpf = ebpf_manager.ebpf.lock().await;
-            let tc: &mut Tc = ebpf.program_mut("xnet_tc").unwrap().try_into().unwrap();
-
-            // 挂载到 ingress
-            let link_id = tc.attach(&request.iface, TcAttachType::Ingress).unwrap();
-            TC_LINK_ID.lock().await.insert(
-                key_from_iface(&request.iface, TcAttachType::Ingress),
-                link_id,
-            );
-
-            // 挂载到 egress
-            let link_id = tc.attach(&request.iface, TcAttachType::Egress).unwrap();
-            TC_LINK_ID.lock().await.insert(
-                key_from_iface(&request.iface, TcAttachType::Egress),
-                link_id,
-            );
-
-            // 释放ebpf锁后再设置设备上下文
-            drop(ebpf);
 
-            // 设置 ingress 设备上下文
-            if let Err(e) = ebpf_manager.set_device_context(device_id, true).await {
-                info!("设置 ingress 设备上下文失败: {}", e);
+            let mut removed = false;
+            for attach_type in request.direction.attach_types() {
+                let Some(link_id) = TC_LINK_ID
+                    .lock()
+                    .await
+                    .remove(&key_from_iface(&label, attach_type))
+                else {
+                    continue;
+                };
+                removed = true;
+                let tc: &mut Tc = program_mut(&mut ebpf, tc_program(attach_type))?;
+                // netlink 请求需在设备所在命名空间内发出
+                let result = match &request.netns {
+                    Some(path) => crate::netns::run_in(path, || Ok(tc.detach(link_id)?)),
+                    None => tc.detach(link_id).map_err(anyhow::Error::from),
+                };
+                // 设备已被删除时内核已移除挂载，只记录日志
+                if let Err(e) = result {
+                    warn!("卸载设备 {} 失败: {}", label, e);
+                }
             }
 
-            // 等待一下，确保ingress上下文设置完成
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-            // 设置 egress 设备上下文
-            if let Err(e) = ebpf_manager.set_device_context(device_id, false).await {
-                info!("设置 egress 设备上下文失败: {}", e);
+            // 两个方向都已卸载时从内存映射中移除设备
+            let remaining = {
+                let links = TC_LINK_ID.lock().await;
+                TcDirection::Both
+                    .attach_types()
+                    .into_iter()
+                    .any(|attach_type| links.contains_key(&key_from_iface(&label, attach_type)))
+            };
+            // 请求的方向都未挂载时不产生卸载事件
+            if !removed {
+                if !remaining {
+                    DEVICE_MAPPINGS.lock().await.remove(&label);
+                }
+                return Err(ApiError::NotFound(format!(
+                    "设备 {} 未挂载 {:?} 方向的流量统计程序",
+                    label, request.direction
+                )));
             }
+            if remaining {
+                info!(iface = %label, action = "detach", direction = ?request.direction, "设备 {} 已卸载 {:?} 方向", label, request.direction);
+                crate::events::attachment("traffic", &label, "detach");
+                return Ok(format!("设备 {} {:?} 方向移除成功", label, request.direction));
+            }
+            DEVICE_MAPPINGS.lock().await.remove(&label);
 
-            info!("设备 {} 已挂载，设备ID: {}", request.iface, device_id);
-            (
-                StatusCode::OK,
-                format!("设备 {} 挂载成功，设备ID: {}", request.iface, device_id),
-            )
+            info!(iface = %label, action = "detach", "设备 {} 已移除", label);
+            crate::events::attachment("traffic", &label, "detach");
+            Ok(format!("设备 {} 移除成功", label))
         }
-        Action::Remove => {
-            let mut ebpf = ebpf_manager.ebpf.lock().await;
-            let tc: &mut Tc = ebpf.program_mut("xnet_tc").unwrap().try_into().unwrap();
+    }
+}
 
-            let ingress_link_id = TC_LINK_ID
-                .lock()
+// 按 --attach 挂载设备，与通过接口挂载相同，任一设备挂载失败时启动失败，
+// 设备模式登记后由 sync_patterns 挂载当前匹配的设备
+async fn attach_devices(ebpf_manager: &Arc<EbpfManager>, specs: &[AttachSpec]) -> Result<(), anyhow::Error> {
+    for spec in specs {
+        if crate::startup_attach::is_pattern(&spec.iface) {
+            let tc = spec.programs.tc().then(TcOptions::default);
+            crate::startup_attach::add_pattern(&spec.iface, tc, spec.programs.xdp()).await;
+            continue;
+        }
+        let request = || TrafficCountDeviceRequest {
+            iface: spec.iface.clone(),
+            action: Action::Add,
+            netns: None,
+            tc: TcOptions::default(),
+            direction: TcDirection::Both,
+        };
+        if spec.programs.tc() {
+            attach_traffic(ebpf_manager, request())
                 .await
-                .remove(&key_from_iface(&request.iface, TcAttachType::Ingress));
-            let egress_link_id = TC_LINK_ID
-                .lock()
+                .map_err(|e| anyhow::anyhow!("failed to attach traffic counting to {}: {}", spec.iface, e))?;
+        }
+        if spec.programs.xdp() {
+            attach_firewall(ebpf_manager, request())
                 .await
-                .remove(&key_from_iface(&request.iface, TcAttachType::Egress));
+                .map_err(|e| anyhow::anyhow!("failed to attach XDP firewall to {}: {}", spec.iface, e))?;
+        }
+    }
+    let errors = sync_patterns(ebpf_manager).await;
+    if !errors.is_empty() {
+        anyhow::bail!("{}", errors.join("; "));
+    }
+    Ok(())
+}
 
-            if let Some(link_id) = ingress_link_id {
-                tc.detach(link_id).unwrap();
+// 通过接口按设备模式挂载/卸载程序：挂载时登记该模式并挂载当前匹配的设备，
+// 卸载时取消登记并卸载当前匹配且已挂载的设备
+async fn attach_pattern(
+    ebpf_manager: &Arc<EbpfManager>,
+    request: TrafficCountDeviceRequest,
+    programs: AttachPrograms,
+) -> Result<String, ApiError> {
+    if request.netns.is_some() || request.direction != TcDirection::Both {
+        return Err(ApiError::BadRequest(
+            "interface patterns are not supported with netns or direction".to_string(),
+        ));
+    }
+    let pattern = request.iface.clone();
+    match request.action {
+        Action::Add => {
+            let tc = programs.tc().then_some(request.tc);
+            crate::startup_attach::add_pattern(&pattern, tc, programs.xdp()).await;
+            let errors = sync_patterns(ebpf_manager).await;
+            if !errors.is_empty() {
+                return Err(ApiError::Internal(errors.join("; ")));
             }
-            if let Some(link_id) = egress_link_id {
-                tc.detach(link_id).unwrap();
+            let matched = crate::startup_attach::expand(&pattern);
+            Ok(format!("设备模式 {} 已登记，当前匹配: {}", pattern, matched.join(" ")))
+        }
+        Action::Remove => {
+            crate::startup_attach::remove_pattern(&pattern, programs.tc(), programs.xdp()).await;
+            let attached = attached_interfaces().await;
+            let mut detached = Vec::new();
+            for iface in crate::startup_attach::expand(&pattern) {
+                let request = TrafficCountDeviceRequest {
+                    iface: iface.clone(),
+                    action: Action::Remove,
+                    netns: None,
+                    tc: TcOptions::default(),
+                    direction: TcDirection::Both,
+                };
+                if programs.tc() && attached.traffic.iter().any(|tc| tc.iface == iface) {
+                    attach_traffic(ebpf_manager, request).await?;
+                    detached.push(iface);
+                } else if programs.xdp() && attached.firewall.iter().any(|xdp| xdp.iface == iface) {
+                    attach_firewall(ebpf_manager, request).await?;
+                    detached.push(iface);
+                }
             }
+            Ok(format!("设备模式 {} 已取消，已卸载: {}", pattern, detached.join(" ")))
+        }
+    }
+}
 
-            // 从内存映射中移除设备
-            DEVICE_MAPPINGS.lock().await.remove(&request.iface);
+// 挂载与已登记的设备模式匹配但尚未挂载的设备，并清理已消失设备的挂载记录，返回挂载失败的原因
+async fn sync_patterns(ebpf_manager: &Arc<EbpfManager>) -> Vec<String> {
+    let patterns = crate::startup_attach::patterns().await;
+    if patterns.is_empty() {
+        return Vec::new();
+    }
+    let present = crate::startup_attach::interfaces();
+    // 设备消失后卸载记录失效，重新出现的设备按模式挂载
+    USER_DETACHED.lock().await.retain(|(iface, _)| present.contains(iface));
+    let detached = USER_DETACHED.lock().await.clone();
+    let mut errors = Vec::new();
+    for (pattern, attach) in &patterns {
+        // 设备被删除时内核已移除挂载，只需清理记录；其他命名空间中的设备名带 @，不在此处理
+        let vanished = |iface: &String| {
+            !iface.contains('@') && crate::startup_attach::matches(pattern, iface) && !present.contains(iface)
+        };
+        let attached = attached_interfaces().await;
+        let mut stale: Vec<(String, bool)> = attached
+            .traffic
+            .iter()
+            .filter(|tc| vanished(&tc.iface))
+            .map(|tc| (tc.iface.clone(), true))
+            .chain(
+                attached
+                    .firewall
+                    .iter()
+                    .filter(|xdp| vanished(&xdp.iface))
+                    .map(|xdp| (xdp.iface.clone(), false)),
+            )
+            .collect();
+        stale.dedup();
+        for (iface, tc) in stale {
+            let request = TrafficCountDeviceRequest {
+                iface,
+                action: Action::Remove,
+                netns: None,
+                tc: TcOptions::default(),
+                direction: TcDirection::Both,
+            };
+            let result = if tc {
+                attach_traffic(ebpf_manager, request).await
+            } else {
+                attach_firewall(ebpf_manager, request).await
+            };
+            if let Err(e) = result {
+                warn!("清理已删除设备的挂载失败: {}", e);
+            }
+        }
 
-            info!("设备 {} 已移除", request.iface);
-            (StatusCode::OK, format!("设备 {} 移除成功", request.iface))
+        for iface in present.iter().filter(|iface| crate::startup_attach::matches(pattern, iface)) {
+            let request = |tc: TcOptions, direction: TcDirection| TrafficCountDeviceRequest {
+                iface: iface.clone(),
+                action: Action::Add,
+                netns: None,
+                tc,
+                direction,
+            };
+            if let Some(tc) = attach.tc {
+                // 只挂载尚未挂载且未被通过接口卸载的方向
+                let missing: Vec<TcAttachType> = {
+                    let links = TC_LINK_ID.lock().await;
+                    TcDirection::Both
+                        .attach_types()
+                        .into_iter()
+                        .filter(|attach_type| {
+                            let point = (iface.clone(), crate::tc_attach::direction(*attach_type));
+                            !links.contains_key(&key_from_iface(iface, *attach_type)) && !detached.contains(&point)
+                        })
+                        .collect()
+                };
+                let direction = match missing.as_slice() {
+                    [] => None,
+                    [TcAttachType::Ingress] => Some(TcDirection::Ingress),
+                    [TcAttachType::Egress] => Some(TcDirection::Egress),
+                    _ => Some(TcDirection::Both),
+                };
+                if let Some(direction) = direction {
+                    if let Err(e) = attach_traffic(ebpf_manager, request(tc, direction)).await {
+                        errors.push(format!("failed to attach traffic counting to {} ({}): {}", iface, pattern, e));
+                    }
+                }
+            }
+            // 三层设备不支持 XDP 防火墙，不作为错误
+            if attach.xdp
+                && !XDP_LINK_ID.lock().await.contains_key(iface)
+                && !detached.contains(&(iface.clone(), "xdp"))
+                && !crate::l3::is_l3(iface).unwrap_or(false)
+            {
+                let request = request(TcOptions::default(), TcDirection::Both);
+                if let Err(e) = attach_firewall(ebpf_manager, request).await {
+                    errors.push(format!("failed to attach XDP firewall to {} ({}): {}", iface, pattern, e));
+                }
+            }
         }
     }
+    errors
 }
 
-pub async fn serve(ebpf: aya::Ebpf) -> Result<(), anyhow::Error> {
-    // 创建 eBPF 管理器
-    let ebpf_manager = Arc::new(EbpfManager::new(ebpf));
+// 定期重新扫描 /sys/class/net，挂载新出现的匹配设备，
+// 同一错误只在首次出现时记录，设备恢复或消失后重新计数
+fn start_pattern_watch(ebpf_manager: Arc<EbpfManager>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut logged: HashSet<String> = HashSet::new();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let errors = sync_patterns(&ebpf_manager).await;
+            for e in &errors {
+                if logged.insert(e.clone()) {
+                    warn!("{}", e);
+                }
+            }
+            logged.retain(|e| errors.contains(e));
+        }
+    });
+}
 
-    // 加载 eBPF 程序
-    ebpf_manager.load_programs().await?;
+// 查询对端链路质量
+async fn peer_quality(Path(name): Path<String>) -> Response {
+    let states = crate::peer::PEER_STATES.lock().await;
+    match states.get(&name) {
+        Some(state) => (StatusCode::OK, Json(state.quality(&name))).into_response(),
+        None => ApiError::NotFound(format!("peer {} not found", name)).into_response(),
+    }
+}
 
-    #[rustfmt::skip]
-    let router = Router::new()
-        .route("/", axum::routing::get(|| async {"ok"}))
-        .route("/traffic_count", axum::routing::get(traffic_count))
-        .route("/traffic_count_attach_device", axum::routing::post(traffic_count_attach_device))
-        .route("/traffic_device_state", axum::routing::get(traffic_device_state))
-        .route("/traffic_device_connection_stats", axum::routing::get(traffic_device_connection_stats))
-        .route("/traffic_device_connection_stats/:device_id", axum::routing::get(traffic_device_connection_stats_by_id))
-        .layer(Extension(ebpf_manager))
-    ;
+// 有界抓包，返回pcap内容或保存到指定文件
+async fn capture(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<CaptureRequest>,
+) -> Response {
+    let output = match request.output.as_deref().map(crate::capture::output_path) {
+        Some(result) => match result.await {
+            Ok(path) => Some(path),
+            Err(msg) => return ApiError::BadRequest(msg).into_response(),
+        },
+        None => None,
+    };
+    let (pcap, packets) = match crate::capture::run_capture(&ebpf_manager, &request).await {
+        Ok(result) => result,
+        Err(CaptureError::Busy) => {
+            return ApiError::Conflict("A capture is already running".to_string()).into_response()
+        }
+        Err(CaptureError::InvalidRequest(msg)) => {
+            return ApiError::BadRequest(msg).into_response()
+        }
+        Err(CaptureError::Failed(e)) => {
+            return ApiError::Internal(format!("Capture failed: {}", e)).into_response()
+        }
+    };
+
+    match output {
+        Some(path) => match std::fs::write(&path, &pcap) {
+            Ok(()) => {
+                let path = path.display().to_string();
+                (StatusCode::OK, Json(CaptureSaved { path, packets })).into_response()
+            }
+            Err(e) => ApiError::Internal(format!("Failed to write {}: {}", path.display(), e))
+                .into_response(),
+        },
+        None => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "application/vnd.tcpdump.pcap"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"xnet.pcap\""),
+            ],
+            pcap,
+        )
+            .into_response(),
+    }
+}
+
+// 开始 AF_XDP 抓包，设备需已挂载XDP程序
+async fn afxdp_capture_start(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<AfXdpRequest>,
+) -> Response {
+    if !XDP_LINK_ID.lock().await.contains_key(&request.iface) {
+        return ApiError::BadRequest(format!("设备 {} 未挂载XDP程序", request.iface)).into_response();
+    }
+    match crate::afxdp::start(ebpf_manager, request).await {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(CaptureError::Busy) => {
+            ApiError::Conflict("An AF_XDP capture is already running".to_string()).into_response()
+        }
+        Err(CaptureError::InvalidRequest(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(CaptureError::Failed(e)) => {
+            ApiError::Internal(format!("AF_XDP capture failed: {}", e)).into_response()
+        }
+    }
+}
+
+// 停止 AF_XDP 抓包
+async fn afxdp_capture_stop() -> Response {
+    match crate::afxdp::stop().await {
+        Some(status) => (StatusCode::OK, Json(status)).into_response(),
+        None => ApiError::NotFound("No AF_XDP capture".to_string()).into_response(),
+    }
+}
+
+// 查询 AF_XDP 抓包状态
+async fn afxdp_capture_status() -> Response {
+    match crate::afxdp::status().await {
+        Some(status) => (StatusCode::OK, Json(status)).into_response(),
+        None => ApiError::NotFound("No AF_XDP capture".to_string()).into_response(),
+    }
+}
+
+// 挂载/卸载 XDP 防火墙程序
+async fn firewall_attach_device(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<TrafficCountDeviceRequest>,
+) -> Result<String, ApiError> {
+    info!(iface = %request.iface, action = ?request.action, "firewall_attach_device 处理请求");
+
+    if request.direction != TcDirection::Both {
+        return Err(ApiError::BadRequest("direction only applies to traffic counting".to_string()));
+    }
+
+    if crate::startup_attach::is_pattern(&request.iface) {
+        return attach_pattern(&ebpf_manager, request, AttachPrograms::Xdp).await;
+    }
+    let (iface, action) = (request.iface.clone(), request.action);
+    let result = attach_firewall(&ebpf_manager, request).await;
+    if result.is_ok() {
+        user_detached(&iface, "xdp", action).await;
+    }
+    result
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+// 在单个设备上挂载/卸载 XDP 防火墙程序
+async fn attach_firewall(
+    ebpf_manager: &Arc<EbpfManager>,
+    request: TrafficCountDeviceRequest,
+) -> Result<String, ApiError> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let xdp: &mut Xdp = program_mut(&mut ebpf, "xnet_xdp")?;
 
-    info!("HTTP 服务器启动在 http://0.0.0.0:8080");
+    match request.action {
+        Action::Add => {
+            if !std::path::Path::new(&format!("/sys/class/net/{}", request.iface)).exists() {
+                return Err(ApiError::BadRequest(format!(
+                    "Interface {} does not exist",
+                    request.iface
+                )));
+            }
+            if XDP_LINK_ID.lock().await.contains_key(&request.iface) {
+                return Err(ApiError::Conflict(format!(
+                    "设备 {} 已挂载XDP程序",
+                    request.iface
+                )));
+            }
+            // XDP 程序按以太网头解析，三层设备只支持 tc 流量统计
+            if crate::l3::is_l3(&request.iface).unwrap_or(false) {
+                return Err(ApiError::BadRequest(format!(
+                    "设备 {} 没有以太网头，不支持XDP防火墙",
+                    request.iface
+                )));
+            }
+
+            match crate::xdp_attach::attach(xdp, &request.iface) {
+                Ok((link_id, mode)) => {
+                    XDP_LINK_ID.lock().await.insert(request.iface.clone(), (link_id, mode));
+                    info!(iface = %request.iface, action = "attach", mode = ?mode, "设备 {} 已挂载XDP程序", request.iface);
+                    crate::events::attachment("firewall", &request.iface, "attach");
+                    Ok(format!("设备 {} XDP挂载成功({:?})", request.iface, mode))
+                }
+                Err(e) => Err(ApiError::Internal(format!(
+                    "设备 {} XDP挂载失败: {}",
+                    request.iface, e
+                ))),
+            }
+        }
+        Action::Remove => {
+            if let Some((link_id, _)) = XDP_LINK_ID.lock().await.remove(&request.iface) {
+                // 设备已被删除时内核已移除挂载，只记录日志
+                if let Err(e) = xdp.detach(link_id) {
+                    warn!("卸载设备 {} 失败: {}", request.iface, e);
+                }
+            }
+            info!(iface = %request.iface, action = "detach", "设备 {} 已卸载XDP程序", request.iface);
+            crate::events::attachment("firewall", &request.iface, "detach");
+            Ok(format!("设备 {} XDP移除成功", request.iface))
+        }
+    }
+}
+
+// 挂载/卸载负载均衡 XDP 程序，与防火墙程序不能挂载到同一设备
+async fn lb_attach_device(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<TrafficCountDeviceRequest>,
+) -> Result<String, ApiError> {
+    info!(iface = %request.iface, action = ?request.action, "lb_attach_device 处理请求");
+
+    if request.direction != TcDirection::Both {
+        return Err(ApiError::BadRequest("direction only applies to traffic counting".to_string()));
+    }
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let xdp: &mut Xdp = program_mut(&mut ebpf, "xnet_lb")?;
+
+    match request.action {
+        Action::Add => {
+            if !std::path::Path::new(&format!("/sys/class/net/{}", request.iface)).exists() {
+                return Err(ApiError::BadRequest(format!(
+                    "Interface {} does not exist",
+                    request.iface
+                )));
+            }
+            if LB_LINK_ID.lock().await.contains_key(&request.iface) {
+                return Err(ApiError::Conflict(format!(
+                    "设备 {} 已挂载负载均衡程序",
+                    request.iface
+                )));
+            }
+
+            match crate::xdp_attach::attach(xdp, &request.iface) {
+                Ok((link_id, mode)) => {
+                    LB_LINK_ID.lock().await.insert(request.iface.clone(), (link_id, mode));
+                    info!(iface = %request.iface, action = "attach", mode = ?mode, "设备 {} 已挂载负载均衡程序", request.iface);
+                    crate::events::attachment("lb", &request.iface, "attach");
+                    Ok(format!("设备 {} 负载均衡挂载成功({:?})", request.iface, mode))
+                }
+                Err(e) => Err(ApiError::Internal(format!(
+                    "设备 {} 负载均衡挂载失败: {}",
+                    request.iface, e
+                ))),
+            }
+        }
+        Action::Remove => {
+            if let Some((link_id, _)) = LB_LINK_ID.lock().await.remove(&request.iface) {
+                // 设备已被删除时内核已移除挂载，只记录日志
+                if let Err(e) = xdp.detach(link_id) {
+                    warn!("卸载设备 {} 失败: {}", request.iface, e);
+                }
+            }
+            info!(iface = %request.iface, action = "detach", "设备 {} 已卸载负载均衡程序", request.iface);
+            crate::events::attachment("lb", &request.iface, "detach");
+            Ok(format!("设备 {} 负载均衡移除成功", request.iface))
+        }
+    }
+}
+
+// 查询负载均衡服务及各后端统计
+async fn lb_services(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::lb::list(&ebpf_manager).await {
+        Ok(services) => (StatusCode::OK, Json(services)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增或替换负载均衡服务
+async fn add_lb_service(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(service): Json<LbServiceConfig>,
+) -> Response {
+    match crate::lb::upsert(&ebpf_manager, service).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除负载均衡服务
+async fn remove_lb_service(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::lb::remove(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("service {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("service {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询NAT规则
+async fn nat_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::nat::list(&ebpf_manager).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
 
-    axum::serve(listener, router).await?;
+// 新增或替换NAT规则，规则在挂载了XDP防火墙的设备上生效
+async fn add_nat_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(rule): Json<NatRule>,
+) -> Response {
+    match crate::nat::upsert(&ebpf_manager, rule).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除NAT规则
+async fn remove_nat_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::nat::remove(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("nat rule {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("nat rule {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 按匹配顺序列出防火墙规则及命中的包数、字节数和最近命中时间
+async fn firewall_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::firewall::list(&ebpf_manager).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+async fn firewall_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::firewall::get(&ebpf_manager, id).await {
+        Ok(Some(rule)) => (StatusCode::OK, Json(rule)).into_response(),
+        Ok(None) => ApiError::NotFound(format!("firewall rule {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct FirewallRuleRequest {
+    #[serde(flatten)]
+    rule: FirewallRule,
+    // 指定后以灰度方式发布，超过影响阈值时自动回滚
+    canary: Option<CanaryConfig>,
+}
+
+// 灰度发布时在规则生效前读取影响指标基线
+async fn canary_baseline(
+    ebpf_manager: &EbpfManager,
+    canary: Option<CanaryConfig>,
+) -> Result<Option<(CanaryConfig, ImpactCounters)>, ApiError> {
+    let Some(config) = canary else {
+        return Ok(None);
+    };
+    match crate::canary::impact_snapshot(ebpf_manager, None).await {
+        Ok(baseline) => Ok(Some((config, baseline))),
+        Err(e) => Err(ApiError::Internal(format!("读取影响指标失败: {}", e))),
+    }
+}
+
+// 新增防火墙规则，入方向规则在挂载了XDP防火墙的设备上生效，出方向规则在挂载了流量统计的设备上生效
+async fn add_firewall_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<FirewallRuleRequest>,
+) -> Response {
+    let canary = match canary_baseline(&ebpf_manager, request.canary).await {
+        Ok(canary) => canary,
+        Err(e) => return e.into_response(),
+    };
+    match crate::firewall::create(&ebpf_manager, request.rule.clone()).await {
+        Ok(Ok(id)) => {
+            if let Some((config, baseline)) = canary {
+                let rollback = Rollback::firewall_rule(id, request.rule, None);
+                let description = format!("firewall rule {} created", id);
+                crate::canary::start(ebpf_manager.clone(), description, config, baseline, rollback).await;
+            }
+            (StatusCode::OK, Json(IdResponse { id })).into_response()
+        }
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 替换防火墙规则
+async fn update_firewall_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+    Json(request): Json<FirewallRuleRequest>,
+) -> Response {
+    let canary = match canary_baseline(&ebpf_manager, request.canary).await {
+        Ok(canary) => canary,
+        Err(e) => return e.into_response(),
+    };
+    match crate::firewall::replace(&ebpf_manager, id, request.rule.clone()).await {
+        Ok(Ok(Some(previous))) => {
+            if let Some((config, baseline)) = canary {
+                let rollback = Rollback::firewall_rule(id, request.rule, Some(previous));
+                let description = format!("firewall rule {} updated", id);
+                crate::canary::start(ebpf_manager.clone(), description, config, baseline, rollback).await;
+            }
+            (StatusCode::OK, Json(IdResponse { id })).into_response()
+        }
+        Ok(Ok(None)) => ApiError::NotFound(format!("firewall rule {} not found", id)).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 导入 nft -j list ruleset 输出中的地址、协议和端口匹配及 accept/drop 规则，不支持的规则在 skipped 中列出
+async fn import_nftables(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Query(query): Query<NftImportQuery>,
+    Json(ruleset): Json<serde_json::Value>,
+) -> Response {
+    match crate::nft::import(&ebpf_manager, &ruleset, &query).await {
+        Ok(Ok(result)) => (StatusCode::OK, Json(result)).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除防火墙规则
+async fn remove_firewall_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::firewall::remove(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("firewall rule {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("firewall rule {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 列出信任名单及放行的包数、字节数和最近放行时间
+async fn firewall_allowlist(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::firewall::allowlist(&ebpf_manager).await {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增信任名单条目，在挂载了XDP防火墙或流量统计的设备上立即生效
+async fn add_firewall_allowlist(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(entry): Json<AllowlistEntry>,
+) -> Response {
+    match crate::firewall::add_allowlist(&ebpf_manager, entry).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除信任名单条目
+async fn remove_firewall_allowlist(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::firewall::remove_allowlist(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("allowlist entry {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("allowlist entry {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 列出开启采样的设备及采样率
+async fn sampling() -> Response {
+    (StatusCode::OK, Json(crate::sampling::list().await)).into_response()
+}
+
+// 设置设备的采样率，rate 为 1 时关闭采样
+async fn set_sampling(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(config): Json<SamplingConfig>,
+) -> Response {
+    match crate::sampling::set(&ebpf_manager, config).await {
+        Ok(Ok(())) => sampling().await,
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 列出开启 RA guard 的设备及丢弃的 RA 数
+async fn firewall_ra_guard(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::firewall::ra_guard_list(&ebpf_manager).await {
+        Ok(ifaces) => (StatusCode::OK, Json(ifaces)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 开启或关闭设备的 RA guard，开启需要设备已挂载XDP防火墙
+async fn set_firewall_ra_guard(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<RaGuardRequest>,
+) -> Response {
+    if request.enabled && !XDP_LINK_ID.lock().await.contains_key(&request.iface) {
+        return ApiError::BadRequest(format!("设备 {} 未挂载XDP防火墙", request.iface)).into_response();
+    }
+    match crate::firewall::set_ra_guard(&ebpf_manager, request).await {
+        Ok(Ok(())) => firewall_ra_guard(Extension(ebpf_manager)).await,
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 按国家拒绝入站流量的规则
+async fn firewall_geo_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    let maps = ebpf_manager.maps();
+    match crate::geo::list(&maps).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增按国家拒绝入站流量的规则，需要启动时设置 --geoip-dir
+async fn add_firewall_geo_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(rule): Json<GeoRuleConfig>,
+) -> Response {
+    match crate::geo::add(&ebpf_manager, rule).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+async fn remove_firewall_geo_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::geo::remove(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("geo rule {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("geo rule {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 非法 TCP 标志组合的处理方式及计数
+async fn firewall_tcp_scan(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    let maps = ebpf_manager.maps();
+    match crate::firewall::tcp_scan_status(&maps) {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 设置 NULL、FIN、XMAS 扫描包只计数还是丢弃
+async fn set_firewall_tcp_scan(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<TcpScanRequest>,
+) -> Response {
+    match crate::firewall::set_tcp_scan(&ebpf_manager, request).await {
+        Ok(()) => firewall_tcp_scan(Extension(ebpf_manager)).await,
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 疑似通过 DNS 外传数据的源地址
+async fn dns_exfil() -> impl IntoResponse {
+    (StatusCode::OK, Json(crate::dns::report().await))
+}
+
+// 发出非法 TCP 标志组合的源地址
+async fn tcp_scans(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    let maps = ebpf_manager.maps();
+    match crate::firewall::tcp_scan_sources(&maps) {
+        Ok(sources) => (StatusCode::OK, Json(sources)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 列出设备的默认拒绝状态及因此丢弃的包数
+async fn firewall_default_deny(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::firewall::default_deny_list(&ebpf_manager).await {
+        Ok(states) => (StatusCode::OK, Json(states)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 开启或关闭设备的默认拒绝，开启需要设备已挂载XDP防火墙
+async fn set_firewall_default_deny(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<DefaultDenyRequest>,
+) -> Response {
+    if request.enabled && !XDP_LINK_ID.lock().await.contains_key(&request.iface) {
+        return ApiError::BadRequest(format!("设备 {} 未挂载XDP防火墙", request.iface)).into_response();
+    }
+    match crate::firewall::set_default_deny(ebpf_manager, request).await {
+        Ok(Ok(state)) => (StatusCode::OK, Json(state)).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 确认默认拒绝的修改，取消超时恢复
+async fn confirm_firewall_default_deny(Path(iface): Path<String>) -> Response {
+    if crate::firewall::confirm_default_deny(&iface).await {
+        (StatusCode::OK, format!("default deny on {} confirmed", iface)).into_response()
+    } else {
+        ApiError::NotFound(format!("no pending default deny change on {}", iface)).into_response()
+    }
+}
+
+// 查询 sock_ops 记录的 TCP socket 指标，并关联头部解析得到的连接统计
+async fn sockets(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Query(query): Query<SocketQuery>,
+) -> Response {
+    if !crate::sockops::enabled() {
+        return ApiError::NotFound("socket accounting is not enabled, start with --sock-ops-cgroup".to_string())
+            .into_response();
+    }
+    let traffic_stats = crate::traffic::snapshot();
+    let maps = ebpf_manager.maps();
+    match crate::sockops::list(&maps, &traffic_stats, &query) {
+        Ok(sockets) => (StatusCode::OK, Json(sockets)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 按 socket cookie 关联的进程、sock_ops 指标和连接统计
+async fn socket_flows(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Query(query): Query<SocketFlowQuery>,
+) -> Response {
+    if !crate::socket_owner::enabled() {
+        return ApiError::NotFound("socket owner tracking is not enabled, start with --socket-owners".to_string())
+            .into_response();
+    }
+    let traffic_stats = crate::traffic::snapshot();
+    let maps = ebpf_manager.maps();
+    match crate::socket_owner::list(&maps, &traffic_stats, &query) {
+        Ok(sockets) => (StatusCode::OK, Json(sockets)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询出方向连接策略及拒绝的连接数
+async fn egress_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::egress::list(&ebpf_manager).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增或替换出方向连接策略
+async fn add_egress_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(rule): Json<EgressRule>,
+) -> Response {
+    match crate::egress::upsert(&ebpf_manager, rule).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除出方向连接策略
+async fn remove_egress_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::egress::remove(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("egress rule {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("egress rule {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询最近被出方向连接策略拒绝的连接
+async fn egress_denials() -> impl IntoResponse {
+    (StatusCode::OK, Json(crate::egress::denials().await))
+}
+
+// 查询 uprobe 统计的 TLS 明文字节数，按进程和连接汇总
+async fn tls_stats(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Query(query): Query<TlsStatsQuery>,
+) -> Response {
+    if !crate::ssl::enabled() {
+        return ApiError::NotFound("tls accounting is not enabled, start with --ssl-lib".to_string())
+            .into_response();
+    }
+    let maps = ebpf_manager.maps();
+    match crate::ssl::list(&maps, &query) {
+        Ok(stats) => (StatusCode::OK, Json(stats)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询 inet_sock_set_state 跟踪点记录的 TCP socket 状态和状态变化统计
+async fn tcp_states(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Query(query): Query<TcpStateQuery>,
+) -> Response {
+    if !crate::tcpstate::enabled() {
+        return ApiError::NotFound("tcp state tracepoint is not attached".to_string())
+            .into_response();
+    }
+    let maps = ebpf_manager.maps();
+    match crate::tcpstate::list(&maps, &query) {
+        Ok(states) => (StatusCode::OK, Json(states)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询DSCP重标记规则及命中的包数
+async fn dscp_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::dscp::list(&ebpf_manager).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增或替换DSCP重标记规则，规则在挂载了TC程序的设备上生效
+async fn add_dscp_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(rule): Json<DscpRule>,
+) -> Response {
+    match crate::dscp::upsert(&ebpf_manager, rule).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除DSCP重标记规则
+async fn remove_dscp_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::dscp::remove(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("dscp rule {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("dscp rule {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询 skb mark 规则及命中的包数
+async fn mark_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::skb_mark::list(&ebpf_manager).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增或替换 skb mark 规则，规则在挂载了流量统计的设备上生效
+async fn add_mark_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(rule): Json<MarkRule>,
+) -> Response {
+    match crate::skb_mark::upsert(&ebpf_manager, rule).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除 skb mark 规则
+async fn remove_mark_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::skb_mark::remove(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("mark rule {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("mark rule {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询 mark 跳过条件及跳过的包数
+async fn mark_skips(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::skb_mark::skip_list(&ebpf_manager).await {
+        Ok(skips) => (StatusCode::OK, Json(skips)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增 mark 跳过条件
+async fn add_mark_skip(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(skip): Json<MarkSkip>,
+) -> Response {
+    match crate::skb_mark::add_skip(&ebpf_manager, skip).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除 mark 跳过条件
+async fn remove_mark_skip(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match crate::skb_mark::remove_skip(&ebpf_manager, id).await {
+        Ok(true) => (StatusCode::OK, format!("mark skip {} removed", id)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("mark skip {} not found", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询二层过滤模式和规则
+async fn mac_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::mac::list(&ebpf_manager).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增或替换源MAC的二层规则，规则在挂载了流量统计的设备上对收到的帧生效
+async fn add_mac_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(rule): Json<MacFilterRule>,
+) -> Response {
+    match crate::mac::upsert(&ebpf_manager, rule).await {
+        Ok(Ok(())) => (StatusCode::OK, "ok".to_string()).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除源MAC的二层规则
+async fn remove_mac_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(mac): Path<String>,
+) -> Response {
+    match crate::mac::remove(&ebpf_manager, &mac).await {
+        Ok(Ok(true)) => (StatusCode::OK, format!("mac rule {} removed", mac)).into_response(),
+        Ok(Ok(false)) => ApiError::NotFound(format!("mac rule {} not found", mac)).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 切换二层过滤的黑名单/白名单模式
+async fn set_mac_mode(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<MacModeRequest>,
+) -> Response {
+    match crate::mac::set_mode(&ebpf_manager, request.mode).await {
+        Ok(()) => (StatusCode::OK, "ok".to_string()).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询出方向整形规则及统计
+async fn shaping_rules(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::shaping::list(&ebpf_manager).await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增或替换设备的出方向整形规则，需要设备的 root qdisc 为 fq
+async fn add_shaping_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(rule): Json<ShapingRuleConfig>,
+) -> Response {
+    let iface = rule.iface.clone();
+    match crate::shaping::upsert(&ebpf_manager, rule).await {
+        Ok(Ok(())) => (StatusCode::OK, Json(IfaceResponse { iface })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除设备的出方向整形规则
+async fn remove_shaping_rule(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(iface): Path<String>,
+) -> Response {
+    match crate::shaping::remove(&ebpf_manager, &iface).await {
+        Ok(true) => (StatusCode::OK, format!("shaping rule {} removed", iface)).into_response(),
+        Ok(false) => {
+            ApiError::NotFound(format!("shaping rule {} not found", iface)).into_response()
+        }
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询设备出方向配额及本周期用量
+async fn quotas(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    let maps = ebpf_manager.maps();
+    match crate::quota::list(&maps).await {
+        Ok(quotas) => (StatusCode::OK, Json(quotas)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 新增或替换设备的出方向配额，设备需已挂载流量统计
+async fn add_quota(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(quota): Json<QuotaConfig>,
+) -> Response {
+    let iface = quota.iface.clone();
+    match crate::quota::upsert(&ebpf_manager, quota).await {
+        Ok(Ok(())) => (StatusCode::OK, Json(IfaceResponse { iface })).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 删除设备的出方向配额
+async fn remove_quota(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(iface): Path<String>,
+) -> Response {
+    match crate::quota::remove(&ebpf_manager, &iface).await {
+        Ok(true) => (StatusCode::OK, format!("quota {} removed", iface)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("quota {} not found", iface)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 导出全部防火墙、NAT和整形规则，格式与 PUT /rules 的请求相同
+async fn rules() -> Response {
+    (StatusCode::OK, Json(crate::rules::export().await)).into_response()
+}
+
+// 整体替换规则，全部校验通过后才修改
+async fn replace_rules(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(set): Json<RuleSet>,
+) -> Response {
+    match crate::rules::replace(&ebpf_manager, set).await {
+        Ok(Ok(())) => (StatusCode::OK, Json(crate::rules::export().await)).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 加载新的 eBPF 目标文件并替换正在运行的程序，统计和规则所在的 map 保持不变
+async fn reload(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    request: Option<Json<ReloadRequest>>,
+) -> Response {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    match crate::reload::reload(&ebpf_manager, request).await {
+        Ok(Ok(result)) => (StatusCode::OK, Json(result)).into_response(),
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询已加载的插件
+async fn plugins() -> impl IntoResponse {
+    (StatusCode::OK, Json(crate::plugin::list().await))
+}
+
+// 加载外部 eBPF 目标文件并挂载其中的程序
+async fn load_plugin(Json(config): Json<PluginConfig>) -> Response {
+    match crate::plugin::load(config).await {
+        Ok(plugin) => (StatusCode::OK, Json(plugin)).into_response(),
+        Err(msg) => ApiError::BadRequest(msg).into_response(),
+    }
+}
+
+// 卸载插件
+async fn unload_plugin(Path(name): Path<String>) -> Response {
+    if crate::plugin::unload(&name).await {
+        (StatusCode::OK, format!("plugin {} unloaded", name)).into_response()
+    } else {
+        ApiError::NotFound(format!("plugin {} not found", name)).into_response()
+    }
+}
+
+// 查询 xnet 自身和所有插件的 map
+async fn maps(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> impl IntoResponse {
+    let mut maps: Vec<serde_json::Value> = {
+        let cache = ebpf_manager.maps();
+        cache
+            .maps()
+            .map(|(name, map)| crate::maps::describe("xnet", name, map))
+            .collect()
+    };
+    maps.extend(crate::plugin::maps().await);
+    (StatusCode::OK, Json(maps))
+}
+
+// 各类程序挂载的网卡，XDP 程序附带挂载模式
+async fn attachments() -> impl IntoResponse {
+    (StatusCode::OK, Json(attached_interfaces().await))
+}
+
+// xnet 版本、已加载的程序、挂载的网卡及内核特性
+async fn info(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> impl IntoResponse {
+    let attached = attached_interfaces().await;
+    (StatusCode::OK, Json(crate::info::info(&ebpf_manager, attached).await))
+}
+
+// Prometheus 格式的 map 使用情况及程序运行统计
+async fn metrics(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> impl IntoResponse {
+    let usage = crate::maps::cached_usage().await;
+    let programs = {
+        let ebpf = ebpf_manager.ebpf.lock().await;
+        crate::profile::programs(&ebpf)
+    };
+    let mut body = crate::maps::prometheus(&usage);
+    body.push_str(&crate::profile::prometheus(&programs));
+    (
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MapDumpQuery {
+    limit: Option<usize>,
+}
+
+// 读出 map 的条目，owner 为 xnet 或插件名
+async fn map_entries(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path((owner, name)): Path<(String, String)>,
+    Query(query): Query<MapDumpQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(1000);
+    let result = if owner == "xnet" {
+        let maps = ebpf_manager.maps();
+        maps.map(&name).map(|map| crate::maps::dump(map, limit))
+    } else {
+        crate::plugin::dump_map(&owner, &name, limit).await
+    };
+    match result {
+        Some(Ok(Ok(entries))) => (StatusCode::OK, Json(entries)).into_response(),
+        Some(Ok(Err(msg))) => ApiError::BadRequest(msg).into_response(),
+        Some(Err(e)) => ApiError::from(e).into_response(),
+        None => ApiError::NotFound(format!("map {}/{} not found", owner, name)).into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct HistoryQuery {
+    device: Option<String>,
+    port: Option<u16>,
+    last_secs: Option<u64>,
+}
+
+// 查询流量历史时间序列
+async fn history(Query(query): Query<HistoryQuery>) -> impl IntoResponse {
+    let history = crate::history::HISTORY.lock().await;
+    let result = history.query(query.device.as_deref(), query.port, query.last_secs);
+    (StatusCode::OK, Json(result))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LatencyQuery {
+    device_id: Option<u32>,
+    port: Option<u16>,
+}
+
+// 查询握手延迟和包间隔的百分位
+async fn latency_percentiles(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Query(query): Query<LatencyQuery>,
+) -> Response {
+    match crate::latency::percentiles(&ebpf_manager, query.device_id, query.port).await {
+        Ok(result) => (StatusCode::OK, Json(result)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询远端IP信誉分
+async fn reputation_by_ip(Path(ip): Path<Ipv4Addr>) -> Response {
+    let reputation = crate::reputation::REPUTATION.lock().await;
+    match reputation.get(&ip) {
+        Some(entry) => (StatusCode::OK, Json(entry.to_json(ip))).into_response(),
+        None => ApiError::NotFound(format!("no observations for {}", ip)).into_response(),
+    }
+}
+
+// TTL 跳变异常事件及仍处于异常状态的源IP
+async fn ttl_anomalies() -> impl IntoResponse {
+    (StatusCode::OK, Json(crate::ttl::anomalies().await))
+}
+
+// 查询单个源IP的TTL分布
+async fn ttl_by_ip(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(ip): Path<Ipv4Addr>,
+) -> Response {
+    match crate::ttl::stats(&ebpf_manager, ip).await {
+        Ok(Some(stats)) => (StatusCode::OK, Json(stats)).into_response(),
+        Ok(None) => ApiError::NotFound(format!("no observations for {}", ip)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// bogon 前缀及命中计数，以及检查 bogon 源地址的设备
+async fn bogon(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::bogon::status(&ebpf_manager).await {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 替换 bogon 前缀，不带 prefixes 时恢复内置前缀
+async fn set_bogon_prefixes(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<BogonPrefixesRequest>,
+) -> Response {
+    match crate::bogon::set_prefixes(&ebpf_manager, request).await {
+        Ok(Ok(())) => bogon(Extension(ebpf_manager)).await,
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 设置设备的 bogon 源地址处理方式: off、count 或 drop
+async fn set_bogon_iface(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<BogonIfaceRequest>,
+) -> Response {
+    match crate::bogon::set_iface(&ebpf_manager, request).await {
+        Ok(Ok(())) => bogon(Extension(ebpf_manager)).await,
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 蜜罐端口以及探测过本机的源地址
+async fn honeypot(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    let maps = ebpf_manager.maps();
+    match crate::honeypot::report(&maps).await {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 添加或更新蜜罐端口，本机正在监听的端口不能作为蜜罐
+async fn set_honeypot_port(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(config): Json<HoneypotPortConfig>,
+) -> Response {
+    match crate::honeypot::set_port(&ebpf_manager, config).await {
+        Ok(Ok(())) => honeypot(Extension(ebpf_manager)).await,
+        Ok(Err(msg)) => ApiError::BadRequest(msg).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+async fn remove_honeypot_port(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Path(port): Path<u16>,
+) -> Response {
+    match crate::honeypot::remove_port(&ebpf_manager, port).await {
+        Ok(true) => (StatusCode::OK, format!("honeypot port {} removed", port)).into_response(),
+        Ok(false) => ApiError::NotFound(format!("honeypot port {} not found", port)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 威胁情报订阅源的同步状态
+async fn blocklist_feeds(Extension(ebpf_manager): Extension<Arc<EbpfManager>>) -> Response {
+    match crate::blocklist::status(&ebpf_manager).await {
+        Ok(feeds) => (StatusCode::OK, Json(feeds)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// 查询信誉策略
+async fn reputation_policy() -> impl IntoResponse {
+    let policy = *crate::reputation::REPUTATION_POLICY.lock().await;
+    (StatusCode::OK, Json(policy))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ReputationPolicyRequest {
+    #[serde(flatten)]
+    policy: ReputationPolicy,
+    // 指定后以灰度方式发布，超过影响阈值时自动回滚
+    canary: Option<CanaryConfig>,
+}
+
+// 设置信誉策略并立即刷新
+async fn set_reputation_policy(
+    Extension(ebpf_manager): Extension<Arc<EbpfManager>>,
+    Json(request): Json<ReputationPolicyRequest>,
+) -> Result<String, ApiError> {
+    info!("设置信誉策略: {:?}", request);
+
+    let baseline = match request.canary {
+        Some(_) => match crate::canary::impact_snapshot(&ebpf_manager, None).await {
+            Ok(baseline) => Some(baseline),
+            Err(e) => return Err(ApiError::Internal(format!("读取影响指标失败: {}", e))),
+        },
+        None => None,
+    };
+
+    let previous = std::mem::replace(
+        &mut *crate::reputation::REPUTATION_POLICY.lock().await,
+        request.policy,
+    );
+    if let Err(e) = crate::reputation::refresh(&ebpf_manager).await {
+        return Err(ApiError::Internal(format!("刷新IP信誉分失败: {}", e)));
+    }
+
+    match (request.canary, baseline) {
+        (Some(config), Some(baseline)) => {
+            let id = crate::canary::start(
+                ebpf_manager.clone(),
+                format!("reputation policy min_score={:?}", request.policy.min_score),
+                config,
+                baseline,
+                Rollback::ReputationPolicy(previous),
+            )
+            .await;
+            Ok(format!("信誉策略已灰度发布, canary id: {}", id))
+        }
+        _ => Ok("信誉策略已更新".to_string()),
+    }
+}
+
+// 查询XDP连接跟踪的状态分布和回收统计
+async fn conntrack() -> impl IntoResponse {
+    let status = crate::conntrack::CONNTRACK.lock().await;
+    (StatusCode::OK, Json(status.to_json()))
+}
+
+// 查询灰度发布状态
+async fn canary_status() -> impl IntoResponse {
+    let canaries = crate::canary::CANARIES.lock().await.clone();
+    (StatusCode::OK, Json(canaries))
+}
+
+// 查询告警规则状态、历史和流量异常
+async fn alerts() -> impl IntoResponse {
+    let alerts = crate::alert::ALERTS.lock().await;
+    let anomalies = crate::anomaly::ANOMALIES.lock().await;
+    (
+        StatusCode::OK,
+        Json(AlertsResponse {
+            rules: alerts.rules.clone(),
+            history: alerts.history.clone(),
+            anomalies: AnomaliesResponse {
+                active: anomalies.active(),
+                history: anomalies.history.clone(),
+            },
+        }),
+    )
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct EventsQuery {
+    // 逗号分隔的事件类型，例如 alert,attachment,rule_change，为空时推送全部
+    types: Option<String>,
+}
+
+// 以 SSE 推送告警、异常、挂载变化、规则变化等事件
+async fn events(
+    Query(query): Query<EventsQuery>,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let types: Option<Vec<String>> = query
+        .types
+        .map(|types| types.split(',').map(|t| t.trim().to_string()).collect());
+    let stream = BroadcastStream::new(crate::events::subscribe()).filter_map(move |event| {
+        // 订阅者落后时跳过丢失的事件
+        let event = event.ok()?;
+        if let Some(types) = &types {
+            if !types.iter().any(|t| t == event.name()) {
+                return None;
+            }
+        }
+        SseEvent::default().json_data(event).ok().map(Ok)
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// 添加告警规则
+async fn add_alert_rule(Json(rule): Json<AlertRule>) -> impl IntoResponse {
+    let id = crate::alert::ALERTS.lock().await.add_rule(rule);
+    crate::events::rule_change("alert", id, "create");
+    (StatusCode::OK, Json(IdResponse { id }))
+}
+
+// 删除告警规则
+async fn remove_alert_rule(Path(id): Path<u64>) -> Result<String, ApiError> {
+    if crate::alert::ALERTS.lock().await.remove_rule(id) {
+        crate::events::rule_change("alert", id, "delete");
+        Ok(format!("告警规则 {} 已删除", id))
+    } else {
+        Err(ApiError::NotFound(format!("告警规则 {} 不存在", id)))
+    }
+}
+
+// 列出端口监视规则
+async fn watches() -> impl IntoResponse {
+    (StatusCode::OK, Json(crate::watch::list().await))
+}
+
+// 添加端口监视规则
+async fn add_watch(Json(rule): Json<WatchRule>) -> Response {
+    match crate::watch::add(rule).await {
+        Ok(id) => (StatusCode::OK, Json(IdResponse { id })).into_response(),
+        Err(msg) => ApiError::BadRequest(msg).into_response(),
+    }
+}
+
+// 删除端口监视规则
+async fn remove_watch(Path(id): Path<u64>) -> Result<String, ApiError> {
+    if crate::watch::remove(id).await {
+        Ok(format!("watch {} removed", id))
+    } else {
+        Err(ApiError::NotFound(format!("watch {} not found", id)))
+    }
+}
+
+// 服务启动选项
+pub struct ServeOptions {
+    // 后台任务刷新统计的间隔
+    pub interval: Duration,
+    // 流量统计快照的刷新间隔
+    pub stats_refresh: Duration,
+    // 流量历史保留时长
+    pub history_retention: Duration,
+    // 设置后将聚合统计持久化到该 SQLite 文件
+    pub sqlite: Option<SqliteConfig>,
+    // 是否通过 OTLP 导出指标
+    pub otlp: bool,
+    pub anomaly: AnomalyConfig,
+    // 修改类接口的访问令牌
+    pub auth: ApiAuth,
+    // 设置后以 HTTPS 提供 API
+    pub tls: Option<TlsOptions>,
+    // 设置后同时在 unix socket 上提供 API
+    pub unix_socket: Option<UnixSocketOptions>,
+    // TCP 监听地址，为空时只监听 unix socket
+    pub listen: Vec<SocketAddr>,
+    pub peer_config: Option<PeerConfig>,
+    // 设置后在该 cgroup 上挂载 sock_ops 程序
+    pub sock_ops_cgroup: Option<PathBuf>,
+    // 设置后在该 libssl 上挂载 uprobe
+    pub ssl_lib: Option<PathBuf>,
+    // 是否挂载 LSM 出方向连接策略程序
+    pub egress_policy: bool,
+    // 是否检查 DNS 查询
+    pub dns_inspect: bool,
+    // 是否挂载 fentry 程序记录 socket 所属进程
+    pub socket_owners: bool,
+    // 设置后从该文件加载防火墙规则，并在规则修改后写回
+    pub firewall_rules_file: Option<PathBuf>,
+    // 设置后从该目录加载防火墙、NAT和整形规则，并在规则修改后写回
+    pub rules_dir: Option<PathBuf>,
+    // 设置后从该文件加载信任名单，并在名单修改后写回
+    pub firewall_allowlist_file: Option<PathBuf>,
+    // 设置后启动时加载该文件中列出的插件
+    pub plugins_file: Option<PathBuf>,
+    // map 使用率告警阈值(百分比)
+    pub map_pressure_percent: u8,
+    // 设置后用该文件中的前缀替换内置的 bogon 前缀
+    pub bogon_prefixes_file: Option<PathBuf>,
+    // 设置后按该文件中的订阅源定期同步 IP 黑名单
+    pub blocklist_feeds_file: Option<PathBuf>,
+    // 设置后可以按国家拒绝入站流量
+    pub geoip_dir: Option<PathBuf>,
+    // 启动时设置的设备采样率
+    pub sampling: Vec<crate::sampling::SamplingConfig>,
+    // 启动时设置的 mark 跳过条件
+    pub skip_marks: Vec<crate::skb_mark::MarkSkip>,
+    // 启动时挂载的设备及程序
+    pub attach: Vec<AttachSpec>,
+    // 抓包文件目录
+    pub capture_dir: PathBuf,
+}
+
+pub async fn serve(mut ebpf: aya::Ebpf, options: ServeOptions) -> Result<(), anyhow::Error> {
+    // 取出抓包 ring buffer，抓包文件写入抓包目录
+    crate::capture::init(&mut ebpf).await?;
+    crate::capture::set_dir(options.capture_dir.clone()).await;
+
+    // 挂载出方向连接策略并取出拒绝事件 ring buffer
+    if options.egress_policy {
+        crate::egress::start(&mut ebpf)?;
+    }
+
+    // 开启 eBPF 程序运行统计
+    crate::profile::enable().await;
+
+    // 创建 eBPF 管理器
+    let ebpf_manager = Arc::new(EbpfManager::new(ebpf)?);
+
+    // 加载 eBPF 程序
+    ebpf_manager.load_programs().await?;
+
+    // 加载持久化的防火墙规则
+    if let Some(path) = &options.firewall_rules_file {
+        crate::firewall::load(&ebpf_manager, path).await?;
+    }
+    if let Some(dir) = &options.rules_dir {
+        crate::rules::load(&ebpf_manager, dir, options.firewall_rules_file.is_none()).await?;
+    }
+    if let Some(path) = &options.firewall_allowlist_file {
+        crate::firewall::load_allowlist(&ebpf_manager, path).await?;
+    }
+
+    // 设置设备采样率
+    crate::sampling::init(&ebpf_manager, &options.sampling).await?;
+
+    // 写入 mark 跳过条件
+    crate::skb_mark::init(&ebpf_manager, &options.skip_marks).await?;
+
+    // 写入 bogon 前缀表
+    crate::bogon::init(&ebpf_manager, options.bogon_prefixes_file.as_deref()).await?;
+
+    // 启动威胁情报订阅源同步任务
+    if let Some(path) = &options.blocklist_feeds_file {
+        crate::blocklist::start(ebpf_manager.clone(), path).await?;
+    }
+
+    // 按国家拒绝入站流量的规则使用的 GeoIP 数据
+    if let Some(dir) = &options.geoip_dir {
+        crate::geo::init(dir).await?;
+    }
+
+    // 加载外部 eBPF 插件
+    if let Some(path) = &options.plugins_file {
+        crate::plugin::load_file(path).await?;
+    }
+
+    // 挂载 sock_ops 程序记录 TCP socket 指标
+    if let Some(cgroup) = &options.sock_ops_cgroup {
+        crate::sockops::attach(&ebpf_manager, cgroup).await?;
+    }
+
+    // 挂载 libssl uprobe 统计 TLS 明文字节数
+    if let Some(lib) = &options.ssl_lib {
+        crate::ssl::attach(&ebpf_manager, lib).await?;
+    }
+
+    // 挂载 fentry 程序记录 socket 所属进程
+    if options.socket_owners {
+        crate::socket_owner::attach(&ebpf_manager).await?;
+    }
+
+    // 开启 DNS 查询检查
+    if options.dns_inspect {
+        crate::dns::start(&ebpf_manager).await?;
+    }
+
+    // 挂载 TCP 状态跟踪点，需要 tracefs，失败时只影响 /tcp_states
+    if let Err(e) = crate::tcpstate::attach(&ebpf_manager).await {
+        warn!("TCP 状态跟踪点挂载失败: {}", e);
+    }
+
+    // 挂载启动时指定的设备，之后定期挂载与设备模式匹配的新设备
+    attach_devices(&ebpf_manager, &options.attach).await?;
+    start_pattern_watch(ebpf_manager.clone(), options.interval);
+
+    // 启动流量统计刷新任务，查询接口和下面的后台任务读取其快照
+    crate::traffic::start(ebpf_manager.clone(), options.stats_refresh);
+
+    // 启动对端模式
+    if let Some(peer_config) = options.peer_config {
+        crate::peer::start(peer_config).await?;
+    }
+
+    // 启动流量历史采样任务
+    crate::history::start(options.interval, options.history_retention).await;
+
+    // 启动SQLite持久化任务
+    if let Some(config) = options.sqlite {
+        crate::sqlite::start(config, options.interval)?;
+    }
+
+    // 启动 OTLP 指标导出
+    if options.otlp {
+        crate::otlp::start(options.interval)?;
+    }
+
+    // 启动流量异常检测任务
+    crate::anomaly::start(options.interval, options.anomaly);
+
+    // 启动告警规则评估任务
+    crate::alert::start(ebpf_manager.clone(), options.interval);
+
+    // 启动端口监视任务，新的入站连接触发 webhook
+    crate::watch::start();
+
+    // 汇总蜜罐端口的访问记录
+    crate::honeypot::start();
+
+    // 检查设备出方向配额的用量
+    crate::quota::start(ebpf_manager.clone());
+
+    // 优先由内核定时器清理过期的连接跟踪条目，不支持时由用户空间清理
+    if let Err(e) = crate::map_gc::start(&ebpf_manager, options.interval).await {
+        info!("内核不支持 bpf_timer 清理连接跟踪，改由用户空间清理: {}", e);
+    }
+
+    // 启动连接跟踪垃圾回收任务
+    crate::conntrack::start(ebpf_manager.clone(), options.interval);
+
+    // 启动 map 使用率统计任务
+    crate::maps::start(ebpf_manager.clone(), options.interval, options.map_pressure_percent);
+
+    // 启动源IP TTL跳变检查任务
+    crate::ttl::start(ebpf_manager.clone(), options.interval);
+
+    // 启动IP信誉分刷新任务
+    crate::reputation::start(ebpf_manager.clone(), options.interval);
+
+    let auth_enabled = options.auth.enabled();
+
+    #[rustfmt::skip]
+    let routes = Router::new()
+        .route("/traffic_count", axum::routing::get(traffic_count))
+        .route("/traffic_count_attach_device", axum::routing::post(traffic_count_attach_device))
+        .route("/traffic_device_state", axum::routing::get(traffic_device_state))
+        .route("/traffic_device_stats", axum::routing::get(traffic_device_stats))
+        .route("/traffic_device_connection_stats", axum::routing::get(traffic_device_connection_stats))
+        .route("/traffic_device_connection_stats/:device_id", axum::routing::get(traffic_device_connection_stats_by_id))
+        .route("/connections", axum::routing::get(traffic_device_connection_stats))
+        .route("/flows", axum::routing::get(traffic_flows))
+        .route("/traffic_fanout", axum::routing::get(traffic_fanout))
+        .route("/traffic_port_stats", axum::routing::get(traffic_port_stats))
+        .route("/traffic_mac_stats", axum::routing::get(traffic_mac_stats))
+        .route("/traffic_protocol_stats", axum::routing::get(traffic_protocol_stats))
+        .route("/traffic_icmpv6_stats", axum::routing::get(traffic_icmpv6_stats))
+        .route("/sampling", axum::routing::get(sampling).post(set_sampling))
+        .route("/peers/:name/quality", axum::routing::get(peer_quality))
+        .route("/capture", axum::routing::post(capture))
+        .route("/capture/afxdp", axum::routing::get(afxdp_capture_status).post(afxdp_capture_start).delete(afxdp_capture_stop))
+        .route("/firewall_attach_device", axum::routing::post(firewall_attach_device))
+        .route("/lb/attach_device", axum::routing::post(lb_attach_device))
+        .route("/lb/services", axum::routing::get(lb_services).post(add_lb_service))
+        .route("/lb/services/:id", axum::routing::delete(remove_lb_service))
+        .route("/firewall/rules", axum::routing::get(firewall_rules).post(add_firewall_rule))
+        .route("/firewall/rules/:id", axum::routing::get(firewall_rule).put(update_firewall_rule).delete(remove_firewall_rule))
+        .route("/firewall/allowlist", axum::routing::get(firewall_allowlist).post(add_firewall_allowlist))
+        .route("/firewall/allowlist/:id", axum::routing::delete(remove_firewall_allowlist))
+        .route("/firewall/ra_guard", axum::routing::get(firewall_ra_guard).post(set_firewall_ra_guard))
+        .route("/firewall/geo", axum::routing::get(firewall_geo_rules).post(add_firewall_geo_rule))
+        .route("/firewall/geo/:id", axum::routing::delete(remove_firewall_geo_rule))
+        .route("/firewall/tcp_scan", axum::routing::get(firewall_tcp_scan).post(set_firewall_tcp_scan))
+        .route("/firewall/import/nftables", axum::routing::post(import_nftables))
+        .route("/firewall/default_deny", axum::routing::get(firewall_default_deny).post(set_firewall_default_deny))
+        .route("/firewall/default_deny/:iface/confirm", axum::routing::post(confirm_firewall_default_deny))
+        .route("/nat/rules", axum::routing::get(nat_rules).post(add_nat_rule))
+        .route("/nat/rules/:id", axum::routing::delete(remove_nat_rule))
+        .route("/sockets", axum::routing::get(sockets))
+        .route("/socket_flows", axum::routing::get(socket_flows))
+        .route("/tcp_states", axum::routing::get(tcp_states))
+        .route("/tls_stats", axum::routing::get(tls_stats))
+        .route("/egress/rules", axum::routing::get(egress_rules).post(add_egress_rule))
+        .route("/egress/rules/:id", axum::routing::delete(remove_egress_rule))
+        .route("/egress/denials", axum::routing::get(egress_denials))
+        .route("/dscp/rules", axum::routing::get(dscp_rules).post(add_dscp_rule))
+        .route("/dscp/rules/:id", axum::routing::delete(remove_dscp_rule))
+        .route("/mark/rules", axum::routing::get(mark_rules).post(add_mark_rule))
+        .route("/mark/rules/:id", axum::routing::delete(remove_mark_rule))
+        .route("/mark/skip", axum::routing::get(mark_skips).post(add_mark_skip))
+        .route("/mark/skip/:id", axum::routing::delete(remove_mark_skip))
+        .route("/mac/rules", axum::routing::get(mac_rules).post(add_mac_rule))
+        .route("/mac/rules/:mac", axum::routing::delete(remove_mac_rule))
+        .route("/mac/mode", axum::routing::post(set_mac_mode))
+        .route("/shaping/rules", axum::routing::get(shaping_rules).post(add_shaping_rule))
+        .route("/shaping/rules/:iface", axum::routing::delete(remove_shaping_rule))
+        .route("/quotas", axum::routing::get(quotas).post(add_quota))
+        .route("/quotas/:iface", axum::routing::delete(remove_quota))
+        .route("/rules", axum::routing::get(rules).put(replace_rules))
+        .route("/history", axum::routing::get(history))
+        .route("/latency/percentiles", axum::routing::get(latency_percentiles))
+        .route("/reputation/policy", axum::routing::get(reputation_policy).post(set_reputation_policy))
+        .route("/reputation/:ip", axum::routing::get(reputation_by_ip))
+        .route("/bogon", axum::routing::get(bogon))
+        .route("/bogon/prefixes", axum::routing::put(set_bogon_prefixes))
+        .route("/bogon/interfaces", axum::routing::post(set_bogon_iface))
+        .route("/blocklist/feeds", axum::routing::get(blocklist_feeds))
+        .route("/security/dns", axum::routing::get(dns_exfil))
+        .route("/security/tcp_scans", axum::routing::get(tcp_scans))
+        .route("/security/honeypot", axum::routing::get(honeypot))
+        .route("/security/honeypot/ports", axum::routing::post(set_honeypot_port))
+        .route("/security/honeypot/ports/:port", axum::routing::delete(remove_honeypot_port))
+        .route("/ttl/anomalies", axum::routing::get(ttl_anomalies))
+        .route("/ttl/:ip", axum::routing::get(ttl_by_ip))
+        .route("/canary", axum::routing::get(canary_status))
+        .route("/conntrack", axum::routing::get(conntrack))
+        .route("/alerts", axum::routing::get(alerts))
+        .route("/events", axum::routing::get(events))
+        .route("/alerts/rules", axum::routing::post(add_alert_rule))
+        .route("/alerts/rules/:id", axum::routing::delete(remove_alert_rule))
+        .route("/watches", axum::routing::get(watches).post(add_watch))
+        .route("/watches/:id", axum::routing::delete(remove_watch))
+        .route("/reload", axum::routing::post(reload))
+        .route("/plugins", axum::routing::get(plugins).post(load_plugin))
+        .route("/plugins/:name", axum::routing::delete(unload_plugin))
+        .route("/maps", axum::routing::get(maps))
+        .route("/maps/:owner/:name", axum::routing::get(map_entries))
+        .route("/attachments", axum::routing::get(attachments))
+        .route("/info", axum::routing::get(info))
+        .route("/metrics", axum::routing::get(metrics));
+
+    #[rustfmt::skip]
+    let router = Router::new()
+        .route("/", axum::routing::get(|| async {"ok"}))
+        .nest(crate::api::PREFIX, routes.clone())
+        // 旧路径保留为弃用的别名，响应头指向 /api/v1 下的新路径
+        .merge(routes.layer(axum::middleware::from_fn(crate::api::deprecated)))
+        .layer(axum::middleware::from_fn_with_state(Arc::new(options.auth), crate::auth::require_token))
+        // 提取器和认证返回的纯文本错误改写为 JSON
+        .layer(axum::middleware::from_fn(crate::api::json_errors))
+        .layer(Extension(ebpf_manager))
+        // 请求 ID 和访问日志，请求内的日志都带上请求 ID
+        .layer(axum::middleware::from_fn(crate::access_log::log_request))
+    ;
+
+    if !auth_enabled {
+        warn!("未配置 API 令牌，修改类接口无需认证");
+    }
+
+    let mut servers = tokio::task::JoinSet::new();
+
+    if let Some(unix_socket) = options.unix_socket {
+        let listener = crate::unix_socket::bind(&unix_socket)?;
+        info!("HTTP 服务器监听 unix socket {}", unix_socket.path.display());
+        let router = router.clone();
+        servers.spawn(async move {
+            crate::unix_socket::serve(listener, router).await;
+            Ok::<(), anyhow::Error>(())
+        });
+    }
+
+    let tls_config = match &options.tls {
+        Some(tls) => Some(crate::tls::rustls_config(tls).await?),
+        None => None,
+    };
+    // 先绑定所有地址，任一地址绑定失败时直接退出
+    for addr in options.listen {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to bind {}: {}", addr, e))?;
+        let router = router.clone();
+        match &tls_config {
+            Some(config) => {
+                info!("HTTPS 服务器启动在 https://{}", addr);
+                let server = axum_server::from_tcp_rustls(listener.into_std()?, config.clone());
+                servers.spawn(async move {
+                    server.serve(router.into_make_service()).await?;
+                    Ok(())
+                });
+            }
+            None => {
+                info!("HTTP 服务器启动在 http://{}", addr);
+                servers.spawn(async move {
+                    axum::serve(listener, router).await?;
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    // 通知 systemd 已就绪并启动看门狗保活
+    crate::systemd::ready(options.interval).await;
+
+    // 任一监听退出时返回
+    if let Some(result) = servers.join_next().await {
+        result??;
+    }
 
     Ok(())
 }
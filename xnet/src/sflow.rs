@@ -0,0 +1,197 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Instant;
+
+use aya::maps::{Array, MapData, RingBuf};
+use aya::Ebpf;
+use tokio::io::unix::AsyncFd;
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+use xnet_common::PacketSample;
+
+// sFlow v5 常量
+const SFLOW_VERSION: u32 = 5;
+const SFLOW_ADDRESS_IPV4: u32 = 1;
+const SFLOW_FLOW_SAMPLE: u32 = 1;
+const SFLOW_RAW_PACKET_HEADER: u32 = 1;
+const SFLOW_HEADER_PROTOCOL_ETHERNET: u32 = 1;
+// 接口未知时使用的 ifindex
+const SFLOW_IFINDEX_UNKNOWN: u32 = 0x3FFF_FFFF;
+// 每个数据报最多携带的采样数，保证数据报不超过常见MTU
+const MAX_SAMPLES_PER_DATAGRAM: usize = 8;
+
+// sFlow 导出器，负责把内核采样的包头编码为 sFlow v5 数据报并发送到采集器
+pub struct SflowExporter {
+    socket: UdpSocket,
+    collector: SocketAddr,
+    agent: Ipv4Addr,
+    sampling_rate: u32,
+    start: Instant,
+    datagram_sequence: u32,
+    sample_sequence: u32,
+    sample_pool: u32,
+}
+
+impl SflowExporter {
+    pub async fn new(
+        collector: SocketAddr,
+        agent: Ipv4Addr,
+        sampling_rate: u32,
+    ) -> Result<Self, anyhow::Error> {
+        let bind_addr = if collector.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        Ok(Self {
+            socket,
+            collector,
+            agent,
+            sampling_rate,
+            start: Instant::now(),
+            datagram_sequence: 0,
+            sample_sequence: 0,
+            sample_pool: 0,
+        })
+    }
+
+    // 编码并发送一批采样
+    pub async fn export(&mut self, samples: &[PacketSample]) -> Result<(), anyhow::Error> {
+        for chunk in samples.chunks(MAX_SAMPLES_PER_DATAGRAM) {
+            let datagram = self.encode_datagram(chunk);
+            self.socket.send_to(&datagram, self.collector).await?;
+        }
+        Ok(())
+    }
+
+    // 编码一个 sFlow v5 数据报 (XDR, 大端序)
+    fn encode_datagram(&mut self, samples: &[PacketSample]) -> Vec<u8> {
+        self.datagram_sequence = self.datagram_sequence.wrapping_add(1);
+
+        let mut buf = Vec::with_capacity(1400);
+        put_u32(&mut buf, SFLOW_VERSION);
+        put_u32(&mut buf, SFLOW_ADDRESS_IPV4);
+        buf.extend_from_slice(&self.agent.octets());
+        put_u32(&mut buf, 0); // sub_agent_id
+        put_u32(&mut buf, self.datagram_sequence);
+        put_u32(&mut buf, self.start.elapsed().as_millis() as u32);
+        put_u32(&mut buf, samples.len() as u32);
+
+        for sample in samples {
+            self.encode_flow_sample(&mut buf, sample);
+        }
+        buf
+    }
+
+    // 编码一个 flow_sample，内含一条 raw packet header 记录
+    fn encode_flow_sample(&mut self, buf: &mut Vec<u8>, sample: &PacketSample) {
+        self.sample_sequence = self.sample_sequence.wrapping_add(1);
+        // 内核不统计未被采样的包，这里用 采样数*N 近似 sample_pool
+        self.sample_pool = self.sample_pool.wrapping_add(self.sampling_rate);
+
+        let header_len = (sample.header_len as usize).min(sample.header.len());
+        let padded_len = (header_len + 3) & !3;
+        let (input, output) = if sample.direction == 0 {
+            (sample.ifindex, SFLOW_IFINDEX_UNKNOWN)
+        } else {
+            (SFLOW_IFINDEX_UNKNOWN, sample.ifindex)
+        };
+
+        // raw packet header: protocol, frame_length, stripped, header_length, header
+        let record_len = 16 + padded_len;
+        // flow_sample 固定字段 8 个u32，加上记录数、记录tag和记录长度
+        let sample_len = 32 + 4 + 8 + record_len;
+
+        put_u32(buf, SFLOW_FLOW_SAMPLE);
+        put_u32(buf, sample_len as u32);
+        put_u32(buf, self.sample_sequence);
+        put_u32(buf, sample.ifindex & 0x00FF_FFFF); // source_id: type 0 + ifindex
+        put_u32(buf, self.sampling_rate);
+        put_u32(buf, self.sample_pool);
+        put_u32(buf, 0); // drops
+        put_u32(buf, input);
+        put_u32(buf, output);
+        put_u32(buf, 1); // flow_records 数量
+
+        put_u32(buf, SFLOW_RAW_PACKET_HEADER);
+        put_u32(buf, record_len as u32);
+        put_u32(buf, SFLOW_HEADER_PROTOCOL_ETHERNET);
+        put_u32(buf, sample.frame_len);
+        put_u32(buf, 0); // stripped
+        put_u32(buf, header_len as u32);
+        buf.extend_from_slice(&sample.header[..header_len]);
+        buf.resize(buf.len() + padded_len - header_len, 0);
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+// 设置内核采样率，并启动后台任务把采样导出到 sFlow 采集器
+pub async fn start(
+    ebpf: &mut Ebpf,
+    collector: SocketAddr,
+    agent: Ipv4Addr,
+    sampling_rate: u32,
+) -> Result<(), anyhow::Error> {
+    if sampling_rate == 0 {
+        warn!("sFlow 采样率为0，不启用采样导出");
+        return Ok(());
+    }
+
+    let ring_buf = RingBuf::try_from(
+        ebpf.take_map("sample_events")
+            .ok_or_else(|| anyhow::anyhow!("sample_events map not found"))?,
+    )?;
+    let mut exporter = SflowExporter::new(collector, agent, sampling_rate).await?;
+
+    // 最后写入采样率，确保导出器就绪后内核才开始推送采样
+    let mut sample_config = Array::<&mut MapData, u32>::try_from(
+        ebpf.map_mut("sample_config")
+            .ok_or_else(|| anyhow::anyhow!("sample_config map not found"))?,
+    )?;
+    sample_config.set(0, sampling_rate, 0)?;
+
+    info!(
+        "sFlow 采样导出已启用: 1-in-{} -> {}",
+        sampling_rate, collector
+    );
+
+    tokio::spawn(async move {
+        let mut ring_buf = match AsyncFd::new(ring_buf) {
+            Ok(fd) => fd,
+            Err(e) => {
+                warn!("sFlow ring buffer 注册失败: {}", e);
+                return;
+            }
+        };
+
+        loop {
+            let mut guard = match ring_buf.readable_mut().await {
+                Ok(guard) => guard,
+                Err(e) => {
+                    warn!("sFlow ring buffer 读取失败: {}", e);
+                    return;
+                }
+            };
+
+            let mut samples = Vec::new();
+            let rb = guard.get_inner_mut();
+            while let Some(item) = rb.next() {
+                if item.len() >= std::mem::size_of::<PacketSample>() {
+                    samples.push(bytemuck::pod_read_unaligned::<PacketSample>(
+                        &item[..std::mem::size_of::<PacketSample>()],
+                    ));
+                }
+            }
+            guard.clear_ready();
+
+            if let Err(e) = exporter.export(&samples).await {
+                warn!("sFlow 数据报发送失败: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use aya::programs::tc::{NlOptions, SchedClassifierLinkId, TcAttachOptions};
+use aya::programs::{LinkOrder, SchedClassifier as Tc, TcAttachType};
+use aya::Ebpf;
+use bytemuck::Zeroable;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::{
+    ShapingRule, ShapingStats, SHAPING_MAX_RULES, SHAPING_MODE_DEVICE, SHAPING_MODE_FLOW,
+};
+
+use crate::server::EbpfManager;
+
+// 默认排队上限，发送时间超过当前时间该值的包被丢弃
+const DEFAULT_HORIZON_MS: u64 = 2000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShapingMode {
+    // 整个设备的出方向流量共享速率
+    #[default]
+    Device,
+    // 每条流独立限速
+    Flow,
+}
+
+// 出方向整形规则，每个设备一条
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ShapingRuleConfig {
+    pub iface: String,
+    // 速率(bit/s)
+    pub rate_bps: u64,
+    #[serde(default)]
+    pub mode: ShapingMode,
+    pub horizon_ms: Option<u64>,
+}
+
+impl ShapingRuleConfig {
+    fn rule(&self) -> ShapingRule {
+        ShapingRule {
+            rate: self.rate_bps / 8,
+            horizon_ns: self.horizon_ms.unwrap_or(DEFAULT_HORIZON_MS) * 1_000_000,
+            mode: match self.mode {
+                ShapingMode::Device => SHAPING_MODE_DEVICE,
+                ShapingMode::Flow => SHAPING_MODE_FLOW,
+            },
+            reserved: 0,
+        }
+    }
+}
+
+struct Shaping {
+    config: ShapingRuleConfig,
+    ifindex: u32,
+    link_id: SchedClassifierLinkId,
+}
+
+lazy_static::lazy_static! {
+    // 设备名 -> 整形规则及 xnet_shaper 的挂载
+    static ref SHAPING: Mutex<BTreeMap<String, Shaping>> = Mutex::new(BTreeMap::new());
+    // 设置后每次修改规则都写入该文件
+    static ref RULES_FILE: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+}
+
+// 挂载到 egress 的最前面，xnet_shaper 不丢包时继续执行 xnet_tc_egress
+fn attach(ebpf: &mut Ebpf, iface: &str) -> Result<SchedClassifierLinkId, anyhow::Error> {
+    let program: &mut Tc = ebpf
+        .program_mut("xnet_shaper")
+        .ok_or_else(|| anyhow::anyhow!("xnet_shaper program not found"))?
+        .try_into()?;
+    let options = if crate::tc_attach::tcx_supported() {
+        TcAttachOptions::TcxOrder(LinkOrder::first())
+    } else {
+        // 旧内核通过 netlink 挂载，需要 clsact qdisc
+        crate::tc_attach::ensure_clsact(iface)?;
+        TcAttachOptions::Netlink(NlOptions {
+            priority: 1,
+            handle: 0,
+        })
+    };
+    Ok(program.attach_with_options(iface, TcAttachType::Egress, options)?)
+}
+
+// 删除设备的发送时间状态，规则变化后重新开始计时
+fn clear_state(ebpf: &mut Ebpf, ifindex: u32) -> Result<(), anyhow::Error> {
+    let mut state = AyaHashMap::<&mut MapData, u64, u64>::try_from(
+        ebpf.map_mut("shaping_state")
+            .ok_or_else(|| anyhow::anyhow!("shaping_state map not found"))?,
+    )?;
+    let keys: Vec<u64> = crate::batch::entries(&state)
+        .into_iter()
+        .map(|(key, _)| key)
+        .filter(|key| (key >> 32) as u32 == ifindex)
+        .collect();
+    crate::batch::remove_keys(&mut state, &keys);
+    Ok(())
+}
+
+// 列出所有整形规则及统计
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let shaping = SHAPING.lock().await;
+    let maps = ebpf_manager.maps();
+    let stats = AyaHashMap::<&MapData, u32, ShapingStats>::try_from(
+        maps.map("shaping_stats")
+            .ok_or_else(|| anyhow::anyhow!("shaping_stats map not found"))?,
+    )?;
+
+    Ok(shaping
+        .values()
+        .map(|s| {
+            let stats = stats.get(&s.ifindex, 0).unwrap_or_else(|_| ShapingStats::zeroed());
+            let avg_delay_us = stats
+                .total_delay_ns
+                .checked_div(stats.delayed)
+                .map(|ns| ns / 1000)
+                .unwrap_or(0);
+            serde_json::json!({
+                "iface": s.config.iface,
+                "ifindex": s.ifindex,
+                "rate_bps": s.config.rate_bps,
+                "mode": s.config.mode,
+                "horizon_ms": s.config.horizon_ms.unwrap_or(DEFAULT_HORIZON_MS),
+                "packets": stats.packets,
+                "bytes": stats.bytes,
+                "delayed": stats.delayed,
+                "dropped": stats.dropped,
+                "avg_delay_us": avg_delay_us,
+            })
+        })
+        .collect())
+}
+
+// 已配置整形的网卡
+pub async fn ifaces() -> Vec<String> {
+    SHAPING.lock().await.keys().cloned().collect()
+}
+
+// 校验整形规则，返回设备的 ifindex
+fn check(config: &ShapingRuleConfig) -> Result<u32, String> {
+    if config.rate_bps < 8 {
+        return Err("rate_bps must be at least 8".to_string());
+    }
+    if config.horizon_ms == Some(0) {
+        return Err("horizon_ms must be greater than 0".to_string());
+    }
+    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", config.iface))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .ok_or_else(|| format!("Interface {} does not exist", config.iface))
+}
+
+// 校验一组规则，每个设备最多一条，返回规则及设备的 ifindex
+pub(crate) fn validate(
+    configs: Vec<ShapingRuleConfig>,
+) -> Result<Vec<(ShapingRuleConfig, u32)>, String> {
+    if configs.len() > SHAPING_MAX_RULES as usize {
+        return Err(format!("at most {} shaping rules", SHAPING_MAX_RULES));
+    }
+    let mut validated: Vec<(ShapingRuleConfig, u32)> = Vec::new();
+    for config in configs {
+        if validated.iter().any(|(c, _)| c.iface == config.iface) {
+            return Err(format!("duplicate shaping rule for {}", config.iface));
+        }
+        let ifindex =
+            check(&config).map_err(|e| format!("shaping rule {}: {}", config.iface, e))?;
+        validated.push((config, ifindex));
+    }
+    Ok(validated)
+}
+
+fn save(shaping: &BTreeMap<String, Shaping>) -> Result<(), anyhow::Error> {
+    let Some(path) = RULES_FILE.lock().unwrap().clone() else {
+        return Ok(());
+    };
+    let configs: Vec<&ShapingRuleConfig> = shaping.values().map(|s| &s.config).collect();
+    crate::rules::write_file(&path, &serde_json::to_string_pretty(&configs)?)
+}
+
+// 读取规则文件，文件不存在时为空
+pub(crate) fn read_rules(path: &Path) -> Result<Vec<ShapingRuleConfig>, anyhow::Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))
+}
+
+// 从规则文件加载整形规则，设备不存在的规则跳过并告警，
+// 在下次修改整形规则前仍保留在文件中
+pub async fn load(ebpf_manager: &EbpfManager, path: &Path) -> Result<(), anyhow::Error> {
+    let configs = read_rules(path)?;
+    let mut shaping = SHAPING.lock().await;
+    for config in configs {
+        match check(&config) {
+            Ok(ifindex) => apply(ebpf_manager, &mut shaping, config, ifindex).await?,
+            Err(e) => warn!(
+                iface = %config.iface,
+                "跳过设备 {} 的整形规则: {}",
+                config.iface,
+                e
+            ),
+        }
+    }
+    *RULES_FILE.lock().unwrap() = Some(path.to_path_buf());
+    info!("从 {} 加载了 {} 条整形规则", path.display(), shaping.len());
+    Ok(())
+}
+
+// 新增或替换设备的整形规则，首次配置时挂载 xnet_shaper
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    config: ShapingRuleConfig,
+) -> Result<Result<(), String>, anyhow::Error> {
+    let ifindex = match check(&config) {
+        Ok(ifindex) => ifindex,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let mut shaping = SHAPING.lock().await;
+    if !shaping.contains_key(&config.iface) && shaping.len() >= SHAPING_MAX_RULES as usize {
+        return Ok(Err(format!("at most {} shaping rules", SHAPING_MAX_RULES)));
+    }
+    let iface = config.iface.clone();
+    let action = if shaping.contains_key(&iface) {
+        "update"
+    } else {
+        "create"
+    };
+    apply(ebpf_manager, &mut shaping, config, ifindex).await?;
+    save(&shaping)?;
+    crate::events::iface_rule_change("shaping", &iface, action);
+    Ok(Ok(()))
+}
+
+async fn apply(
+    ebpf_manager: &EbpfManager,
+    shaping: &mut BTreeMap<String, Shaping>,
+    config: ShapingRuleConfig,
+    ifindex: u32,
+) -> Result<(), anyhow::Error> {
+    let l3 = crate::l3::is_l3(&config.iface)?;
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    crate::l3::set(&mut ebpf, ifindex, l3)?;
+    // 已有规则时沿用原来的挂载
+    let link_id = if shaping.contains_key(&config.iface) {
+        None
+    } else {
+        Some(attach(&mut ebpf, &config.iface)?)
+    };
+
+    {
+        let mut rules = AyaHashMap::<&mut MapData, u32, ShapingRule>::try_from(
+            ebpf.map_mut("shaping_rules")
+                .ok_or_else(|| anyhow::anyhow!("shaping_rules map not found"))?,
+        )?;
+        rules.insert(ifindex, config.rule(), 0)?;
+    }
+    {
+        let mut stats = AyaHashMap::<&mut MapData, u32, ShapingStats>::try_from(
+            ebpf.map_mut("shaping_stats")
+                .ok_or_else(|| anyhow::anyhow!("shaping_stats map not found"))?,
+        )?;
+        stats.insert(ifindex, ShapingStats::zeroed(), 0)?;
+    }
+    clear_state(&mut ebpf, ifindex)?;
+
+    info!(
+        iface = %config.iface,
+        device_id = ifindex,
+        "设备 {} 出方向整形: {} bit/s, 模式 {:?}",
+        config.iface, config.rate_bps, config.mode
+    );
+    match link_id {
+        Some(link_id) => {
+            crate::events::attachment("shaping", &config.iface, "attach");
+            shaping.insert(
+                config.iface.clone(),
+                Shaping {
+                    config,
+                    ifindex,
+                    link_id,
+                },
+            );
+        }
+        None => {
+            if let Some(existing) = shaping.get_mut(&config.iface) {
+                existing.config = config;
+            }
+        }
+    }
+    Ok(())
+}
+
+// 热重载时把各设备上 xnet_shaper 的挂载原子地替换为新的程序，返回替换失败的设备
+pub async fn reattach(ebpf_manager: &EbpfManager, new: &mut Ebpf) -> Vec<String> {
+    let mut shaping = SHAPING.lock().await;
+    let mut old = ebpf_manager.ebpf.lock().await;
+    let mut failed = Vec::new();
+    let ifaces: Vec<String> = shaping.keys().cloned().collect();
+    for iface in ifaces {
+        let Some(s) = shaping.remove(&iface) else {
+            continue;
+        };
+        let result = (|| -> Result<SchedClassifierLinkId, anyhow::Error> {
+            let program: &mut Tc = old
+                .program_mut("xnet_shaper")
+                .ok_or_else(|| anyhow::anyhow!("xnet_shaper program not found"))?
+                .try_into()?;
+            let link = program.take_link(s.link_id)?;
+            let program: &mut Tc = new
+                .program_mut("xnet_shaper")
+                .ok_or_else(|| anyhow::anyhow!("xnet_shaper program not found"))?
+                .try_into()?;
+            Ok(program.attach_to_link(link)?)
+        })();
+        match result {
+            Ok(link_id) => {
+                shaping.insert(iface, Shaping { link_id, ..s });
+            }
+            Err(e) => failed.push(format!("shaper {}: {}", iface, e)),
+        }
+    }
+    failed
+}
+
+// 删除设备的整形规则并卸载 xnet_shaper，规则不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, iface: &str) -> Result<bool, anyhow::Error> {
+    let mut shaping = SHAPING.lock().await;
+    let Some(s) = shaping.remove(iface) else {
+        return Ok(false);
+    };
+    detach(ebpf_manager, iface, s).await?;
+    save(&shaping)?;
+    crate::events::iface_rule_change("shaping", iface, "delete");
+    Ok(true)
+}
+
+async fn detach(ebpf_manager: &EbpfManager, iface: &str, s: Shaping) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    {
+        let program: &mut Tc = ebpf
+            .program_mut("xnet_shaper")
+            .ok_or_else(|| anyhow::anyhow!("xnet_shaper program not found"))?
+            .try_into()?;
+        program.detach(s.link_id)?;
+    }
+    {
+        let mut rules = AyaHashMap::<&mut MapData, u32, ShapingRule>::try_from(
+            ebpf.map_mut("shaping_rules")
+                .ok_or_else(|| anyhow::anyhow!("shaping_rules map not found"))?,
+        )?;
+        rules.remove(&s.ifindex)?;
+    }
+    {
+        let mut stats = AyaHashMap::<&mut MapData, u32, ShapingStats>::try_from(
+            ebpf.map_mut("shaping_stats")
+                .ok_or_else(|| anyhow::anyhow!("shaping_stats map not found"))?,
+        )?;
+        stats.remove(&s.ifindex)?;
+    }
+    clear_state(&mut ebpf, s.ifindex)?;
+    info!(iface, action = "delete", "设备 {} 出方向整形已删除", iface);
+    crate::events::attachment("shaping", iface, "detach");
+    Ok(())
+}
+
+// 当前的全部规则，格式与规则文件相同
+pub async fn export() -> Vec<ShapingRuleConfig> {
+    SHAPING
+        .lock()
+        .await
+        .values()
+        .map(|s| s.config.clone())
+        .collect()
+}
+
+// 用已校验的规则整体替换现有规则，两组规则中都有的设备沿用原来的挂载，只更新速率
+pub async fn replace_all(
+    ebpf_manager: &EbpfManager,
+    validated: Vec<(ShapingRuleConfig, u32)>,
+) -> Result<(), anyhow::Error> {
+    let mut shaping = SHAPING.lock().await;
+    let removed: Vec<String> = shaping
+        .keys()
+        .filter(|iface| !validated.iter().any(|(c, _)| &c.iface == *iface))
+        .cloned()
+        .collect();
+    for iface in removed {
+        if let Some(s) = shaping.remove(&iface) {
+            detach(ebpf_manager, &iface, s).await?;
+        }
+    }
+    for (config, ifindex) in validated {
+        apply(ebpf_manager, &mut shaping, config, ifindex).await?;
+    }
+    save(&shaping)?;
+    info!("整形规则已整体替换为 {} 条", shaping.len());
+    crate::events::rules_replaced("shaping", shaping.len());
+    Ok(())
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+use aya::maps::{Array, HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{
+    MarkEntry, MarkKey, FIREWALL_DIRECTION_ANY, FIREWALL_DIRECTION_EGRESS,
+    FIREWALL_DIRECTION_INGRESS, MARK_MAX_RULES, MARK_SKIP_MAX,
+};
+
+use crate::firewall::Direction;
+use crate::lb::L4Protocol;
+use crate::server::EbpfManager;
+
+// skb mark 规则: 源或目的地址匹配 ip、源或目的端口匹配 port 的包把 mark 中 mask 覆盖的位设为 mark
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MarkRule {
+    pub ip: Option<Ipv4Addr>,
+    pub port: Option<u16>,
+    // 按端口匹配时必填
+    pub protocol: Option<L4Protocol>,
+    pub mark: u32,
+    // 不指定时改写整个 mark
+    pub mask: Option<u32>,
+    #[serde(default)]
+    pub direction: Direction,
+}
+
+// 跳过带有指定 mark 的包: mark & mask == value 的包不统计、不过滤也不改写
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct MarkSkip {
+    pub mark: u32,
+    // 不指定时要求整个 mark 相等
+    pub mask: Option<u32>,
+    #[serde(default)]
+    pub direction: Direction,
+}
+
+// 命令行中的跳过条件，格式与 iptables 的 --mark 相同: value[/mask]，可以是十六进制，例如 0x10/0xff
+pub fn parse_skip_mark(s: &str) -> Result<MarkSkip, String> {
+    let parse = |v: &str| {
+        let v = v.trim();
+        match v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => v.parse(),
+        }
+        .map_err(|_| format!("invalid mark '{}', expected value[/mask]", s))
+    };
+    let (mark, mask) = match s.split_once('/') {
+        Some((mark, mask)) => (parse(mark)?, Some(parse(mask)?)),
+        None => (parse(s)?, None),
+    };
+    let skip = MarkSkip {
+        mark,
+        mask,
+        direction: Direction::Any,
+    };
+    skip.entry()?;
+    Ok(skip)
+}
+
+fn direction(direction: Direction) -> u8 {
+    match direction {
+        Direction::Any => FIREWALL_DIRECTION_ANY,
+        Direction::Ingress => FIREWALL_DIRECTION_INGRESS,
+        Direction::Egress => FIREWALL_DIRECTION_EGRESS,
+    }
+}
+
+impl MarkRule {
+    fn entry(&self) -> Result<MarkEntry, String> {
+        if self.ip.is_none() && self.port.is_none() {
+            return Err("ip or port is required".to_string());
+        }
+        if self.port.is_some() != self.protocol.is_some() {
+            return Err("protocol is required with port and only allowed with port".to_string());
+        }
+        let mask = self.mask.unwrap_or(u32::MAX);
+        if mask == 0 {
+            return Err("mask must not be 0".to_string());
+        }
+        if self.mark & !mask != 0 {
+            return Err(format!(
+                "mark {:#x} has bits outside mask {:#x}",
+                self.mark, mask
+            ));
+        }
+        Ok(MarkEntry {
+            value: self.mark,
+            mask,
+            direction: direction(self.direction),
+            reserved: [0; 7],
+            packets: 0,
+        })
+    }
+
+    fn key(&self) -> MarkKey {
+        MarkKey {
+            addr: self.ip.map(|ip| u32::from(ip).to_be()).unwrap_or(0),
+            port: self.port.unwrap_or(0).to_be(),
+            protocol: self.protocol.map(|p| p.number()).unwrap_or(0),
+            reserved: 0,
+        }
+    }
+}
+
+impl MarkSkip {
+    fn entry(&self) -> Result<MarkEntry, String> {
+        let mask = self.mask.unwrap_or(u32::MAX);
+        // 值为0时会匹配所有没有 mark 的包
+        if self.mark == 0 || mask == 0 {
+            return Err("mark and mask must not be 0".to_string());
+        }
+        if self.mark & !mask != 0 {
+            return Err(format!(
+                "mark {:#x} has bits outside mask {:#x}",
+                self.mark, mask
+            ));
+        }
+        Ok(MarkEntry {
+            value: self.mark,
+            mask,
+            direction: direction(self.direction),
+            reserved: [0; 7],
+            packets: 0,
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    // 规则ID -> 规则
+    static ref MARK_RULES: Mutex<BTreeMap<u32, MarkRule>> = Mutex::new(BTreeMap::new());
+    // mark_skip 中的位置 -> 跳过条件
+    static ref MARK_SKIPS: Mutex<BTreeMap<u32, MarkSkip>> = Mutex::new(BTreeMap::new());
+}
+
+// 列出所有 skb mark 规则及命中的包数
+pub async fn list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let rules = MARK_RULES.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, MarkKey, MarkEntry>::try_from(
+        maps.map("mark_rules")
+            .ok_or_else(|| anyhow::anyhow!("mark_rules map not found"))?,
+    )?;
+
+    Ok(rules
+        .iter()
+        .map(|(id, rule)| {
+            let packets = map.get(&rule.key(), 0).map(|e| e.packets).unwrap_or(0);
+            serde_json::json!({
+                "id": id,
+                "ip": rule.ip,
+                "port": rule.port,
+                "protocol": rule.protocol,
+                "mark": rule.mark,
+                "mask": rule.mask.unwrap_or(u32::MAX),
+                "direction": rule.direction,
+                "packets": packets,
+            })
+        })
+        .collect())
+}
+
+// 新增 skb mark 规则，匹配条件相同的规则会被替换，返回规则ID
+pub async fn upsert(
+    ebpf_manager: &EbpfManager,
+    rule: MarkRule,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    let entry = match rule.entry() {
+        Ok(entry) => entry,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let mut rules = MARK_RULES.lock().await;
+    let key = rule.key();
+    let existing = rules
+        .iter()
+        .find(|(_, r)| r.key() == key)
+        .map(|(id, _)| *id);
+    let id = match existing.or_else(|| (0..MARK_MAX_RULES).find(|id| !rules.contains_key(id))) {
+        Some(id) => id,
+        None => return Ok(Err(format!("at most {} mark rules", MARK_MAX_RULES))),
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, MarkKey, MarkEntry>::try_from(
+        ebpf.map_mut("mark_rules")
+            .ok_or_else(|| anyhow::anyhow!("mark_rules map not found"))?,
+    )?;
+    map.insert(key, entry, 0)?;
+
+    info!(
+        rule_id = id,
+        action = "mark",
+        "skb mark 规则 {}: ip={:?} port={:?} {:?} {:?} -> {:#x}/{:#x}",
+        id,
+        rule.ip,
+        rule.port,
+        rule.protocol,
+        rule.direction,
+        entry.value,
+        entry.mask
+    );
+    rules.insert(id, rule);
+    Ok(Ok(id))
+}
+
+// 删除 skb mark 规则，规则不存在时返回 false
+pub async fn remove(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut rules = MARK_RULES.lock().await;
+    let Some(rule) = rules.remove(&id) else {
+        return Ok(false);
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = AyaHashMap::<&mut MapData, MarkKey, MarkEntry>::try_from(
+        ebpf.map_mut("mark_rules")
+            .ok_or_else(|| anyhow::anyhow!("mark_rules map not found"))?,
+    )?;
+    map.remove(&rule.key())?;
+    info!(
+        rule_id = id,
+        action = "delete",
+        "skb mark 规则 {} 已删除",
+        id
+    );
+    Ok(true)
+}
+
+// 列出所有跳过条件及跳过的包数
+pub async fn skip_list(ebpf_manager: &EbpfManager) -> Result<Vec<Value>, anyhow::Error> {
+    let skips = MARK_SKIPS.lock().await;
+    let maps = ebpf_manager.maps();
+    let map = Array::<&MapData, MarkEntry>::try_from(
+        maps.map("mark_skip")
+            .ok_or_else(|| anyhow::anyhow!("mark_skip map not found"))?,
+    )?;
+
+    Ok(skips
+        .iter()
+        .map(|(id, skip)| {
+            let packets = map.get(id, 0).map(|e| e.packets).unwrap_or(0);
+            serde_json::json!({
+                "id": id,
+                "mark": skip.mark,
+                "mask": skip.mask.unwrap_or(u32::MAX),
+                "direction": skip.direction,
+                "packets": packets,
+            })
+        })
+        .collect())
+}
+
+// 新增跳过条件，mark、mask 和方向都相同的条件会被替换，返回条件ID
+pub async fn add_skip(
+    ebpf_manager: &EbpfManager,
+    skip: MarkSkip,
+) -> Result<Result<u32, String>, anyhow::Error> {
+    let entry = match skip.entry() {
+        Ok(entry) => entry,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let mut skips = MARK_SKIPS.lock().await;
+    let existing = skips
+        .iter()
+        .find(|(_, s)| {
+            s.mark == skip.mark
+                && s.mask.unwrap_or(u32::MAX) == entry.mask
+                && s.direction == skip.direction
+        })
+        .map(|(id, _)| *id);
+    let id = match existing.or_else(|| (0..MARK_SKIP_MAX).find(|id| !skips.contains_key(id))) {
+        Some(id) => id,
+        None => return Ok(Err(format!("at most {} mark skips", MARK_SKIP_MAX))),
+    };
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = Array::<&mut MapData, MarkEntry>::try_from(
+        ebpf.map_mut("mark_skip")
+            .ok_or_else(|| anyhow::anyhow!("mark_skip map not found"))?,
+    )?;
+    map.set(id, entry, 0)?;
+
+    info!(
+        rule_id = id,
+        action = "skip",
+        "跳过 mark 为 {:#x}/{:#x} 的包({:?})",
+        entry.value,
+        entry.mask,
+        skip.direction
+    );
+    skips.insert(id, skip);
+    Ok(Ok(id))
+}
+
+// 删除跳过条件，条件不存在时返回 false
+pub async fn remove_skip(ebpf_manager: &EbpfManager, id: u32) -> Result<bool, anyhow::Error> {
+    let mut skips = MARK_SKIPS.lock().await;
+    if skips.remove(&id).is_none() {
+        return Ok(false);
+    }
+
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    let mut map = Array::<&mut MapData, MarkEntry>::try_from(
+        ebpf.map_mut("mark_skip")
+            .ok_or_else(|| anyhow::anyhow!("mark_skip map not found"))?,
+    )?;
+    // mask 为0的条件不生效
+    map.set(id, <MarkEntry as bytemuck::Zeroable>::zeroed(), 0)?;
+    info!(
+        rule_id = id,
+        action = "delete",
+        "mark 跳过条件 {} 已删除",
+        id
+    );
+    Ok(true)
+}
+
+// 写入启动时通过 --skip-mark 指定的跳过条件
+pub async fn init(ebpf_manager: &EbpfManager, skips: &[MarkSkip]) -> Result<(), anyhow::Error> {
+    for skip in skips {
+        if let Err(e) = add_skip(ebpf_manager, *skip).await? {
+            return Err(anyhow::anyhow!(e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_skip_marks() {
+        let skip = parse_skip_mark("0x10/0xff").unwrap();
+        assert_eq!((skip.mark, skip.mask), (0x10, Some(0xff)));
+        let skip = parse_skip_mark("7").unwrap();
+        assert_eq!((skip.mark, skip.mask), (7, None));
+        assert!(parse_skip_mark("0").is_err());
+        assert!(parse_skip_mark("0x100/0xff").is_err());
+        assert!(parse_skip_mark("fw").is_err());
+    }
+}
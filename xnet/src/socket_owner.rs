@@ -0,0 +1,162 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use aya::programs::FEntry;
+use aya::{Btf, Ebpf};
+use serde_json::Value;
+use tracing::info;
+use xnet_common::{SocketKey, SocketOwner, SocketStats};
+
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+use crate::traffic::TrafficStats;
+
+// 记录 socket 所属进程的 fentry 程序及挂载的内核函数
+const PROGRAMS: [(&str, &str); 2] = [
+    ("xnet_sock_connect", "tcp_connect"),
+    ("xnet_sock_sendmsg", "tcp_sendmsg"),
+];
+
+// 挂载成功后置位，未启用时 /socket_flows 返回错误
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct SocketFlowQuery {
+    pub pid: Option<u32>,
+    // 进程名，不区分大小写
+    pub comm: Option<String>,
+    // 本地或远端端口
+    pub port: Option<u16>,
+    // 按流量从大到小排序后返回的条数
+    pub limit: Option<usize>,
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// 挂载 tcp_connect 和 tcp_sendmsg 的 fentry 程序，需要内核 BTF，
+// 在 tracing 程序中调用 bpf_get_socket_cookie 需要 5.12 及以上的内核
+pub async fn attach(ebpf_manager: &EbpfManager) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    attach_programs(&mut ebpf)?;
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("socket 进程归属 fentry 程序已挂载");
+    Ok(())
+}
+
+fn attach_programs(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    let btf = Btf::from_sys_fs()?;
+    for (name, function) in PROGRAMS {
+        let program: &mut FEntry = ebpf
+            .program_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("{} program not found", name))?
+            .try_into()?;
+        program.load(function, &btf)?;
+        program.attach()?;
+    }
+    Ok(())
+}
+
+// 热重载时挂载新的程序，旧程序随旧的 eBPF 实例释放
+pub fn reattach(new: &mut Ebpf) -> Result<(), anyhow::Error> {
+    if !enabled() {
+        return Ok(());
+    }
+    attach_programs(new)
+}
+
+fn owner_json(owner: &SocketOwner, offset_ns: u64) -> Value {
+    let len = owner
+        .comm
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(owner.comm.len());
+    serde_json::json!({
+        "pid": owner.pid,
+        "comm": String::from_utf8_lossy(&owner.comm[..len]),
+        "cgroup_id": owner.cgroup_id,
+        "since": (owner.timestamp_ns + offset_ns) / 1_000_000_000,
+    })
+}
+
+fn flow_bytes(flows: &[Value]) -> u64 {
+    flows
+        .iter()
+        .map(|flow| flow["rx_bytes"].as_u64().unwrap_or(0) + flow["tx_bytes"].as_u64().unwrap_or(0))
+        .sum()
+}
+
+// 以 socket cookie 关联进程、sock_ops 指标和 TC 连接统计，只返回有指标或流量的 socket
+pub fn list(
+    maps: &MapCache,
+    traffic: &TrafficStats,
+    query: &SocketFlowQuery,
+) -> Result<Vec<Value>, anyhow::Error> {
+    let owners = AyaHashMap::<&MapData, u64, SocketOwner>::try_from(
+        maps.map("socket_owners")
+            .ok_or_else(|| anyhow::anyhow!("socket_owners map not found"))?,
+    )?;
+    let sockets = AyaHashMap::<&MapData, SocketKey, SocketStats>::try_from(
+        maps.map("socket_stats")
+            .ok_or_else(|| anyhow::anyhow!("socket_stats map not found"))?,
+    )?;
+    let owners: HashMap<u64, SocketOwner> = crate::batch::entries(&owners).into_iter().collect();
+    let sockets: HashMap<u64, (SocketKey, SocketStats)> = crate::batch::entries(&sockets)
+        .into_iter()
+        .filter(|(_, stats)| stats.cookie != 0)
+        .map(|(key, stats)| (stats.cookie, (key, stats)))
+        .collect();
+    let mut flows = traffic.flows_by_socket();
+
+    let now = crate::conntrack::monotonic_now_ns();
+    let offset_ns = crate::capture::monotonic_to_realtime_offset_ns();
+    let cookies: BTreeSet<u64> = sockets.keys().chain(flows.keys()).copied().collect();
+    let mut records: Vec<(u64, Value)> = cookies
+        .into_iter()
+        .filter_map(|cookie| {
+            let owner = owners.get(&cookie);
+            if query.pid.is_some_and(|pid| owner.is_none_or(|owner| owner.pid != pid)) {
+                return None;
+            }
+            let process = owner.map(|owner| owner_json(owner, offset_ns));
+            if let Some(comm) = query.comm.as_deref() {
+                let matches = process
+                    .as_ref()
+                    .and_then(|process| process["comm"].as_str())
+                    .is_some_and(|name| name.eq_ignore_ascii_case(comm));
+                if !matches {
+                    return None;
+                }
+            }
+            let socket = sockets.get(&cookie);
+            let socket_flows = flows.remove(&cookie).unwrap_or_default();
+            if let Some(port) = query.port {
+                let socket_port = socket.is_some_and(|(key, _)| key.local_port == port || key.remote_port == port);
+                let flow_port = socket_flows
+                    .iter()
+                    .any(|flow| flow["local_port"] == port || flow["remote_port"] == port);
+                if !socket_port && !flow_port {
+                    return None;
+                }
+            }
+            let bytes = flow_bytes(&socket_flows);
+            Some((
+                bytes,
+                serde_json::json!({
+                    "cookie": cookie,
+                    "process": process,
+                    "socket": socket.map(|(key, stats)| crate::sockops::socket_json(key, stats, now)),
+                    "flows": socket_flows,
+                    "bytes": bytes,
+                }),
+            ))
+        })
+        .collect();
+    records.sort_by_key(|(bytes, _)| std::cmp::Reverse(*bytes));
+    if let Some(limit) = query.limit {
+        records.truncate(limit);
+    }
+    Ok(records.into_iter().map(|(_, record)| record).collect())
+}
@@ -0,0 +1,176 @@
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Context as _;
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use aya::programs::{CgroupAttachMode, SockOps};
+use aya::Ebpf;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{SocketKey, SocketStats};
+
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+use crate::traffic::TrafficStats;
+
+// 内核 TCP 状态，见 include/net/tcp_states.h
+const TCP_STATES: [&str; 12] = [
+    "established",
+    "syn_sent",
+    "syn_recv",
+    "fin_wait1",
+    "fin_wait2",
+    "time_wait",
+    "close",
+    "close_wait",
+    "last_ack",
+    "listen",
+    "closing",
+    "new_syn_recv",
+];
+
+// 挂载成功后置位，未启用时 /sockets 返回错误
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    // 已挂载的 cgroup，热重载时把新的程序挂载到同一 cgroup
+    static ref CGROUP: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+pub(crate) fn state_name(state: u32) -> &'static str {
+    (state as usize)
+        .checked_sub(1)
+        .and_then(|i| TCP_STATES.get(i))
+        .copied()
+        .unwrap_or("unknown")
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct SocketQuery {
+    // 本地或远端端口
+    pub port: Option<u16>,
+    // TCP 状态名，如 established
+    pub state: Option<String>,
+    // 按平滑RTT从大到小排序后返回的条数
+    pub limit: Option<usize>,
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// 把 sock_ops 程序挂载到 cgroup v2 目录，该 cgroup 及其子 cgroup 中的 TCP socket 都会被记录
+pub async fn attach(ebpf_manager: &EbpfManager, cgroup: &Path) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    attach_program(&mut ebpf, cgroup)?;
+    *CGROUP.lock().await = Some(cgroup.to_path_buf());
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("sock_ops 程序已挂载到 cgroup {}", cgroup.display());
+    Ok(())
+}
+
+fn attach_program(ebpf: &mut Ebpf, cgroup: &Path) -> Result<(), anyhow::Error> {
+    let file = std::fs::File::open(cgroup)
+        .with_context(|| format!("failed to open cgroup {}", cgroup.display()))?;
+    let program: &mut SockOps = ebpf
+        .program_mut("xnet_sockops")
+        .ok_or_else(|| anyhow::anyhow!("xnet_sockops program not found"))?
+        .try_into()?;
+    program.attach(file, CgroupAttachMode::AllowMultiple)?;
+    Ok(())
+}
+
+// 热重载时把新的程序挂载到同一 cgroup，旧程序随旧的 eBPF 实例释放
+pub async fn reattach(new: &mut Ebpf) -> Result<(), anyhow::Error> {
+    match CGROUP.lock().await.as_deref() {
+        Some(cgroup) => attach_program(new, cgroup),
+        None => Ok(()),
+    }
+}
+
+// 与头部解析得到的连接统计按端口关联，TCP 连接在每个挂载设备和方向上各有一条
+fn flows_json(traffic: &TrafficStats, key: &SocketKey) -> Vec<Value> {
+    traffic
+        .device_connection_stats
+        .values()
+        .filter(|stats| stats.flow.protocol == 6)
+        .filter(|stats| {
+            (stats.flow.src_port == key.local_port && stats.flow.dst_port == key.remote_port)
+                || (stats.flow.src_port == key.remote_port && stats.flow.dst_port == key.local_port)
+        })
+        .map(|stats| {
+            serde_json::json!({
+                "device_id": stats.device_id,
+                "direction": if stats.direction == 0 { "ingress" } else { "egress" },
+                "packets": stats.total_packets,
+                "bytes": stats.total_bytes,
+                "retransmissions": stats.retransmissions,
+                "dup_acks": stats.dup_acks,
+            })
+        })
+        .collect()
+}
+
+// 列出 socket 指标，按平滑RTT从大到小排序
+pub fn list(
+    maps: &MapCache,
+    traffic: &TrafficStats,
+    query: &SocketQuery,
+) -> Result<Vec<Value>, anyhow::Error> {
+    let map = AyaHashMap::<&MapData, SocketKey, SocketStats>::try_from(
+        maps.map("socket_stats")
+            .ok_or_else(|| anyhow::anyhow!("socket_stats map not found"))?,
+    )?;
+    let now = crate::conntrack::monotonic_now_ns();
+
+    let mut sockets: Vec<(SocketKey, SocketStats)> = crate::batch::entries(&map)
+        .into_iter()
+        .filter(|(key, _)| {
+            query
+                .port
+                .is_none_or(|port| key.local_port == port || key.remote_port == port)
+        })
+        .filter(|(_, stats)| {
+            query
+                .state
+                .as_deref()
+                .is_none_or(|state| state_name(stats.state).eq_ignore_ascii_case(state))
+        })
+        .collect();
+    sockets.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.srtt_us));
+    if let Some(limit) = query.limit {
+        sockets.truncate(limit);
+    }
+
+    Ok(sockets
+        .iter()
+        .map(|(key, stats)| {
+            let mut item = socket_json(key, stats, now);
+            item["flows"] = Value::from(flows_json(traffic, key));
+            item
+        })
+        .collect())
+}
+
+pub(crate) fn socket_json(key: &SocketKey, stats: &SocketStats, now: u64) -> Value {
+    serde_json::json!({
+        "local": format!("{}:{}", Ipv4Addr::from(u32::from_be(key.local_addr)), key.local_port),
+        "remote": format!("{}:{}", Ipv4Addr::from(u32::from_be(key.remote_addr)), key.remote_port),
+        "cookie": stats.cookie,
+        "state": state_name(stats.state),
+        "srtt_us": stats.srtt_us,
+        "rtt_min_us": stats.rtt_min_us,
+        "rtt_samples": stats.rtt_samples,
+        "snd_cwnd": stats.snd_cwnd,
+        "snd_ssthresh": stats.snd_ssthresh,
+        "total_retrans": stats.total_retrans,
+        "bytes_acked": stats.bytes_acked,
+        "bytes_received": stats.bytes_received,
+        "segs_in": stats.segs_in,
+        "segs_out": stats.segs_out,
+        "age_secs": now.saturating_sub(stats.established_ns) / 1_000_000_000,
+        "idle_secs": now.saturating_sub(stats.last_update_ns) / 1_000_000_000,
+    })
+}
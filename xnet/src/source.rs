@@ -0,0 +1,97 @@
+use xnet_common::{
+    DeviceCastStats, DeviceConnectionStats, DeviceStats, DeviceStatsKey, Icmpv6Stats, PortStats,
+    ProtocolStats, ProtocolStatsKey,
+};
+
+use crate::map_cache::TrafficMaps;
+
+// TrafficStats 读取的统计数据来源，正常运行时为启动时打开的 eBPF map，测试时为内存中的数据
+// 只覆盖流量统计的读取，挂载、卸载和写入规则 map 的处理仍直接使用 aya::Ebpf
+pub trait StatsSource {
+    // 总包数和总字节数，map 不存在时返回 None
+    fn total_stats(&self) -> Option<(u64, u64)>;
+    fn port_stats(&self) -> Vec<(u16, PortStats)>;
+    fn device_stats(&self) -> Vec<(DeviceStatsKey, DeviceStats)>;
+    fn device_cast_stats(&self) -> Vec<(DeviceStatsKey, DeviceCastStats)>;
+    fn protocol_stats(&self) -> Vec<(ProtocolStatsKey, ProtocolStats)>;
+    fn icmpv6_stats(&self) -> Vec<(DeviceStatsKey, Icmpv6Stats)>;
+    // key 为连接哈希
+    fn device_connection_stats(&self) -> Vec<(u32, DeviceConnectionStats)>;
+}
+
+impl StatsSource for TrafficMaps {
+    fn total_stats(&self) -> Option<(u64, u64)> {
+        Some((
+            self.total.get(&0, 0).unwrap_or(0),
+            self.total.get(&1, 0).unwrap_or(0),
+        ))
+    }
+
+    fn port_stats(&self) -> Vec<(u16, PortStats)> {
+        crate::batch::entries(&self.ports)
+    }
+
+    fn device_stats(&self) -> Vec<(DeviceStatsKey, DeviceStats)> {
+        crate::batch::entries(&self.devices)
+    }
+
+    fn device_cast_stats(&self) -> Vec<(DeviceStatsKey, DeviceCastStats)> {
+        crate::batch::entries(&self.device_casts)
+    }
+
+    fn protocol_stats(&self) -> Vec<(ProtocolStatsKey, ProtocolStats)> {
+        crate::batch::entries(&self.protocols)
+    }
+
+    fn icmpv6_stats(&self) -> Vec<(DeviceStatsKey, Icmpv6Stats)> {
+        crate::batch::entries(&self.icmpv6)
+    }
+
+    fn device_connection_stats(&self) -> Vec<(u32, DeviceConnectionStats)> {
+        crate::batch::entries(&self.connections)
+    }
+}
+
+// 内存中的统计数据，用于在没有内核和 root 权限时测试聚合和查询逻辑
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySource {
+    pub total: Option<(u64, u64)>,
+    pub ports: Vec<(u16, PortStats)>,
+    pub devices: Vec<(DeviceStatsKey, DeviceStats)>,
+    pub device_casts: Vec<(DeviceStatsKey, DeviceCastStats)>,
+    pub protocols: Vec<(ProtocolStatsKey, ProtocolStats)>,
+    pub icmpv6: Vec<(DeviceStatsKey, Icmpv6Stats)>,
+    pub connections: Vec<(u32, DeviceConnectionStats)>,
+}
+
+#[cfg(test)]
+impl StatsSource for MemorySource {
+    fn total_stats(&self) -> Option<(u64, u64)> {
+        self.total
+    }
+
+    fn port_stats(&self) -> Vec<(u16, PortStats)> {
+        self.ports.clone()
+    }
+
+    fn device_stats(&self) -> Vec<(DeviceStatsKey, DeviceStats)> {
+        self.devices.clone()
+    }
+
+    fn device_cast_stats(&self) -> Vec<(DeviceStatsKey, DeviceCastStats)> {
+        self.device_casts.clone()
+    }
+
+    fn protocol_stats(&self) -> Vec<(ProtocolStatsKey, ProtocolStats)> {
+        self.protocols.clone()
+    }
+
+    fn icmpv6_stats(&self) -> Vec<(DeviceStatsKey, Icmpv6Stats)> {
+        self.icmpv6.clone()
+    }
+
+    fn device_connection_stats(&self) -> Vec<(u32, DeviceConnectionStats)> {
+        self.connections.clone()
+    }
+}
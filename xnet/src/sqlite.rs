@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+use tracing::{info, warn};
+use xnet_common::DeviceConnectionStats;
+
+
+// 统计表定义: 维度列和累加的指标列
+struct Table {
+    name: &'static str,
+    dimensions: &'static [(&'static str, &'static str)],
+    metrics: &'static [&'static str],
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "total_stats",
+        dimensions: &[],
+        metrics: &["packets", "bytes"],
+    },
+    Table {
+        name: "device_stats",
+        dimensions: &[("device", "TEXT")],
+        metrics: &["packets", "bytes"],
+    },
+    Table {
+        name: "port_stats",
+        dimensions: &[("port", "INTEGER")],
+        metrics: &["packets", "bytes"],
+    },
+    Table {
+        name: "connection_stats",
+        dimensions: &[
+            ("device_id", "INTEGER"),
+            ("src_port", "INTEGER"),
+            ("dst_port", "INTEGER"),
+            ("direction", "TEXT"),
+            ("protocol", "TEXT"),
+        ],
+        metrics: &["packets", "bytes", "retransmissions"],
+    },
+];
+
+// 汇总层级: 原始采样(表名无后缀) -> 分钟(_1m) -> 小时(_1h)
+struct Rollup {
+    source: &'static str,
+    target: &'static str,
+    bucket_secs: u64,
+}
+
+const LEVELS: [&str; 3] = ["", "_1m", "_1h"];
+
+const ROLLUPS: &[Rollup] = &[
+    Rollup {
+        source: "",
+        target: "_1m",
+        bucket_secs: 60,
+    },
+    Rollup {
+        source: "_1m",
+        target: "_1h",
+        bucket_secs: 3600,
+    },
+];
+
+// 汇总和过期清理的执行间隔
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+// 周期结束后延迟汇总的时间，等待正在写入的采样落库
+const ROLLUP_GRACE_SECS: u64 = 10;
+
+// 各层级数据的保留时长
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub raw: Duration,
+    pub minute: Duration,
+    pub hour: Duration,
+}
+
+impl Retention {
+    // 保留时长至少覆盖两个下一层级的汇总周期，避免数据在汇总前被删除
+    fn for_level(&self, level: &str) -> Duration {
+        match level {
+            "" => self.raw.max(Duration::from_secs(120)),
+            "_1m" => self.minute.max(Duration::from_secs(7200)),
+            _ => self.hour,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SqliteConfig {
+    pub path: PathBuf,
+    pub retention: Retention,
+}
+
+// 每行为一个周期内的增量，汇总表中的 ts 为周期起始时间
+fn schema() -> String {
+    let mut sql = String::from(
+        "CREATE TABLE IF NOT EXISTS rollup_state (name TEXT PRIMARY KEY, last_ts INTEGER NOT NULL);\n",
+    );
+    for table in TABLES {
+        for level in LEVELS {
+            let mut columns = vec![
+                "ts INTEGER NOT NULL".to_string(),
+                "interval_secs REAL NOT NULL".to_string(),
+            ];
+            columns.extend(
+                table
+                    .dimensions
+                    .iter()
+                    .map(|(name, ty)| format!("{} {} NOT NULL", name, ty)),
+            );
+            columns.extend(
+                table
+                    .metrics
+                    .iter()
+                    .map(|name| format!("{} INTEGER NOT NULL", name)),
+            );
+            sql.push_str(&format!(
+                "CREATE TABLE IF NOT EXISTS {name}{level} ({columns});\n\
+                 CREATE INDEX IF NOT EXISTS idx_{name}{level}_ts ON {name}{level} (ts);\n",
+                name = table.name,
+                level = level,
+                columns = columns.join(", "),
+            ));
+        }
+    }
+    sql
+}
+
+// 某一时刻的累计计数 (包数, 字节数)
+#[derive(Debug, Clone, Default)]
+struct Snapshot {
+    total: (u64, u64),
+    devices: HashMap<String, (u64, u64)>,
+    ports: HashMap<u16, (u64, u64)>,
+    connections: HashMap<u32, DeviceConnectionStats>,
+}
+
+// 一个周期内的连接增量
+struct ConnectionDelta {
+    stats: DeviceConnectionStats,
+    packets: u64,
+    bytes: u64,
+    retransmissions: u64,
+}
+
+// 一个周期内的增量，只包含有流量的设备、端口和连接
+struct Aggregate {
+    ts: u64,
+    interval_secs: f64,
+    total: (u64, u64),
+    devices: Vec<(String, (u64, u64))>,
+    ports: Vec<(u16, (u64, u64))>,
+    connections: Vec<ConnectionDelta>,
+}
+
+fn delta(current: (u64, u64), previous: (u64, u64)) -> (u64, u64) {
+    (
+        current.0.saturating_sub(previous.0),
+        current.1.saturating_sub(previous.1),
+    )
+}
+
+fn deltas<K: Clone + Eq + std::hash::Hash>(
+    current: &HashMap<K, (u64, u64)>,
+    previous: &HashMap<K, (u64, u64)>,
+) -> Vec<(K, (u64, u64))> {
+    current
+        .iter()
+        .map(|(key, counter)| {
+            let prev = previous.get(key).copied().unwrap_or_default();
+            (key.clone(), delta(*counter, prev))
+        })
+        .filter(|(_, (packets, _))| *packets > 0)
+        .collect()
+}
+
+impl Aggregate {
+    fn between(current: &Snapshot, previous: &Snapshot, interval_secs: f64) -> Self {
+        let connections = current
+            .connections
+            .iter()
+            .filter_map(|(key, stats)| {
+                let prev = previous.connections.get(key);
+                let packets = stats
+                    .total_packets
+                    .saturating_sub(prev.map(|p| p.total_packets).unwrap_or(0));
+                if packets == 0 {
+                    return None;
+                }
+                Some(ConnectionDelta {
+                    stats: *stats,
+                    packets,
+                    bytes: stats
+                        .total_bytes
+                        .saturating_sub(prev.map(|p| p.total_bytes).unwrap_or(0)),
+                    retransmissions: stats
+                        .retransmissions
+                        .saturating_sub(prev.map(|p| p.retransmissions).unwrap_or(0)),
+                })
+            })
+            .collect();
+
+        Self {
+            ts: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            interval_secs,
+            total: delta(current.total, previous.total),
+            devices: deltas(&current.devices, &previous.devices),
+            ports: deltas(&current.ports, &previous.ports),
+            connections,
+        }
+    }
+}
+
+pub fn open(path: &Path) -> Result<Connection, anyhow::Error> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(&schema())?;
+    Ok(conn)
+}
+
+// 在一个事务中写入一个周期的聚合数据
+fn write(conn: &mut Connection, aggregate: &Aggregate) -> Result<(), anyhow::Error> {
+    let ts = aggregate.ts as i64;
+    let interval = aggregate.interval_secs;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO total_stats (ts, interval_secs, packets, bytes) VALUES (?1, ?2, ?3, ?4)",
+        params![ts, interval, aggregate.total.0 as i64, aggregate.total.1 as i64],
+    )?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO device_stats (ts, interval_secs, device, packets, bytes) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (device, (packets, bytes)) in &aggregate.devices {
+            stmt.execute(params![ts, interval, device, *packets as i64, *bytes as i64])?;
+        }
+
+        let mut stmt = tx.prepare(
+            "INSERT INTO port_stats (ts, interval_secs, port, packets, bytes) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (port, (packets, bytes)) in &aggregate.ports {
+            stmt.execute(params![ts, interval, port, *packets as i64, *bytes as i64])?;
+        }
+
+        let mut stmt = tx.prepare(
+            "INSERT INTO connection_stats (ts, interval_secs, device_id, src_port, dst_port, direction, protocol, packets, bytes, retransmissions) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?;
+        for conn_delta in &aggregate.connections {
+            let stats = &conn_delta.stats;
+            let direction = if stats.direction == 0 { "ingress" } else { "egress" };
+            let protocol = match stats.flow.protocol {
+                6 => "TCP",
+                17 => "UDP",
+                _ => "UNKNOWN",
+            };
+            stmt.execute(params![
+                ts,
+                interval,
+                stats.device_id,
+                stats.flow.src_port,
+                stats.flow.dst_port,
+                direction,
+                protocol,
+                conn_delta.packets as i64,
+                conn_delta.bytes as i64,
+                conn_delta.retransmissions as i64,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// 将已经结束的周期汇总到下一层级
+fn rollup(conn: &mut Connection, now: u64) -> Result<(), anyhow::Error> {
+    let tx = conn.transaction()?;
+    for rollup in ROLLUPS {
+        let end = now.saturating_sub(ROLLUP_GRACE_SECS) / rollup.bucket_secs * rollup.bucket_secs;
+        for table in TABLES {
+            let state_name = format!("{}{}", table.name, rollup.target);
+            let start: i64 = tx
+                .query_row(
+                    "SELECT last_ts FROM rollup_state WHERE name = ?1",
+                    params![state_name],
+                    |row| row.get(0),
+                )
+                .unwrap_or(0);
+            if start >= end as i64 {
+                continue;
+            }
+
+            let dimensions: Vec<&str> = table.dimensions.iter().map(|(name, _)| *name).collect();
+            let sums: Vec<String> = table
+                .metrics
+                .iter()
+                .map(|name| format!("SUM({})", name))
+                .collect();
+            let mut insert_columns = vec!["ts", "interval_secs"];
+            insert_columns.extend(dimensions.iter());
+            insert_columns.extend(table.metrics.iter());
+            let mut select_columns = vec![
+                format!("ts / {b} * {b} AS bucket", b = rollup.bucket_secs),
+                "SUM(interval_secs)".to_string(),
+            ];
+            select_columns.extend(dimensions.iter().map(|d| d.to_string()));
+            select_columns.extend(sums);
+            let mut group_by = vec!["bucket"];
+            group_by.extend(dimensions.iter());
+
+            tx.execute(
+                &format!(
+                    "INSERT INTO {name}{target} ({insert}) SELECT {select} FROM {name}{source} \
+                     WHERE ts >= ?1 AND ts < ?2 GROUP BY {group_by}",
+                    name = table.name,
+                    target = rollup.target,
+                    source = rollup.source,
+                    insert = insert_columns.join(", "),
+                    select = select_columns.join(", "),
+                    group_by = group_by.join(", "),
+                ),
+                params![start, end as i64],
+            )?;
+            tx.execute(
+                "INSERT INTO rollup_state (name, last_ts) VALUES (?1, ?2) \
+                 ON CONFLICT(name) DO UPDATE SET last_ts = excluded.last_ts",
+                params![state_name, end as i64],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// 删除超过保留时长的数据
+fn expire(conn: &mut Connection, now: u64, retention: &Retention) -> Result<usize, anyhow::Error> {
+    let tx = conn.transaction()?;
+    let mut deleted = 0;
+    for level in LEVELS {
+        let cutoff = now.saturating_sub(retention.for_level(level).as_secs()) as i64;
+        for table in TABLES {
+            deleted += tx.execute(
+                &format!("DELETE FROM {}{} WHERE ts < ?1", table.name, level),
+                params![cutoff],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(deleted)
+}
+
+fn maintain(conn: &mut Connection, retention: &Retention) -> Result<(), anyhow::Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    rollup(conn, now)?;
+    let deleted = expire(conn, now, retention)?;
+    if deleted > 0 {
+        info!("SQLite 过期数据清理: 删除 {} 行", deleted);
+    }
+    Ok(())
+}
+
+fn take_snapshot() -> Snapshot {
+    let traffic_stats = crate::traffic::snapshot();
+
+    Snapshot {
+        total: (traffic_stats.total_packets, traffic_stats.total_bytes),
+        devices: traffic_stats
+            .device_stats
+            .iter()
+            .map(|(name, stats)| (name.clone(), (stats.packets, stats.bytes)))
+            .collect(),
+        ports: traffic_stats
+            .port_stats
+            .iter()
+            .map(|(port, stats)| (*port, (stats.packets, stats.bytes)))
+            .collect(),
+        connections: traffic_stats.device_connection_stats.clone(),
+    }
+}
+
+// 启动SQLite持久化任务，每个周期写入一次聚合统计，并在后台定期汇总和清理过期数据
+pub fn start(config: SqliteConfig, interval: Duration) -> Result<(), anyhow::Error> {
+    let conn = Arc::new(std::sync::Mutex::new(open(&config.path)?));
+    info!("统计数据持久化到 SQLite: {}", config.path.display());
+
+    let maintenance_conn = conn.clone();
+    let retention = config.retention;
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(MAINTENANCE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let conn = maintenance_conn.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+                maintain(&mut conn, &retention)
+            })
+            .await;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("SQLite 汇总清理失败: {}", e),
+                Err(e) => warn!("SQLite 汇总清理任务异常: {}", e),
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut previous: Option<(Instant, Snapshot)> = None;
+        loop {
+            ticker.tick().await;
+            let snapshot = take_snapshot();
+            let now = Instant::now();
+
+            if let Some((at, prev)) = &previous {
+                let aggregate =
+                    Aggregate::between(&snapshot, prev, now.duration_since(*at).as_secs_f64());
+                let conn = conn.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+                    write(&mut conn, &aggregate)
+                })
+                .await;
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("写入 SQLite 失败: {}", e),
+                    Err(e) => warn!("写入 SQLite 任务异常: {}", e),
+                }
+            }
+            previous = Some((now, snapshot));
+        }
+    });
+
+    Ok(())
+}
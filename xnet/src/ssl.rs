@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use aya::programs::UProbe;
+use aya::Ebpf;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+use xnet_common::{TlsConnKey, TlsConnStats};
+
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+
+// uprobe 挂载成功后置位，未启用时 /tls_stats 返回错误
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    // 已挂载 uprobe 的 libssl，热重载时挂载到同一个库
+    static ref SSL_LIB: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct TlsStatsQuery {
+    pub pid: Option<u32>,
+    // 进程名，不区分大小写
+    pub comm: Option<String>,
+    // 按明文总字节数从大到小排序后返回的连接数
+    pub limit: Option<usize>,
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// 在 libssl 的 SSL_set_fd、SSL_read、SSL_write 上挂载 uprobe，lib 可以是路径或 libssl.so.3 等库名
+pub async fn attach(ebpf_manager: &EbpfManager, lib: &Path) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    attach_probes(&mut ebpf, lib)?;
+    *SSL_LIB.lock().await = Some(lib.to_path_buf());
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("TLS uprobe 已挂载到 {}", lib.display());
+    Ok(())
+}
+
+// 热重载时把新的程序挂载到同一个库，旧程序随旧的 eBPF 实例释放
+pub async fn reattach(new: &mut Ebpf) -> Result<(), anyhow::Error> {
+    match SSL_LIB.lock().await.as_deref() {
+        Some(lib) => attach_probes(new, lib),
+        None => Ok(()),
+    }
+}
+
+fn attach_probes(ebpf: &mut Ebpf, lib: &Path) -> Result<(), anyhow::Error> {
+    let probes = [
+        ("xnet_ssl_set_fd", "SSL_set_fd"),
+        ("xnet_ssl_enter", "SSL_read"),
+        ("xnet_ssl_enter", "SSL_write"),
+        ("xnet_ssl_read_ret", "SSL_read"),
+        ("xnet_ssl_write_ret", "SSL_write"),
+    ];
+    for (program, symbol) in probes {
+        let uprobe: &mut UProbe = ebpf
+            .program_mut(program)
+            .ok_or_else(|| anyhow::anyhow!("{} program not found", program))?
+            .try_into()?;
+        uprobe
+            .attach(Some(symbol), 0, lib, None)
+            .map_err(|e| anyhow::anyhow!("failed to attach {} to {}: {}", program, symbol, e))?;
+    }
+    Ok(())
+}
+
+// /proc/net/tcp 中的地址，IPv4 地址按本机字节序输出为十六进制
+fn parse_proc_addr(addr: &str) -> Option<String> {
+    let (ip, port) = addr.split_once(':')?;
+    let ip = u32::from_str_radix(ip, 16).ok()?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    Some(format!("{}:{}", Ipv4Addr::from(u32::from_be(ip)), port))
+}
+
+// 把进程的 socket fd 解析为 (本地地址, 远端地址)，仅支持 IPv4 TCP
+fn socket_endpoints(tgid: u32, fd: i32) -> Option<(String, String)> {
+    let link = std::fs::read_link(format!("/proc/{}/fd/{}", tgid, fd)).ok()?;
+    let inode = link
+        .to_str()?
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .to_string();
+    let table = std::fs::read_to_string(format!("/proc/{}/net/tcp", tgid)).ok()?;
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(9) != Some(&inode.as_str()) {
+            return None;
+        }
+        Some((parse_proc_addr(fields[1])?, parse_proc_addr(fields[2])?))
+    })
+}
+
+fn comm_str(comm: &[u8; 16]) -> String {
+    let len = comm.iter().position(|&b| b == 0).unwrap_or(comm.len());
+    String::from_utf8_lossy(&comm[..len]).into_owned()
+}
+
+// 按进程和连接汇总 SSL_read/SSL_write 的明文字节数
+pub fn list(maps: &MapCache, query: &TlsStatsQuery) -> Result<Value, anyhow::Error> {
+    let map = AyaHashMap::<&MapData, TlsConnKey, TlsConnStats>::try_from(
+        maps.map("tls_conn_stats")
+            .ok_or_else(|| anyhow::anyhow!("tls_conn_stats map not found"))?,
+    )?;
+    let now = crate::conntrack::monotonic_now_ns();
+
+    let mut conns: Vec<(TlsConnKey, TlsConnStats)> = crate::batch::entries(&map)
+        .into_iter()
+        .filter(|(key, _)| query.pid.is_none_or(|pid| key.tgid == pid))
+        .filter(|(_, stats)| {
+            query
+                .comm
+                .as_deref()
+                .is_none_or(|comm| comm_str(&stats.comm).eq_ignore_ascii_case(comm))
+        })
+        .collect();
+    conns.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.read_bytes + stats.write_bytes));
+
+    // (进程名, 读字节, 写字节, 连接数)
+    let mut processes: BTreeMap<u32, (String, u64, u64, u64)> = BTreeMap::new();
+    for (key, stats) in &conns {
+        let process = processes
+            .entry(key.tgid)
+            .or_insert_with(|| (comm_str(&stats.comm), 0, 0, 0));
+        process.1 += stats.read_bytes;
+        process.2 += stats.write_bytes;
+        process.3 += 1;
+    }
+    let processes: Vec<Value> = processes
+        .into_iter()
+        .map(|(pid, (comm, read_bytes, write_bytes, connections))| {
+            serde_json::json!({
+                "pid": pid,
+                "comm": comm,
+                "read_bytes": read_bytes,
+                "write_bytes": write_bytes,
+                "connections": connections,
+            })
+        })
+        .collect();
+
+    if let Some(limit) = query.limit {
+        conns.truncate(limit);
+    }
+    let connections: Vec<Value> = conns
+        .iter()
+        .map(|(key, stats)| {
+            // 进程已退出或 fd 已关闭时无法解析地址
+            let endpoints = (stats.fd >= 0)
+                .then(|| socket_endpoints(key.tgid, stats.fd))
+                .flatten();
+            serde_json::json!({
+                "pid": key.tgid,
+                "comm": comm_str(&stats.comm),
+                "ssl": format!("{:#x}", key.ssl),
+                "fd": (stats.fd >= 0).then_some(stats.fd),
+                "local": endpoints.as_ref().map(|(local, _)| local),
+                "remote": endpoints.as_ref().map(|(_, remote)| remote),
+                "read_bytes": stats.read_bytes,
+                "write_bytes": stats.write_bytes,
+                "reads": stats.reads,
+                "writes": stats.writes,
+                "idle_secs": now.saturating_sub(stats.last_ns) / 1_000_000_000,
+            })
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "processes": processes,
+        "connections": connections,
+    }))
+}
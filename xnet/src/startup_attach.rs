@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use tokio::sync::Mutex;
+
+use crate::tc_attach::TcOptions;
+
+// 启动时在设备上挂载的程序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachPrograms {
+    // 流量统计 tc 程序
+    Tc,
+    // XDP 防火墙程序
+    Xdp,
+    Both,
+}
+
+impl AttachPrograms {
+    pub fn tc(self) -> bool {
+        matches!(self, AttachPrograms::Tc | AttachPrograms::Both)
+    }
+
+    pub fn xdp(self) -> bool {
+        matches!(self, AttachPrograms::Xdp | AttachPrograms::Both)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AttachSpec {
+    pub iface: String,
+    pub programs: AttachPrograms,
+}
+
+// 解析 --attach 参数，格式 iface[=tc|xdp|both]，不指定程序时只挂载流量统计
+pub fn parse_attach(s: &str) -> Result<AttachSpec, String> {
+    let invalid = || format!("invalid attach '{}', expected iface[=tc|xdp|both]", s);
+    let (iface, programs) = match s.split_once('=') {
+        Some((iface, programs)) => (iface.trim(), programs.trim()),
+        None => (s.trim(), "tc"),
+    };
+    if iface.is_empty() {
+        return Err(invalid());
+    }
+    let programs = match programs {
+        "tc" => AttachPrograms::Tc,
+        "xdp" => AttachPrograms::Xdp,
+        "both" => AttachPrograms::Both,
+        _ => return Err(invalid()),
+    };
+    Ok(AttachSpec {
+        iface: iface.to_string(),
+        programs,
+    })
+}
+
+// 校验设备存在，设备模式返回当前匹配的设备
+pub(crate) fn check(spec: &AttachSpec) -> Result<Vec<String>, String> {
+    if is_pattern(&spec.iface) {
+        Ok(expand(&spec.iface))
+    } else if std::path::Path::new(&format!("/sys/class/net/{}", spec.iface)).exists() {
+        Ok(vec![spec.iface.clone()])
+    } else {
+        Err(format!("Interface {} does not exist", spec.iface))
+    }
+}
+
+// 设备模式: * 匹配任意字符串，? 匹配单个字符，与 iptables 相同以 + 结尾时匹配该前缀的所有设备
+pub fn is_pattern(iface: &str) -> bool {
+    iface.contains(['*', '?']) || iface.ends_with('+')
+}
+
+pub fn matches(pattern: &str, name: &str) -> bool {
+    fn glob(pattern: &[u8], name: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((b'*', rest)) => (0..=name.len()).any(|i| glob(rest, &name[i..])),
+            Some((b'?', rest)) => !name.is_empty() && glob(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && glob(rest, &name[1..]),
+        }
+    }
+    match pattern.strip_suffix('+') {
+        Some(prefix) => name.starts_with(prefix),
+        None => glob(pattern.as_bytes(), name.as_bytes()),
+    }
+}
+
+// xnet 所在命名空间中的设备
+pub fn interfaces() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir("/sys/class/net")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+// 当前与设备模式匹配的设备
+pub fn expand(pattern: &str) -> Vec<String> {
+    interfaces()
+        .into_iter()
+        .filter(|name| matches(pattern, name))
+        .collect()
+}
+
+// 按设备模式挂载的程序，之后出现的匹配设备也会被挂载
+#[derive(Debug, Clone, Default)]
+pub struct PatternAttach {
+    // 设置时挂载流量统计程序，使用其中的 tc 选项
+    pub tc: Option<TcOptions>,
+    pub xdp: bool,
+}
+
+lazy_static::lazy_static! {
+    // 设备模式 -> 挂载的程序
+    static ref PATTERNS: Mutex<BTreeMap<String, PatternAttach>> = Mutex::new(BTreeMap::new());
+}
+
+// 登记设备模式，已登记的模式合并挂载的程序
+pub async fn add_pattern(pattern: &str, tc: Option<TcOptions>, xdp: bool) {
+    let mut patterns = PATTERNS.lock().await;
+    let entry = patterns.entry(pattern.to_string()).or_default();
+    if tc.is_some() {
+        entry.tc = tc;
+    }
+    entry.xdp |= xdp;
+}
+
+// 取消设备模式登记的程序，两类程序都取消后删除该模式
+pub async fn remove_pattern(pattern: &str, tc: bool, xdp: bool) {
+    let mut patterns = PATTERNS.lock().await;
+    if let Some(entry) = patterns.get_mut(pattern) {
+        if tc {
+            entry.tc = None;
+        }
+        if xdp {
+            entry.xdp = false;
+        }
+        if entry.tc.is_none() && !entry.xdp {
+            patterns.remove(pattern);
+        }
+    }
+}
+
+pub async fn patterns() -> BTreeMap<String, PatternAttach> {
+    PATTERNS.lock().await.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_attach_specs() {
+        let spec = parse_attach("eth0").unwrap();
+        assert_eq!(
+            (spec.iface.as_str(), spec.programs),
+            ("eth0", AttachPrograms::Tc)
+        );
+        let spec = parse_attach("eth1=both").unwrap();
+        assert!(spec.programs.tc() && spec.programs.xdp());
+        let spec = parse_attach("eth2=xdp").unwrap();
+        assert!(!spec.programs.tc() && spec.programs.xdp());
+        assert!(parse_attach("=tc").is_err());
+        assert!(parse_attach("eth0=lb").is_err());
+    }
+
+    #[test]
+    fn matches_interface_patterns() {
+        assert!(is_pattern("eth*") && is_pattern("veth+") && is_pattern("en?0"));
+        assert!(!is_pattern("eth0"));
+        assert!(matches("eth*", "eth0") && matches("eth*", "eth"));
+        assert!(!matches("eth*", "veth0"));
+        assert!(matches("en*s0", "enp3s0") && !matches("en*s0", "enp3s1"));
+        assert!(matches("veth+", "veth1a2b") && !matches("veth+", "eth0"));
+        assert!(matches("en?0", "enx0") && !matches("en?0", "en0"));
+    }
+}
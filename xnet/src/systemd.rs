@@ -0,0 +1,145 @@
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
+use crate::api::{AttachedInterfaces, XdpAttachment};
+use crate::xdp_attach::XdpMode;
+
+lazy_static::lazy_static! {
+    // 进程启动时间，心跳记录为相对该时间的毫秒数
+    static ref STARTED: Instant = Instant::now();
+}
+
+// 轮询任务最近一次完成的时间(相对 STARTED 的毫秒数)
+static LAST_POLL_MS: AtomicU64 = AtomicU64::new(0);
+
+// 向 $NOTIFY_SOCKET 发送状态，不是由 systemd 以 Type=notify 启动时什么也不做
+fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = (|| -> std::io::Result<()> {
+        let bytes = path.as_encoded_bytes();
+        // @ 开头为抽象命名空间地址
+        let addr = match bytes.strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        debug!("sd_notify {:?} 失败: {}", state, e);
+    }
+}
+
+// 看门狗超时时间，WATCHDOG_PID 指向其他进程时不启用
+fn watchdog_timeout() -> Option<Duration> {
+    if let Some(pid) = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+    {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+    std::env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+// 轮询任务每轮结束时调用，看门狗只在轮询任务正常运行时保活
+pub fn heartbeat() {
+    LAST_POLL_MS.store(STARTED.elapsed().as_millis() as u64, Ordering::Relaxed);
+}
+
+// 挂载状态摘要，例如 "tc: eth0/ingress eth0/egress; xdp: eth1 eth2/generic"
+fn status_line(attached: &AttachedInterfaces) -> String {
+    let traffic: Vec<String> = attached
+        .traffic
+        .iter()
+        .map(|tc| format!("{}/{}", tc.iface, tc.direction))
+        .collect();
+    // 只标出回退到通用模式的设备
+    let xdp = |attachments: &[XdpAttachment]| -> Vec<String> {
+        attachments
+            .iter()
+            .map(|xdp| match xdp.mode {
+                XdpMode::Native => xdp.iface.clone(),
+                XdpMode::Generic => format!("{}/generic", xdp.iface),
+            })
+            .collect()
+    };
+    let parts: Vec<String> = [
+        ("tc", traffic),
+        ("xdp", xdp(&attached.firewall)),
+        ("lb", xdp(&attached.lb)),
+        ("shaping", attached.shaping.clone()),
+    ]
+    .into_iter()
+    .filter(|(_, names)| !names.is_empty())
+    .map(|(label, names)| format!("{}: {}", label, names.join(" ")))
+    .collect();
+    if parts.is_empty() {
+        "running, nothing attached".to_string()
+    } else {
+        parts.join("; ")
+    }
+}
+
+// 程序加载、启动时的挂载和监听都完成后调用：通知 systemd 就绪，
+// 并按看门狗超时的一半发送保活，挂载状态变化时更新 STATUS
+pub async fn ready(poll_interval: Duration) {
+    heartbeat();
+    let mut status = status_line(&crate::server::attached_interfaces().await);
+    notify(&format!("READY=1\nSTATUS={}", status));
+
+    let watchdog = watchdog_timeout();
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    if let Some(timeout) = watchdog {
+        info!("systemd 看门狗已启用，超时 {:?}", timeout);
+    }
+    let tick = watchdog
+        .map(|timeout| (timeout / 2).min(poll_interval))
+        .unwrap_or(poll_interval);
+    // 轮询任务超过该时长没有完成一轮时停止保活，由 systemd 重启
+    let stall = poll_interval * 2 + watchdog.unwrap_or_default();
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(tick);
+        let mut stalled = false;
+        loop {
+            ticker.tick().await;
+            let since_poll = STARTED
+                .elapsed()
+                .saturating_sub(Duration::from_millis(LAST_POLL_MS.load(Ordering::Relaxed)));
+            if watchdog.is_some() {
+                if since_poll <= stall {
+                    notify("WATCHDOG=1");
+                    stalled = false;
+                } else if !stalled {
+                    warn!("轮询任务 {:?} 未完成，停止看门狗保活", since_poll);
+                    stalled = true;
+                }
+            }
+            let current = status_line(&crate::server::attached_interfaces().await);
+            if current != status {
+                notify(&format!("STATUS={}", current));
+                status = current;
+            }
+        }
+    });
+}
+
+// 退出前通知 systemd
+pub fn stopping() {
+    notify("STOPPING=1");
+}
@@ -0,0 +1,173 @@
+use aya::programs::tc::{self, NlOptions, SchedClassifierLinkId, TcAttachOptions};
+use aya::programs::{SchedClassifier, TcAttachType};
+use aya::util::KernelVersion;
+use tracing::info;
+
+// 流量统计 tc 程序的挂载选项，指定 priority 或 handle 时通过 netlink 挂载，否则优先使用 tcx
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct TcOptions {
+    // tc filter 的优先级，数值越小越先执行，为空时由内核分配
+    pub priority: Option<u16>,
+    // tc filter 的句柄，为空时由内核分配
+    pub handle: Option<u32>,
+}
+
+impl TcOptions {
+    fn netlink(&self) -> bool {
+        self.priority.is_some() || self.handle.is_some() || !tcx_supported()
+    }
+}
+
+// 流量统计程序挂载/卸载的方向，默认两个方向
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TcDirection {
+    #[default]
+    Both,
+    Ingress,
+    Egress,
+}
+
+impl TcDirection {
+    pub fn attach_types(self) -> Vec<TcAttachType> {
+        match self {
+            TcDirection::Both => vec![TcAttachType::Ingress, TcAttachType::Egress],
+            TcDirection::Ingress => vec![TcAttachType::Ingress],
+            TcDirection::Egress => vec![TcAttachType::Egress],
+        }
+    }
+}
+
+// 6.6 及以上的内核支持 tcx，不需要 clsact qdisc
+pub fn tcx_supported() -> bool {
+    KernelVersion::current()
+        .map(|v| v >= KernelVersion::new(6, 6, 0))
+        .unwrap_or(false)
+}
+
+pub fn direction(attach_type: TcAttachType) -> &'static str {
+    match attach_type {
+        TcAttachType::Egress => "egress",
+        _ => "ingress",
+    }
+}
+
+// 设备上没有 clsact qdisc 时创建
+pub fn ensure_clsact(iface: &str) -> Result<(), anyhow::Error> {
+    match tc::qdisc_add_clsact(iface) {
+        Ok(()) => {
+            info!(iface, "已在 {} 上创建 clsact qdisc", iface);
+            Ok(())
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+        Err(e) => Err(anyhow::anyhow!(
+            "failed to add clsact qdisc to {}: {}",
+            iface,
+            e
+        )),
+    }
+}
+
+// 挂载失败时，把由已有 tc 配置引起的错误转换为说明冲突原因的信息
+fn conflict(
+    e: &anyhow::Error,
+    iface: &str,
+    attach_type: TcAttachType,
+    options: &TcOptions,
+) -> Option<String> {
+    let errno = e
+        .chain()
+        .find_map(|e| e.downcast_ref::<std::io::Error>())
+        .and_then(|e| e.raw_os_error())?;
+    let dir = direction(attach_type);
+    let filter = format!(
+        "priority {} handle {}",
+        options
+            .priority
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "auto".to_string()),
+        options
+            .handle
+            .map(|h| format!("{:#x}", h))
+            .unwrap_or_else(|| "auto".to_string()),
+    );
+    match errno {
+        libc::EEXIST => Some(format!(
+            "{} on {} {} is already used by another filter (see `tc filter show dev {} {}`), choose a different priority or handle",
+            filter, iface, dir, iface, dir
+        )),
+        // 同一优先级上已有其他协议或类型的 filter
+        libc::EINVAL if options.priority.is_some() => Some(format!(
+            "{} on {} {} conflicts with an existing filter of another kind or protocol (see `tc filter show dev {} {}`), choose a different priority",
+            filter, iface, dir, iface, dir
+        )),
+        // 设备上已有 ingress qdisc 时无法创建 clsact
+        libc::ENOENT | libc::EINVAL => Some(format!(
+            "{} has no clsact qdisc for {} filters, an existing ingress qdisc may have to be replaced (`tc qdisc del dev {} ingress`)",
+            iface, dir, iface
+        )),
+        _ => None,
+    }
+}
+
+// 按选项挂载 tc 程序，通过 netlink 挂载前确保设备上存在 clsact qdisc，
+// 与设备上已有的 tc 配置冲突时返回说明冲突原因的错误
+pub fn attach(
+    program: &mut SchedClassifier,
+    iface: &str,
+    attach_type: TcAttachType,
+    options: &TcOptions,
+) -> Result<Result<SchedClassifierLinkId, String>, anyhow::Error> {
+    let result = if options.netlink() {
+        ensure_clsact(iface)?;
+        program.attach_with_options(
+            iface,
+            attach_type,
+            TcAttachOptions::Netlink(NlOptions {
+                priority: options.priority.unwrap_or(0),
+                handle: options.handle.unwrap_or(0),
+            }),
+        )
+    } else {
+        program.attach(iface, attach_type)
+    };
+    match result {
+        Ok(link_id) => Ok(Ok(link_id)),
+        Err(e) => {
+            let e = anyhow::Error::from(e);
+            match conflict(&e, iface, attach_type, options) {
+                Some(msg) => Ok(Err(msg)),
+                None => Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_filter_conflicts() {
+        let options = TcOptions {
+            priority: Some(1),
+            handle: Some(1),
+        };
+        let exists = anyhow::Error::from(std::io::Error::from_raw_os_error(libc::EEXIST));
+        let msg = conflict(&exists, "eth0", TcAttachType::Ingress, &options).unwrap();
+        assert!(msg.starts_with("priority 1 handle 0x1 on eth0 ingress is already used"));
+
+        let missing = anyhow::Error::from(std::io::Error::from_raw_os_error(libc::ENOENT));
+        let msg = conflict(
+            &missing,
+            "eth0",
+            TcAttachType::Egress,
+            &TcOptions::default(),
+        )
+        .unwrap();
+        assert!(msg.starts_with("eth0 has no clsact qdisc for egress filters"));
+
+        let other = anyhow::anyhow!("program not loaded");
+        assert!(conflict(&other, "eth0", TcAttachType::Egress, &options).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use aya::programs::TracePoint;
+use aya::Ebpf;
+use serde_json::Value;
+use tracing::{info, warn};
+use xnet_common::{SocketKey, TcpSockState, TcpStateOffsets};
+
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+use crate::sockops::state_name;
+
+// 内核 TCP 状态值，见 include/net/tcp_states.h
+const TCP_ESTABLISHED: u32 = 1;
+const TCP_SYN_SENT: u32 = 2;
+const TCP_SYN_RECV: u32 = 3;
+const TCP_CLOSE: u32 = 7;
+const TCP_CLOSE_WAIT: u32 = 8;
+const TCP_LISTEN: u32 = 10;
+
+// 跟踪点挂载成功后置位，未启用时 /tcp_states 返回错误
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct TcpStateQuery {
+    // 本地或远端端口
+    pub port: Option<u16>,
+    // TCP 状态名，如 close_wait
+    pub state: Option<String>,
+    // 按最近一次状态变化从新到旧排序后返回的条数
+    pub limit: Option<usize>,
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// 按内核 BTF 中 struct trace_event_raw_inet_sock_set_state 的布局确定跟踪点字段偏移
+fn btf_offsets() -> Result<TcpStateOffsets, anyhow::Error> {
+    let btf = crate::btf::Btf::from_sys_fs()?;
+    let members = btf
+        .struct_members("trace_event_raw_inet_sock_set_state")
+        .ok_or_else(|| anyhow::anyhow!("trace_event_raw_inet_sock_set_state not found"))?;
+    let offset = |name: &str| {
+        members
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("field {} not found", name))
+    };
+    Ok(TcpStateOffsets {
+        oldstate: offset("oldstate")?,
+        newstate: offset("newstate")?,
+        sport: offset("sport")?,
+        dport: offset("dport")?,
+        family: offset("family")?,
+        protocol: offset("protocol")?,
+        saddr: offset("saddr")?,
+        daddr: offset("daddr")?,
+    })
+}
+
+// 加载 eBPF 程序前调用，内核没有 BTF 时使用默认偏移
+pub fn offsets() -> TcpStateOffsets {
+    match btf_offsets() {
+        Ok(offsets) => offsets,
+        Err(e) => {
+            warn!("无法从内核 BTF 读取 TCP 状态跟踪点字段偏移，使用默认值: {}", e);
+            TcpStateOffsets::DEFAULT
+        }
+    }
+}
+
+// 进入 CLOSE 状态的原因，由变化前的状态判断
+fn close_reason(old_state: u32) -> &'static str {
+    match old_state {
+        // 握手超时或被拒绝
+        TCP_SYN_SENT => "connect_failed",
+        TCP_SYN_RECV => "handshake_failed",
+        // 未经 FIN 交换直接关闭: RST、keepalive 或重传超时、应用 abort
+        TCP_ESTABLISHED | TCP_CLOSE_WAIT => "aborted",
+        TCP_LISTEN => "listener_closed",
+        _ => "normal",
+    }
+}
+
+// 挂载 sock:inet_sock_set_state 跟踪点
+pub async fn attach(ebpf_manager: &EbpfManager) -> Result<(), anyhow::Error> {
+    let mut ebpf = ebpf_manager.ebpf.lock().await;
+    attach_program(&mut ebpf)?;
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("TCP 状态跟踪点 sock:inet_sock_set_state 已挂载");
+    Ok(())
+}
+
+fn attach_program(ebpf: &mut Ebpf) -> Result<(), anyhow::Error> {
+    let program: &mut TracePoint = ebpf
+        .program_mut("xnet_tcp_state")
+        .ok_or_else(|| anyhow::anyhow!("xnet_tcp_state program not found"))?
+        .try_into()?;
+    program.attach("sock", "inet_sock_set_state")?;
+    Ok(())
+}
+
+// 热重载时挂载新的程序，旧程序随旧的 eBPF 实例释放
+pub fn reattach(new: &mut Ebpf) -> Result<(), anyhow::Error> {
+    if !enabled() {
+        return Ok(());
+    }
+    attach_program(new)
+}
+
+// 状态变化计数和按原因汇总的关闭数
+fn transitions_json(maps: &MapCache) -> Result<(Vec<Value>, BTreeMap<&'static str, u64>), anyhow::Error> {
+    let map = AyaHashMap::<&MapData, u32, u64>::try_from(
+        maps.map("tcp_state_transitions")
+            .ok_or_else(|| anyhow::anyhow!("tcp_state_transitions map not found"))?,
+    )?;
+
+    let mut entries = crate::batch::entries(&map);
+    entries.sort_by_key(|(key, _)| *key);
+    let mut closes = BTreeMap::new();
+    let transitions = entries
+        .iter()
+        .map(|(key, count)| {
+            let (from, to) = (key >> 8, key & 0xff);
+            if to == TCP_CLOSE {
+                *closes.entry(close_reason(from)).or_default() += count;
+            }
+            serde_json::json!({
+                "from": state_name(from),
+                "to": state_name(to),
+                "count": count,
+            })
+        })
+        .collect();
+    Ok((transitions, closes))
+}
+
+// 列出内核记录的 TCP socket 状态和状态变化统计
+pub fn list(maps: &MapCache, query: &TcpStateQuery) -> Result<Value, anyhow::Error> {
+    let map = AyaHashMap::<&MapData, SocketKey, TcpSockState>::try_from(
+        maps.map("tcp_sock_states")
+            .ok_or_else(|| anyhow::anyhow!("tcp_sock_states map not found"))?,
+    )?;
+    let now = crate::conntrack::monotonic_now_ns();
+
+    let mut sockets: Vec<(SocketKey, TcpSockState)> = crate::batch::entries(&map)
+        .into_iter()
+        .filter(|(key, _)| {
+            query
+                .port
+                .is_none_or(|port| key.local_port == port || key.remote_port == port)
+        })
+        .filter(|(_, entry)| {
+            query
+                .state
+                .as_deref()
+                .is_none_or(|state| state_name(entry.state).eq_ignore_ascii_case(state))
+        })
+        .collect();
+    sockets.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.changed_ns));
+    if let Some(limit) = query.limit {
+        sockets.truncate(limit);
+    }
+
+    let sockets: Vec<Value> = sockets
+        .iter()
+        .map(|(key, entry)| {
+            serde_json::json!({
+                "local": format!("{}:{}", Ipv4Addr::from(u32::from_be(key.local_addr)), key.local_port),
+                "remote": format!("{}:{}", Ipv4Addr::from(u32::from_be(key.remote_addr)), key.remote_port),
+                "state": state_name(entry.state),
+                "previous_state": state_name(entry.old_state),
+                "close_reason": (entry.state == TCP_CLOSE).then(|| close_reason(entry.old_state)),
+                "transitions": entry.transitions,
+                "age_secs": now.saturating_sub(entry.opened_ns) / 1_000_000_000,
+                "since_change_secs": now.saturating_sub(entry.changed_ns) / 1_000_000_000,
+            })
+        })
+        .collect();
+
+    let (transitions, closes) = transitions_json(maps)?;
+    Ok(serde_json::json!({
+        "sockets": sockets,
+        "transitions": transitions,
+        "closes": closes,
+    }))
+}
@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+
+// HTTPS 证书和私钥(PEM)路径
+pub struct TlsOptions {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // 设置后要求客户端提供由该 CA 签发的证书(mTLS)
+    pub client_ca: Option<PathBuf>,
+}
+
+fn load_certs(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>, anyhow::Error> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow::anyhow!("failed to load certificates from {}: {}", path.display(), e))
+}
+
+// 构建 axum-server 使用的 rustls 配置
+pub async fn rustls_config(tls: &TlsOptions) -> Result<RustlsConfig, anyhow::Error> {
+    let Some(client_ca) = &tls.client_ca else {
+        return RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to load TLS certificate/key: {}", e));
+    };
+
+    let certs = load_certs(&tls.cert)?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key).map_err(|e| {
+        anyhow::anyhow!("failed to load private key from {}: {}", tls.key.display(), e)
+    })?;
+
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(client_ca)? {
+        roots.add(cert)?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+
+    let mut config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(RustlsConfig::from_config(Arc::new(config)))
+}
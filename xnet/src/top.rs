@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use hyper::client::HttpConnector;
+use hyper::Client;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table};
+use ratatui::Frame;
+use serde_json::Value;
+
+// xnet top 的参数
+#[derive(Debug, clap::Args)]
+pub struct TopArgs {
+    /// 本地 xnet API 地址
+    #[clap(long, default_value = "http://127.0.0.1:8080")]
+    url: String,
+    /// 刷新间隔（秒）
+    #[clap(long, default_value = "2")]
+    refresh_secs: u64,
+    /// 端口和连接列表显示的条数
+    #[clap(long, default_value = "10")]
+    limit: usize,
+}
+
+// 一次从 API 拉取的累计数据
+#[derive(Debug, Default)]
+struct Sample {
+    // 设备名_方向 -> 字节数
+    devices: HashMap<String, u64>,
+    // 端口 -> (包数, 字节数)
+    ports: HashMap<u16, (u64, u64)>,
+    connections: Vec<Value>,
+    alerts: Value,
+}
+
+// 界面展示用的速率
+#[derive(Debug, Default)]
+struct View {
+    // (设备, 方向, 字节/秒, 累计字节)
+    devices: Vec<(String, String, f64, u64)>,
+    // (端口, 字节/秒, 包/秒, 累计字节)
+    ports: Vec<(u16, f64, f64, u64)>,
+    connections: Vec<Value>,
+    alerts: Vec<String>,
+    error: Option<String>,
+}
+
+async fn get_json(client: &Client<HttpConnector>, url: &str) -> Result<Value, anyhow::Error> {
+    let response = client.get(url.parse()?).await?;
+    if !response.status().is_success() {
+        anyhow::bail!("GET {} returned {}", url, response.status());
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+async fn fetch(client: &Client<HttpConnector>, args: &TopArgs) -> Result<Sample, anyhow::Error> {
+    let base = args.url.trim_end_matches('/');
+    let devices = get_json(client, &format!("{}/api/v1/traffic_device_state", base)).await?;
+    let ports = get_json(client, &format!("{}/api/v1/traffic_port_stats", base)).await?;
+    let connections = get_json(
+        client,
+        &format!(
+            "{}/api/v1/traffic_device_connection_stats?sort=bytes_per_sec&limit={}",
+            base, args.limit
+        ),
+    )
+    .await?;
+    let alerts = get_json(client, &format!("{}/api/v1/alerts", base)).await?;
+
+    let mut sample = Sample {
+        alerts,
+        ..Default::default()
+    };
+    if let Some(devices) = devices.as_object() {
+        for (key, bytes) in devices {
+            sample
+                .devices
+                .insert(key.clone(), bytes.as_u64().unwrap_or(0));
+        }
+    }
+    for item in ports["items"].as_array().into_iter().flatten() {
+        if let Some(port) = item["port"].as_u64() {
+            sample.ports.insert(
+                port as u16,
+                (
+                    item["packets"].as_u64().unwrap_or(0),
+                    item["bytes"].as_u64().unwrap_or(0),
+                ),
+            );
+        }
+    }
+    sample.connections = connections["items"].as_array().cloned().unwrap_or_default();
+    Ok(sample)
+}
+
+// 根据前后两次采样计算速率
+fn build_view(
+    current: &Sample,
+    previous: Option<&(Instant, Sample)>,
+    now: Instant,
+    limit: usize,
+) -> View {
+    let elapsed = previous
+        .map(|(at, _)| now.duration_since(*at).as_secs_f64())
+        .unwrap_or(0.0);
+    let rate = |cur: u64, prev: Option<u64>| match prev {
+        Some(prev) if elapsed > 0.0 => cur.saturating_sub(prev) as f64 / elapsed,
+        _ => 0.0,
+    };
+
+    let mut view = View::default();
+    for (key, bytes) in current.devices.iter() {
+        let (device, direction) = key.rsplit_once('_').unwrap_or((key.as_str(), ""));
+        let prev = previous.and_then(|(_, p)| p.devices.get(key).copied());
+        view.devices.push((
+            device.to_string(),
+            direction.to_string(),
+            rate(*bytes, prev),
+            *bytes,
+        ));
+    }
+    view.devices.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    for (port, (packets, bytes)) in current.ports.iter() {
+        let prev = previous.and_then(|(_, p)| p.ports.get(port).copied());
+        view.ports.push((
+            *port,
+            rate(*bytes, prev.map(|p| p.1)),
+            rate(*packets, prev.map(|p| p.0)),
+            *bytes,
+        ));
+    }
+    view.ports
+        .sort_by(|a, b| b.1.total_cmp(&a.1).then(b.3.cmp(&a.3)));
+    view.ports.truncate(limit);
+
+    view.connections = current.connections.clone();
+
+    for rule in current.alerts["rules"].as_array().into_iter().flatten() {
+        let state = rule["state"].as_str().unwrap_or("");
+        if state == "firing" || state == "pending" {
+            view.alerts.push(format!(
+                "[{}] {} value={:.2} threshold={}",
+                state,
+                rule["rule"]["name"].as_str().unwrap_or(""),
+                rule["value"].as_f64().unwrap_or(0.0),
+                rule["rule"]["threshold"]
+            ));
+        }
+    }
+    for anomaly in current.alerts["anomalies"]["active"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        view.alerts.push(format!(
+            "[anomaly] {} {} baseline={}/s",
+            anomaly["key"].as_str().unwrap_or(""),
+            anomaly["kind"].as_str().unwrap_or(""),
+            format_bytes(anomaly["baseline"].as_f64().unwrap_or(0.0))
+        ));
+    }
+    view
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn draw(frame: &mut Frame, args: &TopArgs, view: &View) {
+    let [header, devices, ports, connections, alerts] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(view.devices.len() as u16 + 3),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+        Constraint::Length(view.alerts.len().clamp(1, 5) as u16 + 2),
+    ])
+    .areas(frame.area());
+
+    let status = match &view.error {
+        Some(e) => Line::styled(
+            format!("xnet top - {} - {}", args.url, e),
+            Style::new().fg(Color::Red),
+        ),
+        None => Line::from(format!(
+            "xnet top - {} - refresh {}s - press q to quit",
+            args.url, args.refresh_secs
+        )),
+    };
+    frame.render_widget(Paragraph::new(status), header);
+
+    let bold = Style::new().add_modifier(Modifier::BOLD);
+
+    let rows = view.devices.iter().map(|(device, direction, rate, total)| {
+        Row::new(vec![
+            device.clone(),
+            direction.clone(),
+            format!("{}/s", format_bytes(*rate)),
+            format_bytes(*total as f64),
+        ])
+    });
+    let table = Table::new(rows, [Constraint::Fill(1); 4])
+        .header(Row::new(vec!["Device", "Direction", "Rate", "Total"]).style(bold))
+        .block(Block::bordered().title("Devices"));
+    frame.render_widget(table, devices);
+
+    let rows = view.ports.iter().map(|(port, rate, packets, total)| {
+        Row::new(vec![
+            port.to_string(),
+            format!("{}/s", format_bytes(*rate)),
+            format!("{:.0} pkt/s", packets),
+            format_bytes(*total as f64),
+        ])
+    });
+    let table = Table::new(rows, [Constraint::Fill(1); 4])
+        .header(Row::new(vec!["Port", "Rate", "Packets", "Total"]).style(bold))
+        .block(Block::bordered().title("Top ports"));
+    frame.render_widget(table, ports);
+
+    let rows = view.connections.iter().map(|c| {
+        Row::new(vec![
+            c["device_id"].to_string(),
+            format!("{} -> {}", c["src_port"], c["dst_port"]),
+            format!(
+                "{} {}",
+                c["protocol"].as_str().unwrap_or(""),
+                c["direction"].as_str().unwrap_or("")
+            ),
+            format!(
+                "{}/s",
+                format_bytes(c["throughput"]["bytes_per_sec"].as_f64().unwrap_or(0.0))
+            ),
+            format_bytes(c["total_bytes"].as_f64().unwrap_or(0.0)),
+            format!(
+                "{:.2}%",
+                c["retransmission_rate"].as_f64().unwrap_or(0.0) * 100.0
+            ),
+        ])
+    });
+    let table = Table::new(rows, [Constraint::Fill(1); 6])
+        .header(
+            Row::new(vec![
+                "Device", "Ports", "Protocol", "Rate", "Total", "Retrans",
+            ])
+            .style(bold),
+        )
+        .block(Block::bordered().title("Top connections"));
+    frame.render_widget(table, connections);
+
+    let items: Vec<ListItem> = if view.alerts.is_empty() {
+        vec![ListItem::new("no active alerts")]
+    } else {
+        view.alerts
+            .iter()
+            .map(|a| ListItem::new(a.as_str()).style(Style::new().fg(Color::Yellow)))
+            .collect()
+    };
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("Alerts")),
+        alerts,
+    );
+}
+
+// 运行终端界面，按 q 或 Esc 退出
+pub async fn run(args: TopArgs) -> Result<(), anyhow::Error> {
+    let client = Client::new();
+    let refresh = Duration::from_secs(args.refresh_secs.max(1));
+    let mut terminal = ratatui::init();
+    let mut previous: Option<(Instant, Sample)> = None;
+    let mut view = View::default();
+    let mut next_refresh = Instant::now();
+
+    let result = loop {
+        let now = Instant::now();
+        if now >= next_refresh {
+            next_refresh = now + refresh;
+            match fetch(&client, &args).await {
+                Ok(sample) => {
+                    view = build_view(&sample, previous.as_ref(), now, args.limit);
+                    previous = Some((now, sample));
+                }
+                Err(e) => view.error = Some(e.to_string()),
+            }
+        }
+        if let Err(e) = terminal.draw(|frame| draw(frame, &args, &view)) {
+            break Err(e.into());
+        }
+
+        match event::poll(Duration::ZERO) {
+            Ok(true) => match event::read() {
+                Ok(Event::Key(key))
+                    if key.kind == KeyEventKind::Press
+                        && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) =>
+                {
+                    break Ok(());
+                }
+                Ok(_) => continue,
+                Err(e) => break Err(e.into()),
+            },
+            Ok(false) => {}
+            Err(e) => break Err(e.into()),
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+
+    ratatui::restore();
+    result
+}
@@ -1,38 +1,390 @@
-use aya::maps::HashMap as AyaHashMap;
-use aya::maps::MapData;
 use lazy_static::lazy_static;
-use log::debug;
-use log::info;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::Ipv4Addr;
-use std::time::Instant;
-use tokio::sync::Mutex;
-use xnet_common::{DeviceStats, PortStats, DeviceConnectionStats};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+use xnet_common::{DeviceCastStats, DeviceStats, DeviceStatsKey, PortStats, DeviceConnectionStats, FlowTuple, Icmpv6Stats, ProtocolStats, FLOW_FAMILY_V4};
 
 use serde_json::Map as JsonMap;
 use serde_json::Value;
 
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+use crate::source::StatsSource;
+
+#[derive(Clone)]
 pub struct ConnectionInfo {
-    pub src_ip: u32,
-    pub dst_ip: u32,
-    pub src_port: u16,
-    pub dst_port: u16,
+    pub flow: FlowTuple,
     pub status: u32,
     pub bytes: u64,
     pub last_seen: Instant,
 }
 
+// 把 eBPF 中用 bpf_ktime_get_ns 记录的时间转换为 unix 时间戳(秒)和空闲时长(秒)，0 表示没有记录
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    offset_ns: u64,
+    now_ns: u64,
+}
+
+impl Clock {
+    pub fn now() -> Self {
+        Self {
+            offset_ns: crate::capture::monotonic_to_realtime_offset_ns(),
+            now_ns: crate::conntrack::monotonic_now_ns(),
+        }
+    }
+
+    pub fn unix_secs(&self, ktime_ns: u64) -> Option<u64> {
+        (ktime_ns != 0).then(|| (ktime_ns + self.offset_ns) / 1_000_000_000)
+    }
+
+    pub fn idle_secs(&self, ktime_ns: u64) -> Option<u64> {
+        (ktime_ns != 0).then(|| self.now_ns.saturating_sub(ktime_ns) / 1_000_000_000)
+    }
+}
+
+// 统计吞吐的最小间隔，刷新过于频繁时沿用上一个周期的结果
+const RATE_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+// 最近一个统计周期内的包数和字节增量
+#[derive(Debug, Clone, Copy)]
+pub struct FlowRate {
+    pub prev_packets: u64,
+    pub prev_bytes: u64,
+    pub prev_at: Instant,
+    pub packets_delta: u64,
+    pub bytes_delta: u64,
+    pub interval_secs: f64,
+}
+
+impl FlowRate {
+    fn new(packets: u64, bytes: u64, now: Instant) -> Self {
+        Self {
+            prev_packets: packets,
+            prev_bytes: bytes,
+            prev_at: now,
+            packets_delta: 0,
+            bytes_delta: 0,
+            interval_secs: 0.0,
+        }
+    }
+
+    // 距上次采样超过最小间隔时，计算增量并滚动基线
+    fn update(&mut self, packets: u64, bytes: u64, now: Instant) {
+        let elapsed = now.duration_since(self.prev_at);
+        if elapsed < RATE_MIN_INTERVAL {
+            return;
+        }
+        self.packets_delta = packets.saturating_sub(self.prev_packets);
+        self.bytes_delta = bytes.saturating_sub(self.prev_bytes);
+        self.interval_secs = elapsed.as_secs_f64();
+        self.prev_packets = packets;
+        self.prev_bytes = bytes;
+        self.prev_at = now;
+    }
+
+    pub fn packets_per_sec(&self) -> f64 {
+        if self.interval_secs > 0.0 {
+            self.packets_delta as f64 / self.interval_secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        if self.interval_secs > 0.0 {
+            self.bytes_delta as f64 / self.interval_secs
+        } else {
+            0.0
+        }
+    }
+}
+
+// 更新 key 对应的速率，首次出现时只记录基线
+fn track_rate<K: std::hash::Hash + Eq>(
+    rates: &mut HashMap<K, FlowRate>,
+    key: K,
+    packets: u64,
+    bytes: u64,
+    now: Instant,
+) {
+    rates
+        .entry(key)
+        .and_modify(|rate| rate.update(packets, bytes, now))
+        .or_insert_with(|| FlowRate::new(packets, bytes, now));
+}
+
+// (包/秒, 字节/秒)，没有速率数据时为 0
+fn rates_of(rate: Option<&FlowRate>) -> (f64, f64) {
+    rate.map(|r| (r.packets_per_sec(), r.bytes_per_sec()))
+        .unwrap_or((0.0, 0.0))
+}
+
+// 统计列表的排序字段
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Bytes,
+    Packets,
+    Port,
+    Retransmissions,
+    BytesPerSec,
+    Timestamp,
+    // 连接持续时间，只用于连接统计
+    Duration,
+    // 双向字节数的不对称程度，只用于双向流统计
+    Asymmetry,
+}
+
+impl SortKey {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SortKey::Bytes => "bytes",
+            SortKey::Packets => "packets",
+            SortKey::Port => "port",
+            SortKey::Retransmissions => "retransmissions",
+            SortKey::BytesPerSec => "bytes_per_sec",
+            SortKey::Timestamp => "timestamp",
+            SortKey::Duration => "duration",
+            SortKey::Asymmetry => "asymmetry",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn apply(self, ordering: std::cmp::Ordering) -> std::cmp::Ordering {
+        match self {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+// 按最后一个包的时间区分连接
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    // older_than 秒内没有包
+    Idle,
+    // older_than 秒内有包
+    Active,
+}
+
+// 不指定 older_than 时判断空闲的秒数
+const DEFAULT_IDLE_SECS: u64 = 300;
+
+// 连接/端口统计的查询参数，例如 ?port=443&protocol=tcp&min_bytes=1024&sort=bytes&order=desc&limit=100&offset=0
+// 连接统计还支持 ?state=idle&older_than=300，双向流统计还支持 ?min_asymmetry=0.9&one_way=true
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct StatsQuery {
+    // 匹配源端口或目的端口
+    pub port: Option<u16>,
+    // tcp/udp，不区分大小写
+    pub protocol: Option<String>,
+    pub min_bytes: Option<u64>,
+    pub state: Option<ConnectionState>,
+    // 只指定 older_than 时等同于 state=idle
+    pub older_than: Option<u64>,
+    // 不对称程度的绝对值下限，0-1
+    pub min_asymmetry: Option<f64>,
+    // 只看单向或只看双向都有包的流
+    pub one_way: Option<bool>,
+    // 不指定时按key升序
+    pub sort: Option<SortKey>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+}
+
+impl StatsQuery {
+    // 连接最后一个包距今 idle_secs 秒时是否满足 state/older_than 条件
+    fn state_matches(&self, idle_secs: Option<u64>) -> bool {
+        let state = match (self.state, self.older_than) {
+            (Some(state), _) => state,
+            (None, Some(_)) => ConnectionState::Idle,
+            (None, None) => return true,
+        };
+        let idle = idle_secs.unwrap_or(0) >= self.older_than.unwrap_or(DEFAULT_IDLE_SECS);
+        idle == (state == ConnectionState::Idle)
+    }
+
+    // 分页并返回 {total, offset, limit, items}，total 为过滤后的总数
+    fn page<T>(&self, rows: Vec<T>, to_json: impl Fn(T) -> Value) -> Value {
+        let total = rows.len();
+        let items: Vec<Value> = rows
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(to_json)
+            .collect();
+        serde_json::json!({
+            "total": total,
+            "offset": self.offset,
+            "limit": self.limit,
+            "items": items
+        })
+    }
+}
+
+#[derive(Clone)]
 pub struct TrafficStats {
     pub ip_stats: HashMap<u32, u64>,
     pub connections: HashMap<u64, ConnectionInfo>,
     pub last_update: Instant,
     pub port_stats: HashMap<u16, PortStats>,
     pub device_stats: HashMap<String, DeviceStats>,
+    // 与 device_stats 的 key 相同
+    pub device_cast_stats: HashMap<String, DeviceCastStats>,
+    // key 为 device_stats 的 key 和 IP 协议号
+    pub protocol_stats: HashMap<(String, u8), ProtocolStats>,
+    // 与 device_stats 的 key 相同
+    pub icmpv6_stats: HashMap<String, Icmpv6Stats>,
     pub device_connection_stats: HashMap<u32, DeviceConnectionStats>,
+    pub connection_rates: HashMap<u32, FlowRate>,
+    pub port_rates: HashMap<u16, FlowRate>,
+    pub device_rates: HashMap<String, FlowRate>,
+    pub total_rate: Option<FlowRate>,
     pub total_packets: u64,
     pub total_bytes: u64,
 }
 
+// 双向流的key，local 为设备一侧的地址和端口，remote 为对端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct BiFlowKey {
+    netns_cookie: u64,
+    device_id: u32,
+    family: u8,
+    protocol: u8,
+    local: ([u8; 16], u16),
+    remote: ([u8; 16], u16),
+}
+
+// 同一设备上同一连接两个方向的统计，rx 为 ingress，tx 为 egress
+#[derive(Default)]
+struct BiFlow<'a> {
+    rx: Option<&'a DeviceConnectionStats>,
+    tx: Option<&'a DeviceConnectionStats>,
+}
+
+impl BiFlow<'_> {
+    fn rx_bytes(&self) -> u64 {
+        self.rx.map_or(0, |s| s.total_bytes)
+    }
+
+    fn tx_bytes(&self) -> u64 {
+        self.tx.map_or(0, |s| s.total_bytes)
+    }
+
+    fn packets(&self) -> u64 {
+        self.rx.map_or(0, |s| s.total_packets) + self.tx.map_or(0, |s| s.total_packets)
+    }
+
+    // (tx - rx) / (tx + rx)，1 表示只有设备一侧在发送，-1 表示只有对端在发送
+    fn asymmetry(&self) -> f64 {
+        let (rx, tx) = (self.rx_bytes() as f64, self.tx_bytes() as f64);
+        if rx + tx > 0.0 {
+            (tx - rx) / (rx + tx)
+        } else {
+            0.0
+        }
+    }
+
+    // 只在一个方向上看到过包
+    fn one_way(&self) -> bool {
+        self.rx.is_none() || self.tx.is_none()
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.rx.map_or(0, |s| s.timestamp).max(self.tx.map_or(0, |s| s.timestamp))
+    }
+
+    // 只有 egress 的包能取到本机 socket，两个方向都没有时为0
+    fn socket_cookie(&self) -> u64 {
+        [self.tx, self.rx]
+            .into_iter()
+            .flatten()
+            .map(|s| s.socket_cookie)
+            .find(|&cookie| cookie != 0)
+            .unwrap_or(0)
+    }
+
+    fn duration_secs(&self) -> Option<u64> {
+        let first_seen = [self.rx, self.tx]
+            .into_iter()
+            .flatten()
+            .map(|s| s.first_seen)
+            .filter(|&t| t != 0)
+            .min()?;
+        Some(self.timestamp().saturating_sub(first_seen) / 1_000_000_000)
+    }
+}
+
+fn bi_flow_json(key: &BiFlowKey, flow: &BiFlow, clock: &Clock) -> Value {
+    // 按设备一侧发出的方向解析地址
+    let tuple = FlowTuple {
+        saddr: key.local.0,
+        daddr: key.remote.0,
+        src_port: key.local.1,
+        dst_port: key.remote.1,
+        family: key.family,
+        protocol: key.protocol,
+        reserved: 0,
+    };
+    serde_json::json!({
+        "device_id": key.device_id,
+        "netns": crate::netns::inode_of(key.netns_cookie),
+        "family": family_name(key.family),
+        "protocol": protocol_name(key.protocol as u32),
+        "local_ip": tuple.src_ip().to_string(),
+        "local_port": key.local.1,
+        "remote_ip": tuple.dst_ip().to_string(),
+        "remote_port": key.remote.1,
+        "rx_packets": flow.rx.map_or(0, |s| s.total_packets),
+        "rx_bytes": flow.rx_bytes(),
+        "tx_packets": flow.tx.map_or(0, |s| s.total_packets),
+        "tx_bytes": flow.tx_bytes(),
+        "asymmetry": flow.asymmetry(),
+        "one_way": flow.one_way(),
+        "socket_cookie": flow.socket_cookie(),
+        "timestamp": clock.unix_secs(flow.timestamp()),
+        "idle_secs": clock.idle_secs(flow.timestamp()),
+        "duration_secs": flow.duration_secs(),
+    })
+}
+
+// 连接统计中设备一侧和对端的 (地址, 端口)
+fn endpoints(stats: &DeviceConnectionStats) -> (([u8; 16], u16), ([u8; 16], u16)) {
+    let flow = &stats.flow;
+    if stats.direction == 0 {
+        ((flow.daddr, flow.dst_port), (flow.saddr, flow.src_port))
+    } else {
+        ((flow.saddr, flow.src_port), (flow.daddr, flow.dst_port))
+    }
+}
+
+// /traffic_fanout 的查询参数，例如 ?window_secs=300&min_count=10&limit=50
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct FanoutQuery {
+    // 只统计最近 window_secs 秒内有包的连接，默认300
+    pub window_secs: Option<u64>,
+    // 只返回计数不少于 min_count 的端点
+    pub min_count: Option<usize>,
+    // 每个列表返回的端点数，默认100
+    pub limit: Option<usize>,
+}
+
 impl TrafficStats {
     pub fn new() -> Self {
         Self {
@@ -41,98 +393,75 @@ impl TrafficStats {
             last_update: Instant::now(),
             port_stats: HashMap::new(),
             device_stats: HashMap::new(),
+            device_cast_stats: HashMap::new(),
+            protocol_stats: HashMap::new(),
+            icmpv6_stats: HashMap::new(),
             device_connection_stats: HashMap::new(),
+            connection_rates: HashMap::new(),
+            port_rates: HashMap::new(),
+            device_rates: HashMap::new(),
+            total_rate: None,
             total_packets: 0,
             total_bytes: 0,
         }
     }
 
-    pub fn update_from_ebpf(&mut self, ebpf: &aya::Ebpf) {
+    pub fn update_from_maps(&mut self, maps: &MapCache) {
+        self.update_from(&maps.traffic);
+    }
+
+    // 从统计数据源读取各 map 的当前值并更新速率
+    pub fn update_from(&mut self, source: &impl StatsSource) {
+        let now = Instant::now();
+
         // 读取总统计信息
-        if let Some(total_stats) = ebpf.map("total_stats") {
-            if let Ok(total_stats_map) = AyaHashMap::<&MapData, u32, u64>::try_from(&*total_stats) {
-                if let Ok(total_packets) = total_stats_map.get(&0, 0) {
-                    self.total_packets = total_packets;
-                }
-                if let Ok(total_bytes) = total_stats_map.get(&1, 0) {
-                    self.total_bytes = total_bytes;
-                }
+        if let Some((total_packets, total_bytes)) = source.total_stats() {
+            self.total_packets = total_packets;
+            self.total_bytes = total_bytes;
+            match self.total_rate.as_mut() {
+                Some(rate) => rate.update(self.total_packets, self.total_bytes, now),
+                None => self.total_rate = Some(FlowRate::new(self.total_packets, self.total_bytes, now)),
             }
         }
 
-        // 读取端口统计信息
-        if let Some(port_stats) = ebpf.map("port_stats") {
-            if let Ok(port_stats_map) =
-                AyaHashMap::<&MapData, u16, PortStats>::try_from(&*port_stats)
-            {
-                // 遍历所有端口统计
-                for port in 0..u16::MAX {
-                    match port_stats_map.get(&port, 0) {
-                        Ok(stats) if stats.packets > 0 => {
-                            self.port_stats.insert(port, stats);
-                        }
-                        _ => {}
-                    }
-                }
+        // 读取端口统计信息，只保留有流量的端口
+        for (port, stats) in source.port_stats() {
+            if stats.packets > 0 {
+                self.port_stats.insert(port, stats);
+                track_rate(&mut self.port_rates, port, stats.packets, stats.bytes, now);
             }
         }
 
         // 读取设备统计信息
-        if let Some(device_stats) = ebpf.map("device_stats") {
-            if let Ok(device_stats_map) =
-                AyaHashMap::<&MapData, u32, DeviceStats>::try_from(&*device_stats)
-            {
-                // 遍历所有设备统计
-                for key in 0..1024 {
-                    match device_stats_map.get(&key, 0) {
-                        Ok(stats) if stats.packets > 0 => {
-                            // 根据key生成设备名称和方向
-                            let device_id = key / 2;
-                            let is_ingress = key % 2 == 0;
-                            let direction = if is_ingress { "ingress" } else { "egress" };
-
-                            // 从内存中的设备映射获取真实的设备名称
-                            let device_name = {
-                                use crate::server::DEVICE_MAPPINGS;
-                                let device_mappings = DEVICE_MAPPINGS.try_lock();
-                                let mut found_name = format!("device{}", device_id);
-
-                                if let Ok(mappings) = device_mappings {
-                                    for (name, &id) in mappings.iter() {
-                                        if id == device_id {
-                                            found_name = name.clone();
-                                            break;
-                                        }
-                                    }
-                                }
-                                found_name
-                            };
-
-                            let device_key = format!("{}_{}", device_name, direction);
-                            self.device_stats.insert(device_key, stats);
-                        }
-                        _ => {}
-                    }
-                }
+        for (key, stats) in source.device_stats() {
+            if stats.packets > 0 {
+                let device_key = device_stats_key(&key);
+                track_rate(&mut self.device_rates, device_key.clone(), stats.packets, stats.bytes, now);
+                self.device_stats.insert(device_key, stats);
             }
         }
 
-        // 读取设备连接统计信息
-        if let Some(device_connection_stats) = ebpf.map("device_connection_stats") {
-            if let Ok(device_connection_stats_map) =
-                AyaHashMap::<&MapData, u32, DeviceConnectionStats>::try_from(&*device_connection_stats)
-            {
-
-                debug!("device_connection_stats_map: {:?}", device_connection_stats_map);
-                // 遍历所有设备连接统计
-                for key in 0..1024 {
-                    match device_connection_stats_map.get(&key, 0) {
-                        Ok(stats) if stats.total_packets > 0 => {
-                            self.device_connection_stats.insert(key, stats);
-                        }
-                        _ => {}
-                    }
-                }
+        // 读取设备单播、广播、组播统计
+        for (key, stats) in source.device_cast_stats() {
+            self.device_cast_stats.insert(device_stats_key(&key), stats);
+        }
+
+        // 读取按协议区分的设备流量
+        for (key, stats) in source.protocol_stats() {
+            self.protocol_stats
+                .insert((device_stats_key(&key.device), key.protocol as u8), stats);
+        }
+
+        // 读取按类型区分的 ICMPv6 包数
+        for (key, stats) in source.icmpv6_stats() {
+            self.icmpv6_stats.insert(device_stats_key(&key), stats);
+        }
+
+        // 读取设备连接统计信息，key为连接哈希，不限于固定范围
+        for (key, stats) in source.device_connection_stats() {
+            if stats.total_packets > 0 {
+                self.device_connection_stats.insert(key, stats);
+                track_rate(&mut self.connection_rates, key, stats.total_packets, stats.total_bytes, now);
             }
         }
     }
@@ -158,39 +487,447 @@ impl TrafficStats {
         map
     }
 
-    // 输出设备连接统计
+    // 设备统计按行输出，key 拆分为设备名和方向
+    // packets/bytes 只包含TCP和UDP，unicast/broadcast/multicast 包含所有协议
+    pub fn device_rows(&self) -> Vec<Value> {
+        let mut keys: Vec<&String> = self
+            .device_stats
+            .keys()
+            .chain(self.device_cast_stats.keys().filter(|key| !self.device_stats.contains_key(*key)))
+            .collect();
+        keys.sort();
+        let clock = Clock::now();
+        keys.into_iter()
+            .map(|key| {
+                let (device, direction) = key.rsplit_once('_').unwrap_or((key.as_str(), ""));
+                let (packets_per_sec, bytes_per_sec) = rates_of(self.device_rates.get(key));
+                let netns = device_netns(device);
+                let stats = self.device_stats.get(key);
+                let cast = self.device_cast_stats.get(key);
+                serde_json::json!({
+                    "device": device,
+                    "direction": direction,
+                    "netns": netns,
+                    "packets": stats.map(|s| s.packets).unwrap_or(0),
+                    "bytes": stats.map(|s| s.bytes).unwrap_or(0),
+                    "last_seen": stats.and_then(|s| clock.unix_secs(s.last_seen)),
+                    "idle_secs": stats.and_then(|s| clock.idle_secs(s.last_seen)),
+                    "packets_per_sec": packets_per_sec,
+                    "bytes_per_sec": bytes_per_sec,
+                    "unicast": {
+                        "packets": cast.map(|c| c.unicast_packets).unwrap_or(0),
+                        "bytes": cast.map(|c| c.unicast_bytes).unwrap_or(0)
+                    },
+                    "broadcast": {
+                        "packets": cast.map(|c| c.broadcast_packets).unwrap_or(0),
+                        "bytes": cast.map(|c| c.broadcast_bytes).unwrap_or(0)
+                    },
+                    "multicast": {
+                        "packets": cast.map(|c| c.multicast_packets).unwrap_or(0),
+                        "bytes": cast.map(|c| c.multicast_bytes).unwrap_or(0)
+                    }
+                })
+            })
+            .collect()
+    }
+
+    // 每个设备和方向的 ICMPv6 类型计数，rs/ra 为路由器发现，ns/na 为邻居发现
+    pub fn icmpv6_rows(&self, device: Option<&str>) -> Vec<Value> {
+        let mut keys: Vec<&String> = self.icmpv6_stats.keys().collect();
+        keys.sort();
+        keys.into_iter()
+            .filter_map(|key| {
+                let (name, direction) = key.rsplit_once('_').unwrap_or((key.as_str(), ""));
+                if device.is_some_and(|device| device != name) {
+                    return None;
+                }
+                let stats = &self.icmpv6_stats[key];
+                Some(serde_json::json!({
+                    "device": name,
+                    "direction": direction,
+                    "echo_request": stats.echo_request,
+                    "echo_reply": stats.echo_reply,
+                    "router_solicit": stats.router_solicit,
+                    "router_advert": stats.router_advert,
+                    "neighbor_solicit": stats.neighbor_solicit,
+                    "neighbor_advert": stats.neighbor_advert,
+                    "redirect": stats.redirect,
+                    "other": stats.other,
+                }))
+            })
+            .collect()
+    }
+
+    // 按 IP 协议汇总的流量构成，以及每个设备和方向的明细，share 为字节占比
+    pub fn protocol_breakdown(&self, device: Option<&str>) -> Value {
+        let rows = |stats: &BTreeMap<u8, (u64, u64)>| -> Vec<Value> {
+            let total: u64 = stats.values().map(|(_, bytes)| bytes).sum();
+            let mut rows: Vec<(&u8, &(u64, u64))> = stats.iter().collect();
+            rows.sort_by_key(|(_, (_, bytes))| std::cmp::Reverse(*bytes));
+            rows.into_iter()
+                .map(|(protocol, (packets, bytes))| {
+                    serde_json::json!({
+                        "protocol": protocol,
+                        "name": ip_protocol_name(*protocol),
+                        "packets": packets,
+                        "bytes": bytes,
+                        "share": if total > 0 { *bytes as f64 / total as f64 } else { 0.0 },
+                    })
+                })
+                .collect()
+        };
+
+        let mut total: BTreeMap<u8, (u64, u64)> = BTreeMap::new();
+        let mut devices: BTreeMap<&str, BTreeMap<u8, (u64, u64)>> = BTreeMap::new();
+        for ((key, protocol), stats) in &self.protocol_stats {
+            let name = key.rsplit_once('_').map(|(name, _)| name).unwrap_or(key);
+            if device.is_some_and(|device| device != name) {
+                continue;
+            }
+            for entry in [
+                total.entry(*protocol).or_default(),
+                devices.entry(key).or_default().entry(*protocol).or_default(),
+            ] {
+                entry.0 += stats.packets;
+                entry.1 += stats.bytes;
+            }
+        }
+
+        let devices: Vec<Value> = devices
+            .iter()
+            .map(|(key, stats)| {
+                let (device, direction) = key.rsplit_once('_').unwrap_or((key, ""));
+                serde_json::json!({
+                    "device": device,
+                    "direction": direction,
+                    "protocols": rows(stats),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "total": rows(&total),
+            "devices": devices,
+        })
+    }
+
+    // 单个连接统计的JSON表示
     #[rustfmt::skip]
-    pub fn return_device_connection_stats(&self) -> JsonMap<String, Value> {
-        let mut map = JsonMap::<String, Value>::new();
-        for (key, stats) in self.device_connection_stats.iter() {
-            let direction_str = if stats.direction == 0 { "ingress" } else { "egress" };
-            let protocol_str = if stats.protocol == 6 { "TCP" } else if stats.protocol == 17 { "UDP" } else { "UNKNOWN" };
-            
-            let stats_info = serde_json::json!({
-                "device_id": stats.device_id,
-                "src_port": stats.src_port,
-                "dst_port": stats.dst_port,
-                "direction": direction_str,
-                "protocol": protocol_str,
-                "timestamp": stats.timestamp,
-                "total_packets": stats.total_packets,
-                "total_bytes": stats.total_bytes
-            });
-            
-            map.insert(format!("connection_{}", key), stats_info);
+    fn connection_stats_json(&self, key: u32, stats: &DeviceConnectionStats, clock: &Clock) -> Value {
+        let direction_str = if stats.direction == 0 { "ingress" } else { "egress" };
+        let protocol_str = protocol_name(stats.flow.protocol as u32);
+        let rate = self.connection_rates.get(&key);
+        let bytes_per_sec = rate.map(|r| r.bytes_per_sec()).unwrap_or(0.0);
+
+        serde_json::json!({
+            "device_id": stats.device_id,
+            "netns": crate::netns::inode_of(stats.netns_cookie),
+            "family": family_name(stats.flow.family),
+            "src_ip": stats.flow.src_ip().to_string(),
+            "dst_ip": stats.flow.dst_ip().to_string(),
+            "src_port": stats.flow.src_port,
+            "dst_port": stats.flow.dst_port,
+            "direction": direction_str,
+            "protocol": protocol_str,
+            "timestamp": clock.unix_secs(stats.timestamp),
+            "idle_secs": clock.idle_secs(stats.timestamp),
+            "first_seen": clock.unix_secs(stats.first_seen),
+            "duration_secs": duration_secs(stats),
+            "total_packets": stats.total_packets,
+            "total_bytes": stats.total_bytes,
+            "retransmissions": stats.retransmissions,
+            "dup_acks": stats.dup_acks,
+            "retransmission_rate": retransmission_rate(stats),
+            "socket_cookie": stats.socket_cookie,
+            "window": {
+                "last": stats.last_window,
+                "min": stats.min_window,
+                "max": stats.max_window
+            },
+            "throughput": {
+                "packets_delta": rate.map(|r| r.packets_delta).unwrap_or(0),
+                "bytes_delta": rate.map(|r| r.bytes_delta).unwrap_or(0),
+                "interval_secs": rate.map(|r| r.interval_secs).unwrap_or(0.0),
+                "packets_per_sec": rate.map(|r| r.packets_per_sec()).unwrap_or(0.0),
+                "bytes_per_sec": bytes_per_sec,
+                "bits_per_sec": bytes_per_sec * 8.0
+            }
+        })
+    }
+
+    // 按查询条件过滤、排序、分页连接统计，device_id 为空时查询所有设备
+    pub fn query_connections(&self, device_id: Option<u32>, query: &StatsQuery) -> Value {
+        let clock = Clock::now();
+        let mut rows: Vec<(u32, &DeviceConnectionStats)> = self
+            .device_connection_stats
+            .iter()
+            .filter(|(_, stats)| device_id.is_none_or(|id| stats.device_id == id))
+            .filter(|(_, stats)| query.port.is_none_or(|port| stats.flow.src_port == port || stats.flow.dst_port == port))
+            .filter(|(_, stats)| {
+                query
+                    .protocol
+                    .as_deref()
+                    .is_none_or(|protocol| protocol_name(stats.flow.protocol as u32).eq_ignore_ascii_case(protocol))
+            })
+            .filter(|(_, stats)| stats.total_bytes >= query.min_bytes.unwrap_or(0))
+            .filter(|(_, stats)| query.state_matches(clock.idle_secs(stats.timestamp)))
+            .map(|(key, stats)| (*key, stats))
+            .collect();
+
+        rows.sort_by_key(|(key, _)| *key);
+        if let Some(sort) = query.sort {
+            let value = |key: u32, stats: &DeviceConnectionStats| match sort {
+                SortKey::Bytes => stats.total_bytes as f64,
+                SortKey::Packets => stats.total_packets as f64,
+                SortKey::Port => stats.flow.dst_port as f64,
+                SortKey::Retransmissions => stats.retransmissions as f64,
+                SortKey::BytesPerSec => self.connection_rates.get(&key).map(|r| r.bytes_per_sec()).unwrap_or(0.0),
+                SortKey::Timestamp => stats.timestamp as f64,
+                SortKey::Duration => duration_secs(stats).unwrap_or(0) as f64,
+                // 单个方向的连接统计没有对称性
+                SortKey::Asymmetry => 0.0,
+            };
+            rows.sort_by(|a, b| query.order.apply(value(a.0, a.1).total_cmp(&value(b.0, b.1))));
         }
-        map
+
+        query.page(rows, |(key, stats)| {
+            let mut item = self.connection_stats_json(key, stats, &clock);
+            item["id"] = Value::from(key);
+            item
+        })
+    }
+
+    // 把同一设备上同一连接的 ingress 和 egress 统计合并为双向流，按查询条件过滤、排序、分页
+    pub fn query_flows(&self, query: &StatsQuery) -> Result<Value, String> {
+        if let Some(sort @ (SortKey::Retransmissions | SortKey::BytesPerSec)) = query.sort {
+            return Err(format!("sort by {} is not supported for flows", sort.as_str()));
+        }
+        let clock = Clock::now();
+        let mut rows: Vec<(BiFlowKey, BiFlow)> = self
+            .bi_flows()
+            .into_iter()
+            .filter(|(key, _)| query.port.is_none_or(|port| key.local.1 == port || key.remote.1 == port))
+            .filter(|(key, _)| {
+                query
+                    .protocol
+                    .as_deref()
+                    .is_none_or(|protocol| protocol_name(key.protocol as u32).eq_ignore_ascii_case(protocol))
+            })
+            .filter(|(_, flow)| flow.rx_bytes() + flow.tx_bytes() >= query.min_bytes.unwrap_or(0))
+            .filter(|(_, flow)| query.state_matches(clock.idle_secs(flow.timestamp())))
+            .filter(|(_, flow)| query.min_asymmetry.is_none_or(|min| flow.asymmetry().abs() >= min))
+            .filter(|(_, flow)| query.one_way.is_none_or(|one_way| flow.one_way() == one_way))
+            .collect();
+
+        rows.sort_by_key(|(key, _)| *key);
+        if let Some(sort) = query.sort {
+            let value = |key: &BiFlowKey, flow: &BiFlow| match sort {
+                SortKey::Bytes => (flow.rx_bytes() + flow.tx_bytes()) as f64,
+                SortKey::Packets => flow.packets() as f64,
+                SortKey::Port => key.local.1 as f64,
+                SortKey::Timestamp => flow.timestamp() as f64,
+                SortKey::Duration => flow.duration_secs().unwrap_or(0) as f64,
+                SortKey::Asymmetry => flow.asymmetry().abs(),
+                SortKey::Retransmissions | SortKey::BytesPerSec => 0.0,
+            };
+            rows.sort_by(|a, b| query.order.apply(value(&a.0, &a.1).total_cmp(&value(&b.0, &b.1))));
+        }
+
+        Ok(query.page(rows, |(key, flow)| bi_flow_json(&key, &flow, &clock)))
     }
 
-    // 查询指定设备的连接统计
-    pub fn query_device_connection_stats(&self, device_id: u32) -> Vec<DeviceConnectionStats> {
-        let mut result = Vec::new();
-        for (_, stats) in self.device_connection_stats.iter() {
-            if stats.device_id == device_id {
-                result.push(*stats);
+    // 同一设备上同一连接的 ingress 和 egress 统计
+    fn bi_flows(&self) -> HashMap<BiFlowKey, BiFlow<'_>> {
+        let mut flows: HashMap<BiFlowKey, BiFlow> = HashMap::new();
+        for stats in self.device_connection_stats.values() {
+            let flow = &stats.flow;
+            let ingress = stats.direction == 0;
+            let (local, remote) = endpoints(stats);
+            let key = BiFlowKey {
+                netns_cookie: stats.netns_cookie,
+                device_id: stats.device_id,
+                family: flow.family,
+                protocol: flow.protocol,
+                local,
+                remote,
+            };
+            let entry = flows.entry(key).or_default();
+            if ingress {
+                entry.rx = Some(stats);
+            } else {
+                entry.tx = Some(stats);
             }
         }
-        result
+        flows
+    }
+
+    // 按本机 socket cookie 分组的双向流，一个 socket 的流量可能经过多个挂载的设备
+    pub fn flows_by_socket(&self) -> HashMap<u64, Vec<Value>> {
+        let clock = Clock::now();
+        let mut sockets: HashMap<u64, Vec<Value>> = HashMap::new();
+        let mut flows: Vec<(BiFlowKey, BiFlow)> = self.bi_flows().into_iter().collect();
+        flows.sort_by_key(|(key, _)| *key);
+        for (key, flow) in flows {
+            let cookie = flow.socket_cookie();
+            if cookie != 0 {
+                sockets.entry(cookie).or_default().push(bi_flow_json(&key, &flow, &clock));
+            }
+        }
+        sockets
+    }
+
+    // 滑动窗口内每个本地端口的不同对端IP数(fan-in)和每个对端IP访问的不同本地端口数(fan-out)
+    pub fn fanout(&self, query: &FanoutQuery) -> Value {
+        let window_secs = query.window_secs.unwrap_or(300);
+        let min_count = query.min_count.unwrap_or(1);
+        let limit = query.limit.unwrap_or(100);
+        let clock = Clock::now();
+
+        // (IP 协议号, 本地端口) 和 (地址族, 对端地址)
+        type LocalPort = (u8, u16);
+        type RemoteIp = (u8, [u8; 16]);
+        let mut ports: HashMap<LocalPort, HashSet<[u8; 16]>> = HashMap::new();
+        let mut remotes: HashMap<RemoteIp, HashSet<LocalPort>> = HashMap::new();
+        for stats in self.device_connection_stats.values() {
+            if clock.idle_secs(stats.timestamp).is_none_or(|idle| idle > window_secs) {
+                continue;
+            }
+            let ((_, local_port), (remote_addr, _)) = endpoints(stats);
+            let protocol = stats.flow.protocol;
+            ports.entry((protocol, local_port)).or_default().insert(remote_addr);
+            remotes
+                .entry((stats.flow.family, remote_addr))
+                .or_default()
+                .insert((protocol, local_port));
+        }
+
+        let mut ports: Vec<(LocalPort, usize)> = ports
+            .into_iter()
+            .map(|(key, remotes)| (key, remotes.len()))
+            .filter(|(_, count)| *count >= min_count)
+            .collect();
+        ports.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut remotes: Vec<(RemoteIp, usize)> = remotes
+            .into_iter()
+            .map(|(key, ports)| (key, ports.len()))
+            .filter(|(_, count)| *count >= min_count)
+            .collect();
+        remotes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let ports: Vec<Value> = ports
+            .into_iter()
+            .take(limit)
+            .map(|((protocol, port), count)| {
+                serde_json::json!({
+                    "protocol": protocol_name(protocol as u32),
+                    "port": port,
+                    "remote_ips": count,
+                })
+            })
+            .collect();
+        let remotes: Vec<Value> = remotes
+            .into_iter()
+            .take(limit)
+            .map(|((family, addr), count)| {
+                let tuple = FlowTuple {
+                    saddr: addr,
+                    daddr: [0; 16],
+                    src_port: 0,
+                    dst_port: 0,
+                    family,
+                    protocol: 0,
+                    reserved: 0,
+                };
+                serde_json::json!({
+                    "ip": tuple.src_ip().to_string(),
+                    "local_ports": count,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "window_secs": window_secs,
+            "ports": ports,
+            "remotes": remotes,
+        })
+    }
+
+    // 按查询条件过滤、排序、分页端口统计
+    pub fn query_ports(&self, query: &StatsQuery) -> Result<Value, String> {
+        // 端口统计不区分协议，也没有重传数据
+        if query.protocol.is_some() {
+            return Err("protocol filter is not supported for port stats".to_string());
+        }
+        if query.state.is_some() || query.older_than.is_some() {
+            return Err("state filter is not supported for port stats".to_string());
+        }
+        if let Some(sort @ (SortKey::Retransmissions | SortKey::Duration | SortKey::Asymmetry)) = query.sort {
+            return Err(format!("sort by {} is not supported for port stats", sort.as_str()));
+        }
+        let mut rows: Vec<(u16, &PortStats)> = self
+            .port_stats
+            .iter()
+            .filter(|(port, _)| query.port.is_none_or(|p| **port == p))
+            .filter(|(_, stats)| stats.bytes >= query.min_bytes.unwrap_or(0))
+            .map(|(port, stats)| (*port, stats))
+            .collect();
+
+        rows.sort_by_key(|(port, _)| *port);
+        if let Some(sort) = query.sort {
+            let value = |port: u16, stats: &PortStats| match sort {
+                SortKey::Bytes => stats.bytes as f64,
+                SortKey::Packets => stats.packets as f64,
+                SortKey::Port => port as f64,
+                SortKey::Timestamp => stats.last_seen as f64,
+                SortKey::BytesPerSec => rates_of(self.port_rates.get(&port)).1,
+                SortKey::Retransmissions | SortKey::Duration | SortKey::Asymmetry => 0.0,
+            };
+            rows.sort_by(|a, b| query.order.apply(value(a.0, a.1).total_cmp(&value(b.0, b.1))));
+        }
+
+        let clock = Clock::now();
+        let listeners = crate::listeners::snapshot();
+        Ok(query.page(rows, |(port, stats)| {
+            let (packets_per_sec, bytes_per_sec) = rates_of(self.port_rates.get(&port));
+            serde_json::json!({
+                "port": port,
+                // 本机监听该端口的进程，没有时为 null
+                "process": crate::listeners::process_name(&listeners, port),
+                "pids": listeners.get(&port).map(|processes| processes.iter().map(|process| process.pid).collect::<Vec<_>>()).unwrap_or_default(),
+                "packets": stats.packets,
+                "bytes": stats.bytes,
+                "rx_packets": stats.rx_packets,
+                "rx_bytes": stats.rx_bytes,
+                "tx_packets": stats.tx_packets,
+                "tx_bytes": stats.tx_bytes,
+                "last_seen": clock.unix_secs(stats.last_seen),
+                "idle_secs": clock.idle_secs(stats.last_seen),
+                "packets_per_sec": packets_per_sec,
+                "bytes_per_sec": bytes_per_sec
+            })
+        }))
+    }
+
+    // 所有TCP连接的整体重传率
+    pub fn total_retransmission_rate(&self) -> f64 {
+        let (retransmissions, packets) = self
+            .device_connection_stats
+            .values()
+            .filter(|stats| stats.flow.protocol == 6)
+            .fold((0, 0), |(r, p), stats| {
+                (r + stats.retransmissions, p + stats.total_packets)
+            });
+        if packets > 0 {
+            retransmissions as f64 / packets as f64
+        } else {
+            0.0
+        }
+    }
+
+    // 按流量排序的前 n 个端口
+    fn top_ports(&self, n: usize) -> Vec<(&u16, &PortStats)> {
+        let mut sorted_ports: Vec<_> = self.port_stats.iter().collect();
+        sorted_ports.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.bytes));
+        sorted_ports.truncate(n);
+        sorted_ports
     }
 
     // 输出类似print_summary的格式，但是不打印连接信息
@@ -203,9 +940,29 @@ impl TrafficStats {
             "总字节数: {:.2} MB\n",
             self.total_bytes as f64 / (1024.0 * 1024.0)
         ));
+        let (packets_per_sec, bytes_per_sec) = rates_of(self.total_rate.as_ref());
+        summary.push_str(&format!(
+            "当前速率: {:.0} pps | {}\n",
+            packets_per_sec,
+            format_bits_per_sec(bytes_per_sec)
+        ));
+        let listeners = crate::listeners::snapshot();
+        summary.push_str("--- 端口流量统计 (Top 20) ---\n");
+        for (port, stats) in self.top_ports(20) {
+            summary.push_str(&format!(
+                "端口: {:20} | 包数: {:8} | 流量: {:>10}\n",
+                crate::listeners::port_label(&listeners, *port),
+                stats.packets,
+                traffic_str(stats.bytes)
+            ));
+        }
         summary.push_str(&format!("活跃连接数: {}\n", self.connections.len()));
         summary.push_str(&format!("活跃端口数: {}\n", self.port_stats.len()));
         summary.push_str(&format!("活跃设备数: {}\n", self.device_stats.len()));
+        summary.push_str(&format!(
+            "TCP重传率: {:.2}%\n",
+            self.total_retransmission_rate() * 100.0
+        ));
         summary.push_str(&format!("========================\n"));
         summary
     }
@@ -218,23 +975,30 @@ impl TrafficStats {
             "总字节数: {:.2} MB",
             self.total_bytes as f64 / (1024.0 * 1024.0)
         );
+        let (packets_per_sec, bytes_per_sec) = rates_of(self.total_rate.as_ref());
+        println!(
+            "当前速率: {:.0} pps | {}",
+            packets_per_sec,
+            format_bits_per_sec(bytes_per_sec)
+        );
+
+        let clock = Clock::now();
 
         // 显示端口流量统计
         println!("\n--- 端口流量统计 (Top 20) ---");
-        let mut sorted_ports: Vec<_> = self.port_stats.iter().collect();
-        sorted_ports.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes));
-
-        for (port, stats) in sorted_ports.iter().take(20) {
-            let mb = stats.bytes as f64 / (1024.0 * 1024.0);
-            let kb = stats.bytes as f64 / 1024.0;
-            let traffic_str = if mb >= 1.0 {
-                format!("{:.2} MB", mb)
-            } else {
-                format!("{:.2} KB", kb)
-            };
+        let listeners = crate::listeners::snapshot();
+        for (port, stats) in self.top_ports(20) {
+            let (packets_per_sec, bytes_per_sec) = rates_of(self.port_rates.get(port));
             println!(
-                "端口: {:5} | 包数: {:8} | 流量: {:>10} | 最后活跃: {:8}",
-                port, stats.packets, traffic_str, stats.last_seen
+                "端口: {:20} | 包数: {:8} | 流量: {:>10} | 下载: {:>10} | 上传: {:>10} | 速率: {:>8.0} pps {:>12} | 最后活跃: {:>6}s前",
+                crate::listeners::port_label(&listeners, *port),
+                stats.packets,
+                traffic_str(stats.bytes),
+                traffic_str(stats.rx_bytes),
+                traffic_str(stats.tx_bytes),
+                packets_per_sec,
+                format_bits_per_sec(bytes_per_sec),
+                clock.idle_secs(stats.last_seen).unwrap_or(0)
             );
         }
 
@@ -251,9 +1015,15 @@ impl TrafficStats {
             } else {
                 format!("{:.2} KB", kb)
             };
+            let (packets_per_sec, bytes_per_sec) = rates_of(self.device_rates.get(*device_key));
             println!(
-                "设备: {:15} | 包数: {:8} | 流量: {:>10} | 最后活跃: {:8}",
-                device_key, stats.packets, traffic_str, stats.last_seen
+                "设备: {:15} | 包数: {:8} | 流量: {:>10} | 速率: {:>8.0} pps {:>12} | 最后活跃: {:>6}s前",
+                device_key,
+                stats.packets,
+                traffic_str,
+                packets_per_sec,
+                format_bits_per_sec(bytes_per_sec),
+                clock.idle_secs(stats.last_seen).unwrap_or(0)
             );
         }
 
@@ -278,8 +1048,8 @@ impl TrafficStats {
         active_connections.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes));
 
         for (_, conn) in active_connections.iter().take(10) {
-            let src_ip = Ipv4Addr::from(conn.src_ip);
-            let dst_ip = Ipv4Addr::from(conn.dst_ip);
+            let src_ip = conn.flow.src_ip();
+            let dst_ip = conn.flow.dst_ip();
             let mb = conn.bytes as f64 / (1024.0 * 1024.0);
             let status_str = match conn.status {
                 1 => "建立中",
@@ -290,7 +1060,7 @@ impl TrafficStats {
             };
             println!(
                 "{}:{} -> {}:{} | 状态: {} | 流量: {:.2} MB",
-                src_ip, conn.src_port, dst_ip, conn.dst_port, status_str, mb
+                src_ip, conn.flow.src_port, dst_ip, conn.flow.dst_port, status_str, mb
             );
         }
 
@@ -298,11 +1068,391 @@ impl TrafficStats {
         println!("活跃连接数: {}", active_connections.len());
         println!("活跃端口数: {}", self.port_stats.len());
         println!("活跃设备数: {}", self.device_stats.len());
+        println!("TCP重传率: {:.2}%", self.total_retransmission_rate() * 100.0);
         println!("========================\n");
     }
 }
 
+// 设备统计的 key 转换为 设备名_方向，设备名从内存中的设备映射获取，其他命名空间中的设备名为 iface@netns_inode
+fn device_stats_key(key: &DeviceStatsKey) -> String {
+    let device_id = key.key / 2;
+    let direction = if key.key.is_multiple_of(2) { "ingress" } else { "egress" };
+    let netns = crate::netns::inode_of(key.netns_cookie);
+
+    let mut device_name = match netns {
+        Some(inode) if inode != crate::netns::host_inode() => format!("device{}@{}", device_id, inode),
+        Some(_) => format!("device{}", device_id),
+        None => format!("device{}@cookie{}", device_id, key.netns_cookie),
+    };
+    if let Ok(mappings) = crate::server::DEVICE_MAPPINGS.try_lock() {
+        for (name, mapping) in mappings.iter() {
+            if mapping.device_id == device_id && netns.is_none_or(|inode| mapping.netns == inode) {
+                device_name = name.clone();
+                break;
+            }
+        }
+    }
+    format!("{}_{}", device_name, direction)
+}
+
+// 设备所在命名空间的 inode，设备名形如 iface@netns_inode 时为其他命名空间
+fn device_netns(device: &str) -> Option<u64> {
+    match device.rsplit_once('@') {
+        Some((_, inode)) => inode.parse().ok(),
+        None => Some(crate::netns::host_inode()),
+    }
+}
+
+// 以 MB/KB 显示流量
+fn traffic_str(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb >= 1.0 {
+        format!("{:.2} MB", mb)
+    } else {
+        format!("{:.2} KB", bytes as f64 / 1024.0)
+    }
+}
+
+// 以 bps/Kbps/Mbps/Gbps 显示字节速率
+fn format_bits_per_sec(bytes_per_sec: f64) -> String {
+    let bits = bytes_per_sec * 8.0;
+    if bits >= 1e9 {
+        format!("{:.2} Gbps", bits / 1e9)
+    } else if bits >= 1e6 {
+        format!("{:.2} Mbps", bits / 1e6)
+    } else if bits >= 1e3 {
+        format!("{:.2} Kbps", bits / 1e3)
+    } else {
+        format!("{:.0} bps", bits)
+    }
+}
+
+fn protocol_name(protocol: u32) -> &'static str {
+    match protocol {
+        6 => "TCP",
+        17 => "UDP",
+        _ => "UNKNOWN",
+    }
+}
+
+// 流记录的地址族
+fn family_name(family: u8) -> &'static str {
+    if family == FLOW_FAMILY_V4 {
+        "ipv4"
+    } else {
+        "ipv6"
+    }
+}
+
+// 常见的 IP 协议号，其他协议显示为 ip-<协议号>
+fn ip_protocol_name(protocol: u8) -> String {
+    match protocol {
+        1 => "icmp".to_string(),
+        2 => "igmp".to_string(),
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        41 => "ipv6".to_string(),
+        47 => "gre".to_string(),
+        50 => "esp".to_string(),
+        51 => "ah".to_string(),
+        58 => "icmpv6".to_string(),
+        89 => "ospf".to_string(),
+        132 => "sctp".to_string(),
+        _ => format!("ip-{}", protocol),
+    }
+}
+
+// 连接从第一个包到最后一个包的秒数
+fn duration_secs(stats: &DeviceConnectionStats) -> Option<u64> {
+    (stats.first_seen != 0).then(|| stats.timestamp.saturating_sub(stats.first_seen) / 1_000_000_000)
+}
+
+// 单个连接的重传率: 重传包数 / 总包数
+pub fn retransmission_rate(stats: &DeviceConnectionStats) -> f64 {
+    if stats.total_packets > 0 {
+        stats.retransmissions as f64 / stats.total_packets as f64
+    } else {
+        0.0
+    }
+}
+
 // 流量统计信息, 全局共享
+// 只由后台任务刷新，读取时取当前快照，查询不会与刷新互相等待
 lazy_static::lazy_static! {
-    pub static ref TRAFFIC_STATS: Mutex<TrafficStats> = Mutex::new(TrafficStats::new());
+    static ref TRAFFIC_STATS: RwLock<Arc<TrafficStats>> = RwLock::new(Arc::new(TrafficStats::new()));
+}
+
+// 最近一次刷新的流量统计
+pub fn snapshot() -> Arc<TrafficStats> {
+    TRAFFIC_STATS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// 在上一次快照的副本上读取 map 并计算速率，完成后替换快照
+fn refresh(maps: &MapCache) {
+    let mut next = (*snapshot()).clone();
+    next.update_from_maps(maps);
+    *TRAFFIC_STATS.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(next);
+}
+
+// 启动时先刷新一次，之后按 interval 定期刷新。读取 map 和复制快照会阻塞，放在阻塞线程池中执行
+pub fn start(ebpf_manager: Arc<EbpfManager>, interval: Duration) {
+    refresh(&ebpf_manager.maps());
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let maps = ebpf_manager.maps();
+            if let Err(e) = tokio::task::spawn_blocking(move || refresh(&maps)).await {
+                tracing::warn!("刷新流量统计失败: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+    use crate::source::MemorySource;
+
+    fn device_key(device_id: u32, ingress: bool) -> DeviceStatsKey {
+        DeviceStatsKey {
+            netns_cookie: 0,
+            key: device_id * 2 + if ingress { 0 } else { 1 },
+            reserved: 0,
+        }
+    }
+
+    fn port(packets: u64, bytes: u64) -> PortStats {
+        PortStats {
+            packets,
+            bytes,
+            rx_packets: packets,
+            rx_bytes: bytes,
+            ..PortStats::zeroed()
+        }
+    }
+
+    fn connection(device_id: u32, src_port: u16, dst_port: u16, protocol: u8, bytes: u64) -> DeviceConnectionStats {
+        DeviceConnectionStats {
+            device_id,
+            flow: FlowTuple::from_v4(
+                u32::from(Ipv4Addr::new(10, 0, 0, 1)).to_be(),
+                u32::from(Ipv4Addr::new(10, 0, 0, 2)).to_be(),
+                src_port,
+                dst_port,
+                protocol,
+            ),
+            total_packets: 1,
+            total_bytes: bytes,
+            ..DeviceConnectionStats::zeroed()
+        }
+    }
+
+    fn stats_from(source: &MemorySource) -> TrafficStats {
+        crate::netns::init();
+        let mut stats = TrafficStats::new();
+        stats.update_from(source);
+        stats
+    }
+
+    #[test]
+    fn update_skips_entries_without_packets() {
+        let source = MemorySource {
+            total: Some((10, 1000)),
+            ports: vec![(443, port(10, 1000)), (80, port(0, 0))],
+            devices: vec![
+                (device_key(3, true), DeviceStats { packets: 7, bytes: 700, last_seen: 0 }),
+                (device_key(3, false), DeviceStats { packets: 0, bytes: 0, last_seen: 0 }),
+            ],
+            connections: vec![(1, connection(3, 40000, 443, 6, 500)), (2, DeviceConnectionStats::zeroed())],
+            ..Default::default()
+        };
+        let stats = stats_from(&source);
+
+        assert_eq!((stats.total_packets, stats.total_bytes), (10, 1000));
+        assert_eq!(stats.port_stats.keys().collect::<Vec<_>>(), vec![&443]);
+        assert_eq!(stats.device_connection_stats.len(), 1);
+
+        let rows = stats.device_rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["device"], "device3");
+        assert_eq!(rows[0]["direction"], "ingress");
+        assert_eq!(rows[0]["packets"], 7);
+    }
+
+    #[test]
+    fn query_ports_filters_sorts_and_pages() {
+        let source = MemorySource {
+            ports: vec![(22, port(5, 100)), (443, port(50, 9000)), (8080, port(20, 3000))],
+            ..Default::default()
+        };
+        let stats = stats_from(&source);
+
+        let query = StatsQuery {
+            sort: Some(SortKey::Bytes),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = stats.query_ports(&query).unwrap();
+        assert_eq!(page["total"], 3);
+        let ports: Vec<&Value> = page["items"].as_array().unwrap().iter().map(|item| &item["port"]).collect();
+        assert_eq!(ports, vec![&Value::from(443), &Value::from(8080)]);
+
+        let query = StatsQuery {
+            min_bytes: Some(1000),
+            port: Some(22),
+            ..Default::default()
+        };
+        assert_eq!(stats.query_ports(&query).unwrap()["total"], 0);
+
+        let query = StatsQuery {
+            protocol: Some("tcp".to_string()),
+            ..Default::default()
+        };
+        assert!(stats.query_ports(&query).is_err());
+    }
+
+    #[test]
+    fn query_connections_filters_by_device_port_and_protocol() {
+        let source = MemorySource {
+            connections: vec![
+                (1, connection(3, 40000, 443, 6, 500)),
+                (2, connection(3, 40001, 53, 17, 80)),
+                (3, connection(4, 40002, 443, 6, 2000)),
+            ],
+            ..Default::default()
+        };
+        let stats = stats_from(&source);
+
+        let all = StatsQuery::default();
+        assert_eq!(stats.query_connections(None, &all)["total"], 3);
+        assert_eq!(stats.query_connections(Some(3), &all)["total"], 2);
+
+        let query = StatsQuery {
+            port: Some(443),
+            protocol: Some("TCP".to_string()),
+            min_bytes: Some(1000),
+            ..Default::default()
+        };
+        let page = stats.query_connections(None, &query);
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["id"], 3);
+    }
+
+    #[test]
+    fn query_flows_pairs_directions() {
+        // 设备3上的一个连接：10.0.0.1:40000 -> 10.0.0.2:443 为 ingress，反方向为 egress
+        let request = connection(3, 40000, 443, 6, 100);
+        let mut response = connection(3, 443, 40000, 6, 9900);
+        response.direction = 1;
+        response.flow = FlowTuple::from_v4(
+            u32::from(Ipv4Addr::new(10, 0, 0, 2)).to_be(),
+            u32::from(Ipv4Addr::new(10, 0, 0, 1)).to_be(),
+            443,
+            40000,
+            6,
+        );
+        let source = MemorySource {
+            connections: vec![
+                (1, request),
+                (2, response),
+                (3, connection(3, 40001, 53, 17, 80)),
+            ],
+            ..Default::default()
+        };
+        let stats = stats_from(&source);
+
+        let page = stats.query_flows(&StatsQuery::default()).unwrap();
+        assert_eq!(page["total"], 2);
+
+        let query = StatsQuery {
+            one_way: Some(false),
+            ..Default::default()
+        };
+        let page = stats.query_flows(&query).unwrap();
+        assert_eq!(page["total"], 1);
+        let flow = &page["items"][0];
+        assert_eq!(flow["local_ip"], "10.0.0.2");
+        assert_eq!(flow["local_port"], 443);
+        assert_eq!((flow["rx_bytes"].as_u64(), flow["tx_bytes"].as_u64()), (Some(100), Some(9900)));
+        assert_eq!(flow["asymmetry"], 0.98);
+
+        let query = StatsQuery {
+            min_asymmetry: Some(0.99),
+            one_way: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(stats.query_flows(&query).unwrap()["total"], 0);
+    }
+
+    #[test]
+    fn flows_by_socket_joins_ingress_through_egress_cookie() {
+        // 只有 egress 方向带有本机 socket 的 cookie
+        let request = connection(3, 40000, 443, 6, 100);
+        let mut response = connection(3, 443, 40000, 6, 9900);
+        response.direction = 1;
+        response.socket_cookie = 7;
+        response.flow = FlowTuple::from_v4(
+            u32::from(Ipv4Addr::new(10, 0, 0, 2)).to_be(),
+            u32::from(Ipv4Addr::new(10, 0, 0, 1)).to_be(),
+            443,
+            40000,
+            6,
+        );
+        let source = MemorySource {
+            connections: vec![(1, request), (2, response), (3, connection(3, 40001, 53, 17, 80))],
+            ..Default::default()
+        };
+        let sockets = stats_from(&source).flows_by_socket();
+        assert_eq!(sockets.keys().collect::<Vec<_>>(), vec![&7]);
+        let flow = &sockets[&7][0];
+        assert_eq!((flow["rx_bytes"].as_u64(), flow["tx_bytes"].as_u64()), (Some(100), Some(9900)));
+        assert_eq!(flow["socket_cookie"], 7);
+    }
+
+    #[test]
+    fn fanout_counts_distinct_endpoints_in_window() {
+        let now = crate::conntrack::monotonic_now_ns();
+        let probe = |src: u8, dst_port: u16, timestamp: u64| {
+            let mut stats = connection(3, 40000, dst_port, 6, 60);
+            stats.flow = FlowTuple::from_v4(
+                u32::from(Ipv4Addr::new(203, 0, 113, src)).to_be(),
+                u32::from(Ipv4Addr::new(10, 0, 0, 2)).to_be(),
+                40000,
+                dst_port,
+                6,
+            );
+            stats.timestamp = timestamp;
+            stats
+        };
+        let source = MemorySource {
+            connections: vec![
+                (1, probe(7, 22, now)),
+                (2, probe(7, 23, now)),
+                (3, probe(7, 25, now)),
+                (4, probe(8, 22, now)),
+                // 窗口之外
+                (5, probe(9, 22, now - 600_000_000_000)),
+            ],
+            ..Default::default()
+        };
+        let stats = stats_from(&source);
+
+        let fanout = stats.fanout(&FanoutQuery::default());
+        assert_eq!(fanout["ports"][0]["port"], 22);
+        assert_eq!(fanout["ports"][0]["remote_ips"], 2);
+        assert_eq!(fanout["remotes"][0]["ip"], "203.0.113.7");
+        assert_eq!(fanout["remotes"][0]["local_ports"], 3);
+
+        let query = FanoutQuery {
+            min_count: Some(3),
+            ..Default::default()
+        };
+        let fanout = stats.fanout(&query);
+        assert_eq!(fanout["ports"].as_array().unwrap().len(), 0);
+        assert_eq!(fanout["remotes"].as_array().unwrap().len(), 1);
+    }
 }
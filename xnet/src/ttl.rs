@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aya::maps::{HashMap as AyaHashMap, MapData};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xnet_common::{TtlStats, TTL_JUMP_THRESHOLD};
+
+use crate::events::Event;
+use crate::map_cache::MapCache;
+use crate::server::EbpfManager;
+
+// 一个周期内至少有这么多包才判定，避免偶发的路径变化误报
+const MIN_PACKETS: u64 = 10;
+// 一个周期内跳变次数及其占包数的比例均达到阈值时判定为异常
+const MIN_JUMPS: u64 = 3;
+const MIN_JUMP_RATIO: f64 = 0.1;
+// 保留的异常事件数
+const MAX_HISTORY: usize = 1000;
+
+// 源IP的TTL在相邻包之间不合理地变化，可能是伪造源地址或注入的包
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TtlAnomalyEvent {
+    pub ip: Ipv4Addr,
+    // 本周期内的包数和跳变次数
+    pub packets: u64,
+    pub jumps: u64,
+    pub min_ttl: u8,
+    pub max_ttl: u8,
+    // 累计的TTL分布，按 TTL/32 分桶
+    pub histogram: Vec<u32>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Default)]
+struct TtlState {
+    // 上个周期的累计包数和跳变次数
+    previous: HashMap<u32, (u64, u64)>,
+    // 当前处于异常状态的源IP，恢复正常前不重复发布
+    flagged: HashSet<u32>,
+    history: VecDeque<TtlAnomalyEvent>,
+}
+
+lazy_static::lazy_static! {
+    static ref TTL: Mutex<TtlState> = Mutex::new(TtlState::default());
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn read_stats(maps: &MapCache) -> Result<Vec<(u32, TtlStats)>, anyhow::Error> {
+    let map = AyaHashMap::<&MapData, u32, TtlStats>::try_from(
+        maps.map("ttl_stats")
+            .ok_or_else(|| anyhow::anyhow!("ttl_stats map not found"))?,
+    )?;
+    Ok(crate::batch::entries(&map))
+}
+
+fn stats_json(raw_ip: u32, stats: &TtlStats) -> Value {
+    serde_json::json!({
+        "ip": Ipv4Addr::from(u32::from_be(raw_ip)),
+        "packets": stats.packets,
+        "jumps": stats.jumps,
+        "last_ttl": stats.last_ttl,
+        "min_ttl": stats.min_ttl,
+        "max_ttl": stats.max_ttl,
+        "histogram": stats.histogram,
+    })
+}
+
+// 比较两个周期的跳变次数，发布新出现的异常
+async fn check(ebpf_manager: &EbpfManager) -> Result<(), anyhow::Error> {
+    let entries = {
+        let maps = ebpf_manager.maps();
+        read_stats(&maps)?
+    };
+
+    let mut state = TTL.lock().await;
+    let mut previous = HashMap::with_capacity(entries.len());
+    for (raw_ip, stats) in entries {
+        previous.insert(raw_ip, (stats.packets, stats.jumps));
+        // LRU 淘汰后重新出现的IP从0开始计数
+        let (last_packets, last_jumps) = state
+            .previous
+            .get(&raw_ip)
+            .copied()
+            .filter(|(packets, _)| *packets <= stats.packets)
+            .unwrap_or((0, 0));
+        let packets = stats.packets - last_packets;
+        let jumps = stats.jumps.saturating_sub(last_jumps);
+        let ip = Ipv4Addr::from(u32::from_be(raw_ip));
+
+        let anomalous = packets >= MIN_PACKETS
+            && jumps >= MIN_JUMPS
+            && jumps as f64 >= packets as f64 * MIN_JUMP_RATIO;
+        if anomalous && state.flagged.insert(raw_ip) {
+            warn!(
+                src_ip = %ip,
+                "源IP {} 的TTL异常跳变: {} 个包中 {} 次，TTL {}-{}",
+                ip, packets, jumps, stats.min_ttl, stats.max_ttl
+            );
+            let event = TtlAnomalyEvent {
+                ip,
+                packets,
+                jumps,
+                min_ttl: stats.min_ttl,
+                max_ttl: stats.max_ttl,
+                histogram: stats.histogram.to_vec(),
+                timestamp: now_secs(),
+            };
+            crate::events::publish(Event::TtlAnomaly(event.clone()));
+            if state.history.len() >= MAX_HISTORY {
+                state.history.pop_front();
+            }
+            state.history.push_back(event);
+        } else if packets > 0 && jumps == 0 && state.flagged.remove(&raw_ip) {
+            info!(src_ip = %ip, "源IP {} 的TTL恢复稳定", ip);
+        }
+    }
+    state.flagged.retain(|ip| previous.contains_key(ip));
+    state.previous = previous;
+    Ok(())
+}
+
+// 定期检查各源IP的TTL跳变
+pub fn start(ebpf_manager: Arc<EbpfManager>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = check(&ebpf_manager).await {
+                warn!("检查TTL跳变失败: {}", e);
+            }
+        }
+    });
+}
+
+// 最近的异常事件及当前仍处于异常状态的源IP
+pub async fn anomalies() -> Value {
+    let state = TTL.lock().await;
+    let mut active: Vec<Ipv4Addr> = state
+        .flagged
+        .iter()
+        .map(|ip| Ipv4Addr::from(u32::from_be(*ip)))
+        .collect();
+    active.sort();
+    serde_json::json!({
+        "jump_threshold": TTL_JUMP_THRESHOLD,
+        "active": active,
+        "history": state.history,
+    })
+}
+
+// 单个源IP的TTL分布，没有记录时返回 None
+pub async fn stats(
+    ebpf_manager: &EbpfManager,
+    ip: Ipv4Addr,
+) -> Result<Option<Value>, anyhow::Error> {
+    let raw_ip = u32::from(ip).to_be();
+    let maps = ebpf_manager.maps();
+    let map = AyaHashMap::<&MapData, u32, TtlStats>::try_from(
+        maps.map("ttl_stats")
+            .ok_or_else(|| anyhow::anyhow!("ttl_stats map not found"))?,
+    )?;
+    match map.get(&raw_ip, 0) {
+        Ok(stats) => Ok(Some(stats_json(raw_ip, &stats))),
+        Err(aya::maps::MapError::KeyNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
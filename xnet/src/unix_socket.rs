@@ -0,0 +1,70 @@
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::UnixListener;
+use tracing::{debug, warn};
+
+// API 的 unix socket 监听配置
+pub struct UnixSocketOptions {
+    pub path: PathBuf,
+    // socket 文件权限，例如 0o660
+    pub mode: u32,
+}
+
+// 解析八进制权限，例如 660 或 0o660
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("invalid socket mode '{}', expected octal like 660", s)),
+    }
+}
+
+// 删除上次运行遗留的 socket 文件，路径上是其他类型的文件时报错
+fn remove_stale(path: &Path) -> Result<(), anyhow::Error> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => anyhow::bail!("{} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+pub fn bind(options: &UnixSocketOptions) -> Result<UnixListener, anyhow::Error> {
+    remove_stale(&options.path)?;
+    let listener = UnixListener::bind(&options.path).map_err(|e| {
+        anyhow::anyhow!("failed to bind unix socket {}: {}", options.path.display(), e)
+    })?;
+    std::fs::set_permissions(&options.path, std::fs::Permissions::from_mode(options.mode))?;
+    Ok(listener)
+}
+
+// 在 unix socket 上提供与 TCP 相同的路由
+pub async fn serve(listener: UnixListener, router: Router) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("unix socket accept 失败: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let service = TowerToHyperService::new(router.clone());
+        tokio::spawn(async move {
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("unix socket 连接结束: {}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,245 @@
+use std::path::{Path, PathBuf};
+
+use aya::programs::Lsm;
+use aya::{Btf, Ebpf};
+
+use crate::server::ServeOptions;
+
+// 校验结果，逐项打印，结束时汇总失败数
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn check<T>(&mut self, what: &str, result: Result<T, impl std::fmt::Display>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                println!("ok      {}", what);
+                Some(value)
+            }
+            Err(e) => {
+                println!("FAIL    {}: {:#}", what, e);
+                self.failures += 1;
+                None
+            }
+        }
+    }
+
+    fn attach(&self, program: &str, target: impl std::fmt::Display) {
+        println!("attach  {} -> {}", program, target);
+    }
+}
+
+// 校验模式使用的临时固定目录，避免清空正在运行的实例固定的 map
+pub fn pin_path(pin_path: &Path) -> PathBuf {
+    let name = format!("xnet-validate-{}", std::process::id());
+    pin_path
+        .parent()
+        .map(|parent| parent.join(&name))
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+fn readable(path: &Path) -> Result<(), String> {
+    std::fs::metadata(path)
+        .map(|_| ())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// 加载全部程序通过校验器、校验配置文件和设备名，并列出启动时会挂载的程序，不挂载任何程序
+pub async fn run(mut ebpf: Ebpf, options: &ServeOptions) -> Result<(), anyhow::Error> {
+    let mut report = Report::default();
+
+    // 程序通过校验器
+    if report
+        .check("load programs", crate::server::load_programs(&mut ebpf))
+        .is_some()
+    {
+        let mut programs: Vec<(&str, String)> = ebpf
+            .programs()
+            .filter(|(_, program)| program.fd().is_ok())
+            .map(|(name, program)| (name, format!("{:?}", program.prog_type())))
+            .collect();
+        programs.sort();
+        for (name, kind) in programs {
+            println!("        {} ({})", name, kind);
+        }
+    }
+    if options.egress_policy {
+        let result = (|| -> Result<(), anyhow::Error> {
+            let btf = Btf::from_sys_fs()?;
+            let program: &mut Lsm = ebpf
+                .program_mut("xnet_egress_policy")
+                .ok_or_else(|| anyhow::anyhow!("xnet_egress_policy program not found"))?
+                .try_into()?;
+            program.load("socket_connect", &btf)?;
+            Ok(())
+        })();
+        report.check("load xnet_egress_policy", result);
+    }
+
+    // 配置文件
+    if let Some(path) = &options.firewall_rules_file {
+        if let Some(rules) = report.check(
+            &format!("firewall rules {}", path.display()),
+            crate::firewall::read_rules(path),
+        ) {
+            println!("        {} rules", rules.len());
+        }
+    }
+    if let Some(dir) = &options.rules_dir {
+        if let Some((firewall, nat, shaping)) = report.check(
+            &format!("rules dir {}", dir.display()),
+            crate::rules::read(dir),
+        ) {
+            println!(
+                "        {} firewall, {} nat, {} shaping rules",
+                firewall, nat, shaping
+            );
+        }
+    }
+    if let Some(path) = &options.firewall_allowlist_file {
+        if let Some(entries) = report.check(
+            &format!("firewall allowlist {}", path.display()),
+            crate::firewall::read_allowlist(path),
+        ) {
+            println!("        {} entries", entries.len());
+        }
+    }
+    if let Some(path) = &options.bogon_prefixes_file {
+        if let Some(prefixes) = report.check(
+            &format!("bogon prefixes {}", path.display()),
+            crate::bogon::read_file(path),
+        ) {
+            println!("        {} prefixes", prefixes.len());
+        }
+    }
+    if let Some(path) = &options.blocklist_feeds_file {
+        if let Some(feeds) = report.check(
+            &format!("blocklist feeds {}", path.display()),
+            crate::blocklist::read_configs(path),
+        ) {
+            println!("        {} feeds", feeds.len());
+        }
+    }
+    let mut plugins = Vec::new();
+    if let Some(path) = &options.plugins_file {
+        if let Some(configs) = report.check(
+            &format!("plugins {}", path.display()),
+            crate::plugin::read_file(path),
+        ) {
+            for config in configs {
+                if report
+                    .check(
+                        &format!("plugin {}", config.name),
+                        crate::plugin::check(&config),
+                    )
+                    .is_some()
+                {
+                    plugins.push(config);
+                }
+            }
+        }
+    }
+    if let Some(tls) = &options.tls {
+        report.check("tls certificate", crate::tls::rustls_config(tls).await);
+    }
+    if let Some(sqlite) = &options.sqlite {
+        let dir = sqlite
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        report.check(
+            &format!("sqlite directory {}", dir.display()),
+            readable(dir),
+        );
+    }
+
+    // 设备名
+    for config in &options.sampling {
+        report.check(
+            &format!("sampling {}={}", config.iface, config.rate),
+            crate::sampling::check(config),
+        );
+    }
+
+    let mut devices = Vec::new();
+    for spec in &options.attach {
+        // 设备模式列出当前匹配的设备，启动后出现的匹配设备也会被挂载
+        if let Some(ifaces) = report.check(
+            &format!("attach {}", spec.iface),
+            crate::startup_attach::check(spec),
+        ) {
+            devices.extend(ifaces.into_iter().map(|iface| (iface, spec.programs)));
+        }
+    }
+
+    // 启动时会挂载的程序
+    for (iface, programs) in devices {
+        if programs.tc() {
+            report.attach("xnet_tc_ingress", &iface);
+            report.attach("xnet_tc_egress", &iface);
+        }
+        if programs.xdp() {
+            report.attach("xnet_xdp", &iface);
+        }
+    }
+    if let Some(cgroup) = &options.sock_ops_cgroup {
+        if report
+            .check(&format!("cgroup {}", cgroup.display()), readable(cgroup))
+            .is_some()
+        {
+            report.attach("xnet_sockops", cgroup.display());
+        }
+    }
+    if let Some(lib) = &options.ssl_lib {
+        // 库名由加载器按 ld.so 缓存查找，只检查路径形式的参数
+        if lib.components().count() == 1
+            || report
+                .check(&format!("libssl {}", lib.display()), readable(lib))
+                .is_some()
+        {
+            report.attach("xnet_ssl_* uprobes", lib.display());
+        }
+    }
+    if options.egress_policy {
+        report.attach("xnet_egress_policy", "lsm/socket_connect");
+    }
+    if options.socket_owners {
+        report.attach("xnet_sock_connect", "fentry/tcp_connect");
+        report.attach("xnet_sock_sendmsg", "fentry/tcp_sendmsg");
+    }
+    report.attach("xnet_tcp_state", "tracepoint/sock/inet_sock_set_state");
+    for plugin in &plugins {
+        for attach in &plugin.attach {
+            report.attach(&format!("{}/{}", plugin.name, attach.program), &attach.hook);
+        }
+    }
+    for addr in &options.listen {
+        println!(
+            "listen  http{}://{}",
+            if options.tls.is_some() { "s" } else { "" },
+            addr
+        );
+    }
+    if let Some(unix_socket) = &options.unix_socket {
+        println!("listen  unix:{}", unix_socket.path.display());
+    }
+
+    if report.failures > 0 {
+        anyhow::bail!("{} checks failed", report.failures);
+    }
+    println!("configuration is valid");
+    Ok(())
+}
+
+// 删除校验时创建的临时固定目录
+pub fn cleanup(pin_path: &Path) {
+    if let Ok(entries) = std::fs::read_dir(pin_path) {
+        for entry in entries.flatten() {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    let _ = std::fs::remove_dir(pin_path);
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::events::Event;
+use crate::kernel_events::ConnOpenEvent;
+
+const MAX_WATCHES: usize = 64;
+// 记录的来源地址超过该数量时清理冷却期已过的地址
+const MAX_TRACKED_SOURCES: usize = 1024;
+
+fn default_cooldown() -> u64 {
+    60
+}
+
+// 监视规则: 有新的入站连接到 ports 中的端口时 POST 到 webhook
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WatchRule {
+    pub ports: Vec<u16>,
+    // 仅支持 http
+    pub webhook: String,
+    // 同一来源地址在该时间内只通知一次，避免扫描或重连时大量请求
+    #[serde(default = "default_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+impl WatchRule {
+    fn validate(&self) -> Result<(), String> {
+        if self.ports.is_empty() {
+            return Err("ports must not be empty".to_string());
+        }
+        if !self.webhook.starts_with("http://") {
+            return Err("webhook must be an http:// url".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WatchStatus {
+    pub id: u64,
+    #[serde(flatten)]
+    pub rule: WatchRule,
+    // 已发送的通知数
+    pub notified: u64,
+}
+
+// POST 到 webhook 的内容
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WatchNotification {
+    pub watch_id: u64,
+    #[serde(flatten)]
+    pub connection: ConnOpenEvent,
+}
+
+struct Watch {
+    status: WatchStatus,
+    last_notified: HashMap<Ipv4Addr, Instant>,
+}
+
+#[derive(Default)]
+struct Watches {
+    next_id: u64,
+    watches: BTreeMap<u64, Watch>,
+}
+
+lazy_static::lazy_static! {
+    static ref WATCHES: Mutex<Watches> = Mutex::new(Watches::default());
+}
+
+pub async fn list() -> Vec<WatchStatus> {
+    WATCHES
+        .lock()
+        .await
+        .watches
+        .values()
+        .map(|watch| watch.status.clone())
+        .collect()
+}
+
+// 新增监视规则，返回规则ID
+pub async fn add(rule: WatchRule) -> Result<u64, String> {
+    rule.validate()?;
+    let mut watches = WATCHES.lock().await;
+    if watches.watches.len() >= MAX_WATCHES {
+        return Err(format!("at most {} watches", MAX_WATCHES));
+    }
+    watches.next_id += 1;
+    let id = watches.next_id;
+    info!(
+        rule_id = id,
+        "端口监视 {}: {:?} -> {}", id, rule.ports, rule.webhook
+    );
+    watches.watches.insert(
+        id,
+        Watch {
+            status: WatchStatus {
+                id,
+                rule,
+                notified: 0,
+            },
+            last_notified: HashMap::new(),
+        },
+    );
+    crate::events::rule_change("watch", id, "create");
+    Ok(id)
+}
+
+// 删除监视规则，规则不存在时返回 false
+pub async fn remove(id: u64) -> bool {
+    let removed = WATCHES.lock().await.watches.remove(&id).is_some();
+    if removed {
+        crate::events::rule_change("watch", id, "delete");
+    }
+    removed
+}
+
+// 新连接的目的端口在监视列表中且来源不在冷却期内时发送通知
+async fn notify(connection: &ConnOpenEvent) {
+    let now = Instant::now();
+    let src_ip = connection.flow.src_ip;
+    let mut watches = WATCHES.lock().await;
+    for (id, watch) in watches.watches.iter_mut() {
+        if !watch.status.rule.ports.contains(&connection.flow.dst_port) {
+            continue;
+        }
+        let cooldown = Duration::from_secs(watch.status.rule.cooldown_secs);
+        if watch
+            .last_notified
+            .get(&src_ip)
+            .is_some_and(|at| now.duration_since(*at) < cooldown)
+        {
+            continue;
+        }
+        if watch.last_notified.len() >= MAX_TRACKED_SOURCES {
+            watch
+                .last_notified
+                .retain(|_, at| now.duration_since(*at) < cooldown);
+        }
+        watch.last_notified.insert(src_ip, now);
+        watch.status.notified += 1;
+        tokio::spawn(crate::webhook::post(
+            watch.status.rule.webhook.clone(),
+            WatchNotification {
+                watch_id: *id,
+                connection: connection.clone(),
+            },
+        ));
+    }
+}
+
+// 订阅内核推送的新建连接事件
+pub fn start() {
+    let mut events = crate::events::subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(Event::ConnOpen(connection)) => notify(&connection).await,
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("端口监视落后，跳过 {} 个事件", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+// webhook 请求超时
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// POST JSON 到 webhook 地址，仅支持 http，失败时只记录日志
+pub async fn post<T: Serialize>(url: String, body: T) {
+    let body = match serde_json::to_vec(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("webhook 序列化失败: {}", e);
+            return;
+        }
+    };
+    let request = match hyper::Request::post(&url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(body))
+    {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("webhook 地址无效 {}: {}", url, e);
+            return;
+        }
+    };
+    let client = hyper::Client::new();
+    match tokio::time::timeout(WEBHOOK_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => {}
+        Ok(Ok(response)) => warn!("webhook {} 返回 {}", url, response.status()),
+        Ok(Err(e)) => warn!("webhook {} 发送失败: {}", url, e),
+        Err(_) => warn!("webhook {} 超时", url),
+    }
+}
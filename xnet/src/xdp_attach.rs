@@ -0,0 +1,65 @@
+use aya::programs::xdp::XdpLinkId;
+use aya::programs::{Xdp, XdpFlags};
+use tracing::{info, warn};
+
+// XDP 程序的挂载模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XdpMode {
+    // 驱动模式，在驱动分配 skb 之前处理
+    Native,
+    // 通用模式，在内核协议栈中处理，所有设备都支持但性能较低
+    Generic,
+}
+
+// 驱动不支持原生 XDP 时返回 EOPNOTSUPP，MTU 过大等驱动拒绝的配置返回 EINVAL，
+// 其他错误(例如设备上已有 XDP 程序)换成通用模式也无法挂载
+fn driver_refused(e: &anyhow::Error) -> bool {
+    e.chain()
+        .find_map(|e| e.downcast_ref::<std::io::Error>())
+        .and_then(|e| e.raw_os_error())
+        .is_some_and(|errno| errno == libc::EOPNOTSUPP || errno == libc::EINVAL)
+}
+
+// 先以驱动模式挂载，驱动拒绝时回退到通用模式，返回挂载ID及生效的模式
+pub fn attach(program: &mut Xdp, iface: &str) -> Result<(XdpLinkId, XdpMode), anyhow::Error> {
+    let native = match program.attach(iface, XdpFlags::DRV_MODE) {
+        Ok(link_id) => return Ok((link_id, XdpMode::Native)),
+        Err(e) => anyhow::Error::from(e),
+    };
+    if !driver_refused(&native) {
+        return Err(native);
+    }
+    warn!(
+        iface,
+        mode = ?XdpMode::Generic,
+        "设备 {} 不支持原生XDP({:#})，回退到通用模式",
+        iface,
+        native
+    );
+    match program.attach(iface, XdpFlags::SKB_MODE) {
+        Ok(link_id) => {
+            info!(iface, mode = ?XdpMode::Generic, "设备 {} 以通用模式挂载XDP程序", iface);
+            Ok((link_id, XdpMode::Generic))
+        }
+        Err(e) => Err(anyhow::anyhow!(
+            "native mode: {:#}; generic mode: {:#}",
+            native,
+            anyhow::Error::from(e)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_only_when_driver_refuses() {
+        let errno = |errno| anyhow::Error::from(std::io::Error::from_raw_os_error(errno));
+        assert!(driver_refused(&errno(libc::EOPNOTSUPP)));
+        assert!(driver_refused(&errno(libc::EINVAL)));
+        assert!(!driver_refused(&errno(libc::EBUSY)));
+        assert!(!driver_refused(&anyhow::anyhow!("program not loaded")));
+    }
+}
@@ -0,0 +1,256 @@
+// 集成测试公用部分：创建网络命名空间和 veth 对、启动 xnet、调用 API、用 xnet bench 发包
+// 需要 root、iproute2、已挂载的 bpffs 和构建出的 eBPF 程序，条件不满足时测试直接跳过
+
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use hyper::{Body, Client, Method, Request};
+use serde_json::Value;
+
+// 同一进程内多个测试各用不同的设备名和地址
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+const XNET_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 流量统计快照的刷新间隔，发完流量后至少等待一个间隔再查询
+const STATS_REFRESH: Duration = Duration::from_millis(100);
+
+// statfs 返回的 bpffs 文件系统类型
+const BPF_FS_MAGIC: i64 = 0xcafe4a11;
+
+fn is_bpffs(path: &str) -> bool {
+    let path = std::ffi::CString::new(path).unwrap();
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    unsafe { libc::statfs(path.as_ptr(), &mut stat) == 0 && stat.f_type as i64 == BPF_FS_MAGIC }
+}
+
+// 构建脚本未能编译 eBPF 程序时嵌入的目标文件不是 ELF，xnet 无法加载
+fn ebpf_object_built() -> bool {
+    option_env!("XNET_EBPF_OBJECT")
+        .and_then(|path| std::fs::read(path).ok())
+        .is_some_and(|object| object.starts_with(b"\x7fELF"))
+}
+
+// 创建命名空间时 ip 会在 /var/run/netns 下建立挂载点
+fn netns_dir_writable() -> bool {
+    std::fs::create_dir_all("/var/run/netns").is_ok()
+        && unsafe { libc::access(c"/var/run/netns".as_ptr(), libc::W_OK) } == 0
+}
+
+// 不是 root、缺少 ip 命令、bpffs 未挂载、无法创建命名空间或没有构建出 eBPF 程序时返回 false，
+// 调用方直接返回
+pub fn privileged() -> bool {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping: integration tests need root");
+        return false;
+    }
+    if Command::new("ip").arg("-V").output().is_err() {
+        eprintln!("skipping: iproute2 (ip) not found");
+        return false;
+    }
+    if !is_bpffs("/sys/fs/bpf") {
+        eprintln!("skipping: bpffs is not mounted at /sys/fs/bpf");
+        return false;
+    }
+    if !netns_dir_writable() {
+        eprintln!("skipping: /var/run/netns is not writable");
+        return false;
+    }
+    if !ebpf_object_built() {
+        eprintln!("skipping: the eBPF object was not built");
+        return false;
+    }
+    true
+}
+
+fn ip(args: &[&str]) {
+    let output = Command::new("ip").args(args).output().expect("failed to run ip");
+    assert!(
+        output.status.success(),
+        "ip {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+// 一端留在当前命名空间、另一端在独立命名空间中的 veth 对，drop 时删除
+pub struct VethPair {
+    pub host_iface: String,
+    pub host_ip: String,
+    pub peer_ip: String,
+    pub netns: String,
+}
+
+impl VethPair {
+    pub fn new() -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let tag = format!("{}x{}", std::process::id() % 100000, id);
+        let pair = VethPair {
+            host_iface: format!("xth{}", tag),
+            host_ip: format!("10.231.{}.1", id),
+            peer_ip: format!("10.231.{}.2", id),
+            netns: format!("xnet-test-{}", tag),
+        };
+        let peer_iface = format!("xtp{}", tag);
+
+        ip(&["netns", "add", &pair.netns]);
+        ip(&[
+            "link", "add", &pair.host_iface, "type", "veth", "peer", "name", &peer_iface, "netns",
+            &pair.netns,
+        ]);
+        ip(&["addr", "add", &format!("{}/24", pair.host_ip), "dev", &pair.host_iface]);
+        ip(&["link", "set", &pair.host_iface, "up"]);
+        ip(&["-n", &pair.netns, "addr", "add", &format!("{}/24", pair.peer_ip), "dev", &peer_iface]);
+        ip(&["-n", &pair.netns, "link", "set", &peer_iface, "up"]);
+        ip(&["-n", &pair.netns, "link", "set", "lo", "up"]);
+        pair
+    }
+
+    pub fn netns_path(&self) -> PathBuf {
+        PathBuf::from(format!("/var/run/netns/{}", self.netns))
+    }
+
+    // 在对端命名空间中运行 xnet bench，发往本端地址
+    pub fn bench(&self, protocol: &str, dst_port: u16, rate: u32, duration_secs: u32) {
+        let output = Command::new(env!("CARGO_BIN_EXE_xnet"))
+            .args(["bench", "--netns"])
+            .arg(self.netns_path())
+            .args(["--dst", &self.host_ip])
+            .args(["--protocol", protocol])
+            .args(["--dst-port", &dst_port.to_string()])
+            .args(["--rate", &rate.to_string()])
+            .args(["--duration-secs", &duration_secs.to_string()])
+            .output()
+            .expect("failed to run xnet bench");
+        assert!(
+            output.status.success(),
+            "xnet bench failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+impl Drop for VethPair {
+    fn drop(&mut self) {
+        // 删除命名空间时其中的 veth 端随之删除，另一端也会一起消失
+        let _ = Command::new("ip").args(["netns", "del", &self.netns]).status();
+        let _ = Command::new("ip").args(["link", "del", &self.host_iface]).status();
+    }
+}
+
+// 独立的 xnet 实例，使用空闲端口和独立的 pin 目录，drop 时结束进程
+pub struct Xnet {
+    pub addr: SocketAddr,
+    child: Child,
+    pin_path: PathBuf,
+}
+
+impl Xnet {
+    pub async fn start() -> Self {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("no free port");
+        let pin_path = PathBuf::from(format!(
+            "/sys/fs/bpf/xnet-test-{}-{}",
+            std::process::id(),
+            addr.port()
+        ));
+        let child = Command::new(env!("CARGO_BIN_EXE_xnet"))
+            .args(["--listen", &addr.to_string()])
+            .arg("--pin-path")
+            .arg(&pin_path)
+            .args(["--interval-secs", "1"])
+            .arg("--stats-refresh-ms")
+            .arg(STATS_REFRESH.as_millis().to_string())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .expect("failed to start xnet");
+        let mut xnet = Xnet {
+            addr,
+            child,
+            pin_path,
+        };
+        xnet.wait_ready().await;
+        xnet
+    }
+
+    async fn wait_ready(&mut self) {
+        let deadline = Instant::now() + XNET_READY_TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait().expect("failed to poll xnet") {
+                panic!("xnet exited before becoming ready: {}", status);
+            }
+            if let Ok((200, _)) = self.request(Method::GET, "/info", None).await {
+                return;
+            }
+            assert!(Instant::now() < deadline, "xnet did not become ready");
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    // path 为 /api/v1 下的相对路径
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<(u16, Value), hyper::Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}/api/v1{}", self.addr, path))
+            .header("content-type", "application/json")
+            .body(body.map(|b| Body::from(b.to_string())).unwrap_or_else(Body::empty))
+            .expect("invalid request");
+        let response = Client::new().request(request).await?;
+        let status = response.status().as_u16();
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        // 错误响应多为纯文本，按字符串返回
+        let value = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        Ok((status, value))
+    }
+
+    // 等待流量统计快照包含刚发送的流量
+    pub async fn wait_stats_refresh(&self) {
+        tokio::time::sleep(STATS_REFRESH * 3).await;
+    }
+
+    pub async fn get(&self, path: &str) -> (u16, Value) {
+        self.request(Method::GET, path, None)
+            .await
+            .unwrap_or_else(|e| panic!("GET {} failed: {}", path, e))
+    }
+
+    pub async fn post(&self, path: &str, body: Value) -> (u16, Value) {
+        self.request(Method::POST, path, Some(body))
+            .await
+            .unwrap_or_else(|e| panic!("POST {} failed: {}", path, e))
+    }
+}
+
+impl Drop for Xnet {
+    fn drop(&mut self) {
+        unsafe { libc::kill(self.child.id() as i32, libc::SIGTERM) };
+        if self.child.wait().is_err() {
+            let _ = self.child.kill();
+        }
+        let _ = std::fs::remove_dir_all(&self.pin_path);
+    }
+}
+
+// 数组或分页结果中的条目
+pub fn items(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Array(rows) => rows.clone(),
+        Value::Object(map) => map
+            .get("items")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
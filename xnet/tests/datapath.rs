@@ -0,0 +1,141 @@
+// 在 veth 对上挂载程序、发包，并通过 API 检查计数和防火墙规则
+// 需要 root：sudo -E cargo test -p xnet --test datapath
+
+mod common;
+
+use common::{items, privileged, VethPair, Xnet};
+use serde_json::json;
+
+// 2 秒 200pps，留出余量避免偶发丢包导致失败
+const RATE: u32 = 200;
+const DURATION_SECS: u32 = 2;
+const MIN_PACKETS: u64 = 300;
+
+#[tokio::test]
+async fn attach_unknown_device_is_rejected() {
+    if !privileged() {
+        return;
+    }
+    let xnet = Xnet::start().await;
+    let (status, _) = xnet
+        .post(
+            "/traffic_count_attach_device",
+            json!({"iface": "xnet-nonexist", "action": "add"}),
+        )
+        .await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn counts_ingress_udp_per_device_and_port() {
+    if !privileged() {
+        return;
+    }
+    let veth = VethPair::new();
+    let xnet = Xnet::start().await;
+    let (status, body) = xnet
+        .post(
+            "/traffic_count_attach_device",
+            json!({"iface": veth.host_iface, "action": "add"}),
+        )
+        .await;
+    assert_eq!(status, 200, "attach failed: {}", body);
+
+    veth.bench("udp", 5353, RATE, DURATION_SECS);
+    xnet.wait_stats_refresh().await;
+
+    let (status, devices) = xnet.get("/traffic_device_stats").await;
+    assert_eq!(status, 200);
+    let ingress = items(&devices)
+        .into_iter()
+        .find(|row| row["device"] == veth.host_iface.as_str() && row["direction"] == "ingress")
+        .unwrap_or_else(|| panic!("no ingress stats for {}: {}", veth.host_iface, devices));
+    assert!(
+        ingress["packets"].as_u64().unwrap_or(0) >= MIN_PACKETS,
+        "too few ingress packets: {}",
+        ingress
+    );
+
+    let (status, ports) = xnet.get("/traffic_port_stats?port=5353").await;
+    assert_eq!(status, 200);
+    let port = items(&ports)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| panic!("no stats for port 5353: {}", ports));
+    assert!(
+        port["rx_packets"].as_u64().unwrap_or(0) >= MIN_PACKETS,
+        "too few packets on port 5353: {}",
+        port
+    );
+}
+
+#[tokio::test]
+async fn firewall_rule_drops_matching_packets() {
+    if !privileged() {
+        return;
+    }
+    let veth = VethPair::new();
+    let xnet = Xnet::start().await;
+    let (status, body) = xnet
+        .post(
+            "/firewall_attach_device",
+            json!({"iface": veth.host_iface, "action": "add"}),
+        )
+        .await;
+    assert_eq!(status, 200, "attach failed: {}", body);
+    let (status, body) = xnet
+        .post(
+            "/firewall/rules",
+            json!({
+                "dst": veth.host_ip,
+                "dst_port": 5354,
+                "protocol": "udp",
+                "direction": "ingress",
+                "action": "deny",
+            }),
+        )
+        .await;
+    assert_eq!(status, 200, "add rule failed: {}", body);
+    let id = body["id"].as_u64().expect("rule id");
+
+    veth.bench("udp", 5354, RATE, DURATION_SECS);
+
+    let (status, rule) = xnet.get(&format!("/firewall/rules/{}", id)).await;
+    assert_eq!(status, 200);
+    assert!(
+        rule["hits"]["packets"].as_u64().unwrap_or(0) >= MIN_PACKETS,
+        "rule did not match: {}",
+        rule
+    );
+}
+
+#[tokio::test]
+async fn counts_tcp_syns_per_port() {
+    if !privileged() {
+        return;
+    }
+    let veth = VethPair::new();
+    let xnet = Xnet::start().await;
+    let (status, body) = xnet
+        .post(
+            "/traffic_count_attach_device",
+            json!({"iface": veth.host_iface, "action": "add"}),
+        )
+        .await;
+    assert_eq!(status, 200, "attach failed: {}", body);
+
+    veth.bench("tcp", 8443, RATE, DURATION_SECS);
+    xnet.wait_stats_refresh().await;
+
+    let (status, ports) = xnet.get("/traffic_port_stats?port=8443").await;
+    assert_eq!(status, 200);
+    let port = items(&ports)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| panic!("no stats for port 8443: {}", ports));
+    assert!(
+        port["rx_packets"].as_u64().unwrap_or(0) >= MIN_PACKETS,
+        "too few packets on port 8443: {}",
+        port
+    );
+}